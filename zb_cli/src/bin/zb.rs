@@ -1,92 +1,489 @@
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use std::time::Duration;
+
 use clap::Parser;
 use console::style;
 use zb_cli::{
-    cli::{Cli, Commands},
-    commands,
+    cli::{BundleCommands, Cli, Commands, OutputFormat, SnapshotCommands},
+    commands, events,
     init::ensure_init,
     logging,
+    prompt::PromptPolicy,
     ui::Ui,
-    utils::get_root_path,
+    utils::{prefix_drift_message, resolve_paths_from_env, ui_error, version_change_notice},
+    version,
 };
-use zb_io::create_installer;
+use zb_io::{Hooks, create_installer};
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    if version::wants_verbose_version(std::env::args_os().skip(1)) {
+        println!("{}", version::verbose_version(&zb_core::host_bottle_tags()));
+        return ExitCode::SUCCESS;
+    }
+
     let cli = Cli::parse();
     logging::init(cli.verbose, cli.quiet);
+    let output = cli.output;
 
-    if let Err(e) = run(cli).await {
-        eprintln!("{} {}", style("error:").red().bold(), e);
-        std::process::exit(1);
+    match run(cli).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("{} {}", style("error:").red().bold(), e);
+            if output == OutputFormat::JsonLines {
+                let _ = events::emit(&events::Event::Error {
+                    message: e.to_string(),
+                });
+            }
+            ExitCode::from(u8::try_from(e.exit_code()).unwrap_or(1))
+        }
     }
 }
 
-async fn run(cli: Cli) -> Result<(), zb_core::Error> {
-    let mut ui = Ui::new();
+/// Run the command, returning the status zb exits with when it succeeds.
+async fn run(mut cli: Cli) -> Result<ExitCode, zb_core::Error> {
+    let mut ui = match cli.output {
+        OutputFormat::Human => Ui::new(),
+        OutputFormat::JsonLines => {
+            if !matches!(
+                cli.command,
+                Commands::Install { .. }
+                    | Commands::Bundle {
+                        command: None | Some(BundleCommands::Install { .. }),
+                        ..
+                    }
+                    | Commands::Migrate { .. }
+            ) {
+                return Err(zb_core::Error::InvalidArgument {
+                    message: "--output json-lines is only supported by install, bundle and migrate"
+                        .to_string(),
+                });
+            }
+            Ui::json_lines()
+        }
+    };
+    let policy = PromptPolicy::from_flags(cli.yes, cli.non_interactive);
+    // Progress bars are hidden when nobody watches the output live, so
+    // long operations print a summary line now and then instead.
+    if !cli.quiet
+        && cli.heartbeat_interval > 0
+        && !std::io::stdout().is_terminal()
+        && !std::io::stderr().is_terminal()
+    {
+        ui.set_heartbeat(Some(Duration::from_secs(cli.heartbeat_interval)));
+    }
+
+    if let Commands::Completion {
+        shell,
+        install: false,
+    } = cli.command
+    {
+        commands::completion::execute(shell)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Commands::Snapshot {
+        command: SnapshotCommands::Info { file },
+    } = &cli.command
+    {
+        commands::snapshot::info(file)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Before init, so a bad or empty name list changes nothing.
+    if let Commands::Install { formulas, .. } = &mut cli.command {
+        *formulas = commands::install::resolve_formula_args(std::mem::take(formulas), &mut ui)?;
+    }
+
+    let paths = resolve_paths_from_env(cli.root, cli.prefix);
+    let root = paths.root.path.clone();
+    let prefix = paths.prefix.path.clone();
+
+    if let Commands::Completion {
+        shell,
+        install: true,
+    } = cli.command
+    {
+        commands::completion::install(shell, &root, &prefix, &mut ui)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Commands::Init {
+        no_modify_path,
+        dry_run,
+    } = cli.command
+    {
+        commands::init::execute(&root, &prefix, no_modify_path, dry_run, &mut ui)?;
+        if !dry_run && !cli.no_prefetch {
+            commands::init::prefetch_index(&paths.cache.path)?;
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    if let Commands::Completion { shell } = cli.command {
-        return commands::completion::execute(shell);
+    // Reset has to work on a foreign root, and must look at the prefix
+    // before the installer creates its directories there.
+    if let Commands::Reset { force_unowned } = cli.command {
+        commands::reset::execute(
+            &root,
+            &prefix,
+            &paths.cache.path,
+            force_unowned,
+            policy,
+            &mut ui,
+        )?;
+        return Ok(ExitCode::SUCCESS);
     }
 
-    let root = get_root_path(cli.root);
-    let prefix = cli.prefix.unwrap_or_else(|| {
-        // On macOS, Mach-O binaries have fixed-size path fields so the prefix
-        // must be no longer than the original Homebrew prefix (/opt/homebrew = 13 chars).
-        // Using root directly (/opt/zerobrew = 13 chars) keeps us within that limit.
-        if cfg!(target_os = "macos") {
-            root.clone()
-        } else {
-            root.join("prefix")
+    // Reporting what is installed needs neither init nor an installer, so
+    // these run on a machine zerobrew never set up without creating anything.
+    if cli.command.reads_installed_state() {
+        let state = zb_io::InstalledState::open(&root, &prefix)?;
+        if let Some(recorded) = state.prefix_conflict(cli.accept_prefix_change)? {
+            ui.warn(format!(
+                "this root's formulas are installed under prefix {}, not {}",
+                recorded.display(),
+                prefix.display()
+            ))
+            .map_err(ui_error)?;
+        }
+        if let Some(message) = prefix_drift_message(&state.prefix_drift()?, &prefix) {
+            ui.warn(message).map_err(ui_error)?;
         }
-    });
+        return match cli.command {
+            Commands::List {
+                explicit_only,
+                deps_only,
+                installed_by,
+                json,
+                porcelain,
+                ..
+            } => commands::list::execute(
+                &state,
+                &commands::list::filter(explicit_only, deps_only, installed_by),
+                json,
+                porcelain,
+            ),
+            Commands::Info { system: true, .. } => commands::info::system(&state, &paths),
+            Commands::Info {
+                formula: Some(formula),
+                files: true,
+                json,
+                ..
+            } => commands::info::files(&state, &formula, json),
+            Commands::Info {
+                formula: Some(formula),
+                as_installed: true,
+                ..
+            } => commands::info::as_installed(&state, &formula),
+            Commands::Info { formula, .. } => {
+                commands::info::execute(&state, formula.unwrap_or_default(), cli.verbose > 0)
+            }
+            Commands::History { json } => commands::history::execute(&state, json),
+            _ => unreachable!(),
+        }
+        .map(|()| ExitCode::SUCCESS);
+    }
 
-    if let Commands::Init { no_modify_path } = cli.command {
-        return commands::init::execute(&root, &prefix, no_modify_path, &mut ui);
+    let initialized = ensure_init(&root, &prefix, cli.auto_init, &mut ui)?;
+    // A fresh root has no formula index yet. Fetch it before a command
+    // that will need it, so that command finds it cached; otherwise leave
+    // it to the next one, which also picks up a prefetch that `zb init`
+    // didn't finish and fetches it while running.
+    let mut prefetch = None;
+    if !cli.no_prefetch {
+        if initialized && !cli.command.uses_formula_index() {
+            zb_io::mark_index_prefetch_pending(&paths.cache.path)?;
+        } else if initialized || zb_io::index_prefetch_pending(&paths.cache.path) {
+            let spawned = zb_io::IndexPrefetch::spawn(paths.cache.path.clone())?;
+            if cli.command.uses_formula_index() {
+                commands::init::finish_prefetch(spawned);
+            } else {
+                prefetch = Some(spawned);
+            }
+        }
     }
 
-    if !matches!(cli.command, Commands::Reset { .. }) {
-        ensure_init(&root, &prefix, cli.auto_init, &mut ui)?;
+    let mut installer = create_installer(
+        &root,
+        &prefix,
+        &paths.cache.path,
+        cli.concurrency,
+        cli.accept_prefix_change,
+    )?;
+    installer.set_keep_versions(cli.keep_versions);
+    if !cli.no_hooks {
+        let hook_dir = cli.hook_dir.unwrap_or_else(|| root.join("hooks"));
+        installer.set_hooks(Some(Hooks::new(hook_dir)));
     }
 
-    let mut installer = create_installer(&root, &prefix, cli.concurrency)?;
+    if let Some(previous) = installer.check_system(env!("CARGO_PKG_VERSION"))? {
+        ui.note(version_change_notice(&previous, env!("CARGO_PKG_VERSION")))
+            .map_err(ui_error)?;
+    }
 
-    match cli.command {
+    // Kegs patched for another prefix break in confusing ways once used
+    // from this one, so only read-only commands go ahead.
+    if let Some(message) = prefix_drift_message(&installer.prefix_drift()?, &prefix) {
+        if !cli.command.is_read_only() {
+            return Err(zb_core::Error::InvalidArgument { message });
+        }
+        ui.warn(message).map_err(ui_error)?;
+    }
+
+    // Leftovers from crashed runs would otherwise count towards the cache
+    // and be picked up by patching. Only commands that change things pay
+    // for the walk.
+    let swept = if cli.command.is_read_only() {
+        Ok(Vec::new())
+    } else {
+        installer.sweep_stale_temp(zb_io::STALE_TEMP_AGE)
+    };
+    match swept {
+        Ok(removed) if !removed.is_empty() => {
+            let noun = if removed.len() == 1 { "file" } else { "files" };
+            ui.note(format!(
+                "removed {} temporary {noun} left by an interrupted run (-v lists them)",
+                removed.len()
+            ))
+            .map_err(ui_error)?;
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!(error = %e, "failed to sweep stale temporary files"),
+    }
+
+    let mut status = ExitCode::SUCCESS;
+    let result = match cli.command {
         Commands::Init { .. } => unreachable!(),
         Commands::Completion { .. } => unreachable!(),
+        Commands::Install {
+            formulas,
+            build_from_source,
+            save_plan: Some(path),
+            ..
+        } => {
+            commands::install::save_plan(
+                &mut installer,
+                formulas,
+                build_from_source,
+                &path,
+                &mut ui,
+            )
+            .await
+        }
         Commands::Install {
             formulas,
             no_link,
             build_from_source,
+            save_plan: None,
+            fail_fast,
+            preserve_perms,
+            strict_patching,
+            accept_disabled,
+            timings,
         } => {
-            commands::install::execute(
+            installer.set_preserve_permissions(preserve_perms);
+            if strict_patching {
+                installer.set_patch_policy(zb_io::PatchPolicy::Strict);
+            }
+            let result = commands::install::execute(
                 &mut installer,
                 formulas,
                 no_link,
                 build_from_source,
+                fail_fast,
+                accept_disabled,
                 &mut ui,
             )
-            .await
+            .await;
+            if timings {
+                commands::install::print_timings(&installer, &mut ui)?;
+            }
+            result
         }
-        Commands::Bundle { command } => {
-            commands::bundle::execute(&mut installer, command, &mut ui).await
+        Commands::Apply {
+            plan,
+            reconcile,
+            no_link,
+        } => commands::apply::execute(&mut installer, &plan, reconcile, no_link, &mut ui).await,
+        Commands::Bundle { global, command } => {
+            commands::bundle::execute(&mut installer, global, command, &mut ui).await
+        }
+        Commands::Uninstall {
+            formulas,
+            all,
+            purge_config,
+            dry_run,
+            ignore_dependencies,
+            version,
+            force,
+        } => commands::uninstall::execute(
+            &mut installer,
+            formulas,
+            all,
+            purge_config,
+            dry_run,
+            ignore_dependencies,
+            version,
+            force,
+            policy,
+            &mut ui,
+        ),
+        Commands::Migrate { force } => {
+            commands::migrate::execute(&mut installer, policy, force, &mut ui).await
         }
-        Commands::Uninstall { formulas, all } => {
-            commands::uninstall::execute(&mut installer, formulas, all, &mut ui)
+        Commands::Doctor {
+            repair,
+            check_runtime,
+            formula,
+            timeout,
+        } => commands::doctor::execute(
+            &mut installer,
+            repair,
+            check_runtime.then(|| commands::doctor::RuntimeOptions {
+                formula,
+                timeout: std::time::Duration::from_secs(timeout),
+            }),
+            &mut ui,
+        ),
+        Commands::List {
+            explicit_only,
+            deps_only,
+            installed_by,
+            json,
+            ..
+        } => commands::list::with_cached_updates(
+            &installer,
+            &commands::list::filter(explicit_only, deps_only, installed_by),
+            json,
+        ),
+        Commands::Licenses { json, forbid } => {
+            commands::licenses::execute(&mut installer, json, forbid, &mut ui)
         }
-        Commands::Migrate { yes, force } => {
-            commands::migrate::execute(&mut installer, yes, force, &mut ui).await
+        Commands::Info { .. } | Commands::History { .. } => unreachable!(),
+        Commands::Gc {
+            aggressive,
+            keep_metadata,
+            keep_bottles,
+            keep_old_versions,
+            keep_temp,
+            archive,
+            dry_run,
+        } => {
+            let options = zb_io::GcOptions {
+                store_entries: true,
+                blobs: aggressive && !keep_bottles,
+                metadata: aggressive && !keep_metadata,
+                old_kegs: aggressive && !keep_old_versions,
+                temp_files: aggressive && !keep_temp,
+                archive,
+                dry_run,
+            };
+            commands::gc::execute(&mut installer, options, &mut ui)
         }
-        Commands::Doctor { repair } => commands::doctor::execute(&mut installer, repair, &mut ui),
-        Commands::List => commands::list::execute(&mut installer),
-        Commands::Info { formula } => commands::info::execute(&mut installer, formula),
-        Commands::Gc => commands::gc::execute(&mut installer),
         Commands::Update => commands::update::execute(&mut installer),
-        Commands::Outdated { json } => {
-            commands::outdated::execute(&mut installer, cli.quiet, cli.verbose > 0, json).await
+        Commands::Upgrade {
+            formulas,
+            dry_run,
+            json,
+            include_rebuilds,
+            accept_disabled,
+            no_link,
+        } => {
+            let options = commands::upgrade::UpgradeOptions {
+                dry_run,
+                json,
+                include_rebuilds,
+                accept_disabled,
+                no_link,
+            };
+            commands::upgrade::execute(&mut installer, &formulas, options, &mut ui).await
+        }
+        Commands::Pin { formulas } => commands::pin::execute(&installer, &formulas, true, &mut ui),
+        Commands::Unpin { formulas } => {
+            commands::pin::execute(&installer, &formulas, false, &mut ui)
+        }
+        Commands::Outdated {
+            json,
+            include_rebuilds,
+            porcelain,
+        } => {
+            let any_outdated = commands::outdated::execute(
+                &mut installer,
+                cli.quiet,
+                json,
+                porcelain,
+                include_rebuilds,
+            )
+            .await?;
+            if any_outdated {
+                status = ExitCode::from(commands::outdated::OUTDATED_EXIT_CODE);
+            }
+            Ok(())
+        }
+        Commands::Deps {
+            formula,
+            missing: true,
+            fix,
+            porcelain,
+            ..
+        } => commands::deps::missing(&mut installer, formula, fix, porcelain, &mut ui).await,
+        Commands::Deps {
+            formula: Some(formula),
+            tree,
+            porcelain,
+            ..
+        } => commands::deps::resolve(&installer, formula, tree, porcelain, &mut ui).await,
+        Commands::Deps { .. } => unreachable!(),
+        Commands::Tap { command } => commands::tap::execute(&mut installer, command, &mut ui),
+        Commands::Search { query } => commands::search::execute(&installer, &query).await,
+        Commands::Why {
+            formula,
+            all_paths,
+            json,
+        } => commands::why::execute(&mut installer, formula, all_paths, json),
+        Commands::Env {
+            formulas,
+            json,
+            export,
+            combined,
+        } => {
+            let format = if json {
+                commands::env::EnvFormat::Json
+            } else if export {
+                commands::env::EnvFormat::Export
+            } else {
+                commands::env::EnvFormat::Plain
+            };
+            commands::env::execute(&mut installer, formulas, format, combined)
+        }
+        Commands::Snapshot { command } => match command {
+            SnapshotCommands::Create { file, formulas } => {
+                commands::snapshot::create(&mut installer, &file, formulas).await
+            }
+            SnapshotCommands::Info { .. } => unreachable!(),
+        },
+        Commands::Cache { command } => commands::cache::execute(&mut installer, command, &mut ui),
+        Commands::Reset { .. } => unreachable!(),
+        Commands::Rollback { formula, version } => {
+            commands::rollback::execute(&mut installer, formula, version, &mut ui)
+        }
+        Commands::Relocate { prefix: new_prefix } => {
+            commands::relocate::execute(&mut installer, &new_prefix, policy, &mut ui)
         }
-        Commands::Reset { yes } => commands::reset::execute(&root, &prefix, yes, &mut ui),
-        Commands::Run { formula, args } => {
-            commands::run::execute(&mut installer, formula, args).await
+        Commands::Run { with, command } => {
+            let mut command = command.into_iter();
+            let formula = command.next().expect("clap requires a formula");
+            let args = command.collect();
+            if with.is_empty() {
+                commands::run::execute(&mut installer, formula, args).await
+            } else {
+                commands::run::execute_with(&mut installer, &prefix, with, formula, args).await
+            }
         }
+    };
+    if let Some(prefetch) = prefetch {
+        commands::init::finish_prefetch(prefetch);
     }
+    result.map(|()| status)
 }
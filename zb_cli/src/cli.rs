@@ -1,17 +1,22 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
+use crate::porcelain::Porcelain;
+
 #[derive(Parser)]
 #[command(name = "zb")]
 #[command(about = "Zerobrew - A fast Homebrew-compatible package installer")]
 #[command(version)]
 pub struct Cli {
-    #[arg(long, env = "ZEROBREW_ROOT")]
+    /// Data directory [env: ZEROBREW_ROOT]
+    #[arg(long)]
     pub root: Option<PathBuf>,
 
-    #[arg(long, env = "ZEROBREW_PREFIX")]
+    /// Install prefix [env: ZEROBREW_PREFIX]
+    #[arg(long)]
     pub prefix: Option<PathBuf>,
 
+    /// Parallel downloads, and worker threads for patching (at most one per core)
     #[arg(
         long,
         default_value = "20",
@@ -19,6 +24,31 @@ pub struct Cli {
     )]
     pub concurrency: usize,
 
+    /// Previous versions of each formula kept after an upgrade for `zb rollback`
+    #[arg(
+        long,
+        env = "ZEROBREW_KEEP_VERSIONS",
+        default_value_t = zb_io::DEFAULT_KEEP_VERSIONS
+    )]
+    pub keep_versions: usize,
+
+    /// Directory of install and uninstall hook scripts; defaults to ROOT/hooks
+    #[arg(long, global = true, env = "ZEROBREW_HOOK_DIR", value_name = "DIR")]
+    pub hook_dir: Option<PathBuf>,
+
+    /// Run even though the root's formulas were installed under another
+    /// prefix; kegs patched for it still block changes until relocated
+    #[arg(long, global = true)]
+    pub accept_prefix_change: bool,
+
+    /// Don't run install and uninstall hook scripts
+    #[arg(long, global = true)]
+    pub no_hooks: bool,
+
+    /// Don't fetch the formula index ahead of time after initializing
+    #[arg(long, global = true, env = "ZEROBREW_NO_PREFETCH")]
+    pub no_prefetch: bool,
+
     #[arg(long = "auto-init", global = true, env = "ZEROBREW_AUTO_INIT")]
     pub auto_init: bool,
 
@@ -28,10 +58,44 @@ pub struct Cli {
     #[arg(long, short = 'q', global = true, conflicts_with = "verbose")]
     pub quiet: bool,
 
+    /// Seconds between progress summaries when output isn't a terminal;
+    /// 0 turns them off
+    #[arg(
+        long,
+        global = true,
+        env = "ZEROBREW_HEARTBEAT_INTERVAL",
+        value_name = "SECS",
+        default_value_t = crate::heartbeat::DEFAULT_HEARTBEAT_SECS
+    )]
+    pub heartbeat_interval: u64,
+
+    /// Answer yes to every confirmation prompt
+    #[arg(long, short = 'y', global = true)]
+    pub yes: bool,
+
+    /// Never prompt; confirmations that default to no fail instead of blocking
+    #[arg(
+        long = "non-interactive",
+        global = true,
+        env = "ZEROBREW_NON_INTERACTIVE"
+    )]
+    pub non_interactive: bool,
+
+    /// `json-lines` writes one JSON event per line to stdout and everything
+    /// else to stderr (install, bundle and migrate only)
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+    pub output: OutputFormat,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    JsonLines,
+}
+
 fn parse_concurrency(value: &str) -> Result<usize, String> {
     let parsed = value
         .parse::<usize>()
@@ -44,7 +108,7 @@ fn parse_concurrency(value: &str) -> Result<usize, String> {
 
 #[cfg(test)]
 mod tests {
-    use super::Cli;
+    use super::{Cli, Porcelain};
     use clap::Parser;
 
     #[test]
@@ -74,18 +138,757 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn yes_is_accepted_before_and_after_subcommand() {
+        let before = Cli::try_parse_from(["zb", "-y", "reset"]).unwrap();
+        assert!(before.yes);
+
+        let after = Cli::try_parse_from(["zb", "migrate", "--yes", "--force"]).unwrap();
+        assert!(after.yes);
+        assert!(!after.non_interactive);
+    }
+
+    #[test]
+    fn install_accepts_save_plan_and_apply_accepts_reconcile() {
+        let cli = Cli::try_parse_from(["zb", "install", "jq", "--save-plan", "plan.json"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Install { save_plan: Some(ref p), .. } if p.ends_with("plan.json")
+        ));
+
+        let cli = Cli::try_parse_from(["zb", "apply", "plan.json", "--reconcile"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Apply {
+                reconcile: true,
+                ..
+            }
+        ));
+    }
+
     #[test]
     fn outdated_quiet_and_verbose_conflict() {
         let result = Cli::try_parse_from(["zb", "outdated", "--quiet", "--verbose"]);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn include_rebuilds_for_outdated_and_bundle_upgrades() {
+        let cli = Cli::try_parse_from(["zb", "outdated", "--json", "--include-rebuilds"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Outdated {
+                json: true,
+                include_rebuilds: true,
+                porcelain: None
+            }
+        ));
+
+        assert!(
+            Cli::try_parse_from(["zb", "bundle", "install", "--upgrade", "--include-rebuilds"])
+                .is_ok()
+        );
+        assert!(Cli::try_parse_from(["zb", "bundle", "install", "--include-rebuilds"]).is_err());
+        assert!(
+            Cli::try_parse_from(["zb", "bundle", "install", "--upgrade", "--accept-disabled"])
+                .is_ok()
+        );
+        assert!(Cli::try_parse_from(["zb", "bundle", "install", "--accept-disabled"]).is_err());
+    }
+
+    #[test]
+    fn search_takes_a_query_and_changes_nothing() {
+        let cli = Cli::try_parse_from(["zb", "search", "openssl@3"]).unwrap();
+        assert!(matches!(
+            &cli.command,
+            super::Commands::Search { query } if query == "openssl@3"
+        ));
+        assert!(cli.command.is_read_only());
+        assert!(cli.command.uses_formula_index());
+        assert!(Cli::try_parse_from(["zb", "search"]).is_err());
+    }
+
+    #[test]
+    fn pin_and_unpin_take_formulas() {
+        let cli = Cli::try_parse_from(["zb", "pin", "jq", "wget"]).unwrap();
+        assert!(matches!(
+            &cli.command,
+            super::Commands::Pin { formulas } if formulas == &["jq", "wget"]
+        ));
+        assert!(!cli.command.is_read_only());
+        assert!(Cli::try_parse_from(["zb", "unpin", "jq"]).is_ok());
+        assert!(Cli::try_parse_from(["zb", "pin"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "unpin"]).is_err());
+    }
+
+    #[test]
+    fn upgrade_dry_run_is_read_only() {
+        let cli = Cli::try_parse_from(["zb", "upgrade", "jq", "--dry-run", "--json"]).unwrap();
+        assert!(matches!(
+            &cli.command,
+            super::Commands::Upgrade {
+                formulas,
+                dry_run: true,
+                json: true,
+                ..
+            } if formulas == &["jq"]
+        ));
+        assert!(cli.command.is_read_only());
+
+        let cli = Cli::try_parse_from(["zb", "upgrade"]).unwrap();
+        assert!(!cli.command.is_read_only());
+        assert!(Cli::try_parse_from(["zb", "upgrade", "--json"]).is_err());
+    }
+
+    #[test]
+    fn cache_rebuild_index() {
+        let cli = Cli::try_parse_from(["zb", "cache", "rebuild-index"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Cache {
+                command: super::CacheCommands::RebuildIndex
+            }
+        ));
+        assert!(!cli.command.is_read_only());
+        assert!(Cli::try_parse_from(["zb", "cache"]).is_err());
+    }
+
+    #[test]
+    fn cache_clean_needs_archives_or_bottles() {
+        let cli = Cli::try_parse_from(["zb", "cache", "clean", "--archives", "--older-than", "30"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Cache {
+                command: super::CacheCommands::Clean {
+                    archives: true,
+                    bottles: false,
+                    older_than: Some(30)
+                }
+            }
+        ));
+        assert!(!cli.command.is_read_only());
+        assert!(Cli::try_parse_from(["zb", "cache", "clean", "--bottles"]).is_ok());
+        assert!(Cli::try_parse_from(["zb", "cache", "clean"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "cache", "clean", "--older-than", "30"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "gc", "--archive", "--dry-run"]).is_ok());
+    }
+
     #[test]
     fn outdated_quiet_and_json_conflict() {
         let result = Cli::try_parse_from(["zb", "outdated", "--quiet", "--json"]);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn install_fail_fast_flag() {
+        let cli = Cli::try_parse_from(["zb", "install", "jq", "wget", "--fail-fast"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Install {
+                fail_fast: true,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn install_accepts_dash_for_stdin() {
+        let cli = Cli::try_parse_from(["zb", "install", "-"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Install { ref formulas, .. } if formulas == &["-"]
+        ));
+    }
+
+    #[test]
+    fn install_strict_patching_flag() {
+        let cli = Cli::try_parse_from(["zb", "install", "jq", "--strict-patching"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Install {
+                strict_patching: true,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn install_timings_flag() {
+        let cli = Cli::try_parse_from(["zb", "install", "jq", "--timings"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Install { timings: true, .. }
+        ));
+    }
+
+    #[test]
+    fn install_preserve_perms_flag() {
+        let cli = Cli::try_parse_from(["zb", "install", "jq", "--preserve-perms"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Install {
+                preserve_perms: true,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn install_accept_disabled_flag() {
+        let cli = Cli::try_parse_from(["zb", "install", "oldtool", "--accept-disabled"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Install {
+                accept_disabled: true,
+                ..
+            }
+        ));
+        let cli = Cli::try_parse_from(["zb", "install", "oldtool", "--accept-deprecated"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Install {
+                accept_disabled: true,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn uninstall_purge_config_flag() {
+        let cli = Cli::try_parse_from(["zb", "uninstall", "nginx", "--purge-config"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Uninstall {
+                purge_config: true,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn output_json_lines_is_global() {
+        let cli = Cli::try_parse_from(["zb", "install", "jq", "--output", "json-lines"]).unwrap();
+        assert_eq!(cli.output, super::OutputFormat::JsonLines);
+
+        let cli = Cli::try_parse_from(["zb", "bundle"]).unwrap();
+        assert_eq!(cli.output, super::OutputFormat::Human);
+        assert!(Cli::try_parse_from(["zb", "--output", "xml", "install", "jq"]).is_err());
+    }
+
+    #[test]
+    fn bundle_upgrade_and_no_upgrade_conflict() {
+        let cli = Cli::try_parse_from(["zb", "bundle", "install", "--upgrade"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Bundle {
+                command: Some(super::BundleCommands::Install { upgrade: true, .. }),
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from(["zb", "bundle", "install", "--no-upgrade"]).is_ok());
+        assert!(
+            Cli::try_parse_from(["zb", "bundle", "install", "--upgrade", "--no-upgrade"]).is_err()
+        );
+    }
+
+    #[test]
+    fn bundle_global_and_repeated_files() {
+        let cli = Cli::try_parse_from(["zb", "bundle", "--global"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Bundle {
+                global: true,
+                command: None
+            }
+        ));
+        let cli = Cli::try_parse_from(["zb", "bundle", "dump", "--global", "--force"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Bundle {
+                global: true,
+                command: Some(super::BundleCommands::Dump { file: None, .. })
+            }
+        ));
+
+        let cli = Cli::try_parse_from([
+            "zb",
+            "bundle",
+            "install",
+            "-f",
+            "Brewfile",
+            "--file",
+            "Brewfile.dev",
+        ])
+        .unwrap();
+        let super::Commands::Bundle {
+            command: Some(super::BundleCommands::Install { file, .. }),
+            ..
+        } = cli.command
+        else {
+            panic!("expected bundle install");
+        };
+        assert_eq!(
+            file,
+            [std::path::PathBuf::from("Brewfile"), "Brewfile.dev".into()]
+        );
+    }
+
+    #[test]
+    fn list_outdated_cache_flag() {
+        let cli = Cli::try_parse_from(["zb", "list", "--outdated-cache"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::List {
+                outdated_cache: true,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn doctor_runtime_flags_need_check_runtime() {
+        let cli = Cli::try_parse_from([
+            "zb",
+            "doctor",
+            "--check-runtime",
+            "--formula",
+            "jq",
+            "--timeout",
+            "3",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Doctor {
+                check_runtime: true,
+                formula: Some(ref name),
+                timeout: 3,
+                ..
+            } if name == "jq"
+        ));
+        assert!(cli.command.is_read_only());
+
+        let cli = Cli::try_parse_from(["zb", "doctor"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Doctor {
+                check_runtime: false,
+                timeout: 10,
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from(["zb", "doctor", "--formula", "jq"]).is_err());
+    }
+
+    #[test]
+    fn list_filters_are_exclusive() {
+        let cli = Cli::try_parse_from(["zb", "list", "--installed-by", "git", "--json"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::List {
+                installed_by: Some(ref name),
+                json: true,
+                ..
+            } if name == "git"
+        ));
+        assert!(cli.command.reads_installed_state());
+
+        assert!(Cli::try_parse_from(["zb", "list", "--explicit-only", "--deps-only"]).is_err());
+        assert!(
+            Cli::try_parse_from(["zb", "list", "--deps-only", "--installed-by", "git"]).is_err()
+        );
+    }
+
+    #[test]
+    fn history_reads_state_without_an_installer() {
+        let cli = Cli::try_parse_from(["zb", "history", "--json"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::History { json: true }
+        ));
+        assert!(cli.command.reads_installed_state());
+        assert!(cli.command.is_read_only());
+    }
+
+    #[test]
+    fn completion_accepts_every_clap_shell() {
+        use clap_complete::shells::Shell;
+
+        for (arg, shell) in [
+            ("bash", Shell::Bash),
+            ("elvish", Shell::Elvish),
+            ("fish", Shell::Fish),
+            ("powershell", Shell::PowerShell),
+            ("zsh", Shell::Zsh),
+        ] {
+            let cli = Cli::try_parse_from(["zb", "completion", arg]).unwrap();
+            assert!(
+                matches!(cli.command, super::Commands::Completion { shell: s, install: false } if s == shell),
+                "{arg}"
+            );
+        }
+    }
+
+    #[test]
+    fn info_files_accepts_the_long_alias_and_json() {
+        let cli = Cli::try_parse_from(["zb", "info", "jq", "--installed-files", "--json"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Info {
+                files: true,
+                json: true,
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from(["zb", "info", "jq", "--json"]).is_err());
+    }
+
+    #[test]
+    fn info_as_installed_reads_installed_state() {
+        let cli = Cli::try_parse_from(["zb", "info", "jq", "--as-installed"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Info {
+                as_installed: true,
+                ..
+            }
+        ));
+        assert!(cli.command.reads_installed_state());
+        assert!(Cli::try_parse_from(["zb", "info", "jq", "--as-installed", "--files"]).is_err());
+    }
+
+    #[test]
+    fn licenses_json_and_forbid() {
+        let cli =
+            Cli::try_parse_from(["zb", "licenses", "--json", "--forbid", "GPL-*, AGPL-*"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Licenses {
+                json: true,
+                forbid: Some(ref forbid),
+            } if forbid == "GPL-*, AGPL-*"
+        ));
+        assert!(cli.command.is_read_only());
+    }
+
+    #[test]
+    fn uninstall_dry_run_flags() {
+        let cli = Cli::try_parse_from([
+            "zb",
+            "uninstall",
+            "openssl@3",
+            "--dry-run",
+            "--ignore-dependencies",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Uninstall {
+                dry_run: true,
+                ignore_dependencies: true,
+                purge_config: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn uninstall_version_and_force() {
+        let cli = Cli::try_parse_from([
+            "zb",
+            "uninstall",
+            "python@3.11",
+            "--version",
+            "3.11.8",
+            "--force",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Uninstall {
+                version: Some(ref version),
+                force: true,
+                ..
+            } if version == "3.11.8"
+        ));
+        assert!(Cli::try_parse_from(["zb", "uninstall", "jq", "--force"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "uninstall", "--all", "--version", "1.7"]).is_err());
+    }
+
+    #[test]
+    fn porcelain_v1_is_selected_by_version() {
+        for args in [
+            &["zb", "list", "--porcelain=v1"][..],
+            &["zb", "outdated", "--porcelain", "v1"],
+            &["zb", "deps", "git", "--porcelain=v1"],
+            &["zb", "deps", "--missing", "--porcelain=v1"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert!(matches!(
+                cli.command,
+                super::Commands::List {
+                    porcelain: Some(Porcelain::V1),
+                    ..
+                } | super::Commands::Outdated {
+                    porcelain: Some(Porcelain::V1),
+                    ..
+                } | super::Commands::Deps {
+                    porcelain: Some(Porcelain::V1),
+                    ..
+                }
+            ));
+        }
+        assert!(Cli::try_parse_from(["zb", "list", "--porcelain=v2"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "list", "--porcelain=v1", "--json"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "outdated", "--porcelain=v1", "--json"]).is_err());
+        assert!(
+            Cli::try_parse_from(["zb", "deps", "--missing", "--fix", "--porcelain=v1"]).is_err()
+        );
+    }
+
+    #[test]
+    fn deps_missing_takes_an_optional_formula_and_fix() {
+        let cli = Cli::try_parse_from(["zb", "deps", "--missing", "--fix"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Deps {
+                formula: None,
+                tree: false,
+                missing: true,
+                fix: true,
+                porcelain: None
+            }
+        ));
+        assert!(Cli::try_parse_from(["zb", "deps", "git"]).is_ok());
+        assert!(Cli::try_parse_from(["zb", "deps", "git", "--missing"]).is_ok());
+        assert!(Cli::try_parse_from(["zb", "deps"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "deps", "git", "--fix"]).is_err());
+    }
+
+    #[test]
+    fn deps_tree_needs_a_formula_and_plain_output() {
+        let cli = Cli::try_parse_from(["zb", "deps", "git", "--tree"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Deps {
+                formula: Some(ref formula),
+                tree: true,
+                ..
+            } if formula == "git"
+        ));
+        assert!(Cli::try_parse_from(["zb", "deps", "--tree"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "deps", "git", "--tree", "--missing"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "deps", "git", "--tree", "--porcelain=v1"]).is_err());
+    }
+
+    #[test]
+    fn reset_takes_force_unowned() {
+        let cli = Cli::try_parse_from(["zb", "reset", "--force-unowned"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Reset {
+                force_unowned: true
+            }
+        ));
+    }
+
+    #[test]
+    fn relocate_requires_a_prefix() {
+        let cli = Cli::try_parse_from(["zb", "relocate", "--prefix", "/srv/zb"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Relocate { ref prefix } if prefix == std::path::Path::new("/srv/zb")
+        ));
+        assert!(Cli::try_parse_from(["zb", "relocate"]).is_err());
+    }
+
+    #[test]
+    fn rollback_takes_an_optional_version() {
+        let cli = Cli::try_parse_from(["zb", "rollback", "jq"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Rollback { ref formula, version: None } if formula == "jq"
+        ));
+        assert_eq!(cli.keep_versions, zb_io::DEFAULT_KEEP_VERSIONS);
+
+        let cli =
+            Cli::try_parse_from(["zb", "--keep-versions", "3", "rollback", "jq", "1.7"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Rollback { version: Some(ref version), .. } if version == "1.7"
+        ));
+        assert_eq!(cli.keep_versions, 3);
+    }
+
+    #[test]
+    fn tap_subcommands() {
+        let cli = Cli::try_parse_from(["zb", "tap"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Tap { command: None }
+        ));
+
+        let cli = Cli::try_parse_from(["zb", "tap", "add", "internal/tools", "--first"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Tap {
+                command: Some(super::TapCommands::Add { ref tap, first: true })
+            } if tap == "internal/tools"
+        ));
+
+        let cli = Cli::try_parse_from(["zb", "tap", "priority", "b/two", "a/one"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Tap {
+                command: Some(super::TapCommands::Priority { ref taps })
+            } if taps == &["b/two", "a/one"]
+        ));
+        assert!(Cli::try_parse_from(["zb", "tap", "priority"]).is_err());
+    }
+
+    #[test]
+    fn heartbeat_interval_defaults_to_thirty_seconds() {
+        let cli = Cli::try_parse_from(["zb", "install", "jq"]).unwrap();
+        assert_eq!(cli.heartbeat_interval, 30);
+        let cli =
+            Cli::try_parse_from(["zb", "install", "jq", "--heartbeat-interval", "5"]).unwrap();
+        assert_eq!(cli.heartbeat_interval, 5);
+    }
+
+    #[test]
+    fn no_prefetch_flag() {
+        let cli = Cli::try_parse_from(["zb", "init", "--no-prefetch"]).unwrap();
+        assert!(cli.no_prefetch);
+        let cli = Cli::try_parse_from(["zb", "init"]).unwrap();
+        assert!(!cli.no_prefetch);
+    }
+
+    #[test]
+    fn hook_flags() {
+        let cli = Cli::try_parse_from(["zb", "install", "jq"]).unwrap();
+        assert!(!cli.no_hooks);
+
+        let cli =
+            Cli::try_parse_from(["zb", "--hook-dir", "/etc/zb-hooks", "uninstall", "jq"]).unwrap();
+        assert_eq!(
+            cli.hook_dir.as_deref(),
+            Some(std::path::Path::new("/etc/zb-hooks"))
+        );
+
+        let cli = Cli::try_parse_from(["zb", "install", "jq", "--no-hooks"]).unwrap();
+        assert!(cli.no_hooks);
+
+        let cli =
+            Cli::try_parse_from(["zb", "install", "--hook-dir", "/etc/zb-hooks", "jq"]).unwrap();
+        assert_eq!(
+            cli.hook_dir.as_deref(),
+            Some(std::path::Path::new("/etc/zb-hooks"))
+        );
+    }
+
+    #[test]
+    fn gc_keep_flags_require_aggressive() {
+        let cli = Cli::try_parse_from(["zb", "gc", "--aggressive", "--keep-metadata", "--dry-run"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Gc {
+                aggressive: true,
+                keep_metadata: true,
+                keep_bottles: false,
+                dry_run: true,
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from(["zb", "gc", "--keep-temp"]).is_err());
+        assert!(Cli::try_parse_from(["zb", "gc", "--dry-run"]).is_ok());
+    }
+
+    #[test]
+    fn init_accepts_dry_run() {
+        let cli = Cli::try_parse_from(["zb", "init", "--dry-run", "--no-modify-path"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Init {
+                dry_run: true,
+                no_modify_path: true
+            }
+        ));
+    }
+
+    #[test]
+    fn env_json_and_export_conflict() {
+        let result = Cli::try_parse_from(["zb", "env", "openssl@3", "--json", "--export"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn run_with_takes_a_comma_separated_list_before_the_command() {
+        let cli = Cli::try_parse_from(["zb", "run", "--with", "cmake,ninja", "--", "make", "-j8"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Run { ref with, ref command }
+                if with == &["cmake", "ninja"] && command == &["make", "-j8"]
+        ));
+
+        let cli = Cli::try_parse_from(["zb", "run", "jq", "--with", "cmake"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            super::Commands::Run { ref with, ref command }
+                if with.is_empty() && command == &["jq", "--with", "cmake"]
+        ));
+        assert!(Cli::try_parse_from(["zb", "run", "--with", "cmake"]).is_err());
+    }
+
+    #[test]
+    fn only_inspecting_commands_are_read_only() {
+        let read_only = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("zb").chain(args.iter().copied()))
+                .unwrap()
+                .command
+                .is_read_only()
+        };
+
+        assert!(read_only(&["list"]));
+        assert!(read_only(&["doctor"]));
+        assert!(read_only(&["uninstall", "--dry-run", "jq"]));
+        assert!(read_only(&["bundle", "dump"]));
+        assert!(read_only(&["deps", "--missing"]));
+        assert!(read_only(&["tap"]));
+        assert!(read_only(&["tap", "list"]));
+        assert!(!read_only(&["tap", "add", "internal/tools"]));
+        assert!(!read_only(&["tap", "priority", "internal/tools"]));
+        assert!(!read_only(&["deps", "--missing", "--fix"]));
+        assert!(!read_only(&["doctor", "--repair"]));
+        assert!(!read_only(&["doctor", "--fix"]));
+        assert!(!read_only(&["uninstall", "jq"]));
+        assert!(!read_only(&["bundle"]));
+        assert!(!read_only(&["run", "jq"]));
+        assert!(!read_only(&["relocate", "--prefix", "/srv/zb"]));
+    }
+
+    #[test]
+    fn listing_and_info_only_read_installed_state() {
+        let reads_state = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("zb").chain(args.iter().copied()))
+                .unwrap()
+                .command
+                .reads_installed_state()
+        };
+
+        assert!(reads_state(&["list"]));
+        assert!(reads_state(&["info", "jq"]));
+        assert!(reads_state(&["info", "--system"]));
+        assert!(reads_state(&["info", "jq", "--files"]));
+        assert!(!reads_state(&["list", "--outdated-cache"]));
+        assert!(!reads_state(&["deps", "jq"]));
+        assert!(!reads_state(&["deps", "jq", "--porcelain=v1"]));
+        assert!(!reads_state(&["deps", "--missing"]));
+        assert!(!reads_state(&["why", "jq"]));
+    }
+
     #[test]
     fn outdated_verbose_and_json_conflict() {
         let result = Cli::try_parse_from(["zb", "outdated", "--verbose", "--json"]);
@@ -96,14 +899,48 @@ mod tests {
 #[derive(Subcommand)]
 pub enum Commands {
     Install {
+        /// Formulas to install; `-` reads whitespace-separated names from stdin
         #[arg(required = true, num_args = 1..)]
         formulas: Vec<String>,
         #[arg(long)]
         no_link: bool,
         #[arg(long, short = 's')]
         build_from_source: bool,
+        /// Resolve and write the install plan to FILE instead of installing
+        #[arg(long, value_name = "FILE")]
+        save_plan: Option<PathBuf>,
+        /// Abort everything as soon as one formula cannot be installed
+        #[arg(long)]
+        fail_fast: bool,
+        /// Keep group/world-writable permissions from bottles
+        #[arg(long)]
+        preserve_perms: bool,
+        /// Fail and roll back a formula when any of its files can't be patched
+        #[arg(long)]
+        strict_patching: bool,
+        /// Install formulas even if upstream has disabled them
+        #[arg(long, alias = "accept-deprecated")]
+        accept_disabled: bool,
+        /// Also report how many HTTP requests were made and how many
+        /// connections they needed
+        #[arg(long)]
+        timings: bool,
+    },
+    /// Install exactly what a plan saved with `install --save-plan` describes
+    Apply {
+        #[arg(value_name = "FILE")]
+        plan: PathBuf,
+        /// Replace formulas installed at a different version than planned
+        #[arg(long)]
+        reconcile: bool,
+        #[arg(long)]
+        no_link: bool,
     },
     Bundle {
+        /// Use the global Brewfile: ~/.Brewfile, or
+        /// $XDG_CONFIG_HOME/homebrew/Brewfile when only that exists
+        #[arg(long, global = true)]
+        global: bool,
         #[command(subcommand)]
         command: Option<BundleCommands>,
     },
@@ -112,59 +949,453 @@ pub enum Commands {
         formulas: Vec<String>,
         #[arg(long)]
         all: bool,
+        /// Also remove config files the formulas installed into the prefix
+        #[arg(long)]
+        purge_config: bool,
+        /// List the links, kegs and store entries that would be removed
+        /// without removing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Uninstall even when other installed formulas depend on these
+        #[arg(long)]
+        ignore_dependencies: bool,
+        /// Remove only this version of the formula, keeping the others
+        #[arg(long, value_name = "VERSION", conflicts_with = "all")]
+        version: Option<String>,
+        /// With --version, remove the active version and switch to the
+        /// newest remaining one
+        #[arg(long, requires = "version")]
+        force: bool,
     },
     Migrate {
-        #[arg(long, short = 'y')]
-        yes: bool,
         #[arg(long)]
         force: bool,
     },
-    List,
+    List {
+        /// Mark formulas whose cached metadata has a newer version, without
+        /// fetching anything; the hint is only as fresh as the cache
+        #[arg(long)]
+        outdated_cache: bool,
+        /// Only formulas installed on request, including those other
+        /// formulas also depend on
+        #[arg(long, conflicts_with_all = ["deps_only", "installed_by"])]
+        explicit_only: bool,
+        /// Only formulas installed as dependencies
+        #[arg(long, conflicts_with = "installed_by")]
+        deps_only: bool,
+        /// Only the dependencies installed because of FORMULA that weren't
+        /// requested themselves
+        #[arg(long, value_name = "FORMULA")]
+        installed_by: Option<String>,
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+        /// Stable output for scripts, never styled: one line per formula,
+        /// `name<TAB>version<TAB>store_key<TAB>installed_at<TAB>flags`, with
+        /// `installed_at` in seconds since the epoch and flags a
+        /// comma-separated set of `explicit`, `deprecated` and `disabled`, or
+        /// `-`. A version's format never changes
+        #[arg(
+            long,
+            value_enum,
+            value_name = "VERSION",
+            conflicts_with_all = ["json", "outdated_cache"]
+        )]
+        porcelain: Option<Porcelain>,
+    },
     Info {
-        formula: String,
+        #[arg(required_unless_present = "system")]
+        formula: Option<String>,
+        /// Show the zb version and platform recorded for this root
+        #[arg(long, conflicts_with = "formula")]
+        system: bool,
+        /// List every file in the keg and every path it put in the prefix
+        #[arg(long, alias = "installed-files", conflicts_with = "system")]
+        files: bool,
+        /// With --files, output as JSON
+        #[arg(long, requires = "files")]
+        json: bool,
+        /// Show the formula metadata the installed version was installed
+        /// from, without asking the API
+        #[arg(long, conflicts_with_all = ["system", "files"])]
+        as_installed: bool,
     },
-    Doctor {
+    /// Find formulas by name, alias or description
+    Search {
+        /// Matched case-insensitively; `openssl` also finds `openssl@3`
+        query: String,
+    },
+    /// Show the license of every installed formula
+    Licenses {
+        /// Output as JSON, one entry per formula sorted by name
         #[arg(long)]
+        json: bool,
+        /// Exit with an error if any formula is only licensed under these
+        /// SPDX identifiers, e.g. "GPL-*, AGPL-3.0-only"
+        #[arg(long, value_name = "LICENSES")]
+        forbid: Option<String>,
+    },
+    Doctor {
+        /// Fix what can be fixed in place, such as re-signing binaries
+        #[arg(long, alias = "fix")]
         repair: bool,
+        /// Also run each installed formula's main executable with
+        /// `--version`, reporting any the loader can't start
+        #[arg(long)]
+        check_runtime: bool,
+        /// With --check-runtime, only run this formula's executable
+        #[arg(long, value_name = "NAME", requires = "check_runtime")]
+        formula: Option<String>,
+        /// With --check-runtime, kill an executable still running after SECS
+        #[arg(
+            long,
+            value_name = "SECS",
+            requires = "check_runtime",
+            default_value_t = zb_io::DEFAULT_RUNTIME_TIMEOUT.as_secs()
+        )]
+        timeout: u64,
+    },
+    /// Remove store entries no installed formula uses
+    Gc {
+        /// Also remove cached bottles and API metadata, old keg versions and temporary files
+        #[arg(long)]
+        aggressive: bool,
+        /// With --aggressive, keep the cached API metadata
+        #[arg(long, requires = "aggressive")]
+        keep_metadata: bool,
+        /// With --aggressive, keep downloaded bottles
+        #[arg(long, requires = "aggressive")]
+        keep_bottles: bool,
+        /// With --aggressive, keep keg versions that aren't installed
+        #[arg(long, requires = "aggressive")]
+        keep_old_versions: bool,
+        /// With --aggressive, keep partial downloads and extractions
+        #[arg(long, requires = "aggressive")]
+        keep_temp: bool,
+        /// Compress unreferenced store entries into ROOT/archive instead of
+        /// deleting them; an install that needs one expands it again
+        #[arg(long)]
+        archive: bool,
+        /// Report what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
     },
-    Gc,
     Reset {
-        #[arg(long, short = 'y')]
-        yes: bool,
+        /// Clear the prefix even when it isn't marked as belonging to this
+        /// zerobrew root
+        #[arg(long)]
+        force_unowned: bool,
+    },
+    /// Relink a previous version of a formula that an upgrade kept
+    Rollback {
+        formula: String,
+        /// Version to go back to; defaults to the one the last upgrade replaced
+        version: Option<String>,
+    },
+    /// Move every installed keg to a new prefix, rewriting the paths baked into them
+    Relocate {
+        /// Absolute path of the new prefix; must be empty or not exist yet
+        #[arg(long, value_name = "DIR")]
+        prefix: PathBuf,
     },
     Init {
         #[arg(long)]
         no_modify_path: bool,
+        /// Print the directories, ownership changes and shell config edits
+        /// init would make, without making them
+        #[arg(long)]
+        dry_run: bool,
     },
     Completion {
+        /// Shell to generate the script for; `--install` supports bash, zsh
+        /// and fish, the others are printed for you to put in place
         #[arg(value_enum)]
         shell: clap_complete::shells::Shell,
+
+        /// Write the script into the prefix instead of printing it
+        #[arg(long)]
+        install: bool,
     },
     #[command(disable_help_flag = true)]
     Run {
-        formula: String,
-        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
-        args: Vec<String>,
+        /// Formulas whose bin directories go on PATH ahead of the prefix, with
+        /// their build flags exported as `zb env` prints them. The command is
+        /// then looked up on PATH rather than taken from a formula
+        #[arg(long, value_delimiter = ',', value_name = "FORMULA")]
+        with: Vec<String>,
+        /// The formula to run (or with --with, the command) and its arguments.
+        /// Everything from here on is passed through, flags included
+        #[arg(
+            required = true,
+            value_name = "FORMULA",
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        command: Vec<String>,
     },
     Update,
+    /// Install the newer versions of installed formulas
+    Upgrade {
+        /// Formulas to upgrade (default: every outdated formula)
+        formulas: Vec<String>,
+        /// List the upgrades, grouped by how far each version moves, without
+        /// installing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// With --dry-run, output the groups as JSON
+        #[arg(long, requires = "dry_run", conflicts_with_all = ["quiet", "verbose"])]
+        json: bool,
+        /// Also upgrade formulas whose only change is a rebuild of the same
+        /// release (`1.2.3` -> `1.2.3_1`)
+        #[arg(long)]
+        include_rebuilds: bool,
+        /// Also upgrade formulas upstream has disabled
+        #[arg(long, alias = "accept-deprecated")]
+        accept_disabled: bool,
+        #[arg(long)]
+        no_link: bool,
+    },
+    /// Hold installed formulas at their version; upgrades skip them
+    Pin {
+        #[arg(required = true, num_args = 1..)]
+        formulas: Vec<String>,
+    },
+    /// Let upgrades replace pinned formulas again
+    Unpin {
+        #[arg(required = true, num_args = 1..)]
+        formulas: Vec<String>,
+    },
     Outdated {
-        /// Output as JSON
+        /// Output as JSON, one entry per formula sorted by name
         #[arg(long, conflicts_with_all = ["quiet", "verbose"])]
         json: bool,
+        /// Also list formulas whose only change is a rebuild of the same
+        /// release (`1.2.3` -> `1.2.3_1`)
+        #[arg(long)]
+        include_rebuilds: bool,
+        /// Stable output for scripts, never styled: one line per formula,
+        /// `name<TAB>installed_version<TAB>current_version<TAB>flags`, sorted
+        /// by name, with flags `rebuild`, `unknown` (the current version is
+        /// then `-`) or `-`. A version's format never changes
+        #[arg(long, value_enum, value_name = "VERSION", conflicts_with = "json")]
+        porcelain: Option<Porcelain>,
+    },
+    /// List the dependencies of a formula, direct or not, installed or not
+    Deps {
+        /// Formula whose dependencies to list
+        #[arg(required_unless_present = "missing")]
+        formula: Option<String>,
+        /// Show how the dependencies are reached, as a tree
+        #[arg(long, requires = "formula", conflicts_with_all = ["missing", "porcelain"])]
+        tree: bool,
+        /// With FORMULA, print the dependencies it needs that aren't
+        /// installed, one per line, ready for `zb install`. Without, report
+        /// recorded dependencies that aren't installed or whose keg is gone,
+        /// for every installed formula
+        #[arg(long)]
+        missing: bool,
+        /// Install the missing dependencies
+        #[arg(long, requires = "missing")]
+        fix: bool,
+        /// Stable output for scripts, never styled: `formula<TAB>dependency`
+        /// per dependency of FORMULA, direct or not, sorted, and with --missing
+        /// `formula<TAB>dependency<TAB>recorded_version`, the version `-` when
+        /// it was never installed. A version's format never changes
+        #[arg(long, value_enum, value_name = "VERSION", conflicts_with = "fix")]
+        porcelain: Option<Porcelain>,
+    },
+    /// List or change the taps unqualified formula names resolve against
+    Tap {
+        #[command(subcommand)]
+        command: Option<TapCommands>,
+    },
+    /// Show when gc and cache clean ran, what they removed and who ran them
+    History {
+        /// Output as JSON with each run's details in full, oldest first
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show which explicitly installed formulas require a formula
+    Why {
+        formula: String,
+        /// Show every dependency chain instead of the shortest per formula
+        #[arg(long)]
+        all_paths: bool,
+        /// Output as JSON; paths come in the same order on every run
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print CPPFLAGS, LDFLAGS and PKG_CONFIG_PATH for building against installed kegs
+    Env {
+        #[arg(required = true, num_args = 1..)]
+        formulas: Vec<String>,
+        /// Output as JSON with formulas and variables sorted by name
+        #[arg(long, conflicts_with = "export")]
+        json: bool,
+        /// Output as shell `export` lines
+        #[arg(long)]
+        export: bool,
+        /// Merge the paths of all formulas into one set of variables
+        #[arg(long)]
+        combined: bool,
+    },
+    /// Capture formula metadata for reproducible installs (see ZEROBREW_SNAPSHOT)
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommands,
+    },
+    /// Maintain the downloaded bottle cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+}
+
+impl Commands {
+    /// Whether the command looks names up in the bulk formula index, for
+    /// aliases and suggestions, so fetching it early saves it the wait.
+    pub fn uses_formula_index(&self) -> bool {
+        matches!(
+            self,
+            Self::Install { .. }
+                | Self::Outdated { .. }
+                | Self::Search { .. }
+                | Self::Migrate { .. }
+                | Self::Bundle {
+                    command: None | Some(BundleCommands::Install { .. }),
+                    ..
+                }
+        )
+    }
+
+    /// Whether the command only reports what is recorded as installed, so it
+    /// runs without init or an installer and creates nothing.
+    pub fn reads_installed_state(&self) -> bool {
+        matches!(
+            self,
+            Self::List {
+                outdated_cache: false,
+                ..
+            } | Self::Info { .. }
+                | Self::History { .. }
+        )
+    }
+
+    /// Whether the command leaves kegs, links and records alone. Only these
+    /// run while the kegs were patched for another prefix.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Self::List { .. }
+            | Self::Info { .. }
+            | Self::Search { .. }
+            | Self::Outdated { .. }
+            | Self::Why { .. }
+            | Self::History { .. }
+            | Self::Licenses { .. }
+            | Self::Deps { fix: false, .. }
+            | Self::Tap {
+                command: None | Some(TapCommands::List),
+            }
+            | Self::Env { .. }
+            | Self::Update
+            | Self::Snapshot { .. }
+            | Self::Completion { .. }
+            | Self::Bundle {
+                command: Some(BundleCommands::Dump { .. }),
+                ..
+            } => true,
+            Self::Doctor { repair, .. } => !repair,
+            Self::Uninstall { dry_run, .. }
+            | Self::Gc { dry_run, .. }
+            | Self::Upgrade { dry_run, .. } => *dry_run,
+            _ => false,
+        }
+    }
+}
+
+/// Names without a tap resolve to homebrew/core first, then to the first
+/// configured tap that has them.
+#[derive(Subcommand)]
+pub enum TapCommands {
+    /// List configured taps, highest priority first (the default)
+    List,
+    /// Add a tap (OWNER/REPO), after the existing ones
+    Add {
+        tap: String,
+        /// Give it the highest priority instead
+        #[arg(long)]
+        first: bool,
+    },
+    /// Stop resolving names against a tap; formulas installed from it stay
+    Remove { tap: String },
+    /// Move these taps to the top of the priority order, in the order given
+    Priority {
+        #[arg(required = true)]
+        taps: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotCommands {
+    /// Write the formula index plus installed (or listed) formulas to FILE
+    Create {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+        /// Formulas to capture instead of the installed ones
+        formulas: Vec<String>,
+    },
+    /// Show when a snapshot was taken and how many formulas it holds
+    Info {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// Rebuild the index of cached bottles from the files in the cache
+    RebuildIndex,
+    /// Delete store entries archived by `zb gc --archive`, or cached
+    /// bottles no installed formula was extracted from
+    Clean {
+        #[arg(long, required_unless_present = "bottles")]
+        archives: bool,
+        #[arg(long)]
+        bottles: bool,
+        /// Only archives made, or bottles last used, more than DAYS days ago
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
     },
 }
 
 #[derive(Subcommand)]
 pub enum BundleCommands {
     Install {
-        #[arg(long, short = 'f', value_name = "FILE", default_value = "Brewfile")]
-        file: PathBuf,
+        /// Brewfile to install from (default: Brewfile); repeat to merge
+        /// several, later files taking over entries listed twice
+        #[arg(long, short = 'f', value_name = "FILE")]
+        file: Vec<PathBuf>,
         #[arg(long)]
         no_link: bool,
+        /// Upgrade entries installed at an older version
+        #[arg(long, conflicts_with = "no_upgrade")]
+        upgrade: bool,
+        /// Leave installed entries at their version (the default; accepted
+        /// for brew compatibility)
+        #[arg(long)]
+        no_upgrade: bool,
+        /// With --upgrade, also upgrade entries whose only change is a
+        /// rebuild of the same release
+        #[arg(long, requires = "upgrade")]
+        include_rebuilds: bool,
+        /// With --upgrade, also upgrade entries upstream has disabled
+        #[arg(long, requires = "upgrade", alias = "accept-deprecated")]
+        accept_disabled: bool,
     },
     Dump {
-        #[arg(long, short = 'f', value_name = "FILE", default_value = "Brewfile")]
-        file: PathBuf,
+        /// File to write (default: Brewfile)
+        #[arg(long, short = 'f', value_name = "FILE")]
+        file: Option<PathBuf>,
         #[arg(long)]
         force: bool,
     },
@@ -0,0 +1,66 @@
+use console::style;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::commands::install::execute_plan;
+use crate::format::human_duration;
+use crate::ui::StdUi;
+
+pub async fn execute(
+    installer: &mut zb_io::Installer,
+    plan_path: &Path,
+    reconcile: bool,
+    no_link: bool,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let start = Instant::now();
+    let read_ctx = format!("failed to read install plan {}", plan_path.display());
+    let json =
+        std::fs::read_to_string(plan_path).map_err(zb_core::Error::file(read_ctx.as_str()))?;
+    let saved = zb_io::SavedPlan::from_json(&json)?;
+
+    ui.heading(format!(
+        "Applying saved plan ({} packages)...",
+        saved.items.len()
+    ))
+    .map_err(ui_error)?;
+    for item in &saved.items {
+        ui.bullet(format!(
+            "{} {}",
+            style(&item.name).green(),
+            style(&item.version).dim()
+        ))
+        .map_err(ui_error)?;
+    }
+
+    let requested: Vec<String> = saved
+        .items
+        .iter()
+        .map(|item| item.install_name.clone())
+        .collect();
+    let plan = installer.prepare_saved_plan(saved, reconcile)?;
+
+    let installed = if plan.items.is_empty() {
+        0
+    } else {
+        execute_plan(installer, plan, no_link, &requested, ui)
+            .await?
+            .installed
+    };
+
+    ui.blank_line().map_err(ui_error)?;
+    ui.heading(format!(
+        "Installed {} packages in {}",
+        style(installed).green().bold(),
+        human_duration(start.elapsed())
+    ))
+    .map_err(ui_error)?;
+
+    Ok(())
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::FileError {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
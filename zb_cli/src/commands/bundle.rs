@@ -1,52 +1,321 @@
 use console::style;
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
+use zb_io::BundleEntryState;
+
 use super::install;
 use crate::cli::BundleCommands;
+use crate::format::human_duration;
 use crate::ui::StdUi;
+use crate::utils::{normalize_formula_name, suggest_missing_formula_matches};
+
+const DEFAULT_BREWFILE: &str = "Brewfile";
 
 pub async fn execute(
     installer: &mut zb_io::Installer,
+    global: bool,
     command: Option<BundleCommands>,
     ui: &mut StdUi,
 ) -> Result<(), zb_core::Error> {
     match command.unwrap_or(BundleCommands::Install {
-        file: PathBuf::from("Brewfile"),
+        file: Vec::new(),
         no_link: false,
+        upgrade: false,
+        no_upgrade: false,
+        include_rebuilds: false,
+        accept_disabled: false,
     }) {
-        BundleCommands::Install { file, no_link } => {
-            install_from_file(installer, &file, no_link, ui).await
+        BundleCommands::Install {
+            file,
+            no_link,
+            upgrade,
+            include_rebuilds,
+            accept_disabled,
+            ..
+        } => {
+            let files = match (global, file.is_empty()) {
+                (true, false) => return Err(global_with_file()),
+                (true, true) => vec![global_brewfile_from_env()?],
+                (false, true) => vec![PathBuf::from(DEFAULT_BREWFILE)],
+                (false, false) => file,
+            };
+            install_from_files(
+                installer,
+                &files,
+                no_link,
+                upgrade,
+                include_rebuilds,
+                accept_disabled,
+                ui,
+            )
+            .await
+        }
+        BundleCommands::Dump { file, force } => {
+            let file = match (global, file) {
+                (true, Some(_)) => return Err(global_with_file()),
+                (true, None) => global_brewfile_from_env()?,
+                (false, file) => file.unwrap_or_else(|| PathBuf::from(DEFAULT_BREWFILE)),
+            };
+            dump_to_file(installer, &file, force)
+        }
+    }
+}
+
+fn global_with_file() -> zb_core::Error {
+    zb_core::Error::InvalidArgument {
+        message: "--global and --file can't be used together".to_string(),
+    }
+}
+
+fn global_brewfile_from_env() -> Result<PathBuf, zb_core::Error> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    let home = var("HOME").ok_or_else(|| zb_core::Error::InvalidArgument {
+        message: "--global needs HOME to be set".to_string(),
+    })?;
+    Ok(global_brewfile(
+        Path::new(&home),
+        var("XDG_CONFIG_HOME").as_deref().map(Path::new),
+    ))
+}
+
+/// `~/.Brewfile`, like brew's `--global`. When that doesn't exist but
+/// `$XDG_CONFIG_HOME/homebrew/Brewfile` (`~/.config` when unset) does, that
+/// one is used instead.
+fn global_brewfile(home: &Path, config_home: Option<&Path>) -> PathBuf {
+    let dotfile = home.join(".Brewfile");
+    if dotfile.exists() {
+        return dotfile;
+    }
+    let xdg = config_home
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| home.join(".config"))
+        .join("homebrew/Brewfile");
+    if xdg.exists() { xdg } else { dotfile }
+}
+
+/// How a Brewfile entry ended up, as the bundle summary reports it.
+enum EntryStatus {
+    Installed,
+    Upgraded {
+        from: String,
+        to: String,
+    },
+    Present,
+    /// Installed at an older version, left alone without `--upgrade`.
+    PresentOlder {
+        available: String,
+    },
+    /// Installed at an older version upstream has disabled, left alone
+    /// without `--accept-disabled`.
+    PresentDisabled {
+        available: String,
+    },
+    /// Installed at an older version and pinned, left alone by `--upgrade`.
+    PresentPinned {
+        available: String,
+    },
+    /// Installed, but whether it is behind couldn't be checked.
+    PresentUnchecked {
+        reason: String,
+    },
+    /// A directive with no zerobrew equivalent, such as `mas`.
+    Unsupported,
+    Failed,
+}
+
+impl EntryStatus {
+    fn label(&self) -> String {
+        match self {
+            Self::Installed => "installed".to_string(),
+            Self::Upgraded { from, to } => format!("upgraded ({from} -> {to})"),
+            Self::Present => "present".to_string(),
+            Self::PresentOlder { available } => format!("present (older; {available} available)"),
+            Self::PresentDisabled { available } => format!(
+                "present (older; {available} is disabled upstream, pass --accept-disabled to upgrade)"
+            ),
+            Self::PresentPinned { available } => {
+                format!("present (older; {available} available, pinned)")
+            }
+            Self::PresentUnchecked { reason } => {
+                format!("present (couldn't check for updates: {reason})")
+            }
+            Self::Unsupported => "skipped (unsupported)".to_string(),
+            Self::Failed => "failed".to_string(),
+        }
+    }
+
+    /// The summary count this status adds to.
+    fn category(&self) -> usize {
+        match self {
+            Self::Installed => 0,
+            Self::Upgraded { .. } => 1,
+            Self::Present
+            | Self::PresentOlder { .. }
+            | Self::PresentDisabled { .. }
+            | Self::PresentPinned { .. }
+            | Self::PresentUnchecked { .. } => 2,
+            Self::Unsupported => 3,
+            Self::Failed => 4,
         }
-        BundleCommands::Dump { file, force } => dump_to_file(installer, &file, force),
     }
 }
 
-async fn install_from_file(
+const CATEGORIES: [&str; 5] = [
+    "installed",
+    "upgraded",
+    "present",
+    "skipped (unsupported)",
+    "failed",
+];
+
+async fn install_from_files(
     installer: &mut zb_io::Installer,
-    manifest_path: &Path,
+    manifest_paths: &[PathBuf],
     no_link: bool,
+    upgrade: bool,
+    include_rebuilds: bool,
+    accept_disabled: bool,
     ui: &mut StdUi,
 ) -> Result<(), zb_core::Error> {
-    let formulas = load_manifest(manifest_path)?;
-    println!(
-        "{} Installing {} formulas from {}...",
-        style("==>").cyan().bold(),
-        style(formulas.len()).green().bold(),
-        manifest_path.display()
-    );
+    let manifest = load_manifests(manifest_paths)?;
+    let sources: Vec<String> = manifest_paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    ui.heading(format!(
+        "Installing {} formulas from {}...",
+        style(manifest.entries.len()).green().bold(),
+        sources.join(", ")
+    ))
+    .map_err(ui_error)?;
+    // With several files, show which one each entry was taken from.
+    if manifest_paths.len() > 1 {
+        for entry in &manifest.entries {
+            ui.bullet(format!("{} ({})", entry.name, style(&entry.source).dim()))
+                .map_err(ui_error)?;
+        }
+    }
 
     let start = Instant::now();
-    for formula in formulas {
-        install::execute(installer, vec![formula], no_link, false, ui).await?;
+    let mut formulas = Vec::new();
+    let mut casks = Vec::new();
+    for entry in &manifest.entries {
+        let name = normalize_formula_name(&entry.name)?;
+        if name.starts_with("cask:") {
+            casks.push(name);
+        } else {
+            formulas.push(name);
+        }
     }
 
-    println!(
-        "{} Finished installing manifest in {:.2}s",
-        style("==>").cyan().bold(),
-        start.elapsed().as_secs_f64()
-    );
+    let mut statuses: Vec<(String, EntryStatus)> = Vec::with_capacity(manifest.entries.len());
+    let mut error: Option<zb_core::Error> = None;
+
+    if !formulas.is_empty() {
+        let bundle = match installer
+            .plan_bundle(&formulas, false, upgrade, include_rebuilds, accept_disabled)
+            .await
+        {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                suggest_missing_formula_matches(installer, &e).await;
+                return Err(e);
+            }
+        };
+
+        if !bundle.plan.items.is_empty() {
+            install::print_resolved(&bundle.plan, ui)?;
+
+            if let Err(e) =
+                install::execute_plan(installer, bundle.plan, no_link, &formulas, ui).await
+            {
+                error = Some(e);
+            }
+        }
+
+        for entry in bundle.entries {
+            let installed = installer.get_installed(&entry.name)?.map(|keg| keg.version);
+            let status = match entry.state {
+                BundleEntryState::Present => EntryStatus::Present,
+                BundleEntryState::PresentOlder { available, .. } => {
+                    EntryStatus::PresentOlder { available }
+                }
+                BundleEntryState::UpgradeDisabled { available, .. } => {
+                    EntryStatus::PresentDisabled { available }
+                }
+                BundleEntryState::UpgradePinned { available, .. } => {
+                    EntryStatus::PresentPinned { available }
+                }
+                BundleEntryState::Unchecked { reason, .. } => {
+                    EntryStatus::PresentUnchecked { reason }
+                }
+                BundleEntryState::Install if installed.is_some() => EntryStatus::Installed,
+                BundleEntryState::Upgrade {
+                    installed: from,
+                    available,
+                } if installed.as_ref() == Some(&available) => EntryStatus::Upgraded {
+                    from,
+                    to: available,
+                },
+                BundleEntryState::Install | BundleEntryState::Upgrade { .. } => EntryStatus::Failed,
+            };
+            statuses.push((entry.name, status));
+        }
+    }
+
+    for cask in casks {
+        match installer
+            .install_casks(std::slice::from_ref(&cask), !no_link)
+            .await
+        {
+            Ok(_) => statuses.push((cask, EntryStatus::Installed)),
+            Err(e) => {
+                statuses.push((cask, EntryStatus::Failed));
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    for directive in manifest.unsupported {
+        statuses.push((directive, EntryStatus::Unsupported));
+    }
+
+    ui.heading("Bundle summary:").map_err(ui_error)?;
+    let mut counts = [0usize; CATEGORIES.len()];
+    for (name, status) in &statuses {
+        counts[status.category()] += 1;
+        let label = match status {
+            EntryStatus::Failed => style(status.label()).red(),
+            EntryStatus::PresentOlder { .. }
+            | EntryStatus::PresentDisabled { .. }
+            | EntryStatus::PresentPinned { .. }
+            | EntryStatus::PresentUnchecked { .. }
+            | EntryStatus::Unsupported => style(status.label()).yellow(),
+            _ => style(status.label()).green(),
+        };
+        ui.bullet(format!("{} {label}", style(name).bold()))
+            .map_err(ui_error)?;
+    }
+    let summary: Vec<String> = CATEGORIES
+        .iter()
+        .zip(counts)
+        .filter(|(category, count)| **category != "failed" || *count > 0)
+        .map(|(category, count)| format!("{count} {category}"))
+        .collect();
+    ui.info(summary.join(", ")).map_err(ui_error)?;
+
+    if let Some(e) = error {
+        return Err(e);
+    }
+
+    ui.heading(format!(
+        "Finished installing manifest in {}",
+        human_duration(start.elapsed())
+    ))
+    .map_err(ui_error)?;
     Ok(())
 }
 
@@ -84,54 +353,163 @@ fn dump_to_file(
     Ok(())
 }
 
-fn load_manifest(path: &Path) -> Result<Vec<String>, zb_core::Error> {
+#[derive(Debug)]
+struct Manifest {
+    entries: Vec<ManifestEntry>,
+    /// Directives zerobrew has no equivalent for, as written.
+    unsupported: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ManifestEntry {
+    /// The formula, or `cask:<token>` for a cask.
+    name: String,
+    /// Whatever follows the name, such as `args: ["HEAD"]`, with
+    /// whitespace collapsed.
+    options: String,
+    source: Source,
+}
+
+/// The line a Brewfile entry was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Source {
+    file: PathBuf,
+    line: usize,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file.display(), self.line)
+    }
+}
+
+/// Read every Brewfile in `paths` and merge their entries in order. An
+/// entry listed again, in the same or a later file, takes over the earlier
+/// one's source; listed again with other options, it's an error naming
+/// both lines.
+fn load_manifests(paths: &[PathBuf]) -> Result<Manifest, zb_core::Error> {
+    let mut merged = Manifest {
+        entries: Vec::new(),
+        unsupported: Vec::new(),
+    };
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for path in paths {
+        let manifest = load_manifest(path)?;
+        for entry in manifest.entries {
+            let Some(&position) = positions.get(&entry.name) else {
+                positions.insert(entry.name.clone(), merged.entries.len());
+                merged.entries.push(entry);
+                continue;
+            };
+            let earlier = &mut merged.entries[position];
+            if earlier.options != entry.options {
+                return Err(zb_core::Error::InvalidArgument {
+                    message: format!(
+                        "conflicting Brewfile entries for {}: {} at {} and {} at {}",
+                        entry.name,
+                        describe_options(&earlier.options),
+                        earlier.source,
+                        describe_options(&entry.options),
+                        entry.source
+                    ),
+                });
+            }
+            earlier.source = entry.source;
+        }
+        for directive in manifest.unsupported {
+            if !merged.unsupported.contains(&directive) {
+                merged.unsupported.push(directive);
+            }
+        }
+    }
+
+    Ok(merged)
+}
+
+fn describe_options(options: &str) -> String {
+    if options.is_empty() {
+        "no options".to_string()
+    } else {
+        format!("'{options}'")
+    }
+}
+
+/// Read one Brewfile. Entries come back in file order, duplicates
+/// included, for [`load_manifests`] to merge.
+fn load_manifest(path: &Path) -> Result<Manifest, zb_core::Error> {
     let contents = std::fs::read_to_string(path).map_err(|e| zb_core::Error::FileError {
         message: format!("failed to read manifest {}: {}", path.display(), e),
     })?;
 
     let mut formulas = Vec::new();
-    let mut seen: HashSet<String> = HashSet::new();
+    let mut unsupported = Vec::new();
 
-    for line in contents.lines() {
+    for (index, line) in contents.lines().enumerate() {
         // Handle inline comments by splitting on '#' and taking the first part
         let entry = line.split('#').next().unwrap_or("").trim();
         if entry.is_empty() {
             continue;
         }
 
-        if let Some(parsed) = parse_brewfile_entry(entry)
-            && seen.insert(parsed.clone())
-        {
-            formulas.push(parsed);
+        if is_unsupported_directive(entry) {
+            unsupported.push(entry.to_string());
+        } else if let Some((name, options)) = parse_brewfile_entry(entry) {
+            formulas.push(ManifestEntry {
+                name,
+                options,
+                source: Source {
+                    file: path.to_path_buf(),
+                    line: index + 1,
+                },
+            });
         }
     }
 
-    if formulas.is_empty() {
+    if formulas.is_empty() && unsupported.is_empty() {
         return Err(zb_core::Error::FileError {
             message: format!("manifest {} did not contain any formulas", path.display()),
         });
     }
 
-    Ok(formulas)
+    Ok(Manifest {
+        entries: formulas,
+        unsupported,
+    })
+}
+
+/// A Brewfile directive other than `tap`, `brew` and `cask`, such as
+/// `mas "Xcode", id: 497799835` or `vscode "rust-lang.rust-analyzer"`.
+/// A bare formula name never contains a quote, so it can't match.
+fn is_unsupported_directive(line: &str) -> bool {
+    let Some((directive, rest)) = line.split_once(char::is_whitespace) else {
+        return false;
+    };
+    !matches!(directive, "tap" | "brew" | "cask")
+        && directive
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c == '_')
+        && rest.trim_start().starts_with(['"', '\''])
 }
 
-fn parse_brewfile_entry(line: &str) -> Option<String> {
+/// The entry's name and its options (see [`ManifestEntry::options`]).
+fn parse_brewfile_entry(line: &str) -> Option<(String, String)> {
     if line.starts_with("tap ") {
         return None;
     }
 
-    if let Some(token) = parse_quoted_directive(line, "cask") {
-        return Some(format!("cask:{token}"));
+    if let Some((token, options)) = parse_quoted_directive(line, "cask") {
+        return Some((format!("cask:{token}"), options));
     }
 
-    if let Some(formula) = parse_quoted_directive(line, "brew") {
-        return Some(formula.to_string());
+    if let Some((formula, options)) = parse_quoted_directive(line, "brew") {
+        return Some((formula.to_string(), options));
     }
 
-    Some(line.to_string())
+    Some((line.to_string(), String::new()))
 }
 
-fn parse_quoted_directive<'a>(line: &'a str, directive: &str) -> Option<&'a str> {
+fn parse_quoted_directive<'a>(line: &'a str, directive: &str) -> Option<(&'a str, String)> {
     if !line.starts_with(directive) {
         return None;
     }
@@ -144,7 +522,15 @@ fn parse_quoted_directive<'a>(line: &'a str, directive: &str) -> Option<&'a str>
 
     let tail = &rest[1..];
     let end = tail.find(quote)?;
-    Some(&tail[..end])
+    let options = tail[end + 1..].trim_start().trim_start_matches(',');
+    let options = options.split_whitespace().collect::<Vec<_>>().join(" ");
+    Some((&tail[..end], options))
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::FileError {
+        message: format!("failed to write CLI output: {err}"),
+    }
 }
 
 #[cfg(test)]
@@ -152,6 +538,14 @@ mod tests {
     use super::*;
     use std::io::Write;
 
+    fn names(manifest: Manifest) -> Vec<String> {
+        manifest.entries.into_iter().map(|e| e.name).collect()
+    }
+
+    fn entry(name: &str, options: &str) -> (String, String) {
+        (name.to_string(), options.to_string())
+    }
+
     #[test]
     fn load_manifest_parses_entries_ignoring_whitespace_and_comments() {
         let mut file = tempfile::NamedTempFile::new().unwrap();
@@ -161,7 +555,7 @@ mod tests {
         )
         .unwrap();
 
-        let entries = load_manifest(file.path()).unwrap();
+        let entries = names(load_manifests(&[file.path().to_path_buf()]).unwrap());
         assert_eq!(entries, vec!["jq", "wget", "git"]);
     }
 
@@ -174,7 +568,7 @@ mod tests {
         )
         .unwrap();
 
-        let entries = load_manifest(file.path()).unwrap();
+        let entries = names(load_manifest(file.path()).unwrap());
         assert_eq!(entries, vec!["jq", "wget", "git"]);
     }
 
@@ -215,16 +609,37 @@ mod tests {
         )
         .unwrap();
 
-        let entries = load_manifest(file.path()).unwrap();
+        let entries = names(load_manifest(file.path()).unwrap());
         assert_eq!(entries, vec!["wget", "cask:docker-desktop"]);
     }
 
+    #[test]
+    fn load_manifest_sets_aside_unsupported_directives() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "brew \"jq\"\nmas \"Xcode\", id: 497799835\nvscode 'rust-lang.rust-analyzer'\ngit\n"
+        )
+        .unwrap();
+
+        let manifest = load_manifest(file.path()).unwrap();
+        assert_eq!(
+            manifest.unsupported,
+            vec![
+                "mas \"Xcode\", id: 497799835",
+                "vscode 'rust-lang.rust-analyzer'"
+            ]
+        );
+        assert_eq!(names(manifest), vec!["jq", "git"]);
+    }
+
     #[test]
     fn parse_brewfile_entry_handles_brew_directive() {
-        assert_eq!(parse_brewfile_entry("brew \"jq\""), Some("jq".to_string()));
+        assert_eq!(parse_brewfile_entry("brew \"jq\""), Some(entry("jq", "")));
+        assert_eq!(parse_brewfile_entry("brew 'wget'"), Some(entry("wget", "")));
         assert_eq!(
-            parse_brewfile_entry("brew 'wget'"),
-            Some("wget".to_string())
+            parse_brewfile_entry("brew \"vim\",   args: [\"HEAD\"]"),
+            Some(entry("vim", "args: [\"HEAD\"]"))
         );
     }
 
@@ -232,8 +647,82 @@ mod tests {
     fn parse_brewfile_entry_handles_cask_directive() {
         assert_eq!(
             parse_brewfile_entry("cask \"docker\""),
-            Some("cask:docker".to_string())
+            Some(entry("cask:docker", ""))
+        );
+    }
+
+    fn write_brewfile(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn later_files_take_over_duplicate_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = write_brewfile(dir.path(), "Brewfile", "brew \"jq\"\nbrew \"git\"\n");
+        let dev = write_brewfile(
+            dir.path(),
+            "Brewfile.dev",
+            "brew \"ripgrep\"\nbrew 'jq'\nmas \"Xcode\", id: 1\n",
         );
+
+        let manifest = load_manifests(&[base.clone(), dev.clone()]).unwrap();
+        let sources: Vec<(&str, String)> = manifest
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.source.to_string()))
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                ("jq", format!("{}:2", dev.display())),
+                ("git", format!("{}:2", base.display())),
+                ("ripgrep", format!("{}:1", dev.display())),
+            ]
+        );
+        assert_eq!(manifest.unsupported, ["mas \"Xcode\", id: 1"]);
+    }
+
+    #[test]
+    fn duplicates_with_other_options_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = write_brewfile(dir.path(), "Brewfile", "brew \"git\"\nbrew \"vim\"\n");
+        let ci = write_brewfile(
+            dir.path(),
+            "Brewfile.ci",
+            "brew \"vim\", args: [\"HEAD\"]\n",
+        );
+
+        let err = load_manifests(&[base.clone(), ci.clone()]).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains(&format!("no options at {}:2", base.display())),
+            "{message}"
+        );
+        assert!(
+            message.contains(&format!("'args: [\"HEAD\"]' at {}:1", ci.display())),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn global_brewfile_prefers_the_dotfile_then_xdg() {
+        let home = tempfile::tempdir().unwrap();
+        let dotfile = home.path().join(".Brewfile");
+        let xdg = home.path().join(".config/homebrew/Brewfile");
+
+        // Neither exists: the dotfile, so the read error names it.
+        assert_eq!(global_brewfile(home.path(), None), dotfile);
+
+        std::fs::create_dir_all(xdg.parent().unwrap()).unwrap();
+        std::fs::write(&xdg, "jq\n").unwrap();
+        assert_eq!(global_brewfile(home.path(), None), xdg);
+        let elsewhere = home.path().join("elsewhere");
+        assert_eq!(global_brewfile(home.path(), Some(&elsewhere)), dotfile);
+
+        std::fs::write(&dotfile, "jq\n").unwrap();
+        assert_eq!(global_brewfile(home.path(), None), dotfile);
     }
 
     #[test]
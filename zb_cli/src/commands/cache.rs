@@ -0,0 +1,105 @@
+use std::time::{Duration, SystemTime};
+
+use console::style;
+
+use crate::cli::CacheCommands;
+use crate::format::human_bytes;
+use crate::ui::StdUi;
+
+pub fn execute(
+    installer: &mut zb_io::Installer,
+    command: CacheCommands,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    match command {
+        CacheCommands::RebuildIndex => rebuild_index(installer, ui),
+        CacheCommands::Clean {
+            archives,
+            bottles,
+            older_than,
+        } => {
+            let before = older_than.map(|days| {
+                SystemTime::now()
+                    .checked_sub(Duration::from_secs(days.saturating_mul(86400)))
+                    .unwrap_or(SystemTime::UNIX_EPOCH)
+            });
+            if archives {
+                clean_archives(installer, before, ui)?;
+            }
+            if bottles {
+                clean_bottles(installer, before, ui)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn clean_bottles(
+    installer: &mut zb_io::Installer,
+    before: Option<SystemTime>,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let removed = installer.clean_bottles(before)?;
+    if removed.is_empty() {
+        return ui.info("No cached bottles to remove.").map_err(ui_error);
+    }
+
+    let bytes: u64 = removed.iter().map(|entry| entry.size).sum();
+    ui.info(format!(
+        "Removed {} cached {} ({})",
+        style(removed.len()).green().bold(),
+        if removed.len() == 1 {
+            "bottle"
+        } else {
+            "bottles"
+        },
+        human_bytes(bytes)
+    ))
+    .map_err(ui_error)
+}
+
+fn clean_archives(
+    installer: &mut zb_io::Installer,
+    before: Option<SystemTime>,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let removed = installer.clean_archives(before)?;
+    if removed.is_empty() {
+        return ui.info("No archives to remove.").map_err(ui_error);
+    }
+
+    let bytes: u64 = removed.iter().map(|archive| archive.bytes).sum();
+    ui.info(format!(
+        "Removed {} {} ({})",
+        style(removed.len()).green().bold(),
+        if removed.len() == 1 {
+            "archived store entry"
+        } else {
+            "archived store entries"
+        },
+        human_bytes(bytes)
+    ))
+    .map_err(ui_error)
+}
+
+fn rebuild_index(installer: &mut zb_io::Installer, ui: &mut StdUi) -> Result<(), zb_core::Error> {
+    let report = installer.rebuild_blob_index()?;
+    ui.info(format!(
+        "Indexed {} cached {} ({} added, {} dropped)",
+        style(report.blobs).green().bold(),
+        if report.blobs == 1 {
+            "bottle"
+        } else {
+            "bottles"
+        },
+        report.added,
+        report.removed
+    ))
+    .map_err(ui_error)
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
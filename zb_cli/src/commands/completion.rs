@@ -1,6 +1,15 @@
 use clap::{CommandFactory, Parser};
 use clap_complete::generate;
+use clap_complete::shells::Shell;
+use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::ui::Ui;
+
+/// Scripts written by `zb completion --install`, one absolute path per line,
+/// so a manual uninstall knows what lives outside the Cellar.
+const COMPLETIONS_MANIFEST: &str = "completions.list";
 
 #[derive(Parser)]
 #[command(name = "zb")]
@@ -11,8 +20,185 @@ pub struct Cli {
     command: crate::cli::Commands,
 }
 
-pub fn execute(shell: clap_complete::shells::Shell) -> Result<(), zb_core::Error> {
+pub fn execute(shell: Shell) -> Result<(), zb_core::Error> {
     let mut cmd = crate::cli::Cli::command();
     generate(shell, &mut cmd, "zb", &mut io::stdout());
     Ok(())
 }
+
+/// Write the completion script for `shell` into its conventional location
+/// under `prefix`, overwriting any previous copy.
+pub fn install<O: io::Write, E: io::Write>(
+    shell: Shell,
+    root: &Path,
+    prefix: &Path,
+    ui: &mut Ui<O, E>,
+) -> Result<(), zb_core::Error> {
+    let path = install_script(shell, root, prefix)?;
+
+    ui.heading(format!(
+        "Installed {shell} completions to {}",
+        path.display()
+    ))
+    .map_err(ui_error)?;
+
+    if let Some(hint) = rc_hint(shell, prefix, &path) {
+        ui.note("To enable them, add this to your shell config:")
+            .map_err(ui_error)?;
+        ui.println(format!("    {hint}")).map_err(ui_error)?;
+    }
+
+    Ok(())
+}
+
+fn install_script(shell: Shell, root: &Path, prefix: &Path) -> Result<PathBuf, zb_core::Error> {
+    let path = completion_path(shell, prefix)?;
+    let dir = path.parent().expect("completion path has a parent");
+    let ctx = format!("failed to create {}", dir.display());
+    fs::create_dir_all(dir).map_err(zb_core::Error::file(ctx.as_str()))?;
+
+    let mut script = Vec::new();
+    generate(shell, &mut crate::cli::Cli::command(), "zb", &mut script);
+
+    // Write next to the target and rename so a re-run never leaves a
+    // half-written script behind.
+    let tmp = path.with_extension("zb-tmp");
+    let ctx = format!("failed to write {}", path.display());
+    fs::write(&tmp, &script).map_err(zb_core::Error::file(ctx.as_str()))?;
+    fs::rename(&tmp, &path).map_err(zb_core::Error::file(ctx.as_str()))?;
+
+    record_completion(root, &path)?;
+    Ok(path)
+}
+
+pub(crate) fn completion_path(shell: Shell, prefix: &Path) -> Result<PathBuf, zb_core::Error> {
+    match shell {
+        Shell::Zsh => Ok(prefix.join("share/zsh/site-functions/_zb")),
+        Shell::Bash => Ok(prefix.join("etc/bash_completion.d/zb")),
+        Shell::Fish => Ok(prefix.join("share/fish/vendor_completions.d/zb.fish")),
+        other => Err(zb_core::Error::InvalidArgument {
+            message: format!(
+                "--install is not supported for {other}; redirect `zb completion {other}` instead"
+            ),
+        }),
+    }
+}
+
+fn rc_hint(shell: Shell, prefix: &Path, path: &Path) -> Option<String> {
+    match shell {
+        Shell::Zsh => Some(format!(
+            "fpath=({} $fpath)  # before compinit",
+            prefix.join("share/zsh/site-functions").display()
+        )),
+        Shell::Bash => Some(format!("source {}", path.display())),
+        Shell::Fish => Some(format!(
+            "set -gx XDG_DATA_DIRS {} $XDG_DATA_DIRS",
+            prefix.join("share").display()
+        )),
+        _ => None,
+    }
+}
+
+fn record_completion(root: &Path, path: &Path) -> Result<(), zb_core::Error> {
+    let manifest = root.join(COMPLETIONS_MANIFEST);
+    let mut entries: Vec<String> = fs::read_to_string(&manifest)
+        .map(|raw| raw.lines().map(str::to_owned).collect())
+        .unwrap_or_default();
+
+    let entry = path.to_string_lossy().into_owned();
+    if entries.contains(&entry) {
+        return Ok(());
+    }
+    entries.push(entry);
+    entries.sort();
+
+    let ctx = format!("failed to record completion in {}", manifest.display());
+    fs::create_dir_all(root).map_err(zb_core::Error::file(ctx.as_str()))?;
+    fs::write(&manifest, entries.join("\n") + "\n").map_err(zb_core::Error::file(ctx.as_str()))
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::FileError {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn installs_each_shell_into_prefix() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        let prefix = tmp.path().join("prefix");
+
+        for (shell, relative) in [
+            (Shell::Zsh, "share/zsh/site-functions/_zb"),
+            (Shell::Bash, "etc/bash_completion.d/zb"),
+            (Shell::Fish, "share/fish/vendor_completions.d/zb.fish"),
+        ] {
+            let mut ui = Ui::with_writers(Vec::<u8>::new(), Vec::<u8>::new());
+            install(shell, &root, &prefix, &mut ui).unwrap();
+
+            let script = fs::read_to_string(prefix.join(relative)).unwrap();
+            assert!(script.contains("zb"), "{shell} script is empty");
+        }
+
+        let manifest = fs::read_to_string(root.join(COMPLETIONS_MANIFEST)).unwrap();
+        assert_eq!(manifest.lines().count(), 3);
+    }
+
+    #[test]
+    fn reinstall_overwrites_without_duplicating_manifest() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        let prefix = tmp.path().join("prefix");
+        let target = prefix.join("share/zsh/site-functions/_zb");
+
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        fs::write(&target, "stale").unwrap();
+
+        install_script(Shell::Zsh, &root, &prefix).unwrap();
+        install_script(Shell::Zsh, &root, &prefix).unwrap();
+
+        assert!(fs::read_to_string(&target).unwrap().contains("#compdef zb"));
+        assert!(!target.with_extension("zb-tmp").exists());
+        let manifest = fs::read_to_string(root.join(COMPLETIONS_MANIFEST)).unwrap();
+        assert_eq!(
+            manifest.lines().collect::<Vec<_>>(),
+            vec![target.to_str().unwrap()]
+        );
+    }
+
+    #[test]
+    fn every_shell_completes_every_subcommand() {
+        use clap::ValueEnum;
+
+        let cmd = crate::cli::Cli::command();
+        let subcommands: Vec<&str> = cmd
+            .get_subcommands()
+            .map(|sub| sub.get_name())
+            .filter(|name| *name != "help")
+            .collect();
+
+        for shell in Shell::value_variants() {
+            let mut script = Vec::new();
+            generate(*shell, &mut crate::cli::Cli::command(), "zb", &mut script);
+            let script = String::from_utf8(script).unwrap();
+            for name in &subcommands {
+                assert!(script.contains(name), "{shell} script lacks {name}");
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_shell_is_rejected() {
+        let tmp = TempDir::new().unwrap();
+        assert!(matches!(
+            completion_path(Shell::Elvish, tmp.path()),
+            Err(zb_core::Error::InvalidArgument { .. })
+        ));
+    }
+}
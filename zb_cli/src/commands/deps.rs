@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use console::style;
+use zb_io::DependencyGraph;
+
+use crate::porcelain::{self, Porcelain};
+use crate::ui::StdUi;
+use crate::utils::{normalize_formula_name, ui_error};
+
+/// Print every dependency of `formula`, installed or not, sorted or as a
+/// tree, marking the installed ones. When its metadata can't be fetched,
+/// an installed formula's recorded dependencies are listed instead, so
+/// this works offline. Porcelain lists the same dependencies, unmarked.
+pub async fn resolve(
+    installer: &zb_io::Installer,
+    formula: String,
+    tree: bool,
+    porcelain: Option<Porcelain>,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let name = normalize_formula_name(&formula)?;
+    let graph = match installer.dependency_graph(&name).await {
+        Ok(graph) => graph,
+        Err(e) => {
+            let Ok(recorded) = installer.dependencies(&name) else {
+                return Err(e);
+            };
+            ui.warn(format!(
+                "couldn't look up {name}'s dependencies ({e}); listing the ones recorded when it was installed"
+            ))
+            .map_err(ui_error)?;
+            let mut out = std::io::stdout().lock();
+            if let Some(Porcelain::V1) = porcelain {
+                return porcelain::write_deps(&mut out, &name, &recorded).map_err(ui_error);
+            }
+            for dependency in recorded {
+                let label = if installer.is_installed(&dependency) {
+                    format!(" {}", style("(installed)").green())
+                } else {
+                    String::new()
+                };
+                writeln!(out, "{dependency}{label}").map_err(ui_error)?;
+            }
+            return Ok(());
+        }
+    };
+    let mut out = std::io::stdout().lock();
+    if let Some(Porcelain::V1) = porcelain {
+        let closure: Vec<String> = graph.closure().into_iter().map(String::from).collect();
+        return porcelain::write_deps(&mut out, &name, &closure).map_err(ui_error);
+    }
+    if tree {
+        return write_tree(&mut out, &graph).map_err(ui_error);
+    }
+    for dependency in graph.closure() {
+        writeln!(out, "{dependency}{}", installed_label(&graph, dependency)).map_err(ui_error)?;
+    }
+    Ok(())
+}
+
+fn installed_label(graph: &DependencyGraph, name: &str) -> String {
+    if graph.is_installed(name) {
+        format!(" {}", style("(installed)").green())
+    } else {
+        String::new()
+    }
+}
+
+/// `graph` from its root down. A formula that leads back to one above it is
+/// marked `(cycle)`, and one already expanded higher up `(see above)`, so
+/// each subtree is printed once.
+fn write_tree(out: &mut impl Write, graph: &DependencyGraph) -> io::Result<()> {
+    writeln!(out, "{}", graph.root)?;
+    let mut path = vec![graph.root.as_str()];
+    write_branches(out, graph, "", &mut path, &mut HashSet::new())
+}
+
+fn write_branches<'a>(
+    out: &mut impl Write,
+    graph: &'a DependencyGraph,
+    indent: &str,
+    path: &mut Vec<&'a str>,
+    expanded: &mut HashSet<&'a str>,
+) -> io::Result<()> {
+    let parent = path.last().copied().unwrap_or(&graph.root);
+    let dependencies: Vec<&str> = graph.dependencies_of(parent).collect();
+    for (i, &dependency) in dependencies.iter().enumerate() {
+        let (branch, continuation) = if i + 1 == dependencies.len() {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        let line = format!(
+            "{indent}{branch}{dependency}{}",
+            installed_label(graph, dependency)
+        );
+        if path.contains(&dependency) {
+            writeln!(out, "{line} (cycle)")?;
+            continue;
+        }
+        let has_dependencies = graph.dependencies_of(dependency).next().is_some();
+        if has_dependencies && !expanded.insert(dependency) {
+            writeln!(out, "{line} (see above)")?;
+            continue;
+        }
+        writeln!(out, "{line}")?;
+        path.push(dependency);
+        write_branches(
+            out,
+            graph,
+            &format!("{indent}{continuation}"),
+            path,
+            expanded,
+        )?;
+        path.pop();
+    }
+    Ok(())
+}
+
+/// Report dependencies that aren't installed or whose keg is gone: those
+/// `formula` needs, printed bare for `zb install` unless `fix` installs
+/// them, or without it the recorded ones of every installed formula.
+pub async fn missing(
+    installer: &mut zb_io::Installer,
+    formula: Option<String>,
+    fix: bool,
+    porcelain: Option<Porcelain>,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let name = formula.as_deref().map(normalize_formula_name).transpose()?;
+    // Porcelain output is the recorded dependencies, as it always was.
+    let missing = match name.as_deref() {
+        Some(name) if porcelain.is_none() => installer.dependency_graph(name).await?.missing(),
+        name => installer.missing_dependencies(name)?,
+    };
+    if let Some(Porcelain::V1) = porcelain {
+        return porcelain::write_missing(&mut std::io::stdout().lock(), &missing).map_err(ui_error);
+    }
+    if name.is_some() && !fix {
+        for entry in &missing {
+            ui.println(&entry.dependency).map_err(ui_error)?;
+        }
+        return Ok(());
+    }
+    if missing.is_empty() {
+        ui.info("No missing dependencies.").map_err(ui_error)?;
+        return Ok(());
+    }
+
+    for entry in &missing {
+        let state = match &entry.version {
+            Some(version) => format!("recorded at {version}, but its keg is gone"),
+            None => "not installed".to_string(),
+        };
+        ui.bullet(format!(
+            "{} needs {} ({state})",
+            style(&entry.formula).bold(),
+            style(&entry.dependency).bold()
+        ))
+        .map_err(ui_error)?;
+    }
+
+    let mut names: Vec<&str> = missing.iter().map(|m| m.dependency.as_str()).collect();
+    names.sort();
+    names.dedup();
+    if !fix {
+        ui.blank_line().map_err(ui_error)?;
+        ui.println(format!(
+            "    Run {} (or {}) to install them",
+            style("zb deps --missing --fix").bold(),
+            style(format!("zb install {}", names.join(" "))).bold()
+        ))
+        .map_err(ui_error)?;
+        return Ok(());
+    }
+
+    ui.heading(format!("Installing {}...", style(names.join(", ")).bold()))
+        .map_err(ui_error)?;
+    let result = installer.install_missing_dependencies(&missing).await?;
+    ui.heading(format!(
+        "Installed {} missing {}",
+        style(result.installed).green().bold(),
+        if result.installed == 1 {
+            "dependency"
+        } else {
+            "dependencies"
+        }
+    ))
+    .map_err(ui_error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])], installed: &[&str]) -> DependencyGraph {
+        DependencyGraph {
+            root: edges[0].0.to_string(),
+            edges: edges
+                .iter()
+                .map(|(name, deps)| {
+                    (
+                        name.to_string(),
+                        deps.iter().map(|dep| dep.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            installed: installed
+                .iter()
+                .map(|name| (name.to_string(), ("1.0".to_string(), true)))
+                .collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    fn tree(graph: &DependencyGraph) -> String {
+        let mut out = Vec::new();
+        write_tree(&mut out, graph).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn tree_marks_cycles_and_prints_shared_subtrees_once() {
+        let graph = graph(
+            &[
+                ("app", &["lib", "tls"]),
+                ("lib", &["util"]),
+                ("tls", &["util", "zlib"]),
+                ("util", &["lib", "app"]),
+                ("zlib", &[]),
+            ],
+            &["zlib"],
+        );
+
+        assert_eq!(
+            tree(&graph),
+            "app\n\
+             ├── lib\n\
+             │   └── util\n\
+             │       ├── lib (cycle)\n\
+             │       └── app (cycle)\n\
+             └── tls\n    \
+                 ├── util (see above)\n    \
+                 └── zlib (installed)\n"
+        );
+    }
+}
@@ -1,17 +1,42 @@
+use std::time::Duration;
+
 use console::style;
+use zb_io::{RuntimeCheck, RuntimeOutcome};
 
 use crate::ui::StdUi;
 
+/// What `--check-runtime` asks for: the formula to limit it to, if any, and
+/// how long each executable may run.
+pub struct RuntimeOptions {
+    pub formula: Option<String>,
+    pub timeout: Duration,
+}
+
 pub fn execute(
     installer: &mut zb_io::Installer,
     repair: bool,
+    runtime: Option<RuntimeOptions>,
     ui: &mut StdUi,
 ) -> Result<(), zb_core::Error> {
+    ui.println(
+        style(crate::version::build_summary(&zb_core::host_bottle_tags()))
+            .dim()
+            .to_string(),
+    )
+    .map_err(ui_error)?;
     ui.heading("Running diagnostics...").map_err(ui_error)?;
 
     let report = installer.doctor()?;
+    let runtime_failures = match runtime {
+        Some(options) => {
+            let checks = installer.check_runtime(options.formula.as_deref(), options.timeout)?;
+            print_runtime(&checks, ui)?;
+            checks.iter().filter(|check| check.failed()).count()
+        }
+        None => 0,
+    };
 
-    if report.is_healthy() {
+    if report.is_healthy() && runtime_failures == 0 {
         ui.println(format!("    {} No issues found", style("✓").green()))
             .map_err(ui_error)?;
         return Ok(());
@@ -65,6 +90,53 @@ pub fn execute(
             .map_err(ui_error)?;
     }
 
+    for link in &report.symlink_loops {
+        ui.warn(format!(
+            "Symlink loop: {} (left in place; remove it by hand)",
+            link.display()
+        ))
+        .map_err(ui_error)?;
+    }
+
+    for keg in &report.prefix_drift {
+        ui.warn(format!(
+            "Patched for another prefix: {}/{} (patched for {}; see 'zb relocate')",
+            keg.name,
+            keg.version,
+            keg.patched_prefix.display()
+        ))
+        .map_err(ui_error)?;
+    }
+
+    for missing in &report.missing_dependencies {
+        let state = match &missing.version {
+            Some(version) => format!("the {version} keg is gone"),
+            None => "not installed".to_string(),
+        };
+        ui.warn(format!(
+            "Missing dependency: {} needs {} ({state}; see 'zb deps --missing --fix')",
+            missing.formula, missing.dependency
+        ))
+        .map_err(ui_error)?;
+    }
+
+    for path in &report.invalid_signatures {
+        ui.warn(format!(
+            "Invalid code signature: {} (macOS will refuse to run it)",
+            path.display()
+        ))
+        .map_err(ui_error)?;
+    }
+
+    for missing in &report.missing_libraries {
+        ui.warn(format!(
+            "Missing libraries: {} needs {} (it will fail to start)",
+            missing.path.display(),
+            missing.sonames.join(", ")
+        ))
+        .map_err(ui_error)?;
+    }
+
     if report.stale_keg_file_records > 0 {
         ui.warn(format!(
             "{} stale keg_files records (referencing uninstalled kegs)",
@@ -78,7 +150,13 @@ pub fn execute(
         + report.orphaned_store_entries.len()
         + report.stale_store_refs.len()
         + report.broken_symlinks.len()
-        + usize::from(report.stale_keg_file_records > 0);
+        + report.symlink_loops.len()
+        + report.prefix_drift.len()
+        + report.missing_dependencies.len()
+        + report.invalid_signatures.len()
+        + report.missing_libraries.len()
+        + usize::from(report.stale_keg_file_records > 0)
+        + runtime_failures;
 
     ui.blank_line().map_err(ui_error)?;
     ui.heading(format!(
@@ -88,6 +166,10 @@ pub fn execute(
     ))
     .map_err(ui_error)?;
 
+    // Nothing in place fixes an executable that won't run.
+    if report.is_healthy() {
+        return Ok(());
+    }
     if !repair {
         ui.println(format!(
             "    Run {} to fix",
@@ -151,6 +233,15 @@ pub fn execute(
         .map_err(ui_error)?;
     }
 
+    if summary.resigned_binaries > 0 {
+        ui.bullet(format!(
+            "Re-signed {} {}",
+            summary.resigned_binaries,
+            pluralize("binary", summary.resigned_binaries)
+        ))
+        .map_err(ui_error)?;
+    }
+
     ui.blank_line().map_err(ui_error)?;
     ui.println(format!(
         "    {} Applied {} {}",
@@ -163,6 +254,51 @@ pub fn execute(
     Ok(())
 }
 
+/// One line per formula on stdout; failures are warnings with what the
+/// executable printed to stderr.
+fn print_runtime(checks: &[RuntimeCheck], ui: &mut StdUi) -> Result<(), zb_core::Error> {
+    for check in checks {
+        let executable = check
+            .executable
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match &check.outcome {
+            RuntimeOutcome::Passed => ui.println(format!(
+                "    {} {} {} ({executable} runs)",
+                style("✓").green(),
+                check.name,
+                style(&check.version).dim()
+            )),
+            RuntimeOutcome::Skipped { reason } => ui.println(format!(
+                "    {} {} {} {}",
+                style("-").dim(),
+                check.name,
+                style(&check.version).dim(),
+                style(format!("(skipped: {reason})")).dim()
+            )),
+            RuntimeOutcome::TimedOut => ui.warn(format!(
+                "Runtime check: {} {}: {executable} was still running at the timeout and was killed",
+                check.name, check.version
+            )),
+            RuntimeOutcome::Failed { reason, stderr } => {
+                ui.warn(format!(
+                    "Runtime check: {} {}: {executable} {reason}",
+                    check.name, check.version
+                ))
+                .and_then(|()| {
+                    stderr
+                        .lines()
+                        .try_for_each(|line| ui.eprintln(format!("    {line}")))
+                })
+            }
+        }
+        .map_err(ui_error)?;
+    }
+    Ok(())
+}
+
 fn pluralize(word: &str, count: usize) -> &str {
     if count == 1 {
         word
@@ -173,6 +309,7 @@ fn pluralize(word: &str, count: usize) -> &str {
             "ref" => "refs",
             "entry" => "entries",
             "symlink" => "symlinks",
+            "binary" => "binaries",
             "fix" => "fixes",
             "issue" => "issues",
             _ => word,
@@ -0,0 +1,238 @@
+use console::style;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use zb_core::formula_token;
+
+use crate::utils::normalize_formula_name;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EnvFormat {
+    Plain,
+    Export,
+    Json,
+}
+
+/// Compiler and linker search paths contributed by one or more kegs. Only
+/// directories that exist in a keg are included, so a header-only formula
+/// adds nothing to `LDFLAGS`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BuildEnv {
+    include_dirs: Vec<PathBuf>,
+    lib_dirs: Vec<PathBuf>,
+    pkg_config_dirs: Vec<PathBuf>,
+}
+
+impl BuildEnv {
+    pub fn for_keg(keg: &Path) -> Self {
+        let mut env = Self::default();
+        env.add_keg(keg);
+        env
+    }
+
+    pub fn add_keg(&mut self, keg: &Path) {
+        push_if_dir(&mut self.include_dirs, keg.join("include"));
+        push_if_dir(&mut self.lib_dirs, keg.join("lib"));
+        push_if_dir(&mut self.pkg_config_dirs, keg.join("lib/pkgconfig"));
+        push_if_dir(&mut self.pkg_config_dirs, keg.join("share/pkgconfig"));
+    }
+
+    /// Variables in the order a build script would usually set them. Empty
+    /// variables are left out.
+    pub fn vars(&self) -> Vec<(&'static str, String)> {
+        let join = |flag: &str, dirs: &[PathBuf]| {
+            dirs.iter()
+                .map(|dir| format!("{flag}{}", dir.display()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let pkg_config_path = self
+            .pkg_config_dirs
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<_>>()
+            .join(":");
+
+        [
+            ("CPPFLAGS", join("-I", &self.include_dirs)),
+            ("LDFLAGS", join("-L", &self.lib_dirs)),
+            ("PKG_CONFIG_PATH", pkg_config_path),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .collect()
+    }
+}
+
+fn push_if_dir(dirs: &mut Vec<PathBuf>, dir: PathBuf) {
+    if dir.is_dir() && !dirs.contains(&dir) {
+        dirs.push(dir);
+    }
+}
+
+pub fn execute(
+    installer: &mut zb_io::Installer,
+    formulas: Vec<String>,
+    format: EnvFormat,
+    combined: bool,
+) -> Result<(), zb_core::Error> {
+    let mut kegs = Vec::with_capacity(formulas.len());
+    for formula in &formulas {
+        let name = normalize_formula_name(formula)?;
+        let keg = installer
+            .get_installed(&name)?
+            .ok_or_else(|| zb_core::Error::NotInstalled { name: name.clone() })?;
+        let path = installer.keg_path(formula_token(&keg.name), &keg.version);
+        kegs.push((name, path));
+    }
+
+    if combined {
+        let mut env = BuildEnv::default();
+        for (_, path) in &kegs {
+            env.add_keg(path);
+        }
+        print!("{}", render(&env, format));
+        return Ok(());
+    }
+
+    if format == EnvFormat::Json {
+        let all: BTreeMap<&str, BTreeMap<&str, String>> = kegs
+            .iter()
+            .map(|(name, path)| {
+                let vars = BuildEnv::for_keg(path).vars().into_iter().collect();
+                (name.as_str(), vars)
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&all).unwrap());
+        return Ok(());
+    }
+
+    let single = kegs.len() == 1;
+    for (name, path) in &kegs {
+        if !single {
+            match format {
+                EnvFormat::Export => println!("# {name}"),
+                _ => println!("{} {}", style("==>").cyan().bold(), style(name).bold()),
+            }
+        }
+        print!("{}", render(&BuildEnv::for_keg(path), format));
+    }
+
+    Ok(())
+}
+
+fn render(env: &BuildEnv, format: EnvFormat) -> String {
+    let vars = env.vars();
+    match format {
+        EnvFormat::Json => {
+            let map: BTreeMap<_, _> = vars.into_iter().collect();
+            format!("{}\n", serde_json::to_string_pretty(&map).unwrap())
+        }
+        EnvFormat::Export => vars
+            .iter()
+            .map(|(key, value)| format!("export {key}={}\n", shell_quote(value)))
+            .collect(),
+        EnvFormat::Plain => vars
+            .iter()
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect(),
+    }
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn fake_keg(root: &Path, name: &str, dirs: &[&str]) -> PathBuf {
+        let keg = root.join(name).join("1.0");
+        for dir in dirs {
+            fs::create_dir_all(keg.join(dir)).unwrap();
+        }
+        fs::create_dir_all(&keg).unwrap();
+        keg
+    }
+
+    #[test]
+    fn only_existing_directories_contribute() {
+        let tmp = TempDir::new().unwrap();
+        let keg = fake_keg(tmp.path(), "openssl@3", &["include", "lib/pkgconfig"]);
+
+        let vars = BuildEnv::for_keg(&keg).vars();
+        assert_eq!(
+            vars,
+            vec![
+                ("CPPFLAGS", format!("-I{}", keg.join("include").display())),
+                ("LDFLAGS", format!("-L{}", keg.join("lib").display())),
+                (
+                    "PKG_CONFIG_PATH",
+                    keg.join("lib/pkgconfig").display().to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn header_only_keg_sets_cppflags_only() {
+        let tmp = TempDir::new().unwrap();
+        let keg = fake_keg(tmp.path(), "headers", &["include"]);
+
+        let vars = BuildEnv::for_keg(&keg).vars();
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars[0].0, "CPPFLAGS");
+    }
+
+    #[test]
+    fn empty_keg_renders_nothing() {
+        let tmp = TempDir::new().unwrap();
+        let keg = fake_keg(tmp.path(), "empty", &["bin"]);
+
+        assert_eq!(render(&BuildEnv::for_keg(&keg), EnvFormat::Plain), "");
+        assert_eq!(render(&BuildEnv::for_keg(&keg), EnvFormat::Json), "{}\n");
+    }
+
+    #[test]
+    fn combined_kegs_merge_in_order() {
+        let tmp = TempDir::new().unwrap();
+        let openssl = fake_keg(tmp.path(), "openssl@3", &["include", "lib/pkgconfig"]);
+        let readline = fake_keg(tmp.path(), "readline", &["include", "lib"]);
+
+        let mut env = BuildEnv::default();
+        env.add_keg(&openssl);
+        env.add_keg(&readline);
+        env.add_keg(&openssl);
+
+        let vars: BTreeMap<_, _> = env.vars().into_iter().collect();
+        assert_eq!(
+            vars["CPPFLAGS"],
+            format!(
+                "-I{} -I{}",
+                openssl.join("include").display(),
+                readline.join("include").display()
+            )
+        );
+        assert_eq!(
+            vars["PKG_CONFIG_PATH"],
+            openssl.join("lib/pkgconfig").display().to_string()
+        );
+    }
+
+    #[test]
+    fn export_format_quotes_values() {
+        let tmp = TempDir::new().unwrap();
+        let keg = fake_keg(tmp.path(), "it's", &["lib"]);
+
+        let rendered = render(&BuildEnv::for_keg(&keg), EnvFormat::Export);
+        assert_eq!(
+            rendered,
+            format!(
+                "export LDFLAGS='-L{}'\n",
+                keg.join("lib").display().to_string().replace('\'', r"'\''")
+            )
+        );
+    }
+}
@@ -1,24 +1,78 @@
 use console::style;
 
-pub fn execute(installer: &mut zb_io::Installer) -> Result<(), zb_core::Error> {
-    println!(
-        "{} Running garbage collection...",
-        style("==>").cyan().bold()
-    );
-    let removed = installer.gc()?;
+use crate::format::human_bytes;
+use crate::ui::StdUi;
 
-    if removed.is_empty() {
-        println!("No unreferenced store entries to remove.");
+pub fn execute(
+    installer: &mut zb_io::Installer,
+    options: zb_io::GcOptions,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    ui.heading("Running garbage collection...")
+        .map_err(ui_error)?;
+    let report = installer.gc_with(&options)?;
+
+    let verb = if options.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+    for key in &report.removed_store_keys {
+        ui.println(format!(
+            "    {} {verb} {}",
+            style("✓").green(),
+            &key[..key.len().min(12)]
+        ))
+        .map_err(ui_error)?;
+    }
+    let verb = if options.dry_run {
+        "Would archive"
     } else {
-        for key in &removed {
-            println!("    {} Removed {}", style("✓").green(), &key[..12]);
-        }
-        println!(
-            "{} Removed {} store entries",
-            style("==>").cyan().bold(),
-            style(removed.len()).green().bold()
-        );
+        "Archived"
+    };
+    for key in &report.archived_store_keys {
+        ui.println(format!(
+            "    {} {verb} {}",
+            style("✓").green(),
+            &key[..key.len().min(12)]
+        ))
+        .map_err(ui_error)?;
     }
 
+    let found: Vec<_> = report
+        .categories()
+        .into_iter()
+        .filter(|(_, reclaimed)| reclaimed.items > 0)
+        .collect();
+    if found.is_empty() {
+        ui.println("Nothing to remove.").map_err(ui_error)?;
+        return Ok(());
+    }
+
+    for (label, reclaimed) in found {
+        ui.bullet(format!(
+            "{label}: {} ({})",
+            style(reclaimed.items).green(),
+            human_bytes(reclaimed.bytes)
+        ))
+        .map_err(ui_error)?;
+    }
+    let verb = if options.dry_run {
+        "Would reclaim"
+    } else {
+        "Reclaimed"
+    };
+    ui.heading(format!(
+        "{verb} {}",
+        style(human_bytes(report.total_bytes())).green().bold()
+    ))
+    .map_err(ui_error)?;
+
     Ok(())
 }
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use console::style;
+use serde_json::json;
+
+use crate::format::{human_bytes, relative_time_at};
+
+pub fn execute(state: &zb_io::InstalledState, json: bool) -> Result<(), zb_core::Error> {
+    let events = state.history()?;
+    let out = &mut io::stdout().lock();
+    if json {
+        return write_json(out, &events).map_err(ui_error);
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    write_human(out, &events, now).map_err(ui_error)
+}
+
+/// One line per run, oldest first:
+/// `2 hours ago  gc  removed 12 entries (1.2 GB)  by alice`.
+fn write_human(out: &mut impl Write, events: &[zb_io::HistoryEvent], now: i64) -> io::Result<()> {
+    if events.is_empty() {
+        return writeln!(out, "No history recorded.");
+    }
+    for event in events {
+        let mut line = format!(
+            "{}  {}",
+            style(relative_time_at(event.at, now)).dim(),
+            style(&event.kind).bold()
+        );
+        if let Some(details) = event.details.as_ref().map(summary)
+            && !details.is_empty()
+        {
+            line.push_str(&format!("  {details}"));
+        }
+        if let Some(user) = &event.user {
+            line.push_str(&format!("  {}", style(format!("by {user}")).dim()));
+        }
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+/// `removed 12 entries (1.2 GB)` for the counts gc and cache-clean record;
+/// anything else as `key=value`.
+fn summary(details: &serde_json::Value) -> String {
+    let Some(fields) = details.as_object() else {
+        return details.to_string();
+    };
+    let mut parts = Vec::new();
+    if let (Some(entries), Some(bytes)) = (
+        fields.get("entries").and_then(|v| v.as_u64()),
+        fields.get("bytes").and_then(|v| v.as_u64()),
+    ) {
+        let noun = if entries == 1 { "entry" } else { "entries" };
+        parts.push(format!("removed {entries} {noun} ({})", human_bytes(bytes)));
+    }
+    for (key, value) in fields {
+        if key != "entries" && key != "bytes" {
+            parts.push(format!("{key}={value}"));
+        }
+    }
+    parts.join(", ")
+}
+
+/// Every event with its details in full, oldest first.
+fn write_json(out: &mut impl Write, events: &[zb_io::HistoryEvent]) -> io::Result<()> {
+    let entries: Vec<serde_json::Value> = events
+        .iter()
+        .map(|event| {
+            json!({
+                "at": event.at,
+                "kind": event.kind,
+                "user": event.user,
+                "details": event.details,
+            })
+        })
+        .collect();
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&entries).unwrap_or_default()
+    )
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(
+        kind: &str,
+        user: Option<&str>,
+        details: Option<serde_json::Value>,
+    ) -> zb_io::HistoryEvent {
+        zb_io::HistoryEvent {
+            at: 1_000,
+            kind: kind.to_string(),
+            user: user.map(str::to_string),
+            details,
+        }
+    }
+
+    #[test]
+    fn runs_are_summarized_one_per_line() {
+        let events = [
+            event(
+                "gc",
+                Some("alice"),
+                Some(json!({ "entries": 12, "bytes": 1_200_000_000u64 })),
+            ),
+            event(
+                "cache-clean",
+                None,
+                Some(json!({ "entries": 1, "bytes": 0 })),
+            ),
+        ];
+
+        let mut out = Vec::new();
+        write_human(&mut out, &events, 1_000 + 7200).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2 hours ago  gc  removed 12 entries (1.2 GB)  by alice\n\
+             2 hours ago  cache-clean  removed 1 entry (0 B)\n"
+        );
+    }
+
+    #[test]
+    fn json_keeps_the_details_whole() {
+        let events = [event(
+            "gc",
+            None,
+            Some(json!({ "entries": 3, "bytes": 10 })),
+        )];
+
+        let mut out = Vec::new();
+        write_json(&mut out, &events).unwrap();
+
+        let parsed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            parsed,
+            json!([{ "at": 1000, "kind": "gc", "user": null, "details": { "entries": 3, "bytes": 10 } }])
+        );
+    }
+}
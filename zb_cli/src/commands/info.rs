@@ -1,12 +1,34 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
 use chrono::{DateTime, Local};
 use console::style;
+use serde_json::json;
+use zb_core::{Formula, KegOnly, Lifecycle, formula_token};
+use zb_io::{InstalledKeg, KegFileRecord};
+
+use crate::format::{human_bytes, relative_time};
+use crate::pager::Pager;
+use crate::utils::{ResolvedPath, ZbPaths};
 
-pub fn execute(installer: &mut zb_io::Installer, formula: String) -> Result<(), zb_core::Error> {
-    if let Some(keg) = installer.get_installed(&formula) {
+pub fn execute(
+    state: &zb_io::InstalledState,
+    formula: String,
+    verbose: bool,
+) -> Result<(), zb_core::Error> {
+    if let Some(keg) = state.get_installed(&formula)? {
         print_field("Name:", style(&keg.name).bold());
-        print_field("Version:", &keg.version);
+        print_field("Version:", keg.build());
+        if keg.pinned {
+            print_field("Pinned:", "yes; upgrades skip it until zb unpin");
+        }
+        print_field("License:", keg.license.as_deref().unwrap_or("unknown"));
         print_field("Store key:", &keg.store_key[..12]);
         print_field("Installed:", format_timestamp(keg.installed_at));
+        if verbose && let Some(strategy) = keg.copy_strategy {
+            print_field("Copied by:", strategy.as_str());
+        }
     } else {
         println!("Formula '{}' is not installed.", formula);
     }
@@ -14,37 +36,400 @@ pub fn execute(installer: &mut zb_io::Installer, formula: String) -> Result<(),
     Ok(())
 }
 
+pub fn system(state: &zb_io::InstalledState, paths: &ZbPaths) -> Result<(), zb_core::Error> {
+    let running = zb_io::SystemRecord::current(env!("CARGO_PKG_VERSION"));
+    print_field("Running:", format_system(&running.last_used_by, &running));
+    print_field("Root:", format_path(&paths.root));
+    print_field("Prefix:", format_path(&paths.prefix));
+    print_field("Cache:", format_path(&paths.cache));
+
+    match state.system_record()? {
+        Some(record) => {
+            print_field("Created:", format_system(&record.created_by, &record));
+            print_field("Last used:", format!("zb {}", record.last_used_by));
+        }
+        None => println!("No system record yet; most other zb commands write it."),
+    }
+
+    Ok(())
+}
+
+/// The metadata the installed keg was installed from, as kept at install
+/// time, so it reads the same offline and after upstream changes it.
+pub fn as_installed(state: &zb_io::InstalledState, formula: &str) -> Result<(), zb_core::Error> {
+    let Some(keg) = state.get_installed(formula)? else {
+        return Err(zb_core::Error::NotInstalled {
+            name: formula.to_string(),
+        });
+    };
+    let kept = state.installed_formula(&keg.name, &keg.version)?;
+    write_as_installed(&mut io::stdout().lock(), &keg, kept.as_ref()).map_err(|e| {
+        zb_core::Error::StoreCorruption {
+            message: format!("failed to write CLI output: {e}"),
+        }
+    })
+}
+
+fn write_as_installed(
+    out: &mut impl Write,
+    keg: &InstalledKeg,
+    formula: Option<&Formula>,
+) -> io::Result<()> {
+    let Some(formula) = formula else {
+        return writeln!(
+            out,
+            "No metadata was kept when {} {} was installed; reinstall it to keep it.",
+            keg.name, keg.version
+        );
+    };
+    writeln!(out, "==> {} {} (as installed)", keg.name, keg.version)?;
+    let field = |out: &mut dyn Write, label: &str, value: String| {
+        writeln!(out, "{:<11}  {}", style(label).dim(), value)
+    };
+    let list = |names: &[String]| {
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+
+    field(out, "Version:", formula.effective_version())?;
+    let keg_only = match &formula.keg_only {
+        _ if !formula.is_keg_only() => "no".to_string(),
+        KegOnly::Reason(reason) => format!("yes ({reason})"),
+        _ => "yes".to_string(),
+    };
+    field(out, "Keg-only:", keg_only)?;
+    field(out, "Depends on:", list(&formula.dependencies))?;
+    field(out, "Build deps:", list(&formula.build_dependencies))?;
+    field(
+        out,
+        "License:",
+        formula
+            .license
+            .clone()
+            .unwrap_or_else(|| "unknown".to_string()),
+    )?;
+    let upstream = match formula.lifecycle() {
+        Lifecycle::Active => None,
+        Lifecycle::Deprecated { reason } => Some(("deprecated", reason)),
+        Lifecycle::Disabled { reason } => Some(("disabled", reason)),
+    };
+    if let Some((state, reason)) = upstream {
+        let details: Vec<String> = reason
+            .map(|reason| reason.replace('_', " "))
+            .into_iter()
+            .chain(formula.lifecycle_date().map(|date| format!("since {date}")))
+            .collect();
+        let value = if details.is_empty() {
+            state.to_string()
+        } else {
+            format!("{state} ({})", details.join("; "))
+        };
+        field(out, "Upstream:", value)?;
+    }
+    Ok(())
+}
+
+/// List every file under the keg, then every prefix path recorded when it
+/// was linked, through the pager when interactive.
+pub fn files(
+    state: &zb_io::InstalledState,
+    formula: &str,
+    json: bool,
+) -> Result<(), zb_core::Error> {
+    let Some(keg) = state.get_installed(formula)? else {
+        return Err(zb_core::Error::NotInstalled {
+            name: formula.to_string(),
+        });
+    };
+    let keg_path = state.keg_path(formula_token(&keg.name), &keg.version);
+    let records = state.keg_files(&keg.name, &keg.version)?;
+
+    let mut out = Pager::start();
+    let written = write_files(&mut out, &keg, &keg_path, &records, json);
+    match written.and(out.finish()) {
+        Ok(()) => Ok(()),
+        // The pager was quit before the end of the listing.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        Err(e) => Err(zb_core::Error::FileError {
+            message: format!("failed to list files of {}: {e}", keg.name),
+        }),
+    }
+}
+
+/// Stream the listing to `out` entry by entry, so a huge keg is never held
+/// in memory. The keg walk and the recorded links are separate sections,
+/// and separate arrays in JSON.
+fn write_files(
+    out: &mut impl Write,
+    keg: &InstalledKeg,
+    keg_path: &Path,
+    records: &[KegFileRecord],
+    json: bool,
+) -> io::Result<()> {
+    if json {
+        write!(
+            out,
+            "{{\"formula\":{},\"version\":{},\"keg\":{},\"files\":[",
+            json!(keg.name),
+            json!(keg.version),
+            json!(keg_path.display().to_string())
+        )?;
+    } else {
+        writeln!(out, "==> Files in {}", keg_path.display())?;
+    }
+
+    let (mut count, mut total) = (0usize, 0u64);
+    let walk = walkdir::WalkDir::new(keg_path)
+        .follow_links(false)
+        .min_depth(1)
+        .sort_by_file_name();
+    for entry in walk {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(keg_path).unwrap_or(entry.path());
+        let size = entry.metadata()?.len();
+        let target = fs::read_link(entry.path()).ok();
+
+        if json {
+            let mut file = json!({
+                "path": relative.display().to_string(),
+                "kind": if target.is_some() { "symlink" } else { "file" },
+                "size": size,
+            });
+            if let Some(target) = &target {
+                file["target"] = json!(target.display().to_string());
+            }
+            if count > 0 {
+                out.write_all(b",")?;
+            }
+            serde_json::to_writer(&mut *out, &file)?;
+        } else {
+            write!(out, "{:>12}  {}", human_bytes(size), relative.display())?;
+            if let Some(target) = &target {
+                write!(out, " -> {}", target.display())?;
+            }
+            writeln!(out)?;
+        }
+        count += 1;
+        total += size;
+    }
+
+    if json {
+        out.write_all(b"],\"links\":[")?;
+    } else {
+        writeln!(out, "{count} files, {}", human_bytes(total))?;
+        writeln!(out)?;
+        writeln!(out, "==> Recorded in the prefix")?;
+    }
+
+    for (index, record) in records.iter().enumerate() {
+        // What the link resolves to; none when it dangles.
+        let size = fs::metadata(&record.linked_path).ok().map(|m| m.len());
+        if json {
+            if index > 0 {
+                out.write_all(b",")?;
+            }
+            let link = json!({
+                "path": record.linked_path,
+                "target": record.target_path,
+                "kind": record.kind.as_str(),
+                "size": size,
+            });
+            serde_json::to_writer(&mut *out, &link)?;
+        } else {
+            let size = size.map_or("-".to_string(), human_bytes);
+            write!(
+                out,
+                "{size:>12}  {} -> {}",
+                record.linked_path, record.target_path
+            )?;
+            if record.kind != zb_io::KegFileKind::Symlink {
+                write!(out, " ({})", record.kind.as_str())?;
+            }
+            writeln!(out)?;
+        }
+    }
+
+    if json {
+        out.write_all(b"]}\n")?;
+    } else {
+        writeln!(out, "{} paths", records.len())?;
+    }
+    out.flush()
+}
+
+fn format_path(resolved: &ResolvedPath) -> String {
+    format!(
+        "{} {}",
+        resolved.path.display(),
+        style(format!("({})", resolved.source.as_str())).dim()
+    )
+}
+
+fn format_system(version: &str, record: &zb_io::SystemRecord) -> String {
+    format!("zb {version} ({}/{})", record.os, record.arch)
+}
+
 fn print_field(label: &str, value: impl std::fmt::Display) {
     println!("{:<10}  {}", style(label).dim(), value);
 }
 
+/// The local date, with the time for the last day, followed by how long
+/// ago that was.
 fn format_timestamp(timestamp: i64) -> String {
-    match DateTime::from_timestamp(timestamp, 0) {
-        Some(dt) => {
-            let local_dt = dt.with_timezone(&Local);
-            let now = Local::now();
-            let duration = now.signed_duration_since(local_dt);
-
-            if duration.num_days() > 0 {
-                format!(
-                    "{} ({} days ago)",
-                    local_dt.format("%Y-%m-%d"),
-                    duration.num_days()
-                )
-            } else if duration.num_hours() > 0 {
-                format!(
-                    "{} ({} hours ago)",
-                    local_dt.format("%Y-%m-%d %H:%M"),
-                    duration.num_hours()
-                )
-            } else {
-                format!(
-                    "{} ({} minutes ago)",
-                    local_dt.format("%H:%M"),
-                    duration.num_minutes()
-                )
-            }
-        }
-        None => "invalid timestamp".to_string(),
+    let Some(dt) = DateTime::from_timestamp(timestamp, 0) else {
+        return "invalid timestamp".to_string();
+    };
+    let local_dt = dt.with_timezone(&Local);
+    let date = if Local::now().signed_duration_since(local_dt).num_days() > 0 {
+        local_dt.format("%Y-%m-%d")
+    } else {
+        local_dt.format("%Y-%m-%d %H:%M")
+    };
+    format!("{date} ({})", relative_time(timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use tempfile::TempDir;
+    use zb_io::KegFileKind;
+
+    use super::*;
+
+    fn fixture() -> (TempDir, InstalledKeg, Vec<KegFileRecord>) {
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path().join("Cellar/jq/1.7.1");
+        fs::create_dir_all(keg.join("bin")).unwrap();
+        fs::create_dir_all(keg.join("lib")).unwrap();
+        fs::create_dir_all(keg.join("share/man/man1")).unwrap();
+        fs::write(keg.join("bin/jq"), vec![0u8; 2048]).unwrap();
+        fs::write(keg.join("lib/libjq.1.dylib"), b"lib").unwrap();
+        symlink("libjq.1.dylib", keg.join("lib/libjq.dylib")).unwrap();
+        fs::write(keg.join("share/man/man1/jq.1"), b"man").unwrap();
+
+        fs::create_dir_all(tmp.path().join("bin")).unwrap();
+        symlink(keg.join("bin/jq"), tmp.path().join("bin/jq")).unwrap();
+        let record = |path: &str, target: &Path, kind| KegFileRecord {
+            name: "jq".to_string(),
+            version: "1.7.1".to_string(),
+            linked_path: tmp.path().join(path).display().to_string(),
+            target_path: target.display().to_string(),
+            link_target: None,
+            kind,
+        };
+        let records = vec![
+            record("bin/jq", &keg.join("bin/jq"), KegFileKind::Symlink),
+            record("etc/jq.conf", &keg.join("etc/jq.conf"), KegFileKind::Config),
+        ];
+
+        let installed = InstalledKeg {
+            name: "jq".to_string(),
+            version: "1.7.1".to_string(),
+            store_key: "abc".to_string(),
+            installed_at: 0,
+            explicit: true,
+            copy_strategy: None,
+            license: Some("MIT".to_string()),
+            revision: 0,
+            rebuild: Some(0),
+            formula_version: None,
+            pinned: false,
+        };
+        (tmp, installed, records)
+    }
+
+    #[test]
+    fn as_installed_shows_the_kept_metadata() {
+        let (_tmp, keg, _) = fixture();
+        let formula = Formula::from_json(
+            "jq",
+            r#"{
+                "name": "jq",
+                "versions": { "stable": "1.7.1" },
+                "dependencies": ["oniguruma"],
+                "keg_only": "provided by macOS",
+                "bottle": { "stable": { "files": {} } },
+                "license": "MIT",
+                "deprecated": true,
+                "deprecation_reason": "repo_archived",
+                "deprecation_date": "2024-04-04"
+            }"#,
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        write_as_installed(&mut out, &keg, Some(&formula)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "==> jq 1.7.1 (as installed)\n\
+             Version:     1.7.1\n\
+             Keg-only:    yes (provided by macOS)\n\
+             Depends on:  oniguruma\n\
+             Build deps:  none\n\
+             License:     MIT\n\
+             Upstream:    deprecated (repo archived; since 2024-04-04)\n"
+        );
+
+        let mut out = Vec::new();
+        write_as_installed(&mut out, &keg, None).unwrap();
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .starts_with("No metadata was kept")
+        );
+    }
+
+    #[test]
+    fn lists_keg_files_then_recorded_links() {
+        let (tmp, keg, records) = fixture();
+        let keg_path = tmp.path().join("Cellar/jq/1.7.1");
+        let mut out = Vec::new();
+
+        write_files(&mut out, &keg, &keg_path, &records, false).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("2.0 kB  bin/jq\n"));
+        assert!(out.contains("lib/libjq.dylib -> libjq.1.dylib\n"));
+        assert!(out.contains("4 files,"));
+        assert!(out.find("share/man/man1/jq.1") < out.find("==> Recorded in the prefix"));
+        assert!(out.contains(&format!("-  {}/etc/jq.conf", tmp.path().display())));
+        assert!(out.ends_with("2 paths\n"));
+    }
+
+    #[test]
+    fn json_keeps_the_two_sections_apart() {
+        let (tmp, keg, records) = fixture();
+        let keg_path = tmp.path().join("Cellar/jq/1.7.1");
+        let mut out = Vec::new();
+
+        write_files(&mut out, &keg, &keg_path, &records, true).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        let files = value["files"].as_array().unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f["path"].as_str().unwrap()).collect();
+        assert_eq!(
+            paths,
+            [
+                "bin/jq",
+                "lib/libjq.1.dylib",
+                "lib/libjq.dylib",
+                "share/man/man1/jq.1"
+            ]
+        );
+        assert_eq!(files[0]["size"], 2048);
+        assert_eq!(files[2]["target"], "libjq.1.dylib");
+
+        let links = value["links"].as_array().unwrap();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0]["size"], 2048);
+        assert_eq!(links[1]["kind"], "config");
+        assert!(links[1]["size"].is_null());
     }
 }
@@ -1,15 +1,73 @@
 use std::path::Path;
 
-use crate::init::{InitError, run_init};
+use console::style;
+use tracing::warn;
+
+use crate::init::{InitAction, InitError, InitPlan, run_init};
 use crate::ui::StdUi;
 
 pub fn execute(
     root: &Path,
     prefix: &Path,
     no_modify_path: bool,
+    dry_run: bool,
     ui: &mut StdUi,
 ) -> Result<(), zb_core::Error> {
-    run_init(root, prefix, no_modify_path, ui).map_err(|e| match e {
+    let plan = run_init(root, prefix, no_modify_path, dry_run, ui).map_err(|e| match e {
         InitError::Message(msg) => zb_core::Error::StoreCorruption { message: msg },
-    })
+    })?;
+
+    if dry_run {
+        print_plan(&plan, ui).map_err(ui_error)?;
+    }
+    Ok(())
+}
+
+/// Fetch the formula index for the first command after init.
+pub fn prefetch_index(cache_dir: &Path) -> Result<(), zb_core::Error> {
+    finish_prefetch(zb_io::IndexPrefetch::spawn(cache_dir.to_path_buf())?);
+    Ok(())
+}
+
+/// Give `prefetch` [`zb_io::INDEX_PREFETCH_BUDGET`] to finish; past that
+/// (or on failure) it stays pending and the next command finishes it.
+pub fn finish_prefetch(prefetch: zb_io::IndexPrefetch) {
+    if let Some(Err(e)) = prefetch.wait(zb_io::INDEX_PREFETCH_BUDGET) {
+        warn!(error = %e, "failed to prefetch the formula index");
+    }
+}
+
+fn print_plan(plan: &InitPlan, ui: &mut StdUi) -> std::io::Result<()> {
+    if plan.is_empty() {
+        ui.heading("zerobrew is already initialized; init would change nothing.")?;
+        return Ok(());
+    }
+
+    ui.heading("zb init would:")?;
+    for action in &plan.actions {
+        ui.bullet(action)?;
+        if let InitAction::UpdateShellConfig { block, .. } = action {
+            for line in block.lines() {
+                if line.is_empty() {
+                    ui.blank_line()?;
+                } else {
+                    ui.println(format!("        {}", style(line).dim()))?;
+                }
+            }
+        }
+    }
+
+    ui.blank_line()?;
+    if plan.needs_sudo() {
+        ui.note("sudo is required to create these directories.")?;
+    } else {
+        ui.info("No sudo required.")?;
+    }
+    Ok(())
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
 }
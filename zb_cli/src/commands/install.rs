@@ -1,18 +1,103 @@
 use console::style;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{IsTerminal, Read};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
-use zb_io::{InstallProgress, ProgressCallback};
+use zb_io::{
+    BlobSource, ExecuteResult, FormulaOutcome, InstallAdvice, InstallProgress, Phase,
+    ProgressCallback,
+};
 
+use crate::events::{self, Event, PlannedFormula};
+use crate::format::{human_bytes, human_duration};
+use crate::heartbeat::Heartbeat;
 use crate::ui::StdUi;
 use crate::utils::{normalize_formula_name, suggest_homebrew, suggest_missing_formula_matches};
 
+/// Expand a `-` argument into the whitespace-separated names on stdin, drop
+/// repeated names, and validate every name, so a bad line in a piped list is
+/// reported, along with every other bad line, before anything is installed.
+pub fn resolve_formula_args(
+    formulas: Vec<String>,
+    ui: &mut StdUi,
+) -> Result<Vec<String>, zb_core::Error> {
+    let stdin = std::io::stdin();
+    let stdin_is_terminal = stdin.is_terminal();
+    let names = expand_stdin_arg(formulas, stdin.lock(), stdin_is_terminal)?;
+
+    let mut invalid: Vec<zb_core::Error> = names
+        .iter()
+        .filter_map(|name| normalize_formula_name(name).err())
+        .collect();
+    match invalid.len() {
+        0 => Ok(names),
+        1 => Err(invalid.remove(0)),
+        count => {
+            for err in &invalid {
+                ui.error(err.to_string()).map_err(ui_error)?;
+            }
+            Err(zb_core::Error::InvalidArgument {
+                message: format!("{count} formula names are invalid; nothing was installed"),
+            })
+        }
+    }
+}
+
+fn expand_stdin_arg(
+    formulas: Vec<String>,
+    mut stdin: impl Read,
+    stdin_is_terminal: bool,
+) -> Result<Vec<String>, zb_core::Error> {
+    let mut seen = HashSet::new();
+    let mut names = Vec::new();
+    let mut read_stdin = false;
+    for arg in formulas {
+        if arg != "-" {
+            if seen.insert(arg.clone()) {
+                names.push(arg);
+            }
+            continue;
+        }
+        if read_stdin {
+            continue;
+        }
+        read_stdin = true;
+
+        // Reading a terminal would wait for input nobody knows to type.
+        if stdin_is_terminal {
+            return Err(zb_core::Error::InvalidArgument {
+                message: "`-` reads formula names from stdin, but stdin is a terminal".to_string(),
+            });
+        }
+        let mut input = String::new();
+        stdin
+            .read_to_string(&mut input)
+            .map_err(|e| zb_core::Error::FileError {
+                message: format!("failed to read formula names from stdin: {e}"),
+            })?;
+        if input.split_whitespace().next().is_none() {
+            return Err(zb_core::Error::InvalidArgument {
+                message: "`-` was given but stdin has no formula names".to_string(),
+            });
+        }
+        for name in input.split_whitespace() {
+            if seen.insert(name.to_string()) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
 pub async fn execute(
     installer: &mut zb_io::Installer,
     formulas: Vec<String>,
     no_link: bool,
     build_from_source: bool,
+    fail_fast: bool,
+    accept_disabled: bool,
     ui: &mut StdUi,
 ) -> Result<(), zb_core::Error> {
     let start = Instant::now();
@@ -41,217 +126,919 @@ pub async fn execute(
     }
 
     let mut installed_count = 0usize;
+    let mut blob_sources = Vec::new();
+    let mut failure = None;
 
-    if !normalized_names.is_empty() {
+    if !normalized_names.is_empty() && fail_fast {
         let plan = match installer
             .plan_with_options(&normalized_names, build_from_source)
             .await
         {
             Ok(p) => p,
             Err(e) => {
-                let handled_missing = suggest_missing_formula_matches(installer, &e).await;
+                suggest_for_error(installer, &formulas, &e).await;
+                return Err(e);
+            }
+        };
 
-                if !handled_missing {
-                    for formula in &formulas {
-                        suggest_homebrew(formula, &e);
-                    }
-                }
+        let refused = report_advice(installer, &plan, accept_disabled, ui).await?;
+        if !refused.is_empty() {
+            let names: Vec<String> = refused.into_iter().map(|(name, _)| name).collect();
+            return Err(zb_core::Error::InvalidArgument {
+                message: format!(
+                    "refusing to install disabled formula(s): {}; pass --accept-disabled to install anyway",
+                    names.join(", ")
+                ),
+            });
+        }
+        if !plan.items.is_empty() {
+            print_resolved(&plan, ui)?;
+            let required_by = plan.required_by();
+            let result = execute_plan(installer, plan, no_link, &formulas, ui).await?;
+            installed_count += result.installed;
+            blob_sources.extend(result.blob_sources.into_values());
+            if !required_by.is_empty() {
+                print_summary(&result.outcomes, &required_by, ui)?;
+            }
+        }
+    } else if !normalized_names.is_empty() {
+        let mut isolated = match installer
+            .plan_isolated(&normalized_names, build_from_source)
+            .await
+        {
+            Ok(p) => p,
+            Err(e) => {
+                suggest_for_error(installer, &formulas, &e).await;
                 return Err(e);
             }
         };
 
+        let refused = report_advice(installer, &isolated.plan, accept_disabled, ui).await?;
+        isolated.refuse(refused);
+        let mut outcomes = isolated.not_planned;
+        emit_outcomes(&outcomes, ui)?;
+        let required_by = isolated.plan.required_by();
+        if !isolated.plan.items.is_empty() {
+            print_resolved(&isolated.plan, ui)?;
+            let result =
+                run_with_progress(installer, isolated.plan, no_link, true, &Arc::default(), ui)
+                    .await?;
+            installed_count += result.installed;
+            outcomes.extend(result.outcomes);
+            blob_sources.extend(result.blob_sources.into_values());
+        }
+        let failed = outcomes.iter().find_map(|(_, outcome)| match outcome {
+            FormulaOutcome::Failed(err) => Some(err.clone()),
+            _ => None,
+        });
+        if failed.is_some() || !required_by.is_empty() {
+            print_summary(&outcomes, &required_by, ui)?;
+        }
+        if let Some(err) = failed {
+            suggest_for_error(installer, &formulas, &err).await;
+            failure = Some(err);
+        }
+    }
+
+    if !cask_names.is_empty() {
         ui.heading(format!(
-            "Resolving dependencies ({} packages)...",
-            plan.items.len()
+            "Installing casks ({} packages)...",
+            cask_names.len()
         ))
         .map_err(ui_error)?;
-        for item in &plan.items {
-            ui.bullet(format!(
-                "{} {}",
-                style(&item.formula.name).green(),
-                style(&item.formula.versions.stable).dim()
-            ))
+        let result = installer.install_casks(&cask_names, !no_link).await?;
+        installed_count += result.installed;
+    }
+
+    let elapsed = start.elapsed();
+    ui.blank_line().map_err(ui_error)?;
+    ui.heading(format!(
+        "Installed {} packages in {}{}",
+        style(installed_count).green().bold(),
+        human_duration(elapsed),
+        download_summary(&blob_sources)
+    ))
+    .map_err(ui_error)?;
+
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// ` (2 cached, 1 downloaded: 4.2 MB)`, or nothing when no bottles were
+/// fetched.
+fn download_summary(sources: &[BlobSource]) -> String {
+    if sources.is_empty() {
+        return String::new();
+    }
+    let cached = sources
+        .iter()
+        .filter(|source| matches!(source, BlobSource::Cached))
+        .count();
+    let downloaded_bytes: u64 = sources
+        .iter()
+        .map(|source| match source {
+            BlobSource::Downloaded { bytes } => *bytes,
+            BlobSource::Cached => 0,
+        })
+        .sum();
+    format!(
+        " ({cached} cached, {} downloaded: {})",
+        sources.len() - cached,
+        human_bytes(downloaded_bytes)
+    )
+}
+
+fn phase_message(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Copy => "copying...",
+        Phase::Permissions => "fixing permissions...",
+        Phase::PatchBinaries => "patching binaries...",
+        Phase::PatchText => "patching text files...",
+        Phase::Codesign => "signing...",
+    }
+}
+
+/// `18902` as `18,902`.
+fn group_digits(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Point out aliases, older release lines, installed sibling release lines
+/// and upstream deprecations among the requested formulas. Returns the
+/// disabled formulas, each with the error refusing it, unless
+/// `accept_disabled` is set.
+async fn report_advice(
+    installer: &zb_io::Installer,
+    plan: &zb_io::InstallPlan,
+    accept_disabled: bool,
+    ui: &mut StdUi,
+) -> Result<Vec<(String, zb_core::Error)>, zb_core::Error> {
+    let mut disabled = Vec::new();
+    for advice in installer.install_advice(plan).await {
+        match advice {
+            InstallAdvice::NewerRelease {
+                requested,
+                newer,
+                version,
+            } => ui.note(format!(
+                "{requested} is an older release line; {} {version} is current",
+                style(newer).green()
+            )),
+            InstallAdvice::Deprecated { name, reason } => ui.warn(format!(
+                "{} has been deprecated upstream{}",
+                style(&name).bold(),
+                reason_suffix(reason.as_deref())
+            )),
+            InstallAdvice::Disabled { name, reason } => {
+                let result = ui.warn(format!(
+                    "{} has been disabled upstream{}",
+                    style(&name).bold(),
+                    reason_suffix(reason.as_deref())
+                ));
+                disabled.push(name);
+                result
+            }
+            InstallAdvice::Alias {
+                alias,
+                canonical,
+                installed: true,
+            } => ui.note(format!(
+                "{alias} is an alias for {}, which is already installed",
+                style(canonical).green()
+            )),
+            InstallAdvice::Alias {
+                alias, canonical, ..
+            } => ui.note(format!(
+                "{alias} is an alias for {}",
+                style(canonical).green()
+            )),
+            InstallAdvice::TapChoice {
+                name,
+                chosen,
+                shadowed,
+            } => ui.note(format!(
+                "{name} is in {chosen} and {}; using {} (install {}/{name} for another)",
+                shadowed.join(", "),
+                style(&chosen).green(),
+                shadowed[0]
+            )),
+            InstallAdvice::SiblingLinked {
+                name,
+                sibling,
+                files,
+            } => ui.warn(format!(
+                "{} is installed and links {}; linking {} will conflict with it",
+                style(&sibling).bold(),
+                files.join(", "),
+                style(&name).bold()
+            )),
+        }
+        .map_err(ui_error)?;
+    }
+
+    if accept_disabled {
+        return Ok(Vec::new());
+    }
+    Ok(disabled
+        .into_iter()
+        .map(|name| {
+            let err = zb_core::Error::InvalidArgument {
+                message: format!(
+                    "refusing to install disabled formula {name}; pass --accept-disabled to install anyway"
+                ),
+            };
+            (name, err)
+        })
+        .collect())
+}
+
+/// Upstream reasons are Ruby symbols such as `does_not_build`.
+fn reason_suffix(reason: Option<&str>) -> String {
+    reason
+        .map(|reason| format!(" ({})", reason.replace('_', " ")))
+        .unwrap_or_default()
+}
+
+pub(crate) fn print_resolved(
+    plan: &zb_io::InstallPlan,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    if ui.is_json_lines() {
+        let formulas = plan
+            .items
+            .iter()
+            .map(|item| PlannedFormula {
+                name: &item.formula.name,
+                version: &item.formula.versions.stable,
+            })
+            .collect();
+        events::emit(&Event::PlanResolved { formulas }).map_err(ui_error)?;
+    }
+    ui.heading(format!(
+        "Resolving dependencies ({} packages)...",
+        plan.items.len()
+    ))
+    .map_err(ui_error)?;
+    for item in &plan.items {
+        ui.bullet(format!(
+            "{} {}",
+            style(&item.formula.name).green(),
+            style(&item.formula.versions.stable).dim()
+        ))
+        .map_err(ui_error)?;
+    }
+    for (name, version) in &plan.pinned {
+        ui.note(format!(
+            "{} is pinned; keeping {version}",
+            style(name).bold()
+        ))
+        .map_err(ui_error)?;
+    }
+    Ok(())
+}
+
+/// One line per formula: installed, skipped because a dependency failed, or
+/// failed with the reason. Formulas pulled in as dependencies also say which
+/// requested formulas needed them.
+fn print_summary(
+    outcomes: &[(String, FormulaOutcome)],
+    required_by: &BTreeMap<String, Vec<Vec<String>>>,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let width = outcomes
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+
+    ui.blank_line().map_err(ui_error)?;
+    ui.heading("Install summary:").map_err(ui_error)?;
+    for (name, outcome) in outcomes {
+        let status = match outcome {
+            FormulaOutcome::Installed => style("installed".to_string()).green(),
+            FormulaOutcome::Skipped { dependency } => {
+                style(format!("skipped: dependency failed ({dependency})")).yellow()
+            }
+            FormulaOutcome::Failed(err) => style(format!("failed: {err}")).red(),
+        };
+        let reason = match required_by.get(name) {
+            Some(chains) if !chains.is_empty() => {
+                format!("  {}", style(format_required_by(chains)).dim())
+            }
+            _ => String::new(),
+        };
+        ui.println(format!("    {name:<width$}  {status}{reason}"))
             .map_err(ui_error)?;
+    }
+    Ok(())
+}
+
+/// A `formula-done` event per outcome when streaming events.
+fn emit_outcomes(outcomes: &[(String, FormulaOutcome)], ui: &StdUi) -> Result<(), zb_core::Error> {
+    if !ui.is_json_lines() {
+        return Ok(());
+    }
+    for (name, outcome) in outcomes {
+        let (status, detail) = match outcome {
+            FormulaOutcome::Installed => ("installed", None),
+            FormulaOutcome::Skipped { dependency } => ("skipped", Some(dependency.clone())),
+            FormulaOutcome::Failed(err) => ("failed", Some(err.to_string())),
+        };
+        events::emit(&Event::FormulaDone {
+            name,
+            status,
+            detail,
+        })
+        .map_err(ui_error)?;
+    }
+    Ok(())
+}
+
+/// Longer chains keep only their requested formula and the direct dependent.
+const MAX_CHAIN_LEN: usize = 3;
+/// Direct dependents listed before the rest are only counted.
+const MAX_CHAINS: usize = 2;
+
+/// `required by: git → gettext, curl (+1 more)` for the chains
+/// [`zb_io::InstallPlan::required_by`] gives a dependency.
+fn format_required_by(chains: &[Vec<String>]) -> String {
+    let shown: Vec<String> = chains
+        .iter()
+        .take(MAX_CHAINS)
+        .map(|chain| match chain.as_slice() {
+            [first, .., last] if chain.len() > MAX_CHAIN_LEN => format!("{first} → … → {last}"),
+            _ => chain.join(" → "),
+        })
+        .collect();
+    let mut line = format!("required by: {}", shown.join(", "));
+    if chains.len() > MAX_CHAINS {
+        line.push_str(&format!(" (+{} more)", chains.len() - MAX_CHAINS));
+    }
+    line
+}
+
+async fn suggest_for_error(
+    installer: &mut zb_io::Installer,
+    formulas: &[String],
+    err: &zb_core::Error,
+) {
+    let handled_missing = suggest_missing_formula_matches(installer, err).await;
+
+    if !handled_missing {
+        for formula in formulas {
+            suggest_homebrew(formula, err);
         }
+    }
+}
 
-        let multi = MultiProgress::new();
-        let bars: Arc<Mutex<HashMap<String, ProgressBar>>> = Arc::new(Mutex::new(HashMap::new()));
+/// Resolve `formulas` and write the plan to `path` without installing
+/// anything, so it can be reviewed and later run with `zb apply`.
+pub async fn save_plan(
+    installer: &mut zb_io::Installer,
+    formulas: Vec<String>,
+    build_from_source: bool,
+    path: &Path,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let mut normalized_names = Vec::new();
+    for formula in &formulas {
+        let name = normalize_formula_name(formula)?;
+        if name.starts_with("cask:") {
+            return Err(zb_core::Error::InvalidArgument {
+                message: format!("casks cannot be saved in an install plan: {formula}"),
+            });
+        }
+        normalized_names.push(name);
+    }
 
-        let download_style = ProgressStyle::default_bar()
-            .template("    {prefix:<16} {bar:25.cyan/dim} {bytes:>10}/{total_bytes:<10} {eta:>6}")
-            .unwrap()
-            .progress_chars("━━╸");
+    let plan = match installer
+        .plan_with_options(&normalized_names, build_from_source)
+        .await
+    {
+        Ok(p) => p,
+        Err(e) => {
+            suggest_missing_formula_matches(installer, &e).await;
+            return Err(e);
+        }
+    };
 
-        let spinner_style = ProgressStyle::default_spinner()
-            .template("    {prefix:<16} {spinner:.cyan} {msg}")
-            .unwrap()
-            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏");
+    let saved = installer.save_plan(&plan);
+    let write_ctx = format!("failed to write install plan to {}", path.display());
+    std::fs::write(path, saved.to_json()?).map_err(zb_core::Error::file(write_ctx.as_str()))?;
 
-        let done_style = ProgressStyle::default_spinner()
-            .template("    {prefix:<16} {msg}")
-            .unwrap();
+    ui.heading(format!(
+        "Saved plan for {} packages to {}",
+        style(saved.items.len()).green().bold(),
+        path.display()
+    ))
+    .map_err(ui_error)?;
+    for item in &saved.items {
+        ui.bullet(format!(
+            "{} {}",
+            style(&item.name).green(),
+            style(&item.version).dim()
+        ))
+        .map_err(ui_error)?;
+    }
 
+    Ok(())
+}
+
+/// Download, unpack and link every item of a resolved plan, rendering
+/// per-formula progress bars.
+pub(crate) async fn execute_plan(
+    installer: &mut zb_io::Installer,
+    plan: zb_io::InstallPlan,
+    no_link: bool,
+    requested: &[String],
+    ui: &mut StdUi,
+) -> Result<ExecuteResult, zb_core::Error> {
+    let restored = Arc::default();
+    let result = match run_with_progress(installer, plan, no_link, false, &restored, ui).await {
+        Ok(r) => r,
+        Err(ref e @ zb_core::Error::LinkConflict { ref conflicts }) => {
+            ui.blank_line().map_err(ui_error)?;
+            ui.error("The link step did not complete successfully.")
+                .map_err(ui_error)?;
+            ui.println(unlinked_note(&restored.lock().unwrap()))
+                .map_err(ui_error)?;
+            ui.blank_line().map_err(ui_error)?;
+            ui.println("Possible conflicting files:")
+                .map_err(ui_error)?;
+            for c in conflicts {
+                if let Some(ref owner) = c.owned_by {
+                    ui.println(format!(
+                        "  {} (symlink belonging to {})",
+                        c.path.display(),
+                        style(owner).yellow()
+                    ))
+                    .map_err(ui_error)?;
+                } else {
+                    ui.println(format!("  {}", c.path.display()))
+                        .map_err(ui_error)?;
+                }
+            }
+            ui.blank_line().map_err(ui_error)?;
+            return Err(e.clone());
+        }
+        Err(e) => {
+            suggest_for_error(installer, requested, &e).await;
+            return Err(e);
+        }
+    };
+    Ok(result)
+}
+
+/// Formulas put back at their installed version, with that version, when
+/// the new one couldn't be linked.
+type Restored = Arc<Mutex<Vec<(String, String)>>>;
+
+/// What a failed link left in place: the versions kept for upgrades, or
+/// an installed but unlinked keg for a new formula.
+fn unlinked_note(restored: &[(String, String)]) -> String {
+    if restored.is_empty() {
+        return "The formula was installed, but is not symlinked into the prefix.".to_string();
+    }
+    let kept: Vec<String> = restored
+        .iter()
+        .map(|(name, version)| format!("{name} {version}"))
+        .collect();
+    format!(
+        "The new version was removed; kept {} linked.",
+        kept.join(", ")
+    )
+}
+
+/// Run `plan` behind progress bars. With `isolated`, per-formula failures are
+/// reported in the result's outcomes rather than as an error. Versions put
+/// back after a failed link are added to `restored`.
+async fn run_with_progress(
+    installer: &mut zb_io::Installer,
+    plan: zb_io::InstallPlan,
+    no_link: bool,
+    isolated: bool,
+    restored: &Restored,
+    ui: &mut StdUi,
+) -> Result<ExecuteResult, zb_core::Error> {
+    if ui.is_json_lines() {
         ui.heading("Downloading and installing formulas...")
             .map_err(ui_error)?;
+        let restored = restored.clone();
+        let progress_callback: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
+            if let InstallProgress::LinkRestored { name, version } = &event {
+                restored
+                    .lock()
+                    .unwrap()
+                    .push((name.clone(), version.clone()));
+            }
+            let _ = events::emit(&event);
+        }));
+        let result = run_plan(installer, plan, no_link, isolated, progress_callback).await?;
+        emit_outcomes(&result.outcomes, ui)?;
+        return Ok(result);
+    }
 
-        let bars_clone = bars.clone();
-        let multi_clone = multi.clone();
-        let download_style_clone = download_style.clone();
-        let spinner_style_clone = spinner_style.clone();
-        let done_style_clone = done_style.clone();
+    let multi = MultiProgress::new();
+    let bars: Arc<Mutex<HashMap<String, ProgressBar>>> = Arc::new(Mutex::new(HashMap::new()));
 
-        let progress_callback: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
-            let mut bars = bars_clone.lock().unwrap();
-            match event {
-                InstallProgress::DownloadStarted { name, total_bytes } => {
-                    let pb = if let Some(total) = total_bytes {
-                        let pb = multi_clone.add(ProgressBar::new(total));
-                        pb.set_style(download_style_clone.clone());
-                        pb
-                    } else {
-                        let pb = multi_clone.add(ProgressBar::new_spinner());
-                        pb.set_style(spinner_style_clone.clone());
-                        pb.set_message("downloading...");
-                        pb.enable_steady_tick(std::time::Duration::from_millis(80));
-                        pb
-                    };
-                    pb.set_prefix(name.clone());
-                    bars.insert(name, pb);
+    let download_style = ProgressStyle::default_bar()
+        .template("    {prefix:<16} {bar:25.cyan/dim} {bytes:>10}/{total_bytes:<10} {eta:>6}")
+        .unwrap()
+        .progress_chars("━━╸");
+
+    let spinner_style = ProgressStyle::default_spinner()
+        .template("    {prefix:<16} {spinner:.cyan} {msg}")
+        .unwrap()
+        .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏");
+
+    let done_style = ProgressStyle::default_spinner()
+        .template("    {prefix:<16} {msg}")
+        .unwrap();
+
+    ui.heading("Downloading and installing formulas...")
+        .map_err(ui_error)?;
+
+    let bars_clone = bars.clone();
+    let multi_clone = multi.clone();
+    let download_style_clone = download_style.clone();
+    let spinner_style_clone = spinner_style.clone();
+    let done_style_clone = done_style.clone();
+    // Where each bottle came from, appended to its final line.
+    let sources: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+    let heartbeat = ui
+        .heartbeat()
+        .map(|interval| Arc::new(Heartbeat::start(interval, plan.items.len())));
+    let heartbeat_clone = heartbeat.clone();
+    let restored = restored.clone();
+
+    let progress_callback: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
+        if let Some(heartbeat) = &heartbeat_clone {
+            heartbeat.observe(&event);
+        }
+        let mut bars = bars_clone.lock().unwrap();
+        match event {
+            InstallProgress::DownloadStarted { name, total_bytes } => {
+                let pb = if let Some(total) = total_bytes {
+                    let pb = multi_clone.add(ProgressBar::new(total));
+                    pb.set_style(download_style_clone.clone());
+                    pb
+                } else {
+                    let pb = multi_clone.add(ProgressBar::new_spinner());
+                    pb.set_style(spinner_style_clone.clone());
+                    pb.set_message("downloading...");
+                    pb.enable_steady_tick(std::time::Duration::from_millis(80));
+                    pb
+                };
+                pb.set_prefix(name.clone());
+                bars.insert(name, pb);
+            }
+            InstallProgress::DownloadProgress {
+                name,
+                downloaded,
+                total_bytes,
+            } => {
+                if let Some(pb) = bars.get(&name)
+                    && total_bytes.is_some()
+                {
+                    pb.set_position(downloaded);
                 }
-                InstallProgress::DownloadProgress {
-                    name,
-                    downloaded,
-                    total_bytes,
-                } => {
-                    if let Some(pb) = bars.get(&name)
-                        && total_bytes.is_some()
-                    {
-                        pb.set_position(downloaded);
-                    }
+            }
+            InstallProgress::DownloadCached { name } => {
+                let pb = multi_clone.add(ProgressBar::new_spinner());
+                pb.set_style(spinner_style_clone.clone());
+                pb.set_prefix(name.clone());
+                pb.set_message("cached");
+                pb.enable_steady_tick(std::time::Duration::from_millis(80));
+                sources
+                    .lock()
+                    .unwrap()
+                    .insert(name.clone(), "(cached)".to_string());
+                bars.insert(name, pb);
+            }
+            InstallProgress::DownloadCompleted { name, total_bytes } => {
+                if total_bytes > 0 {
+                    sources.lock().unwrap().insert(
+                        name.clone(),
+                        format!("(downloaded {})", human_bytes(total_bytes)),
+                    );
                 }
-                InstallProgress::DownloadCompleted { name, total_bytes } => {
-                    if let Some(pb) = bars.get(&name) {
-                        if total_bytes > 0 {
-                            pb.set_position(total_bytes);
-                        }
-                        pb.set_style(spinner_style_clone.clone());
-                        pb.set_message("unpacking...");
-                        pb.enable_steady_tick(std::time::Duration::from_millis(80));
+                if let Some(pb) = bars.get(&name) {
+                    if total_bytes > 0 {
+                        pb.set_position(total_bytes);
                     }
+                    pb.set_style(spinner_style_clone.clone());
+                    pb.set_message("unpacking...");
+                    pb.enable_steady_tick(std::time::Duration::from_millis(80));
                 }
-                InstallProgress::UnpackStarted { name } => {
-                    if let Some(pb) = bars.get(&name) {
-                        pb.set_message("unpacking...");
-                    }
+            }
+            InstallProgress::UnpackStarted { name } => {
+                if let Some(pb) = bars.get(&name) {
+                    pb.set_message("unpacking...");
                 }
-                InstallProgress::UnpackCompleted { name } => {
-                    if let Some(pb) = bars.get(&name) {
-                        pb.set_message("unpacked");
-                    }
+            }
+            InstallProgress::UnpackProgress { name, files, bytes } => {
+                if let Some(pb) = bars.get(&name) {
+                    pb.set_message(format!("copying {files} files ({})...", human_bytes(bytes)));
                 }
-                InstallProgress::LinkStarted { name } => {
-                    if let Some(pb) = bars.get(&name) {
-                        pb.set_message("linking...");
-                    }
+            }
+            InstallProgress::PatchProgress { name, files, total } => {
+                if let Some(pb) = bars.get(&name) {
+                    pb.set_message(format!(
+                        "patching {}/{} files...",
+                        group_digits(files),
+                        group_digits(total)
+                    ));
                 }
-                InstallProgress::LinkCompleted { name } => {
-                    if let Some(pb) = bars.get(&name) {
-                        pb.set_message("linked");
-                    }
+            }
+            InstallProgress::UnpackPhase { name, phase } => {
+                if let Some(pb) = bars.get(&name) {
+                    pb.set_message(phase_message(phase));
                 }
-                InstallProgress::LinkSkipped { name, reason } => {
-                    if let Some(pb) = bars.get(&name) {
-                        pb.set_message(format!("keg-only ({})", reason));
-                    }
+            }
+            InstallProgress::UnpackCompleted { name } => {
+                if let Some(pb) = bars.get(&name) {
+                    pb.set_message("unpacked");
                 }
-                InstallProgress::InstallCompleted { name } => {
-                    if let Some(pb) = bars.get(&name) {
-                        pb.set_style(done_style_clone.clone());
-                        pb.set_message(format!("{} installed", style("✓").green()));
-                        pb.finish();
-                    }
+            }
+            InstallProgress::LinkStarted { name } => {
+                if let Some(pb) = bars.get(&name) {
+                    pb.set_message("linking...");
                 }
             }
-        }));
-
-        let result_val = installer
-            .execute_with_progress(plan, !no_link, Some(progress_callback))
-            .await;
-
-        {
-            let bars = bars.lock().unwrap();
-            for (_, pb) in bars.iter() {
-                if !pb.is_finished() {
+            InstallProgress::LinkCompleted { name } => {
+                if let Some(pb) = bars.get(&name) {
+                    pb.set_message("linked");
+                }
+            }
+            InstallProgress::LinkSkipped { name, reason } => {
+                if let Some(pb) = bars.get(&name) {
+                    pb.set_message(format!("keg-only ({})", reason));
+                }
+            }
+            InstallProgress::LinkRestored { name, version } => {
+                if let Some(pb) = bars.get(&name) {
+                    pb.set_message(format!("kept {version} linked"));
+                }
+                restored.lock().unwrap().push((name, version));
+            }
+            InstallProgress::RateLimited { host, wait_secs } => {
+                let _ = multi_clone.println(format!(
+                    "    {} rate limited by {}, waiting {}s",
+                    style("!").yellow(),
+                    host,
+                    wait_secs
+                ));
+            }
+            InstallProgress::InstallCompleted { name } => {
+                if let Some(pb) = bars.get(&name) {
+                    let source = sources.lock().unwrap().remove(&name).unwrap_or_default();
+                    pb.set_style(done_style_clone.clone());
+                    pb.set_message(format!(
+                        "{} installed {}",
+                        style("✓").green(),
+                        style(source).dim()
+                    ));
                     pb.finish();
                 }
             }
         }
+    }));
 
-        let result = match result_val {
-            Ok(r) => r,
-            Err(ref e @ zb_core::Error::LinkConflict { ref conflicts }) => {
-                ui.blank_line().map_err(ui_error)?;
-                ui.error("The link step did not complete successfully.")
-                    .map_err(ui_error)?;
-                ui.println("The formula was installed, but is not symlinked into the prefix.")
-                    .map_err(ui_error)?;
-                ui.blank_line().map_err(ui_error)?;
-                ui.println("Possible conflicting files:")
-                    .map_err(ui_error)?;
-                for c in conflicts {
-                    if let Some(ref owner) = c.owned_by {
-                        ui.println(format!(
-                            "  {} (symlink belonging to {})",
-                            c.path.display(),
-                            style(owner).yellow()
-                        ))
-                        .map_err(ui_error)?;
-                    } else {
-                        ui.println(format!("  {}", c.path.display()))
-                            .map_err(ui_error)?;
-                    }
-                }
-                ui.blank_line().map_err(ui_error)?;
-                return Err(e.clone());
-            }
-            Err(e) => {
-                let handled_missing = suggest_missing_formula_matches(installer, &e).await;
+    let result = run_plan(installer, plan, no_link, isolated, progress_callback).await;
+    if let Some(heartbeat) = heartbeat {
+        heartbeat.stop();
+    }
 
-                if !handled_missing {
-                    for formula in &formulas {
-                        suggest_homebrew(formula, &e);
-                    }
-                }
-                return Err(e);
+    {
+        let bars = bars.lock().unwrap();
+        for (_, pb) in bars.iter() {
+            if !pb.is_finished() {
+                pb.finish();
             }
-        };
-        installed_count += result.installed;
+        }
     }
 
-    if !cask_names.is_empty() {
-        ui.heading(format!(
-            "Installing casks ({} packages)...",
-            cask_names.len()
-        ))
-        .map_err(ui_error)?;
-        let result = installer.install_casks(&cask_names, !no_link).await?;
-        installed_count += result.installed;
+    result
+}
+
+async fn run_plan(
+    installer: &mut zb_io::Installer,
+    plan: zb_io::InstallPlan,
+    no_link: bool,
+    isolated: bool,
+    progress_callback: Arc<ProgressCallback>,
+) -> Result<ExecuteResult, zb_core::Error> {
+    if isolated {
+        installer
+            .execute_isolated(plan, !no_link, Some(progress_callback))
+            .await
+    } else {
+        installer
+            .execute_with_progress(plan, !no_link, Some(progress_callback))
+            .await
     }
+}
 
-    let elapsed = start.elapsed();
-    ui.blank_line().map_err(ui_error)?;
-    ui.heading(format!(
-        "Installed {} packages in {:.2}s",
-        style(installed_count).green().bold(),
-        elapsed.as_secs_f64()
+/// The `--timings` summary: requests made and how many connections they
+/// needed, to show that the pool was reused, and the memory bottles were
+/// unpacked within.
+pub fn print_timings(installer: &zb_io::Installer, ui: &mut StdUi) -> Result<(), zb_core::Error> {
+    let stats = installer.connection_stats();
+    ui.heading("Timings").map_err(ui_error)?;
+    ui.bullet(format!(
+        "HTTP requests: {} over {} connections ({} reused)",
+        style(stats.requests).bold(),
+        style(stats.connections_opened).bold(),
+        style(stats.reused()).green()
     ))
     .map_err(ui_error)?;
 
+    let (limits, extraction) = installer.extraction_budget();
+    ui.bullet(extraction_line(&limits, &extraction))
+        .map_err(ui_error)?;
     Ok(())
 }
 
+/// `Extraction memory: 2.1 GB budget, up to 28 at once with 8.4 MB read
+/// buffers (peak 3)`.
+fn extraction_line(limits: &zb_io::ExtractionLimits, stats: &zb_io::ExtractionStats) -> String {
+    let mode = if limits.streamed() {
+        format!(
+            "one at a time, streamed with {} read buffers",
+            human_bytes(limits.read_buffer as u64)
+        )
+    } else {
+        format!(
+            "up to {} at once with {} read buffers",
+            limits.max_concurrent,
+            human_bytes(limits.read_buffer as u64)
+        )
+    };
+    format!(
+        "Extraction memory: {} budget, {mode} (peak {})",
+        style(human_bytes(limits.budget)).bold(),
+        stats.peak_in_flight
+    )
+}
+
 fn ui_error(err: std::io::Error) -> zb_core::Error {
     zb_core::Error::FileError {
         message: format!("failed to write CLI output: {err}"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        expand_stdin_arg, extraction_line, format_required_by, group_digits, unlinked_note,
+    };
+    use zb_io::installer::install::PlannedInstall;
+
+    fn args(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn file_counts_are_grouped_by_thousands() {
+        assert_eq!(group_digits(0), "0");
+        assert_eq!(group_digits(999), "999");
+        assert_eq!(group_digits(3214), "3,214");
+        assert_eq!(group_digits(1_018_902), "1,018,902");
+    }
+
+    #[test]
+    fn timings_show_the_extraction_budget() {
+        let budget = zb_io::ExtractionBudget::new(2_147_483_648);
+        let stats = zb_io::ExtractionStats {
+            peak_in_flight: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            extraction_line(&budget.limits(), &stats),
+            "Extraction memory: 2.1 GB budget, up to 31 at once with 262.1 kB read buffers (peak 3)"
+        );
+
+        let tight = zb_io::ExtractionBudget::new(16 << 20);
+        assert_eq!(
+            extraction_line(&tight.limits(), &zb_io::ExtractionStats::default()),
+            "Extraction memory: 16.8 MB budget, one at a time, streamed with 8.2 kB read buffers (peak 0)"
+        );
+    }
+
+    #[test]
+    fn a_failed_link_names_the_version_kept() {
+        assert_eq!(
+            unlinked_note(&[]),
+            "The formula was installed, but is not symlinked into the prefix."
+        );
+        assert_eq!(
+            unlinked_note(&[("jq".to_string(), "1.7.1".to_string())]),
+            "The new version was removed; kept jq 1.7.1 linked."
+        );
+    }
+
+    #[test]
+    fn dash_reads_names_from_stdin_and_deduplicates() {
+        let stdin = "jq wget\n\njq\n  tree\t\n".as_bytes();
+        let names = expand_stdin_arg(args(&["tree", "-", "git"]), stdin, false).unwrap();
+        assert_eq!(names, ["tree", "jq", "wget", "git"]);
+    }
+
+    #[test]
+    fn empty_stdin_is_an_error() {
+        let err = expand_stdin_arg(args(&["-"]), " \n\n".as_bytes(), false).unwrap_err();
+        assert!(err.to_string().contains("stdin has no formula names"));
+    }
+
+    #[test]
+    fn terminal_stdin_is_not_read() {
+        let err = expand_stdin_arg(args(&["-"]), "jq\n".as_bytes(), true).unwrap_err();
+        assert!(err.to_string().contains("stdin is a terminal"));
+    }
+
+    fn chain(names: &[&str]) -> Vec<String> {
+        args(names)
+    }
+
+    fn planned(name: &str, deps: &[&str], explicit: bool) -> PlannedInstall {
+        let json = serde_json::json!({
+            "name": name,
+            "versions": { "stable": "1.0" },
+            "dependencies": deps,
+            "bottle": { "stable": { "files": {} } },
+        });
+        PlannedInstall {
+            install_name: name.to_string(),
+            formula: zb_core::Formula::from_value(name, json).unwrap(),
+            method: zb_core::InstallMethod::Bottle(zb_core::SelectedBottle {
+                tag: "all".to_string(),
+                url: String::new(),
+                sha256: String::new(),
+            }),
+            explicit,
+        }
+    }
+
+    #[test]
+    fn required_by_shows_short_chains_whole() {
+        assert_eq!(format_required_by(&[chain(&["jq"])]), "required by: jq");
+        assert_eq!(
+            format_required_by(&[chain(&["foo", "bar", "baz"])]),
+            "required by: foo → bar → baz"
+        );
+    }
+
+    #[test]
+    fn required_by_truncates_long_chains_and_many_dependents() {
+        assert_eq!(
+            format_required_by(&[chain(&["ffmpeg", "libass", "harfbuzz", "cairo", "glib"])]),
+            "required by: ffmpeg → … → glib"
+        );
+        assert_eq!(
+            format_required_by(&[
+                chain(&["a"]),
+                chain(&["b", "c"]),
+                chain(&["d"]),
+                chain(&["e"])
+            ]),
+            "required by: a, b → c (+2 more)"
+        );
+    }
+
+    #[test]
+    fn required_by_for_a_deep_plan_graph() {
+        // foo -> bar -> l1 -> l2 -> l3 -> leaf, and foo -> leaf directly.
+        let plan = zb_io::InstallPlan {
+            items: vec![
+                planned("foo", &["bar", "leaf"], true),
+                planned("bar", &["l1"], false),
+                planned("l1", &["l2"], false),
+                planned("l2", &["l3"], false),
+                planned("l3", &["leaf"], false),
+                planned("leaf", &[], false),
+            ],
+            ..Default::default()
+        };
+        let required_by = plan.required_by();
+
+        assert_eq!(format_required_by(&required_by["bar"]), "required by: foo");
+        assert_eq!(
+            format_required_by(&required_by["l1"]),
+            "required by: foo → bar"
+        );
+        assert_eq!(
+            format_required_by(&required_by["leaf"]),
+            "required by: foo, foo → … → l3"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::io::{self, Write};
+
+use console::style;
+use serde_json::json;
+use zb_core::ForbiddenLicenses;
+use zb_io::InstalledKeg;
+
+use crate::ui::StdUi;
+
+const UNKNOWN: &str = "unknown";
+
+/// Print the license of every installed formula. With `forbid`, fail when
+/// any of them can only be used under a forbidden license; formulas with
+/// no recorded license are reported but never fail the check.
+pub fn execute(
+    installer: &mut zb_io::Installer,
+    json: bool,
+    forbid: Option<String>,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let forbidden = forbid
+        .as_deref()
+        .map(ForbiddenLicenses::parse)
+        .transpose()?;
+    let kegs = installer.list_installed()?;
+    let check = forbidden.as_ref().map(|f| Check::run(&kegs, f));
+
+    let mut out = io::stdout().lock();
+    let written = if json {
+        write_json(&mut out, &kegs, forbidden.as_ref())
+    } else if kegs.is_empty() {
+        writeln!(out, "No formulas installed.")
+    } else {
+        write_table(&mut out, &kegs)
+    };
+    written.map_err(ui_error)?;
+
+    let Some(check) = check else {
+        return Ok(());
+    };
+    if !check.unknown.is_empty() {
+        ui.warn(format!(
+            "No license recorded for {}",
+            check.unknown.join(", ")
+        ))
+        .map_err(ui_error)?;
+    }
+    if check.forbidden.is_empty() {
+        return Ok(());
+    }
+    Err(zb_core::Error::ExecutionError {
+        message: format!(
+            "{} installed {} a forbidden license: {}",
+            check.forbidden.len(),
+            if check.forbidden.len() == 1 {
+                "formula has"
+            } else {
+                "formulas have"
+            },
+            check.forbidden.join(", ")
+        ),
+    })
+}
+
+/// Formulas that fail a `--forbid` check, and those that can't be checked,
+/// each as `name (license)` or `name`, in name order.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Check {
+    forbidden: Vec<String>,
+    unknown: Vec<String>,
+}
+
+impl Check {
+    fn run(kegs: &[InstalledKeg], forbidden: &ForbiddenLicenses) -> Self {
+        let mut check = Self::default();
+        for keg in kegs {
+            match license(keg) {
+                None => check.unknown.push(keg.name.clone()),
+                Some(license) if forbidden.forbids(license) => {
+                    check.forbidden.push(format!("{} ({license})", keg.name));
+                }
+                Some(_) => {}
+            }
+        }
+        check
+    }
+}
+
+fn license(keg: &InstalledKeg) -> Option<&str> {
+    keg.license.as_deref().filter(|l| !l.trim().is_empty())
+}
+
+fn write_table(out: &mut impl Write, kegs: &[InstalledKeg]) -> io::Result<()> {
+    let name_width = kegs
+        .iter()
+        .map(|k| k.name.len())
+        .chain(["Formula".len()])
+        .max()
+        .unwrap_or_default();
+    let version_width = kegs
+        .iter()
+        .map(|k| k.version.len())
+        .chain(["Version".len()])
+        .max()
+        .unwrap_or_default();
+
+    writeln!(
+        out,
+        "{:<name_width$}  {:<version_width$}  License",
+        "Formula", "Version"
+    )?;
+    for keg in kegs {
+        let license = match license(keg) {
+            Some(license) => license.to_string(),
+            None => style(UNKNOWN).yellow().to_string(),
+        };
+        writeln!(
+            out,
+            "{:<name_width$}  {:<version_width$}  {license}",
+            keg.name, keg.version
+        )?;
+    }
+    Ok(())
+}
+
+/// One object per formula; `license` is null when unknown, and `forbidden`
+/// is only present when checking.
+fn write_json(
+    out: &mut impl Write,
+    kegs: &[InstalledKeg],
+    forbidden: Option<&ForbiddenLicenses>,
+) -> io::Result<()> {
+    let entries: Vec<serde_json::Value> = kegs
+        .iter()
+        .map(|keg| {
+            let mut entry = json!({
+                "name": keg.name,
+                "version": keg.version,
+                "license": license(keg),
+            });
+            if let Some(forbidden) = forbidden {
+                entry["forbidden"] = json!(license(keg).is_some_and(|l| forbidden.forbids(l)));
+            }
+            entry
+        })
+        .collect();
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&entries).unwrap_or_default()
+    )
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zb_core::Formula;
+
+    use super::*;
+
+    /// Kegs as recorded from formula metadata, licenses included.
+    fn installed(metadata: &[&str]) -> Vec<InstalledKeg> {
+        metadata
+            .iter()
+            .map(|json| {
+                let formula = Formula::from_json("fixture", json).unwrap();
+                InstalledKeg {
+                    name: formula.name.clone(),
+                    version: formula.effective_version(),
+                    store_key: "abc".to_string(),
+                    installed_at: 0,
+                    explicit: true,
+                    copy_strategy: None,
+                    revision: formula.revision,
+                    rebuild: Some(formula.bottle.stable.rebuild),
+                    formula_version: None,
+                    pinned: false,
+                    license: formula.license,
+                }
+            })
+            .collect()
+    }
+
+    fn fixture() -> Vec<InstalledKeg> {
+        let bottle = r#""bottle": {"stable": {"files": {}}}"#;
+        installed(&[
+            &format!(
+                r#"{{"name": "git", "versions": {{"stable": "2.47.0"}}, "dependencies": [], {bottle}, "license": "GPL-2.0-only AND LGPL-2.1-or-later"}}"#
+            ),
+            &format!(
+                r#"{{"name": "jq", "versions": {{"stable": "1.7.1"}}, "dependencies": [], {bottle}, "license": "MIT"}}"#
+            ),
+            &format!(
+                r#"{{"name": "mystery", "versions": {{"stable": "0.1"}}, "dependencies": [], {bottle}, "license": null}}"#
+            ),
+            &format!(
+                r#"{{"name": "ripgrep", "versions": {{"stable": "14.1.1"}}, "dependencies": [], {bottle}, "license": "Unlicense OR MIT"}}"#
+            ),
+        ])
+    }
+
+    #[test]
+    fn forbidding_licenses_checks_compound_expressions() {
+        let kegs = fixture();
+
+        let check = Check::run(&kegs, &ForbiddenLicenses::parse("GPL-*").unwrap());
+        assert_eq!(
+            check.forbidden,
+            ["git (GPL-2.0-only AND LGPL-2.1-or-later)"]
+        );
+        assert_eq!(check.unknown, ["mystery"]);
+
+        // ripgrep can still be used under the Unlicense.
+        let check = Check::run(&kegs, &ForbiddenLicenses::parse("MIT").unwrap());
+        assert_eq!(check.forbidden, ["jq (MIT)"]);
+    }
+
+    #[test]
+    fn table_lines_up_and_marks_unknown_licenses() {
+        let mut out = Vec::new();
+        write_table(&mut out, &fixture()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Formula  Version  License\n\
+             git      2.47.0   GPL-2.0-only AND LGPL-2.1-or-later\n\
+             jq       1.7.1    MIT\n\
+             mystery  0.1      unknown\n\
+             ripgrep  14.1.1   Unlicense OR MIT\n"
+        );
+    }
+
+    #[test]
+    fn json_has_null_for_unknown_and_flags_forbidden_when_checking() {
+        let kegs = fixture();
+        let mut out = Vec::new();
+        write_json(
+            &mut out,
+            &kegs,
+            Some(&ForbiddenLicenses::parse("GPL-*").unwrap()),
+        )
+        .unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            entries[0],
+            json!({
+                "name": "git",
+                "version": "2.47.0",
+                "license": "GPL-2.0-only AND LGPL-2.1-or-later",
+                "forbidden": true,
+            })
+        );
+        assert_eq!(entries[2]["license"], serde_json::Value::Null);
+        assert_eq!(entries[2]["forbidden"], false);
+
+        let mut out = Vec::new();
+        write_json(&mut out, &kegs, None).unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert!(entries[1].get("forbidden").is_none());
+    }
+}
@@ -1,15 +1,262 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use console::style;
+use serde_json::json;
+use zb_core::Lifecycle;
+use zb_io::ListFilter;
+
+use crate::format::relative_time_at;
+use crate::porcelain::{self, Porcelain};
+
+/// The filter the `--explicit-only`, `--deps-only` and `--installed-by`
+/// flags ask for; clap keeps them exclusive.
+pub fn filter(explicit_only: bool, deps_only: bool, installed_by: Option<String>) -> ListFilter {
+    match installed_by {
+        Some(name) => ListFilter::InstalledBy(name),
+        None if explicit_only => ListFilter::Explicit,
+        None if deps_only => ListFilter::Dependencies,
+        None => ListFilter::All,
+    }
+}
 
-pub fn execute(installer: &mut zb_io::Installer) -> Result<(), zb_core::Error> {
-    let installed = installer.list_installed()?;
+pub fn execute(
+    state: &zb_io::InstalledState,
+    filter: &ListFilter,
+    json: bool,
+    porcelain: Option<Porcelain>,
+) -> Result<(), zb_core::Error> {
+    let installed = state.list_filtered(filter)?;
+    let upstream = by_name(state.upstream_statuses());
+    if let Some(Porcelain::V1) = porcelain {
+        return porcelain::write_list(&mut io::stdout().lock(), &installed, &upstream)
+            .map_err(ui_error);
+    }
+    if json {
+        return write_json(
+            &mut io::stdout().lock(),
+            &installed,
+            &HashMap::new(),
+            &upstream,
+        )
+        .map_err(ui_error);
+    }
+    print(installed, filter, &HashMap::new(), &upstream);
+    Ok(())
+}
 
+/// `--outdated-cache` reads the metadata cache, which takes the installer.
+pub fn with_cached_updates(
+    installer: &zb_io::Installer,
+    filter: &ListFilter,
+    json: bool,
+) -> Result<(), zb_core::Error> {
+    let updates = installer
+        .cached_updates()?
+        .into_iter()
+        .map(|update| (update.name.clone(), update))
+        .collect();
+    let installed = installer.list_filtered(filter)?;
+    let upstream = by_name(installer.upstream_statuses());
+    if json {
+        return write_json(&mut io::stdout().lock(), &installed, &updates, &upstream)
+            .map_err(ui_error);
+    }
+    print(installed, filter, &updates, &upstream);
+    Ok(())
+}
+
+/// Upstream markers are only advice, so a list whose statuses can't be
+/// read back is shown without them.
+fn by_name(
+    statuses: Result<Vec<zb_io::UpstreamStatus>, zb_core::Error>,
+) -> HashMap<String, Lifecycle> {
+    statuses
+        .unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "could not read upstream statuses");
+            Vec::new()
+        })
+        .into_iter()
+        .map(|status| (status.name, status.lifecycle))
+        .collect()
+}
+
+/// Formulas upstream deprecated or disabled are marked as of the last
+/// command that fetched their metadata, so this works offline.
+fn print(
+    installed: Vec<zb_io::InstalledKeg>,
+    filter: &ListFilter,
+    updates: &HashMap<String, zb_io::CachedUpdate>,
+    upstream: &HashMap<String, Lifecycle>,
+) {
     if installed.is_empty() {
-        println!("No formulas installed.");
-    } else {
-        for keg in installed {
-            println!("{} {}", style(&keg.name).bold(), style(&keg.version).dim());
+        if *filter == ListFilter::All {
+            println!("No formulas installed.");
+        } else {
+            println!("No installed formulas match.");
         }
+        return;
     }
 
-    Ok(())
+    let now = SystemTime::now();
+    for keg in installed {
+        let mut marker = match upstream.get(&keg.name) {
+            Some(Lifecycle::Deprecated { .. }) => format!(" {}", style("(deprecated)").yellow()),
+            Some(Lifecycle::Disabled { .. }) => format!(" {}", style("(disabled)").red()),
+            Some(Lifecycle::Active) | None => String::new(),
+        };
+        if keg.pinned {
+            marker.push_str(&format!(" {}", style("(pinned)").cyan()));
+        }
+        match updates.get(&keg.name) {
+            Some(update) => println!(
+                "{} {}{marker} {}",
+                style(&keg.name).bold(),
+                style(&keg.version).dim(),
+                style(annotation(update, now)).yellow()
+            ),
+            None => println!(
+                "{} {}{marker}",
+                style(&keg.name).bold(),
+                style(&keg.version).dim()
+            ),
+        }
+    }
+}
+
+/// One object per formula. `upstream` is `deprecated`, `disabled` or null,
+/// `pinned` says whether `zb pin` holds it, and `cached_version` is only
+/// present with `--outdated-cache` when the cache has a newer version.
+fn write_json(
+    out: &mut impl Write,
+    installed: &[zb_io::InstalledKeg],
+    updates: &HashMap<String, zb_io::CachedUpdate>,
+    upstream: &HashMap<String, Lifecycle>,
+) -> io::Result<()> {
+    let entries: Vec<serde_json::Value> = installed
+        .iter()
+        .map(|keg| {
+            let status = match upstream.get(&keg.name) {
+                Some(Lifecycle::Deprecated { .. }) => Some("deprecated"),
+                Some(Lifecycle::Disabled { .. }) => Some("disabled"),
+                Some(Lifecycle::Active) | None => None,
+            };
+            let mut entry = json!({
+                "name": keg.name,
+                "version": keg.version,
+                "explicit": keg.explicit,
+                "upstream": status,
+                "pinned": keg.pinned,
+            });
+            if let Some(update) = updates.get(&keg.name) {
+                entry["cached_version"] = json!(update.cached_version);
+            }
+            entry
+        })
+        .collect();
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&entries).unwrap_or_default()
+    )
+}
+
+fn ui_error(err: io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
+
+/// The cache is only as fresh as the last command that refreshed it, so
+/// the hint says how old it is rather than claiming an update exists.
+fn annotation(update: &zb_io::CachedUpdate, now: SystemTime) -> String {
+    let unix = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64)
+    };
+    format!(
+        "(newer: {} cached {}; may be stale)",
+        update.cached_version,
+        relative_time_at(unix(update.fetched_at), unix(now))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn annotation_names_the_cached_version_and_its_age() {
+        let now = SystemTime::now();
+        let update = zb_io::CachedUpdate {
+            name: "jq".to_string(),
+            installed_version: "1.7.0".to_string(),
+            cached_version: "1.8.0".to_string(),
+            fetched_at: now - Duration::from_secs(3 * 86400 + 5),
+        };
+
+        assert_eq!(
+            annotation(&update, now),
+            "(newer: 1.8.0 cached 3 days ago; may be stale)"
+        );
+    }
+
+    #[test]
+    fn flags_map_to_one_filter() {
+        assert_eq!(filter(false, false, None), ListFilter::All);
+        assert_eq!(filter(true, false, None), ListFilter::Explicit);
+        assert_eq!(filter(false, true, None), ListFilter::Dependencies);
+        assert_eq!(
+            filter(false, false, Some("git".into())),
+            ListFilter::InstalledBy("git".into())
+        );
+    }
+
+    #[test]
+    fn json_carries_the_install_reason_and_upstream_status() {
+        let keg = |name: &str, explicit| zb_io::InstalledKeg {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            store_key: String::new(),
+            installed_at: 0,
+            explicit,
+            copy_strategy: None,
+            license: None,
+            revision: 0,
+            rebuild: None,
+            formula_version: None,
+            pinned: false,
+        };
+        let upstream = HashMap::from([(
+            "gettext".to_string(),
+            Lifecycle::Deprecated {
+                reason: Some("unmaintained".into()),
+            },
+        )]);
+
+        let mut out = Vec::new();
+        write_json(
+            &mut out,
+            &[keg("gettext", false), keg("git", true)],
+            &HashMap::new(),
+            &upstream,
+        )
+        .unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            entries[0],
+            json!({
+                "name": "gettext",
+                "version": "1.0",
+                "explicit": false,
+                "upstream": "deprecated",
+                "pinned": false,
+            })
+        );
+        assert_eq!(entries[1]["explicit"], json!(true));
+        assert_eq!(entries[1]["upstream"], serde_json::Value::Null);
+    }
 }
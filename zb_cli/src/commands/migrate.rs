@@ -1,10 +1,11 @@
+use crate::prompt::{PromptPolicy, confirm};
 use crate::ui::{PromptDefault, StdUi};
 use console::style;
 use std::process::Command;
 
 pub async fn execute(
     installer: &mut zb_io::Installer,
-    yes: bool,
+    policy: PromptPolicy,
     force: bool,
     ui: &mut StdUi,
 ) -> Result<(), zb_core::Error> {
@@ -66,11 +67,7 @@ pub async fn execute(
     }
     ui.blank_line().map_err(ui_error)?;
 
-    if !yes
-        && !ui
-            .prompt_yes_no("Continue with migration? [y/N]", PromptDefault::No)
-            .map_err(ui_error)?
-    {
+    if !confirm(ui, "Continue with migration?", PromptDefault::No, policy)? {
         ui.println("Aborted.").map_err(ui_error)?;
         return Ok(());
     }
@@ -89,6 +86,8 @@ pub async fn execute(
         formula_names.clone(),
         false, // no_link
         false, // build_from_source
+        false, // fail_fast
+        true,  // accept_disabled
         ui,
     )
     .await
@@ -125,17 +124,15 @@ pub async fn execute(
     }
 
     ui.blank_line().map_err(ui_error)?;
-    if !yes
-        && !ui
-            .prompt_yes_no(
-                &format!(
-                    "Uninstall {} formula(s) from Homebrew? [y/N]",
-                    style(success_count).green()
-                ),
-                PromptDefault::No,
-            )
-            .map_err(ui_error)?
-    {
+    if !confirm(
+        ui,
+        &format!(
+            "Uninstall {} formula(s) from Homebrew?",
+            style(success_count).green()
+        ),
+        PromptDefault::No,
+        policy,
+    )? {
         ui.println("Skipped uninstall from Homebrew.")
             .map_err(ui_error)?;
         return Ok(());
@@ -225,17 +222,14 @@ fn check_install_status(
     let mut successfully_installed = Vec::new();
     let mut failed_installed = Vec::new();
 
-    let installed_kegs =
-        installer
-            .list_installed()
-            .map_err(|e| zb_core::Error::StoreCorruption {
-                message: format!("Failed to verify installation status: {}", e),
-            })?;
+    let installed = installer
+        .installed_set()
+        .map_err(|e| zb_core::Error::StoreCorruption {
+            message: format!("Failed to verify installation status: {}", e),
+        })?;
 
-    let installed_names: std::collections::HashSet<String> =
-        installed_kegs.into_iter().map(|k| k.name).collect();
     for name in formula_names {
-        if !installed_names.contains(name) {
+        if !installed.contains(name) {
             failed_installed.push(name.clone());
         } else {
             successfully_installed.push(name.clone());
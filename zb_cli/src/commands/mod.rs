@@ -1,14 +1,28 @@
+pub mod apply;
 pub mod bundle;
+pub mod cache;
 pub mod completion;
+pub mod deps;
 pub mod doctor;
+pub mod env;
 pub mod gc;
+pub mod history;
 pub mod info;
 pub mod init;
 pub mod install;
+pub mod licenses;
 pub mod list;
 pub mod migrate;
 pub mod outdated;
+pub mod pin;
+pub mod relocate;
 pub mod reset;
+pub mod rollback;
 pub mod run;
+pub mod search;
+pub mod snapshot;
+pub mod tap;
 pub mod uninstall;
 pub mod update;
+pub mod upgrade;
+pub mod why;
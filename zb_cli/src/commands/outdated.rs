@@ -1,61 +1,330 @@
+use std::io::{self, Write};
+
 use console::style;
+use zb_core::{ChangeKind, Lifecycle};
+use zb_io::{OutdatedPackage, UncheckedPackage};
+
+use crate::porcelain::{self, Porcelain};
 
+/// Exit status of `zb outdated` when something is behind upstream, as with
+/// `brew outdated`, so scripts can branch on it.
+pub const OUTDATED_EXIT_CODE: u8 = 1;
+
+/// List installed formulas with a newer version upstream, and those whose
+/// upstream version couldn't be looked up as `unknown`. Rebuild-only
+/// changes (a new revision or bottle of the same release) are left out
+/// unless `include_rebuilds` is set. Returns whether anything listed is
+/// outdated, which zb reports as [`OUTDATED_EXIT_CODE`].
 pub async fn execute(
     installer: &mut zb_io::Installer,
     quiet: bool,
-    verbose: bool,
     json: bool,
-) -> Result<(), zb_core::Error> {
-    let (outdated, warnings) = installer.check_outdated().await?;
+    porcelain: Option<Porcelain>,
+    include_rebuilds: bool,
+) -> Result<bool, zb_core::Error> {
+    let (mut outdated, unchecked) = installer.check_outdated().await?;
+    let before = outdated.len();
+    if !include_rebuilds {
+        outdated.retain(|pkg| pkg.change_kind == ChangeKind::Version);
+    }
+    let hidden = before - outdated.len();
+
+    // What upstream deprecated is only advice, so failing to read it back
+    // leaves the list as it is.
+    let statuses = installer.upstream_statuses().unwrap_or_else(|e| {
+        tracing::warn!(error = %e, "could not read upstream statuses");
+        Vec::new()
+    });
 
     // Warnings always go to stderr (never pollute stdout, especially in --json mode)
-    for warning in &warnings {
+    for warning in unchecked
+        .iter()
+        .map(ToString::to_string)
+        .chain(upstream_summary(&statuses))
+    {
         eprintln!("{} {}", style("Warning:").yellow().bold(), warning);
     }
 
-    if json {
-        let json_output: Vec<serde_json::Value> = outdated
-            .iter()
-            .map(|pkg| {
-                serde_json::json!({
-                    "name": pkg.name,
-                    "installed_versions": [pkg.installed_version],
-                    "current_version": pkg.current_version,
-                })
-            })
-            .collect();
-        println!("{}", serde_json::to_string_pretty(&json_output).unwrap());
-        return Ok(());
+    let out = &mut io::stdout().lock();
+    if let Some(Porcelain::V1) = porcelain {
+        porcelain::write_outdated(out, &outdated, &unchecked).map_err(ui_error)?;
+    } else if json {
+        let json = serde_json::to_string_pretty(&json_entries(&outdated, &unchecked))
+            .map_err(|e| ui_error(e.into()))?;
+        writeln!(out, "{json}").map_err(ui_error)?;
+    } else {
+        write_human(out, &outdated, &unchecked, quiet).map_err(ui_error)?;
+        if hidden > 0 && !quiet {
+            writeln!(
+                out,
+                "{}",
+                style(format!(
+                    "{hidden} rebuild-only {} hidden; pass --include-rebuilds to list {}",
+                    if hidden == 1 { "update" } else { "updates" },
+                    if hidden == 1 { "it" } else { "them" },
+                ))
+                .dim()
+            )
+            .map_err(ui_error)?;
+        }
     }
 
-    if outdated.is_empty() {
-        if !quiet {
-            println!(
-                "{} All packages are up to date.",
-                style("==>").cyan().bold()
-            );
+    Ok(!outdated.is_empty())
+}
+
+/// `name installed -> current` per formula, sorted by name, with `unknown`
+/// for formulas that couldn't be looked up. `quiet` lists only the names of
+/// the outdated ones.
+fn write_human(
+    out: &mut impl Write,
+    outdated: &[OutdatedPackage],
+    unchecked: &[UncheckedPackage],
+    quiet: bool,
+) -> io::Result<()> {
+    if quiet {
+        for pkg in outdated {
+            writeln!(out, "{}", pkg.name)?;
         }
         return Ok(());
     }
+    if outdated.is_empty() && unchecked.is_empty() {
+        return writeln!(
+            out,
+            "{} All packages are up to date.",
+            style("==>").cyan().bold()
+        );
+    }
 
-    for pkg in &outdated {
-        if quiet {
-            println!("{}", pkg.name);
-        } else if verbose {
-            println!(
-                "{} {} {} {}",
+    let mut lines: Vec<(&str, String)> = outdated
+        .iter()
+        .map(|pkg| {
+            let rebuild = match pkg.change_kind {
+                ChangeKind::Rebuild => style(" (rebuild)").dim().to_string(),
+                ChangeKind::Version => String::new(),
+            };
+            let line = format!(
+                "{} {} {} {}{rebuild}",
                 pkg.name,
                 style(&pkg.installed_version).red(),
-                style("→").dim(),
+                style("->").dim(),
                 style(&pkg.current_version).green(),
             );
-        } else {
-            println!(
-                "{} ({}) < {}",
-                pkg.name, pkg.installed_version, pkg.current_version
+            (pkg.name.as_str(), line)
+        })
+        .chain(unchecked.iter().map(|pkg| {
+            let line = format!(
+                "{} {} {} {}",
+                pkg.name,
+                pkg.installed_version,
+                style("->").dim(),
+                style("unknown").yellow(),
             );
+            (pkg.name.as_str(), line)
+        }))
+        .collect();
+    lines.sort_by(|a, b| a.0.cmp(b.0));
+    for (_, line) in lines {
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+/// One object per formula, sorted by name. Formulas that couldn't be looked
+/// up have a `null` current version and `"status": "unknown"`.
+fn json_entries(
+    outdated: &[OutdatedPackage],
+    unchecked: &[UncheckedPackage],
+) -> Vec<serde_json::Value> {
+    let mut entries: Vec<serde_json::Value> = outdated
+        .iter()
+        .map(|pkg| {
+            serde_json::json!({
+                "name": pkg.name,
+                "installed_versions": [pkg.installed_version],
+                "current_version": pkg.current_version,
+                "change_kind": pkg.change_kind,
+                "status": "outdated",
+            })
+        })
+        .chain(unchecked.iter().map(|pkg| {
+            serde_json::json!({
+                "name": pkg.name,
+                "installed_versions": [pkg.installed_version],
+                "current_version": null,
+                "change_kind": null,
+                "status": "unknown",
+            })
+        }))
+        .collect();
+    entries.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    entries
+}
+
+fn ui_error(err: io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
+
+/// One line each for the installed formulas upstream deprecated and those
+/// it disabled, as recorded by the refresh that just ran.
+fn upstream_summary(statuses: &[zb_io::UpstreamStatus]) -> Vec<String> {
+    let mut deprecated = Vec::new();
+    let mut disabled = Vec::new();
+    for status in statuses {
+        let (list, reason) = match &status.lifecycle {
+            Lifecycle::Active => continue,
+            Lifecycle::Deprecated { reason } => (&mut deprecated, reason),
+            Lifecycle::Disabled { reason } => (&mut disabled, reason),
+        };
+        let details: Vec<String> = reason
+            .iter()
+            .map(|reason| reason.replace('_', " "))
+            .chain(status.since.iter().map(|since| format!("since {since}")))
+            .collect();
+        list.push(if details.is_empty() {
+            status.name.clone()
+        } else {
+            format!("{} ({})", status.name, details.join("; "))
+        });
+    }
+
+    let line = |names: Vec<String>, state: &str| {
+        let count = if names.len() == 1 {
+            "1 installed formula is".to_string()
+        } else {
+            format!("{} installed formulas are", names.len())
+        };
+        format!("{count} {state} upstream: {}", names.join(", "))
+    };
+    let mut lines = Vec::new();
+    if !deprecated.is_empty() {
+        lines.push(line(deprecated, "deprecated"));
+    }
+    if !disabled.is_empty() {
+        lines.push(format!(
+            "{}; upgrading them needs --accept-disabled",
+            line(disabled, "disabled")
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outdated(name: &str, from: &str, to: &str) -> OutdatedPackage {
+        OutdatedPackage {
+            name: name.to_string(),
+            installed_version: from.to_string(),
+            current_version: to.to_string(),
+            change_kind: ChangeKind::between(from, to),
+            installed_sha256: String::new(),
+            current_sha256: String::new(),
+            current_url: String::new(),
+            is_source_build: false,
         }
     }
 
-    Ok(())
+    fn unchecked(name: &str, version: &str) -> UncheckedPackage {
+        UncheckedPackage {
+            name: name.to_string(),
+            installed_version: version.to_string(),
+            reason: "network failure".to_string(),
+        }
+    }
+
+    fn render(outdated: &[OutdatedPackage], unchecked: &[UncheckedPackage], quiet: bool) -> String {
+        console::set_colors_enabled(false);
+        let mut out = Vec::new();
+        write_human(&mut out, outdated, unchecked, quiet).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn outdated_and_unknown_formulas_are_listed_by_name() {
+        let stale = [
+            outdated("wget", "1.21.3", "1.21.4"),
+            outdated("jq", "1.7.1", "1.7.1_1"),
+        ];
+        let failed = [unchecked("hashicorp/tap/terraform", "1.9.0")];
+
+        assert_eq!(
+            render(&stale, &failed, false),
+            "\
+hashicorp/tap/terraform 1.9.0 -> unknown
+jq 1.7.1 -> 1.7.1_1 (rebuild)
+wget 1.21.3 -> 1.21.4
+"
+        );
+        assert_eq!(render(&stale, &failed, true), "wget\njq\n");
+        assert_eq!(
+            render(&[], &[], false),
+            "==> All packages are up to date.\n"
+        );
+    }
+
+    #[test]
+    fn json_marks_formulas_that_could_not_be_checked() {
+        let entries = json_entries(
+            &[outdated("wget", "1.21.3", "1.21.4")],
+            &[unchecked("curl", "8.1.0")],
+        );
+
+        assert_eq!(
+            serde_json::Value::Array(entries),
+            serde_json::json!([
+                {
+                    "name": "curl",
+                    "installed_versions": ["8.1.0"],
+                    "current_version": null,
+                    "change_kind": null,
+                    "status": "unknown",
+                },
+                {
+                    "name": "wget",
+                    "installed_versions": ["1.21.3"],
+                    "current_version": "1.21.4",
+                    "change_kind": "version",
+                    "status": "outdated",
+                },
+            ])
+        );
+    }
+
+    fn status(name: &str, lifecycle: Lifecycle, since: Option<&str>) -> zb_io::UpstreamStatus {
+        zb_io::UpstreamStatus {
+            name: name.to_string(),
+            lifecycle,
+            since: since.map(str::to_string),
+            checked_at: 0,
+        }
+    }
+
+    #[test]
+    fn upstream_summary_groups_deprecated_and_disabled_formulas() {
+        let statuses = [
+            status(
+                "terraform",
+                Lifecycle::Deprecated {
+                    reason: Some("repo_archived".to_string()),
+                },
+                Some("2024-04-04"),
+            ),
+            status("vault", Lifecycle::Deprecated { reason: None }, None),
+            status("sunset", Lifecycle::Disabled { reason: None }, None),
+        ];
+
+        assert_eq!(
+            upstream_summary(&statuses),
+            [
+                "2 installed formulas are deprecated upstream: \
+                 terraform (repo archived; since 2024-04-04), vault",
+                "1 installed formula is disabled upstream: sunset; \
+                 upgrading them needs --accept-disabled",
+            ]
+        );
+        assert!(upstream_summary(&[]).is_empty());
+    }
 }
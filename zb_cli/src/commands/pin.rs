@@ -0,0 +1,36 @@
+use console::style;
+
+use crate::ui::StdUi;
+
+/// Pin or unpin each of `formulas`, stopping at the first that isn't
+/// installed.
+pub fn execute(
+    installer: &zb_io::Installer,
+    formulas: &[String],
+    pinned: bool,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    for name in formulas {
+        let keg = installer
+            .get_installed(name)?
+            .ok_or_else(|| zb_core::Error::NotInstalled { name: name.clone() })?;
+        installer.set_pinned(name, pinned)?;
+        let message = if pinned {
+            format!(
+                "Pinned {} at {}; upgrades will skip it",
+                style(name).bold(),
+                keg.version
+            )
+        } else {
+            format!("Unpinned {}", style(name).bold())
+        };
+        ui.info(message).map_err(ui_error)?;
+    }
+    Ok(())
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use console::style;
+
+use crate::prompt::{PromptPolicy, confirm};
+use crate::ui::{PromptDefault, StdUi};
+
+pub fn execute(
+    installer: &mut zb_io::Installer,
+    new_prefix: &Path,
+    policy: PromptPolicy,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let plan = installer.plan_relocation(new_prefix)?;
+
+    if plan.resuming {
+        ui.note(format!(
+            "Resuming the relocation from {} to {}",
+            plan.from.display(),
+            plan.to.display()
+        ))
+        .map_err(ui_error)?;
+    } else {
+        ui.println(format!(
+            "{} installed kegs will move from {} to {}",
+            style(plan.pending.len()).green(),
+            plan.from.display(),
+            plan.to.display()
+        ))
+        .map_err(ui_error)?;
+        if !confirm(ui, "Continue?", PromptDefault::No, policy)? {
+            ui.info("Aborted.").map_err(ui_error)?;
+            return Ok(());
+        }
+    }
+
+    ui.heading("Relocating kegs...").map_err(ui_error)?;
+    installer.relocate(&plan, |name, version| {
+        let _ = ui.bullet(format!("{name} {}", style(version).dim()));
+    })?;
+
+    ui.heading(format!("Relocated to {}", plan.to.display()))
+        .map_err(ui_error)?;
+    ui.note(format!(
+        "Run 'zb --prefix {} init' to point your shell at the new prefix.",
+        plan.to.display()
+    ))
+    .map_err(ui_error)?;
+    ui.note(format!(
+        "Anything else left in {} is yours to remove.",
+        plan.from.display()
+    ))
+    .map_err(ui_error)?;
+
+    Ok(())
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
@@ -1,42 +1,77 @@
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 
 use zb_io::validate_privileged_path;
 
-use crate::init::{InitError, run_init};
+use crate::init::{InitError, PREFIX_MARKER, PrefixOwner, prefix_owner, run_init};
+use crate::prompt::{PromptPolicy, confirm};
 use crate::ui::{PromptDefault, StdUi};
 
 pub fn execute(
     root: &Path,
     prefix: &Path,
-    yes: bool,
+    cache: &Path,
+    force_unowned: bool,
+    policy: PromptPolicy,
     ui: &mut StdUi,
 ) -> Result<(), zb_core::Error> {
     validate_privileged_path(root)?;
     validate_privileged_path(prefix)?;
+    validate_privileged_path(cache)?;
 
-    if !root.exists() && !prefix.exists() {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let dirs = deletion_set(root, prefix, cache, home.as_deref())?;
+
+    if dirs.iter().all(|dir| !dir.exists()) {
         ui.info("Nothing to reset - directories do not exist.")
             .map_err(ui_error)?;
         return Ok(());
     }
 
-    if !yes {
+    // A prefix set by habit (ZEROBREW_PREFIX=/usr/local) must not be
+    // emptied; only one init marked for this root, or an empty one, is.
+    let is_empty = |dir: &Path| std::fs::read_dir(dir).is_ok_and(|mut e| e.next().is_none());
+    let unowned = match prefix_owner(prefix, root) {
+        PrefixOwner::Root => None,
+        _ if !prefix.exists() || is_empty(prefix) => None,
+        PrefixOwner::Unmarked => Some(format!(
+            "{} has no {PREFIX_MARKER} marker, so it may not be a zerobrew prefix",
+            prefix.display()
+        )),
+        PrefixOwner::OtherRoot(other) => Some(format!(
+            "{} belongs to the zerobrew root {}, not {}",
+            prefix.display(),
+            other.display(),
+            root.display()
+        )),
+    };
+    if let Some(reason) = unowned {
+        if !force_unowned {
+            return Err(zb_core::Error::InvalidArgument {
+                message: format!(
+                    "refusing to reset: {reason}; check ZEROBREW_PREFIX, run 'zb init' to claim it, or pass \
+                     --force-unowned to delete everything in it anyway"
+                ),
+            });
+        }
+        ui.warn(format!("{reason}; clearing it anyway (--force-unowned)"))
+            .map_err(ui_error)?;
+    }
+
+    if policy != PromptPolicy::AssumeYes {
         ui.note("This will delete all zerobrew data at:")
             .map_err(ui_error)?;
-        ui.bullet(root.display()).map_err(ui_error)?;
-        ui.bullet(prefix.display()).map_err(ui_error)?;
+        for dir in &dirs {
+            ui.bullet(dir.display()).map_err(ui_error)?;
+        }
 
-        if !ui
-            .prompt_yes_no("Continue? [y/N]", PromptDefault::No)
-            .map_err(ui_error)?
-        {
+        if !confirm(ui, "Continue?", PromptDefault::No, policy)? {
             ui.info("Aborted.").map_err(ui_error)?;
             return Ok(());
         }
     }
 
-    for dir in [root, prefix] {
+    for dir in &dirs {
         if !dir.exists() {
             continue;
         }
@@ -87,7 +122,7 @@ pub fn execute(
     }
 
     // Pass false for no_modify_shell since this is a re-initialization
-    run_init(root, prefix, false, ui).map_err(|e| match e {
+    run_init(root, prefix, false, false, ui).map_err(|e| match e {
         InitError::Message(msg) => zb_core::Error::StoreCorruption { message: msg },
     })?;
 
@@ -97,8 +132,188 @@ pub fn execute(
     Ok(())
 }
 
+/// The directories whose contents reset deletes: the root, prefix and
+/// cache, minus any nested in another, which go with it. Refuses a
+/// filesystem root or the home directory, and a prefix or cache above
+/// the root's directory, which would take unrelated data with it.
+fn deletion_set(
+    root: &Path,
+    prefix: &Path,
+    cache: &Path,
+    home: Option<&Path>,
+) -> Result<Vec<PathBuf>, zb_core::Error> {
+    let refuse = |message: String| zb_core::Error::InvalidArgument {
+        message: format!("refusing to reset: {message}"),
+    };
+    let root = normalize(root);
+    let home = home.map(normalize);
+    let named = [
+        ("root", root.clone()),
+        ("prefix", normalize(prefix)),
+        ("cache", normalize(cache)),
+    ];
+
+    for (name, dir) in &named {
+        if dir.parent().is_none() {
+            return Err(refuse(format!(
+                "the {name} is a filesystem root ({})",
+                dir.display()
+            )));
+        }
+        if home.as_ref() == Some(dir) {
+            return Err(refuse(format!(
+                "the {name} is the home directory ({})",
+                dir.display()
+            )));
+        }
+    }
+    if let Some(parent) = root.parent() {
+        for (name, dir) in &named[1..] {
+            if parent.starts_with(dir) {
+                return Err(refuse(format!(
+                    "the {name} {} contains the directory the root {} is in",
+                    dir.display(),
+                    root.display()
+                )));
+            }
+        }
+    }
+
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for (_, dir) in named {
+        if dirs.iter().any(|kept| dir.starts_with(kept)) {
+            continue;
+        }
+        dirs.retain(|kept| !kept.starts_with(&dir));
+        dirs.push(dir);
+    }
+    Ok(dirs)
+}
+
+/// Drop `.` components and trailing separators, so nesting is judged on
+/// the paths as written; `..` is already rejected.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
 fn ui_error(err: std::io::Error) -> zb_core::Error {
     zb_core::Error::StoreCorruption {
         message: format!("failed to write CLI output: {err}"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME: &str = "/home/me";
+
+    fn set(root: &str, prefix: &str, cache: &str) -> Result<Vec<PathBuf>, String> {
+        deletion_set(
+            Path::new(root),
+            Path::new(prefix),
+            Path::new(cache),
+            Some(Path::new(HOME)),
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    fn paths(dirs: &[&str]) -> Result<Vec<PathBuf>, String> {
+        Ok(dirs.iter().map(PathBuf::from).collect())
+    }
+
+    #[test]
+    fn separate_directories_are_all_cleared() {
+        assert_eq!(
+            set("/opt/zerobrew", "/opt/homebrew", "/var/cache/zb"),
+            paths(&["/opt/zerobrew", "/opt/homebrew", "/var/cache/zb"])
+        );
+    }
+
+    #[test]
+    fn nested_directories_are_cleared_once() {
+        // The default layout: prefix and cache inside the root.
+        assert_eq!(
+            set(
+                "/opt/zerobrew",
+                "/opt/zerobrew/prefix",
+                "/opt/zerobrew/cache"
+            ),
+            paths(&["/opt/zerobrew"])
+        );
+        assert_eq!(
+            set(
+                "/opt/zerobrew",
+                "/opt/zerobrew/./prefix/",
+                "/opt/zerobrew/cache"
+            ),
+            paths(&["/opt/zerobrew"])
+        );
+        assert_eq!(
+            set("/opt/zerobrew", "/opt/zerobrew", "/var/cache/zb"),
+            paths(&["/opt/zerobrew", "/var/cache/zb"])
+        );
+        // A cache holding the prefix takes its place.
+        assert_eq!(
+            set("/opt/zerobrew", "/srv/zb/prefix", "/srv/zb"),
+            paths(&["/opt/zerobrew", "/srv/zb"])
+        );
+        assert_eq!(
+            set("/opt/zerobrew", "/srv/zb", "/srv/zb/cache"),
+            paths(&["/opt/zerobrew", "/srv/zb"])
+        );
+    }
+
+    #[test]
+    fn a_prefix_or_cache_above_the_root_is_refused() {
+        for (root, prefix, cache) in [
+            (
+                "/opt/zerobrew/root",
+                "/opt/zerobrew",
+                "/opt/zerobrew/root/cache",
+            ),
+            ("/opt/zerobrew/root", "/opt", "/opt/zerobrew/root/cache"),
+            ("/opt/zerobrew/data/root", "/opt/zerobrew", "/var/cache/zb"),
+            ("/opt/zerobrew", "/opt/homebrew", "/opt"),
+        ] {
+            let err = set(root, prefix, cache).unwrap_err();
+            assert!(err.contains("contains the directory the root"), "{err}");
+        }
+    }
+
+    #[test]
+    fn filesystem_roots_and_home_are_refused() {
+        for (root, prefix, cache, name) in [
+            ("/", "/opt/homebrew", "/var/cache/zb", "root"),
+            ("/opt/zerobrew", "/", "/var/cache/zb", "prefix"),
+            ("/opt/zerobrew", "/opt/homebrew", "/.", "cache"),
+        ] {
+            let err = set(root, prefix, cache).unwrap_err();
+            assert!(
+                err.contains(&format!("the {name} is a filesystem root")),
+                "{err}"
+            );
+        }
+        for (root, prefix, name) in [
+            (HOME, "/opt/homebrew", "root"),
+            ("/opt/zerobrew", "/home/me/", "prefix"),
+        ] {
+            let err = set(root, prefix, "/var/cache/zb").unwrap_err();
+            assert!(
+                err.contains(&format!("the {name} is the home directory")),
+                "{err}"
+            );
+        }
+        // Directories inside the home directory are fine.
+        assert_eq!(
+            set(
+                "/home/me/.zerobrew",
+                "/home/me/.zerobrew/prefix",
+                "/home/me/.cache/zb"
+            ),
+            paths(&["/home/me/.zerobrew", "/home/me/.cache/zb"])
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use console::style;
+
+use crate::ui::StdUi;
+use crate::utils::normalize_formula_name;
+
+pub fn execute(
+    installer: &mut zb_io::Installer,
+    formula: String,
+    version: Option<String>,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let name = normalize_formula_name(&formula)?;
+    let rollback = installer.rollback(&name, version.as_deref())?;
+
+    ui.heading(format!(
+        "Rolled back {} from {} to {}",
+        style(&rollback.name).bold(),
+        style(&rollback.from).red(),
+        style(&rollback.to).green()
+    ))
+    .map_err(ui_error)?;
+    ui.println(format!(
+        "{} {} is kept; run `zb rollback {} {}` to return to it.",
+        rollback.name, rollback.from, rollback.name, rollback.from
+    ))
+    .map_err(ui_error)?;
+
+    Ok(())
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
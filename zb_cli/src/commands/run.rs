@@ -5,38 +5,57 @@ use std::process::Command;
 use zb_core::formula_token;
 use zb_io::Installer;
 
+use crate::commands::env::BuildEnv;
 use crate::utils::{normalize_formula_name, suggest_missing_formula_matches};
 
-/// Prepare a package for execution by ensuring it's installed
-/// Returns the path to the executable
-pub async fn prepare_execution(
+/// Make sure every formula has a keg, installing the missing ones without
+/// linking them, and return the keg paths in the order given. Kegs installed
+/// this way stay in the cellar like any other unlinked install.
+async fn ensure_kegs(
     installer: &mut Installer,
-    formula: &str,
-) -> Result<PathBuf, zb_core::Error> {
-    let normalized = normalize_formula_name(formula)?;
-
-    let was_installed = installer.is_installed(&normalized);
-
-    if !was_installed {
+    names: &[String],
+) -> Result<Vec<PathBuf>, zb_core::Error> {
+    let missing: Vec<String> = names
+        .iter()
+        .filter(|name| !installer.is_installed(name))
+        .cloned()
+        .collect();
+
+    if !missing.is_empty() {
         println!(
             "{} Installing {} temporarily...",
             style("==>").cyan().bold(),
-            style(&normalized).green()
+            style(missing.join(", ")).green()
         );
 
-        let plan = installer.plan(std::slice::from_ref(&normalized)).await?;
+        let plan = installer.plan(&missing).await?;
         installer.execute(plan, false).await?;
     }
 
-    let installed =
-        installer
-            .get_installed(&normalized)
-            .ok_or_else(|| zb_core::Error::NotInstalled {
-                name: normalized.clone(),
-            })?;
+    names
+        .iter()
+        .map(|name| {
+            let installed = installer
+                .get_installed(name)?
+                .ok_or_else(|| zb_core::Error::NotInstalled { name: name.clone() })?;
+            Ok(installer.keg_path(formula_token(&installed.name), &installed.version))
+        })
+        .collect()
+}
+
+/// Prepare a package for execution by ensuring it's installed
+/// Returns the path to the executable
+pub async fn prepare_execution(
+    installer: &mut Installer,
+    formula: &str,
+) -> Result<PathBuf, zb_core::Error> {
+    let normalized = normalize_formula_name(formula)?;
 
-    let executable_name = formula_token(&installed.name);
-    let keg_path = installer.keg_path(executable_name, &installed.version);
+    let keg_path = ensure_kegs(installer, std::slice::from_ref(&normalized))
+        .await?
+        .remove(0);
+
+    let executable_name = formula_token(&normalized);
     let bin_path = keg_path.join("bin").join(executable_name);
 
     if !bin_path.exists() {
@@ -80,33 +99,118 @@ pub async fn execute(
     cmd.args(&args);
 
     if let Some(prefix_path) = detect_runtime_prefix(&bin_path) {
-        if let Some(ca_bundle) = zb_io::find_ca_bundle_from_prefix(&prefix_path) {
-            cmd.env("CURL_CA_BUNDLE", &ca_bundle);
-            cmd.env("SSL_CERT_FILE", &ca_bundle);
-        }
+        set_prefix_env(&mut cmd, &prefix_path);
+    }
+
+    let err = cmd.exec();
 
-        if let Some(ca_dir) = zb_io::find_ca_dir(&prefix_path) {
-            cmd.env("SSL_CERT_DIR", &ca_dir);
+    Err(zb_core::Error::ExecutionError {
+        message: format!("failed to execute '{}': {}", formula, err),
+    })
+}
+
+/// Run `command` from PATH with the `with` formulas available: their bin
+/// directories come ahead of the prefix on PATH, and their headers,
+/// libraries and pkg-config files are exported the way `zb env` prints them.
+/// Nothing is linked into the prefix.
+pub async fn execute_with(
+    installer: &mut Installer,
+    prefix: &Path,
+    with: Vec<String>,
+    command: String,
+    args: Vec<String>,
+) -> Result<(), zb_core::Error> {
+    let mut names = Vec::with_capacity(with.len());
+    for formula in &with {
+        let name = normalize_formula_name(formula)?;
+        if !names.contains(&name) {
+            names.push(name);
         }
+    }
 
-        let lib_path = prefix_path.join("lib");
-        if let Ok(existing_ld_path) = std::env::var("LD_LIBRARY_PATH") {
-            cmd.env(
-                "LD_LIBRARY_PATH",
-                format!("{}:{}", lib_path.display(), existing_ld_path),
-            );
-        } else {
-            cmd.env("LD_LIBRARY_PATH", lib_path);
+    println!(
+        "{} Running {} with {}...",
+        style("==>").cyan().bold(),
+        style(&command).bold(),
+        style(names.join(", ")).green()
+    );
+
+    let kegs = match ensure_kegs(installer, &names).await {
+        Ok(kegs) => kegs,
+        Err(e) => {
+            let _ = suggest_missing_formula_matches(installer, &e).await;
+            return Err(e);
         }
+    };
+
+    let mut cmd = Command::new(&command);
+    cmd.args(&args);
+    set_prefix_env(&mut cmd, prefix);
+    for (key, value) in toolchain_env(&kegs, prefix, |key| std::env::var(key).ok()) {
+        cmd.env(key, value);
     }
 
     let err = cmd.exec();
 
     Err(zb_core::Error::ExecutionError {
-        message: format!("failed to execute '{}': {}", formula, err),
+        message: format!("failed to execute '{}': {}", command, err),
     })
 }
 
+/// PATH and build flags for a run with `kegs` available, each prepended to
+/// the value `current` reports so the caller's own settings still apply.
+fn toolchain_env(
+    kegs: &[PathBuf],
+    prefix: &Path,
+    current: impl Fn(&str) -> Option<String>,
+) -> Vec<(&'static str, String)> {
+    let mut path: Vec<String> = kegs
+        .iter()
+        .map(|keg| keg.join("bin"))
+        .filter(|bin| bin.is_dir())
+        .chain([prefix.join("bin")])
+        .map(|dir| dir.display().to_string())
+        .collect();
+    path.extend(current("PATH").filter(|value| !value.is_empty()));
+
+    let mut build = BuildEnv::default();
+    for keg in kegs {
+        build.add_keg(keg);
+    }
+
+    let mut vars = vec![("PATH", path.join(":"))];
+    for (key, value) in build.vars() {
+        let separator = if key == "PKG_CONFIG_PATH" { ":" } else { " " };
+        let value = match current(key).filter(|existing| !existing.is_empty()) {
+            Some(existing) => format!("{value}{separator}{existing}"),
+            None => value,
+        };
+        vars.push((key, value));
+    }
+    vars
+}
+
+fn set_prefix_env(cmd: &mut Command, prefix_path: &Path) {
+    if let Some(ca_bundle) = zb_io::find_ca_bundle_from_prefix(prefix_path) {
+        cmd.env("CURL_CA_BUNDLE", &ca_bundle);
+        cmd.env("SSL_CERT_FILE", &ca_bundle);
+    }
+
+    if let Some(ca_dir) = zb_io::find_ca_dir(prefix_path) {
+        cmd.env("SSL_CERT_DIR", &ca_dir);
+    }
+
+    let lib_path = prefix_path.join("lib");
+    if let Ok(existing_ld_path) = std::env::var("LD_LIBRARY_PATH") {
+        cmd.env(
+            "LD_LIBRARY_PATH",
+            format!("{}:{}", lib_path.display(), existing_ld_path),
+        );
+    } else {
+        cmd.env("LD_LIBRARY_PATH", lib_path);
+    }
+}
+
 fn detect_runtime_prefix(bin_path: &Path) -> Option<PathBuf> {
     let env_prefix = std::env::var("ZEROBREW_PREFIX").ok();
     detect_runtime_prefix_with_env(bin_path, env_prefix.as_deref())
@@ -244,7 +348,7 @@ mod tests {
             linker,
             db,
             prefix.clone(),
-            root.join("locks"),
+            root.to_path_buf(),
         );
 
         assert!(!installer.is_installed("testrun"));
@@ -323,7 +427,7 @@ mod tests {
             linker,
             db,
             prefix.clone(),
-            root.join("locks"),
+            root.to_path_buf(),
         );
 
         installer
@@ -377,13 +481,55 @@ mod tests {
             linker,
             db,
             prefix.clone(),
-            root.join("locks"),
+            root.to_path_buf(),
         );
 
         let result = prepare_execution(&mut installer, "nonexistent").await;
         assert!(result.is_err());
     }
 
+    #[test]
+    fn toolchain_env_puts_kegs_ahead_of_prefix_and_keeps_caller_values() {
+        let tmp = TempDir::new().unwrap();
+        let cmake = tmp.path().join("cellar/cmake/3.30.0");
+        let pkgconf = tmp.path().join("cellar/pkgconf/2.3.0");
+        fs::create_dir_all(cmake.join("bin")).unwrap();
+        fs::create_dir_all(pkgconf.join("bin")).unwrap();
+        fs::create_dir_all(pkgconf.join("include")).unwrap();
+        fs::create_dir_all(pkgconf.join("lib/pkgconfig")).unwrap();
+        let prefix = tmp.path().join("prefix");
+
+        let vars: std::collections::HashMap<_, _> = toolchain_env(
+            &[cmake.clone(), pkgconf.clone()],
+            &prefix,
+            |key| match key {
+                "PATH" => Some("/usr/bin:/bin".to_string()),
+                "CPPFLAGS" => Some("-DNDEBUG".to_string()),
+                _ => None,
+            },
+        )
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            vars["PATH"],
+            format!(
+                "{}:{}:{}:/usr/bin:/bin",
+                cmake.join("bin").display(),
+                pkgconf.join("bin").display(),
+                prefix.join("bin").display()
+            )
+        );
+        assert_eq!(
+            vars["CPPFLAGS"],
+            format!("-I{} -DNDEBUG", pkgconf.join("include").display())
+        );
+        assert_eq!(
+            vars["PKG_CONFIG_PATH"],
+            pkgconf.join("lib/pkgconfig").display().to_string()
+        );
+    }
+
     #[test]
     fn ssl_cert_paths_use_prefix() {
         let prefix = "/opt/test/prefix";
@@ -0,0 +1,88 @@
+use std::io::{self, Write};
+
+use console::style;
+use zb_io::{MatchKind, SearchResult};
+
+/// List formulas matching `query`, marking the installed ones.
+pub async fn execute(installer: &zb_io::Installer, query: &str) -> Result<(), zb_core::Error> {
+    let results = installer.search(query).await?;
+    write_results(&mut io::stdout().lock(), query, &results).map_err(ui_error)
+}
+
+/// One line per formula, `name (installed)  description`. Near misses are
+/// introduced as such, since none of them contain the query.
+fn write_results(out: &mut impl Write, query: &str, results: &[SearchResult]) -> io::Result<()> {
+    if results.is_empty() {
+        return writeln!(out, "No formulas match {query}.");
+    }
+    if results.iter().all(|r| r.matched == MatchKind::Fuzzy) {
+        writeln!(out, "No formulas match {query}; the closest names are:")?;
+    }
+    for result in results {
+        let mut line = style(&result.name).bold().to_string();
+        if result.installed {
+            line.push_str(&format!(" {}", style("(installed)").green()));
+        }
+        if let Some(desc) = &result.desc {
+            line.push_str(&format!("  {}", style(desc).dim()));
+        }
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+fn ui_error(err: io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(name: &str, desc: Option<&str>, matched: MatchKind, installed: bool) -> SearchResult {
+        SearchResult {
+            name: name.to_string(),
+            desc: desc.map(str::to_string),
+            matched,
+            installed,
+        }
+    }
+
+    fn render(query: &str, results: &[SearchResult]) -> String {
+        console::set_colors_enabled(false);
+        let mut out = Vec::new();
+        write_results(&mut out, query, results).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn matches_are_listed_with_installed_ones_marked() {
+        let results = [
+            result(
+                "openssl@3",
+                Some("Cryptography and SSL/TLS Toolkit"),
+                MatchKind::Exact,
+                true,
+            ),
+            result("openssl@1.1", None, MatchKind::Prefix, false),
+        ];
+        assert_eq!(
+            render("openssl", &results),
+            "\
+openssl@3 (installed)  Cryptography and SSL/TLS Toolkit
+openssl@1.1
+"
+        );
+    }
+
+    #[test]
+    fn near_misses_and_no_matches_say_so() {
+        assert_eq!(
+            render("wgte", &[result("wget", None, MatchKind::Fuzzy, false)]),
+            "No formulas match wgte; the closest names are:\nwget\n"
+        );
+        assert_eq!(render("zzz", &[]), "No formulas match zzz.\n");
+    }
+}
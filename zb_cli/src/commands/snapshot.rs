@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use chrono::{DateTime, Local};
+use console::style;
+use zb_io::FormulaSnapshot;
+
+use crate::utils::normalize_formula_name;
+
+pub async fn create(
+    installer: &mut zb_io::Installer,
+    file: &Path,
+    formulas: Vec<String>,
+) -> Result<(), zb_core::Error> {
+    let names = formulas
+        .iter()
+        .map(|f| normalize_formula_name(f))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!(
+        "{} Snapshotting formula metadata...",
+        style("==>").cyan().bold()
+    );
+    let snapshot = installer.create_snapshot(&names).await?;
+    snapshot.write(file)?;
+
+    println!(
+        "{} Wrote {} formulas to {}",
+        style("==>").cyan().bold(),
+        style(snapshot.formula_count()).green().bold(),
+        file.display()
+    );
+    println!(
+        "    Use it with {}",
+        style(format!("ZEROBREW_SNAPSHOT={}", file.display())).bold()
+    );
+
+    Ok(())
+}
+
+pub fn info(file: &Path) -> Result<(), zb_core::Error> {
+    let snapshot = FormulaSnapshot::read(file)?;
+
+    let created = DateTime::from_timestamp(snapshot.created_at as i64, 0)
+        .map(|dt| {
+            dt.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S %Z")
+                .to_string()
+        })
+        .unwrap_or_else(|| snapshot.created_at.to_string());
+
+    println!("{:<10}  {}", style("Created:").dim(), created);
+    println!(
+        "{:<10}  {}",
+        style("Formulas:").dim(),
+        snapshot.formula_count()
+    );
+
+    Ok(())
+}
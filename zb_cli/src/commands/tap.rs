@@ -0,0 +1,58 @@
+use console::style;
+
+use crate::cli::TapCommands;
+use crate::ui::StdUi;
+
+pub fn execute(
+    installer: &mut zb_io::Installer,
+    command: Option<TapCommands>,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    match command.unwrap_or(TapCommands::List) {
+        TapCommands::List => list(&installer.taps()?, ui),
+        TapCommands::Add { tap, first } => {
+            match installer.add_tap(&tap, first)? {
+                Some(tap) => ui.info(format!("Added {}", style(tap).green())),
+                None => ui.info(format!("{tap} is already configured")),
+            }
+            .map_err(ui_error)?;
+            list(&installer.taps()?, ui)
+        }
+        TapCommands::Remove { tap } => {
+            let tap = installer.remove_tap(&tap)?;
+            ui.info(format!("Removed {}", style(tap).green()))
+                .map_err(ui_error)?;
+            list(&installer.taps()?, ui)
+        }
+        TapCommands::Priority { taps } => {
+            let order = installer.prioritize_taps(&taps)?;
+            list(&order, ui)
+        }
+    }
+}
+
+/// The resolution order, core first, as it is searched.
+fn list(taps: &[String], ui: &mut StdUi) -> Result<(), zb_core::Error> {
+    if taps.is_empty() {
+        ui.info(format!(
+            "No taps configured; names resolve to {} only.",
+            zb_io::CORE_TAP
+        ))
+        .map_err(ui_error)?;
+        return Ok(());
+    }
+    ui.heading("Resolution order:").map_err(ui_error)?;
+    ui.println(format!("  1. {} (always first)", zb_io::CORE_TAP))
+        .map_err(ui_error)?;
+    for (position, tap) in taps.iter().enumerate() {
+        ui.println(format!("  {}. {tap}", position + 2))
+            .map_err(ui_error)?;
+    }
+    Ok(())
+}
+
+fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
@@ -1,11 +1,21 @@
-use crate::ui::StdUi;
+use crate::format::human_bytes;
+use crate::prompt::{PromptPolicy, confirm};
+use crate::ui::{PromptDefault, StdUi};
 use crate::utils::normalize_formula_name;
 use console::style;
+use zb_io::{KegFileKind, KegRemoval, UninstallPlan};
 
+#[allow(clippy::too_many_arguments)]
 pub fn execute(
     installer: &mut zb_io::Installer,
     formulas: Vec<String>,
     all: bool,
+    purge_config: bool,
+    dry_run: bool,
+    ignore_dependencies: bool,
+    version: Option<String>,
+    force: bool,
+    policy: PromptPolicy,
     ui: &mut StdUi,
 ) -> Result<(), zb_core::Error> {
     let formulas = if all {
@@ -23,6 +33,93 @@ pub fn execute(
         normalized
     };
 
+    if let Some(version) = &version
+        && formulas.len() != 1
+    {
+        return Err(zb_core::Error::InvalidArgument {
+            message: format!("--version {version} needs exactly one formula"),
+        });
+    }
+
+    let plan = installer.plan_uninstall(&formulas, purge_config)?;
+    if let Some(version) = version {
+        // With other versions left, only the one keg goes; removing the
+        // last version is an ordinary uninstall.
+        if let Some(Ok(removal)) = plan.formulas.first()
+            && removal.kegs.len() > 1
+        {
+            return uninstall_version(installer, removal, &version, dry_run, force, ui);
+        }
+        if let Some(Ok(removal)) = plan.formulas.first()
+            && removal.kegs[0].version != version
+        {
+            return Err(zb_core::Error::InvalidArgument {
+                message: format!(
+                    "{} {version} is not installed; installed version: {}",
+                    removal.name, removal.kegs[0].version
+                ),
+            });
+        }
+    }
+
+    let required: Vec<_> = plan
+        .formulas
+        .iter()
+        .flatten()
+        .filter(|removal| !removal.dependents.is_empty())
+        .collect();
+    if !required.is_empty() && !ignore_dependencies {
+        for removal in &required {
+            ui.error(format!(
+                "{} is required by {}",
+                style(&removal.name).bold(),
+                removal.dependents.join(", ")
+            ))
+            .map_err(ui_error)?;
+        }
+        return Err(zb_core::Error::InvalidArgument {
+            message: "refusing to uninstall formulas other installed formulas depend on; \
+                      pass --ignore-dependencies to uninstall them anyway"
+                .to_string(),
+        });
+    }
+
+    if dry_run {
+        return print_plan(plan, ui);
+    }
+
+    let several: Vec<&KegRemoval> = plan
+        .formulas
+        .iter()
+        .flatten()
+        .filter(|removal| removal.kegs.len() > 1)
+        .collect();
+    if !several.is_empty() {
+        for removal in &several {
+            ui.info(format!(
+                "{} has {} versions installed:",
+                style(&removal.name).bold(),
+                removal.kegs.len()
+            ))
+            .map_err(ui_error)?;
+            for (index, keg) in removal.kegs.iter().enumerate() {
+                let active = if index == 0 { " (active)" } else { "" };
+                ui.bullet(format!("{}{active}", keg.version))
+                    .map_err(ui_error)?;
+            }
+        }
+        if !confirm(
+            ui,
+            "Uninstall all of these versions?",
+            PromptDefault::Yes,
+            policy,
+        )? {
+            ui.info("Aborted; pass --version to remove a single version.")
+                .map_err(ui_error)?;
+            return Ok(());
+        }
+    }
+
     ui.heading(format!(
         "Uninstalling {}...",
         style(formulas.join(", ")).bold()
@@ -30,20 +127,40 @@ pub fn execute(
     .map_err(ui_error)?;
 
     let mut errors: Vec<(String, zb_core::Error)> = Vec::new();
+    let mut kept_configs = Vec::new();
+    let batch = formulas.len() > 1;
 
-    if formulas.len() > 1 {
-        for name in &formulas {
-            ui.step_start(name).map_err(ui_error)?;
-            match installer.uninstall(name) {
-                Ok(()) => ui.step_ok().map_err(ui_error)?,
-                Err(e) => {
+    for outcome in installer.execute_uninstall(plan)? {
+        kept_configs.extend(outcome.kept_configs);
+        if batch {
+            ui.step_start(&outcome.name).map_err(ui_error)?;
+        }
+        match outcome.result {
+            Ok(()) if batch => ui.step_ok().map_err(ui_error)?,
+            Ok(()) => {}
+            Err(e) => {
+                if batch {
                     ui.step_fail().map_err(ui_error)?;
-                    errors.push((name.clone(), e));
                 }
+                errors.push((outcome.name, e));
             }
         }
-    } else if let Err(e) = installer.uninstall(&formulas[0]) {
-        errors.push((formulas[0].clone(), e));
+    }
+
+    if !kept_configs.is_empty() {
+        ui.note(format!(
+            "Kept {} config {}; pass --purge-config to remove:",
+            kept_configs.len(),
+            if kept_configs.len() == 1 {
+                "file"
+            } else {
+                "files"
+            }
+        ))
+        .map_err(ui_error)?;
+        for config in &kept_configs {
+            ui.bullet(config.display().to_string()).map_err(ui_error)?;
+        }
     }
 
     if errors.is_empty() {
@@ -62,6 +179,126 @@ pub fn execute(
     }
 }
 
+/// Remove the one version of `removal` asked for, leaving the others.
+fn uninstall_version(
+    installer: &mut zb_io::Installer,
+    removal: &KegRemoval,
+    version: &str,
+    dry_run: bool,
+    force: bool,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let name = &removal.name;
+    if dry_run {
+        let Some(index) = removal.kegs.iter().position(|keg| keg.version == version) else {
+            // Let the real removal report the versions that are installed.
+            return installer
+                .uninstall_version(name, version, false)
+                .map(|_| ());
+        };
+        let keg = &removal.kegs[index];
+        ui.heading(format!("Would uninstall {} {version}", style(name).bold()))
+            .map_err(ui_error)?;
+        ui.bullet(format!(
+            "keg {} ({})",
+            keg.path.display(),
+            human_bytes(keg.bytes)
+        ))
+        .map_err(ui_error)?;
+        if index == 0 {
+            let next = &removal.kegs[1].version;
+            if force {
+                ui.bullet(format!("switch to {next}")).map_err(ui_error)?;
+            } else {
+                ui.bullet(format!(
+                    "{} it is the active version; pass --force to switch to {next}",
+                    style("refused:").yellow()
+                ))
+                .map_err(ui_error)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let removed = installer.uninstall_version(name, version, force)?;
+    ui.heading(format!("Uninstalled {} {version}", style(name).bold()))
+        .map_err(ui_error)?;
+    if let Some(activated) = removed.activated {
+        ui.info(format!("{name} now uses {activated}"))
+            .map_err(ui_error)?;
+    }
+    Ok(())
+}
+
+/// List what the plan removes, one heading per formula. Uninstalling
+/// changes nothing else, so this is the whole effect of the real run.
+fn print_plan(plan: UninstallPlan, ui: &mut StdUi) -> Result<(), zb_core::Error> {
+    let mut errors = Vec::new();
+    for formula in plan.formulas {
+        let removal = match formula {
+            Ok(removal) => removal,
+            Err(e) => {
+                ui.error(e.to_string()).map_err(ui_error)?;
+                errors.push(e);
+                continue;
+            }
+        };
+
+        ui.heading(format!("Would uninstall {}", style(&removal.name).bold()))
+            .map_err(ui_error)?;
+        for (index, keg) in removal.kegs.iter().enumerate() {
+            let retained = if index > 0 { ", kept for rollback" } else { "" };
+            ui.bullet(format!(
+                "keg {} ({}{retained})",
+                keg.path.display(),
+                human_bytes(keg.bytes)
+            ))
+            .map_err(ui_error)?;
+        }
+        if let Some(opt_link) = &removal.opt_link {
+            ui.bullet(format!("link {}", opt_link.display()))
+                .map_err(ui_error)?;
+        }
+        for file in &removal.linked_files {
+            let line = match file.kind {
+                KegFileKind::Symlink => format!("link {}", file.linked_path),
+                KegFileKind::Wrapper => format!("wrapper {}", file.linked_path),
+                KegFileKind::Config if plan.purge_config => {
+                    format!("config {}", file.linked_path)
+                }
+                KegFileKind::Config => {
+                    format!(
+                        "config {} (kept; pass --purge-config to remove)",
+                        file.linked_path
+                    )
+                }
+                KegFileKind::Dir => format!("directory {} (if empty)", file.linked_path),
+            };
+            ui.bullet(line).map_err(ui_error)?;
+        }
+        for key in &removal.unreferenced_store_keys {
+            ui.bullet(format!(
+                "store entry {} becomes unreferenced; zb gc removes it",
+                &key[..key.len().min(12)]
+            ))
+            .map_err(ui_error)?;
+        }
+        if !removal.dependents.is_empty() {
+            ui.bullet(format!(
+                "{} {}",
+                style("required by").yellow(),
+                removal.dependents.join(", ")
+            ))
+            .map_err(ui_error)?;
+        }
+    }
+
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
 fn ui_error(err: std::io::Error) -> zb_core::Error {
     zb_core::Error::StoreCorruption {
         message: format!("failed to write CLI output: {err}"),
@@ -0,0 +1,320 @@
+use std::io::{self, Write};
+
+use console::{StyledObject, style};
+use zb_core::VersionBump;
+use zb_io::{FormulaOutcome, UpgradeCandidate, UpgradePreview};
+
+use crate::commands::install;
+use crate::format::human_bytes;
+use crate::ui::StdUi;
+
+pub struct UpgradeOptions {
+    pub dry_run: bool,
+    pub json: bool,
+    pub include_rebuilds: bool,
+    pub accept_disabled: bool,
+    pub no_link: bool,
+}
+
+/// Upgrade `formulas` (every outdated formula when empty) to their current
+/// version, after listing the upgrades grouped by how far they move.
+pub async fn execute(
+    installer: &mut zb_io::Installer,
+    formulas: &[String],
+    options: UpgradeOptions,
+    ui: &mut StdUi,
+) -> Result<(), zb_core::Error> {
+    let (preview, warnings) = installer
+        .preview_upgrade(formulas, options.include_rebuilds, options.accept_disabled)
+        .await?;
+    for warning in &warnings {
+        ui.warn(warning).map_err(ui_error)?;
+    }
+
+    let out = &mut io::stdout().lock();
+    if options.json {
+        let json = serde_json::to_string_pretty(&preview).map_err(|e| ui_error(e.into()))?;
+        return writeln!(out, "{json}").map_err(ui_error);
+    }
+    write_preview(out, &preview).map_err(ui_error)?;
+    if options.dry_run || preview.groups.is_empty() {
+        return Ok(());
+    }
+
+    let plan = installer.plan_upgrade(&preview).await?;
+    install::print_resolved(&plan, ui)?;
+    let result =
+        install::execute_plan(installer, plan, options.no_link, &preview.names(), ui).await?;
+
+    let upgraded: Vec<&UpgradeCandidate> = preview
+        .groups
+        .iter()
+        .flat_map(|group| &group.formulas)
+        .filter(|candidate| {
+            result.outcomes.iter().any(|(name, outcome)| {
+                *name == candidate.name && matches!(outcome, FormulaOutcome::Installed)
+            })
+        })
+        .collect();
+    ui.heading(format!(
+        "Upgraded {} packages",
+        style(upgraded.len()).green().bold()
+    ))
+    .map_err(ui_error)?;
+    for candidate in upgraded {
+        ui.bullet(upgraded_line(candidate)).map_err(ui_error)?;
+    }
+    Ok(())
+}
+
+/// `jq 1.7 -> 1.7.1`
+fn upgraded_line(candidate: &UpgradeCandidate) -> String {
+    format!(
+        "{} {} {} {}",
+        style(&candidate.name).bold(),
+        style(&candidate.installed_version).red(),
+        style("->").dim(),
+        style(&candidate.current_version).green()
+    )
+}
+
+/// One section per kind of version bump, largest first:
+///
+/// ```text
+/// ==> Major (1, 31.2 MB)
+///     node  20.1.0 -> 22.3.0  31.2 MB
+/// ```
+///
+/// then the formulas held back because upstream disabled them or they are
+/// pinned, and a total.
+fn write_preview(out: &mut impl Write, preview: &UpgradePreview) -> io::Result<()> {
+    let candidates = preview.groups.iter().flat_map(|g| &g.formulas);
+    let name_width = candidates
+        .clone()
+        .chain(&preview.held)
+        .chain(&preview.pinned)
+        .map(|c| c.name.len())
+        .max()
+        .unwrap_or(0);
+    let version_width = candidates
+        .clone()
+        .chain(&preview.held)
+        .chain(&preview.pinned)
+        .map(|c| c.installed_version.len())
+        .max()
+        .unwrap_or(0);
+    let current_width = candidates
+        .chain(&preview.held)
+        .chain(&preview.pinned)
+        .map(|c| c.current_version.len())
+        .max()
+        .unwrap_or(0);
+    let row = |candidate: &UpgradeCandidate| {
+        let mut line = format!(
+            "    {:name_width$}  {:version_width$} {} {:current_width$}  {}",
+            candidate.name,
+            style(&candidate.installed_version).red(),
+            style("->").dim(),
+            style(&candidate.current_version).green(),
+            size(candidate.download_bytes),
+        );
+        if candidate.deprecated {
+            line.push_str(&format!("  {}", style("[deprecated]").yellow()));
+        }
+        line
+    };
+
+    if preview.groups.is_empty() {
+        writeln!(
+            out,
+            "{} All packages are up to date.",
+            style("==>").cyan().bold()
+        )?;
+    }
+    for group in &preview.groups {
+        writeln!(
+            out,
+            "{} {} ({}, {})",
+            style("==>").cyan().bold(),
+            bump_label(group.bump).bold(),
+            group.formulas.len(),
+            human_bytes(group.download_bytes)
+        )?;
+        for candidate in &group.formulas {
+            writeln!(out, "{}", row(candidate))?;
+        }
+    }
+    if !preview.held.is_empty() {
+        writeln!(
+            out,
+            "{} {} ({}): disabled upstream; pass --accept-disabled to upgrade",
+            style("==>").cyan().bold(),
+            style("Held back").yellow().bold(),
+            preview.held.len()
+        )?;
+        for candidate in &preview.held {
+            writeln!(out, "{}", row(candidate))?;
+        }
+    }
+    if !preview.pinned.is_empty() {
+        writeln!(
+            out,
+            "{} {} ({}): run zb unpin to upgrade",
+            style("==>").cyan().bold(),
+            style("Pinned").yellow().bold(),
+            preview.pinned.len()
+        )?;
+        for candidate in &preview.pinned {
+            writeln!(out, "{}", row(candidate))?;
+        }
+    }
+    if !preview.groups.is_empty() {
+        let count = preview.names().len();
+        writeln!(
+            out,
+            "{} {count} {}, {} to download",
+            style("==>").cyan().bold(),
+            if count == 1 { "upgrade" } else { "upgrades" },
+            human_bytes(preview.download_bytes())
+        )?;
+    }
+    let hidden = preview.hidden_rebuilds;
+    if hidden > 0 {
+        writeln!(
+            out,
+            "{}",
+            style(format!(
+                "{hidden} rebuild-only {} hidden; pass --include-rebuilds to upgrade {}",
+                if hidden == 1 { "update" } else { "updates" },
+                if hidden == 1 { "it" } else { "them" },
+            ))
+            .dim()
+        )?;
+    }
+    Ok(())
+}
+
+fn bump_label(bump: VersionBump) -> StyledObject<&'static str> {
+    match bump {
+        VersionBump::Major => style("Major").red(),
+        VersionBump::Minor => style("Minor").yellow(),
+        VersionBump::Patch => style("Patch").green(),
+        VersionBump::Rebuild => style("Rebuild").dim(),
+    }
+}
+
+/// `cached` when the bottle is already downloaded, `?` when the size is
+/// unknown (source builds, servers that don't report it).
+fn size(bytes: Option<u64>) -> String {
+    match bytes {
+        Some(0) => "cached".to_string(),
+        Some(bytes) => human_bytes(bytes),
+        None => "?".to_string(),
+    }
+}
+
+fn ui_error(err: io::Error) -> zb_core::Error {
+    zb_core::Error::StoreCorruption {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str, from: &str, to: &str, bytes: Option<u64>) -> UpgradeCandidate {
+        UpgradeCandidate {
+            name: name.to_string(),
+            installed_version: from.to_string(),
+            current_version: to.to_string(),
+            bump: VersionBump::between(from, to),
+            download_bytes: bytes,
+            deprecated: false,
+            disabled: false,
+            pinned: false,
+        }
+    }
+
+    fn render(preview: &UpgradePreview) -> String {
+        console::set_colors_enabled(false);
+        let mut out = Vec::new();
+        write_preview(&mut out, preview).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn upgrades_are_listed_by_group_with_sizes() {
+        let mut terraform = candidate("terraform", "1.5.7", "1.9.0", Some(0));
+        terraform.deprecated = true;
+        let mut sunset = candidate("sunset", "1.0", "2.0", Some(1_000));
+        sunset.disabled = true;
+        let mut go = candidate("go", "1.21.0", "1.22.0", None);
+        go.pinned = true;
+        let preview = UpgradePreview::new(
+            vec![
+                candidate("node", "20.1.0", "22.3.0", Some(31_200_000)),
+                terraform,
+                candidate("wget", "1.21.3", "1.21.4", Some(1_500_000)),
+                candidate("curl", "8.1.0", "8.1.2", None),
+                candidate("jq", "1.7.1", "1.7.1_1", Some(500)),
+                sunset,
+                go,
+            ],
+            false,
+            false,
+        );
+
+        assert_eq!(
+            render(&preview),
+            "\
+==> Major (1, 31.2 MB)
+    node       20.1.0 -> 22.3.0  31.2 MB
+==> Minor (1, 0 B)
+    terraform  1.5.7  -> 1.9.0   cached  [deprecated]
+==> Patch (2, 1.5 MB)
+    curl       8.1.0  -> 8.1.2   ?
+    wget       1.21.3 -> 1.21.4  1.5 MB
+==> Held back (1): disabled upstream; pass --accept-disabled to upgrade
+    sunset     1.0    -> 2.0     1.0 kB
+==> Pinned (1): run zb unpin to upgrade
+    go         1.21.0 -> 1.22.0  ?
+==> 4 upgrades, 32.7 MB to download
+1 rebuild-only update hidden; pass --include-rebuilds to upgrade it
+"
+        );
+    }
+
+    #[test]
+    fn upgraded_formulas_are_summarized_old_to_new() {
+        console::set_colors_enabled(false);
+        assert_eq!(
+            upgraded_line(&candidate("jq", "1.7", "1.7.1", None)),
+            "jq 1.7 -> 1.7.1"
+        );
+    }
+
+    #[test]
+    fn nothing_to_upgrade() {
+        assert_eq!(
+            render(&UpgradePreview::default()),
+            "==> All packages are up to date.\n"
+        );
+    }
+
+    #[test]
+    fn json_mirrors_the_groups() {
+        let preview = UpgradePreview::new(
+            vec![candidate("jq", "1.6", "1.7.1", Some(50))],
+            false,
+            false,
+        );
+        let json = serde_json::to_value(&preview).unwrap();
+        assert_eq!(json["groups"][0]["bump"], "minor");
+        assert_eq!(json["groups"][0]["download_bytes"], 50);
+        assert_eq!(json["groups"][0]["formulas"][0]["name"], "jq");
+        assert_eq!(json["groups"][0]["formulas"][0]["current_version"], "1.7.1");
+        assert_eq!(json["held"], serde_json::json!([]));
+        assert_eq!(json["pinned"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,46 @@
+use console::style;
+use zb_io::InstallReason;
+
+use crate::utils::normalize_formula_name;
+
+pub fn execute(
+    installer: &mut zb_io::Installer,
+    formula: String,
+    all_paths: bool,
+    json: bool,
+) -> Result<(), zb_core::Error> {
+    let name = normalize_formula_name(&formula)?;
+    let reason = installer.why(&name, all_paths)?;
+
+    if json {
+        let mut output = serde_json::to_value(&reason).unwrap_or_default();
+        output["formula"] = serde_json::Value::String(name);
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return Ok(());
+    }
+
+    match reason {
+        InstallReason::Explicit => {
+            println!("{} was installed explicitly.", style(&name).bold());
+        }
+        InstallReason::Dependency { paths } => {
+            println!(
+                "{} {} is required by:",
+                style("==>").cyan().bold(),
+                style(&name).bold()
+            );
+            for path in paths {
+                println!("    {}", path.join(" -> "));
+            }
+        }
+        InstallReason::Unrequired => {
+            println!(
+                "Nothing installed requires {}; it can be removed with `zb uninstall {}`.",
+                style(&name).bold(),
+                name
+            );
+        }
+    }
+
+    Ok(())
+}
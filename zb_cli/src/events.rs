@@ -0,0 +1,70 @@
+//! The `--output json-lines` stream: one JSON object per line on stdout,
+//! each tagged with its kind under `event`. Download, unpack and link
+//! events are [`zb_io::InstallProgress`] serialized as-is, so they carry
+//! the same data the progress bars render; the events below fill in what
+//! only the CLI knows.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// The formulas about to be installed, dependencies first.
+    PlanResolved { formulas: Vec<PlannedFormula<'a>> },
+    /// A formula's final state: `installed`, `skipped` (a dependency
+    /// failed) or `failed`, with the dependency or error in `detail`.
+    FormulaDone {
+        name: &'a str,
+        status: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+    },
+    /// The command failed; always the last event.
+    Error { message: String },
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedFormula<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+}
+
+/// Write `event` as one line on stdout. Lines are written whole and
+/// flushed, so events from download threads never interleave.
+pub fn emit(event: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(event).map_err(io::Error::other)?;
+    line.push(b'\n');
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&line)?;
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_share_the_library_tag() {
+        let plan = Event::PlanResolved {
+            formulas: vec![PlannedFormula {
+                name: "jq",
+                version: "1.7.1",
+            }],
+        };
+        let progress = zb_io::InstallProgress::UnpackPhase {
+            name: "jq".to_string(),
+            phase: zb_io::Phase::PatchBinaries,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&plan).unwrap(),
+            r#"{"event":"plan-resolved","formulas":[{"name":"jq","version":"1.7.1"}]}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&progress).unwrap(),
+            r#"{"event":"unpack-phase","name":"jq","phase":"patch-binaries"}"#
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Sizes, durations and ages as every command prints them. Units are
+//! decimal and spelled the same regardless of locale: `1.4 GB`,
+//! `3 min 12 s`, `2 days ago`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const BYTE_UNITS: [&str; 6] = ["B", "kB", "MB", "GB", "TB", "PB"];
+
+/// `999 B`, `1.0 kB`, `1.4 GB`: one decimal above bytes, rounded to
+/// nearest, moving to the next unit once the rounded value reaches 1000.
+pub fn human_bytes(bytes: u64) -> String {
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    // Tenths of the current unit, rounded half up.
+    let mut divisor: u64 = 1000;
+    for unit in &BYTE_UNITS[1..] {
+        let tenths = (u128::from(bytes) * 10 + u128::from(divisor) / 2) / u128::from(divisor);
+        if tenths < 10_000 || *unit == BYTE_UNITS[BYTE_UNITS.len() - 1] {
+            return format!("{}.{} {unit}", tenths / 10, tenths % 10);
+        }
+        divisor = divisor.saturating_mul(1000);
+    }
+    unreachable!("the last unit always returns")
+}
+
+/// `420 ms`, `1.4 s`, `59 s`, `3 min 12 s`, `2 h 5 min`, `3 d 4 h`: at
+/// most two units, the smaller one rounded to nearest and left out when
+/// zero.
+pub fn human_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis < 1000 {
+        return format!("{millis} ms");
+    }
+    if millis < 9_950 {
+        let tenths = (millis + 50) / 100;
+        return format!("{}.{} s", tenths / 10, tenths % 10);
+    }
+
+    let secs = (millis + 500) / 1000;
+    if secs < 60 {
+        return format!("{secs} s");
+    }
+    if secs < 60 * 60 {
+        return pair(secs / 60, "min", secs % 60, "s");
+    }
+    let mins = (secs + 30) / 60;
+    if mins < 24 * 60 {
+        return pair(mins / 60, "h", mins % 60, "min");
+    }
+    let hours = (mins + 30) / 60;
+    pair(hours / 24, "d", hours % 24, "h")
+}
+
+fn pair(major: u128, major_unit: &str, minor: u128, minor_unit: &str) -> String {
+    if minor == 0 {
+        format!("{major} {major_unit}")
+    } else {
+        format!("{major} {major_unit} {minor} {minor_unit}")
+    }
+}
+
+/// How long ago the Unix `timestamp` was, in the largest whole unit:
+/// `just now`, `1 minute ago`, `23 hours ago`, `2 days ago`.
+pub fn relative_time(timestamp: i64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    relative_time_at(timestamp, now)
+}
+
+/// [`relative_time`] as seen at `now`. Timestamps after `now` (a clock
+/// that moved backwards) read as `just now`.
+pub fn relative_time_at(timestamp: i64, now: i64) -> String {
+    let secs = now.saturating_sub(timestamp).max(0);
+    let (count, unit) = match secs {
+        0..60 => return "just now".to_string(),
+        60..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural} ago")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_switch_units_where_the_rounded_value_reaches_1000() {
+        let cases = [
+            (0, "0 B"),
+            (999, "999 B"),
+            (1000, "1.0 kB"),
+            (1049, "1.0 kB"),
+            (1050, "1.1 kB"),
+            (2048, "2.0 kB"),
+            (999_949, "999.9 kB"),
+            (999_950, "1.0 MB"),
+            (4_200_000, "4.2 MB"),
+            (1_449_999_999, "1.4 GB"),
+            (1_450_000_000, "1.5 GB"),
+            (u64::MAX, "18446.7 PB"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(human_bytes(bytes), expected, "{bytes} bytes");
+        }
+    }
+
+    #[test]
+    fn durations_use_at_most_two_units() {
+        let cases = [
+            (Duration::from_millis(0), "0 ms"),
+            (Duration::from_millis(999), "999 ms"),
+            (Duration::from_millis(1000), "1.0 s"),
+            (Duration::from_millis(1449), "1.4 s"),
+            (Duration::from_millis(9949), "9.9 s"),
+            (Duration::from_millis(9950), "10 s"),
+            (Duration::from_secs(59), "59 s"),
+            (Duration::from_millis(59_499), "59 s"),
+            (Duration::from_millis(59_500), "1 min"),
+            (Duration::from_secs(60), "1 min"),
+            (Duration::from_secs(192), "3 min 12 s"),
+            (Duration::from_secs(3599), "59 min 59 s"),
+            (Duration::from_secs(3600), "1 h"),
+            (Duration::from_secs(2 * 3600 + 5 * 60 + 20), "2 h 5 min"),
+            (Duration::from_secs(86_399), "1 d"),
+            (Duration::from_secs(3 * 86_400 + 4 * 3600), "3 d 4 h"),
+        ];
+        for (duration, expected) in cases {
+            assert_eq!(human_duration(duration), expected, "{duration:?}");
+        }
+    }
+
+    #[test]
+    fn relative_time_picks_the_largest_whole_unit() {
+        let now = 1_700_000_000;
+        let cases = [
+            (now + 30, "just now"),
+            (now, "just now"),
+            (now - 59, "just now"),
+            (now - 60, "1 minute ago"),
+            (now - 3599, "59 minutes ago"),
+            (now - 3600, "1 hour ago"),
+            (now - 86_399, "23 hours ago"),
+            (now - 86_400, "1 day ago"),
+            (now - 3 * 86_400 - 5, "3 days ago"),
+        ];
+        for (timestamp, expected) in cases {
+            assert_eq!(relative_time_at(timestamp, now), expected, "{timestamp}");
+        }
+    }
+}
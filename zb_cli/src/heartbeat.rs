@@ -0,0 +1,259 @@
+//! A periodic one-line progress summary for runs whose output isn't a
+//! terminal. The progress bars are hidden there, so a CI log would show
+//! nothing during a long download or patch phase, and some CI systems kill
+//! jobs that stay silent for too long.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use zb_io::{InstallProgress, Phase};
+
+use crate::format::human_bytes;
+
+pub const DEFAULT_HEARTBEAT_SECS: u64 = 30;
+
+/// What the progress events of one run add up to so far.
+#[derive(Debug, Default)]
+struct Progress {
+    bottles: usize,
+    fetched: usize,
+    /// Bytes downloaded and expected, per bottle downloaded from the network.
+    downloads: BTreeMap<String, (u64, Option<u64>)>,
+    /// What each formula past its download is doing.
+    activity: BTreeMap<String, &'static str>,
+}
+
+impl Progress {
+    fn observe(&mut self, event: &InstallProgress) {
+        match event {
+            InstallProgress::DownloadStarted { name, total_bytes } => {
+                self.downloads.insert(name.clone(), (0, *total_bytes));
+            }
+            InstallProgress::DownloadProgress {
+                name,
+                downloaded,
+                total_bytes,
+            } => {
+                self.downloads
+                    .insert(name.clone(), (*downloaded, *total_bytes));
+            }
+            InstallProgress::DownloadCompleted { name, total_bytes } => {
+                self.fetched += 1;
+                self.downloads
+                    .insert(name.clone(), (*total_bytes, Some(*total_bytes)));
+            }
+            InstallProgress::DownloadCached { .. } => self.fetched += 1,
+            InstallProgress::UnpackStarted { name }
+            | InstallProgress::UnpackProgress { name, .. } => {
+                self.activity.insert(name.clone(), "unpacking");
+            }
+            InstallProgress::UnpackPhase { name, phase } => {
+                let activity = match phase {
+                    Phase::Copy => "copying",
+                    Phase::Permissions => "fixing permissions",
+                    Phase::PatchBinaries => "patching binaries",
+                    Phase::PatchText => "patching text files",
+                    Phase::Codesign => "signing",
+                };
+                self.activity.insert(name.clone(), activity);
+            }
+            InstallProgress::LinkStarted { name } => {
+                self.activity.insert(name.clone(), "linking");
+            }
+            InstallProgress::UnpackCompleted { name }
+            | InstallProgress::LinkCompleted { name }
+            | InstallProgress::LinkSkipped { name, .. }
+            | InstallProgress::LinkRestored { name, .. }
+            | InstallProgress::InstallCompleted { name } => {
+                self.activity.remove(name);
+            }
+            InstallProgress::PatchProgress { .. } | InstallProgress::RateLimited { .. } => {}
+        }
+    }
+
+    /// `downloaded 3/12 bottles, 412.0 MB/1.9 GB; ffmpeg (patching binaries)`
+    fn summary(&self) -> String {
+        let mut summary = format!("downloaded {}/{} bottles", self.fetched, self.bottles);
+        let downloaded: u64 = self.downloads.values().map(|(done, _)| done).sum();
+        let expected: u64 = self
+            .downloads
+            .values()
+            .filter_map(|(_, total)| *total)
+            .sum();
+        if expected > 0 {
+            summary.push_str(&format!(
+                ", {}/{}",
+                human_bytes(downloaded),
+                human_bytes(expected)
+            ));
+        }
+        if !self.activity.is_empty() {
+            let active: Vec<String> = self
+                .activity
+                .iter()
+                .map(|(name, activity)| format!("{name} ({activity})"))
+                .collect();
+            summary.push_str("; ");
+            summary.push_str(&active.join(", "));
+        }
+        summary
+    }
+}
+
+/// Prints a [`Progress`] summary every interval, on its own thread, until
+/// stopped or dropped.
+pub struct Heartbeat {
+    progress: Arc<Mutex<Progress>>,
+    stop: Mutex<Option<Sender<()>>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Heartbeat {
+    /// Summarize the install of `bottles` bottles on stderr every
+    /// `interval`.
+    pub fn start(interval: Duration, bottles: usize) -> Self {
+        Self::start_with(interval, bottles, io::stderr())
+    }
+
+    fn start_with(
+        interval: Duration,
+        bottles: usize,
+        mut out: impl Write + Send + 'static,
+    ) -> Self {
+        let progress = Arc::new(Mutex::new(Progress {
+            bottles,
+            ..Progress::default()
+        }));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let shared = progress.clone();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let summary = shared.lock().map(|p| p.summary()).unwrap_or_default();
+                let _ = writeln!(out, "==> Working: {summary}");
+            }
+        });
+        Self {
+            progress,
+            stop: Mutex::new(Some(stop)),
+            thread: Mutex::new(Some(thread)),
+        }
+    }
+
+    pub fn observe(&self, event: &InstallProgress) {
+        if let Ok(mut progress) = self.progress.lock() {
+            progress.observe(event);
+        }
+    }
+
+    /// Stop printing; returns once the thread is gone, so no line follows.
+    pub fn stop(&self) {
+        if let Ok(mut stop) = self.stop.lock() {
+            stop.take();
+        }
+        if let Some(thread) = self.thread.lock().ok().and_then(|mut t| t.take()) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn summary_counts_bottles_bytes_and_current_work() {
+        let mut progress = Progress {
+            bottles: 3,
+            ..Progress::default()
+        };
+        assert_eq!(progress.summary(), "downloaded 0/3 bottles");
+
+        for event in [
+            InstallProgress::DownloadCached {
+                name: "jq".to_string(),
+            },
+            InstallProgress::DownloadStarted {
+                name: "ffmpeg".to_string(),
+                total_bytes: Some(1_900_000_000),
+            },
+            InstallProgress::DownloadProgress {
+                name: "ffmpeg".to_string(),
+                downloaded: 412_000_000,
+                total_bytes: Some(1_900_000_000),
+            },
+            InstallProgress::UnpackStarted {
+                name: "jq".to_string(),
+            },
+            InstallProgress::UnpackPhase {
+                name: "jq".to_string(),
+                phase: Phase::PatchBinaries,
+            },
+        ] {
+            progress.observe(&event);
+        }
+        assert_eq!(
+            progress.summary(),
+            "downloaded 1/3 bottles, 412.0 MB/1.9 GB; jq (patching binaries)"
+        );
+
+        progress.observe(&InstallProgress::InstallCompleted {
+            name: "jq".to_string(),
+        });
+        progress.observe(&InstallProgress::DownloadCompleted {
+            name: "ffmpeg".to_string(),
+            total_bytes: 1_900_000_000,
+        });
+        assert_eq!(progress.summary(), "downloaded 2/3 bottles, 1.9 GB/1.9 GB");
+    }
+
+    #[test]
+    fn beats_until_stopped() {
+        let out = Shared::default();
+        let heartbeat = Heartbeat::start_with(Duration::from_millis(20), 1, out.clone());
+        heartbeat.observe(&InstallProgress::DownloadCached {
+            name: "jq".to_string(),
+        });
+        thread::sleep(Duration::from_millis(150));
+        heartbeat.stop();
+
+        let lines = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let count = lines.lines().count();
+        assert!(count >= 2, "{lines}");
+        assert!(
+            lines.contains("==> Working: downloaded 1/1 bottles"),
+            "{lines}"
+        );
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .count(),
+            count
+        );
+    }
+}
@@ -1,10 +1,14 @@
 use console::style;
+use fs4::fs_std::FileExt;
 use std::io::Write;
+use std::os::unix::fs::DirBuilderExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use tracing::info;
 
+use crate::prompt::{PromptPolicy, confirm};
 use crate::ui::{PromptDefault, StdUi};
-use zb_io::validate_privileged_path;
+use zb_io::{probe_filesystem, same_filesystem, validate_privileged_path};
 
 #[derive(Debug)]
 pub enum InitError {
@@ -45,17 +49,179 @@ pub fn is_writable(path: &Path) -> bool {
     }
 }
 
+/// File in the prefix naming the root it belongs to, written by init.
+/// `zb reset` only clears prefixes that carry it.
+pub const PREFIX_MARKER: &str = ".zerobrew";
+
+/// Whose prefix a directory is, going by its [`PREFIX_MARKER`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrefixOwner {
+    /// The marker names this root.
+    Root,
+    /// There is no readable marker.
+    Unmarked,
+    /// The marker names another root.
+    OtherRoot(PathBuf),
+}
+
+pub fn prefix_owner(prefix: &Path, root: &Path) -> PrefixOwner {
+    let Ok(contents) = std::fs::read_to_string(prefix.join(PREFIX_MARKER)) else {
+        return PrefixOwner::Unmarked;
+    };
+    let recorded = contents.trim_end_matches('\n');
+    if recorded.is_empty() {
+        return PrefixOwner::Unmarked;
+    }
+    let recorded = PathBuf::from(recorded);
+    let same = recorded == root
+        || matches!(
+            (std::fs::canonicalize(&recorded), std::fs::canonicalize(root)),
+            (Ok(a), Ok(b)) if a == b
+        );
+    if same {
+        PrefixOwner::Root
+    } else {
+        PrefixOwner::OtherRoot(recorded)
+    }
+}
+
+/// Record that `prefix` belongs to `root`. The marker is replaced whole, so
+/// a concurrent reader never sees it half written.
+pub fn write_prefix_marker(prefix: &Path, root: &Path) -> std::io::Result<()> {
+    static STAGED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let staged = prefix.join(format!(
+        "{PREFIX_MARKER}.{}.{}",
+        std::process::id(),
+        STAGED.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    ));
+    std::fs::write(&staged, format!("{}\n", root.display()))?;
+    std::fs::rename(&staged, prefix.join(PREFIX_MARKER)).inspect_err(|_| {
+        let _ = std::fs::remove_file(&staged);
+    })
+}
+
+/// Whether an unmarked `prefix` can be claimed without asking: it is empty,
+/// or has the Cellar zerobrew creates and no Homebrew installation.
+fn is_claimable(prefix: &Path) -> bool {
+    let Ok(mut entries) = std::fs::read_dir(prefix) else {
+        return false;
+    };
+    if entries.next().is_none() {
+        return true;
+    }
+    prefix.join(zb_core::CELLAR_DIR).is_dir()
+        && !prefix.join("Homebrew").exists()
+        && !prefix.join("bin/brew").exists()
+}
+
 /// Longest Homebrew prefix we may need to replace in Mach-O binaries.
 /// On macOS, paths inside Mach-O headers are fixed-size, so the replacement
 /// prefix must be no longer than the original.  `/opt/homebrew` = 13 chars.
 const MAX_PREFIX_LEN_MACOS: usize = 13;
 
+/// Mode given to every directory `zb init` creates.
+const DIR_MODE: u32 = 0o755;
+
+/// One step of `zb init`. The whole plan is worked out before anything is
+/// touched, so `zb init --dry-run` shows exactly what a real run executes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitAction {
+    /// Create a directory and any missing parents.
+    CreateDir {
+        path: PathBuf,
+        mode: u32,
+        sudo: bool,
+    },
+    /// Hand a tree created with sudo over to the invoking user.
+    Chown { path: PathBuf, user: String },
+    /// Write the [`PREFIX_MARKER`] claiming `prefix` for `root`.
+    MarkPrefix { prefix: PathBuf, root: PathBuf },
+    /// Insert or replace the managed zerobrew block in a shell config file,
+    /// which puts `path_dirs` on `PATH`.
+    UpdateShellConfig {
+        file: PathBuf,
+        block: String,
+        path_dirs: Vec<PathBuf>,
+    },
+}
+
+impl std::fmt::Display for InitAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InitAction::CreateDir { path, mode, sudo } => {
+                write!(f, "create {} (mode {mode:o})", path.display())?;
+                if *sudo {
+                    write!(f, " with sudo")?;
+                }
+                Ok(())
+            }
+            InitAction::Chown { path, user } => {
+                write!(f, "sudo chown -R {user} {}", path.display())
+            }
+            InitAction::MarkPrefix { prefix, root } => write!(
+                f,
+                "mark {} as the prefix of {}",
+                prefix.join(PREFIX_MARKER).display(),
+                root.display()
+            ),
+            InitAction::UpdateShellConfig {
+                file, path_dirs, ..
+            } => {
+                let dirs: Vec<_> = path_dirs.iter().map(|d| d.display().to_string()).collect();
+                write!(
+                    f,
+                    "write the zerobrew block to {}, adding {} to PATH",
+                    file.display(),
+                    dirs.join(" and ")
+                )
+            }
+        }
+    }
+}
+
+/// Everything `zb init` would do, in order. Empty when the root, prefix and
+/// shell config are already set up.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InitPlan {
+    pub actions: Vec<InitAction>,
+}
+
+impl InitPlan {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    pub fn needs_sudo(&self) -> bool {
+        self.actions.iter().any(|action| {
+            matches!(
+                action,
+                InitAction::CreateDir { sudo: true, .. } | InitAction::Chown { .. }
+            )
+        })
+    }
+}
+
+/// Set up `root` and `prefix` and the shell config. With `dry_run` nothing
+/// is changed; either way the returned plan lists what was (or would be) done.
 pub fn run_init(
     root: &Path,
     prefix: &Path,
     no_modify_path: bool,
+    dry_run: bool,
     ui: &mut StdUi,
-) -> Result<(), InitError> {
+) -> Result<InitPlan, InitError> {
+    if dry_run {
+        check_paths(root, prefix, ui)?;
+        return plan_init(root, prefix, no_modify_path);
+    }
+
+    let _lock = InitLock::acquire()?;
+    init_locked(root, prefix, no_modify_path, ui)
+}
+
+/// Refuse paths that can't safely be passed to sudo, and warn when the
+/// prefix is too long to patch Mach-O binaries for.
+fn check_paths(root: &Path, prefix: &Path, ui: &mut StdUi) -> Result<(), InitError> {
     validate_privileged_path(root)
         .map_err(|e| InitError::Message(format!("invalid root path: {e}")))?;
     validate_privileged_path(prefix)
@@ -79,28 +245,70 @@ pub fn run_init(
             ui.blank_line()?;
         }
     }
+    Ok(())
+}
+
+/// Serializes init, manual or automatic, for the current user. Two zb
+/// commands started together on a fresh machine would otherwise both create
+/// the directories and both rewrite the shell config. The lock lives in
+/// `ZEROBREW_DIR`, which is the user's own and exists before the root does.
+struct InitLock {
+    _file: std::fs::File,
+}
+
+impl InitLock {
+    fn acquire() -> Result<Self, InitError> {
+        let (zerobrew_dir, _) = zerobrew_dirs()?;
+        std::fs::create_dir_all(&zerobrew_dir)?;
+        let file = std::fs::File::create(Path::new(&zerobrew_dir).join("init.lock"))?;
+        file.lock_exclusive()
+            .map_err(|e| InitError::Message(format!("Failed to acquire init lock: {e}")))?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// Plan and run init while holding the [`InitLock`], so the plan reflects
+/// whatever an init that finished first already did. Every route into the
+/// privileged steps goes through here, so the paths are checked here.
+fn init_locked(
+    root: &Path,
+    prefix: &Path,
+    no_modify_path: bool,
+    ui: &mut StdUi,
+) -> Result<InitPlan, InitError> {
+    check_paths(root, prefix, ui)?;
+    let plan = plan_init(root, prefix, no_modify_path)?;
 
     ui.heading("Initializing zerobrew...")?;
 
-    let zerobrew_dir = match std::env::var("ZEROBREW_DIR") {
-        Ok(dir) => dir,
-        Err(_) => {
-            let home = std::env::var("HOME")
-                .map_err(|_| InitError::Message("HOME not set".to_string()))?;
-            format!("{}/.zerobrew", home)
-        }
-    };
-    let zerobrew_bin = format!("{}/bin", zerobrew_dir);
+    if plan.needs_sudo() {
+        ui.info("Creating directories (requires sudo)...")?;
+    }
+    for action in &plan.actions {
+        execute_action(action, ui)?;
+    }
+
+    check_filesystems(root, prefix, ui)?;
+
+    if no_modify_path {
+        report_skipped_path(prefix, ui)?;
+    }
 
+    ui.heading("Initialization complete!")?;
+
+    Ok(plan)
+}
+
+/// Work out what `zb init` has to do without changing anything.
+pub fn plan_init(root: &Path, prefix: &Path, no_modify_path: bool) -> Result<InitPlan, InitError> {
     let dirs_to_create: Vec<PathBuf> = vec![
         root.to_path_buf(),
         root.join("store"),
         root.join("db"),
-        root.join("cache"),
         root.join("locks"),
         prefix.to_path_buf(),
         prefix.join("bin"),
-        prefix.join("Cellar"),
+        prefix.join(zb_core::CELLAR_DIR),
     ];
 
     let need_sudo = dirs_to_create.iter().any(|d| {
@@ -113,76 +321,204 @@ pub fn run_init(
         }
     });
 
+    let mut actions: Vec<InitAction> = dirs_to_create
+        .into_iter()
+        .filter(|dir| !dir.exists())
+        .map(|path| InitAction::CreateDir {
+            path,
+            mode: DIR_MODE,
+            sudo: need_sudo,
+        })
+        .collect();
+
     if need_sudo {
-        ui.info("Creating directories (requires sudo)...")?;
+        let user = current_user()?;
+        for path in [root, prefix] {
+            actions.push(InitAction::Chown {
+                path: path.to_path_buf(),
+                user: user.clone(),
+            });
+        }
+    }
+
+    match prefix_owner(prefix, root) {
+        PrefixOwner::Root => {}
+        PrefixOwner::Unmarked => actions.push(InitAction::MarkPrefix {
+            prefix: prefix.to_path_buf(),
+            root: root.to_path_buf(),
+        }),
+        PrefixOwner::OtherRoot(other) => return Err(other_root_error(prefix, root, &other)),
+    }
+
+    if !no_modify_path {
+        let (zerobrew_dir, zerobrew_bin) = zerobrew_dirs()?;
+        actions.extend(shell_config_action(
+            prefix,
+            &zerobrew_dir,
+            &zerobrew_bin,
+            root,
+        )?);
+    }
+
+    Ok(InitPlan { actions })
+}
+
+fn zerobrew_dirs() -> Result<(String, String), InitError> {
+    let zerobrew_dir = match std::env::var("ZEROBREW_DIR") {
+        Ok(dir) => dir,
+        Err(_) => {
+            let home = std::env::var("HOME")
+                .map_err(|_| InitError::Message("HOME not set".to_string()))?;
+            format!("{}/.zerobrew", home)
+        }
+    };
+    let zerobrew_bin = format!("{}/bin", zerobrew_dir);
+    Ok((zerobrew_dir, zerobrew_bin))
+}
+
+fn current_user() -> Result<String, InitError> {
+    Command::new("whoami")
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .or_else(|| std::env::var("USER").ok())
+        .ok_or_else(|| {
+            InitError::Message(
+                "Could not determine current user (whoami failed and $USER not set)".into(),
+            )
+        })
+}
 
-        for dir in &dirs_to_create {
+fn execute_action(action: &InitAction, ui: &mut StdUi) -> Result<(), InitError> {
+    match action {
+        InitAction::CreateDir {
+            path,
+            mode,
+            sudo: true,
+        } => {
             let status = Command::new("sudo")
-                .args(["mkdir", "-p", &dir.to_string_lossy()])
+                .args(["mkdir", "-p", "-m", &format!("{mode:o}")])
+                .arg(path)
                 .status()
                 .map_err(|e| InitError::Message(format!("Failed to run sudo mkdir: {}", e)))?;
 
             if !status.success() {
                 return Err(InitError::Message(format!(
                     "Failed to create directory: {}",
-                    dir.display()
+                    path.display()
                 )));
             }
         }
+        InitAction::CreateDir {
+            path,
+            mode,
+            sudo: false,
+        } => {
+            match std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(*mode)
+                .create(path)
+            {
+                Ok(()) => {}
+                // Created by someone else since the plan was made.
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && path.is_dir() => {}
+                Err(e) => {
+                    return Err(InitError::Message(format!(
+                        "Failed to create {}: {}",
+                        path.display(),
+                        e
+                    )));
+                }
+            }
+        }
+        InitAction::Chown { path, user } => {
+            let status = Command::new("sudo")
+                .args(["chown", "-R", user])
+                .arg(path)
+                .status()
+                .map_err(|e| InitError::Message(format!("Failed to run sudo chown: {}", e)))?;
 
-        let user = Command::new("whoami")
-            .output()
-            .ok()
-            .and_then(|o| String::from_utf8(o.stdout).ok())
-            .map(|s| s.trim().to_string())
-            .or_else(|| std::env::var("USER").ok())
-            .ok_or_else(|| {
-                InitError::Message(
-                    "Could not determine current user (whoami failed and $USER not set)".into(),
-                )
+            if !status.success() {
+                return Err(InitError::Message(format!(
+                    "Failed to set ownership on {}",
+                    path.display()
+                )));
+            }
+        }
+        InitAction::MarkPrefix { prefix, root } => {
+            write_prefix_marker(prefix, root).map_err(|e| {
+                InitError::Message(format!(
+                    "Failed to write {}: {e}",
+                    prefix.join(PREFIX_MARKER).display()
+                ))
             })?;
-
-        let status = Command::new("sudo")
-            .args(["chown", "-R", &user, &root.to_string_lossy()])
-            .status()
-            .map_err(|e| InitError::Message(format!("Failed to run sudo chown: {}", e)))?;
-
-        if !status.success() {
-            return Err(InitError::Message(format!(
-                "Failed to set ownership on {}",
-                root.display()
-            )));
         }
+        InitAction::UpdateShellConfig {
+            file,
+            block,
+            path_dirs,
+        } => write_shell_config(file, block, path_dirs, ui)?,
+    }
+    Ok(())
+}
 
-        let status = Command::new("sudo")
-            .args(["chown", "-R", &user, &prefix.to_string_lossy()])
-            .status()
-            .map_err(|e| InitError::Message(format!("Failed to run sudo chown: {}", e)))?;
+fn other_root_error(prefix: &Path, root: &Path, other: &Path) -> InitError {
+    InitError::Message(format!(
+        "{} belongs to the zerobrew root {}, not {}; set ZEROBREW_ROOT={} or pick \
+         another prefix (delete {} if that root is gone)",
+        prefix.display(),
+        other.display(),
+        root.display(),
+        other.display(),
+        prefix.join(PREFIX_MARKER).display()
+    ))
+}
 
-        if !status.success() {
-            return Err(InitError::Message(format!(
-                "Failed to set ownership on {}",
-                prefix.display()
-            )));
+/// Check the marker of an initialized prefix. A prefix claimed by another
+/// root is refused. An unmarked one is claimed when it is safe to (see
+/// [`is_claimable`]) and otherwise left unmarked with a warning, since
+/// `zb reset` won't clear it until `zb init` claims it.
+fn check_prefix_owner(root: &Path, prefix: &Path, ui: &mut StdUi) -> Result<(), zb_core::Error> {
+    match prefix_owner(prefix, root) {
+        PrefixOwner::Root => Ok(()),
+        PrefixOwner::OtherRoot(other) => {
+            let InitError::Message(message) = other_root_error(prefix, root, &other);
+            Err(zb_core::Error::InvalidArgument { message })
         }
-    } else {
-        for dir in &dirs_to_create {
-            std::fs::create_dir_all(dir).map_err(|e| {
-                InitError::Message(format!("Failed to create {}: {}", dir.display(), e))
-            })?;
+        PrefixOwner::Unmarked if is_claimable(prefix) => {
+            if let Err(e) = write_prefix_marker(prefix, root) {
+                tracing::warn!(prefix = %prefix.display(), error = %e, "failed to mark prefix");
+            }
+            Ok(())
         }
+        PrefixOwner::Unmarked => ui
+            .warn(format!(
+                "{} is not marked as a zerobrew prefix; run 'zb init' to claim it \
+                 ('zb reset' refuses to clear it until then)",
+                prefix.display()
+            ))
+            .map_err(io_to_core_error),
+    }
+}
+/// Refuse roots and prefixes whose filesystem cannot hold kegs, before any
+/// install fails with a less obvious error.
+fn check_filesystems(root: &Path, prefix: &Path, ui: &mut StdUi) -> Result<(), InitError> {
+    let mut hardlinks = true;
+    for dir in [root, prefix] {
+        let caps = probe_filesystem(dir).map_err(|e| InitError::Message(e.to_string()))?;
+        hardlinks &= caps.hardlinks;
     }
 
-    add_to_path(
-        prefix,
-        &zerobrew_dir,
-        &zerobrew_bin,
-        root,
-        no_modify_path,
-        ui,
-    )?;
-
-    ui.heading("Initialization complete!")?;
+    if !same_filesystem(root, prefix) {
+        ui.note(format!(
+            "{} and {} are on different filesystems; kegs will be copied instead of hard-linked.",
+            root.display(),
+            prefix.display()
+        ))?;
+    } else if !hardlinks {
+        ui.note("This filesystem does not support hard links; kegs will be copied.")?;
+    }
 
     Ok(())
 }
@@ -220,23 +556,17 @@ fn upsert_managed_block(existing: &str, managed_block: &str) -> String {
     }
 }
 
-fn add_to_path(
-    prefix: &Path,
-    zerobrew_dir: &str,
-    zerobrew_bin: &str,
-    root: &Path,
-    no_modify_path: bool,
-    ui: &mut StdUi,
-) -> Result<(), InitError> {
-    enum ShellConfigKind {
-        Posix,
-        Fish,
-    }
+enum ShellConfigKind {
+    Posix,
+    Fish,
+}
 
+/// The shell config file `zb init` writes to for the user's `$SHELL`.
+fn shell_config_target() -> Result<(String, ShellConfigKind), InitError> {
     let shell = std::env::var("SHELL").unwrap_or_default();
     let home = std::env::var("HOME").map_err(|_| InitError::Message("HOME not set".to_string()))?;
 
-    let (config_file, shell_kind) = if shell.contains("zsh") {
+    let target = if shell.contains("zsh") {
         let zdotdir = std::env::var("ZDOTDIR").unwrap_or_else(|_| home.clone());
         let zshenv = format!("{}/.zshenv", zdotdir);
         let zshrc = format!("{}/.zshrc", zdotdir);
@@ -264,14 +594,23 @@ fn add_to_path(
     } else {
         (format!("{}/.profile", home), ShellConfigKind::Posix)
     };
+    Ok(target)
+}
 
-    let prefix_bin = prefix.join("bin");
+/// The step that brings the shell config up to date, or `None` when it
+/// already carries the current zerobrew block.
+fn shell_config_action(
+    prefix: &Path,
+    zerobrew_dir: &str,
+    zerobrew_bin: &str,
+    root: &Path,
+) -> Result<Option<InitAction>, InitError> {
+    let (config_file, shell_kind) = shell_config_target()?;
     let existing_config = std::fs::read_to_string(&config_file).unwrap_or_default();
 
-    if !no_modify_path {
-        let block_body = match shell_kind {
-            ShellConfigKind::Posix => format!(
-                r#"
+    let block_body = match shell_kind {
+        ShellConfigKind::Posix => format!(
+            r#"
 # zerobrew
 export ZEROBREW_DIR={zerobrew_dir}
 export ZEROBREW_BIN={zerobrew_bin}
@@ -318,13 +657,13 @@ _zb_path_append() {{
 _zb_path_append "$ZEROBREW_BIN"
 _zb_path_append "$ZEROBREW_PREFIX/bin"
 "#,
-                zerobrew_dir = zerobrew_dir,
-                zerobrew_bin = zerobrew_bin,
-                root = root.display(),
-                prefix = prefix.display()
-            ),
-            ShellConfigKind::Fish => format!(
-                r#"
+            zerobrew_dir = zerobrew_dir,
+            zerobrew_bin = zerobrew_bin,
+            root = root.display(),
+            prefix = prefix.display()
+        ),
+        ShellConfigKind::Fish => format!(
+            r#"
 # zerobrew
 set -gx ZEROBREW_DIR "{zerobrew_dir}"
 set -gx ZEROBREW_BIN "{zerobrew_bin}"
@@ -370,67 +709,94 @@ if not contains -- "$ZEROBREW_PREFIX/bin" $PATH
     set -gx PATH "$ZEROBREW_PREFIX/bin" $PATH
 end
 "#,
-                zerobrew_dir = zerobrew_dir,
-                zerobrew_bin = zerobrew_bin,
-                root = root.display(),
-                prefix = prefix.display()
-            ),
-        };
-        let managed_block = format!("{ZB_BLOCK_START}{block_body}\n{ZB_BLOCK_END}\n");
-        let updated_config = upsert_managed_block(&existing_config, &managed_block);
+            zerobrew_dir = zerobrew_dir,
+            zerobrew_bin = zerobrew_bin,
+            root = root.display(),
+            prefix = prefix.display()
+        ),
+    };
+    let managed_block = format!("{ZB_BLOCK_START}{block_body}\n{ZB_BLOCK_END}\n");
+    if upsert_managed_block(&existing_config, &managed_block) == existing_config {
+        return Ok(None);
+    }
 
-        if let Some(parent) = std::path::Path::new(&config_file).parent() {
-            std::fs::create_dir_all(parent).map_err(|e| {
-                InitError::Message(format!(
-                    "Failed to create shell config directory {}: {}",
-                    parent.display(),
-                    e
-                ))
-            })?;
-        }
+    Ok(Some(InitAction::UpdateShellConfig {
+        file: PathBuf::from(config_file),
+        block: managed_block,
+        path_dirs: vec![PathBuf::from(zerobrew_bin), prefix.join("bin")],
+    }))
+}
 
-        let write_result = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&config_file)
-            .and_then(|mut f| f.write_all(updated_config.as_bytes()));
+fn write_shell_config(
+    config_file: &Path,
+    managed_block: &str,
+    path_dirs: &[PathBuf],
+    ui: &mut StdUi,
+) -> Result<(), InitError> {
+    let existing_config = std::fs::read_to_string(config_file).unwrap_or_default();
+    let updated_config = upsert_managed_block(&existing_config, managed_block);
+
+    if let Some(parent) = config_file.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            InitError::Message(format!(
+                "Failed to create shell config directory {}: {}",
+                parent.display(),
+                e
+            ))
+        })?;
+    }
 
-        if let Err(e) = write_result {
-            ui.note(format!(
-                "Could not write to {} due to error: {}",
-                config_file, e
-            ))?;
-            ui.info(format!("Please add the following to {}:", config_file))?;
-            ui.info(&managed_block)?;
-        } else {
-            ui.info(format!("Updated zerobrew configuration in {}", config_file))?;
-            ui.info(format!(
-                "Added {} and {} to PATH",
-                zerobrew_bin,
-                prefix_bin.display()
-            ))?;
-        }
-    } else if no_modify_path {
-        ui.info("Skipped shell configuration (--no-modify-path)")?;
+    let write_result = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(config_file)
+        .and_then(|mut f| f.write_all(updated_config.as_bytes()));
+
+    if let Err(e) = write_result {
+        ui.note(format!(
+            "Could not write to {} due to error: {}",
+            config_file.display(),
+            e
+        ))?;
+        ui.info(format!(
+            "Please add the following to {}:",
+            config_file.display()
+        ))?;
+        ui.info(managed_block)?;
+    } else {
+        let dirs: Vec<_> = path_dirs.iter().map(|d| d.display().to_string()).collect();
         ui.info(format!(
-            "To use zerobrew, add {} and {} to your PATH",
-            zerobrew_bin,
-            prefix_bin.display()
+            "Updated zerobrew configuration in {}",
+            config_file.display()
         ))?;
+        ui.info(format!("Added {} to PATH", dirs.join(" and ")))?;
     }
 
     Ok(())
 }
 
+fn report_skipped_path(prefix: &Path, ui: &mut StdUi) -> Result<(), InitError> {
+    let (_, zerobrew_bin) = zerobrew_dirs()?;
+    ui.info("Skipped shell configuration (--no-modify-path)")?;
+    ui.info(format!(
+        "To use zerobrew, add {} and {} to your PATH",
+        zerobrew_bin,
+        prefix.join("bin").display()
+    ))?;
+    Ok(())
+}
+
+/// Initialize on first use when allowed. Returns whether this call did the
+/// initializing.
 pub fn ensure_init(
     root: &Path,
     prefix: &Path,
     auto_init: bool,
     ui: &mut StdUi,
-) -> Result<(), zb_core::Error> {
+) -> Result<bool, zb_core::Error> {
     if !needs_init(root, prefix) {
-        return Ok(());
+        return check_prefix_owner(root, prefix, ui).map(|()| false);
     }
 
     // Check if both stdin and stdout are TTYs
@@ -448,10 +814,12 @@ pub fn ensure_init(
         ui.bullet(prefix.display()).map_err(io_to_core_error)?;
         ui.blank_line().map_err(io_to_core_error)?;
 
-        if !ui
-            .prompt_yes_no("Initialize now? [Y/n]", PromptDefault::Yes)
-            .map_err(io_to_core_error)?
-        {
+        if !confirm(
+            ui,
+            "Initialize now?",
+            PromptDefault::Yes,
+            PromptPolicy::Interactive,
+        )? {
             return Err(zb_core::Error::StoreCorruption {
                 message: "Initialization required. Run 'zb init' first.".to_string(),
             });
@@ -463,11 +831,21 @@ pub fn ensure_init(
         });
     }
     // Auto-initialize without prompting when non-interactive or auto_init is set
+    let init_error = |e| match e {
+        InitError::Message(msg) => zb_core::Error::StoreCorruption { message: msg },
+    };
+    let _lock = InitLock::acquire().map_err(init_error)?;
+    // Another zb command may have initialized while this one waited.
+    if !needs_init(root, prefix) {
+        return check_prefix_owner(root, prefix, ui).map(|()| false);
+    }
 
     // Pass false for no_modify_shell since user confirmed they want full initialization
-    run_init(root, prefix, false, ui).map_err(|e| match e {
-        InitError::Message(msg) => zb_core::Error::StoreCorruption { message: msg },
-    })
+    let plan = init_locked(root, prefix, false, ui).map_err(init_error)?;
+    for action in &plan.actions {
+        info!(%action, "auto-init");
+    }
+    Ok(true)
 }
 
 fn io_to_core_error(err: std::io::Error) -> zb_core::Error {
@@ -490,15 +868,14 @@ mod tests {
         root: &Path,
         no_modify_path: bool,
     ) -> Result<(), InitError> {
+        if no_modify_path {
+            return Ok(());
+        }
         let mut ui = Ui::new();
-        super::add_to_path(
-            prefix,
-            zerobrew_dir,
-            zerobrew_bin,
-            root,
-            no_modify_path,
-            &mut ui,
-        )
+        if let Some(action) = shell_config_action(prefix, zerobrew_dir, zerobrew_bin, root)? {
+            execute_action(&action, &mut ui)?;
+        }
+        Ok(())
     }
     use std::os::unix::fs::PermissionsExt;
     use std::sync::{Mutex, OnceLock};
@@ -585,6 +962,26 @@ mod tests {
         fs::set_permissions(&readonly, perms).unwrap();
     }
 
+    #[test]
+    fn auto_init_refuses_paths_unsafe_for_sudo() {
+        let _lock = env_lock();
+        let tmp = TempDir::new().unwrap();
+        unsafe {
+            std::env::set_var("ZEROBREW_DIR", tmp.path().join(".zerobrew"));
+        }
+        let root = tmp.path().join("a/../root");
+        let prefix = tmp.path().join("prefix");
+
+        let err = ensure_init(&root, &prefix, true, &mut Ui::new()).unwrap_err();
+        unsafe {
+            std::env::remove_var("ZEROBREW_DIR");
+        }
+
+        assert!(err.to_string().contains("invalid root path"), "{err}");
+        assert!(!tmp.path().join("root").exists());
+        assert!(!prefix.exists());
+    }
+
     #[test]
     fn add_to_path_writes_core_env_vars_with_guarded_ca_setup() {
         let _lock = env_lock();
@@ -987,6 +1384,151 @@ mod tests {
         assert!(content.contains("# zerobrew"));
     }
 
+    fn fresh_bash_home(tmp: &TempDir) -> (PathBuf, PathBuf) {
+        unsafe {
+            std::env::set_var("HOME", tmp.path());
+            std::env::set_var("SHELL", "/bin/bash");
+            std::env::remove_var("ZEROBREW_DIR");
+        }
+        (tmp.path().join("root"), tmp.path().join("prefix"))
+    }
+
+    #[test]
+    fn plan_for_fresh_root_creates_dirs_and_writes_shell_config() {
+        let _lock = env_lock();
+        let tmp = TempDir::new().unwrap();
+        let (root, prefix) = fresh_bash_home(&tmp);
+
+        let plan = plan_init(&root, &prefix, false).unwrap();
+
+        let created: Vec<_> = plan
+            .actions
+            .iter()
+            .filter_map(|action| match action {
+                InitAction::CreateDir { path, mode, sudo } => {
+                    assert_eq!(*mode, 0o755);
+                    assert!(!sudo);
+                    Some(path.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            created,
+            vec![
+                root.clone(),
+                root.join("store"),
+                root.join("db"),
+                root.join("locks"),
+                prefix.clone(),
+                prefix.join("bin"),
+                prefix.join("Cellar"),
+            ]
+        );
+        assert!(!plan.needs_sudo());
+
+        let Some(InitAction::UpdateShellConfig {
+            file,
+            block,
+            path_dirs,
+        }) = plan.actions.last()
+        else {
+            panic!("expected a shell config update, got {:?}", plan.actions);
+        };
+        assert_eq!(file, &tmp.path().join(".bashrc"));
+        assert!(block.starts_with(ZB_BLOCK_START));
+        assert!(block.contains(&format!("export ZEROBREW_ROOT={}", root.display())));
+        assert_eq!(
+            path_dirs,
+            &vec![tmp.path().join(".zerobrew/bin"), prefix.join("bin")]
+        );
+
+        // Planning touches nothing.
+        assert!(!root.exists());
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn plan_skips_shell_config_with_no_modify_path() {
+        let _lock = env_lock();
+        let tmp = TempDir::new().unwrap();
+        let (root, prefix) = fresh_bash_home(&tmp);
+
+        let plan = plan_init(&root, &prefix, true).unwrap();
+
+        assert!(plan.actions.iter().all(|action| matches!(
+            action,
+            InitAction::CreateDir { .. } | InitAction::MarkPrefix { .. }
+        )));
+    }
+
+    #[test]
+    fn plan_for_initialized_root_is_empty() {
+        let _lock = env_lock();
+        let tmp = TempDir::new().unwrap();
+        let (root, prefix) = fresh_bash_home(&tmp);
+        let mut ui = Ui::new();
+
+        let executed = run_init(&root, &prefix, false, false, &mut ui).unwrap();
+        assert!(!executed.is_empty());
+        assert!(root.join("store").is_dir());
+        assert!(prefix.join("Cellar").is_dir());
+
+        assert_eq!(
+            plan_init(&root, &prefix, false).unwrap(),
+            InitPlan::default()
+        );
+        let dry_run = run_init(&root, &prefix, false, true, &mut ui).unwrap();
+        assert!(dry_run.is_empty());
+    }
+
+    #[test]
+    fn init_marks_the_prefix_for_its_root() {
+        let _lock = env_lock();
+        let tmp = TempDir::new().unwrap();
+        let (root, prefix) = fresh_bash_home(&tmp);
+
+        assert_eq!(prefix_owner(&prefix, &root), PrefixOwner::Unmarked);
+        run_init(&root, &prefix, true, false, &mut Ui::new()).unwrap();
+        assert_eq!(prefix_owner(&prefix, &root), PrefixOwner::Root);
+        assert_eq!(
+            fs::read_to_string(prefix.join(PREFIX_MARKER)).unwrap(),
+            format!("{}\n", root.display())
+        );
+
+        let other = tmp.path().join("other-root");
+        assert_eq!(
+            prefix_owner(&prefix, &other),
+            PrefixOwner::OtherRoot(root.clone())
+        );
+        let err = plan_init(&other, &prefix, true).unwrap_err();
+        assert!(err.to_string().contains("belongs to the zerobrew root"));
+        assert!(ensure_init(&other, &prefix, true, &mut Ui::new()).is_err());
+    }
+
+    #[test]
+    fn ensure_init_claims_only_prefixes_that_look_like_zerobrew() {
+        let _lock = env_lock();
+        let tmp = TempDir::new().unwrap();
+        let (root, prefix) = fresh_bash_home(&tmp);
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&prefix).unwrap();
+
+        ensure_init(&root, &prefix, true, &mut Ui::new()).unwrap();
+        assert_eq!(prefix_owner(&prefix, &root), PrefixOwner::Root);
+
+        let system = tmp.path().join("usr-local");
+        fs::create_dir_all(system.join("lib")).unwrap();
+        ensure_init(&root, &system, true, &mut Ui::new()).unwrap();
+        assert_eq!(prefix_owner(&system, &root), PrefixOwner::Unmarked);
+
+        let homebrew = tmp.path().join("homebrew");
+        fs::create_dir_all(homebrew.join("Cellar")).unwrap();
+        fs::create_dir_all(homebrew.join("Homebrew")).unwrap();
+        ensure_init(&root, &homebrew, true, &mut Ui::new()).unwrap();
+        assert_eq!(prefix_owner(&homebrew, &root), PrefixOwner::Unmarked);
+    }
+
     #[test]
     fn upsert_managed_block_replacement_consumes_trailing_newline() {
         let managed_block =
@@ -1002,4 +1544,24 @@ mod tests {
         assert!(first.contains("# <<< zerobrew <<<\npostfix\n"));
         assert!(!first.contains("# <<< zerobrew <<<\n\npostfix\n"));
     }
+
+    #[test]
+    fn concurrent_ensure_init_writes_one_shell_block() {
+        let _lock = env_lock();
+        let tmp = TempDir::new().unwrap();
+        let (root, prefix) = fresh_bash_home(&tmp);
+
+        let results: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| ensure_init(&root, &prefix, true, &mut Ui::new())))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let initialized = results.into_iter().filter(|r| *r.as_ref().unwrap());
+        assert_eq!(initialized.count(), 1);
+        let bashrc = fs::read_to_string(tmp.path().join(".bashrc")).unwrap();
+        assert_eq!(bashrc.matches(ZB_BLOCK_START).count(), 1);
+        assert!(!needs_init(&root, &prefix));
+    }
 }
@@ -1,6 +1,13 @@
 pub mod cli;
 pub mod commands;
+pub mod events;
+pub mod format;
+pub mod heartbeat;
 pub mod init;
 pub mod logging;
+pub mod pager;
+pub mod porcelain;
+pub mod prompt;
 pub mod ui;
 pub mod utils;
+pub mod version;
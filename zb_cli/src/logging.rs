@@ -23,6 +23,7 @@ pub fn init(verbose: u8, quiet: bool) {
         .with(filter)
         .with(
             tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
                 .with_target(false)
                 .without_time(),
         )
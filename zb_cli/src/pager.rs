@@ -0,0 +1,71 @@
+//! Long listings go through `$PAGER` when stdout is a terminal.
+
+use std::io::{self, IsTerminal, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+pub enum Pager {
+    Direct(io::Stdout),
+    Spawned { child: Child, stdin: ChildStdin },
+}
+
+impl Pager {
+    /// Start `$PAGER` (`less` when unset) if stdout is a terminal. Output
+    /// goes straight to stdout when it isn't, or when the pager can't be
+    /// started.
+    pub fn start() -> Self {
+        if !io::stdout().is_terminal() {
+            return Self::Direct(io::stdout());
+        }
+
+        let command = std::env::var("PAGER")
+            .ok()
+            .filter(|pager| !pager.trim().is_empty())
+            .unwrap_or_else(|| "less".to_string());
+        let mut words = command.split_whitespace();
+        let Some(program) = words.next() else {
+            return Self::Direct(io::stdout());
+        };
+        let mut pager = Command::new(program);
+        pager.args(words).stdin(Stdio::piped());
+        if std::env::var_os("LESS").is_none() {
+            // Quit when it all fits on one screen, keep colors, and leave
+            // the output on screen afterwards.
+            pager.env("LESS", "FRX");
+        }
+
+        match pager.spawn() {
+            Ok(mut child) => match child.stdin.take() {
+                Some(stdin) => Self::Spawned { child, stdin },
+                None => Self::Direct(io::stdout()),
+            },
+            Err(_) => Self::Direct(io::stdout()),
+        }
+    }
+
+    /// Close the pager's input and wait for the user to quit it.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Direct(mut stdout) => stdout.flush(),
+            Self::Spawned { mut child, stdin } => {
+                drop(stdin);
+                child.wait().map(|_| ())
+            }
+        }
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Direct(stdout) => stdout.write(buf),
+            Self::Spawned { stdin, .. } => stdin.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Direct(stdout) => stdout.flush(),
+            Self::Spawned { stdin, .. } => stdin.flush(),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Output for scripts, selected with `--porcelain=v1` on `list`, `outdated`
+//! and `deps`. One record per line, fields separated by a tab, an absent
+//! value written as `-`, and never any styling, whatever the terminal. A
+//! version's format doesn't change once released; new columns or flags
+//! mean a new version. The tests below pin v1.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use zb_core::{ChangeKind, Lifecycle};
+use zb_io::{InstalledKeg, MissingDependency, OutdatedPackage, UncheckedPackage};
+
+/// The writers below produce v1, the only version so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Porcelain {
+    V1,
+}
+
+const ABSENT: &str = "-";
+
+fn record(out: &mut impl Write, fields: &[&str]) -> io::Result<()> {
+    writeln!(out, "{}", fields.join("\t"))
+}
+
+/// `,`-joined, or `-` when there are none.
+fn flags(flags: &[&str]) -> String {
+    if flags.is_empty() {
+        ABSENT.to_string()
+    } else {
+        flags.join(",")
+    }
+}
+
+/// `name version store_key installed_at flags`, where `installed_at` is in
+/// seconds since the Unix epoch and the flags are `explicit`, then
+/// `deprecated` or `disabled` as of the last refresh.
+pub fn write_list(
+    out: &mut impl Write,
+    installed: &[InstalledKeg],
+    upstream: &HashMap<String, Lifecycle>,
+) -> io::Result<()> {
+    for keg in installed {
+        let mut set = Vec::new();
+        if keg.explicit {
+            set.push("explicit");
+        }
+        match upstream.get(&keg.name) {
+            Some(Lifecycle::Deprecated { .. }) => set.push("deprecated"),
+            Some(Lifecycle::Disabled { .. }) => set.push("disabled"),
+            Some(Lifecycle::Active) | None => {}
+        }
+        record(
+            out,
+            &[
+                &keg.name,
+                &keg.version,
+                &keg.store_key,
+                &keg.installed_at.to_string(),
+                &flags(&set),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// `name installed_version current_version flags`, sorted by name. The
+/// flags are `rebuild` for a new build of the same release, and `unknown`,
+/// with `-` as the current version, when it couldn't be looked up.
+pub fn write_outdated(
+    out: &mut impl Write,
+    outdated: &[OutdatedPackage],
+    unchecked: &[UncheckedPackage],
+) -> io::Result<()> {
+    let mut records: Vec<[String; 4]> = outdated
+        .iter()
+        .map(|pkg| {
+            let set: &[&str] = match pkg.change_kind {
+                ChangeKind::Rebuild => &["rebuild"],
+                ChangeKind::Version => &[],
+            };
+            [
+                pkg.name.clone(),
+                pkg.installed_version.clone(),
+                pkg.current_version.clone(),
+                flags(set),
+            ]
+        })
+        .chain(unchecked.iter().map(|pkg| {
+            [
+                pkg.name.clone(),
+                pkg.installed_version.clone(),
+                ABSENT.to_string(),
+                flags(&["unknown"]),
+            ]
+        }))
+        .collect();
+    records.sort();
+    for fields in &records {
+        record(out, &fields.each_ref().map(String::as_str))?;
+    }
+    Ok(())
+}
+
+/// `formula dependency` for each dependency of `formula`, direct or not,
+/// sorted; offline, the ones recorded when `formula` was installed.
+pub fn write_deps(out: &mut impl Write, formula: &str, dependencies: &[String]) -> io::Result<()> {
+    for dependency in dependencies {
+        record(out, &[formula, dependency])?;
+    }
+    Ok(())
+}
+
+/// `formula dependency recorded_version` for each missing dependency, the
+/// version being `-` when the dependency was never installed.
+pub fn write_missing(out: &mut impl Write, missing: &[MissingDependency]) -> io::Result<()> {
+    for entry in missing {
+        record(
+            out,
+            &[
+                &entry.formula,
+                &entry.dependency,
+                entry.version.as_deref().unwrap_or(ABSENT),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// These pin the v1 format. If one fails, the change breaks scripts parsing
+/// v1: add a new version instead.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keg(name: &str, explicit: bool) -> InstalledKeg {
+        InstalledKeg {
+            name: name.to_string(),
+            version: "1.7.1".to_string(),
+            store_key: format!("{name}sha"),
+            installed_at: 1_700_000_000,
+            explicit,
+            copy_strategy: None,
+            license: None,
+            revision: 0,
+            rebuild: None,
+            formula_version: None,
+            pinned: false,
+        }
+    }
+
+    fn render(write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn list_v1() {
+        let upstream =
+            HashMap::from([("oldlib".to_string(), Lifecycle::Deprecated { reason: None })]);
+        let installed = [keg("jq", true), keg("oldlib", false), keg("onig", false)];
+
+        assert_eq!(
+            render(|out| write_list(out, &installed, &upstream)),
+            "jq\t1.7.1\tjqsha\t1700000000\texplicit\n\
+             oldlib\t1.7.1\toldlibsha\t1700000000\tdeprecated\n\
+             onig\t1.7.1\tonigsha\t1700000000\t-\n"
+        );
+    }
+
+    #[test]
+    fn outdated_v1() {
+        let pkg = |name: &str, from: &str, to: &str| OutdatedPackage {
+            name: name.to_string(),
+            installed_version: from.to_string(),
+            current_version: to.to_string(),
+            change_kind: ChangeKind::between(from, to),
+            installed_sha256: String::new(),
+            current_sha256: String::new(),
+            current_url: String::new(),
+            is_source_build: false,
+        };
+        let unchecked = [UncheckedPackage {
+            name: "curl".to_string(),
+            installed_version: "8.1.0".to_string(),
+            reason: "network failure".to_string(),
+        }];
+
+        assert_eq!(
+            render(|out| write_outdated(
+                out,
+                &[
+                    pkg("wget", "1.21.3", "1.21.4"),
+                    pkg("jq", "1.7.1", "1.7.1_1")
+                ],
+                &unchecked,
+            )),
+            "curl\t8.1.0\t-\tunknown\n\
+             jq\t1.7.1\t1.7.1_1\trebuild\n\
+             wget\t1.21.3\t1.21.4\t-\n"
+        );
+    }
+
+    #[test]
+    fn deps_v1() {
+        assert_eq!(
+            render(|out| write_deps(
+                out,
+                "wget",
+                &["libidn2".to_string(), "openssl@3".to_string()],
+            )),
+            "wget\tlibidn2\nwget\topenssl@3\n"
+        );
+
+        let missing = [
+            MissingDependency {
+                formula: "wget".to_string(),
+                dependency: "libidn2".to_string(),
+                version: Some("2.3.7".to_string()),
+            },
+            MissingDependency {
+                formula: "wget".to_string(),
+                dependency: "openssl@3".to_string(),
+                version: None,
+            },
+        ];
+        assert_eq!(
+            render(|out| write_missing(out, &missing)),
+            "wget\tlibidn2\t2.3.7\nwget\topenssl@3\t-\n"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::{self, BufRead, IsTerminal};
+
+use crate::ui::{PromptDefault, Ui};
+
+/// How confirmation prompts are answered for this invocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptPolicy {
+    /// Ask on the terminal and wait for an answer.
+    Interactive,
+    /// Every prompt is answered "yes" (`--yes`).
+    AssumeYes,
+    /// Never read stdin: prompts that default to yes take their default,
+    /// anything else fails asking for `--yes`.
+    NonInteractive,
+}
+
+impl PromptPolicy {
+    /// Resolve the policy from the global flags. A non-TTY stdin implies
+    /// non-interactive mode so CI jobs never block on `read_line`.
+    pub fn from_flags(yes: bool, non_interactive: bool) -> Self {
+        Self::resolve(yes, non_interactive, io::stdin().is_terminal())
+    }
+
+    fn resolve(yes: bool, non_interactive: bool, stdin_is_tty: bool) -> Self {
+        if yes {
+            Self::AssumeYes
+        } else if non_interactive || !stdin_is_tty {
+            Self::NonInteractive
+        } else {
+            Self::Interactive
+        }
+    }
+}
+
+/// Ask for confirmation according to `policy`. All CLI prompts go through
+/// here so none of them can block a non-interactive run.
+pub fn confirm<O: io::Write, E: io::Write>(
+    ui: &mut Ui<O, E>,
+    message: &str,
+    default: PromptDefault,
+    policy: PromptPolicy,
+) -> Result<bool, zb_core::Error> {
+    let mut stdin = io::stdin().lock();
+    confirm_with_reader(ui, message, default, policy, &mut stdin)
+}
+
+pub fn confirm_with_reader<O: io::Write, E: io::Write, R: BufRead>(
+    ui: &mut Ui<O, E>,
+    message: &str,
+    default: PromptDefault,
+    policy: PromptPolicy,
+    reader: &mut R,
+) -> Result<bool, zb_core::Error> {
+    match policy {
+        PromptPolicy::AssumeYes => Ok(true),
+        PromptPolicy::NonInteractive => match default {
+            PromptDefault::Yes => Ok(true),
+            PromptDefault::No => Err(zb_core::Error::InvalidArgument {
+                message: format!(
+                    "confirmation required ({}), pass -y to proceed non-interactively",
+                    message.trim_end_matches(|c: char| c == '?' || c.is_whitespace())
+                ),
+            }),
+        },
+        PromptPolicy::Interactive => {
+            let suffix = match default {
+                PromptDefault::Yes => "[Y/n]",
+                PromptDefault::No => "[y/N]",
+            };
+            ui.prompt_yes_no_with_reader(&format!("{message} {suffix}"), default, reader)
+                .map_err(|e| zb_core::Error::StoreCorruption {
+                    message: format!("failed to read confirmation: {e}"),
+                })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{PromptPolicy, confirm_with_reader};
+    use crate::ui::{PromptDefault, Ui};
+
+    #[test]
+    fn resolve_prefers_explicit_yes() {
+        assert_eq!(
+            PromptPolicy::resolve(true, true, false),
+            PromptPolicy::AssumeYes
+        );
+    }
+
+    #[test]
+    fn resolve_treats_closed_stdin_as_non_interactive() {
+        assert_eq!(
+            PromptPolicy::resolve(false, false, false),
+            PromptPolicy::NonInteractive
+        );
+        assert_eq!(
+            PromptPolicy::resolve(false, true, true),
+            PromptPolicy::NonInteractive
+        );
+        assert_eq!(
+            PromptPolicy::resolve(false, false, true),
+            PromptPolicy::Interactive
+        );
+    }
+
+    #[test]
+    fn non_interactive_fails_on_default_no_without_reading() {
+        let mut ui = Ui::with_writers(Vec::<u8>::new(), Vec::<u8>::new());
+        let mut input = Cursor::new("y\n");
+
+        let err = confirm_with_reader(
+            &mut ui,
+            "Continue?",
+            PromptDefault::No,
+            PromptPolicy::NonInteractive,
+            &mut input,
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("pass -y"));
+        assert_eq!(input.position(), 0);
+    }
+
+    #[test]
+    fn non_interactive_takes_default_yes() {
+        let mut ui = Ui::with_writers(Vec::<u8>::new(), Vec::<u8>::new());
+        let mut input = Cursor::new("");
+
+        assert!(
+            confirm_with_reader(
+                &mut ui,
+                "Initialize now?",
+                PromptDefault::Yes,
+                PromptPolicy::NonInteractive,
+                &mut input,
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn interactive_reads_answer_and_renders_suffix() {
+        let mut ui = Ui::with_writers(Vec::<u8>::new(), Vec::<u8>::new());
+        let mut input = Cursor::new("n\n");
+
+        let accepted = confirm_with_reader(
+            &mut ui,
+            "Continue?",
+            PromptDefault::Yes,
+            PromptPolicy::Interactive,
+            &mut input,
+        )
+        .unwrap();
+
+        assert!(!accepted);
+    }
+}
@@ -1,6 +1,7 @@
 use console::Style;
 use std::fmt::Display;
 use std::io::{self, BufRead, Write};
+use std::time::Duration;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PromptDefault {
@@ -76,20 +77,59 @@ pub struct Ui<O: Write, E: Write> {
     out: O,
     err: E,
     pub theme: UiTheme,
+    json_lines: bool,
+    heartbeat: Option<Duration>,
 }
 
-pub type StdUi = Ui<io::Stdout, io::Stderr>;
+/// Where [`StdUi`] writes its regular output: stdout, unless stdout is
+/// taken by `--output json-lines`.
+pub enum StdOut {
+    Stdout(io::Stdout),
+    Stderr(io::Stderr),
+}
+
+impl Write for StdOut {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout(out) => out.write(buf),
+            Self::Stderr(err) => err.write(buf),
+        }
+    }
 
-impl Ui<io::Stdout, io::Stderr> {
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(out) => out.flush(),
+            Self::Stderr(err) => err.flush(),
+        }
+    }
+}
+
+pub type StdUi = Ui<StdOut, io::Stderr>;
+
+impl Ui<StdOut, io::Stderr> {
     pub fn new() -> Self {
         Self::with_theme(UiTheme::default())
     }
 
     pub fn with_theme(theme: UiTheme) -> Self {
         Self {
-            out: io::stdout(),
+            out: StdOut::Stdout(io::stdout()),
             err: io::stderr(),
             theme,
+            json_lines: false,
+            heartbeat: None,
+        }
+    }
+
+    /// Everything human-readable goes to stderr, leaving stdout to the
+    /// events written with [`crate::events::emit`].
+    pub fn json_lines() -> Self {
+        Self {
+            out: StdOut::Stderr(io::stderr()),
+            err: io::stderr(),
+            theme: UiTheme::default(),
+            json_lines: true,
+            heartbeat: None,
         }
     }
 }
@@ -100,11 +140,34 @@ impl<O: Write, E: Write> Ui<O, E> {
             out,
             err,
             theme: UiTheme::default(),
+            json_lines: false,
+            heartbeat: None,
         }
     }
 
     pub fn with_theme_and_writers(theme: UiTheme, out: O, err: E) -> Self {
-        Self { out, err, theme }
+        Self {
+            out,
+            err,
+            theme,
+            json_lines: false,
+            heartbeat: None,
+        }
+    }
+
+    /// Whether commands should also emit JSON events on stdout.
+    pub fn is_json_lines(&self) -> bool {
+        self.json_lines
+    }
+
+    /// Print a progress summary this often during long operations, for
+    /// output nobody watches live; `None` (the default) never does.
+    pub fn set_heartbeat(&mut self, interval: Option<Duration>) {
+        self.heartbeat = interval;
+    }
+
+    pub fn heartbeat(&self) -> Option<Duration> {
+        self.heartbeat
     }
 
     pub fn heading(&mut self, message: impl Display) -> io::Result<()> {
@@ -232,7 +295,7 @@ impl<O: Write, E: Write> Ui<O, E> {
     }
 }
 
-impl Default for Ui<io::Stdout, io::Stderr> {
+impl Default for Ui<StdOut, io::Stderr> {
     fn default() -> Self {
         Self::new()
     }
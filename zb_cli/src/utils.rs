@@ -1,7 +1,15 @@
 use console::style;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use zb_io::Installer;
 
+/// The error for a failed write to the terminal.
+pub fn ui_error(err: std::io::Error) -> zb_core::Error {
+    zb_core::Error::FileError {
+        message: format!("failed to write CLI output: {err}"),
+    }
+}
+
 pub fn normalize_formula_name(name: &str) -> Result<String, zb_core::Error> {
     let trimmed = name.trim();
     if let Some(token) = trimmed.strip_prefix("cask:") {
@@ -10,6 +18,7 @@ pub fn normalize_formula_name(name: &str) -> Result<String, zb_core::Error> {
                 message: "cask token cannot be empty".to_string(),
             });
         }
+        zb_core::validate_formula_name(token)?;
         return Ok(trimmed.to_string());
     }
 
@@ -21,16 +30,20 @@ pub fn normalize_formula_name(name: &str) -> Result<String, zb_core::Error> {
         }
 
         if tap == "homebrew/core" {
+            zb_core::validate_formula_name(formula)?;
             return Ok(formula.to_string());
         }
 
         if tap == "homebrew/cask" {
+            zb_core::validate_formula_name(formula)?;
             return Ok(format!("cask:{formula}"));
         }
 
+        zb_core::validate_formula_name(trimmed)?;
         return Ok(trimmed.to_string());
     }
 
+    zb_core::validate_formula_name(trimmed)?;
     Ok(trimmed.to_string())
 }
 
@@ -119,33 +132,213 @@ pub fn suggest_homebrew(formula: &str, error: &zb_core::Error) {
     eprintln!();
 }
 
-pub fn get_root_path(cli_root: Option<PathBuf>) -> PathBuf {
-    if let Some(root) = cli_root {
-        return root;
-    }
+/// The root every platform used before XDG directories on Linux.
+const LEGACY_ROOT: &str = "/opt/zerobrew";
+
+/// Where a resolved path came from, as `zb info --system` reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathSource {
+    Flag,
+    Env,
+    /// Recorded in the root's database by `zb relocate`.
+    Config,
+    /// Left at an old default by an earlier version and kept, so an
+    /// existing install isn't silently replaced by an empty one.
+    Existing,
+    Default,
+}
 
-    if let Ok(env_root) = std::env::var("ZEROBREW_ROOT") {
-        return PathBuf::from(env_root);
+impl PathSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Flag => "flag",
+            Self::Env => "env",
+            Self::Config => "config",
+            Self::Existing => "existing",
+            Self::Default => "default",
+        }
     }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedPath {
+    pub path: PathBuf,
+    pub source: PathSource,
+}
 
-    let legacy_root = PathBuf::from("/opt/zerobrew");
-    if legacy_root.exists() {
-        return legacy_root;
+impl ResolvedPath {
+    fn new(path: impl Into<PathBuf>, source: PathSource) -> Self {
+        Self {
+            path: path.into(),
+            source,
+        }
     }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZbPaths {
+    pub root: ResolvedPath,
+    pub prefix: ResolvedPath,
+    pub cache: ResolvedPath,
+}
+
+/// Everything path resolution reads besides the flags, so every
+/// combination can be tested without touching the environment or disk.
+pub struct PathInputs<'a> {
+    pub env: &'a HashMap<String, String>,
+    pub macos: bool,
+    pub exists: &'a dyn Fn(&Path) -> bool,
+    /// The prefix `zb relocate` recorded for a root.
+    pub recorded_prefix: &'a dyn Fn(&Path) -> Option<PathBuf>,
+}
+
+/// Resolve the root, prefix and cache for this process. Flags win over
+/// `ZEROBREW_ROOT` / `ZEROBREW_PREFIX`, which win over anything recorded or
+/// defaulted.
+pub fn resolve_paths_from_env(cli_root: Option<PathBuf>, cli_prefix: Option<PathBuf>) -> ZbPaths {
+    let env: HashMap<String, String> = std::env::vars().collect();
+    resolve_paths(
+        cli_root,
+        cli_prefix,
+        &PathInputs {
+            env: &env,
+            macos: cfg!(target_os = "macos"),
+            exists: &|path| path.exists(),
+            recorded_prefix: &recorded_prefix,
+        },
+    )
+}
 
-    if cfg!(target_os = "macos") {
-        legacy_root
+/// On Linux the root defaults to `$XDG_DATA_HOME/zerobrew` and the cache to
+/// `$XDG_CACHE_HOME/zerobrew`; macOS keeps `/opt/zerobrew` with the cache
+/// inside it. A root at `/opt/zerobrew` from before XDG support, or a cache
+/// already inside the root, keeps being used.
+pub fn resolve_paths(
+    cli_root: Option<PathBuf>,
+    cli_prefix: Option<PathBuf>,
+    inputs: &PathInputs,
+) -> ZbPaths {
+    let var = |name: &str| inputs.env.get(name).filter(|value| !value.is_empty());
+    let home = var("HOME").map(PathBuf::from);
+
+    let root = if let Some(root) = cli_root {
+        ResolvedPath::new(root, PathSource::Flag)
+    } else if let Some(root) = var("ZEROBREW_ROOT") {
+        ResolvedPath::new(root, PathSource::Env)
+    } else if inputs.macos {
+        ResolvedPath::new(LEGACY_ROOT, PathSource::Default)
+    } else if (inputs.exists)(Path::new(LEGACY_ROOT)) {
+        ResolvedPath::new(LEGACY_ROOT, PathSource::Existing)
     } else {
-        let xdg_data_home = std::env::var("XDG_DATA_HOME")
-            .ok()
+        let data_home = var("XDG_DATA_HOME")
             .map(PathBuf::from)
-            .unwrap_or_else(|| {
-                std::env::var("HOME")
-                    .map(|h| PathBuf::from(h).join(".local").join("share"))
-                    .unwrap_or_else(|_| legacy_root.clone())
-            });
-        xdg_data_home.join("zerobrew")
+            .or_else(|| home.as_ref().map(|home| home.join(".local/share")));
+        match data_home {
+            Some(dir) => ResolvedPath::new(dir.join("zerobrew"), PathSource::Default),
+            None => ResolvedPath::new(LEGACY_ROOT, PathSource::Default),
+        }
+    };
+
+    let prefix = if let Some(prefix) = cli_prefix {
+        ResolvedPath::new(prefix, PathSource::Flag)
+    } else if let Some(prefix) = var("ZEROBREW_PREFIX") {
+        ResolvedPath::new(prefix, PathSource::Env)
+    } else if let Some(prefix) = (inputs.recorded_prefix)(&root.path) {
+        ResolvedPath::new(prefix, PathSource::Config)
+    } else if inputs.macos {
+        // Mach-O binaries have fixed-size path fields, so the prefix must
+        // be no longer than Homebrew's (/opt/homebrew = 13 chars). The root
+        // itself (/opt/zerobrew = 13 chars) stays within that limit.
+        ResolvedPath::new(root.path.clone(), PathSource::Default)
+    } else {
+        ResolvedPath::new(root.path.join("prefix"), PathSource::Default)
+    };
+
+    // An explicit root keeps everything, the cache included, under it.
+    let in_root = root.path.join("cache");
+    let cache = if matches!(root.source, PathSource::Flag | PathSource::Env) {
+        ResolvedPath::new(in_root, root.source)
+    } else if (inputs.exists)(&in_root.join("api-cache.sqlite")) {
+        ResolvedPath::new(in_root, PathSource::Existing)
+    } else if inputs.macos {
+        ResolvedPath::new(in_root, PathSource::Default)
+    } else {
+        let cache_home = var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|home| home.join(".cache")));
+        match cache_home {
+            Some(dir) => ResolvedPath::new(dir.join("zerobrew"), PathSource::Default),
+            None => ResolvedPath::new(in_root, PathSource::Default),
+        }
+    };
+
+    ZbPaths {
+        root,
+        prefix,
+        cache,
+    }
+}
+
+/// The prefix `zb relocate` last moved the root at `root` to, if any.
+pub fn recorded_prefix(root: &Path) -> Option<PathBuf> {
+    let db_path = root.join("db/zb.sqlite3");
+    if !db_path.exists() {
+        return None;
     }
+    zb_io::Database::open(&db_path)
+        .ok()?
+        .recorded_prefix()
+        .ok()
+        .flatten()
+}
+
+/// One-time notice shown when a root was last used by a different zb.
+pub fn version_change_notice(previous: &str, current: &str) -> String {
+    let parse = |v: &str| -> Vec<u64> {
+        v.split(['.', '-'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    let direction = if parse(current) < parse(previous) {
+        "downgraded"
+    } else {
+        "upgraded"
+    };
+    format!("zerobrew was {direction} from {previous} to {current} since this root was last used.")
+}
+
+/// Why zb won't change anything when installed kegs were patched for a
+/// prefix other than `prefix`, and how to fix it; `None` when none were.
+pub fn prefix_drift_message(drifted: &[zb_io::DriftedKeg], prefix: &Path) -> Option<String> {
+    const SHOWN: usize = 5;
+
+    let old = &drifted.first()?.patched_prefix;
+    let mut names: Vec<&str> = drifted
+        .iter()
+        .take(SHOWN)
+        .map(|keg| keg.name.as_str())
+        .collect();
+    let more = format!("{} more", drifted.len().saturating_sub(SHOWN));
+    if drifted.len() > SHOWN {
+        names.push(&more);
+    }
+    let (count, verb) = match drifted.len() {
+        1 => ("1 installed formula".to_string(), "was"),
+        n => (format!("{n} installed formulas"), "were"),
+    };
+
+    Some(format!(
+        "{count} ({}) {verb} patched for prefix {}, but zb is using {}; \
+         they won't work from there.\n\
+         To move them, run 'zb --prefix {} relocate --prefix {}'. \
+         To keep using them, set ZEROBREW_PREFIX={} again.",
+        names.join(", "),
+        old.display(),
+        prefix.display(),
+        old.display(),
+        prefix.display(),
+        old.display()
+    ))
 }
 
 #[cfg(test)]
@@ -160,10 +353,202 @@ mod tests {
     use zb_io::storage::{BlobCache, Database, Store};
     use zb_io::{Installer, Linker};
 
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
     use super::{
-        format_formula_suggestions, normalize_formula_name, suggest_missing_formula_matches,
+        PathInputs, PathSource, ResolvedPath, ZbPaths, format_formula_suggestions,
+        normalize_formula_name, prefix_drift_message, resolve_paths,
+        suggest_missing_formula_matches, version_change_notice,
     };
 
+    /// Resolve with no flags, `env`, and only `existing` on disk.
+    fn resolve(macos: bool, env: &[(&str, &str)], existing: &[&str]) -> ZbPaths {
+        resolve_with(None, None, macos, env, existing, None)
+    }
+
+    fn resolve_with(
+        root: Option<&str>,
+        prefix: Option<&str>,
+        macos: bool,
+        env: &[(&str, &str)],
+        existing: &[&str],
+        recorded: Option<&str>,
+    ) -> ZbPaths {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        resolve_paths(
+            root.map(PathBuf::from),
+            prefix.map(PathBuf::from),
+            &PathInputs {
+                env: &env,
+                macos,
+                exists: &|path| existing.iter().any(|e| Path::new(e) == path),
+                recorded_prefix: &|_| recorded.map(PathBuf::from),
+            },
+        )
+    }
+
+    fn at(path: &str, source: PathSource) -> ResolvedPath {
+        ResolvedPath {
+            path: PathBuf::from(path),
+            source,
+        }
+    }
+
+    #[test]
+    fn linux_defaults_follow_xdg() {
+        let paths = resolve(
+            false,
+            &[
+                ("HOME", "/home/u"),
+                ("XDG_DATA_HOME", "/data"),
+                ("XDG_CACHE_HOME", "/cache"),
+            ],
+            &[],
+        );
+        assert_eq!(paths.root, at("/data/zerobrew", PathSource::Default));
+        assert_eq!(
+            paths.prefix,
+            at("/data/zerobrew/prefix", PathSource::Default)
+        );
+        assert_eq!(paths.cache, at("/cache/zerobrew", PathSource::Default));
+    }
+
+    #[test]
+    fn linux_falls_back_to_home_and_ignores_empty_xdg() {
+        let paths = resolve(
+            false,
+            &[
+                ("HOME", "/home/u"),
+                ("XDG_DATA_HOME", ""),
+                ("XDG_CACHE_HOME", ""),
+            ],
+            &[],
+        );
+        assert_eq!(
+            paths.root,
+            at("/home/u/.local/share/zerobrew", PathSource::Default)
+        );
+        assert_eq!(
+            paths.cache,
+            at("/home/u/.cache/zerobrew", PathSource::Default)
+        );
+
+        let homeless = resolve(false, &[], &[]);
+        assert_eq!(homeless.root, at("/opt/zerobrew", PathSource::Default));
+        assert_eq!(
+            homeless.cache,
+            at("/opt/zerobrew/cache", PathSource::Default)
+        );
+    }
+
+    #[test]
+    fn linux_keeps_an_existing_old_default_root() {
+        let paths = resolve(
+            false,
+            &[("HOME", "/home/u")],
+            &["/opt/zerobrew", "/opt/zerobrew/cache/api-cache.sqlite"],
+        );
+        assert_eq!(paths.root, at("/opt/zerobrew", PathSource::Existing));
+        assert_eq!(
+            paths.prefix,
+            at("/opt/zerobrew/prefix", PathSource::Default)
+        );
+        assert_eq!(paths.cache, at("/opt/zerobrew/cache", PathSource::Existing));
+
+        // A root that never cached anything moves its cache to XDG.
+        let fresh = resolve(false, &[("HOME", "/home/u")], &["/opt/zerobrew"]);
+        assert_eq!(
+            fresh.cache,
+            at("/home/u/.cache/zerobrew", PathSource::Default)
+        );
+    }
+
+    #[test]
+    fn macos_keeps_opt_zerobrew_and_ignores_xdg() {
+        let paths = resolve(
+            true,
+            &[
+                ("HOME", "/Users/u"),
+                ("XDG_DATA_HOME", "/data"),
+                ("XDG_CACHE_HOME", "/cache"),
+            ],
+            &[],
+        );
+        assert_eq!(paths.root, at("/opt/zerobrew", PathSource::Default));
+        assert_eq!(paths.prefix, at("/opt/zerobrew", PathSource::Default));
+        assert_eq!(paths.cache, at("/opt/zerobrew/cache", PathSource::Default));
+    }
+
+    #[test]
+    fn env_beats_defaults_and_flags_beat_env() {
+        let env = [
+            ("HOME", "/home/u"),
+            ("XDG_CACHE_HOME", "/cache"),
+            ("ZEROBREW_ROOT", "/env/root"),
+            ("ZEROBREW_PREFIX", "/env/prefix"),
+        ];
+        for macos in [false, true] {
+            let from_env = resolve_with(None, None, macos, &env, &["/opt/zerobrew"], Some("/rec"));
+            assert_eq!(from_env.root, at("/env/root", PathSource::Env));
+            assert_eq!(from_env.prefix, at("/env/prefix", PathSource::Env));
+            assert_eq!(from_env.cache, at("/env/root/cache", PathSource::Env));
+
+            let from_flags = resolve_with(
+                Some("/f/root"),
+                Some("/f/prefix"),
+                macos,
+                &env,
+                &[],
+                Some("/rec"),
+            );
+            assert_eq!(from_flags.root, at("/f/root", PathSource::Flag));
+            assert_eq!(from_flags.prefix, at("/f/prefix", PathSource::Flag));
+            assert_eq!(from_flags.cache, at("/f/root/cache", PathSource::Flag));
+        }
+    }
+
+    #[test]
+    fn recorded_prefix_beats_the_default_but_not_env() {
+        let recorded = resolve_with(None, None, false, &[("HOME", "/h")], &[], Some("/moved"));
+        assert_eq!(recorded.prefix, at("/moved", PathSource::Config));
+
+        let env = [("HOME", "/h"), ("ZEROBREW_PREFIX", "/env")];
+        let overridden = resolve_with(None, None, false, &env, &[], Some("/moved"));
+        assert_eq!(overridden.prefix, at("/env", PathSource::Env));
+    }
+
+    #[test]
+    fn prefix_drift_message_names_kegs_and_the_fix() {
+        let drifted: Vec<zb_io::DriftedKeg> = ["a", "b", "c", "d", "e", "f", "g"]
+            .into_iter()
+            .map(|name| zb_io::DriftedKeg {
+                name: name.to_string(),
+                version: "1.0".to_string(),
+                patched_prefix: PathBuf::from("/old"),
+            })
+            .collect();
+
+        assert_eq!(prefix_drift_message(&[], Path::new("/new")), None);
+
+        let one = prefix_drift_message(&drifted[..1], Path::new("/new")).unwrap();
+        assert!(one.starts_with("1 installed formula (a) was patched for prefix /old"));
+
+        let many = prefix_drift_message(&drifted, Path::new("/new")).unwrap();
+        assert!(many.starts_with("7 installed formulas (a, b, c, d, e, 2 more) were"));
+        assert!(many.contains("'zb --prefix /old relocate --prefix /new'"));
+        assert!(many.contains("ZEROBREW_PREFIX=/old"));
+    }
+
+    #[test]
+    fn version_change_notice_names_direction() {
+        assert!(version_change_notice("0.2.1", "0.10.0").contains("upgraded from 0.2.1"));
+        assert!(version_change_notice("0.3.0", "0.2.9").contains("downgraded from 0.3.0"));
+    }
+
     #[test]
     fn normalize_core_tap_formula() {
         assert_eq!(
@@ -188,6 +573,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn normalize_rejects_invalid_characters() {
+        let err = normalize_formula_name("jq;rm").unwrap_err();
+        assert!(matches!(err, zb_core::Error::InvalidArgument { .. }));
+        assert!(normalize_formula_name("homebrew/core/../jq").is_err());
+        assert!(normalize_formula_name("cask:bad token").is_err());
+    }
+
     #[test]
     fn format_formula_suggestions_renders_list() {
         let rendered =
@@ -241,7 +634,7 @@ mod tests {
             linker,
             db,
             prefix,
-            root.join("locks"),
+            root.to_path_buf(),
         );
 
         let error = zb_core::Error::MissingFormula {
@@ -251,6 +644,55 @@ mod tests {
         assert!(suggest_missing_formula_matches(&installer, &error).await);
     }
 
+    #[tokio::test]
+    async fn missing_formula_404_produces_ranked_suggestions() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        Mock::given(method("GET"))
+            .and(path("/formula/jqq.json"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"[
+                    {"name":"jq"},
+                    {"name":"yq"},
+                    {"name":"wget"}
+                ]"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let api_client =
+            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
+        let installer = Installer::new(
+            api_client,
+            BlobCache::new(&root.join("cache")).unwrap(),
+            Store::new(&root).unwrap(),
+            Cellar::new(&root).unwrap(),
+            Linker::new(&prefix).unwrap(),
+            Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+            prefix,
+            root.to_path_buf(),
+        );
+
+        let name = normalize_formula_name("jqq").unwrap();
+        let error = installer.plan(&[name]).await.unwrap_err();
+        assert!(matches!(error, zb_core::Error::MissingFormula { .. }));
+
+        let suggestions = installer.suggest_formulas("jqq", 3).await.unwrap();
+        assert_eq!(suggestions.first().map(String::as_str), Some("jq"));
+        assert!(!suggestions.contains(&"wget".to_string()));
+        assert!(suggest_missing_formula_matches(&installer, &error).await);
+    }
+
     #[tokio::test]
     async fn suggest_missing_formula_matches_returns_false_for_non_missing_errors() {
         let tmp = TempDir::new().unwrap();
@@ -272,7 +714,7 @@ mod tests {
             linker,
             db,
             prefix,
-            root.join("locks"),
+            root.to_path_buf(),
         );
 
         let error = zb_core::Error::InvalidArgument {
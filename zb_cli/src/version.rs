@@ -0,0 +1,97 @@
+//! What `zb --version --verbose` and `zb doctor` report about the build,
+//! for bug reports.
+
+use std::ffi::OsStr;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const COMMIT: &str = env!("ZB_GIT_COMMIT");
+pub const BUILD_DATE: &str = env!("ZB_BUILD_DATE");
+pub const TARGET: &str = env!("ZB_TARGET");
+
+/// Whether the arguments after the program name are `--version` (or `-V`)
+/// with `--verbose` (or `-v`) and nothing else. Clap prints the plain
+/// version and exits before it would see `--verbose`, so this is checked
+/// first.
+pub fn wants_verbose_version<I, S>(args: I) -> bool
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let (mut version, mut verbose) = (false, false);
+    for arg in args {
+        match arg.as_ref().to_str() {
+            Some("--version" | "-V") => version = true,
+            Some("--verbose") => verbose = true,
+            Some(flags)
+                if flags.len() > 1 && flags.starts_with('-') && !flags.starts_with("--") =>
+            {
+                for flag in flags[1..].chars() {
+                    match flag {
+                        'V' => version = true,
+                        'v' => verbose = true,
+                        _ => return false,
+                    }
+                }
+            }
+            _ => return false,
+        }
+    }
+    version && verbose
+}
+
+/// `zb 0.2.1` followed by the build details and the bottle tags this
+/// machine asks for, most preferred first.
+pub fn verbose_version(bottle_tags: &[String]) -> String {
+    format!(
+        "zb {VERSION}\ncommit: {COMMIT}\nbuilt: {BUILD_DATE}\ntarget: {TARGET}\nbottle tags: {}",
+        tag_chain(bottle_tags)
+    )
+}
+
+/// One line for the top of `zb doctor`.
+pub fn build_summary(bottle_tags: &[String]) -> String {
+    format!(
+        "zb {VERSION} ({COMMIT}, built {BUILD_DATE}) for {TARGET}; bottle tags: {}",
+        tag_chain(bottle_tags)
+    )
+}
+
+/// The tags in order of preference, ending with `all`, which fits every
+/// machine.
+fn tag_chain(bottle_tags: &[String]) -> String {
+    bottle_tags
+        .iter()
+        .map(String::as_str)
+        .chain(["all"])
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbose_version_needs_both_flags_and_nothing_else() {
+        assert!(wants_verbose_version(["--version", "--verbose"]));
+        assert!(wants_verbose_version(["-v", "-V"]));
+        assert!(wants_verbose_version(["-vV"]));
+        assert!(!wants_verbose_version(["--version"]));
+        assert!(!wants_verbose_version(["-v", "list"]));
+        assert!(!wants_verbose_version([
+            "--version",
+            "--verbose",
+            "install"
+        ]));
+        assert!(!wants_verbose_version(["-vq", "--version"]));
+    }
+
+    #[test]
+    fn verbose_version_lists_the_tag_chain() {
+        let tags = ["arm64_sequoia".to_string(), "arm64_sonoma".to_string()];
+        let version = verbose_version(&tags);
+        assert!(version.starts_with(&format!("zb {VERSION}\ncommit: ")));
+        assert!(version.ends_with("bottle tags: arm64_sequoia, arm64_sonoma, all"));
+        assert!(build_summary(&[]).ends_with("bottle tags: all"));
+    }
+}
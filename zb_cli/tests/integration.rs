@@ -1,5 +1,7 @@
+use std::io::Write;
 use std::path::PathBuf;
-use std::process::{Command, Output};
+use std::process::{Command, Output, Stdio};
+use std::time::Duration;
 
 struct TestEnv {
     root: tempfile::TempDir,
@@ -39,6 +41,29 @@ impl TestEnv {
             .unwrap_or_else(|_| panic!("failed to execute {zb} command"))
     }
 
+    fn zb_with_stdin(&self, args: &[&str], input: &str) -> Output {
+        let zb = env!("CARGO_BIN_EXE_zb");
+        let mut child = Command::new(zb)
+            .env("ZEROBREW_ROOT", self.root.path())
+            .env("ZEROBREW_PREFIX", self.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|_| panic!("failed to execute {zb} command"));
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child
+            .wait_with_output()
+            .unwrap_or_else(|_| panic!("failed to wait for {zb} command"))
+    }
+
     fn bin_dir(&self) -> PathBuf {
         self.prefix().join("bin")
     }
@@ -278,3 +303,1248 @@ fn test_gc_removes_unused_store_entries() {
     assert_success(&t.zb(&["gc"]), "zb gc");
     assert_eq!(t.count_store_entries(), 0);
 }
+
+#[test]
+#[ignore = "integration test"]
+fn test_gc_aggressive_leaves_only_installed_kegs() {
+    let t = TestEnv::new();
+    assert_success(&t.zb(&["install", "jq"]), "zb install jq");
+    assert_success(&t.zb(&["uninstall", "jq"]), "zb uninstall jq");
+
+    let root = t.root.path();
+    let names = |dir: &str| -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(root.join(dir))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+    std::fs::write(root.join("cache/tmp/.tmpPartial"), b"half a download").unwrap();
+    std::fs::create_dir_all(t.prefix().join("Cellar/oniguruma/0.0.1")).unwrap();
+    let blobs_before = names("cache/blobs");
+    assert!(!blobs_before.is_empty());
+
+    let dry_run = t.zb(&["gc", "--aggressive", "--dry-run"]);
+    assert_success(&dry_run, "zb gc --aggressive --dry-run");
+    assert_stdout_contains(&dry_run, "Would reclaim");
+    assert_eq!(names("cache/blobs"), blobs_before);
+    assert!(t.prefix().join("Cellar/oniguruma/0.0.1").exists());
+
+    assert_success(&t.zb(&["gc", "--aggressive"]), "zb gc --aggressive");
+
+    let remaining_blobs = names("cache/blobs");
+    assert_eq!(remaining_blobs.len(), 1, "only oniguruma's bottle stays");
+    assert_eq!(
+        names("store"),
+        [remaining_blobs[0].trim_end_matches(".tar.gz").to_string()]
+    );
+    assert!(names("cache/tmp").is_empty());
+    assert!(!t.prefix().join("Cellar/oniguruma/0.0.1").exists());
+    assert!(!t.prefix().join("Cellar/jq").exists());
+    assert!(t.prefix().join("opt/oniguruma").exists());
+}
+
+#[test]
+#[ignore = "integration test"]
+fn test_relocate_moves_install_to_new_prefix() {
+    let t = TestEnv::new();
+    // Same length as the original prefix, so paths compiled into binaries
+    // can be rewritten in place.
+    let new_prefix_dir = tempfile::Builder::new()
+        .prefix("zb")
+        .rand_bytes(3)
+        .tempdir_in("/tmp")
+        .expect("failed to create second prefix temp dir");
+    let new_prefix = new_prefix_dir.path();
+
+    assert_success(&t.zb(&["install", "jq"]), "zb install jq");
+    assert_success(
+        &t.zb(&[
+            "--yes",
+            "relocate",
+            "--prefix",
+            new_prefix.to_str().unwrap(),
+        ]),
+        "zb relocate",
+    );
+
+    let output = Command::new(new_prefix.join("bin/jq"))
+        .arg("--version")
+        .output()
+        .expect("failed to run relocated jq");
+    assert_success(&output, "relocated jq --version");
+    assert!(!t.prefix().join("Cellar/jq").exists());
+    assert!(!t.bin_dir().join("jq").exists());
+}
+
+#[test]
+fn test_verbose_version_reports_build_and_bottle_tags() {
+    let t = TestEnv::new();
+
+    let output = t.zb(&["--version", "--verbose"]);
+    assert_success(&output, "zb --version --verbose");
+    assert_stdout_contains(
+        &output,
+        &format!("zb {}\ncommit: ", env!("CARGO_PKG_VERSION")),
+    );
+    assert_stdout_contains(&output, "\nbuilt: ");
+    assert_stdout_contains(&output, "\ntarget: ");
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    assert_stdout_contains(&output, "bottle tags: x86_64_linux, all");
+
+    let output = t.zb(&["--version"]);
+    assert_success(&output, "zb --version");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        format!("zb {}", env!("CARGO_PKG_VERSION"))
+    );
+}
+
+#[test]
+fn test_list_and_info_leave_an_uninitialized_root_alone() {
+    let t = TestEnv::new();
+    let root = t.root.path().join("zerobrew");
+    let prefix = t.prefix().join("prefix");
+    let zb = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_zb"))
+            .env("ZEROBREW_ROOT", &root)
+            .env("ZEROBREW_PREFIX", &prefix)
+            .env_remove("ZEROBREW_AUTO_INIT")
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .expect("failed to execute zb")
+    };
+
+    let output = zb(&["list"]);
+    assert_success(&output, "zb list");
+    assert_stdout_contains(&output, "No formulas installed.");
+
+    let output = zb(&["info", "jq"]);
+    assert_success(&output, "zb info jq");
+    assert_stdout_contains(&output, "not installed");
+
+    assert!(!root.exists(), "zb list created {}", root.display());
+    assert!(!prefix.exists(), "zb list created {}", prefix.display());
+    assert_eq!(std::fs::read_dir(t.root.path()).unwrap().count(), 0);
+}
+
+#[test]
+#[ignore = "integration test"]
+fn test_install_reads_names_from_stdin() {
+    let t = TestEnv::new();
+
+    let output = t.zb_with_stdin(
+        &["install", "-"],
+        "jq
+tree jq
+",
+    );
+    assert_success(&output, "zb install -");
+
+    assert!(t.bin_dir().join("jq").exists());
+    assert!(t.bin_dir().join("tree").exists());
+}
+
+#[test]
+fn test_install_with_empty_stdin_fails_without_initializing() {
+    let t = TestEnv::new();
+
+    let output = t.zb_with_stdin(&["install", "-"], "\n  \n");
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("stdin has no formula names"),
+        "unexpected stderr: {stderr}"
+    );
+    assert!(!t.root.path().join("db").exists());
+}
+
+#[test]
+fn test_install_reports_every_invalid_name_from_stdin() {
+    let t = TestEnv::new();
+
+    let output = t.zb_with_stdin(&["install", "-"], "jq\njq!\n../etc/passwd\n");
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'jq!'"), "unexpected stderr: {stderr}");
+    assert!(
+        stderr.contains("'../etc/passwd'"),
+        "unexpected stderr: {stderr}"
+    );
+    assert!(stderr.contains("2 formula names are invalid"));
+    assert!(!t.root.path().join("db").exists());
+}
+
+#[test]
+fn test_reset_with_closed_stdin_fails_instead_of_prompting() {
+    let t = TestEnv::new();
+    let started = std::time::Instant::now();
+
+    let output = t.zb(&["reset"]);
+
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("confirmation required"),
+        "unexpected stderr: {stderr}"
+    );
+    assert!(t.root.path().exists());
+}
+
+/// A gzipped bottle for `name` 1.0.0 holding one executable and an empty
+/// file at each of `extra_files` (relative to the keg).
+fn mock_bottle(name: &str, extra_files: &[&str]) -> Vec<u8> {
+    mock_bottle_with_script(name, &format!("#!/bin/sh\necho {name}\n"), extra_files)
+}
+
+/// Like [`mock_bottle`], with `content` as the executable.
+fn mock_bottle_with_script(name: &str, content: &str, extra_files: &[&str]) -> Vec<u8> {
+    versioned_mock_bottle(name, "1.0.0", content, extra_files)
+}
+
+/// Like [`mock_bottle_with_script`], for `name` at `version`.
+fn versioned_mock_bottle(
+    name: &str,
+    version: &str,
+    content: &str,
+    extra_files: &[&str],
+) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header
+        .set_path(format!("{name}/{version}/bin/{name}"))
+        .unwrap();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o755);
+    header.set_cksum();
+    builder.append(&header, content.as_bytes()).unwrap();
+    for file in extra_files {
+        let mut header = tar::Header::new_gnu();
+        header.set_path(format!("{name}/{version}/{file}")).unwrap();
+        header.set_size(0);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, std::io::empty()).unwrap();
+    }
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+    encoder.finish().unwrap()
+}
+
+async fn mount_mock_formula(server: &wiremock::MockServer, name: &str, deps: &[&str]) {
+    mount_mock_formula_with_files(server, name, deps, &[]).await;
+}
+
+async fn mount_mock_formula_with_files(
+    server: &wiremock::MockServer,
+    name: &str,
+    deps: &[&str],
+    extra_files: &[&str],
+) {
+    mount_throttled_mock_formula(server, name, deps, extra_files, Duration::ZERO).await;
+}
+
+/// Serve `name`'s bottle only after `delay`.
+async fn mount_throttled_mock_formula(
+    server: &wiremock::MockServer,
+    name: &str,
+    deps: &[&str],
+    extra_files: &[&str],
+    delay: Duration,
+) {
+    let bottle = mock_bottle(name, extra_files);
+    mount_mock_bottle(server, name, deps, bottle, delay).await;
+}
+
+async fn mount_mock_bottle(
+    server: &wiremock::MockServer,
+    name: &str,
+    deps: &[&str],
+    bottle: Vec<u8>,
+    delay: Duration,
+) {
+    mount_versioned_mock_bottle(server, name, "1.0.0", deps, bottle, delay).await;
+}
+
+/// Serve `name` at `version` with `bottle`.
+async fn mount_versioned_mock_bottle(
+    server: &wiremock::MockServer,
+    name: &str,
+    version: &str,
+    deps: &[&str],
+    bottle: Vec<u8>,
+    delay: Duration,
+) {
+    use sha2::{Digest, Sha256};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    let tag = if cfg!(target_os = "linux") {
+        "x86_64_linux"
+    } else if cfg!(target_arch = "x86_64") {
+        "sonoma"
+    } else {
+        "arm64_sonoma"
+    };
+    let sha256 = format!("{:x}", Sha256::digest(&bottle));
+    let json = serde_json::json!({
+        "name": name,
+        "versions": { "stable": version },
+        "dependencies": deps,
+        "license": "MIT",
+        "bottle": { "stable": { "files": { tag: {
+            "url": format!("{}/bottles/{name}-{version}.{tag}.bottle.tar.gz", server.uri()),
+            "sha256": sha256,
+        } } } }
+    });
+    Mock::given(method("GET"))
+        .and(path(format!("/formula/{name}.json")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json))
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/bottles/{name}-{version}.{tag}.bottle.tar.gz"
+        )))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_bytes(bottle)
+                .set_delay(delay),
+        )
+        .mount(server)
+        .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_install_json_lines_stream_reconstructs_the_timeline() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "evtlib", &[]).await;
+    mount_mock_formula(&server, "evtapp", &["evtlib"]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+    command
+        .env("ZEROBREW_ROOT", t.root.path())
+        .env("ZEROBREW_PREFIX", t.prefix())
+        .env("ZEROBREW_AUTO_INIT", "true")
+        .env("ZEROBREW_API_URL", &api_url)
+        .env("HOME", home.path())
+        .args(["install", "evtapp", "--output", "json-lines"]);
+    let output = tokio::task::spawn_blocking(move || command.output().unwrap())
+        .await
+        .unwrap();
+    assert_success(&output, "zb install --output json-lines");
+
+    let events: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("every stdout line is a JSON event"))
+        .collect();
+    let timeline = |name: &str| -> Vec<String> {
+        events
+            .iter()
+            .filter(|event| event["name"] == name)
+            .map(|event| event["event"].as_str().unwrap().to_string())
+            .filter(|kind| !kind.ends_with("-progress") && kind != "unpack-phase")
+            .collect()
+    };
+
+    assert_eq!(events[0]["event"], "plan-resolved");
+    let planned: Vec<&str> = events[0]["formulas"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|formula| formula["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(planned, ["evtlib", "evtapp"]);
+    for name in ["evtlib", "evtapp"] {
+        assert_eq!(
+            timeline(name),
+            [
+                "download-started",
+                "download-completed",
+                "unpack-started",
+                "unpack-completed",
+                "link-started",
+                "link-completed",
+                "install-completed",
+                "formula-done",
+            ],
+            "timeline for {name}"
+        );
+    }
+    let done = events.last().unwrap();
+    assert_eq!(done["event"], "formula-done");
+    assert_eq!(done["status"], "installed");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Installed 2 packages"), "stderr: {stderr}");
+    assert!(t.bin_dir().join("evtapp").exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_run_with_composes_path_and_build_flags_without_linking() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula_with_files(&server, "toolone", &[], &["include/toolone.h"]).await;
+    mount_mock_formula_with_files(&server, "tooltwo", &[], &["include/tooltwo.h"]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let script = home.path().join("check.sh");
+    std::fs::write(
+        &script,
+        "#!/bin/sh\n\
+         set -e\n\
+         test \"$(toolone)\" = toolone\n\
+         test \"$(tooltwo)\" = tooltwo\n\
+         echo \"PATH=$PATH\"\n\
+         echo \"CPPFLAGS=$CPPFLAGS\"\n",
+    )
+    .unwrap();
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+    command
+        .env("ZEROBREW_ROOT", t.root.path())
+        .env("ZEROBREW_PREFIX", t.prefix())
+        .env("ZEROBREW_AUTO_INIT", "true")
+        .env("ZEROBREW_API_URL", format!("{}/formula", server.uri()))
+        .env("HOME", home.path())
+        .env_remove("CPPFLAGS")
+        .args(["run", "--with", "toolone,tooltwo", "--", "sh"])
+        .arg(&script);
+    let output = tokio::task::spawn_blocking(move || command.output().unwrap())
+        .await
+        .unwrap();
+    assert_success(&output, "zb run --with");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let value = |key: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{key}=")))
+            .unwrap_or_else(|| panic!("{key} missing from: {stdout}"))
+            .to_string()
+    };
+    let path = value("PATH");
+    let entries: Vec<&str> = path.split(':').collect();
+    let position = |needle: &str| {
+        entries
+            .iter()
+            .position(|entry| entry.ends_with(needle))
+            .unwrap_or_else(|| panic!("{needle} not on PATH: {path}"))
+    };
+    let prefix_bin = t.bin_dir().display().to_string();
+    assert!(position("toolone/1.0.0/bin") < position("tooltwo/1.0.0/bin"));
+    assert!(position("tooltwo/1.0.0/bin") < position(&prefix_bin));
+
+    let flags: Vec<String> = value("CPPFLAGS").split(' ').map(str::to_string).collect();
+    assert_eq!(flags.len(), 2, "CPPFLAGS: {flags:?}");
+    assert!(flags[0].starts_with("-I") && flags[0].ends_with("toolone/1.0.0/include"));
+    assert!(flags[1].starts_with("-I") && flags[1].ends_with("tooltwo/1.0.0/include"));
+
+    assert!(!t.bin_dir().join("toolone").exists());
+    assert!(!t.bin_dir().join("tooltwo").exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_changed_prefix_is_refused_unless_accepted() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "driftone", &[]).await;
+    mount_mock_formula(&server, "drifttwo", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let other_prefix = tempfile::Builder::new()
+        .prefix("zb")
+        .rand_bytes(3)
+        .tempdir_in("/tmp")
+        .unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |prefix: PathBuf, args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", prefix)
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(t.prefix(), &["install", "driftone"]).await.unwrap();
+    assert_success(&output, "zb install under the original prefix");
+
+    let moved = other_prefix.path().to_path_buf();
+    let output = zb(moved.clone(), &["install", "drifttwo"]).await.unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "this root's formulas are installed under prefix {}, but zb is using {}",
+            t.prefix().display(),
+            moved.display()
+        )),
+        "stderr: {stderr}"
+    );
+    assert!(
+        stderr.contains("--accept-prefix-change"),
+        "stderr: {stderr}"
+    );
+    assert!(!moved.join("Cellar/drifttwo").exists());
+
+    // Accepting the change still leaves kegs patched for the old prefix alone.
+    let output = zb(
+        moved.clone(),
+        &["--accept-prefix-change", "install", "drifttwo"],
+    )
+    .await
+    .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "1 installed formula (driftone) was patched for prefix {}",
+            t.prefix().display()
+        )),
+        "stderr: {stderr}"
+    );
+    assert!(stderr.contains("relocate --prefix"), "stderr: {stderr}");
+    assert!(!moved.join("Cellar/drifttwo").exists());
+
+    // Reads are refused too: a stale ZEROBREW_PREFIX shouldn't quietly
+    // report links that aren't there.
+    let output = zb(moved.clone(), &["list"]).await.unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("set ZEROBREW_PREFIX={}", t.prefix().display())),
+        "stderr: {stderr}"
+    );
+
+    let output = zb(moved.clone(), &["--accept-prefix-change", "list"])
+        .await
+        .unwrap();
+    assert_success(&output, "zb list under an accepted prefix change");
+    assert_stdout_contains(&output, "driftone");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("was patched for prefix"),
+        "stderr: {stderr}"
+    );
+    assert!(
+        stderr.contains("this root's formulas are installed under prefix"),
+        "stderr: {stderr}"
+    );
+
+    let output = zb(moved, &["--accept-prefix-change", "doctor"])
+        .await
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Patched for another prefix: driftone/1.0.0"),
+        "stderr: {stderr}"
+    );
+
+    let output = zb(t.prefix(), &["install", "drifttwo"]).await.unwrap();
+    assert_success(&output, "zb install back under the original prefix");
+}
+
+#[tokio::test]
+async fn test_deps_missing_reports_and_reinstalls_a_deleted_dependency() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "depapp", &["deplibx"]).await;
+    mount_mock_formula(&server, "deplibx", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(&["install", "depapp"]).await.unwrap();
+    assert_success(&output, "zb install depapp");
+    let output = zb(&["deps", "depapp"]).await.unwrap();
+    assert_success(&output, "zb deps depapp");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "deplibx (installed)\n"
+    );
+
+    let keg = t.prefix().join("Cellar/deplibx/1.0.0");
+    std::fs::remove_dir_all(&keg).unwrap();
+
+    let output = zb(&["deps", "--missing"]).await.unwrap();
+    assert_success(&output, "zb deps --missing");
+    assert_stdout_contains(
+        &output,
+        "depapp needs deplibx (recorded at 1.0.0, but its keg is gone)",
+    );
+    let output = zb(&["deps", "depapp", "--missing"]).await.unwrap();
+    assert_success(&output, "zb deps depapp --missing");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "deplibx\n");
+    let output = zb(&["deps", "depapp", "--porcelain=v1"]).await.unwrap();
+    assert_success(&output, "zb deps depapp --porcelain=v1");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "depapp\tdeplibx\n");
+    let output = zb(&["deps", "depapp", "--tree"]).await.unwrap();
+    assert_success(&output, "zb deps depapp --tree");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "depapp\n└── deplibx\n"
+    );
+
+    let output = zb(&["doctor"]).await.unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Missing dependency: depapp needs deplibx"),
+        "stderr: {stderr}"
+    );
+
+    let output = zb(&["deps", "--missing", "--fix"]).await.unwrap();
+    assert_success(&output, "zb deps --missing --fix");
+    assert!(keg.is_dir());
+    let output = zb(&["deps", "--missing"]).await.unwrap();
+    assert_stdout_contains(&output, "No missing dependencies.");
+
+    // Offline, the dependencies recorded at install are used.
+    server.reset().await;
+    let output = zb(&["deps", "depapp"]).await.unwrap();
+    assert_success(&output, "zb deps depapp offline");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("listing the ones recorded when it was installed"),
+        "stderr: {stderr}"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "deplibx (installed)\n"
+    );
+    let output = zb(&["deps", "depapp", "--porcelain=v1"]).await.unwrap();
+    assert_success(&output, "zb deps depapp --porcelain=v1 offline");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "depapp\tdeplibx\n");
+    let output = zb(&["deps", "depapp", "--missing", "--porcelain=v1"])
+        .await
+        .unwrap();
+    assert_success(&output, "zb deps depapp --missing --porcelain=v1 offline");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+}
+
+/// `--porcelain=v1` is a contract with scripts: if this fails, the change
+/// breaks them and belongs in a new porcelain version instead.
+#[tokio::test]
+async fn test_porcelain_v1_output_is_stable() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "porcelaintool", &["porcelainapp"]).await;
+    mount_mock_formula(&server, "porcelainapp", &["porcelainlib"]).await;
+    mount_mock_formula(&server, "porcelainlib", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .env("CLICOLOR_FORCE", "1")
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(&["install", "porcelainapp"]).await.unwrap();
+    assert_success(&output, "zb install porcelainapp");
+
+    let output = zb(&["list", "--porcelain=v1"]).await.unwrap();
+    assert_success(&output, "zb list --porcelain=v1");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let records: Vec<Vec<&str>> = stdout.lines().map(|l| l.split('\t').collect()).collect();
+    assert_eq!(records.len(), 2, "stdout: {stdout:?}");
+    for (record, (name, flags)) in records
+        .iter()
+        .zip([("porcelainapp", "explicit"), ("porcelainlib", "-")])
+    {
+        assert_eq!(record.len(), 5, "record: {record:?}");
+        assert_eq!(record[0], name);
+        assert_eq!(record[1], "1.0.0");
+        assert_eq!(record[2].len(), 64, "store key: {}", record[2]);
+        assert!(record[3].parse::<i64>().unwrap() > 1_600_000_000);
+        assert_eq!(record[4], flags);
+    }
+
+    let output = zb(&["deps", "porcelainapp", "--porcelain=v1"])
+        .await
+        .unwrap();
+    assert_success(&output, "zb deps --porcelain=v1");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "porcelainapp\tporcelainlib\n"
+    );
+    // v1 `deps` lists every dependency, direct or not, of formulas that
+    // aren't installed too.
+    let output = zb(&["deps", "porcelaintool", "--porcelain=v1"])
+        .await
+        .unwrap();
+    assert_success(&output, "zb deps porcelaintool --porcelain=v1");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "porcelaintool\tporcelainapp\nporcelaintool\tporcelainlib\n"
+    );
+
+    let output = zb(&["deps", "--missing", "--porcelain=v1"]).await.unwrap();
+    assert_success(&output, "zb deps --missing --porcelain=v1");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    let output = zb(&["outdated", "--porcelain=v1"]).await.unwrap();
+    assert_success(&output, "zb outdated --porcelain=v1");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+}
+
+#[tokio::test]
+async fn test_outdated_exits_with_its_own_status_when_something_is_behind() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "staleapp", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(&["install", "staleapp"]).await.unwrap();
+    assert_success(&output, "zb install staleapp");
+    let output = zb(&["outdated", "--include-rebuilds"]).await.unwrap();
+    assert_success(&output, "zb outdated with nothing behind");
+
+    // A new bottle of the same version.
+    server.reset().await;
+    let rebuilt = mock_bottle("staleapp", &["share/staleapp/new"]);
+    mount_mock_bottle(&server, "staleapp", &[], rebuilt, Duration::ZERO).await;
+
+    let output = zb(&["outdated", "--include-rebuilds"]).await.unwrap();
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert_stdout_contains(&output, "staleapp");
+    assert!(output.stderr.is_empty(), "{output:?}");
+}
+
+#[tokio::test]
+async fn test_upgrade_installs_and_links_the_new_version() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "upapp", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(&["install", "upapp"]).await.unwrap();
+    assert_success(&output, "zb install upapp");
+
+    server.reset().await;
+    let bottle = versioned_mock_bottle("upapp", "1.1.0", "#!/bin/sh\necho upapp 1.1.0\n", &[]);
+    mount_versioned_mock_bottle(&server, "upapp", "1.1.0", &[], bottle, Duration::ZERO).await;
+
+    let output = zb(&["upgrade", "--dry-run"]).await.unwrap();
+    assert_success(&output, "zb upgrade --dry-run");
+    assert_stdout_contains(&output, "==> Minor (1, ");
+    assert_stdout_contains(&output, "upapp  1.0.0 -> 1.1.0");
+    assert!(t.prefix().join("Cellar/upapp/1.0.0").exists());
+
+    let output = zb(&["upgrade"]).await.unwrap();
+    assert_success(&output, "zb upgrade");
+    assert_stdout_contains(&output, "Upgraded 1 packages");
+    assert_stdout_contains(&output, "upapp 1.0.0 -> 1.1.0");
+
+    let linked = std::fs::read_to_string(t.prefix().join("bin/upapp")).unwrap();
+    assert_eq!(linked, "#!/bin/sh\necho upapp 1.1.0\n");
+    let output = zb(&["outdated"]).await.unwrap();
+    assert_success(&output, "zb outdated after upgrading");
+}
+
+#[tokio::test]
+async fn test_pin_is_shown_by_list_and_info_until_unpinned() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "pinapp", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(&["pin", "pinapp"]).await.unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("formula 'pinapp' is not installed"),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = zb(&["install", "pinapp"]).await.unwrap();
+    assert_success(&output, "zb install pinapp");
+    let output = zb(&["pin", "pinapp"]).await.unwrap();
+    assert_success(&output, "zb pin pinapp");
+
+    let output = zb(&["list"]).await.unwrap();
+    assert_stdout_contains(&output, "pinapp 1.0.0 (pinned)");
+    let output = zb(&["info", "pinapp"]).await.unwrap();
+    assert_stdout_contains(&output, "Pinned:");
+
+    let output = zb(&["unpin", "pinapp"]).await.unwrap();
+    assert_success(&output, "zb unpin pinapp");
+    let output = zb(&["list"]).await.unwrap();
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("(pinned)"));
+}
+
+#[tokio::test]
+async fn test_doctor_check_runtime_reports_a_binary_the_loader_cannot_start() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "runsfine", &[]).await;
+    // Prints what ld.so prints for a missing library, and exits as it does.
+    let broken = mock_bottle_with_script(
+        "libgone",
+        "#!/bin/sh\necho 'libgone: error while loading shared libraries: libgone.so.1: \
+         cannot open shared object file: No such file or directory' >&2\nexit 127\n",
+        &[],
+    );
+    mount_mock_bottle(&server, "libgone", &[], broken, Duration::ZERO).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(&["install", "runsfine", "libgone"]).await.unwrap();
+    assert_success(&output, "zb install runsfine libgone");
+
+    let output = zb(&["doctor"]).await.unwrap();
+    assert_stdout_contains(&output, "No issues found");
+
+    let output = zb(&["doctor", "--check-runtime"]).await.unwrap();
+    assert_success(&output, "zb doctor --check-runtime");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("runsfine"), "stdout: {stdout}");
+    assert!(stdout.contains("Found 1 issue"), "stdout: {stdout}");
+    assert!(
+        stderr.contains("Runtime check: libgone 1.0.0: libgone the loader failed"),
+        "stderr: {stderr}"
+    );
+    assert!(stderr.contains("libgone.so.1"), "stderr: {stderr}");
+
+    let output = zb(&["doctor", "--check-runtime", "--formula", "runsfine"])
+        .await
+        .unwrap();
+    assert_stdout_contains(&output, "No issues found");
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("libgone"));
+}
+
+#[test]
+fn test_reset_refuses_a_prefix_zerobrew_does_not_own() {
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let precious = t.prefix().join("lib/libsystem.so");
+    std::fs::create_dir_all(precious.parent().unwrap()).unwrap();
+    std::fs::write(&precious, b"not ours").unwrap();
+    let reset = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_zb"))
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("HOME", home.path())
+            .env("SHELL", "/bin/bash")
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = reset(&["-y", "reset"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("refusing to reset") && stderr.contains("--force-unowned"),
+        "stderr: {stderr}"
+    );
+    assert!(precious.exists());
+
+    let output = reset(&["-y", "reset", "--force-unowned"]);
+    assert_success(&output, "zb reset --force-unowned");
+    assert!(!precious.exists());
+    assert_eq!(
+        std::fs::read_to_string(t.prefix().join(".zerobrew")).unwrap(),
+        format!("{}\n", t.root.path().display())
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_heartbeat_reports_progress_when_output_is_not_a_terminal() {
+    let server = wiremock::MockServer::start().await;
+    mount_throttled_mock_formula(&server, "slowpkg", &[], &[], Duration::from_millis(3500)).await;
+
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |t: &TestEnv, args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(
+        &TestEnv::new(),
+        &["install", "slowpkg", "--heartbeat-interval", "1"],
+    )
+    .await
+    .unwrap();
+    assert_success(&output, "zb install slowpkg");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let beats: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.starts_with("==> Working: downloaded 0/1 bottles"))
+        .collect();
+    assert!(
+        beats.len() >= 2,
+        "expected periodic heartbeats, got: {stderr}"
+    );
+
+    let output = zb(
+        &TestEnv::new(),
+        &["install", "slowpkg", "--heartbeat-interval", "1", "--quiet"],
+    )
+    .await
+    .unwrap();
+    assert_success(&output, "zb install slowpkg --quiet");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("==> Working"), "stderr: {stderr}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_bundle_merges_several_brewfiles() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "alpha", &[]).await;
+    mount_mock_formula(&server, "beta", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: Vec<String>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+    let brewfile = |name: &str, contents: &str| {
+        let path = home.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path.display().to_string()
+    };
+    let base = brewfile("Brewfile", "brew \"alpha\"\n");
+    let dev = brewfile("Brewfile.dev", "brew \"beta\"\nbrew \"alpha\"\n");
+    let conflicting = brewfile("Brewfile.ci", "brew \"alpha\", args: [\"HEAD\"]\n");
+    let bundle = |files: &[&String]| {
+        let mut args = vec!["bundle".to_string(), "install".to_string()];
+        for file in files {
+            args.extend(["-f".to_string(), file.to_string()]);
+        }
+        zb(args)
+    };
+
+    let output = bundle(&[&base, &conflicting]).await.unwrap();
+    assert!(!output.status.success(), "conflicting entries must fail");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("{base}:1")), "stderr: {stderr}");
+    assert!(
+        stderr.contains(&format!("{conflicting}:1")),
+        "stderr: {stderr}"
+    );
+
+    let output = bundle(&[&base, &dev]).await.unwrap();
+    assert_success(&output, "zb bundle install -f Brewfile -f Brewfile.dev");
+    assert_stdout_contains(&output, &format!("alpha ({dev}:2)"));
+    assert_stdout_contains(&output, &format!("beta ({dev}:1)"));
+    assert!(t.prefix().join("Cellar/alpha/1.0.0").is_dir());
+    assert!(t.prefix().join("Cellar/beta/1.0.0").is_dir());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_init_prefetches_the_formula_index() {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    let server = wiremock::MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/formula.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"[{"name":"prefetched"}]"#))
+        .mount(&server)
+        .await;
+    let broken = wiremock::MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/formula.json"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&broken)
+        .await;
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |t: &TestEnv, home: &std::path::Path, api_url: &str, args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_API_URL", api_url)
+            .env("HOME", home)
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+    let init = |t: &TestEnv, home: &std::path::Path, extra: &[&str]| {
+        let mut args = vec!["init", "--no-modify-path"];
+        args.extend(extra);
+        zb(t, home, &api_url, &args)
+    };
+    let cached_index = |t: &TestEnv| {
+        std::fs::read(t.root.path().join("cache/api-cache.sqlite"))
+            .is_ok_and(|db| String::from_utf8_lossy(&db).contains("prefetched"))
+    };
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let output = init(&t, home.path(), &[]).await.unwrap();
+    assert_success(&output, "zb init");
+    assert!(cached_index(&t), "init should cache the formula index");
+    assert!(!t.root.path().join("cache/index-prefetch-pending").exists());
+
+    let t = TestEnv::new();
+    let output = init(&t, home.path(), &["--no-prefetch"]).await.unwrap();
+    assert_success(&output, "zb init --no-prefetch");
+    assert!(!cached_index(&t), "--no-prefetch must not fetch the index");
+
+    // A pending prefetch is finished by the next command; one that fails
+    // stays pending for the command after.
+    let marker = t.root.path().join("cache/index-prefetch-pending");
+    std::fs::create_dir_all(marker.parent().unwrap()).unwrap();
+    std::fs::write(&marker, b"").unwrap();
+    let broken_url = format!("{}/formula", broken.uri());
+    let output = zb(&t, home.path(), &broken_url, &["gc"]).await.unwrap();
+    assert_success(&output, "zb gc with a failing prefetch");
+    assert!(marker.exists(), "a failed prefetch must stay pending");
+
+    let output = zb(&t, home.path(), &api_url, &["gc"]).await.unwrap();
+    assert_success(&output, "zb gc with a pending prefetch");
+    assert!(cached_index(&t), "the pending prefetch should be finished");
+    assert!(!marker.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_hooks_run_around_install_and_uninstall() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "hookapp", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+    let log = t.root.path().join("hook.log");
+    let hook = |point: &str, body: &str| {
+        let dir = t.root.path().join(format!("hooks/{point}.d"));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("10-test");
+        std::fs::write(&script, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    let record = format!(
+        "echo \"$ZEROBREW_HOOK_ACTION $ZEROBREW_HOOK_FORMULA $ZEROBREW_HOOK_VERSION $ZEROBREW_HOOK_KEG\" >> {}",
+        log.display()
+    );
+    hook("post-install", &record);
+    hook("pre-uninstall", "echo 'hookapp is in use' >&2; exit 1");
+    hook("post-uninstall", &record);
+
+    let output = zb(&["install", "hookapp"]).await.unwrap();
+    assert_success(&output, "zb install hookapp");
+    let keg = t.prefix().join("Cellar/hookapp/1.0.0");
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        format!("post-install hookapp 1.0.0 {}\n", keg.display())
+    );
+
+    let output = zb(&["uninstall", "hookapp"]).await.unwrap();
+    assert!(!output.status.success(), "a failing pre hook must abort");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("hookapp is in use"), "stderr: {stderr}");
+    assert!(keg.is_dir());
+
+    let output = zb(&["uninstall", "hookapp", "--no-hooks"]).await.unwrap();
+    assert_success(&output, "zb uninstall hookapp --no-hooks");
+    assert!(!keg.exists());
+    assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 1);
+
+    hook("pre-install", "exit 1");
+    let output = zb(&["install", "hookapp"]).await.unwrap();
+    assert!(!output.status.success(), "a failing pre hook must abort");
+    assert!(!keg.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_licenses_are_recorded_and_checked() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "licensed", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(&["install", "licensed"]).await.unwrap();
+    assert_success(&output, "zb install licensed");
+
+    let output = zb(&["info", "licensed"]).await.unwrap();
+    assert_stdout_contains(&output, "MIT");
+
+    let output = zb(&["licenses", "--forbid", "GPL-*"]).await.unwrap();
+    assert_success(&output, "zb licenses --forbid GPL-*");
+    assert_stdout_contains(&output, "licensed");
+
+    let output = zb(&["licenses", "--json", "--forbid", "GPL-*, MIT"])
+        .await
+        .unwrap();
+    assert!(!output.status.success(), "a forbidden license must fail");
+    let entries: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(entries[0]["license"], "MIT");
+    assert_eq!(entries[0]["forbidden"], true);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("licensed (MIT)"), "stderr: {stderr}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_cache_index_is_rebuilt_from_the_cache_directory() {
+    let server = wiremock::MockServer::start().await;
+    mount_mock_formula(&server, "cached", &[]).await;
+
+    let t = TestEnv::new();
+    let home = tempfile::TempDir::new().unwrap();
+    let api_url = format!("{}/formula", server.uri());
+    let zb = |args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_zb"));
+        command
+            .env("ZEROBREW_ROOT", t.root.path())
+            .env("ZEROBREW_PREFIX", t.prefix())
+            .env("ZEROBREW_AUTO_INIT", "true")
+            .env("ZEROBREW_API_URL", &api_url)
+            .env("HOME", home.path())
+            .args(args);
+        tokio::task::spawn_blocking(move || command.output().unwrap())
+    };
+
+    let output = zb(&["install", "cached"]).await.unwrap();
+    assert_success(&output, "zb install cached");
+
+    // The install indexed its bottle.
+    let output = zb(&["cache", "rebuild-index"]).await.unwrap();
+    assert_success(&output, "zb cache rebuild-index");
+    assert_stdout_contains(&output, "Indexed 1 cached bottle (0 added, 0 dropped)");
+
+    // Move the bottle behind the index's back, as a crash or a hand-edited
+    // cache would: its entry is stale and the file it is now is unknown.
+    let blobs = t.root.path().join("cache/blobs");
+    let bottle = std::fs::read_dir(&blobs)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    std::fs::rename(&bottle, blobs.join("moved.tar.gz")).unwrap();
+
+    let output = zb(&["cache", "rebuild-index"]).await.unwrap();
+    assert_success(&output, "zb cache rebuild-index");
+    assert_stdout_contains(&output, "Indexed 1 cached bottle (1 added, 1 dropped)");
+
+    let output = zb(&["cache", "clean", "--bottles"]).await.unwrap();
+    assert_success(&output, "zb cache clean --bottles");
+    assert_stdout_contains(&output, "Removed 1 cached bottle");
+}
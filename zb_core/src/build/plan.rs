@@ -35,7 +35,10 @@ impl BuildPlan {
     pub fn from_formula(formula: &Formula, prefix: &Path) -> Option<Self> {
         let source = formula.source_url()?;
         let version = formula.effective_version();
-        let cellar_path = prefix.join("Cellar").join(&formula.name).join(&version);
+        let cellar_path = prefix
+            .join(crate::CELLAR_DIR)
+            .join(&formula.name)
+            .join(&version);
 
         let all_build_deps = formula.all_build_dependencies();
         let detected_system = detect_build_system(&source.url, &all_build_deps);
@@ -86,6 +89,7 @@ mod tests {
             BottleFile {
                 url: format!("https://example.com/{name}.tar.gz"),
                 sha256: "deadbeef".repeat(8),
+                cellar: None,
             },
         );
 
@@ -116,6 +120,13 @@ mod tests {
             uses_from_macos: Vec::new(),
             requirements: Vec::new(),
             variations: None,
+            deprecated: false,
+            deprecation_reason: None,
+            deprecation_date: None,
+            disabled: false,
+            disable_reason: None,
+            disable_date: None,
+            license: None,
         }
     }
 
@@ -1,5 +1,10 @@
 use std::path::PathBuf;
 
+/// Name of the directory kegs are poured into. Bottles have
+/// `<prefix>/Cellar` baked into them, so the paths patching writes only
+/// resolve when ours is spelled the same way, case included.
+pub const CELLAR_DIR: &str = "Cellar";
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Paths {
     pub root: PathBuf,
@@ -13,7 +18,7 @@ pub struct Paths {
 impl Paths {
     pub fn from_root(root: PathBuf) -> Self {
         let store = root.join("store");
-        let cellar = root.join("cellar");
+        let cellar = root.join(CELLAR_DIR);
         let cache = root.join("cache");
         let db = root.join("db").join("zb.sqlite3");
         let locks = root.join("locks");
@@ -98,7 +103,7 @@ mod tests {
         );
         assert_eq!(
             context.paths.cellar,
-            PathBuf::from("/opt/zerobrew").join("cellar")
+            PathBuf::from("/opt/zerobrew").join("Cellar")
         );
         assert_eq!(
             context.paths.cache,
@@ -9,19 +9,81 @@ pub struct ConflictedLink {
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
-    UnsupportedBottle { name: String },
-    ChecksumMismatch { expected: String, actual: String },
-    LinkConflict { conflicts: Vec<ConflictedLink> },
-    StoreCorruption { message: String },
-    NetworkFailure { message: String },
-    MissingFormula { name: String },
-    UnsupportedTap { name: String },
-    UnsupportedFormula { name: String, reason: String },
-    DependencyCycle { cycle: Vec<String> },
-    NotInstalled { name: String },
-    FileError { message: String },
-    InvalidArgument { message: String },
-    ExecutionError { message: String },
+    UnsupportedBottle {
+        name: String,
+    },
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+    LinkConflict {
+        conflicts: Vec<ConflictedLink>,
+    },
+    StoreCorruption {
+        message: String,
+    },
+    NetworkFailure {
+        message: String,
+    },
+    /// The host name of a request could not be resolved.
+    DnsFailure {
+        host: String,
+    },
+    /// Connecting to, or waiting for a response from, `url` took too long.
+    NetworkTimeout {
+        url: String,
+    },
+    /// The TLS handshake with `host` failed, usually because its certificate
+    /// could not be verified.
+    TlsFailure {
+        host: String,
+        message: String,
+    },
+    /// The server answered `url` with a non-success status.
+    HttpStatus {
+        url: String,
+        status: u16,
+    },
+    MissingFormula {
+        name: String,
+    },
+    /// The metadata for formula `name` doesn't fit the expected schema.
+    /// `field` is the path to the offending value, like
+    /// `bottle.stable.files.arm64_sonoma`, and empty for the whole document.
+    InvalidFormula {
+        name: String,
+        field: String,
+        message: String,
+    },
+    UnsupportedTap {
+        name: String,
+    },
+    UnsupportedFormula {
+        name: String,
+        reason: String,
+    },
+    DependencyCycle {
+        cycle: Vec<String>,
+    },
+    NotInstalled {
+        name: String,
+    },
+    FileError {
+        message: String,
+    },
+    InvalidArgument {
+        message: String,
+    },
+    ExecutionError {
+        message: String,
+    },
+    /// Files in `name`'s keg that couldn't be patched under a strict patch
+    /// policy.
+    PatchFailed {
+        name: String,
+        files: Vec<PathBuf>,
+    },
+    Cancelled,
 }
 
 impl fmt::Display for Error {
@@ -53,7 +115,28 @@ impl fmt::Display for Error {
             }
             Error::StoreCorruption { message } => write!(f, "store corruption: {message}"),
             Error::NetworkFailure { message } => write!(f, "network failure: {message}"),
+            Error::DnsFailure { host } => {
+                write!(f, "cannot resolve {host} — check your network/VPN")
+            }
+            Error::NetworkTimeout { url } => write!(f, "timed out connecting to {url}"),
+            Error::TlsFailure { host, message } => write!(
+                f,
+                "TLS verification failed for {host}: {message} \
+                 (set ZEROBREW_CA_BUNDLE to a PEM file if a proxy re-signs HTTPS traffic)"
+            ),
+            Error::HttpStatus { url, status } => write!(f, "HTTP {status} from {url}"),
             Error::MissingFormula { name } => write!(f, "missing formula '{name}'"),
+            Error::InvalidFormula {
+                name,
+                field,
+                message,
+            } => {
+                write!(f, "invalid metadata for formula '{name}'")?;
+                if !field.is_empty() {
+                    write!(f, " at `{field}`")?;
+                }
+                write!(f, ": {message}")
+            }
             Error::UnsupportedTap { name } => {
                 write!(
                     f,
@@ -71,12 +154,35 @@ impl fmt::Display for Error {
             Error::FileError { message } => write!(f, "file error: {message}"),
             Error::InvalidArgument { message } => write!(f, "invalid argument: {message}"),
             Error::ExecutionError { message } => write!(f, "{message}"),
+            Error::PatchFailed { name, files } => {
+                write!(f, "failed to patch {} files in '{name}':", files.len())?;
+                for file in files {
+                    write!(f, "\n  '{}'", file.display())?;
+                }
+                Ok(())
+            }
+            Error::Cancelled => write!(f, "operation cancelled"),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// Process exit status for this error. Network failures get their own
+    /// codes (from `sysexits.h`) so scripts can tell them apart; everything
+    /// else exits with 1.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::DnsFailure { .. } => 68,
+            Error::HttpStatus { .. } => 69,
+            Error::NetworkTimeout { .. } => 75,
+            Error::TlsFailure { .. } => 76,
+            _ => 1,
+        }
+    }
+}
+
 macro_rules! error_helpers {
     ($($fn_name:ident => $variant:ident),* $(,)?) => {
         impl Error {
@@ -108,4 +214,41 @@ mod tests {
 
         assert!(err.to_string().contains("libheif"));
     }
+
+    #[test]
+    fn network_classes_have_distinct_exit_codes() {
+        let errors = [
+            Error::DnsFailure {
+                host: "formulae.brew.sh".to_string(),
+            },
+            Error::HttpStatus {
+                url: "https://formulae.brew.sh/api/formula/jq.json".to_string(),
+                status: 503,
+            },
+            Error::NetworkTimeout {
+                url: "https://ghcr.io".to_string(),
+            },
+            Error::TlsFailure {
+                host: "ghcr.io".to_string(),
+                message: "unknown issuer".to_string(),
+            },
+        ];
+
+        let mut codes: Vec<i32> = errors.iter().map(Error::exit_code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&1));
+        assert_eq!(Error::Cancelled.exit_code(), 1);
+    }
+
+    #[test]
+    fn tls_failure_mentions_ca_bundle_variable() {
+        let err = Error::TlsFailure {
+            host: "ghcr.io".to_string(),
+            message: "invalid peer certificate: UnknownIssuer".to_string(),
+        };
+
+        assert!(err.to_string().contains("ZEROBREW_CA_BUNDLE"));
+    }
 }
@@ -7,6 +7,41 @@ pub struct SelectedBottle {
     pub sha256: String,
 }
 
+/// The relocation requirement a bottle declares through its `cellar` field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BottleCellar {
+    /// `:any`, or no metadata: paths must be relocated.
+    Any,
+    /// `:any_skip_relocation`: binaries contain no prefix-dependent paths.
+    AnySkipRelocation,
+    /// Built for this exact Cellar path.
+    Path(String),
+}
+
+impl BottleCellar {
+    pub fn parse(value: Option<&str>) -> Self {
+        match value.map(str::trim) {
+            Some(":any_skip_relocation") => Self::AnySkipRelocation,
+            Some(path) if path.starts_with('/') => {
+                Self::Path(path.trim_end_matches('/').to_string())
+            }
+            _ => Self::Any,
+        }
+    }
+
+    /// Look up the declaration for the bottle `tag` of `formula`.
+    pub fn for_bottle(formula: &Formula, tag: &str) -> Self {
+        Self::parse(
+            formula
+                .bottle
+                .stable
+                .files
+                .get(tag)
+                .and_then(|file| file.cellar.as_deref()),
+        )
+    }
+}
+
 const MACOS_CODENAMES_NEWEST_FIRST: &[&str] = &["tahoe", "sequoia", "sonoma", "ventura"];
 
 #[cfg(target_os = "macos")]
@@ -43,108 +78,61 @@ pub fn compatible_codenames(major_version: Option<u32>) -> Vec<&'static str> {
     MACOS_CODENAMES_NEWEST_FIRST[pos..].to_vec()
 }
 
-pub fn select_bottle(formula: &Formula) -> Result<SelectedBottle, Error> {
-    #[cfg(target_os = "macos")]
-    let macos_version = macos_major_version();
-    #[cfg(not(target_os = "macos"))]
-    let macos_version: Option<u32> = None;
-
-    select_bottle_with_version(formula, macos_version)
-}
-
-fn select_bottle_with_version(
-    formula: &Formula,
-    macos_version: Option<u32>,
-) -> Result<SelectedBottle, Error> {
-    // Consumed only in #[cfg(target_os = "macos")] blocks; silence unused-variable on Linux.
-    let _ = &macos_version;
-
-    #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-    {
-        let codenames = compatible_codenames(macos_version);
-        let tags: Vec<String> = codenames.iter().map(|c| format!("arm64_{c}")).collect();
-
-        for tag in &tags {
-            if let Some(file) = formula.bottle.stable.files.get(tag.as_str()) {
-                return Ok(SelectedBottle {
-                    tag: tag.clone(),
-                    url: file.url.clone(),
-                    sha256: file.sha256.clone(),
-                });
-            }
-        }
+/// The bottle tags a machine can use, most preferred first, for an `os`
+/// and `arch` as in [`std::env::consts`]. On macOS these are the release
+/// the machine runs and every older one (all known releases when the
+/// version is unknown). `all` bottles, which fit anywhere, come after
+/// these and aren't listed.
+pub fn bottle_tags(os: &str, arch: &str, macos_version: Option<u32>) -> Vec<String> {
+    match (os, arch) {
+        ("macos", "aarch64") => compatible_codenames(macos_version)
+            .into_iter()
+            .map(|codename| format!("arm64_{codename}"))
+            .collect(),
+        ("macos", "x86_64") => compatible_codenames(macos_version)
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        ("linux", _) => vec!["x86_64_linux".to_string()],
+        _ => Vec::new(),
     }
+}
 
-    #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
-    {
-        let tags = compatible_codenames(macos_version);
-
-        for tag in &tags {
-            if let Some(file) = formula.bottle.stable.files.get(*tag) {
-                return Ok(SelectedBottle {
-                    tag: tag.to_string(),
-                    url: file.url.clone(),
-                    sha256: file.sha256.clone(),
-                });
-            }
-        }
-    }
+/// [`bottle_tags`] for the machine zb is running on.
+pub fn host_bottle_tags() -> Vec<String> {
+    bottle_tags(
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        host_macos_version(),
+    )
+}
 
-    #[cfg(target_os = "linux")]
-    {
-        let linux_tags = ["x86_64_linux"];
-        for preferred_tag in linux_tags {
-            if let Some(file) = formula.bottle.stable.files.get(preferred_tag) {
-                return Ok(SelectedBottle {
-                    tag: preferred_tag.to_string(),
-                    url: file.url.clone(),
-                    sha256: file.sha256.clone(),
-                });
-            }
-        }
-    }
+#[cfg(target_os = "macos")]
+fn host_macos_version() -> Option<u32> {
+    macos_major_version()
+}
 
-    if let Some(file) = formula.bottle.stable.files.get("all") {
-        return Ok(SelectedBottle {
-            tag: "all".to_string(),
-            url: file.url.clone(),
-            sha256: file.sha256.clone(),
-        });
-    }
+#[cfg(not(target_os = "macos"))]
+fn host_macos_version() -> Option<u32> {
+    None
+}
 
-    #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-    {
-        let codenames = compatible_codenames(macos_version);
-        for (tag, file) in &formula.bottle.stable.files {
-            if tag.starts_with("arm64_") && !tag.contains("linux") {
-                let bare = tag.strip_prefix("arm64_").unwrap_or(tag);
-                if codenames.contains(&bare) {
-                    return Ok(SelectedBottle {
-                        tag: tag.clone(),
-                        url: file.url.clone(),
-                        sha256: file.sha256.clone(),
-                    });
-                }
-            }
-        }
-    }
+pub fn select_bottle(formula: &Formula) -> Result<SelectedBottle, Error> {
+    select_bottle_from(formula, &host_bottle_tags())
+}
 
-    #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
-    {
-        let codenames = compatible_codenames(macos_version);
-        for (tag, file) in &formula.bottle.stable.files {
-            if !tag.starts_with("arm64_") && !tag.contains("linux") && tag != "all" {
-                if codenames.contains(&tag.as_str()) {
-                    return Ok(SelectedBottle {
-                        tag: tag.clone(),
-                        url: file.url.clone(),
-                        sha256: file.sha256.clone(),
-                    });
-                }
-            }
+fn select_bottle_from(formula: &Formula, tags: &[String]) -> Result<SelectedBottle, Error> {
+    for tag in tags.iter().map(String::as_str).chain(["all"]) {
+        if let Some(file) = formula.bottle.stable.files.get(tag) {
+            return Ok(SelectedBottle {
+                tag: tag.to_string(),
+                url: file.url.clone(),
+                sha256: file.sha256.clone(),
+            });
         }
     }
 
+    // Any Linux bottle beats building from source.
     #[cfg(target_os = "linux")]
     for (tag, file) in &formula.bottle.stable.files {
         if tag.contains("linux") {
@@ -223,6 +211,7 @@ mod tests {
                 url: "https://ghcr.io/v2/homebrew/core/ca-certificates/blobs/sha256:abc123"
                     .to_string(),
                 sha256: "abc123".to_string(),
+                cellar: None,
             },
         );
 
@@ -245,6 +234,13 @@ mod tests {
             uses_from_macos: Vec::new(),
             requirements: Vec::new(),
             variations: None,
+            deprecated: false,
+            deprecation_reason: None,
+            deprecation_date: None,
+            disabled: false,
+            disable_reason: None,
+            disable_date: None,
+            license: None,
         };
 
         let selected = select_bottle(&formula).unwrap();
@@ -262,6 +258,7 @@ mod tests {
                 url: "https://example.com/legacy.tar.gz".to_string(),
                 sha256: "cccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccc"
                     .to_string(),
+                cellar: None,
             },
         );
 
@@ -284,6 +281,13 @@ mod tests {
             uses_from_macos: Vec::new(),
             requirements: Vec::new(),
             variations: None,
+            deprecated: false,
+            deprecation_reason: None,
+            deprecation_date: None,
+            disabled: false,
+            disable_reason: None,
+            disable_date: None,
+            license: None,
         };
 
         let err = select_bottle(&formula).unwrap_err();
@@ -303,6 +307,7 @@ mod tests {
                 url: "https://example.com/legacy.tar.gz".to_string(),
                 sha256: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
                     .to_string(),
+                cellar: None,
             },
         );
 
@@ -325,6 +330,13 @@ mod tests {
             uses_from_macos: Vec::new(),
             requirements: Vec::new(),
             variations: None,
+            deprecated: false,
+            deprecation_reason: None,
+            deprecation_date: None,
+            disabled: false,
+            disable_reason: None,
+            disable_date: None,
+            license: None,
         };
 
         let err = select_bottle(&formula).unwrap_err();
@@ -371,7 +383,6 @@ mod tests {
     }
 
     #[test]
-    #[cfg(target_os = "macos")]
     fn sequoia_user_skips_tahoe_bottle() {
         let mut files = BTreeMap::new();
         files.insert(
@@ -379,6 +390,7 @@ mod tests {
             BottleFile {
                 url: "https://example.com/tahoe.tar.gz".to_string(),
                 sha256: "aaaa".repeat(16),
+                cellar: None,
             },
         );
         files.insert(
@@ -386,6 +398,7 @@ mod tests {
             BottleFile {
                 url: "https://example.com/sequoia.tar.gz".to_string(),
                 sha256: "bbbb".repeat(16),
+                cellar: None,
             },
         );
 
@@ -408,19 +421,21 @@ mod tests {
             uses_from_macos: Vec::new(),
             requirements: Vec::new(),
             variations: None,
+            deprecated: false,
+            deprecation_reason: None,
+            deprecation_date: None,
+            disabled: false,
+            disable_reason: None,
+            disable_date: None,
+            license: None,
         };
 
-        let selected = select_bottle_with_version(&formula, Some(15)).unwrap();
-
-        #[cfg(target_arch = "aarch64")]
+        let tags = bottle_tags("macos", "aarch64", Some(15));
+        let selected = select_bottle_from(&formula, &tags).unwrap();
         assert_eq!(selected.tag, "arm64_sequoia");
-
-        #[cfg(target_arch = "x86_64")]
-        assert_eq!(selected.tag, "all");
     }
 
     #[test]
-    #[cfg(target_os = "macos")]
     fn tahoe_user_picks_tahoe_bottle() {
         let mut files = BTreeMap::new();
         files.insert(
@@ -428,6 +443,7 @@ mod tests {
             BottleFile {
                 url: "https://example.com/tahoe.tar.gz".to_string(),
                 sha256: "aaaa".repeat(16),
+                cellar: None,
             },
         );
         files.insert(
@@ -435,6 +451,7 @@ mod tests {
             BottleFile {
                 url: "https://example.com/sequoia.tar.gz".to_string(),
                 sha256: "bbbb".repeat(16),
+                cellar: None,
             },
         );
 
@@ -457,14 +474,94 @@ mod tests {
             uses_from_macos: Vec::new(),
             requirements: Vec::new(),
             variations: None,
+            deprecated: false,
+            deprecation_reason: None,
+            deprecation_date: None,
+            disabled: false,
+            disable_reason: None,
+            disable_date: None,
+            license: None,
         };
 
-        let selected = select_bottle_with_version(&formula, Some(26)).unwrap();
-
-        #[cfg(target_arch = "aarch64")]
+        let tags = bottle_tags("macos", "aarch64", Some(26));
+        let selected = select_bottle_from(&formula, &tags).unwrap();
         assert_eq!(selected.tag, "arm64_tahoe");
+    }
 
-        #[cfg(target_arch = "x86_64")]
-        assert_eq!(selected.tag, "all");
+    #[test]
+    fn bottle_tags_fall_back_through_older_macos_releases() {
+        assert_eq!(
+            bottle_tags("macos", "aarch64", Some(15)),
+            ["arm64_sequoia", "arm64_sonoma", "arm64_ventura"]
+        );
+        assert_eq!(
+            bottle_tags("macos", "x86_64", Some(14)),
+            ["sonoma", "ventura"]
+        );
+        // Unknown or undetected releases may use any known bottle.
+        assert_eq!(
+            bottle_tags("macos", "aarch64", None),
+            [
+                "arm64_tahoe",
+                "arm64_sequoia",
+                "arm64_sonoma",
+                "arm64_ventura"
+            ]
+        );
+        assert_eq!(bottle_tags("macos", "x86_64", Some(12)).len(), 4);
+        assert_eq!(bottle_tags("linux", "x86_64", None), ["x86_64_linux"]);
+        assert_eq!(bottle_tags("linux", "aarch64", Some(15)), ["x86_64_linux"]);
+        assert!(bottle_tags("windows", "x86_64", None).is_empty());
+    }
+
+    #[test]
+    fn parses_bottle_cellar_variants() {
+        assert_eq!(BottleCellar::parse(None), BottleCellar::Any);
+        assert_eq!(BottleCellar::parse(Some(":any")), BottleCellar::Any);
+        assert_eq!(
+            BottleCellar::parse(Some(":any_skip_relocation")),
+            BottleCellar::AnySkipRelocation
+        );
+        assert_eq!(
+            BottleCellar::parse(Some("/opt/homebrew/Cellar/")),
+            BottleCellar::Path("/opt/homebrew/Cellar".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_cellar_from_formula_json() {
+        let formula: Formula = serde_json::from_str(
+            r#"{
+                "name": "jq",
+                "versions": { "stable": "1.7" },
+                "dependencies": [],
+                "bottle": { "stable": { "files": {
+                    "x86_64_linux": {
+                        "cellar": "/home/linuxbrew/.linuxbrew/Cellar",
+                        "url": "https://example.com/jq.tar.gz",
+                        "sha256": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
+                    },
+                    "arm64_sonoma": {
+                        "cellar": ":any_skip_relocation",
+                        "url": "https://example.com/jq.tar.gz",
+                        "sha256": "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"
+                    }
+                } } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            BottleCellar::for_bottle(&formula, "x86_64_linux"),
+            BottleCellar::Path("/home/linuxbrew/.linuxbrew/Cellar".to_string())
+        );
+        assert_eq!(
+            BottleCellar::for_bottle(&formula, "arm64_sonoma"),
+            BottleCellar::AnySkipRelocation
+        );
+        assert_eq!(
+            BottleCellar::for_bottle(&formula, "missing"),
+            BottleCellar::Any
+        );
     }
 }
@@ -0,0 +1,211 @@
+//! Checking a formula's SPDX license expression against licenses a user
+//! won't accept. Only the parts of SPDX that formula metadata uses are
+//! understood: identifiers, `AND`, `OR`, `WITH` and parentheses.
+
+use crate::Error;
+
+/// Licenses that must not be installed, as SPDX identifiers. A pattern
+/// ending in `*` matches every identifier starting with the rest, so
+/// `GPL-*` covers `GPL-2.0-only` and `GPL-3.0-or-later`. Matching ignores
+/// case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForbiddenLicenses {
+    patterns: Vec<String>,
+}
+
+impl ForbiddenLicenses {
+    /// Parse `GPL-3.0-only OR AGPL-*`, `GPL-*, SSPL-1.0` or similar: every
+    /// identifier listed is forbidden, whether joined by commas or
+    /// operators.
+    pub fn parse(spec: &str) -> Result<Self, Error> {
+        let patterns: Vec<String> = tokenize(spec)
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Id(id) => Some(id.to_ascii_lowercase()),
+                _ => None,
+            })
+            .collect();
+        if patterns.is_empty() {
+            return Err(Error::InvalidArgument {
+                message: format!("no license identifiers in '{spec}'"),
+            });
+        }
+        Ok(Self { patterns })
+    }
+
+    /// Whether `expression` can't be satisfied without a forbidden
+    /// license: `MIT OR GPL-3.0-only` is fine when only GPL is forbidden,
+    /// `MIT AND GPL-3.0-only` is not. An expression that doesn't parse is
+    /// forbidden when any identifier in it is.
+    pub fn forbids(&self, expression: &str) -> bool {
+        let tokens = tokenize(expression);
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            forbidden: self,
+        };
+        match parser.or_expr() {
+            Some(allowed) if parser.pos == tokens.len() => !allowed,
+            _ => tokens
+                .iter()
+                .any(|token| matches!(token, Token::Id(id) if self.matches(id))),
+        }
+    }
+
+    fn matches(&self, id: &str) -> bool {
+        let id = id.to_ascii_lowercase();
+        // `GPL-2.0+` is the older spelling of `GPL-2.0-or-later`.
+        let base = id.trim_end_matches('+');
+        self.patterns
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => base.starts_with(prefix),
+                None => *pattern == id || pattern == base,
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Id(String),
+    And,
+    Or,
+    With,
+    Open,
+    Close,
+    Comma,
+}
+
+/// Split an expression into tokens. Words that aren't operators run
+/// together into one identifier, so Homebrew's `Public Domain` stays a
+/// single license; commas only appear in lists of forbidden licenses.
+fn tokenize(expression: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let spaced = expression
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .replace(',', " , ");
+    for word in spaced.split_whitespace() {
+        let token = match word {
+            "(" => Token::Open,
+            ")" => Token::Close,
+            "," => Token::Comma,
+            _ if word.eq_ignore_ascii_case("and") => Token::And,
+            _ if word.eq_ignore_ascii_case("or") => Token::Or,
+            _ if word.eq_ignore_ascii_case("with") => Token::With,
+            _ => {
+                if let Some(Token::Id(previous)) = tokens.last_mut() {
+                    previous.push(' ');
+                    previous.push_str(word);
+                    continue;
+                }
+                Token::Id(word.to_string())
+            }
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Evaluates whether an expression can be satisfied with allowed licenses.
+/// `OR` binds looser than `AND`, as in SPDX.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    forbidden: &'a ForbiddenLicenses,
+}
+
+impl Parser<'_> {
+    fn or_expr(&mut self) -> Option<bool> {
+        let mut allowed = self.and_expr()?;
+        while self.eat(&Token::Or) {
+            allowed |= self.and_expr()?;
+        }
+        Some(allowed)
+    }
+
+    fn and_expr(&mut self) -> Option<bool> {
+        let mut allowed = self.term()?;
+        while self.eat(&Token::And) {
+            allowed &= self.term()?;
+        }
+        Some(allowed)
+    }
+
+    fn term(&mut self) -> Option<bool> {
+        if self.eat(&Token::Open) {
+            let allowed = self.or_expr()?;
+            return self.eat(&Token::Close).then_some(allowed);
+        }
+        let Some(Token::Id(id)) = self.tokens.get(self.pos) else {
+            return None;
+        };
+        self.pos += 1;
+        // An exception only grants extra permissions.
+        if self.eat(&Token::With) {
+            match self.tokens.get(self.pos) {
+                Some(Token::Id(_)) => self.pos += 1,
+                _ => return None,
+            }
+        }
+        Some(!self.forbidden.matches(id))
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.tokens.get(self.pos) == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forbid(spec: &str) -> ForbiddenLicenses {
+        ForbiddenLicenses::parse(spec).unwrap()
+    }
+
+    #[test]
+    fn single_identifiers_match_exactly_or_by_prefix() {
+        let gpl = forbid("GPL-3.0-only");
+        assert!(gpl.forbids("GPL-3.0-only"));
+        assert!(gpl.forbids("gpl-3.0-only"));
+        assert!(!gpl.forbids("GPL-3.0-or-later"));
+        assert!(!gpl.forbids("LGPL-3.0-only"));
+
+        let any_gpl = forbid("GPL-*");
+        assert!(any_gpl.forbids("GPL-2.0-or-later"));
+        assert!(any_gpl.forbids("GPL-2.0+"));
+        assert!(!any_gpl.forbids("LGPL-2.1-only"));
+        assert!(forbid("GPL-2.0").forbids("GPL-2.0+"));
+    }
+
+    #[test]
+    fn compound_expressions_need_an_allowed_choice() {
+        let gpl = forbid("GPL-*, AGPL-3.0-only");
+        assert!(!gpl.forbids("MIT OR GPL-3.0-only"));
+        assert!(gpl.forbids("MIT AND GPL-3.0-only"));
+        assert!(gpl.forbids("GPL-2.0-only OR AGPL-3.0-only"));
+        assert!(!gpl.forbids("(GPL-2.0-only AND MIT) OR Apache-2.0"));
+        assert!(gpl.forbids("Apache-2.0 AND (GPL-2.0-only OR AGPL-3.0-only)"));
+        assert!(gpl.forbids("GPL-2.0-or-later WITH Classpath-exception-2.0"));
+        assert!(!gpl.forbids("Apache-2.0 WITH LLVM-exception"));
+    }
+
+    #[test]
+    fn multi_word_and_malformed_expressions() {
+        assert!(forbid("Public Domain").forbids("Public Domain"));
+        assert!(!forbid("Public").forbids("Public Domain"));
+        // Unbalanced, so any forbidden identifier counts.
+        assert!(forbid("GPL-*").forbids("(MIT OR GPL-3.0-only"));
+        assert!(!forbid("GPL-*").forbids("(MIT OR BSD-2-Clause"));
+    }
+
+    #[test]
+    fn a_spec_without_identifiers_is_rejected() {
+        assert!(ForbiddenLicenses::parse(" OR ( ) ").is_err());
+    }
+}
@@ -0,0 +1,436 @@
+use std::cmp::Ordering;
+
+use serde::Serialize;
+
+use super::Formula;
+
+/// Where upstream stands on a formula.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lifecycle {
+    Active,
+    Deprecated { reason: Option<String> },
+    Disabled { reason: Option<String> },
+}
+
+impl Formula {
+    /// Disabled takes precedence, since upstream keeps the deprecation flag
+    /// set after a deprecated formula is disabled.
+    pub fn lifecycle(&self) -> Lifecycle {
+        if self.disabled {
+            Lifecycle::Disabled {
+                reason: self.disable_reason.clone(),
+            }
+        } else if self.deprecated {
+            Lifecycle::Deprecated {
+                reason: self.deprecation_reason.clone(),
+            }
+        } else {
+            Lifecycle::Active
+        }
+    }
+
+    /// When the state `lifecycle` reports began, if upstream says.
+    pub fn lifecycle_date(&self) -> Option<&str> {
+        if self.disabled {
+            self.disable_date.as_deref()
+        } else if self.deprecated {
+            self.deprecation_date.as_deref()
+        } else {
+            None
+        }
+    }
+}
+
+/// The unversioned formula a versioned core formula shadows:
+/// `node@18` -> `node`. Tap references and plain names have none.
+pub fn versioned_base(name: &str) -> Option<&str> {
+    if name.contains('/') {
+        return None;
+    }
+    let (base, suffix) = name.split_once('@')?;
+    (!base.is_empty() && !suffix.is_empty()).then_some(base)
+}
+
+/// Whether `version` is on a newer release line than the one a versioned
+/// name pins. Only as many components as the suffix has are compared, so
+/// `node@18` is behind `22.9.0` but `node@22` is not behind `22.10.0`.
+pub fn is_newer_release_line(versioned_name: &str, version: &str) -> bool {
+    let Some((_, suffix)) = versioned_name.split_once('@') else {
+        return false;
+    };
+    let pinned = numeric_components(suffix);
+    if pinned.is_empty() {
+        return false;
+    }
+    let mut candidate = numeric_components(version);
+    candidate.truncate(pinned.len());
+    compare_components(&candidate, &pinned) == Ordering::Greater
+}
+
+/// Order two formula versions component by component, so `1.10` sorts
+/// after `1.9` and a rebuild (`1.7.1_1`) after its release. Runs of
+/// digits compare as numbers and runs of letters lexically (`2024a`
+/// before `2024b`); letters sort before numbers, so `1.0rc1` comes before
+/// `1.0`.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a, b) = (version_components(a), version_components(b));
+    for i in 0..a.len().max(b.len()) {
+        let missing = VersionComponent::Number(0);
+        let ordering = a
+            .get(i)
+            .unwrap_or(&missing)
+            .cmp(b.get(i).unwrap_or(&missing));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// A run of letters or digits in a version. The variant order makes
+/// letters sort before numbers.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum VersionComponent<'a> {
+    Text(&'a str),
+    Number(u64),
+}
+
+fn version_components(version: &str) -> Vec<VersionComponent<'_>> {
+    let mut components = Vec::new();
+    let mut rest = version;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric()) {
+        rest = &rest[start..];
+        let digits = rest.starts_with(|c: char| c.is_ascii_digit());
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() || c.is_ascii_digit() != digits)
+            .unwrap_or(rest.len());
+        let run = &rest[..end];
+        components.push(if digits {
+            VersionComponent::Number(run.parse().unwrap_or(u64::MAX))
+        } else {
+            VersionComponent::Text(run)
+        });
+        rest = &rest[end..];
+    }
+    components
+}
+
+/// What separates an installed formula from the current one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// Upstream released another version: `1.2.3` -> `1.2.4`.
+    Version,
+    /// The same release rebuilt, with only the revision (`1.2.3` ->
+    /// `1.2.3_1`) or the bottle itself changed.
+    Rebuild,
+}
+
+impl ChangeKind {
+    pub fn between(installed: &str, current: &str) -> Self {
+        if strip_revision(installed) == strip_revision(current) {
+            Self::Rebuild
+        } else {
+            Self::Version
+        }
+    }
+}
+
+/// How far a version change reaches, judged by the first release
+/// component that differs: `1.2.3` -> `2.0.0` is major, -> `1.3.0` minor
+/// and -> `1.2.4` patch. Changes past the third component, and ones only
+/// in non-numeric parts, count as patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionBump {
+    Major,
+    Minor,
+    Patch,
+    /// Same release; see [`ChangeKind::Rebuild`].
+    Rebuild,
+}
+
+impl VersionBump {
+    pub fn between(installed: &str, current: &str) -> Self {
+        let (installed, current) = (strip_revision(installed), strip_revision(current));
+        if installed == current {
+            return Self::Rebuild;
+        }
+        let (a, b) = (numeric_components(installed), numeric_components(current));
+        let differs =
+            (0..a.len().max(b.len())).find(|&i| a.get(i).unwrap_or(&0) != b.get(i).unwrap_or(&0));
+        match differs {
+            Some(0) => Self::Major,
+            Some(1) => Self::Minor,
+            _ => Self::Patch,
+        }
+    }
+}
+
+/// What a keg is built from: the version as installed (`1.7.1_1`, with the
+/// revision suffix), the formula revision, and the bottle rebuild. A rebuild
+/// changes the bottle without changing the version string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildId {
+    pub version: String,
+    pub revision: u32,
+    /// `None` for source builds and kegs installed before rebuilds were
+    /// recorded; comparisons then leave the rebuild out.
+    pub rebuild: Option<u32>,
+}
+
+impl BuildId {
+    /// What installing `formula` from its bottle produces.
+    pub fn bottle(formula: &Formula) -> Self {
+        Self {
+            rebuild: Some(formula.bottle.stable.rebuild),
+            ..Self::source(formula)
+        }
+    }
+
+    /// What building `formula` from source produces.
+    pub fn source(formula: &Formula) -> Self {
+        Self {
+            version: formula.effective_version(),
+            revision: formula.revision,
+            rebuild: None,
+        }
+    }
+
+    /// The release decides first, then the revision, then the rebuild, so
+    /// `1.7_1` is older than `1.7.1` even though their digits read the same.
+    pub fn compare(&self, other: &Self) -> Ordering {
+        compare_versions(
+            strip_revision(&self.version),
+            strip_revision(&other.version),
+        )
+        .then(self.revision.cmp(&other.revision))
+        .then(match (self.rebuild, other.rebuild) {
+            (Some(ours), Some(theirs)) => ours.cmp(&theirs),
+            _ => Ordering::Equal,
+        })
+    }
+}
+
+/// `1.7.1_1` or, with a bottle rebuild, `1.7.1_1 (rebuild 2)`.
+impl std::fmt::Display for BuildId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.version)?;
+        match self.rebuild {
+            Some(rebuild) if rebuild > 0 => write!(f, " (rebuild {rebuild})"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The revision in a version as installed: `1.2.3_1` -> 1, `1.2.3` -> 0.
+pub fn revision_of(version: &str) -> u32 {
+    version[strip_revision(version).len()..]
+        .strip_prefix('_')
+        .and_then(|revision| revision.parse().ok())
+        .unwrap_or(0)
+}
+
+/// `1.2.3_1` -> `1.2.3`. Only a numeric suffix is a revision.
+fn strip_revision(version: &str) -> &str {
+    match version.rsplit_once('_') {
+        Some((release, revision))
+            if !revision.is_empty() && revision.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            release
+        }
+        _ => version,
+    }
+}
+
+fn numeric_components(version: &str) -> Vec<u64> {
+    version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+fn compare_components(a: &[u64], b: &[u64]) -> Ordering {
+    for i in 0..a.len().max(b.len()) {
+        let ordering = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formula(json: &str) -> Formula {
+        serde_json::from_str(&format!(
+            r#"{{
+                "name": "old",
+                "versions": {{ "stable": "1.0" }},
+                "dependencies": [],
+                "bottle": {{ "stable": {{ "files": {{}} }} }}
+                {json}
+            }}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn version_bumps_are_classified_by_the_first_differing_component() {
+        assert_eq!(VersionBump::between("1.2.3", "2.0.0"), VersionBump::Major);
+        assert_eq!(VersionBump::between("1.2.3", "1.3"), VersionBump::Minor);
+        assert_eq!(VersionBump::between("1.2.3", "1.2.4_1"), VersionBump::Patch);
+        assert_eq!(VersionBump::between("1.2", "1.2.0.1"), VersionBump::Patch);
+        assert_eq!(VersionBump::between("1.0a", "1.0b"), VersionBump::Patch);
+        assert_eq!(
+            VersionBump::between("1.2.3", "1.2.3_1"),
+            VersionBump::Rebuild
+        );
+        assert_eq!(
+            VersionBump::between("2024.01", "2025.03"),
+            VersionBump::Major
+        );
+    }
+
+    #[test]
+    fn lifecycle_reads_upstream_flags() {
+        assert_eq!(formula("").lifecycle(), Lifecycle::Active);
+        assert_eq!(
+            formula(r#", "deprecated": true, "deprecation_reason": "unmaintained""#).lifecycle(),
+            Lifecycle::Deprecated {
+                reason: Some("unmaintained".to_string())
+            }
+        );
+        assert_eq!(
+            formula(
+                r#", "deprecated": true, "deprecation_reason": "unmaintained",
+                   "disabled": true, "disable_reason": null"#
+            )
+            .lifecycle(),
+            Lifecycle::Disabled { reason: None }
+        );
+    }
+
+    #[test]
+    fn lifecycle_date_follows_the_state_in_force() {
+        let flags = r#", "deprecated": true, "deprecation_date": "2024-04-04",
+                       "disable_date": "2025-04-04""#;
+        assert_eq!(formula(flags).lifecycle_date(), Some("2024-04-04"));
+        let disabled = format!(r#"{flags}, "disabled": true"#);
+        assert_eq!(formula(&disabled).lifecycle_date(), Some("2025-04-04"));
+        assert_eq!(formula("").lifecycle_date(), None);
+    }
+
+    #[test]
+    fn versioned_base_only_for_core_versioned_names() {
+        assert_eq!(versioned_base("node@18"), Some("node"));
+        assert_eq!(versioned_base("python@3.11"), Some("python"));
+        assert_eq!(versioned_base("node"), None);
+        assert_eq!(versioned_base("@18"), None);
+        assert_eq!(versioned_base("owner/tap/node@18"), None);
+    }
+
+    #[test]
+    fn newer_release_line_compares_pinned_components() {
+        assert!(is_newer_release_line("node@18", "22.9.0"));
+        assert!(is_newer_release_line("python@3.11", "3.13.0"));
+        assert!(is_newer_release_line("postgresql@9", "17.0"));
+        assert!(!is_newer_release_line("node@22", "22.10.0"));
+        assert!(!is_newer_release_line("python@3.13", "3.12.7"));
+        assert!(!is_newer_release_line("node", "22.9.0"));
+        assert!(!is_newer_release_line("openssl@next", "3.4.0"));
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert_eq!(compare_versions("1.10.0", "1.9.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.7.1_1", "1.7.1"), Ordering::Greater);
+        assert_eq!(compare_versions("3.0", "3.0.0"), Ordering::Equal);
+        assert_eq!(compare_versions("2024a", "2024b"), Ordering::Less);
+        assert_eq!(compare_versions("1.0rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0rc2", "1.0rc1"), Ordering::Greater);
+        assert_eq!(compare_versions("1.1rc1", "1.0"), Ordering::Greater);
+        assert_eq!(compare_versions("0.9", "1.0"), Ordering::Less);
+    }
+
+    #[test]
+    fn changes_are_rebuilds_when_only_the_revision_moves() {
+        assert_eq!(ChangeKind::between("1.2.3", "1.2.3_1"), ChangeKind::Rebuild);
+        assert_eq!(
+            ChangeKind::between("1.2.3_1", "1.2.3_2"),
+            ChangeKind::Rebuild
+        );
+        // Same version, new bottle.
+        assert_eq!(ChangeKind::between("1.2.3", "1.2.3"), ChangeKind::Rebuild);
+        assert_eq!(ChangeKind::between("1.2.3", "1.2.4"), ChangeKind::Version);
+        assert_eq!(ChangeKind::between("1.2.3_1", "1.2.4"), ChangeKind::Version);
+        assert_eq!(ChangeKind::between("1.0_beta", "1.0"), ChangeKind::Version);
+    }
+
+    fn build(version: &str, rebuild: Option<u32>) -> BuildId {
+        BuildId {
+            version: version.to_string(),
+            revision: revision_of(version),
+            rebuild,
+        }
+    }
+
+    #[test]
+    fn builds_compare_release_then_revision_then_rebuild() {
+        let cmp = |a: BuildId, b: BuildId| a.compare(&b);
+
+        assert_eq!(
+            cmp(build("1.7.1_1", None), build("1.7.1", None)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp(build("1.7_1", None), build("1.7.1", None)),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp(build("1.7.1_1", None), build("1.7.1.1", None)),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp(build("1.7.1_2", None), build("1.7.1_10", None)),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp(build("1.8.0", Some(0)), build("1.7.1_3", Some(2))),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp(build("1.7.1", Some(1)), build("1.7.1", Some(0))),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp(build("1.7.1_1", Some(0)), build("1.7.1", Some(4))),
+            Ordering::Greater
+        );
+        // An unknown rebuild can't tell two bottles of one release apart.
+        assert_eq!(
+            cmp(build("1.7.1", None), build("1.7.1", Some(1))),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn builds_read_revision_and_rebuild_from_the_formula() {
+        let mut formula = formula("");
+        formula.revision = 2;
+        formula.bottle.stable.rebuild = 1;
+
+        let bottle = BuildId::bottle(&formula);
+        assert_eq!(bottle.version, "1.0_2");
+        assert_eq!(bottle.revision, 2);
+        assert_eq!(bottle.rebuild, Some(1));
+        assert_eq!(bottle.to_string(), "1.0_2 (rebuild 1)");
+        assert_eq!(BuildId::source(&formula).to_string(), "1.0_2");
+        assert_eq!(revision_of("1.0_2"), 2);
+        assert_eq!(revision_of("1.0_beta"), 0);
+        assert_eq!(revision_of("1.0"), 0);
+    }
+}
@@ -1,17 +1,29 @@
 pub mod bottle;
+pub mod license;
+pub mod lifecycle;
 pub mod resolve;
 pub mod types;
 
-pub use bottle::{SelectedBottle, compatible_codenames, select_bottle};
+pub use bottle::{
+    BottleCellar, SelectedBottle, bottle_tags, compatible_codenames, host_bottle_tags,
+    select_bottle,
+};
 
 #[cfg(target_os = "macos")]
 pub use bottle::macos_major_version;
-pub use resolve::resolve_closure;
+pub use license::ForbiddenLicenses;
+pub use lifecycle::{
+    BuildId, ChangeKind, Lifecycle, VersionBump, compare_versions, is_newer_release_line,
+    revision_of, versioned_base,
+};
+pub use resolve::{blocked_by_failures, resolve_closure, topological_order};
 pub use types::{
     Bottle, BottleFile, BottleStable, Formula, FormulaUrls, KegOnly, KegOnlyReason,
     RubySourceChecksum, SourceUrl, UsesFromMacos, Versions,
 };
 
+use crate::Error;
+
 /// Extract the formula token from an install key.
 /// Examples:
 /// - `wget` -> `wget`
@@ -26,9 +38,44 @@ pub fn formula_token(name: &str) -> &str {
         .unwrap_or("")
 }
 
+/// Check that a requested name only uses characters Homebrew allows in
+/// formula names, so typos like `jq!` fail before any network request.
+/// Tap references (`owner/repo/formula`) are validated segment by segment.
+pub fn validate_formula_name(name: &str) -> Result<(), Error> {
+    let invalid = |reason: &str| Error::InvalidArgument {
+        message: format!("invalid formula name '{name}': {reason}"),
+    };
+
+    if name.is_empty() {
+        return Err(invalid("name cannot be empty"));
+    }
+
+    let segments: Vec<&str> = name.split('/').collect();
+    if segments.len() != 1 && segments.len() != 3 {
+        return Err(invalid("expected 'formula' or 'owner/repo/formula'"));
+    }
+
+    for segment in segments {
+        if segment.is_empty() {
+            return Err(invalid("path segments cannot be empty"));
+        }
+        if segment.starts_with('.') || segment.starts_with('-') {
+            return Err(invalid("names cannot start with '.' or '-'"));
+        }
+        if let Some(c) = segment
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '@' | '+' | '.' | '_' | '-')))
+        {
+            return Err(invalid(&format!("unexpected character '{c}'")));
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::formula_token;
+    use super::{formula_token, validate_formula_name};
 
     #[test]
     fn formula_token_keeps_core_formula_name() {
@@ -54,4 +101,35 @@ mod tests {
     fn formula_token_handles_only_separators() {
         assert_eq!(formula_token("///"), "");
     }
+
+    #[test]
+    fn validate_formula_name_accepts_homebrew_names() {
+        for name in [
+            "jq",
+            "python@3.13",
+            "gtk+3",
+            "libjpeg-turbo",
+            "hashicorp/tap/terraform",
+        ] {
+            assert!(
+                validate_formula_name(name).is_ok(),
+                "{name} should be valid"
+            );
+        }
+    }
+
+    #[test]
+    fn validate_formula_name_rejects_bad_characters() {
+        let err = validate_formula_name("jq!").unwrap_err();
+        assert!(err.to_string().contains("unexpected character '!'"));
+        assert!(validate_formula_name("../etc/passwd").is_err());
+        assert!(validate_formula_name("foo bar").is_err());
+    }
+
+    #[test]
+    fn validate_formula_name_rejects_malformed_tap_references() {
+        assert!(validate_formula_name("").is_err());
+        assert!(validate_formula_name("owner/formula").is_err());
+        assert!(validate_formula_name("owner//formula").is_err());
+    }
 }
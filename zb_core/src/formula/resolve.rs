@@ -1,5 +1,5 @@
 use crate::{Error, Formula};
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 
 pub fn resolve_closure(
     roots: &[String],
@@ -11,61 +11,105 @@ pub fn resolve_closure(
         .map(|(i, k)| (k.as_str(), i))
         .collect();
     let idx_to_name: Vec<&str> = formulas.keys().map(|k| k.as_str()).collect();
-    let n = idx_to_name.len();
 
     let closure = compute_closure(roots, formulas, &name_to_idx)?;
+    let dependencies: BTreeMap<String, Vec<String>> = closure
+        .iter()
+        .map(|&idx| {
+            let name = idx_to_name[idx];
+            (name.to_string(), formulas[name].dependencies.clone())
+        })
+        .collect();
 
-    let mut indegree = vec![0u32; n];
-    let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); n];
+    topological_order(&dependencies)
+}
 
-    for &idx in &closure {
-        let formula = &formulas[idx_to_name[idx]];
-        let mut dep_indices: Vec<usize> = formula
-            .dependencies
+/// Order the keys of `dependencies` so that each comes after every
+/// dependency it lists. Whenever several are ready, the alphabetically first
+/// goes next, so the result only depends on the graph. Dependencies that are
+/// not keys themselves are ignored.
+pub fn topological_order(
+    dependencies: &BTreeMap<String, Vec<String>>,
+) -> Result<Vec<String>, Error> {
+    let mut indegree: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+
+    for (name, deps) in dependencies {
+        let deps: BTreeSet<&str> = deps
             .iter()
-            .filter_map(|dep| {
-                let &di = name_to_idx.get(dep.as_str())?;
-                closure.contains(&di).then_some(di)
-            })
+            .map(String::as_str)
+            .filter(|dep| dependencies.contains_key(*dep))
             .collect();
-        dep_indices.sort_unstable();
-        for di in dep_indices {
-            indegree[idx] += 1;
-            adjacency[di].push(idx);
+        indegree.insert(name, deps.len());
+        for dep in deps {
+            dependents.entry(dep).or_default().push(name);
         }
     }
 
-    let mut ready: BTreeSet<usize> = closure
+    let mut ready: BTreeSet<&str> = indegree
         .iter()
-        .copied()
-        .filter(|&i| indegree[i] == 0)
+        .filter(|&(_, &count)| count == 0)
+        .map(|(&name, _)| name)
         .collect();
 
-    let mut ordered = Vec::with_capacity(closure.len());
-    while let Some(&idx) = ready.iter().next() {
-        ready.remove(&idx);
-        ordered.push(idx);
-        for &child in &adjacency[idx] {
-            indegree[child] -= 1;
-            if indegree[child] == 0 {
+    let mut ordered = Vec::with_capacity(dependencies.len());
+    while let Some(name) = ready.pop_first() {
+        ordered.push(name.to_string());
+        for &child in dependents.get(name).into_iter().flatten() {
+            let count = indegree.get_mut(child).expect("dependents are keys");
+            *count -= 1;
+            if *count == 0 {
                 ready.insert(child);
             }
         }
     }
 
-    if ordered.len() != closure.len() {
-        let cycle: Vec<String> = closure
+    if ordered.len() != dependencies.len() {
+        let cycle: Vec<String> = indegree
             .iter()
-            .filter(|&&i| indegree[i] > 0)
-            .map(|&i| idx_to_name[i].to_string())
+            .filter(|&(_, &count)| count > 0)
+            .map(|(name, _)| name.to_string())
             .collect();
         return Err(Error::DependencyCycle { cycle });
     }
 
-    Ok(ordered
-        .into_iter()
-        .map(|i| idx_to_name[i].to_string())
-        .collect())
+    Ok(ordered)
+}
+
+/// For every node that depends on a member of `failed`, directly or
+/// transitively, the failed dependency it is blocked by. When several failed
+/// dependencies are reachable, the one closest in dependency order wins.
+/// Members of `failed` themselves are not included.
+pub fn blocked_by_failures(
+    dependencies: &BTreeMap<String, Vec<String>>,
+    failed: &BTreeSet<String>,
+) -> BTreeMap<String, String> {
+    let mut blocked = BTreeMap::new();
+    if failed.is_empty() {
+        return blocked;
+    }
+
+    for name in dependencies.keys() {
+        if failed.contains(name) {
+            continue;
+        }
+
+        let mut seen: BTreeSet<&str> = BTreeSet::from([name.as_str()]);
+        let mut queue: VecDeque<&str> = VecDeque::from([name.as_str()]);
+        'search: while let Some(current) = queue.pop_front() {
+            for dep in dependencies.get(current).into_iter().flatten() {
+                if failed.contains(dep) {
+                    blocked.insert(name.clone(), dep.clone());
+                    break 'search;
+                }
+                if seen.insert(dep.as_str()) {
+                    queue.push_back(dep.as_str());
+                }
+            }
+        }
+    }
+
+    blocked
 }
 
 fn compute_closure(
@@ -116,6 +160,7 @@ mod tests {
             BottleFile {
                 url: format!("https://example.com/{name}.tar.gz"),
                 sha256: "deadbeef".repeat(8),
+                cellar: None,
             },
         );
 
@@ -138,6 +183,13 @@ mod tests {
             uses_from_macos: Vec::new(),
             requirements: Vec::new(),
             variations: None,
+            deprecated: false,
+            deprecation_reason: None,
+            deprecation_date: None,
+            disabled: false,
+            disable_reason: None,
+            disable_date: None,
+            license: None,
         }
     }
 
@@ -189,4 +241,132 @@ mod tests {
         // Should successfully resolve with just git and gettext
         assert_eq!(order, vec!["gettext", "git"]);
     }
+
+    fn graph(edges: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        edges
+            .iter()
+            .map(|(name, deps)| {
+                (
+                    name.to_string(),
+                    deps.iter().map(|d| d.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn topological_order_handles_diamonds() {
+        let order = topological_order(&graph(&[
+            ("app", &["left", "right"]),
+            ("left", &["base"]),
+            ("right", &["base"]),
+            ("base", &[]),
+        ]))
+        .unwrap();
+        assert_eq!(order, vec!["base", "left", "right", "app"]);
+    }
+
+    #[test]
+    fn topological_order_puts_dependencies_before_earlier_names() {
+        // Two roots sharing a diamond; alphabetical order alone would put
+        // `aaa` and `abc` before the libraries they need.
+        let order = topological_order(&graph(&[
+            ("aaa", &["zlib", "openssl"]),
+            ("abc", &["openssl"]),
+            ("openssl", &["zlib", "ca-certificates"]),
+            ("ca-certificates", &[]),
+            ("zlib", &[]),
+        ]))
+        .unwrap();
+        assert_eq!(
+            order,
+            vec!["ca-certificates", "zlib", "openssl", "aaa", "abc"]
+        );
+    }
+
+    #[test]
+    fn topological_order_is_deterministic() {
+        let edges: &[(&str, &[&str])] = &[
+            ("ffmpeg", &["x264", "lame", "opus", "sdl2"]),
+            ("sdl2", &["libx11"]),
+            ("libx11", &["xorgproto"]),
+            ("x264", &[]),
+            ("lame", &[]),
+            ("opus", &[]),
+            ("xorgproto", &[]),
+        ];
+        let reversed: Vec<(&str, Vec<&str>)> = edges
+            .iter()
+            .rev()
+            .map(|(name, deps)| (*name, deps.iter().rev().copied().collect()))
+            .collect();
+        let reversed: Vec<(&str, &[&str])> = reversed
+            .iter()
+            .map(|(name, deps)| (*name, deps.as_slice()))
+            .collect();
+
+        let first = topological_order(&graph(edges)).unwrap();
+        assert_eq!(first, topological_order(&graph(edges)).unwrap());
+        assert_eq!(first, topological_order(&graph(&reversed)).unwrap());
+    }
+
+    #[test]
+    fn topological_order_reports_cycles() {
+        let err =
+            topological_order(&graph(&[("a", &["b"]), ("b", &["a"]), ("c", &[])])).unwrap_err();
+        assert_eq!(
+            err,
+            Error::DependencyCycle {
+                cycle: vec!["a".to_string(), "b".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn failure_blocks_direct_and_transitive_dependents() {
+        let deps = graph(&[
+            ("app", &["lib"]),
+            ("lib", &["broken"]),
+            ("broken", &[]),
+            ("other", &["zlib"]),
+            ("zlib", &[]),
+        ]);
+        let failed = BTreeSet::from(["broken".to_string()]);
+
+        let blocked = blocked_by_failures(&deps, &failed);
+
+        assert_eq!(
+            blocked,
+            BTreeMap::from([
+                ("app".to_string(), "broken".to_string()),
+                ("lib".to_string(), "broken".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn nearest_failed_dependency_is_reported() {
+        let deps = graph(&[
+            ("app", &["near", "mid"]),
+            ("mid", &["far"]),
+            ("near", &[]),
+            ("far", &[]),
+        ]);
+        let failed = BTreeSet::from(["near".to_string(), "far".to_string()]);
+
+        let blocked = blocked_by_failures(&deps, &failed);
+
+        assert_eq!(blocked["app"], "near");
+        assert_eq!(blocked["mid"], "far");
+        assert!(!blocked.contains_key("near"));
+    }
+
+    #[test]
+    fn no_failures_blocks_nothing_and_cycles_terminate() {
+        let deps = graph(&[("a", &["b"]), ("b", &["a"])]);
+        assert!(blocked_by_failures(&deps, &BTreeSet::new()).is_empty());
+
+        let failed = BTreeSet::from(["c".to_string()]);
+        assert!(blocked_by_failures(&deps, &failed).is_empty());
+    }
 }
@@ -1,6 +1,8 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
+use crate::Error;
+
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum KegOnly {
     #[default]
@@ -20,7 +22,17 @@ impl<'de> Deserialize<'de> for KegOnly {
     }
 }
 
-#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+impl Serialize for KegOnly {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            KegOnly::No => serializer.serialize_bool(false),
+            KegOnly::Yes => serializer.serialize_bool(true),
+            KegOnly::Reason(reason) => serializer.serialize_str(reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct KegOnlyReason {
     #[serde(default)]
     pub reason: String,
@@ -37,7 +49,7 @@ impl KegOnlyReason {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct SourceUrl {
     pub url: String,
     #[serde(default)]
@@ -48,7 +60,7 @@ pub struct SourceUrl {
     pub revision: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct FormulaUrls {
     #[serde(default)]
     pub stable: Option<SourceUrl>,
@@ -56,7 +68,7 @@ pub struct FormulaUrls {
     pub head: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct RubySourceChecksum {
     pub sha256: String,
 }
@@ -85,6 +97,21 @@ impl<'de> Deserialize<'de> for UsesFromMacos {
     }
 }
 
+impl Serialize for UsesFromMacos {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        match self {
+            UsesFromMacos::Plain(name) => serializer.serialize_str(name),
+            UsesFromMacos::WithContext { name, context } => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(name, context)?;
+                map.end()
+            }
+        }
+    }
+}
+
 impl UsesFromMacos {
     pub fn name(&self) -> &str {
         match self {
@@ -94,7 +121,7 @@ impl UsesFromMacos {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Formula {
     pub name: String,
     pub versions: Versions,
@@ -120,9 +147,39 @@ pub struct Formula {
     pub requirements: Vec<serde_json::Value>,
     #[serde(default)]
     pub variations: Option<serde_json::Value>,
+    #[serde(default)]
+    pub deprecated: bool,
+    #[serde(default)]
+    pub deprecation_reason: Option<String>,
+    /// When upstream deprecated the formula, as `YYYY-MM-DD`.
+    #[serde(default)]
+    pub deprecation_date: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub disable_reason: Option<String>,
+    /// When upstream disabled, or will disable, the formula, as `YYYY-MM-DD`.
+    #[serde(default)]
+    pub disable_date: Option<String>,
+    /// SPDX license expression, like `MIT` or `Apache-2.0 OR MIT`.
+    #[serde(default)]
+    pub license: Option<String>,
 }
 
 impl Formula {
+    /// Parse the API metadata for `name`. Unknown fields are ignored; a
+    /// missing or mistyped required field is reported with its path.
+    pub fn from_json(name: &str, json: &str) -> Result<Self, Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        serde_path_to_error::deserialize(&mut deserializer).map_err(|e| invalid_formula(name, e))
+    }
+
+    /// Like [`Formula::from_json`], for an entry already parsed as part of a
+    /// larger document such as the bulk formula index.
+    pub fn from_value(name: &str, value: serde_json::Value) -> Result<Self, Error> {
+        serde_path_to_error::deserialize(value).map_err(|e| invalid_formula(name, e))
+    }
+
     pub fn effective_version(&self) -> String {
         if self.revision > 0 {
             format!("{}_{}", self.versions.stable, self.revision)
@@ -169,17 +226,26 @@ impl Formula {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+fn invalid_formula(name: &str, err: serde_path_to_error::Error<serde_json::Error>) -> Error {
+    let field = err.path().to_string();
+    Error::InvalidFormula {
+        name: name.to_string(),
+        field: if field == "." { String::new() } else { field },
+        message: err.into_inner().to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Versions {
     pub stable: String,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Bottle {
     pub stable: BottleStable,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct BottleStable {
     pub files: BTreeMap<String, BottleFile>,
     /// Rebuild number for the bottle. When > 0, the bottle's internal paths
@@ -188,10 +254,14 @@ pub struct BottleStable {
     pub rebuild: u32,
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct BottleFile {
     pub url: String,
     pub sha256: String,
+    /// Where the bottle expects to be poured: `:any`, `:any_skip_relocation`
+    /// or an absolute Cellar path. See [`crate::BottleCellar`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cellar: Option<String>,
 }
 
 #[cfg(test)]
@@ -213,6 +283,96 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_api_formula_with_bottles() {
+        let formula =
+            Formula::from_json("jq", include_str!("../../fixtures/formula_api_jq.json")).unwrap();
+
+        assert_eq!(formula.name, "jq");
+        assert_eq!(formula.effective_version(), "1.7.1");
+        assert_eq!(formula.dependencies, ["oniguruma"]);
+        assert_eq!(formula.bottle.stable.rebuild, 1);
+        assert_eq!(formula.bottle.stable.files.len(), 7);
+        let file = &formula.bottle.stable.files["arm64_sonoma"];
+        assert!(file.url.ends_with(&file.sha256));
+        assert_eq!(file.cellar.as_deref(), Some(":any"));
+        assert!(!formula.deprecated);
+    }
+
+    #[test]
+    fn parses_api_keg_only_formula() {
+        let formula = Formula::from_json(
+            "sqlite",
+            include_str!("../../fixtures/formula_api_sqlite.json"),
+        )
+        .unwrap();
+
+        assert_eq!(formula.keg_only, KegOnly::Yes);
+        assert!(formula.keg_only_reason.unwrap().is_macos_specific());
+        assert_eq!(
+            formula.uses_from_macos,
+            [UsesFromMacos::Plain("zlib".into())]
+        );
+    }
+
+    #[test]
+    fn parses_api_deprecated_formula() {
+        let formula = Formula::from_json(
+            "terraform",
+            include_str!("../../fixtures/formula_api_terraform.json"),
+        )
+        .unwrap();
+
+        assert!(formula.deprecated);
+        assert!(!formula.disabled);
+        assert!(formula.deprecation_reason.unwrap().contains("BUSL"));
+        assert_eq!(formula.deprecation_date.as_deref(), Some("2024-04-04"));
+        assert_eq!(formula.build_dependencies, ["go"]);
+    }
+
+    #[test]
+    fn missing_bottle_field_names_formula_and_path() {
+        let mut json: serde_json::Value =
+            serde_json::from_str(include_str!("../../fixtures/formula_api_jq.json")).unwrap();
+        json["bottle"]["stable"]["files"]["arm64_sonoma"]
+            .as_object_mut()
+            .unwrap()
+            .remove("sha256");
+
+        let err = Formula::from_value("jq", json.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            Error::InvalidFormula {
+                name: "jq".to_string(),
+                field: "bottle.stable.files.arm64_sonoma".to_string(),
+                message: "missing field `sha256`".to_string(),
+            }
+        );
+
+        let err = Formula::from_json("jq", &json.to_string()).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.starts_with(
+                "invalid metadata for formula 'jq' at `bottle.stable.files.arm64_sonoma`: \
+                 missing field `sha256`"
+            ),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn mistyped_version_names_the_field() {
+        let mut value: serde_json::Value =
+            serde_json::from_str(include_str!("../../fixtures/formula_api_jq.json")).unwrap();
+        value["versions"]["stable"] = serde_json::Value::Null;
+        let err = Formula::from_value("jq", value).unwrap_err();
+
+        assert!(
+            matches!(&err, Error::InvalidFormula { field, .. } if field == "versions.stable"),
+            "{err:?}"
+        );
+    }
+
     #[test]
     fn effective_version_without_revision() {
         let fixture = include_str!("../../fixtures/formula_foo.json");
@@ -411,4 +571,24 @@ mod tests {
         assert!(formula.keg_only_reason.is_none());
         assert!(formula.is_keg_only());
     }
+
+    #[test]
+    fn formula_serialization_round_trips() {
+        let json = r#"{
+            "name": "libfoo",
+            "versions": { "stable": "1.0" },
+            "dependencies": ["zlib"],
+            "keg_only": "provided by macOS",
+            "uses_from_macos": ["curl", { "bison": "build" }],
+            "bottle": { "stable": { "files": {
+                "arm64_sonoma": { "url": "https://x.com/a.tar.gz", "sha256": "aa" }
+            }}}
+        }"#;
+        let formula: Formula = serde_json::from_str(json).unwrap();
+
+        let encoded = serde_json::to_string(&formula).unwrap();
+        let decoded: Formula = serde_json::from_str(&encoded).unwrap();
+
+        assert_eq!(decoded, formula);
+    }
 }
@@ -4,11 +4,13 @@ pub mod errors;
 pub mod formula;
 
 pub use build::{BuildPlan, BuildSystem, InstallMethod};
-pub use context::{ConcurrencyLimits, Context, LogLevel, LoggerHandle, Paths};
+pub use context::{CELLAR_DIR, ConcurrencyLimits, Context, LogLevel, LoggerHandle, Paths};
 pub use errors::{ConflictedLink, Error};
 pub use formula::{
-    Formula, KegOnly, KegOnlyReason, SelectedBottle, compatible_codenames, formula_token,
-    resolve_closure, select_bottle,
+    BottleCellar, BuildId, ChangeKind, ForbiddenLicenses, Formula, KegOnly, KegOnlyReason,
+    Lifecycle, SelectedBottle, VersionBump, blocked_by_failures, bottle_tags, compare_versions,
+    compatible_codenames, formula_token, host_bottle_tags, is_newer_release_line, resolve_closure,
+    revision_of, select_bottle, topological_order, validate_formula_name, versioned_base,
 };
 
 #[cfg(target_os = "macos")]
@@ -5,6 +5,7 @@ use zb_core::Error;
 
 use crate::checksum::verify_sha256_bytes;
 use crate::extraction::extract_tarball;
+use crate::network::failure::{ca_bundle_certificates, request_error, status_error};
 
 pub async fn download_and_extract_source(
     url: &str,
@@ -29,6 +30,7 @@ pub async fn download_and_extract_source(
 async fn download_source(url: &str, dest: &Path) -> Result<(), Error> {
     let client = reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(300))
+        .tls_certs_merge(ca_bundle_certificates())
         .build()
         .map_err(Error::network("failed to create HTTP client"))?;
 
@@ -36,13 +38,11 @@ async fn download_source(url: &str, dest: &Path) -> Result<(), Error> {
         .get(url)
         .send()
         .await
-        .map_err(Error::network("failed to download source"))?;
+        .map_err(|e| request_error(&e, url))?;
 
     let status = response.status();
     if !status.is_success() {
-        return Err(Error::NetworkFailure {
-            message: format!("source download returned HTTP {status}"),
-        });
+        return Err(status_error(status, url));
     }
 
     let bytes = response
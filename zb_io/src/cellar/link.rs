@@ -1,9 +1,15 @@
+use std::collections::HashSet;
 use std::fs;
 use std::io;
 use std::path::{Component, Path, PathBuf};
 
 use zb_core::{ConflictedLink, Error};
 
+use super::walk::{self, DirGuard};
+use super::wrapper::{self, WrapperRule};
+use crate::extraction::extract::normalize_path;
+use crate::storage::db::{KegFileKind, KegFileRecord, LinkedFile};
+
 const LINK_DIRS: &[&str] = &["bin", "lib", "libexec", "include", "share", "etc"];
 const LIBEXEC_SKIP_FILES: &[&str] = &[".gitignore", "pyvenv.cfg"];
 
@@ -24,10 +30,249 @@ pub struct Linker {
     opt_dir: PathBuf,
 }
 
+/// Which part of the prefix a planned link lands in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkClass {
+    Bin,
+    Lib,
+    Libexec,
+    Include,
+    Man,
+    /// Shell completions, under `share` or `etc/bash_completion.d`.
+    Completion,
+    Share,
+    Etc,
+}
+
+impl LinkClass {
+    fn of(prefix: &Path, link_path: &Path) -> Self {
+        let relative = link_path.strip_prefix(prefix).unwrap_or(link_path);
+        let mut parts = relative.components().filter_map(|c| match c {
+            Component::Normal(s) => s.to_str(),
+            _ => None,
+        });
+        let first = parts.next().unwrap_or_default();
+        let second = parts.next().unwrap_or_default();
+        let third = parts.next().unwrap_or_default();
+        match (first, second, third) {
+            ("bin", ..) => Self::Bin,
+            ("lib", ..) => Self::Lib,
+            ("libexec", ..) => Self::Libexec,
+            ("include", ..) => Self::Include,
+            ("etc", "bash_completion.d", _) => Self::Completion,
+            ("etc", ..) => Self::Etc,
+            ("share", "man", _) => Self::Man,
+            ("share", "bash-completion" | "zsh-completions", _)
+            | ("share", "zsh", "site-functions")
+            | ("share", "fish", "vendor_completions.d" | "completions") => Self::Completion,
+            _ => Self::Share,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct LinkedFile {
-    pub link_path: PathBuf,
-    pub target_path: PathBuf,
+pub struct PlannedLink {
+    pub file: LinkedFile,
+    pub class: LinkClass,
+}
+
+/// Everything linking a keg should leave in the prefix, worked out from the
+/// keg alone. Directories come before what goes in them.
+#[derive(Debug, Clone)]
+pub struct LinkPlan {
+    pub keg_path: PathBuf,
+    pub entries: Vec<PlannedLink>,
+    wrapper: Option<&'static WrapperRule>,
+}
+
+/// How a keg's links on disk and in its `keg_files` records differ from
+/// its [`LinkPlan`].
+#[derive(Debug, Clone, Default)]
+pub struct LinkDiff {
+    /// Planned but not in place on disk.
+    pub missing: Vec<PlannedLink>,
+    /// In place on disk but not recorded.
+    pub unrecorded: Vec<PlannedLink>,
+    /// Recorded but no longer planned.
+    pub stale: Vec<KegFileRecord>,
+}
+
+impl LinkDiff {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.unrecorded.is_empty() && self.stale.is_empty()
+    }
+}
+
+impl LinkPlan {
+    /// Plan linking `keg_path` into `prefix`. Only the keg is read; nothing
+    /// under `prefix` is looked at or created.
+    pub fn new(prefix: &Path, keg_path: &Path) -> Result<Self, Error> {
+        let mut entries = Vec::new();
+        for dir_name in LINK_DIRS {
+            let src_dir = keg_path.join(dir_name);
+            if src_dir.exists() {
+                let guard = &mut DirGuard::default();
+                plan_tree(
+                    prefix,
+                    &src_dir,
+                    &prefix.join(dir_name),
+                    false,
+                    guard,
+                    &mut entries,
+                )?;
+            }
+        }
+        let wrapper = keg_path
+            .parent()
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .and_then(wrapper::rule_for);
+        if wrapper.is_some() {
+            let bin_dir = prefix.join("bin");
+            for entry in &mut entries {
+                let file = &mut entry.file;
+                if file.kind == KegFileKind::Symlink && file.link_path.parent() == Some(&bin_dir) {
+                    file.kind = KegFileKind::Wrapper;
+                    file.link_target = None;
+                }
+            }
+        }
+        Ok(Self {
+            keg_path: keg_path.to_path_buf(),
+            entries,
+            wrapper,
+        })
+    }
+
+    /// Compare the plan with the keg's `recorded` files and with what is
+    /// actually on disk. Configs aren't linked, so their records are left
+    /// out.
+    pub fn diff(&self, recorded: &[KegFileRecord]) -> LinkDiff {
+        let recorded_paths: HashSet<&str> = recorded
+            .iter()
+            .map(|record| record.linked_path.as_str())
+            .collect();
+        let planned: HashSet<PathBuf> = self
+            .entries
+            .iter()
+            .map(|entry| entry.file.link_path.clone())
+            .collect();
+
+        let mut diff = LinkDiff::default();
+        for entry in &self.entries {
+            if !is_in_place(&entry.file) {
+                diff.missing.push(entry.clone());
+            } else if !entry
+                .file
+                .link_path
+                .to_str()
+                .is_some_and(|path| recorded_paths.contains(path))
+            {
+                diff.unrecorded.push(entry.clone());
+            }
+        }
+        diff.stale = recorded
+            .iter()
+            .filter(|record| record.kind != KegFileKind::Config)
+            .filter(|record| !planned.contains(Path::new(&record.linked_path)))
+            .cloned()
+            .collect();
+        diff
+    }
+}
+
+/// Plan the links for everything under `src` into `dst`, with `dst` itself
+/// as a directory entry when `include_dst` is set.
+fn plan_tree(
+    prefix: &Path,
+    src: &Path,
+    dst: &Path,
+    include_dst: bool,
+    guard: &mut DirGuard,
+    entries: &mut Vec<PlannedLink>,
+) -> Result<(), Error> {
+    if !guard.enter(src)? {
+        return Ok(());
+    }
+    if include_dst {
+        entries.push(PlannedLink {
+            file: LinkedFile {
+                link_path: dst.to_path_buf(),
+                target_path: src.to_path_buf(),
+                link_target: None,
+                kind: KegFileKind::Dir,
+            },
+            class: LinkClass::of(prefix, dst),
+        });
+    }
+    for entry in fs::read_dir(src).map_err(Error::store("failed to read directory"))? {
+        let entry = entry.map_err(Error::store("failed to read directory entry"))?;
+        let file_name = entry.file_name();
+        if should_skip_link_entry(src, &file_name) {
+            continue;
+        }
+
+        let src_path = entry.path();
+        let dst_path = dst.join(&file_name);
+
+        // Use src_path.is_dir() which follows symlinks, so that keg entries
+        // like `man -> ../gnuman` (symlinks to directories) are expanded
+        // into individual file symlinks instead of conflicting.
+        if src_path.is_dir() {
+            plan_tree(prefix, &src_path, &dst_path, true, guard, entries)?;
+            continue;
+        }
+        entries.push(PlannedLink {
+            class: LinkClass::of(prefix, &dst_path),
+            file: LinkedFile {
+                link_target: Some(symlink_target(prefix, &dst_path, &src_path)),
+                link_path: dst_path,
+                target_path: src_path,
+                kind: KegFileKind::Symlink,
+            },
+        });
+    }
+    guard.leave();
+    Ok(())
+}
+
+/// Whether `file` is on disk the way the plan has it.
+fn is_in_place(file: &LinkedFile) -> bool {
+    match file.kind {
+        KegFileKind::Dir => file.link_path.is_dir(),
+        KegFileKind::Wrapper => is_wrapper_for(&file.link_path, &file.target_path),
+        KegFileKind::Symlink | KegFileKind::Config => {
+            let Ok(target) = fs::read_link(&file.link_path) else {
+                return false;
+            };
+            let resolved = file
+                .link_path
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(target);
+            fs::canonicalize(&resolved).ok() == fs::canonicalize(&file.target_path).ok()
+        }
+    }
+}
+
+/// The target to give a symlink at `link` to `target`: relative when both
+/// are inside `prefix`, so the link still resolves after the prefix is
+/// moved, and absolute otherwise.
+fn symlink_target(prefix: &Path, link: &Path, target: &Path) -> PathBuf {
+    let prefix = normalize_path(prefix);
+    let target = normalize_path(target);
+    let dir = normalize_path(link.parent().unwrap_or(Path::new("")));
+    if !target.starts_with(&prefix) || !dir.starts_with(&prefix) {
+        return target;
+    }
+    let common = dir
+        .components()
+        .zip(target.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative: PathBuf = dir.components().skip(common).map(|_| "..").collect();
+    relative.extend(target.components().skip(common));
+    relative
 }
 
 fn keg_name_from_path(path: &Path) -> Option<String> {
@@ -54,6 +299,12 @@ fn keg_name_from_symlink(dst: &Path) -> Option<String> {
     keg_name_from_path(&canonical)
 }
 
+/// Whether `dst` is a generated wrapper that execs `src`.
+fn is_wrapper_for(dst: &Path, src: &Path) -> bool {
+    wrapper::wrapped_target(dst)
+        .is_some_and(|target| fs::canonicalize(target).ok() == fs::canonicalize(src).ok())
+}
+
 impl Linker {
     pub fn new(prefix: &Path) -> io::Result<Self> {
         let bin_dir = prefix.join("bin");
@@ -82,20 +333,36 @@ impl Linker {
             let src_dir = keg_path.join(dir_name);
             let dst_dir = self.prefix.join(dir_name);
             if src_dir.exists() {
-                Self::collect_conflicts(&src_dir, &dst_dir, &mut conflicts);
+                let guard = &mut DirGuard::default();
+                Self::collect_conflicts(&src_dir, &dst_dir, &mut conflicts, guard);
             }
         }
         if conflicts.is_empty() {
             Ok(())
         } else {
+            // Directory listings come back in filesystem order.
+            conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+            conflicts.dedup_by(|a, b| a.path == b.path);
             Err(Error::LinkConflict { conflicts })
         }
     }
 
-    fn collect_conflicts(src: &Path, dst: &Path, conflicts: &mut Vec<ConflictedLink>) {
+    fn collect_conflicts(
+        src: &Path,
+        dst: &Path,
+        conflicts: &mut Vec<ConflictedLink>,
+        guard: &mut DirGuard,
+    ) {
+        // Past the depth limit linking itself fails with the reason.
+        if !guard.enter(src).unwrap_or(false) {
+            return;
+        }
         let entries = match fs::read_dir(src) {
             Ok(e) => e,
-            Err(_) => return,
+            Err(_) => {
+                guard.leave();
+                return;
+            }
         };
         for entry in entries.flatten() {
             let file_name = entry.file_name();
@@ -109,6 +376,15 @@ impl Linker {
             // Use src_path.is_dir() which follows symlinks, so that keg entries
             // like `man -> ../gnuman` (symlinks to directories) are treated as dirs.
             if src_path.is_dir() {
+                // A link the user made back into the prefix would be expanded
+                // into a copy of the prefix inside itself.
+                if walk::is_symlink_loop(&dst_path) {
+                    conflicts.push(ConflictedLink {
+                        path: dst_path,
+                        owned_by: None,
+                    });
+                    continue;
+                }
                 // When the destination is a symlink to a directory, actual linking will
                 // expand it into individual file symlinks. Check the expanded contents.
                 if dst_path.symlink_metadata().is_ok()
@@ -120,10 +396,12 @@ impl Linker {
                     } else {
                         old_target
                     };
-                    Self::collect_conflicts_merged(&src_path, &resolved, &dst_path, conflicts);
+                    Self::collect_conflicts_merged(
+                        &src_path, &resolved, &dst_path, conflicts, guard,
+                    );
                     continue;
                 }
-                Self::collect_conflicts(&src_path, &dst_path, conflicts);
+                Self::collect_conflicts(&src_path, &dst_path, conflicts, guard);
                 continue;
             }
 
@@ -137,10 +415,14 @@ impl Linker {
                     if fs::canonicalize(&resolved).ok() == fs::canonicalize(&src_path).ok() {
                         continue;
                     }
+                } else if is_wrapper_for(&dst_path, &src_path) {
+                    continue;
                 }
                 conflicts.push(ConflictedLink {
                     path: dst_path.clone(),
-                    owned_by: keg_name_from_symlink(&dst_path),
+                    owned_by: keg_name_from_symlink(&dst_path).or_else(|| {
+                        wrapper::wrapped_target(&dst_path).and_then(|t| keg_name_from_path(&t))
+                    }),
                 });
             } else if dst_path.exists() {
                 conflicts.push(ConflictedLink {
@@ -149,6 +431,7 @@ impl Linker {
                 });
             }
         }
+        guard.leave();
     }
 
     /// Check for conflicts when a directory symlink will be expanded into file-level links.
@@ -159,10 +442,17 @@ impl Linker {
         old_target: &Path,
         dst: &Path,
         conflicts: &mut Vec<ConflictedLink>,
+        guard: &mut DirGuard,
     ) {
+        if !guard.enter(src).unwrap_or(false) {
+            return;
+        }
         let new_entries = match fs::read_dir(src) {
             Ok(e) => e,
-            Err(_) => return,
+            Err(_) => {
+                guard.leave();
+                return;
+            }
         };
         for entry in new_entries.flatten() {
             let src_path = entry.path();
@@ -171,9 +461,15 @@ impl Linker {
 
             if src_path.is_dir() {
                 if matching_old.exists() {
-                    Self::collect_conflicts_merged(&src_path, &matching_old, &dst_path, conflicts);
+                    Self::collect_conflicts_merged(
+                        &src_path,
+                        &matching_old,
+                        &dst_path,
+                        conflicts,
+                        guard,
+                    );
                 } else {
-                    Self::collect_conflicts(&src_path, &dst_path, conflicts);
+                    Self::collect_conflicts(&src_path, &dst_path, conflicts, guard);
                 }
                 continue;
             }
@@ -187,103 +483,150 @@ impl Linker {
                 });
             }
         }
+        guard.leave();
+    }
+
+    /// What linking `keg_path` into this linker's prefix would create.
+    pub fn plan(&self, keg_path: &Path) -> Result<LinkPlan, Error> {
+        LinkPlan::new(&self.prefix, keg_path)
     }
 
     pub fn link_keg(&self, keg_path: &Path) -> Result<Vec<LinkedFile>, Error> {
-        self.check_conflicts(keg_path)?;
-        self.link_opt(keg_path)?;
-        let mut linked = Vec::new();
-        for dir_name in LINK_DIRS {
-            let src_dir = keg_path.join(dir_name);
-            let dst_dir = self.prefix.join(dir_name);
-            if src_dir.exists() {
-                linked.extend(Self::link_recursive(&src_dir, &dst_dir)?);
-            }
-        }
-        Ok(linked)
+        self.apply(&self.plan(keg_path)?)
     }
 
-    fn link_recursive(src: &Path, dst: &Path) -> Result<Vec<LinkedFile>, Error> {
+    /// Create everything `plan` lists, all or nothing as far as conflicts
+    /// go, and return the files now in place with the link targets actually
+    /// on disk.
+    pub fn apply(&self, plan: &LinkPlan) -> Result<Vec<LinkedFile>, Error> {
+        self.check_conflicts(&plan.keg_path)?;
+        self.link_opt(&plan.keg_path)?;
         let mut linked = Vec::new();
-        if !dst.exists() {
-            fs::create_dir_all(dst).map_err(Error::store("failed to create directory"))?;
+        for entry in &plan.entries {
+            self.apply_entry(&entry.file, &mut linked)?;
         }
+        if let Some(rule) = plan.wrapper {
+            wrapper::write_wrappers(
+                rule,
+                &self.prefix,
+                &self.bin_dir,
+                &plan.keg_path,
+                &mut linked,
+            )?;
+        }
+        Ok(linked)
+    }
 
-        for entry in fs::read_dir(src).map_err(Error::store("failed to read directory"))? {
-            let entry = entry.map_err(Error::store("failed to read directory entry"))?;
-            let file_name = entry.file_name();
-            if should_skip_link_entry(src, &file_name) {
-                continue;
-            }
-
-            let src_path = entry.path();
-            let dst_path = dst.join(&file_name);
+    fn apply_entry(&self, file: &LinkedFile, linked: &mut Vec<LinkedFile>) -> Result<(), Error> {
+        let dst_path = &file.link_path;
+        let src_path = &file.target_path;
 
-            // Use src_path.is_dir() which follows symlinks, so that keg entries
-            // like `man -> ../gnuman` (symlinks to directories) are expanded
-            // into individual file symlinks instead of conflicting.
-            if src_path.is_dir() {
-                if dst_path.symlink_metadata().is_ok() && dst_path.is_symlink() {
-                    let old_target = fs::read_link(&dst_path)
-                        .map_err(Error::store("failed to read symlink target"))?;
-                    let _ = fs::remove_file(&dst_path);
-                    Self::link_recursive(&old_target, &dst_path)?;
+        if file.kind == KegFileKind::Dir {
+            // A directory another keg linked whole is expanded into a real
+            // directory of links so both kegs' files fit in it.
+            if dst_path.symlink_metadata().is_ok() && dst_path.is_symlink() {
+                if walk::is_symlink_loop(dst_path) {
+                    return Err(Error::LinkConflict {
+                        conflicts: vec![ConflictedLink {
+                            path: dst_path.clone(),
+                            owned_by: None,
+                        }],
+                    });
+                }
+                let old_target = fs::read_link(dst_path)
+                    .map_err(Error::store("failed to read symlink target"))?;
+                let old_target = dst_path.parent().unwrap_or(Path::new("")).join(old_target);
+                let _ = fs::remove_file(dst_path);
+                let mut expanded = Vec::new();
+                let guard = &mut DirGuard::default();
+                plan_tree(
+                    &self.prefix,
+                    &old_target,
+                    dst_path,
+                    true,
+                    guard,
+                    &mut expanded,
+                )?;
+                for entry in &expanded {
+                    self.apply_entry(&entry.file, &mut Vec::new())?;
                 }
-                linked.extend(Self::link_recursive(&src_path, &dst_path)?);
-                continue;
             }
+            if !dst_path.exists() {
+                fs::create_dir_all(dst_path).map_err(Error::store("failed to create directory"))?;
+            }
+            linked.push(file.clone());
+            return Ok(());
+        }
 
-            if dst_path.symlink_metadata().is_ok() {
-                if let Ok(target) = fs::read_link(&dst_path) {
-                    let resolved = if target.is_relative() {
-                        dst_path.parent().unwrap_or(Path::new("")).join(&target)
-                    } else {
-                        target
-                    };
-                    if fs::canonicalize(&resolved).ok() == fs::canonicalize(&src_path).ok() {
-                        if resolved.exists() {
-                            linked.push(LinkedFile {
-                                link_path: dst_path,
-                                target_path: src_path,
-                            });
-                            continue;
-                        } else {
-                            let _ = fs::remove_file(&dst_path);
-                        }
-                    } else {
-                        return Err(Error::LinkConflict {
-                            conflicts: vec![ConflictedLink {
-                                path: dst_path.clone(),
-                                owned_by: keg_name_from_symlink(&dst_path),
-                            }],
+        if let Some(parent) = dst_path.parent()
+            && !parent.exists()
+        {
+            fs::create_dir_all(parent).map_err(Error::store("failed to create directory"))?;
+        }
+
+        if dst_path.symlink_metadata().is_ok() {
+            if let Ok(target) = fs::read_link(dst_path) {
+                let resolved = if target.is_relative() {
+                    dst_path.parent().unwrap_or(Path::new("")).join(&target)
+                } else {
+                    target.clone()
+                };
+                if fs::canonicalize(&resolved).ok() == fs::canonicalize(src_path).ok() {
+                    if resolved.exists() {
+                        linked.push(LinkedFile {
+                            link_path: dst_path.clone(),
+                            target_path: src_path.clone(),
+                            link_target: Some(target),
+                            kind: KegFileKind::Symlink,
                         });
+                        return Ok(());
                     }
+                    let _ = fs::remove_file(dst_path);
                 } else {
                     return Err(Error::LinkConflict {
                         conflicts: vec![ConflictedLink {
-                            path: dst_path,
-                            owned_by: None,
+                            path: dst_path.clone(),
+                            owned_by: keg_name_from_symlink(dst_path),
                         }],
                     });
                 }
-            } else if dst_path.exists() {
+            } else if is_wrapper_for(dst_path, src_path) {
+                // Rewritten by apply once everything is linked.
+                linked.push(LinkedFile {
+                    link_path: dst_path.clone(),
+                    target_path: src_path.clone(),
+                    link_target: None,
+                    kind: KegFileKind::Symlink,
+                });
+                return Ok(());
+            } else {
                 return Err(Error::LinkConflict {
                     conflicts: vec![ConflictedLink {
-                        path: dst_path,
+                        path: dst_path.clone(),
                         owned_by: None,
                     }],
                 });
             }
-
-            #[cfg(unix)]
-            std::os::unix::fs::symlink(&src_path, &dst_path)
-                .map_err(Error::store("failed to create symlink"))?;
-            linked.push(LinkedFile {
-                link_path: dst_path,
-                target_path: src_path,
+        } else if dst_path.exists() {
+            return Err(Error::LinkConflict {
+                conflicts: vec![ConflictedLink {
+                    path: dst_path.clone(),
+                    owned_by: None,
+                }],
             });
         }
-        Ok(linked)
+
+        let target = symlink_target(&self.prefix, dst_path, src_path);
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&target, dst_path)
+            .map_err(Error::store("failed to create symlink"))?;
+        linked.push(LinkedFile {
+            link_path: dst_path.clone(),
+            target_path: src_path.clone(),
+            link_target: Some(target),
+            kind: KegFileKind::Symlink,
+        });
+        Ok(())
     }
 
     pub fn unlink_keg(&self, keg_path: &Path) -> Result<Vec<PathBuf>, Error> {
@@ -293,7 +636,8 @@ impl Linker {
             let src_dir = keg_path.join(dir_name);
             let dst_dir = self.prefix.join(dir_name);
             if src_dir.exists() {
-                unlinked.extend(Self::unlink_recursive(&src_dir, &dst_dir)?);
+                let guard = &mut DirGuard::default();
+                unlinked.extend(Self::unlink_recursive(&src_dir, &dst_dir, guard)?);
             }
         }
         Ok(unlinked)
@@ -305,15 +649,20 @@ impl Linker {
             let src_dir = keg_path.join(dir_name);
             let dst_dir = self.prefix.join(dir_name);
             if src_dir.exists() {
-                linked.extend(Self::collect_linked_recursive(&src_dir, &dst_dir)?);
+                let guard = &mut DirGuard::default();
+                linked.extend(Self::collect_linked_recursive(&src_dir, &dst_dir, guard)?);
             }
         }
         Ok(linked)
     }
 
-    fn unlink_recursive(src: &Path, dst: &Path) -> Result<Vec<PathBuf>, Error> {
+    fn unlink_recursive(
+        src: &Path,
+        dst: &Path,
+        guard: &mut DirGuard,
+    ) -> Result<Vec<PathBuf>, Error> {
         let mut unlinked = Vec::new();
-        if !src.exists() || !dst.exists() {
+        if !src.exists() || !dst.exists() || !guard.enter(src)? {
             return Ok(unlinked);
         }
         for entry in fs::read_dir(src).map_err(Error::store("failed to read directory"))? {
@@ -322,7 +671,7 @@ impl Linker {
             let dst_path = dst.join(entry.file_name());
 
             if src_path.is_dir() && dst_path.is_dir() && !dst_path.is_symlink() {
-                unlinked.extend(Self::unlink_recursive(&src_path, &dst_path)?);
+                unlinked.extend(Self::unlink_recursive(&src_path, &dst_path, guard)?);
                 if let Ok(mut entries) = fs::read_dir(&dst_path)
                     && entries.next().is_none()
                 {
@@ -341,14 +690,22 @@ impl Linker {
                     let _ = fs::remove_file(&dst_path);
                     unlinked.push(dst_path);
                 }
+            } else if is_wrapper_for(&dst_path, &src_path) {
+                let _ = fs::remove_file(&dst_path);
+                unlinked.push(dst_path);
             }
         }
+        guard.leave();
         Ok(unlinked)
     }
 
-    fn collect_linked_recursive(src: &Path, dst: &Path) -> Result<Vec<LinkedFile>, Error> {
+    fn collect_linked_recursive(
+        src: &Path,
+        dst: &Path,
+        guard: &mut DirGuard,
+    ) -> Result<Vec<LinkedFile>, Error> {
         let mut linked = Vec::new();
-        if !src.exists() || !dst.exists() {
+        if !src.exists() || !dst.exists() || !guard.enter(src)? {
             return Ok(linked);
         }
         for entry in fs::read_dir(src).map_err(Error::store("failed to read directory"))? {
@@ -357,7 +714,7 @@ impl Linker {
             let dst_path = dst.join(entry.file_name());
 
             if src_path.is_dir() && dst_path.is_dir() && !dst_path.is_symlink() {
-                linked.extend(Self::collect_linked_recursive(&src_path, &dst_path)?);
+                linked.extend(Self::collect_linked_recursive(&src_path, &dst_path, guard)?);
                 continue;
             }
 
@@ -365,40 +722,50 @@ impl Linker {
                 let resolved = if target.is_relative() {
                     dst_path.parent().unwrap_or(Path::new("")).join(&target)
                 } else {
-                    target
+                    target.clone()
                 };
                 if fs::canonicalize(&resolved).ok() == fs::canonicalize(&src_path).ok() {
                     linked.push(LinkedFile {
                         link_path: dst_path,
                         target_path: src_path,
+                        link_target: Some(target),
+                        kind: KegFileKind::Symlink,
                     });
                 }
+            } else if is_wrapper_for(&dst_path, &src_path) {
+                linked.push(LinkedFile {
+                    link_path: dst_path,
+                    target_path: src_path,
+                    link_target: None,
+                    kind: KegFileKind::Wrapper,
+                });
             }
         }
+        guard.leave();
         Ok(linked)
     }
 
     fn unlink_opt(&self, keg_path: &Path) -> Result<(), Error> {
-        let name = keg_path
-            .parent()
-            .and_then(|p| p.file_name())
-            .and_then(|n| n.to_str());
-        if let Some(name) = name {
-            let opt_link = self.opt_dir.join(name);
-            if let Ok(target) = fs::read_link(&opt_link) {
-                let resolved = if target.is_relative() {
-                    opt_link.parent().unwrap_or(Path::new("")).join(&target)
-                } else {
-                    target
-                };
-                if fs::canonicalize(&resolved).ok() == fs::canonicalize(keg_path).ok() {
-                    let _ = fs::remove_file(&opt_link);
-                }
-            }
+        if let Some(opt_link) = self.opt_link(keg_path) {
+            let _ = fs::remove_file(&opt_link);
         }
         Ok(())
     }
 
+    /// The `opt` link for the keg's formula, if it currently points at this
+    /// keg; unlinking the keg removes it.
+    pub fn opt_link(&self, keg_path: &Path) -> Option<PathBuf> {
+        let name = keg_path.parent()?.file_name()?.to_str()?;
+        let opt_link = self.opt_dir.join(name);
+        let target = fs::read_link(&opt_link).ok()?;
+        let resolved = if target.is_relative() {
+            opt_link.parent().unwrap_or(Path::new("")).join(&target)
+        } else {
+            target
+        };
+        (fs::canonicalize(&resolved).ok() == fs::canonicalize(keg_path).ok()).then_some(opt_link)
+    }
+
     pub fn link_opt(&self, keg_path: &Path) -> Result<(), Error> {
         let name = keg_path
             .parent()
@@ -422,7 +789,7 @@ impl Linker {
             let _ = fs::remove_file(&opt_link);
         }
         #[cfg(unix)]
-        std::os::unix::fs::symlink(keg_path, &opt_link)
+        std::os::unix::fs::symlink(symlink_target(&self.prefix, &opt_link, keg_path), &opt_link)
             .map_err(Error::store("failed to create opt symlink"))?;
         Ok(())
     }
@@ -449,6 +816,17 @@ impl Linker {
         }
         false
     }
+
+    /// Symlink loops under the directories kegs are linked into, such as
+    /// a user's `share/bad -> ..`. Nothing is followed while looking.
+    pub fn symlink_loops(&self) -> Vec<PathBuf> {
+        LINK_DIRS
+            .iter()
+            .map(|dir| self.prefix.join(dir))
+            .chain([self.opt_dir.clone()])
+            .flat_map(|dir| walk::find_symlink_loops(&dir))
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -467,6 +845,216 @@ mod tests {
         keg_path
     }
 
+    fn record(file: &LinkedFile) -> KegFileRecord {
+        KegFileRecord {
+            name: "foo".into(),
+            version: "1.0.0".into(),
+            linked_path: file.link_path.to_string_lossy().into_owned(),
+            target_path: file.target_path.to_string_lossy().into_owned(),
+            link_target: None,
+            kind: file.kind,
+        }
+    }
+
+    #[test]
+    fn planning_classifies_links_without_touching_the_prefix() {
+        let tmp = TempDir::new().unwrap();
+        let keg = setup_keg(&tmp, "foo");
+        for dir in [
+            "share/man/man1",
+            "share/zsh/site-functions",
+            "lib",
+            "libexec",
+        ] {
+            fs::create_dir_all(keg.join(dir)).unwrap();
+        }
+        fs::write(keg.join("share/man/man1/foo.1"), b"man").unwrap();
+        fs::write(keg.join("share/zsh/site-functions/_foo"), b"comp").unwrap();
+        fs::write(keg.join("lib/libfoo.a"), b"lib").unwrap();
+        fs::write(keg.join("libexec/pyvenv.cfg"), b"home=/").unwrap();
+        let prefix = tmp.path().join("prefix");
+
+        let plan = LinkPlan::new(&prefix, &keg).unwrap();
+        assert!(!prefix.exists());
+
+        let class = |path: &str| {
+            plan.entries
+                .iter()
+                .find(|entry| entry.file.link_path == prefix.join(path))
+                .map(|entry| (entry.class, entry.file.kind))
+        };
+        assert_eq!(
+            class("bin/foo"),
+            Some((LinkClass::Bin, KegFileKind::Symlink))
+        );
+        assert_eq!(
+            class("share/man/man1/foo.1"),
+            Some((LinkClass::Man, KegFileKind::Symlink))
+        );
+        assert_eq!(class("share/man"), Some((LinkClass::Man, KegFileKind::Dir)));
+        assert_eq!(
+            class("share/zsh/site-functions/_foo"),
+            Some((LinkClass::Completion, KegFileKind::Symlink))
+        );
+        assert_eq!(
+            class("share/zsh"),
+            Some((LinkClass::Share, KegFileKind::Dir))
+        );
+        assert_eq!(
+            class("lib/libfoo.a"),
+            Some((LinkClass::Lib, KegFileKind::Symlink))
+        );
+        assert_eq!(class("libexec/pyvenv.cfg"), None);
+
+        // Directories come before what goes in them.
+        let position = |path: &str| {
+            plan.entries
+                .iter()
+                .position(|entry| entry.file.link_path == prefix.join(path))
+                .unwrap()
+        };
+        assert!(position("share/man") < position("share/man/man1"));
+        assert!(position("share/man/man1") < position("share/man/man1/foo.1"));
+    }
+
+    #[test]
+    fn planned_wrappers_are_the_executables_directly_in_bin() {
+        let tmp = TempDir::new().unwrap();
+        let keg = setup_keg(&tmp, "git");
+        fs::create_dir_all(keg.join("libexec/git-core")).unwrap();
+        fs::write(keg.join("libexec/git-core/git-remote-https"), b"hi").unwrap();
+        let prefix = tmp.path().join("prefix");
+
+        let plan = LinkPlan::new(&prefix, &keg).unwrap();
+        let kind = |path: &str| {
+            plan.entries
+                .iter()
+                .find(|entry| entry.file.link_path == prefix.join(path))
+                .map(|entry| (entry.file.kind, entry.file.link_target.is_some()))
+        };
+        assert_eq!(kind("bin/git"), Some((KegFileKind::Wrapper, false)));
+        assert_eq!(
+            kind("libexec/git-core/git-remote-https"),
+            Some((KegFileKind::Symlink, true))
+        );
+
+        let linker = Linker::new(&prefix).unwrap();
+        let linked = linker.apply(&plan).unwrap();
+        let planned: Vec<_> = plan
+            .entries
+            .iter()
+            .map(|e| e.file.link_path.clone())
+            .collect();
+        let applied: Vec<_> = linked.iter().map(|f| f.link_path.clone()).collect();
+        assert_eq!(planned, applied);
+        assert!(
+            plan.diff(&linked.iter().map(record).collect::<Vec<_>>())
+                .is_clean()
+        );
+    }
+
+    #[test]
+    fn diff_reports_missing_unrecorded_and_stale_links() {
+        let tmp = TempDir::new().unwrap();
+        let keg = setup_keg(&tmp, "foo");
+        fs::write(keg.join("bin/foo-extra"), b"hi").unwrap();
+        let prefix = tmp.path().join("prefix");
+        let linker = Linker::new(&prefix).unwrap();
+        let plan = linker.plan(&keg).unwrap();
+
+        // Nothing linked or recorded yet.
+        let diff = plan.diff(&[]);
+        assert_eq!(diff.missing.len(), 2);
+        assert!(diff.unrecorded.is_empty() && diff.stale.is_empty());
+
+        let linked = linker.apply(&plan).unwrap();
+        let diff = plan.diff(&[]);
+        assert!(diff.missing.is_empty());
+        assert_eq!(diff.unrecorded.len(), 2);
+
+        let mut records: Vec<_> = linked.iter().map(record).collect();
+        assert!(plan.diff(&records).is_clean());
+
+        // A link removed by hand, and a record for a file the keg dropped.
+        fs::remove_file(prefix.join("bin/foo-extra")).unwrap();
+        let mut gone = record(&linked[0]);
+        gone.linked_path = prefix.join("bin/foo-old").to_string_lossy().into_owned();
+        records.push(gone);
+        let mut config = record(&linked[0]);
+        config.linked_path = prefix.join("etc/foo.conf").to_string_lossy().into_owned();
+        config.kind = KegFileKind::Config;
+        records.push(config);
+
+        let diff = plan.diff(&records);
+        assert_eq!(diff.missing.len(), 1);
+        assert_eq!(diff.missing[0].file.link_path, prefix.join("bin/foo-extra"));
+        assert!(diff.unrecorded.is_empty());
+        assert_eq!(diff.stale.len(), 1);
+        assert_eq!(
+            diff.stale[0].linked_path,
+            prefix.join("bin/foo-old").to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn diff_treats_a_link_to_another_keg_as_missing() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let linker = Linker::new(&prefix).unwrap();
+        let other = setup_keg(&tmp, "other");
+        std::os::unix::fs::symlink(other.join("bin/other"), prefix.join("bin/foo")).unwrap();
+
+        let keg = setup_keg(&tmp, "foo");
+        let diff = linker.plan(&keg).unwrap().diff(&[]);
+        assert_eq!(diff.missing.len(), 1);
+        assert!(linker.link_keg(&keg).is_err());
+    }
+
+    #[test]
+    fn links_inside_the_prefix_are_relative_and_survive_moving_it() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let keg = prefix.join("Cellar/foo/1.0.0");
+        fs::create_dir_all(keg.join("bin")).unwrap();
+        fs::write(keg.join("bin/foo"), b"hi").unwrap();
+        let linker = Linker::new(&prefix).unwrap();
+
+        let linked = linker.link_keg(&keg).unwrap();
+        let link = prefix.join("bin/foo");
+        let literal = fs::read_link(&link).unwrap();
+        assert_eq!(literal, Path::new("../Cellar/foo/1.0.0/bin/foo"));
+        let file = linked.iter().find(|f| f.link_path == link).unwrap();
+        assert_eq!(file.link_target.as_deref(), Some(literal.as_path()));
+        assert_eq!(file.target_path, keg.join("bin/foo"));
+        assert_eq!(
+            fs::read_link(prefix.join("opt/foo")).unwrap(),
+            Path::new("../Cellar/foo/1.0.0")
+        );
+
+        // Relinking finds the relative link already in place.
+        let relinked = linker.collect_linked_files(&keg).unwrap();
+        assert_eq!(relinked[0].link_target.as_deref(), Some(literal.as_path()));
+
+        let moved = tmp.path().join("moved");
+        fs::rename(&prefix, &moved).unwrap();
+        assert_eq!(fs::read(moved.join("bin/foo")).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn links_to_kegs_outside_the_prefix_are_absolute() {
+        let tmp = TempDir::new().unwrap();
+        let keg = setup_keg(&tmp, "foo");
+        let prefix = tmp.path().join("prefix");
+        let linker = Linker::new(&prefix).unwrap();
+
+        let linked = linker.link_keg(&keg).unwrap();
+        assert_eq!(
+            fs::read_link(prefix.join("bin/foo")).unwrap(),
+            keg.join("bin/foo")
+        );
+        assert_eq!(linked[0].link_target, Some(keg.join("bin/foo")));
+    }
+
     #[test]
     fn links_executables_to_bin() {
         let tmp = TempDir::new().unwrap();
@@ -615,6 +1203,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_conflicts_are_reported_in_path_order() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path();
+        let linker = Linker::new(prefix).unwrap();
+
+        let names = ["zeta", "alpha", "mu", "beta", "omega"];
+        let keg1 = prefix.join("Cellar/pkg1/1.0.0");
+        let keg2 = prefix.join("Cellar/pkg2/1.0.0");
+        for keg in [&keg1, &keg2] {
+            fs::create_dir_all(keg.join("bin")).unwrap();
+            fs::create_dir_all(keg.join("share/man")).unwrap();
+            for name in names {
+                fs::write(keg.join("bin").join(name), b"x").unwrap();
+                fs::write(keg.join("share/man").join(name), b"x").unwrap();
+            }
+        }
+        linker.link_keg(&keg1).unwrap();
+
+        let first = linker.check_conflicts(&keg2).unwrap_err().to_string();
+        let second = linker.check_conflicts(&keg2).unwrap_err().to_string();
+        assert_eq!(first, second);
+
+        let Err(Error::LinkConflict { conflicts }) = linker.check_conflicts(&keg2) else {
+            panic!("expected link conflicts");
+        };
+        let paths: Vec<_> = conflicts.iter().map(|c| c.path.clone()).collect();
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!(paths, sorted);
+        assert_eq!(paths.len(), names.len() * 2);
+    }
+
     #[test]
     fn link_keg_rejects_conflicts_without_creating_links() {
         let tmp = TempDir::new().unwrap();
@@ -699,4 +1320,53 @@ mod tests {
         // Pre-flight check should pass since the files don't overlap
         assert!(linker.check_conflicts(&keg2).is_ok());
     }
+
+    #[test]
+    fn prefix_link_back_into_the_prefix_is_a_conflict() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let linker = Linker::new(&prefix).unwrap();
+        fs::create_dir_all(prefix.join("share")).unwrap();
+        std::os::unix::fs::symlink(&prefix, prefix.join("share/bad")).unwrap();
+
+        let keg = setup_keg(&tmp, "loopy");
+        fs::create_dir_all(keg.join("share/bad")).unwrap();
+        fs::write(keg.join("share/bad/file"), b"data").unwrap();
+
+        let started = std::time::Instant::now();
+        let err = linker.link_keg(&keg).unwrap_err();
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        let Error::LinkConflict { conflicts } = err else {
+            panic!("expected a link conflict, got {err:?}");
+        };
+        assert_eq!(conflicts[0].path, prefix.join("share/bad"));
+        assert!(prefix.join("share/bad").is_symlink());
+        assert_eq!(linker.symlink_loops(), [prefix.join("share/bad")]);
+    }
+
+    #[test]
+    fn keg_directory_loop_is_walked_once() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let linker = Linker::new(&prefix).unwrap();
+        let keg = setup_keg(&tmp, "loopy");
+        fs::create_dir_all(keg.join("lib/pkg")).unwrap();
+        fs::write(keg.join("lib/pkg/libloopy.a"), b"lib").unwrap();
+        std::os::unix::fs::symlink("..", keg.join("lib/pkg/up")).unwrap();
+
+        let started = std::time::Instant::now();
+        linker.link_keg(&keg).unwrap();
+        let collected = linker.collect_linked_files(&keg).unwrap();
+        let unlinked = linker.unlink_keg(&keg).unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        assert!(
+            collected
+                .iter()
+                .any(|file| file.link_path == prefix.join("lib/pkg/libloopy.a"))
+        );
+        assert!(unlinked.contains(&prefix.join("lib/pkg/libloopy.a")));
+        assert!(!prefix.join("lib/pkg/up").exists());
+    }
 }
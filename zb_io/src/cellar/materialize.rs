@@ -1,23 +1,75 @@
 use std::fs;
 use std::io;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use zb_core::Error;
-
-#[cfg(target_os = "linux")]
-use crate::extraction::patch::linux::patch_placeholders;
-
-#[cfg(target_os = "macos")]
-use crate::extraction::patch::macos::{codesign_and_strip_xattrs, patch_homebrew_placeholders};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum CopyStrategy {
-    Clonefile,
-    Hardlink,
-    Copy,
+use std::sync::Arc;
+
+use rayon::ThreadPool;
+use tracing::{debug, warn};
+use zb_core::{BottleCellar, CELLAR_DIR, Error};
+
+use crate::extraction::patch::{PatchConfig, PatchPolicy, PatchReport, Relocation};
+use crate::progress::{MaterializeObserver, NoopObserver, Phase, check_cancelled};
+use crate::storage::db::CopyStrategy;
+
+#[cfg(all(target_os = "linux", feature = "patch-elf"))]
+use crate::extraction::patch::LibraryResolver;
+#[cfg(all(target_os = "linux", feature = "patch-elf"))]
+use crate::extraction::patch::linux::{patch_placeholders, relocate_placeholders};
+
+#[cfg(all(target_os = "macos", feature = "patch-macho"))]
+use crate::extraction::patch::macos::{
+    codesign_and_strip_xattrs, patch_homebrew_placeholders, relocate_placeholders,
+};
+#[cfg(all(target_os = "macos", feature = "patch-macho"))]
+use crate::extraction::patch::scan::KegScan;
+
+#[cfg(not(any(
+    all(target_os = "macos", feature = "patch-macho"),
+    all(target_os = "linux", feature = "patch-elf")
+)))]
+use crate::extraction::patch::placeholder_files;
+
+/// What to rebuild with to get a patch backend on this platform.
+#[cfg(all(target_os = "linux", not(feature = "patch-elf")))]
+const PATCH_BACKEND_HINT: &str = " (rebuild with the patch-elf feature)";
+#[cfg(all(target_os = "macos", not(feature = "patch-macho")))]
+const PATCH_BACKEND_HINT: &str = " (rebuild with the patch-macho feature)";
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const PATCH_BACKEND_HINT: &str = " on this platform";
+
+/// Neither clonefile nor hard links cross devices, so a store entry on
+/// another device than the Cellar goes straight to copying instead of
+/// failing once per file with `EXDEV`.
+pub fn copy_strategy_for(preferred: CopyStrategy, store_dev: u64, cellar_dev: u64) -> CopyStrategy {
+    if store_dev == cellar_dev {
+        preferred
+    } else {
+        CopyStrategy::Copy
+    }
 }
 
 pub struct Cellar {
     cellar_dir: PathBuf,
+    patch_config: PatchConfig,
+    copy_strategy: CopyStrategy,
+    preserve_permissions: bool,
+    pool: Option<Arc<ThreadPool>>,
+    /// Built on first use, so the Cellar's libraries are indexed once.
+    #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+    libraries: std::sync::OnceLock<LibraryResolver>,
+}
+
+/// A keg produced by [`Cellar::materialize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Materialized {
+    pub path: PathBuf,
+    /// The keg directory name, which is the bottle's own version directory
+    /// when that differs from the version asked for (`1.2.3_1` for `1.2.3`).
+    pub version: String,
+    /// How the store entry was copied, or `None` when the keg already
+    /// existed. `Hardlink` means at least one file was hard-linked.
+    pub strategy: Option<CopyStrategy>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -29,12 +81,68 @@ pub struct MaterializedKeg {
 
 impl Cellar {
     pub fn new(root: &Path) -> io::Result<Self> {
-        Self::new_at(root.join("cellar"))
+        Self::new_at(root.join(CELLAR_DIR))
     }
 
+    /// A Cellar at `cellar_dir`. A lowercase `cellar` beside it, as older
+    /// roots had, is moved into place first.
     pub fn new_at(cellar_dir: PathBuf) -> io::Result<Self> {
+        migrate_lowercase_cellar(&cellar_dir)?;
         fs::create_dir_all(&cellar_dir)?;
-        Ok(Self { cellar_dir })
+        Ok(Self {
+            cellar_dir,
+            patch_config: PatchConfig::default(),
+            copy_strategy: CopyStrategy::Clonefile,
+            preserve_permissions: false,
+            pool: None,
+            #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+            libraries: std::sync::OnceLock::new(),
+        })
+    }
+
+    /// Use `config` for size limits and per-formula globs when patching
+    /// text files during materialization.
+    pub fn with_patch_config(mut self, config: PatchConfig) -> Self {
+        self.patch_config = config;
+        self
+    }
+
+    /// Cheapest way materialize may copy store entries. Each strategy falls
+    /// back to the ones after it, so `Copy` skips clonefile and hard links.
+    pub fn with_copy_strategy(mut self, strategy: CopyStrategy) -> Self {
+        self.copy_strategy = strategy;
+        self
+    }
+
+    /// Run patching and other parallel work on `pool` instead of rayon's
+    /// global pool, which uses every core.
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Run `op` on this Cellar's thread pool, so any rayon iterators inside
+    /// it stay within the pool's threads.
+    pub(crate) fn in_pool<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match &self.pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Override the patch policy from `patch.json`.
+    pub fn set_patch_policy(&mut self, policy: PatchPolicy) {
+        self.patch_config.policy = policy;
+    }
+
+    /// Keep group/other write bits from bottles instead of clearing them.
+    /// Setuid and setgid bits are dropped either way.
+    pub fn set_preserve_permissions(&mut self, preserve: bool) {
+        self.preserve_permissions = preserve;
+    }
+
+    pub fn cellar_dir(&self) -> &Path {
+        &self.cellar_dir
     }
 
     pub fn keg_path(&self, name: &str, version: &str) -> PathBuf {
@@ -87,19 +195,67 @@ impl Cellar {
             }
         }
 
+        kegs.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
         Ok(kegs)
     }
 
+    /// Copy a store entry into the Cellar and relocate it. `bottle_cellar`
+    /// is the bottle's `cellar` declaration and decides which patch passes run.
+    /// The keg is named after the bottle's version directory, which may not
+    /// be `version`; see [`Materialized::version`].
     pub fn materialize(
         &self,
         name: &str,
         version: &str,
         store_entry: &Path,
-    ) -> Result<PathBuf, Error> {
+        bottle_cellar: &BottleCellar,
+    ) -> Result<Materialized, Error> {
+        self.materialize_with_observer(name, version, store_entry, bottle_cellar, &NoopObserver)
+    }
+
+    /// [`Self::materialize`], reporting copied files and phases to
+    /// `observer`. When it asks to cancel, the partial keg is removed and
+    /// [`Error::Cancelled`] returned; so is a keg that failed to patch
+    /// under [`PatchPolicy::Strict`], or failed in any other way.
+    pub fn materialize_with_observer(
+        &self,
+        name: &str,
+        requested: &str,
+        store_entry: &Path,
+        bottle_cellar: &BottleCellar,
+        observer: &dyn MaterializeObserver,
+    ) -> Result<Materialized, Error> {
+        // The keg and every patched path use the directory the bottle was
+        // built into, so the two can never disagree.
+        let version = &bottle_keg_version(store_entry, name, requested);
+        if version != requested {
+            debug!(
+                formula = name,
+                requested,
+                keg = %version,
+                "bottle names its keg directory differently; using the bottle's"
+            );
+        }
         let keg_path = self.keg_path(name, version);
 
+        // A keg under the bottle's name may belong to another release of
+        // the formula; reusing it would install that release instead.
+        if version != requested && keg_path.exists() {
+            return Err(Error::ExecutionError {
+                message: format!(
+                    "the bottle for {name} {requested} installs into {}, which another keg already uses; \
+                     uninstall {name} {version} first",
+                    keg_path.display()
+                ),
+            });
+        }
         if keg_path.exists() {
-            return Ok(keg_path);
+            return Ok(Materialized {
+                path: keg_path,
+                version: version.clone(),
+                strategy: None,
+            });
         }
 
         // Create parent directory for the keg
@@ -108,38 +264,139 @@ impl Cellar {
                 .map_err(Error::store("failed to create keg parent directory"))?;
         }
 
+        let result =
+            self.in_pool(|| self.populate_keg(name, version, store_entry, bottle_cellar, observer));
+        if let Err(e) = &result {
+            debug!(formula = name, error = %e, "materialize failed; removing partial keg");
+            self.remove_keg(name, version)?;
+        }
+        result
+    }
+
+    fn populate_keg(
+        &self,
+        name: &str,
+        version: &str,
+        store_entry: &Path,
+        bottle_cellar: &BottleCellar,
+        observer: &dyn MaterializeObserver,
+    ) -> Result<Materialized, Error> {
+        let keg_path = self.keg_path(name, version);
+
         // Homebrew bottles have structure {name}/{version}/ inside
         // Find the source directory to copy from
         let src_path = find_bottle_content(store_entry, name, version)?;
 
         // Copy the content to the cellar using best available strategy
-        copy_dir_with_fallback(&src_path, &keg_path)?;
+        let strategy = match (fs::metadata(&src_path), fs::metadata(&self.cellar_dir)) {
+            (Ok(store), Ok(cellar)) => {
+                copy_strategy_for(self.copy_strategy, store.dev(), cellar.dev())
+            }
+            _ => self.copy_strategy,
+        };
+        if strategy != self.copy_strategy {
+            debug!(
+                formula = name,
+                "store entry is on another device than the Cellar; copying"
+            );
+        }
+        observer.on_phase(Phase::Copy);
+        let strategy = copy_dir_with_fallback(&src_path, &keg_path, strategy, observer)?;
+        check_cancelled(observer)?;
+
+        observer.on_phase(Phase::Permissions);
+        let mut permissions = PermissionReport::default();
+        sanitize_permissions(&keg_path, self.preserve_permissions, &mut permissions)?;
+        if !permissions.setid_dropped.is_empty() {
+            warn!(
+                formula = name,
+                files = ?permissions.setid_dropped,
+                "bottle shipped setuid/setgid files; the bits were dropped"
+            );
+        }
+        check_cancelled(observer)?;
+
+        let relocation = Relocation::for_bottle(bottle_cellar, &self.cellar_dir);
+        if let Relocation::CellarMismatch { built_for } = &relocation {
+            warn!(
+                formula = name,
+                built_for = %built_for,
+                cellar = %self.cellar_dir.display(),
+                "bottle was built for a different Cellar; forcing full relocation"
+            );
+        }
+
+        // Nothing rewrites the keg without a patch backend, so refuse one
+        // that needs it rather than pour a keg that can't run.
+        #[cfg(not(any(
+            all(target_os = "macos", feature = "patch-macho"),
+            all(target_os = "linux", feature = "patch-elf")
+        )))]
+        {
+            let unpatched = placeholder_files(&keg_path)?;
+            if !unpatched.is_empty() || matches!(relocation, Relocation::CellarMismatch { .. }) {
+                let mut message = format!(
+                    "'{name}' needs relocating, but this build of zerobrew can't patch kegs{}",
+                    PATCH_BACKEND_HINT
+                );
+                for file in &unpatched {
+                    message.push_str(&format!("\n  '{}'", file.display()));
+                }
+                return Err(Error::ExecutionError { message });
+            }
+        }
+
+        // One walk of the keg feeds patching and signing.
+        #[cfg(all(target_os = "macos", feature = "patch-macho"))]
+        let scan = KegScan::new(&keg_path);
 
         // Patch Homebrew placeholders in Mach-O binaries
-        #[cfg(target_os = "macos")]
-        patch_homebrew_placeholders(&keg_path, &self.cellar_dir, name, version)?;
+        #[cfg(all(target_os = "macos", feature = "patch-macho"))]
+        let report = patch_homebrew_placeholders(
+            &keg_path,
+            &scan,
+            &self.cellar_dir,
+            name,
+            version,
+            &self.patch_config,
+            relocation,
+            observer,
+        )?;
 
         // Patch Homebrew placeholders in ELF binaries
-        #[cfg(target_os = "linux")]
+        #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+        let report = patch_placeholders(
+            &keg_path,
+            self.prefix_dir()?,
+            name,
+            version,
+            &self.patch_config,
+            relocation,
+            self.libraries()?,
+            observer,
+        )?;
+
+        #[cfg(any(
+            all(target_os = "macos", feature = "patch-macho"),
+            all(target_os = "linux", feature = "patch-elf")
+        ))]
         {
-            // Derive prefix from cellar_dir directly without hardcoded fallback
-            let prefix = self
-                .cellar_dir
-                .parent()
-                .ok_or_else(|| Error::StoreCorruption {
-                    message: format!(
-                        "Invalid cellar directory (no parent): {}",
-                        self.cellar_dir.display()
-                    ),
-                })?;
-            patch_placeholders(&keg_path, prefix, name, version)?;
+            log_patch_report(name, &report, &permissions);
+            report.enforce(name, self.patch_config.policy)?;
         }
 
         // Strip quarantine xattrs and ad-hoc sign Mach-O binaries
-        #[cfg(target_os = "macos")]
-        codesign_and_strip_xattrs(&keg_path)?;
+        #[cfg(all(target_os = "macos", feature = "patch-macho"))]
+        {
+            observer.on_phase(Phase::Codesign);
+            codesign_and_strip_xattrs(&keg_path, &scan)?;
+        }
 
-        Ok(keg_path)
+        Ok(Materialized {
+            path: keg_path,
+            version: version.to_string(),
+            strategy: Some(strategy),
+        })
     }
 
     pub fn remove_keg(&self, name: &str, version: &str) -> Result<(), Error> {
@@ -158,6 +415,278 @@ impl Cellar {
 
         Ok(())
     }
+
+    /// A Cellar at `cellar_dir` with the same patch and copy settings, for
+    /// moving kegs to another prefix.
+    pub fn relocated(&self, cellar_dir: PathBuf) -> io::Result<Self> {
+        let mut cellar = Self::new_at(cellar_dir)?
+            .with_patch_config(self.patch_config.clone())
+            .with_copy_strategy(self.copy_strategy);
+        cellar.set_preserve_permissions(self.preserve_permissions);
+        Ok(cellar)
+    }
+
+    /// Copy the keg `name`/`version` from `from_cellar`, the Cellar of
+    /// `old_prefix`, into this Cellar and rewrite its paths under
+    /// `old_prefix` to this Cellar's prefix. A partial keg left by an
+    /// interrupted run is replaced; the source keg is not touched.
+    pub fn relocate_keg(
+        &self,
+        name: &str,
+        version: &str,
+        from_cellar: &Path,
+        old_prefix: &Path,
+    ) -> Result<PathBuf, Error> {
+        self.in_pool(|| self.copy_and_relocate_keg(name, version, from_cellar, old_prefix))
+    }
+
+    fn copy_and_relocate_keg(
+        &self,
+        name: &str,
+        version: &str,
+        from_cellar: &Path,
+        old_prefix: &Path,
+    ) -> Result<PathBuf, Error> {
+        let keg_path = self.keg_path(name, version);
+        if keg_path.exists() {
+            fs::remove_dir_all(&keg_path)
+                .map_err(Error::store("failed to remove partially relocated keg"))?;
+        }
+        if let Some(parent) = keg_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(Error::store("failed to create keg parent directory"))?;
+        }
+
+        // Never hard-link: the copy is patched while the source keg, and
+        // the store entries it may share inodes with, stay as they are.
+        clone_or_copy_dir(&from_cellar.join(name).join(version), &keg_path)?;
+        retarget_symlinks(&keg_path, old_prefix, self.prefix_dir()?)?;
+
+        #[cfg(all(target_os = "macos", feature = "patch-macho"))]
+        let scan = KegScan::new(&keg_path);
+
+        #[cfg(all(target_os = "macos", feature = "patch-macho"))]
+        let report = relocate_placeholders(
+            &keg_path,
+            &scan,
+            &self.cellar_dir,
+            old_prefix,
+            name,
+            version,
+            &self.patch_config,
+            &NoopObserver,
+        )?;
+
+        #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+        let report = relocate_placeholders(
+            &keg_path,
+            self.prefix_dir()?,
+            old_prefix,
+            name,
+            version,
+            &self.patch_config,
+            self.libraries()?,
+            &NoopObserver,
+        )?;
+
+        #[cfg(any(
+            all(target_os = "macos", feature = "patch-macho"),
+            all(target_os = "linux", feature = "patch-elf")
+        ))]
+        {
+            log_patch_report(name, &report, &PermissionReport::default());
+            if let Err(e) = report.enforce(name, self.patch_config.policy) {
+                let _ = fs::remove_dir_all(&keg_path);
+                return Err(e);
+            }
+        }
+
+        #[cfg(all(target_os = "macos", feature = "patch-macho"))]
+        codesign_and_strip_xattrs(&keg_path, &scan)?;
+
+        Ok(keg_path)
+    }
+
+    /// Resolves the libraries ELF files in this Cellar's kegs need.
+    #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+    pub(crate) fn libraries(&self) -> Result<&LibraryResolver, Error> {
+        let prefix_dir = self.prefix_dir()?;
+        Ok(self
+            .libraries
+            .get_or_init(|| LibraryResolver::new(prefix_dir)))
+    }
+
+    /// The prefix this Cellar lives in.
+    fn prefix_dir(&self) -> Result<&Path, Error> {
+        // Derive prefix from cellar_dir directly without hardcoded fallback
+        self.cellar_dir
+            .parent()
+            .ok_or_else(|| Error::StoreCorruption {
+                message: format!(
+                    "Invalid cellar directory (no parent): {}",
+                    self.cellar_dir.display()
+                ),
+            })
+    }
+}
+
+/// Move a lowercase `cellar` directory beside `cellar_dir` to `cellar_dir`
+/// and leave a `cellar` symlink behind, so paths already patched into its
+/// kegs keep resolving. When it can't be moved, `cellar_dir` becomes a
+/// symlink to it instead. On a case-insensitive filesystem both names are
+/// the same directory and nothing changes.
+fn migrate_lowercase_cellar(cellar_dir: &Path) -> io::Result<()> {
+    let lowercase = CELLAR_DIR.to_ascii_lowercase();
+    let Some(parent) = cellar_dir.parent() else {
+        return Ok(());
+    };
+    if cellar_dir.file_name() != Some(std::ffi::OsStr::new(CELLAR_DIR))
+        || cellar_dir.symlink_metadata().is_ok()
+    {
+        return Ok(());
+    }
+    let legacy = parent.join(&lowercase);
+    if !legacy.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+        return Ok(());
+    }
+
+    match fs::rename(&legacy, cellar_dir) {
+        Ok(()) => {
+            warn!(
+                from = %legacy.display(),
+                to = %cellar_dir.display(),
+                "moved lowercase cellar directory"
+            );
+            std::os::unix::fs::symlink(CELLAR_DIR, &legacy)
+        }
+        Err(e) => {
+            warn!(
+                cellar = %legacy.display(),
+                error = %e,
+                "could not move lowercase cellar directory; linking to it instead"
+            );
+            std::os::unix::fs::symlink(&lowercase, cellar_dir)
+        }
+    }
+}
+
+/// Point absolute symlinks under `old_prefix` inside a keg at the same
+/// path under `new_prefix`.
+fn retarget_symlinks(keg_path: &Path, old_prefix: &Path, new_prefix: &Path) -> Result<(), Error> {
+    for entry in walkdir::WalkDir::new(keg_path).follow_links(false) {
+        let entry = entry.map_err(|e| Error::StoreCorruption {
+            message: format!("failed to walk {}: {e}", keg_path.display()),
+        })?;
+        if !entry.path_is_symlink() {
+            continue;
+        }
+        let target = fs::read_link(entry.path()).map_err(Error::store("failed to read symlink"))?;
+        let Ok(rest) = target.strip_prefix(old_prefix) else {
+            continue;
+        };
+        let ctx = format!("failed to retarget symlink {}", entry.path().display());
+        fs::remove_file(entry.path()).map_err(Error::store(ctx.as_str()))?;
+        std::os::unix::fs::symlink(new_prefix.join(rest), entry.path())
+            .map_err(Error::store(ctx.as_str()))?;
+    }
+    Ok(())
+}
+
+#[cfg_attr(
+    not(any(
+        all(target_os = "macos", feature = "patch-macho"),
+        all(target_os = "linux", feature = "patch-elf")
+    )),
+    allow(dead_code)
+)]
+fn log_patch_report(name: &str, report: &PatchReport, permissions: &PermissionReport) {
+    debug!(
+        formula = name,
+        relocation = ?report.relocation,
+        text_patched = report.text.patched,
+        text_skipped_by_size = report.text.skipped_by_size,
+        signature_failures = report.signature_failures.len(),
+        permissions_sanitized = permissions.sanitized,
+        setid_dropped = permissions.setid_dropped.len(),
+        "patched keg"
+    );
+}
+
+/// What [`sanitize_permissions`] changed in a keg.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PermissionReport {
+    /// Entries that lost group/other write bits.
+    pub sanitized: usize,
+    /// Entries whose setuid/setgid bits were dropped.
+    pub setid_dropped: Vec<PathBuf>,
+}
+
+/// Strip modes a shared prefix should never inherit from a bottle: setuid
+/// and setgid always, and group/other write bits unless `preserve` is set.
+/// Bits are only ever cleared. Symlinks are left alone, since chmod would
+/// follow them.
+///
+/// Hard-linked files share their mode with the store entry, which is
+/// sanitized along with the keg.
+fn sanitize_permissions(
+    path: &Path,
+    preserve: bool,
+    report: &mut PermissionReport,
+) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata =
+        fs::symlink_metadata(path).map_err(Error::store("failed to read permissions"))?;
+    if metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+
+    if metadata.is_dir() {
+        let read_ctx = format!("failed to read directory {}", path.display());
+        for entry in fs::read_dir(path).map_err(Error::store(read_ctx.as_str()))? {
+            let entry = entry.map_err(Error::store("failed to read directory entry"))?;
+            sanitize_permissions(&entry.path(), preserve, report)?;
+        }
+    }
+
+    let mode = metadata.permissions().mode();
+    let mut cleared = mode & 0o6000;
+    if cleared != 0 {
+        report.setid_dropped.push(path.to_path_buf());
+    }
+    if !preserve && mode & 0o022 != 0 {
+        cleared |= mode & 0o022;
+        report.sanitized += 1;
+    }
+    if cleared != 0 {
+        let ctx = format!("failed to set permissions on {}", path.display());
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777 & !cleared))
+            .map_err(Error::store(ctx.as_str()))?;
+    }
+
+    Ok(())
+}
+
+/// The version directory a bottle keeps its keg in: `version` when the
+/// store entry has `{name}/{version}`, else the only directory under
+/// `{name}/`, else `version`.
+fn bottle_keg_version(store_entry: &Path, name: &str, version: &str) -> String {
+    let name_path = store_entry.join(name);
+    if name_path.join(version).is_dir() {
+        return version.to_string();
+    }
+
+    let dirs: Vec<String> = fs::read_dir(&name_path)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect();
+    match <[String; 1]>::try_from(dirs) {
+        Ok([dir]) => dir,
+        Err(_) => version.to_string(),
+    }
 }
 
 /// Find the bottle content directory inside a store entry.
@@ -191,17 +720,42 @@ fn find_bottle_content(store_entry: &Path, name: &str, version: &str) -> Result<
     Ok(store_entry.to_path_buf())
 }
 
-fn copy_dir_with_fallback(src: &Path, dst: &Path) -> Result<(), Error> {
-    // Try clonefile first (APFS), then hardlink, then copy
+/// Copy `src` to `dst` without ever hard-linking, for copies whose files
+/// will change independently of `src`. Clones where the filesystem allows.
+pub(crate) fn clone_or_copy_dir(src: &Path, dst: &Path) -> Result<CopyStrategy, Error> {
     #[cfg(target_os = "macos")]
     {
         if try_clonefile_dir(src, dst).is_ok() {
-            return Ok(());
+            return Ok(CopyStrategy::Clonefile);
+        }
+    }
+
+    copy_dir_recursive(src, dst, false, &NoopObserver)?;
+    Ok(CopyStrategy::Copy)
+}
+
+/// Returns the strategy that actually produced `dst`.
+fn copy_dir_with_fallback(
+    src: &Path,
+    dst: &Path,
+    strategy: CopyStrategy,
+    observer: &dyn MaterializeObserver,
+) -> Result<CopyStrategy, Error> {
+    // Try clonefile first (APFS), then hardlink, then copy
+    #[cfg(target_os = "macos")]
+    {
+        if strategy == CopyStrategy::Clonefile && try_clonefile_dir(src, dst).is_ok() {
+            return Ok(CopyStrategy::Clonefile);
         }
     }
 
     // Fall back to recursive copy with hardlink/copy per file
-    copy_dir_recursive(src, dst, true)
+    let hardlinked = copy_dir_recursive(src, dst, strategy != CopyStrategy::Copy, observer)?;
+    Ok(if hardlinked > 0 {
+        CopyStrategy::Hardlink
+    } else {
+        CopyStrategy::Copy
+    })
 }
 
 #[cfg(target_os = "macos")]
@@ -229,13 +783,49 @@ fn try_clonefile_dir(src: &Path, dst: &Path) -> io::Result<()> {
     }
 }
 
-fn copy_dir_recursive(src: &Path, dst: &Path, try_hardlink: bool) -> Result<(), Error> {
-    let create_ctx = format!("failed to create directory {}", dst.display());
-    fs::create_dir_all(dst).map_err(Error::store(create_ctx.as_str()))?;
+/// An error for `action` failing on `dst`, naming `src` too when there is
+/// one. Deep kegs can produce destinations the system refuses as too long;
+/// the message then says how long, so the offending path is plain.
+fn copy_error<'a>(
+    action: &'static str,
+    src: Option<&'a Path>,
+    dst: &'a Path,
+) -> impl FnOnce(io::Error) -> Error + 'a {
+    move |err| {
+        let paths = match src {
+            Some(src) => format!("{} to {}", src.display(), dst.display()),
+            None => dst.display().to_string(),
+        };
+        let length = if err.raw_os_error() == Some(libc::ENAMETOOLONG) {
+            format!(
+                " (the destination is {} bytes in {} components)",
+                dst.as_os_str().len(),
+                dst.components().count()
+            )
+        } else {
+            String::new()
+        };
+        Error::StoreCorruption {
+            message: format!("failed to {action} {paths}: {err}{length}"),
+        }
+    }
+}
+
+/// Returns how many files were hard-linked rather than copied. Stops with
+/// [`Error::Cancelled`] before the next entry once `observer` asks to.
+fn copy_dir_recursive(
+    src: &Path,
+    dst: &Path,
+    try_hardlink: bool,
+    observer: &dyn MaterializeObserver,
+) -> Result<usize, Error> {
+    let mut hardlinked = 0;
+    fs::create_dir_all(dst).map_err(copy_error("create directory", None, dst))?;
 
     let read_ctx = format!("failed to read directory {}", src.display());
     for entry in fs::read_dir(src).map_err(Error::store(read_ctx.as_str()))? {
         let entry = entry.map_err(Error::store("failed to read directory entry"))?;
+        check_cancelled(observer)?;
 
         let src_path = entry.path();
         let dst_path = dst.join(entry.file_name());
@@ -244,26 +834,39 @@ fn copy_dir_recursive(src: &Path, dst: &Path, try_hardlink: bool) -> Result<(),
             .map_err(Error::store("failed to get file type"))?;
 
         if file_type.is_dir() {
-            copy_dir_recursive(&src_path, &dst_path, try_hardlink)?;
+            hardlinked += copy_dir_recursive(&src_path, &dst_path, try_hardlink, observer)?;
         } else if file_type.is_symlink() {
             let target =
                 fs::read_link(&src_path).map_err(Error::store("failed to read symlink"))?;
 
             #[cfg(unix)]
-            std::os::unix::fs::symlink(&target, &dst_path)
-                .map_err(Error::store("failed to create symlink"))?;
+            std::os::unix::fs::symlink(&target, &dst_path).map_err(copy_error(
+                "copy symlink",
+                Some(&src_path),
+                &dst_path,
+            ))?;
 
             #[cfg(not(unix))]
-            fs::copy(&src_path, &dst_path)
-                .map_err(Error::store("failed to copy symlink as file"))?;
+            fs::copy(&src_path, &dst_path).map_err(copy_error(
+                "copy symlink as file",
+                Some(&src_path),
+                &dst_path,
+            ))?;
         } else {
             // Try hardlink first, then copy
             if try_hardlink && fs::hard_link(&src_path, &dst_path).is_ok() {
+                hardlinked += 1;
+                let bytes = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                observer.on_file_copied(&dst_path, bytes);
                 continue;
             }
 
             // Fall back to copy
-            fs::copy(&src_path, &dst_path).map_err(Error::store("failed to copy file"))?;
+            let bytes = fs::copy(&src_path, &dst_path).map_err(copy_error(
+                "copy",
+                Some(&src_path),
+                &dst_path,
+            ))?;
 
             // Preserve permissions
             #[cfg(unix)]
@@ -273,21 +876,23 @@ fn copy_dir_recursive(src: &Path, dst: &Path, try_hardlink: bool) -> Result<(),
                 fs::set_permissions(&dst_path, metadata.permissions())
                     .map_err(Error::store("failed to set permissions"))?;
             }
+            observer.on_file_copied(&dst_path, bytes);
         }
     }
 
-    Ok(())
+    Ok(hardlinked)
 }
 
 // For testing - copy without fallback strategies
 #[cfg(test)]
 fn copy_dir_copy_only(src: &Path, dst: &Path) -> Result<(), Error> {
-    copy_dir_recursive(src, dst, false)
+    copy_dir_recursive(src, dst, false, &NoopObserver).map(|_| ())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::progress::PatchCounter;
     use std::os::unix::fs::PermissionsExt;
     use tempfile::TempDir;
 
@@ -315,13 +920,80 @@ mod tests {
         store_entry
     }
 
+    #[test]
+    #[cfg(any(
+        all(target_os = "linux", feature = "patch-elf"),
+        all(target_os = "macos", feature = "patch-macho")
+    ))]
+    fn skip_relocation_bottles_still_get_text_placeholders() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = tmp.path().join("store/def456");
+        fs::create_dir_all(store_entry.join("bin")).unwrap();
+        fs::write(
+            store_entry.join("bin/tool"),
+            "#!/bin/sh\nexec @@HOMEBREW_PREFIX@@/libexec/tool\n",
+        )
+        .unwrap();
+
+        let cellar = Cellar::new_at(tmp.path().join("prefix/Cellar")).unwrap();
+        let keg_path = cellar
+            .materialize(
+                "tool",
+                "1.0.0",
+                &store_entry,
+                &BottleCellar::AnySkipRelocation,
+            )
+            .unwrap()
+            .path;
+
+        let script = fs::read_to_string(keg_path.join("bin/tool")).unwrap();
+        assert!(!script.contains("@@HOMEBREW_PREFIX@@"));
+        assert!(script.contains(tmp.path().join("prefix").to_str().unwrap()));
+    }
+
+    #[test]
+    #[cfg(not(any(
+        all(target_os = "linux", feature = "patch-elf"),
+        all(target_os = "macos", feature = "patch-macho")
+    )))]
+    fn kegs_needing_a_patch_backend_are_refused_without_one() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = tmp.path().join("store/def456");
+        fs::create_dir_all(store_entry.join("bin")).unwrap();
+        fs::write(
+            store_entry.join("bin/tool"),
+            "#!/bin/sh\nexec @@HOMEBREW_PREFIX@@/libexec/tool\n",
+        )
+        .unwrap();
+        fs::write(store_entry.join("bin/plain"), "#!/bin/sh\necho plain\n").unwrap();
+
+        let cellar = Cellar::new_at(tmp.path().join("prefix/Cellar")).unwrap();
+        let err = cellar
+            .materialize(
+                "tool",
+                "1.0.0",
+                &store_entry,
+                &BottleCellar::AnySkipRelocation,
+            )
+            .unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("'tool' needs relocating"), "{message}");
+        assert!(message.contains("bin/tool"), "{message}");
+        assert!(!message.contains("bin/plain"), "{message}");
+        assert!(!cellar.keg_path("tool", "1.0.0").exists());
+    }
+
     #[test]
     fn tree_reproduced_exactly() {
         let tmp = TempDir::new().unwrap();
         let store_entry = setup_store_entry(&tmp);
 
         let cellar = Cellar::new(tmp.path()).unwrap();
-        let keg_path = cellar.materialize("foo", "1.2.3", &store_entry).unwrap();
+        let keg_path = cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap()
+            .path;
 
         // Check directory structure exists
         assert!(keg_path.exists());
@@ -359,6 +1031,60 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg(any(
+        all(target_os = "linux", feature = "patch-elf"),
+        all(target_os = "macos", feature = "patch-macho")
+    ))]
+    fn keg_is_named_after_the_bottles_version_directory() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = tmp.path().join("store/abc123");
+        fs::create_dir_all(store_entry.join("foo/1.2.3_1/bin")).unwrap();
+        fs::create_dir_all(store_entry.join("foo/1.2.3_1/libexec")).unwrap();
+        fs::write(
+            store_entry.join("foo/1.2.3_1/bin/foo"),
+            "#!/bin/sh\nexec @@HOMEBREW_CELLAR@@/foo/1.2.3_1/libexec/foo\n",
+        )
+        .unwrap();
+
+        let cellar = Cellar::new_at(tmp.path().join("prefix/Cellar")).unwrap();
+        let materialized = cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap();
+
+        assert_eq!(materialized.version, "1.2.3_1");
+        assert_eq!(materialized.path, cellar.keg_path("foo", "1.2.3_1"));
+        assert!(!cellar.has_keg("foo", "1.2.3"));
+        let script = fs::read_to_string(materialized.path.join("bin/foo")).unwrap();
+        let libexec = materialized.path.join("libexec");
+        assert!(script.contains(libexec.to_str().unwrap()), "{script}");
+
+        // The keg it would pour into may hold another release of foo.
+        let err = cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("uninstall foo 1.2.3_1 first"),
+            "{err}"
+        );
+        assert!(materialized.path.join("bin/foo").exists());
+    }
+
+    #[test]
+    fn matching_version_directory_is_kept() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = tmp.path().join("store/abc123");
+        fs::create_dir_all(store_entry.join("foo/1.2.3/bin")).unwrap();
+        fs::create_dir_all(store_entry.join("foo/1.2.3_1/bin")).unwrap();
+
+        let cellar = Cellar::new(tmp.path()).unwrap();
+        let materialized = cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap();
+        assert_eq!(materialized.version, "1.2.3");
+        assert!(cellar.has_keg("foo", "1.2.3"));
+    }
+
     #[test]
     fn second_materialize_is_noop() {
         let tmp = TempDir::new().unwrap();
@@ -367,13 +1093,19 @@ mod tests {
         let cellar = Cellar::new(tmp.path()).unwrap();
 
         // First materialize
-        let keg_path1 = cellar.materialize("foo", "1.2.3", &store_entry).unwrap();
+        let keg_path1 = cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap()
+            .path;
 
         // Add a marker file
         fs::write(keg_path1.join("marker.txt"), b"original").unwrap();
 
         // Second materialize should be no-op
-        let keg_path2 = cellar.materialize("foo", "1.2.3", &store_entry).unwrap();
+        let keg_path2 = cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap()
+            .path;
         assert_eq!(keg_path1, keg_path2);
 
         // Marker should still exist
@@ -386,7 +1118,9 @@ mod tests {
         let store_entry = setup_store_entry(&tmp);
 
         let cellar = Cellar::new(tmp.path()).unwrap();
-        cellar.materialize("foo", "1.2.3", &store_entry).unwrap();
+        cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap();
 
         assert!(cellar.has_keg("foo", "1.2.3"));
 
@@ -395,13 +1129,133 @@ mod tests {
         assert!(!cellar.has_keg("foo", "1.2.3"));
     }
 
+    #[test]
+    fn lowercase_cellar_is_moved_and_left_as_a_link() {
+        let tmp = TempDir::new().unwrap();
+        let legacy = tmp.path().join("cellar");
+        fs::create_dir_all(legacy.join("foo/1.0/bin")).unwrap();
+        fs::write(legacy.join("foo/1.0/bin/foo"), b"foo").unwrap();
+        if tmp.path().join("Cellar").exists() {
+            // Case-insensitive: `cellar` already is the Cellar.
+            return;
+        }
+
+        let cellar = Cellar::new(tmp.path()).unwrap();
+
+        assert_eq!(cellar.cellar_dir(), tmp.path().join("Cellar"));
+        assert!(!cellar.cellar_dir().is_symlink());
+        assert!(cellar.has_keg("foo", "1.0"));
+        assert_eq!(fs::read_link(&legacy).unwrap(), Path::new("Cellar"));
+        assert!(legacy.join("foo/1.0/bin/foo").exists());
+
+        // Once moved, opening it again changes nothing.
+        Cellar::new(tmp.path()).unwrap();
+        assert!(legacy.is_symlink());
+    }
+
     #[test]
     fn keg_path_format() {
         let tmp = TempDir::new().unwrap();
         let cellar = Cellar::new(tmp.path()).unwrap();
 
         let path = cellar.keg_path("libheif", "2.0.1");
-        assert!(path.ends_with("cellar/libheif/2.0.1"));
+        assert!(path.ends_with("Cellar/libheif/2.0.1"));
+    }
+
+    #[test]
+    fn copy_strategy_never_shares_inodes_with_store() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+
+        let cellar = Cellar::new(tmp.path())
+            .unwrap()
+            .with_copy_strategy(CopyStrategy::Copy);
+        let keg_path = cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap()
+            .path;
+
+        let store_ino = fs::metadata(store_entry.join("lib/libfoo.dylib"))
+            .unwrap()
+            .ino();
+        let keg_ino = fs::metadata(keg_path.join("lib/libfoo.dylib"))
+            .unwrap()
+            .ino();
+        assert_ne!(store_ino, keg_ino);
+    }
+
+    #[test]
+    fn materializes_entries_added_from_directories() {
+        let tmp = TempDir::new().unwrap();
+        let src = setup_store_entry(&tmp);
+        let store = crate::storage::store::Store::new(&tmp.path().join("root")).unwrap();
+        let key = store.add_dir(&src).unwrap();
+
+        let cellar = Cellar::new(tmp.path()).unwrap();
+        let keg = cellar
+            .materialize("local", "1.0", &store.path_for(&key), &BottleCellar::Any)
+            .unwrap()
+            .path;
+
+        assert_eq!(
+            fs::read_to_string(keg.join("bin/foo")).unwrap(),
+            "#!/bin/sh\necho foo"
+        );
+        assert!(
+            fs::symlink_metadata(keg.join("lib/libfoo.1.dylib"))
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+    }
+
+    #[test]
+    fn cross_device_store_skips_hardlinks() {
+        assert_eq!(
+            copy_strategy_for(CopyStrategy::Clonefile, 41, 41),
+            CopyStrategy::Clonefile
+        );
+        assert_eq!(
+            copy_strategy_for(CopyStrategy::Hardlink, 41, 41),
+            CopyStrategy::Hardlink
+        );
+        assert_eq!(
+            copy_strategy_for(CopyStrategy::Clonefile, 41, 42),
+            CopyStrategy::Copy
+        );
+        assert_eq!(
+            copy_strategy_for(CopyStrategy::Hardlink, 41, 42),
+            CopyStrategy::Copy
+        );
+    }
+
+    #[test]
+    fn materialize_reports_effective_strategy() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+
+        let cellar = Cellar::new(tmp.path())
+            .unwrap()
+            .with_copy_strategy(CopyStrategy::Hardlink);
+        let first = cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap();
+        assert_eq!(first.strategy, Some(CopyStrategy::Hardlink));
+
+        let again = cellar
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap();
+        assert_eq!(again.strategy, None);
+
+        let copying = Cellar::new(&tmp.path().join("other"))
+            .unwrap()
+            .with_copy_strategy(CopyStrategy::Copy);
+        let copied = copying
+            .materialize("foo", "1.2.3", &store_entry, &BottleCellar::Any)
+            .unwrap();
+        assert_eq!(copied.strategy, Some(CopyStrategy::Copy));
     }
 
     #[test]
@@ -426,6 +1280,312 @@ mod tests {
         );
     }
 
+    #[derive(Default)]
+    struct CountingObserver {
+        files: std::sync::Mutex<Vec<(PathBuf, u64)>>,
+        phases: std::sync::Mutex<Vec<Phase>>,
+        cancel_after: Option<usize>,
+    }
+
+    impl MaterializeObserver for CountingObserver {
+        fn on_file_copied(&self, path: &Path, bytes: u64) {
+            self.files.lock().unwrap().push((path.to_path_buf(), bytes));
+        }
+
+        fn on_phase(&self, phase: Phase) {
+            self.phases.lock().unwrap().push(phase);
+        }
+
+        fn should_cancel(&self) -> bool {
+            self.cancel_after
+                .is_some_and(|n| self.files.lock().unwrap().len() >= n)
+        }
+    }
+
+    #[derive(Default)]
+    #[cfg_attr(
+        not(any(
+            all(target_os = "linux", feature = "patch-elf"),
+            all(target_os = "macos", feature = "patch-macho")
+        )),
+        allow(dead_code)
+    )]
+    struct ThreadObserver {
+        threads: std::sync::Mutex<std::collections::HashSet<std::thread::ThreadId>>,
+        names: std::sync::Mutex<std::collections::HashSet<String>>,
+    }
+
+    impl MaterializeObserver for ThreadObserver {
+        fn should_cancel(&self) -> bool {
+            let thread = std::thread::current();
+            self.threads.lock().unwrap().insert(thread.id());
+            self.names
+                .lock()
+                .unwrap()
+                .insert(thread.name().unwrap_or_default().to_string());
+            false
+        }
+    }
+
+    #[test]
+    #[cfg(any(
+        all(target_os = "linux", feature = "patch-elf"),
+        all(target_os = "macos", feature = "patch-macho")
+    ))]
+    fn patching_runs_on_the_cellar_thread_pool() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = tmp.path().join("store/many");
+        fs::create_dir_all(store_entry.join("share")).unwrap();
+        for i in 0..256 {
+            fs::write(
+                store_entry.join(format!("share/{i}.txt")),
+                "@@HOMEBREW_PREFIX@@/share",
+            )
+            .unwrap();
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .thread_name(|i| format!("test-pool-{i}"))
+            .build()
+            .unwrap();
+        let cellar = Cellar::new(tmp.path())
+            .unwrap()
+            .with_copy_strategy(CopyStrategy::Copy)
+            .with_thread_pool(Arc::new(pool));
+
+        let observer = ThreadObserver::default();
+        cellar
+            .materialize_with_observer("many", "1.0", &store_entry, &BottleCellar::Any, &observer)
+            .unwrap();
+
+        let threads = observer.threads.into_inner().unwrap();
+        assert!(!threads.is_empty() && threads.len() <= 2, "{threads:?}");
+        let names = observer.names.into_inner().unwrap();
+        assert!(
+            names.iter().all(|name| name.starts_with("test-pool-")),
+            "{names:?}"
+        );
+    }
+
+    #[derive(Default)]
+    #[cfg_attr(
+        not(any(
+            all(target_os = "linux", feature = "patch-elf"),
+            all(target_os = "macos", feature = "patch-macho")
+        )),
+        allow(dead_code)
+    )]
+    struct PatchObserver {
+        total: std::sync::atomic::AtomicUsize,
+        calls: std::sync::atomic::AtomicUsize,
+        highest: std::sync::atomic::AtomicUsize,
+    }
+
+    impl MaterializeObserver for PatchObserver {
+        fn on_patch_started(&self, counter: &PatchCounter) {
+            use std::sync::atomic::Ordering;
+            assert_eq!(counter.done(), 0);
+            self.total.store(counter.total(), Ordering::Relaxed);
+        }
+
+        fn on_file_patched(&self, counter: &PatchCounter) {
+            use std::sync::atomic::Ordering;
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.highest.fetch_max(counter.done(), Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    #[cfg(any(
+        all(target_os = "linux", feature = "patch-elf"),
+        all(target_os = "macos", feature = "patch-macho")
+    ))]
+    fn patch_progress_counts_every_candidate_file() {
+        use std::sync::atomic::Ordering;
+
+        let tmp = TempDir::new().unwrap();
+        let store_entry = tmp.path().join("store/generated");
+        for dir in 0..10 {
+            let share = store_entry.join(format!("share/{dir}"));
+            fs::create_dir_all(&share).unwrap();
+            for file in 0..30 {
+                let content = if file % 2 == 0 {
+                    "@@HOMEBREW_PREFIX@@/share"
+                } else {
+                    "nothing to patch"
+                };
+                fs::write(share.join(format!("{file}.txt")), content).unwrap();
+            }
+        }
+        std::os::unix::fs::symlink("0/0.txt", store_entry.join("share/link.txt")).unwrap();
+        let cellar = Cellar::new(tmp.path())
+            .unwrap()
+            .with_copy_strategy(CopyStrategy::Copy);
+
+        let observer = PatchObserver::default();
+        cellar
+            .materialize_with_observer(
+                "generated",
+                "1.0",
+                &store_entry,
+                &BottleCellar::Any,
+                &observer,
+            )
+            .unwrap();
+
+        // Symlinks aren't candidates.
+        assert_eq!(observer.total.load(Ordering::Relaxed), 300);
+        assert_eq!(observer.calls.load(Ordering::Relaxed), 300);
+        assert_eq!(observer.highest.load(Ordering::Relaxed), 300);
+    }
+
+    #[test]
+    #[cfg(any(
+        all(target_os = "linux", feature = "patch-elf"),
+        all(target_os = "macos", feature = "patch-macho")
+    ))]
+    fn observer_sees_every_copied_file_and_phase() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+        let cellar = Cellar::new(tmp.path())
+            .unwrap()
+            .with_copy_strategy(CopyStrategy::Copy);
+
+        let observer = CountingObserver::default();
+        let keg = cellar
+            .materialize_with_observer("foo", "1.0.0", &store_entry, &BottleCellar::Any, &observer)
+            .unwrap()
+            .path;
+
+        let mut files = observer.files.into_inner().unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                (keg.join("bin/foo"), 18),
+                (keg.join("lib/libfoo.dylib"), 10),
+            ]
+        );
+        let phases = observer.phases.into_inner().unwrap();
+        assert_eq!(phases[..2], [Phase::Copy, Phase::Permissions]);
+        assert!(phases.contains(&Phase::PatchText));
+    }
+
+    #[test]
+    fn cancelling_removes_the_partial_keg() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+        let cellar = Cellar::new(tmp.path())
+            .unwrap()
+            .with_copy_strategy(CopyStrategy::Copy);
+
+        let observer = CountingObserver {
+            cancel_after: Some(1),
+            ..Default::default()
+        };
+        let err = cellar
+            .materialize_with_observer("foo", "1.0.0", &store_entry, &BottleCellar::Any, &observer)
+            .unwrap_err();
+
+        assert_eq!(err, Error::Cancelled);
+        assert_eq!(observer.files.lock().unwrap().len(), 1);
+        assert!(!cellar.has_keg("foo", "1.0.0"));
+        assert!(!tmp.path().join("Cellar/foo").exists());
+
+        // Nothing stale is left to short-circuit the next attempt.
+        let retried = cellar
+            .materialize("foo", "1.0.0", &store_entry, &BottleCellar::Any)
+            .unwrap();
+        assert!(retried.strategy.is_some());
+        assert!(retried.path.join("lib/libfoo.dylib").exists());
+    }
+
+    fn add_unsafe_modes(store_entry: &Path) {
+        fs::create_dir_all(store_entry.join("share/open")).unwrap();
+        fs::write(store_entry.join("share/open/data"), b"data").unwrap();
+        fs::set_permissions(
+            store_entry.join("share/open/data"),
+            fs::Permissions::from_mode(0o777),
+        )
+        .unwrap();
+        fs::set_permissions(
+            store_entry.join("share/open"),
+            fs::Permissions::from_mode(0o777),
+        )
+        .unwrap();
+        fs::write(store_entry.join("bin/suid"), b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(
+            store_entry.join("bin/suid"),
+            fs::Permissions::from_mode(0o6755),
+        )
+        .unwrap();
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::symlink_metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[test]
+    fn materialize_clears_world_writable_and_setid_bits() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+        add_unsafe_modes(&store_entry);
+
+        let cellar = Cellar::new(tmp.path()).unwrap();
+        let keg = cellar
+            .materialize("perms", "1.0.0", &store_entry, &BottleCellar::Any)
+            .unwrap()
+            .path;
+
+        assert_eq!(mode(&keg.join("share/open")), 0o755);
+        assert_eq!(mode(&keg.join("share/open/data")), 0o755);
+        assert_eq!(mode(&keg.join("bin/suid")), 0o755);
+        assert_eq!(mode(&keg.join("bin/foo")), 0o755);
+        assert!(
+            fs::symlink_metadata(keg.join("lib/libfoo.1.dylib"))
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+    }
+
+    #[test]
+    fn preserve_permissions_still_drops_setid_bits() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+        add_unsafe_modes(&store_entry);
+
+        let mut cellar = Cellar::new(tmp.path()).unwrap();
+        cellar.set_preserve_permissions(true);
+        let keg = cellar
+            .materialize("perms", "1.0.0", &store_entry, &BottleCellar::Any)
+            .unwrap()
+            .path;
+
+        // Keg directories are created fresh under the umask; files keep
+        // the mode they had in the bottle.
+        assert_eq!(mode(&keg.join("share/open/data")), 0o777);
+        assert_eq!(mode(&keg.join("bin/suid")), 0o755);
+    }
+
+    #[test]
+    fn permission_report_counts_sanitized_entries() {
+        let tmp = TempDir::new().unwrap();
+        let store_entry = setup_store_entry(&tmp);
+        add_unsafe_modes(&store_entry);
+
+        let mut report = PermissionReport::default();
+        sanitize_permissions(&store_entry, false, &mut report).unwrap();
+
+        assert_eq!(report.sanitized, 2);
+        assert_eq!(report.setid_dropped, vec![store_entry.join("bin/suid")]);
+
+        // A second pass finds nothing left to change.
+        let mut again = PermissionReport::default();
+        sanitize_permissions(&store_entry, false, &mut again).unwrap();
+        assert_eq!(again, PermissionReport::default());
+    }
+
     #[test]
     #[cfg(target_os = "macos")]
     fn clonefile_fallback_works() {
@@ -434,7 +1594,10 @@ mod tests {
         let store_entry = setup_store_entry(&tmp);
 
         let cellar = Cellar::new(tmp.path()).unwrap();
-        let keg_path = cellar.materialize("clone", "1.0.0", &store_entry).unwrap();
+        let keg_path = cellar
+            .materialize("clone", "1.0.0", &store_entry, &BottleCellar::Any)
+            .unwrap()
+            .path;
 
         // Verify content is correct regardless of which strategy was used
         assert_eq!(
@@ -445,57 +1608,66 @@ mod tests {
 
     #[test]
     fn version_mismatch_regex_fixes_paths() {
-        use regex::Regex;
-
-        let pkg_name = "ffmpeg";
-        let pkg_version = "8.0.1_2";
+        use crate::extraction::patch::version::VersionFixer;
 
-        // Create the version mismatch regex
-        let version_pattern = format!(r"(/{}/)([^/]+)(/)", regex::escape(pkg_name));
-        let version_regex = Regex::new(&version_pattern).unwrap();
+        let tmp = TempDir::new().unwrap();
+        let cellar_dir = tmp.path().join("Cellar");
+        let fixer = VersionFixer::new(&cellar_dir, "ffmpeg", "8.0.1_2");
 
         // Test case: path with wrong version
         let old_path = "/opt/zerobrew/prefix/Cellar/ffmpeg/8.0.1_1/lib/libavdevice.62.dylib";
-        let replacement = format!("/{}/{}/", pkg_name, pkg_version);
-
-        let fixed = version_regex.replace(old_path, |caps: &regex::Captures| {
-            let matched_version = &caps[2];
-            if matched_version != pkg_version {
-                replacement.clone()
-            } else {
-                caps[0].to_string()
-            }
-        });
-
         assert_eq!(
-            fixed,
-            "/opt/zerobrew/prefix/Cellar/ffmpeg/8.0.1_2/lib/libavdevice.62.dylib"
+            fixer.fix(old_path).as_deref(),
+            Some("/opt/zerobrew/prefix/Cellar/ffmpeg/8.0.1_2/lib/libavdevice.62.dylib")
         );
 
         // Test case: path with correct version (should not change)
         let correct_path = "/opt/zerobrew/prefix/Cellar/ffmpeg/8.0.1_2/lib/libavdevice.62.dylib";
-        let fixed2 = version_regex.replace(correct_path, |caps: &regex::Captures| {
-            let matched_version = &caps[2];
-            if matched_version != pkg_version {
-                replacement.clone()
-            } else {
-                caps[0].to_string()
-            }
-        });
-
-        assert_eq!(fixed2, correct_path);
+        assert_eq!(fixer.fix(correct_path), None);
 
         // Test case: path for different package (should not change)
         let other_path = "/opt/zerobrew/prefix/Cellar/libvpx/1.0.0/lib/libvpx.dylib";
-        let fixed3 = version_regex.replace(other_path, |caps: &regex::Captures| {
-            let matched_version = &caps[2];
-            if matched_version != pkg_version {
-                replacement.clone()
-            } else {
-                caps[0].to_string()
-            }
-        });
+        assert_eq!(fixer.fix(other_path), None);
+
+        // Test case: the other version is installed side by side (should not change)
+        fs::create_dir_all(cellar_dir.join("ffmpeg/8.0.1_1")).unwrap();
+        assert_eq!(fixer.fix(old_path), None);
+        let older_path = "/opt/zerobrew/prefix/Cellar/ffmpeg/7.1/lib/libavdevice.61.dylib";
+        assert_eq!(
+            fixer.fix(older_path).as_deref(),
+            Some("/opt/zerobrew/prefix/Cellar/ffmpeg/8.0.1_2/lib/libavdevice.61.dylib")
+        );
+    }
 
-        assert_eq!(fixed3, other_path);
+    #[test]
+    fn copying_past_the_path_limit_names_the_destination() {
+        let tmp = TempDir::new().unwrap();
+        let limit = libc::PATH_MAX as usize - 1;
+        let segment = "d".repeat(200);
+
+        // A source tree whose deepest file sits just under the limit.
+        let src = tmp.path().join("s");
+        let mut deepest = src.clone();
+        while deepest.as_os_str().len() + 2 * (segment.len() + 1) < limit {
+            deepest.push(&segment);
+        }
+        fs::create_dir_all(&deepest).unwrap();
+        fs::write(deepest.join("f"), b"x").unwrap();
+        let relative = deepest.strip_prefix(&src).unwrap();
+
+        // A destination of the same length still fits.
+        let beside = tmp.path().join("t");
+        copy_dir_copy_only(&src, &beside).unwrap();
+        assert_eq!(fs::read(beside.join(relative).join("f")).unwrap(), b"x");
+
+        // One a few hundred bytes longer pushes the deepest entries past it.
+        let longer = tmp
+            .path()
+            .join("x".repeat(250))
+            .join("y".repeat(250))
+            .join("z".repeat(250));
+        let err = copy_dir_copy_only(&src, &longer).unwrap_err().to_string();
+        assert!(err.contains(&longer.display().to_string()), "{err}");
+        assert!(err.contains("bytes in"), "{err}");
     }
 }
@@ -1,5 +1,9 @@
 pub mod link;
 pub mod materialize;
+pub(crate) mod walk;
+pub mod wrapper;
 
-pub use link::{LinkedFile, Linker};
-pub use materialize::{Cellar, CopyStrategy, MaterializedKeg};
+pub use crate::storage::db::{CopyStrategy, LinkedFile};
+pub use link::{LinkClass, LinkDiff, LinkPlan, Linker, PlannedLink};
+pub use materialize::{Cellar, Materialized, MaterializedKeg};
+pub use wrapper::{EnvValue, WRAPPER_RULES, WrapperRule};
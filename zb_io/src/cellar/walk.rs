@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+use zb_core::Error;
+
+/// Depth past which a walk logs that the tree looks suspiciously deep.
+pub(crate) const SOFT_DEPTH_LIMIT: usize = 32;
+/// Depth at which a walk gives up. Nothing a keg links nests this far; a
+/// tree that does is almost always a symlink loop being followed.
+pub(crate) const HARD_DEPTH_LIMIT: usize = 128;
+
+/// Directories on the path a recursive walk is currently inside, by
+/// canonical path, so a directory reached again through a symlink is
+/// recognised instead of walked forever.
+#[derive(Debug, Default)]
+pub(crate) struct DirGuard {
+    stack: Vec<PathBuf>,
+    warned: bool,
+}
+
+impl DirGuard {
+    /// Step into `dir`. `Ok(false)` means `dir` is already being walked
+    /// higher up (or can't be resolved at all) and must be skipped; every
+    /// `Ok(true)` has to be paired with [`DirGuard::leave`].
+    pub(crate) fn enter(&mut self, dir: &Path) -> Result<bool, Error> {
+        let Ok(canonical) = fs::canonicalize(dir) else {
+            return Ok(false);
+        };
+        if self.stack.contains(&canonical) {
+            warn!(path = %dir.display(), "skipping symlink loop");
+            return Ok(false);
+        }
+        if self.stack.len() >= HARD_DEPTH_LIMIT {
+            return Err(Error::FileError {
+                message: format!(
+                    "{} is nested more than {HARD_DEPTH_LIMIT} directories deep; is there a symlink loop?",
+                    dir.display()
+                ),
+            });
+        }
+        if self.stack.len() >= SOFT_DEPTH_LIMIT && !self.warned {
+            self.warned = true;
+            warn!(path = %dir.display(), depth = self.stack.len(), "unusually deep directory tree");
+        }
+        self.stack.push(canonical);
+        Ok(true)
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.stack.pop();
+    }
+}
+
+/// Whether the symlink at `link` can never resolve to something walkable:
+/// it points at a directory containing itself (`prefix/bad -> prefix`), or
+/// its chain of links comes back around (`a -> b`, `b -> a`).
+pub(crate) fn is_symlink_loop(link: &Path) -> bool {
+    let Ok(target) = fs::read_link(link) else {
+        return false;
+    };
+    let parent = link.parent().unwrap_or(Path::new(""));
+    match fs::canonicalize(parent.join(target)) {
+        Ok(resolved) => {
+            resolved.is_dir() && fs::canonicalize(parent).is_ok_and(|p| p.starts_with(&resolved))
+        }
+        Err(_) => chain_revisits(link),
+    }
+}
+
+fn chain_revisits(link: &Path) -> bool {
+    let mut seen = HashSet::new();
+    let mut current = link.to_path_buf();
+    for _ in 0..HARD_DEPTH_LIMIT {
+        let (Some(parent), Some(name)) = (current.parent(), current.file_name()) else {
+            return false;
+        };
+        let Ok(parent) = fs::canonicalize(parent) else {
+            return false;
+        };
+        let key = parent.join(name);
+        if !seen.insert(key.clone()) {
+            return true;
+        }
+        let Ok(target) = fs::read_link(&key) else {
+            return false;
+        };
+        current = parent.join(target);
+    }
+    true
+}
+
+/// Symlink loops anywhere under `dir`. Links are never followed, so the
+/// walk itself can't loop.
+pub(crate) fn find_symlink_loops(dir: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(dir)
+        .follow_links(false)
+        .max_depth(HARD_DEPTH_LIMIT)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path_is_symlink() && is_symlink_loop(entry.path()))
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn detects_links_to_an_ancestor_and_link_cycles() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path();
+        fs::create_dir_all(prefix.join("share/doc")).unwrap();
+        symlink(prefix, prefix.join("share/bad")).unwrap();
+        symlink("b", prefix.join("share/a")).unwrap();
+        symlink("./a", prefix.join("share/b")).unwrap();
+        symlink("doc", prefix.join("share/fine")).unwrap();
+        symlink("missing", prefix.join("share/dangling")).unwrap();
+
+        let mut loops = find_symlink_loops(prefix);
+        loops.sort();
+
+        assert_eq!(
+            loops,
+            [
+                prefix.join("share/a"),
+                prefix.join("share/b"),
+                prefix.join("share/bad"),
+            ]
+        );
+    }
+
+    #[test]
+    fn guard_skips_a_directory_reached_again() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("lib");
+        fs::create_dir_all(&dir).unwrap();
+        symlink(".", dir.join("again")).unwrap();
+
+        let mut guard = DirGuard::default();
+        assert!(guard.enter(&dir).unwrap());
+        assert!(!guard.enter(&dir.join("again")).unwrap());
+        guard.leave();
+        assert!(guard.enter(&dir.join("again")).unwrap());
+    }
+}
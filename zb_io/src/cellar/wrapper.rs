@@ -0,0 +1,233 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use zb_core::Error;
+
+use crate::storage::db::KegFileKind;
+use crate::storage::db::LinkedFile;
+
+/// First line after the shebang of every generated wrapper. Linking and
+/// unlinking only treat a regular file as ours when it carries this marker.
+const MARKER: &str = "# Generated by zerobrew";
+
+/// Where a wrapped variable points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvValue {
+    /// A path inside the formula's own keg.
+    Keg(&'static str),
+    /// A path inside another formula's `opt` link, so it follows upgrades of
+    /// that formula without rewriting the wrapper. Only used when the
+    /// variable isn't already set, so a caller's own choice wins.
+    Opt {
+        formula: &'static str,
+        path: &'static str,
+    },
+}
+
+/// Environment a formula's executables need that a plain symlink can't
+/// give them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrapperRule {
+    pub formula: &'static str,
+    pub env: &'static [(&'static str, EnvValue)],
+}
+
+/// Homebrew's bottles expect the CA bundle from its `ca-certificates`
+/// formula; without it they fall back to a path that only exists under
+/// Homebrew's own prefix.
+const SSL_CERT_FILE: (&str, EnvValue) = (
+    "SSL_CERT_FILE",
+    EnvValue::Opt {
+        formula: "ca-certificates",
+        path: "share/ca-certificates/cacert.pem",
+    },
+);
+
+pub const WRAPPER_RULES: &[WrapperRule] = &[
+    WrapperRule {
+        formula: "aria2",
+        env: &[SSL_CERT_FILE],
+    },
+    WrapperRule {
+        formula: "curl",
+        env: &[SSL_CERT_FILE],
+    },
+    WrapperRule {
+        formula: "git",
+        // git looks for its helpers (git-remote-https, ...) relative to the
+        // directory it was built for, not the one it was relocated to.
+        env: &[("GIT_EXEC_PATH", EnvValue::Keg("libexec/git-core"))],
+    },
+    WrapperRule {
+        formula: "wget",
+        env: &[SSL_CERT_FILE],
+    },
+];
+
+/// The rule for the formula whose Cellar directory is `name`, if any.
+pub fn rule_for(name: &str) -> Option<&'static WrapperRule> {
+    WRAPPER_RULES.iter().find(|rule| rule.formula == name)
+}
+
+/// Replace the symlinks `linked` put directly in `bin_dir` with wrapper
+/// scripts that set the rule's environment and exec the keg's executable.
+pub(crate) fn write_wrappers(
+    rule: &WrapperRule,
+    prefix: &Path,
+    bin_dir: &Path,
+    keg_path: &Path,
+    linked: &mut [LinkedFile],
+) -> Result<(), Error> {
+    let env: Vec<(&str, PathBuf, bool)> = rule
+        .env
+        .iter()
+        .map(|(var, value)| match value {
+            EnvValue::Keg(path) => (*var, keg_path.join(path), false),
+            EnvValue::Opt { formula, path } => {
+                (*var, prefix.join("opt").join(formula).join(path), true)
+            }
+        })
+        .collect();
+
+    for file in linked
+        .iter_mut()
+        .filter(|file| file.kind == KegFileKind::Symlink)
+        .filter(|file| file.link_path.parent() == Some(bin_dir))
+    {
+        let script = render(rule.formula, &env, &file.target_path);
+        let _ = fs::remove_file(&file.link_path);
+        let ctx = format!("failed to write wrapper {}", file.link_path.display());
+        fs::write(&file.link_path, script).map_err(Error::store(ctx.as_str()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&file.link_path, fs::Permissions::from_mode(0o755))
+                .map_err(Error::store(ctx.as_str()))?;
+        }
+        file.kind = KegFileKind::Wrapper;
+        file.link_target = None;
+    }
+    Ok(())
+}
+
+/// A POSIX sh script that sets `env`, keeping a variable the caller already
+/// set where its flag says so, and execs `target`, which keeps the process
+/// (and its signals) the same one the caller started.
+fn render(formula: &str, env: &[(&str, PathBuf, bool)], target: &Path) -> String {
+    let mut script = format!("#!/bin/sh\n{MARKER} for {formula}; removed on uninstall.\n");
+    for (var, path, keep_set) in env {
+        if *keep_set {
+            script.push_str(&format!(": \"${{{var}:={}}}\"\n", escape_in_braces(path)));
+            script.push_str(&format!("export {var}\n"));
+        } else {
+            script.push_str(&format!("export {var}={}\n", quote(path)));
+        }
+    }
+    script.push_str(&format!("exec {} \"$@\"\n", quote(target)));
+    script
+}
+
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// `path` escaped for the default in a double-quoted `${VAR:=...}`, where
+/// single quotes would be kept literally.
+fn escape_in_braces(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        if matches!(c, '\\' | '"' | '$' | '`' | '}') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The keg executable a generated wrapper at `path` execs, or `None` when
+/// `path` is not one of our wrappers.
+pub(crate) fn wrapped_target(path: &Path) -> Option<PathBuf> {
+    if path.is_symlink() || !path.is_file() {
+        return None;
+    }
+    let script = fs::read_to_string(path).ok()?;
+    let mut lines = script.lines();
+    lines.next();
+    if !lines.next()?.starts_with(MARKER) {
+        return None;
+    }
+    // Older wrappers were bash scripts that exec'd with `-a "$0"`.
+    let quoted = lines
+        .find_map(|line| {
+            line.strip_prefix("exec -a \"$0\" ")
+                .or_else(|| line.strip_prefix("exec "))
+        })?
+        .strip_suffix(" \"$@\"")?;
+    let inner = quoted.strip_prefix('\'')?.strip_suffix('\'')?;
+    Some(PathBuf::from(inner.replace(r"'\''", "'")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrapper_round_trips_its_target() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let target = Path::new("/opt/zerobrew/cellar/it's/1.0/bin/it's");
+        let wrapper = tmp.path().join("wrapper");
+        let env = [(
+            "GIT_EXEC_PATH",
+            PathBuf::from("/keg/libexec/git-core"),
+            false,
+        )];
+        fs::write(&wrapper, render("git", &env, target)).unwrap();
+
+        assert_eq!(wrapped_target(&wrapper).as_deref(), Some(target));
+        let script = fs::read_to_string(&wrapper).unwrap();
+        assert!(script.contains("export GIT_EXEC_PATH='/keg/libexec/git-core'\n"));
+
+        let bash_era = format!(
+            "#!/bin/bash\n{MARKER} for git; removed on uninstall.\nexec -a \"$0\" '/keg/bin/git' \"$@\"\n"
+        );
+        fs::write(&wrapper, bash_era).unwrap();
+        assert_eq!(
+            wrapped_target(&wrapper).as_deref(),
+            Some(Path::new("/keg/bin/git"))
+        );
+    }
+
+    #[test]
+    fn defaulted_variables_keep_the_callers_value() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let wrapper = tmp.path().join("wrapper");
+        let bundle = "/opt/c a/$x}\"`'.pem";
+        let env = [("SSL_CERT_FILE", PathBuf::from(bundle), true)];
+        fs::write(&wrapper, render("curl", &env, Path::new("/usr/bin/env"))).unwrap();
+
+        let run = |value: Option<&str>| {
+            let mut command = std::process::Command::new("sh");
+            command.arg(&wrapper).env_remove("SSL_CERT_FILE");
+            if let Some(value) = value {
+                command.env("SSL_CERT_FILE", value);
+            }
+            let output = command.output().unwrap();
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .find_map(|line| line.strip_prefix("SSL_CERT_FILE="))
+                .map(str::to_string)
+        };
+
+        assert_eq!(run(None).as_deref(), Some(bundle));
+        assert_eq!(run(Some("/etc/mine.pem")).as_deref(), Some("/etc/mine.pem"));
+    }
+
+    #[test]
+    fn unmarked_script_is_not_a_wrapper() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let script = tmp.path().join("tool");
+        fs::write(&script, "#!/bin/sh\nexec -a \"$0\" '/bin/true' \"$@\"\n").unwrap();
+
+        assert_eq!(wrapped_target(&script), None);
+    }
+}
@@ -0,0 +1,298 @@
+//! A memory budget shared by every extraction into one store, so unpacking
+//! several large bottles at once can't take more memory than the machine
+//! can spare.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Read buffer an extraction gets when the budget has room for it.
+pub const BUFFERED_READ_BYTES: usize = 256 << 10;
+
+/// Read buffer an extraction gets when the budget can't fit a buffered one,
+/// the same as `BufReader`'s. It is then admitted only while nothing else
+/// is extracting.
+pub const STREAMED_READ_BYTES: usize = 8 << 10;
+
+/// The most memory one decoder may hold besides its read buffer: the xz
+/// dictionary or zstd window it is limited to.
+pub const DECODER_BYTES: u64 = 64 << 20;
+
+/// Budget used when the system's memory can't be read.
+const FALLBACK_BUDGET: u64 = 1 << 30;
+
+/// Reservations are counted in these, so a budget of any size fits the
+/// semaphore's permit count.
+const PERMIT_BYTES: u64 = 1 << 10;
+
+/// The limits an [`ExtractionBudget`] enforces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractionLimits {
+    /// Bytes all extractions together may hold.
+    pub budget: u64,
+    /// Extractions admitted at once; at least one, however small the budget.
+    pub max_concurrent: usize,
+    /// Read buffer each extraction gets.
+    pub read_buffer: usize,
+}
+
+impl ExtractionLimits {
+    /// Whether the budget is too small for a buffered extraction, so each
+    /// one is streamed with a small buffer and they run one at a time.
+    pub fn streamed(&self) -> bool {
+        self.read_buffer == STREAMED_READ_BYTES
+    }
+
+    fn cost(&self) -> u64 {
+        DECODER_BYTES + self.read_buffer as u64
+    }
+}
+
+/// What a budget has admitted so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractionStats {
+    pub admitted: u64,
+    /// Extractions that had to wait for in-flight ones to finish.
+    pub waited: u64,
+    pub peak_in_flight: usize,
+    /// Most bytes reserved at once.
+    pub peak_bytes: u64,
+}
+
+/// Admits extractions in arrival order while their reservations fit the
+/// budget. One that doesn't fit waits for in-flight extractions to finish
+/// rather than being started alongside them, and nothing behind it is
+/// admitted first. Clones share the budget.
+#[derive(Debug, Clone)]
+pub struct ExtractionBudget {
+    limits: ExtractionLimits,
+    /// Holds the budget in [`PERMIT_BYTES`] units; tokio hands permits out
+    /// first come, first served.
+    permits: Arc<Semaphore>,
+    /// Units one extraction reserves, never more than the whole budget.
+    units: u32,
+    stats: Arc<Mutex<BudgetStats>>,
+}
+
+#[derive(Debug, Default)]
+struct BudgetStats {
+    in_flight: usize,
+    bytes: u64,
+    totals: ExtractionStats,
+}
+
+impl ExtractionBudget {
+    /// A budget of `bytes`. Below what one buffered extraction needs, each
+    /// extraction is streamed instead.
+    pub fn new(bytes: u64) -> Self {
+        let buffered = DECODER_BYTES + BUFFERED_READ_BYTES as u64;
+        let read_buffer = if bytes >= buffered {
+            BUFFERED_READ_BYTES
+        } else {
+            STREAMED_READ_BYTES
+        };
+        let max_concurrent = (bytes / buffered).max(1) as usize;
+        let limits = ExtractionLimits {
+            budget: bytes,
+            max_concurrent,
+            read_buffer,
+        };
+
+        let total = (bytes / PERMIT_BYTES).clamp(1, u64::from(u32::MAX));
+        let units = limits.cost().div_ceil(PERMIT_BYTES).min(total) as u32;
+        Self {
+            limits,
+            permits: Arc::new(Semaphore::new(total as usize)),
+            units,
+            stats: Arc::default(),
+        }
+    }
+
+    /// A quarter of the machine's physical memory.
+    pub fn from_system_memory() -> Self {
+        Self::new(physical_memory().map_or(FALLBACK_BUDGET, |bytes| bytes / 4))
+    }
+
+    pub fn limits(&self) -> ExtractionLimits {
+        self.limits
+    }
+
+    pub fn stats(&self) -> ExtractionStats {
+        self.lock().totals
+    }
+
+    /// Wait until an extraction fits, then reserve its share until the
+    /// returned permit is dropped. A budget smaller than one extraction
+    /// still admits one at a time, so it can't stall.
+    pub async fn admit(&self) -> ExtractionPermit {
+        let (permit, waited) = match Arc::clone(&self.permits).try_acquire_many_owned(self.units) {
+            Ok(permit) => (permit, false),
+            Err(_) => (
+                Arc::clone(&self.permits)
+                    .acquire_many_owned(self.units)
+                    .await
+                    .expect("the extraction budget is never closed"),
+                true,
+            ),
+        };
+
+        let cost = self.limits.cost();
+        let mut stats = self.lock();
+        stats.in_flight += 1;
+        stats.bytes += cost;
+        stats.totals.admitted += 1;
+        stats.totals.waited += u64::from(waited);
+        stats.totals.peak_in_flight = stats.totals.peak_in_flight.max(stats.in_flight);
+        stats.totals.peak_bytes = stats.totals.peak_bytes.max(stats.bytes);
+        drop(stats);
+
+        ExtractionPermit {
+            _permit: permit,
+            stats: Arc::clone(&self.stats),
+            cost,
+            read_buffer: self.limits.read_buffer,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BudgetStats> {
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// One admitted extraction's reservation, given back when dropped.
+#[derive(Debug)]
+pub struct ExtractionPermit {
+    _permit: OwnedSemaphorePermit,
+    stats: Arc<Mutex<BudgetStats>>,
+    cost: u64,
+    read_buffer: usize,
+}
+
+impl ExtractionPermit {
+    pub fn read_buffer(&self) -> usize {
+        self.read_buffer
+    }
+}
+
+impl Drop for ExtractionPermit {
+    fn drop(&mut self) {
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        stats.in_flight -= 1;
+        stats.bytes -= self.cost;
+    }
+}
+
+fn physical_memory() -> Option<u64> {
+    // SAFETY: sysconf only reads system configuration.
+    let (pages, page_size) = unsafe {
+        (
+            libc::sysconf(libc::_SC_PHYS_PAGES),
+            libc::sysconf(libc::_SC_PAGESIZE),
+        )
+    };
+    (pages > 0 && page_size > 0).then(|| pages as u64 * page_size as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::sync::Barrier;
+
+    use super::*;
+
+    const ONE_BUFFERED: u64 = DECODER_BYTES + BUFFERED_READ_BYTES as u64;
+
+    async fn run_concurrently(budget: &ExtractionBudget, extractions: usize) {
+        let handles: Vec<_> = (0..extractions)
+            .map(|_| {
+                let budget = budget.clone();
+                tokio::spawn(async move {
+                    let _permit = budget.admit().await;
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_budget_for_one_serializes_extractions() {
+        let budget = ExtractionBudget::new(ONE_BUFFERED);
+        run_concurrently(&budget, 8).await;
+
+        let stats = budget.stats();
+        assert_eq!(stats.admitted, 8);
+        assert_eq!(stats.peak_in_flight, 1);
+        assert!(stats.peak_bytes <= ONE_BUFFERED);
+        assert_eq!(budget.limits().max_concurrent, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_budget_below_one_extraction_streams_them_one_at_a_time() {
+        let budget = ExtractionBudget::new(16 << 20);
+        let limits = budget.limits();
+        assert!(limits.streamed());
+        assert_eq!(limits.read_buffer, STREAMED_READ_BYTES);
+
+        run_concurrently(&budget, 4).await;
+        assert_eq!(budget.stats().peak_in_flight, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn extractions_that_fit_run_together() {
+        let budget = ExtractionBudget::new(2 * ONE_BUFFERED);
+        assert_eq!(budget.limits().max_concurrent, 2);
+
+        // Each holds its permit until both are admitted, which only
+        // happens if the budget lets them in together.
+        let barrier = Arc::new(Barrier::new(2));
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let budget = budget.clone();
+                let barrier = barrier.clone();
+                tokio::spawn(async move {
+                    let _permit = budget.admit().await;
+                    barrier.wait().await;
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let stats = budget.stats();
+        assert_eq!(stats.peak_in_flight, 2);
+        assert!(stats.peak_bytes <= 2 * ONE_BUFFERED);
+    }
+
+    #[tokio::test]
+    async fn a_permit_gives_its_share_back() {
+        let budget = ExtractionBudget::new(ONE_BUFFERED);
+        drop(budget.admit().await);
+        let _second = budget.admit().await;
+        assert_eq!(budget.stats().waited, 0);
+        assert_eq!(budget.stats().peak_in_flight, 1);
+    }
+
+    #[tokio::test]
+    async fn an_extraction_waits_for_the_one_in_flight() {
+        let budget = ExtractionBudget::new(ONE_BUFFERED);
+        let first = budget.admit().await;
+        let second = tokio::spawn({
+            let budget = budget.clone();
+            async move {
+                let _permit = budget.admit().await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!second.is_finished());
+
+        drop(first);
+        second.await.unwrap();
+        assert_eq!(budget.stats().waited, 1);
+    }
+}
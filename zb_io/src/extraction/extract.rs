@@ -9,6 +9,68 @@ use zstd::stream::read::Decoder as ZstdDecoder;
 
 use zb_core::Error;
 
+use super::budget::DECODER_BYTES;
+
+/// Limits on the entry paths an archive may contain. An entry past them is
+/// refused before anything is unpacked from it, naming the entry, instead of
+/// failing with an error from deep inside the filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathLimits {
+    /// Most components an entry path may have.
+    pub max_depth: usize,
+    /// Longest a single component may be, in bytes.
+    pub max_component_len: usize,
+    /// Longest the entry's path may be once joined to the destination, in
+    /// bytes.
+    pub max_path_len: usize,
+}
+
+impl Default for PathLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 128,
+            max_component_len: 255,
+            // PATH_MAX counts the terminating NUL.
+            max_path_len: libc::PATH_MAX as usize - 1,
+        }
+    }
+}
+
+impl PathLimits {
+    fn check(&self, path: &Path, dest_dir: &Path) -> Result<(), Error> {
+        let refuse = |problem: String| {
+            Err(Error::StoreCorruption {
+                message: format!("archive entry {} {problem}", path.display()),
+            })
+        };
+        let depth = path.components().count();
+        if depth > self.max_depth {
+            return refuse(format!(
+                "is nested {depth} components deep; the limit is {}",
+                self.max_depth
+            ));
+        }
+        if let Some(component) = path
+            .components()
+            .map(|c| c.as_os_str().len())
+            .find(|&len| len > self.max_component_len)
+        {
+            return refuse(format!(
+                "has a {component}-byte name; the limit is {}",
+                self.max_component_len
+            ));
+        }
+        let full = dest_dir.join(path).as_os_str().len();
+        if full > self.max_path_len {
+            return refuse(format!(
+                "would be unpacked to a {full}-byte path; the limit is {}",
+                self.max_path_len
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum CompressionFormat {
     Gzip,
@@ -62,35 +124,67 @@ pub fn extract_tarball(tarball_path: &Path, dest_dir: &Path) -> Result<(), Error
 }
 
 pub fn extract_archive(archive_path: &Path, dest_dir: &Path) -> Result<(), Error> {
+    extract_archive_with_limits(archive_path, dest_dir, &PathLimits::default())
+}
+
+pub fn extract_archive_with_limits(
+    archive_path: &Path,
+    dest_dir: &Path,
+    limits: &PathLimits,
+) -> Result<(), Error> {
+    extract_archive_buffered(archive_path, dest_dir, limits, DEFAULT_READ_BUFFER)
+}
+
+/// Read buffer of extractions made outside an
+/// [`ExtractionBudget`](super::ExtractionBudget), the same as `BufReader`'s.
+const DEFAULT_READ_BUFFER: usize = 8 * 1024;
+
+/// [`extract_archive_with_limits`], reading the archive through a
+/// `read_buffer`-byte buffer. Zip archives are read directly.
+pub(crate) fn extract_archive_buffered(
+    archive_path: &Path,
+    dest_dir: &Path,
+    limits: &PathLimits,
+    read_buffer: usize,
+) -> Result<(), Error> {
     let format = detect_compression(archive_path)?;
 
     let file = File::open(archive_path).map_err(Error::store("failed to open archive"))?;
-    let reader = BufReader::new(file);
+    let reader = BufReader::with_capacity(read_buffer, file);
 
     match format {
         CompressionFormat::Gzip => {
             let decoder = GzDecoder::new(reader);
-            extract_tar_archive(decoder, dest_dir)
+            extract_tar_archive(decoder, dest_dir, limits)
         }
         CompressionFormat::Xz => {
-            let decoder = XzDecoder::new(reader);
-            extract_tar_archive(decoder, dest_dir)
+            let stream = xz2::stream::Stream::new_stream_decoder(DECODER_BYTES, 0)
+                .map_err(Error::store("failed to create xz decoder"))?;
+            let decoder = XzDecoder::new_stream(reader, stream);
+            extract_tar_archive(decoder, dest_dir, limits)
         }
         CompressionFormat::Zstd => {
-            let decoder =
-                ZstdDecoder::new(reader).map_err(Error::store("failed to create zstd decoder"))?;
-            extract_tar_archive(decoder, dest_dir)
+            let mut decoder = ZstdDecoder::with_buffer(reader)
+                .map_err(Error::store("failed to create zstd decoder"))?;
+            decoder
+                .window_log_max(DECODER_BYTES.ilog2())
+                .map_err(Error::store("failed to create zstd decoder"))?;
+            extract_tar_archive(decoder, dest_dir, limits)
         }
-        CompressionFormat::Zip => extract_zip_archive(archive_path, dest_dir),
+        CompressionFormat::Zip => extract_zip_archive(archive_path, dest_dir, limits),
         CompressionFormat::Unknown => {
             // Try gzip as fallback
             let decoder = GzDecoder::new(reader);
-            extract_tar_archive(decoder, dest_dir)
+            extract_tar_archive(decoder, dest_dir, limits)
         }
     }
 }
 
-fn extract_tar_archive<R: Read>(reader: R, dest_dir: &Path) -> Result<(), Error> {
+fn extract_tar_archive<R: Read>(
+    reader: R,
+    dest_dir: &Path,
+    limits: &PathLimits,
+) -> Result<(), Error> {
     let mut archive = Archive::new(reader);
 
     archive.set_preserve_permissions(true);
@@ -111,6 +205,7 @@ fn extract_tar_archive<R: Read>(reader: R, dest_dir: &Path) -> Result<(), Error>
 
         // Security check: validate path doesn't escape destination
         validate_path(&entry_path, dest_dir)?;
+        limits.check(&entry_path, dest_dir)?;
 
         let ctx = format!("failed to unpack entry {path_display}");
         entry.unpack_in(dest_dir).map_err(Error::store(&ctx))?;
@@ -119,7 +214,7 @@ fn extract_tar_archive<R: Read>(reader: R, dest_dir: &Path) -> Result<(), Error>
     Ok(())
 }
 
-fn extract_zip_archive(path: &Path, dest_dir: &Path) -> Result<(), Error> {
+fn extract_zip_archive(path: &Path, dest_dir: &Path, limits: &PathLimits) -> Result<(), Error> {
     let file = File::open(path).map_err(Error::store("failed to open zip archive"))?;
     let mut zip = zip::ZipArchive::new(file).map_err(Error::store("failed to open zip archive"))?;
 
@@ -134,6 +229,7 @@ fn extract_zip_archive(path: &Path, dest_dir: &Path) -> Result<(), Error> {
         };
 
         validate_path(&raw_path, dest_dir)?;
+        limits.check(&raw_path, dest_dir)?;
 
         let out_path = dest_dir.join(&raw_path);
 
@@ -225,7 +321,7 @@ fn validate_path(path: &Path, dest_dir: &Path) -> Result<(), Error> {
 ///
 /// For absolute paths, .. components cannot escape above the root.
 /// For relative paths, leading .. components are preserved.
-fn normalize_path(path: &Path) -> PathBuf {
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
     use std::path::Component;
 
     let mut components = Vec::new();
@@ -275,7 +371,7 @@ fn normalize_path(path: &Path) -> PathBuf {
 /// For file-based extraction with auto-detection, use `extract_tarball` instead.
 pub fn extract_tarball_from_reader<R: Read>(reader: R, dest_dir: &Path) -> Result<(), Error> {
     let decoder = GzDecoder::new(reader);
-    extract_tar_archive(decoder, dest_dir)
+    extract_tar_archive(decoder, dest_dir, &PathLimits::default())
 }
 
 #[cfg(test)]
@@ -629,4 +725,64 @@ mod tests {
         fs::write(&path, b"\x7fELF raw executable bytes").unwrap();
         assert!(!is_archive(&path).unwrap());
     }
+
+    #[test]
+    fn refuses_entries_past_the_path_limits_naming_them() {
+        let tmp = TempDir::new().unwrap();
+        let deep = format!("{}/file", vec!["d"; 20].join("/"));
+        let tarball = create_test_tarball(vec![(deep.as_str(), b"x", None)]);
+        let archive = tmp.path().join("deep.tar.gz");
+        fs::write(&archive, tarball).unwrap();
+        for dir in ["ok", "shallow", "short", "shorter"] {
+            fs::create_dir(tmp.path().join(dir)).unwrap();
+        }
+
+        extract_archive(&archive, &tmp.path().join("ok")).unwrap();
+        assert!(tmp.path().join("ok").join(&deep).exists());
+
+        let shallow = PathLimits {
+            max_depth: 10,
+            ..PathLimits::default()
+        };
+        let err = extract_archive_with_limits(&archive, &tmp.path().join("shallow"), &shallow)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&deep), "{err}");
+        assert!(err.contains("21 components deep; the limit is 10"), "{err}");
+        assert!(!tmp.path().join("shallow/d").exists());
+
+        let dest = tmp.path().join("short");
+        let short = PathLimits {
+            max_path_len: dest.as_os_str().len() + 1 + deep.len(),
+            ..PathLimits::default()
+        };
+        extract_archive_with_limits(&archive, &dest, &short).unwrap();
+        // "shorter" is two bytes longer than "short".
+        let err = extract_archive_with_limits(&archive, &tmp.path().join("shorter"), &short)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("-byte path"), "{err}");
+    }
+
+    #[test]
+    fn refuses_overlong_names_by_default() {
+        let tmp = TempDir::new().unwrap();
+        let name = format!("share/{}", "n".repeat(300));
+        let mut builder = Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, &name, &b"x"[..]).unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+        let archive = tmp.path().join("long.tar.gz");
+        fs::write(&archive, encoder.finish().unwrap()).unwrap();
+        fs::create_dir(tmp.path().join("out")).unwrap();
+
+        let err = extract_archive(&archive, &tmp.path().join("out"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&name), "{err}");
+        assert!(err.contains("300-byte name; the limit is 255"), "{err}");
+    }
 }
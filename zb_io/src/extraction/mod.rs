@@ -1,4 +1,17 @@
+pub mod budget;
 pub mod extract;
+#[cfg(feature = "materialize")]
+#[cfg_attr(
+    not(any(
+        all(target_os = "macos", feature = "patch-macho"),
+        all(target_os = "linux", feature = "patch-elf")
+    )),
+    allow(dead_code)
+)]
 pub mod patch;
 
-pub use extract::{extract_archive, extract_tarball, extract_tarball_from_reader, is_archive};
+pub use budget::{ExtractionBudget, ExtractionLimits, ExtractionPermit, ExtractionStats};
+pub use extract::{
+    PathLimits, extract_archive, extract_archive_with_limits, extract_tarball,
+    extract_tarball_from_reader, is_archive,
+};
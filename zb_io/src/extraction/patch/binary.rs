@@ -0,0 +1,271 @@
+use std::fs;
+use std::io::Write as _;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+use zb_core::Error;
+
+use super::{create_patch_temp, is_patch_temp};
+
+/// Offset of the first path under `prefix` at or after `from`: `prefix` at
+/// the end of the data or followed by `/` or a NUL terminator.
+fn find_prefix_path(contents: &[u8], prefix: &[u8], from: usize) -> Option<usize> {
+    if prefix.is_empty() {
+        return None;
+    }
+    (from..contents.len().saturating_sub(prefix.len()) + 1).find(|&i| {
+        contents[i..].starts_with(prefix)
+            && matches!(
+                contents.get(i + prefix.len()).copied(),
+                None | Some(0) | Some(b'/')
+            )
+    })
+}
+
+/// How many paths under `prefix` are embedded in `contents`.
+pub(crate) fn count_prefix_paths(contents: &[u8], prefix: &str) -> usize {
+    let prefix = prefix.as_bytes();
+    let mut count = 0;
+    let mut from = 0;
+    while let Some(i) = find_prefix_path(contents, prefix, from) {
+        count += 1;
+        from = i + prefix.len();
+    }
+    count
+}
+
+/// Replace `old` at `at` with the shorter `new` inside the NUL-terminated
+/// string there, shifting the rest of the string left and padding its end
+/// with NULs so nothing after it moves.
+fn replace_in_c_string(contents: &mut [u8], at: usize, old: &[u8], new: &[u8]) {
+    let end = contents[at..]
+        .iter()
+        .position(|&b| b == 0)
+        .map_or(contents.len(), |n| at + n);
+    let mut replacement = new.to_vec();
+    replacement.extend_from_slice(&contents[at + old.len()..end]);
+    replacement.resize(end - at, 0);
+    contents[at..end].copy_from_slice(&replacement);
+}
+
+/// Rewrite paths under any of `old_prefixes` compiled into the binary at
+/// `path` so they point under `new_prefix`. Strings can't grow in place, so
+/// each rewritten string is NUL-padded to its old length, and an old prefix
+/// shorter than `new_prefix` is left alone with a warning. Returns whether
+/// the file changed; permissions are preserved.
+pub(crate) fn patch_binary_prefixes(
+    path: &Path,
+    old_prefixes: &[&str],
+    new_prefix: &str,
+) -> Result<bool, Error> {
+    let metadata = fs::metadata(path).map_err(Error::store("failed to read metadata"))?;
+    let original_mode = metadata.permissions().mode();
+    let is_readonly = original_mode & 0o200 == 0;
+
+    if is_readonly {
+        let mut perms = metadata.permissions();
+        perms.set_mode(original_mode | 0o200);
+        fs::set_permissions(path, perms).map_err(Error::store("failed to make writable"))?;
+    }
+
+    let mut contents = fs::read(path).map_err(Error::store("failed to read file"))?;
+    let mut patched = false;
+
+    for old_prefix in old_prefixes {
+        if *old_prefix == new_prefix {
+            continue;
+        }
+
+        let old_bytes = old_prefix.as_bytes();
+        let new_bytes = new_prefix.as_bytes();
+
+        if new_bytes.len() > old_bytes.len() {
+            // Cannot expand shorter paths in-place in binaries. Skip this
+            // prefix — load commands and RUNPATHs are rewritten by their own
+            // passes regardless of length, and many binaries legitimately
+            // reference shorter prefixes like /usr/local for system
+            // libraries (not Homebrew paths).
+            //
+            // See: https://github.com/lucasgelfond/zerobrew/issues/286
+            let has_old_paths = contents
+                .windows(old_bytes.len() + 1)
+                .any(|w| w[..old_bytes.len()] == *old_bytes && w[old_bytes.len()] == b'/');
+            if has_old_paths {
+                warn!(
+                    path = %path.display(),
+                    old_prefix = %old_prefix,
+                    new_prefix = %new_prefix,
+                    "binary contains hardcoded paths under {old_prefix} that \
+                    could not be rewritten to {new_prefix} (new path is longer). \
+                    this package may not work correctly
+                    tracking issue: https://github.com/lucasgelfond/zerobrew/issues/286
+                    ",
+                );
+            }
+            continue;
+        }
+
+        let mut from = 0;
+        while let Some(i) = find_prefix_path(&contents, old_bytes, from) {
+            replace_in_c_string(&mut contents, i, old_bytes, new_bytes);
+            patched = true;
+            from = i + new_bytes.len();
+        }
+    }
+
+    if patched {
+        let (temp_path, mut temp_file) =
+            create_patch_temp(path).map_err(Error::store("failed to create temp file"))?;
+        temp_file
+            .write_all(&contents)
+            .map_err(Error::store("failed to write temp file"))?;
+        drop(temp_file);
+
+        fs::rename(&temp_path, path).map_err(Error::store("failed to rename temp file"))?;
+
+        // Restore original permissions — the temp file is created 0644,
+        // which drops the execute bit from patched binaries.
+        fs::set_permissions(path, metadata.permissions())
+            .map_err(Error::store("failed to restore permissions after patching"))?;
+    } else if is_readonly {
+        let mut perms = metadata.permissions();
+        perms.set_mode(original_mode);
+        let _ = fs::set_permissions(path, perms);
+    }
+
+    Ok(patched)
+}
+
+/// Non-text files under `keg_path` with paths under `prefix` in them, and
+/// how many such paths each holds. Symlinks are skipped.
+pub(crate) fn binaries_with_prefix(
+    keg_path: &Path,
+    prefix: &str,
+) -> Result<Vec<(PathBuf, Vec<u8>)>, Error> {
+    let mut binaries = Vec::new();
+
+    for entry in walkdir::WalkDir::new(keg_path).follow_links(false) {
+        let entry = entry.map_err(|e| Error::StoreCorruption {
+            message: format!("failed to walk {}: {e}", keg_path.display()),
+        })?;
+        if !entry.file_type().is_file() || is_patch_temp(entry.path()) {
+            continue;
+        }
+        let ctx = format!("failed to read {}", entry.path().display());
+        let contents = fs::read(entry.path()).map_err(Error::store(ctx.as_str()))?;
+        if !is_text(&contents) && count_prefix_paths(&contents, prefix) > 0 {
+            binaries.push((entry.into_path(), contents));
+        }
+    }
+
+    binaries.sort();
+    Ok(binaries)
+}
+
+/// Files under `keg_path` with paths under `old_prefix` compiled into them
+/// that only an in-place rewrite can change, so a longer prefix can't be
+/// written. Text files are rewritten at any length and don't count, and
+/// neither do ELF RUNPATH and interpreter entries or Mach-O dylib and rpath
+/// load commands, which the patch backends rewrite at any length too.
+/// Without a backend every embedded path counts.
+pub fn baked_prefix_files(keg_path: &Path, old_prefix: &Path) -> Result<Vec<PathBuf>, Error> {
+    let old_prefix = old_prefix.to_string_lossy();
+
+    Ok(binaries_with_prefix(keg_path, &old_prefix)?
+        .into_iter()
+        .filter(|(_, contents)| {
+            let embedded = count_prefix_paths(contents, &old_prefix);
+            #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+            let embedded =
+                embedded.saturating_sub(super::linux::dynamic_prefix_paths(contents, &old_prefix));
+            #[cfg(all(target_os = "macos", feature = "patch-macho"))]
+            let embedded = embedded.saturating_sub(count_prefix_paths(
+                &super::codesign::load_command_paths(contents),
+                &old_prefix,
+            ));
+            embedded > 0
+        })
+        .map(|(path, _)| path)
+        .collect())
+}
+
+fn is_text(contents: &[u8]) -> bool {
+    !contents.contains(&0) && std::str::from_utf8(contents).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn counts_only_whole_path_prefixes() {
+        let contents = b"/opt/zb/lib\0/opt/zbx/lib\0/opt/zb\0x/opt/zb";
+        assert_eq!(count_prefix_paths(contents, "/opt/zb"), 3);
+        assert_eq!(count_prefix_paths(contents, ""), 0);
+    }
+
+    #[test]
+    fn rewrites_shorter_prefixes_with_nul_padding() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("tool");
+        fs::write(
+            &path,
+            b"\0\x01/home/me/zb/etc/tool.conf\0/home/me/zbx\0/home/me/zb/a:/home/me/zb/b\0",
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o555)).unwrap();
+        let lookalike = tmp.path().join("tool.tmp_patch");
+        fs::write(&lookalike, b"shipped by the bottle").unwrap();
+
+        assert!(patch_binary_prefixes(&path, &["/home/me/zb"], "/opt/zb").unwrap());
+
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            b"\0\x01/opt/zb/etc/tool.conf\0\0\0\0\0/home/me/zbx\0/opt/zb/a:/opt/zb/b\0\0\0\0\0\0\0\0\0"
+        );
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o555
+        );
+        assert_eq!(fs::read(&lookalike).unwrap(), b"shipped by the bottle");
+        let mut left: Vec<_> = fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["tool", "tool.tmp_patch"]);
+    }
+
+    #[test]
+    fn leaves_binaries_alone_when_new_prefix_is_longer() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("tool");
+        let original = b"\0/opt/zb/etc/tool.conf\0".to_vec();
+        fs::write(&path, &original).unwrap();
+
+        assert!(!patch_binary_prefixes(&path, &["/opt/zb"], "/home/me/zb").unwrap());
+
+        assert_eq!(fs::read(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn baked_prefix_files_ignores_text_and_unrelated_files() {
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path().join("keg");
+        fs::create_dir_all(keg.join("bin")).unwrap();
+        fs::create_dir_all(keg.join("lib/pkgconfig")).unwrap();
+        fs::write(keg.join("bin/baked"), b"\0\x02/opt/zb/etc/baked.conf\0").unwrap();
+        fs::write(keg.join("bin/clean"), b"\0\x02/usr/lib/libc.so\0").unwrap();
+        fs::write(
+            keg.join("lib/pkgconfig/x.pc"),
+            "prefix=/opt/zb/Cellar/x/1.0\n",
+        )
+        .unwrap();
+
+        let baked = baked_prefix_files(&keg, Path::new("/opt/zb")).unwrap();
+
+        assert_eq!(baked, vec![keg.join("bin/baked")]);
+    }
+}
@@ -0,0 +1,707 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use super::PatchFailure;
+
+const MH_MAGIC: u32 = 0xfeedface;
+const MH_MAGIC_64: u32 = 0xfeedfacf;
+const MH_EXECUTE: u32 = 0x2;
+const FAT_MAGIC: u32 = 0xcafebabe;
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+/// Load commands holding a path `install_name_tool` can rewrite at any
+/// length: dylib ids and references (`LC_ID_DYLIB`, `LC_LOAD_DYLIB` and
+/// its weak, re-export, lazy and upward variants) and `LC_RPATH`.
+const PATH_LOAD_COMMANDS: [u32; 7] = [
+    0xc, 0xd, 0x20, 0x80000018, 0x8000001c, 0x8000001f, 0x80000023,
+];
+const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade0cc0;
+const CSMAGIC_CODEDIRECTORY: u32 = 0xfade0c02;
+const CSSLOT_CODEDIRECTORY: u32 = 0;
+const CSSLOT_ALTERNATE_CODEDIRECTORIES: u32 = 0x1000;
+const CS_HASHTYPE_SHA256: u8 = 2;
+
+/// Java class files share the fat magic; real universal binaries have only
+/// a handful of slices.
+const MAX_FAT_ARCHS: u32 = 30;
+
+/// Files per `codesign` invocation, well below any argument length limit.
+const SIGN_BATCH_SIZE: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Signature {
+    NotMachO,
+    Signed,
+    Unsigned,
+    /// Signed, but the page hashes no longer match the file: it was
+    /// modified after signing, and macOS kills it on launch.
+    Broken,
+}
+
+/// Look for an embedded code signature without spawning `codesign -v`.
+///
+/// A Mach-O is treated as signed when it has an `LC_CODE_SIGNATURE` load
+/// command pointing at an in-bounds embedded-signature superblob whose
+/// SHA-256 code directory, if it has one, hashes to the pages it covers. A
+/// universal binary is signed only if every slice is. The signature's CMS
+/// blob is never checked; bottles ship either ad-hoc signed or with no
+/// signature.
+pub(crate) fn signature_state(data: &[u8]) -> Signature {
+    let Some(magic) = read_u32_be(data, 0) else {
+        return Signature::NotMachO;
+    };
+
+    if magic == FAT_MAGIC {
+        return fat_signature_state(data);
+    }
+
+    thin_signature_state(data).unwrap_or(Signature::NotMachO)
+}
+
+fn fat_signature_state(data: &[u8]) -> Signature {
+    let Some(nfat) = read_u32_be(data, 4) else {
+        return Signature::NotMachO;
+    };
+    if nfat == 0 || nfat > MAX_FAT_ARCHS {
+        return Signature::NotMachO;
+    }
+
+    for i in 0..nfat as usize {
+        // fat_arch: cputype, cpusubtype, offset, size, align
+        let entry = 8 + i * 20;
+        let (Some(offset), Some(size)) =
+            (read_u32_be(data, entry + 8), read_u32_be(data, entry + 12))
+        else {
+            return Signature::Unsigned;
+        };
+        let Some(slice) = data.get(offset as usize..offset as usize + size as usize) else {
+            return Signature::Unsigned;
+        };
+        match thin_signature_state(slice) {
+            Some(Signature::Signed) => {}
+            Some(Signature::Broken) => return Signature::Broken,
+            _ => return Signature::Unsigned,
+        }
+    }
+
+    Signature::Signed
+}
+
+/// `None` when `data` is not a thin Mach-O.
+fn thin_signature_state(data: &[u8]) -> Option<Signature> {
+    let read: fn(&[u8], usize) -> Option<u32> = match read_u32_le(data, 0)? {
+        MH_MAGIC | MH_MAGIC_64 => read_u32_le,
+        _ => match read_u32_be(data, 0)? {
+            MH_MAGIC | MH_MAGIC_64 => read_u32_be,
+            _ => return None,
+        },
+    };
+    let is_64 = read(data, 0)? == MH_MAGIC_64;
+
+    let ncmds = read(data, 16)?;
+    let mut offset = if is_64 { 32 } else { 28 };
+
+    for _ in 0..ncmds {
+        let (Some(cmd), Some(cmdsize)) = (read(data, offset), read(data, offset + 4)) else {
+            return Some(Signature::Unsigned);
+        };
+        if cmd == LC_CODE_SIGNATURE {
+            let (Some(dataoff), Some(datasize)) = (read(data, offset + 8), read(data, offset + 12))
+            else {
+                return Some(Signature::Unsigned);
+            };
+            let (dataoff, datasize) = (dataoff as usize, datasize as usize);
+            return Some(if !superblob_in_bounds(data, dataoff, datasize) {
+                Signature::Unsigned
+            } else if page_hashes_match(data, dataoff) {
+                Signature::Signed
+            } else {
+                Signature::Broken
+            });
+        }
+        if cmdsize < 8 {
+            return Some(Signature::Unsigned);
+        }
+        offset += cmdsize as usize;
+    }
+
+    Some(Signature::Unsigned)
+}
+
+fn superblob_in_bounds(data: &[u8], dataoff: usize, datasize: usize) -> bool {
+    if datasize < 12
+        || dataoff
+            .checked_add(datasize)
+            .is_none_or(|end| end > data.len())
+    {
+        return false;
+    }
+    // The superblob is always big-endian: magic, length, count.
+    read_u32_be(data, dataoff) == Some(CSMAGIC_EMBEDDED_SIGNATURE)
+        && read_u32_be(data, dataoff + 4).is_some_and(|len| len as usize <= datasize)
+}
+
+/// Check the first SHA-256 code directory in the superblob at `dataoff`
+/// against the bytes it covers. Signatures with only SHA-1 directories
+/// can't be checked this way and pass.
+fn page_hashes_match(data: &[u8], dataoff: usize) -> bool {
+    let Some(count) = read_u32_be(data, dataoff + 8) else {
+        return false;
+    };
+    let alternates = CSSLOT_ALTERNATE_CODEDIRECTORIES..CSSLOT_ALTERNATE_CODEDIRECTORIES + 5;
+    for i in 0..count as usize {
+        let entry = dataoff + 12 + i * 8;
+        let (Some(slot), Some(offset)) = (read_u32_be(data, entry), read_u32_be(data, entry + 4))
+        else {
+            return false;
+        };
+        if slot != CSSLOT_CODEDIRECTORY && !alternates.contains(&slot) {
+            continue;
+        }
+        let directory = dataoff + offset as usize;
+        if read_u32_be(data, directory) != Some(CSMAGIC_CODEDIRECTORY) {
+            return false;
+        }
+        if data.get(directory + 37) == Some(&CS_HASHTYPE_SHA256) {
+            return code_directory_matches(data, directory);
+        }
+    }
+    true
+}
+
+fn code_directory_matches(data: &[u8], directory: usize) -> bool {
+    // CodeDirectory: magic, length, version, flags, hashOffset, identOffset,
+    // nSpecialSlots, nCodeSlots, codeLimit, then hashSize, hashType,
+    // platform and log2(pageSize) as single bytes.
+    let (
+        Some(hash_offset),
+        Some(code_slots),
+        Some(code_limit),
+        Some(&hash_size),
+        Some(&page_shift),
+    ) = (
+        read_u32_be(data, directory + 16),
+        read_u32_be(data, directory + 28),
+        read_u32_be(data, directory + 32),
+        data.get(directory + 36),
+        data.get(directory + 39),
+    )
+    else {
+        return false;
+    };
+    let code_limit = code_limit as usize;
+    if hash_size != 32 || code_limit > data.len() {
+        return false;
+    }
+    // A zero page size means the whole signed range is one page.
+    let page_size = match page_shift {
+        0 => code_limit.max(1),
+        shift => 1usize.checked_shl(shift.into()).unwrap_or(usize::MAX),
+    };
+    if code_slots as usize != code_limit.div_ceil(page_size) {
+        return false;
+    }
+
+    let hashes = directory + hash_offset as usize;
+    data[..code_limit]
+        .chunks(page_size)
+        .enumerate()
+        .all(|(i, page)| {
+            data.get(hashes + i * 32..hashes + (i + 1) * 32)
+                .is_some_and(|expected| Sha256::digest(page).as_slice() == expected)
+        })
+}
+
+/// The paths in `data`'s dylib and rpath load commands, NUL-separated, from
+/// every slice of a universal binary. Empty when `data` isn't a Mach-O.
+pub(crate) fn load_command_paths(data: &[u8]) -> Vec<u8> {
+    let mut paths = Vec::new();
+    if read_u32_be(data, 0) == Some(FAT_MAGIC) {
+        let nfat = read_u32_be(data, 4).unwrap_or(0);
+        if nfat > MAX_FAT_ARCHS {
+            return paths;
+        }
+        for i in 0..nfat as usize {
+            let entry = 8 + i * 20;
+            let (Some(offset), Some(size)) =
+                (read_u32_be(data, entry + 8), read_u32_be(data, entry + 12))
+            else {
+                break;
+            };
+            if let Some(slice) = data.get(offset as usize..offset as usize + size as usize) {
+                thin_load_command_paths(slice, &mut paths);
+            }
+        }
+    } else {
+        thin_load_command_paths(data, &mut paths);
+    }
+    paths
+}
+
+fn thin_load_command_paths(data: &[u8], paths: &mut Vec<u8>) {
+    let read: fn(&[u8], usize) -> Option<u32> = match read_u32_le(data, 0) {
+        Some(MH_MAGIC | MH_MAGIC_64) => read_u32_le,
+        _ => match read_u32_be(data, 0) {
+            Some(MH_MAGIC | MH_MAGIC_64) => read_u32_be,
+            _ => return,
+        },
+    };
+    let is_64 = read(data, 0) == Some(MH_MAGIC_64);
+    let Some(ncmds) = read(data, 16) else {
+        return;
+    };
+    let mut offset = if is_64 { 32 } else { 28 };
+
+    for _ in 0..ncmds {
+        let (Some(cmd), Some(cmdsize)) = (read(data, offset), read(data, offset + 4)) else {
+            return;
+        };
+        if cmdsize < 8 {
+            return;
+        }
+        let command = data.get(offset..offset + cmdsize as usize);
+        // Every one of these keeps the offset of its path, from the start
+        // of the command, right after cmd and cmdsize.
+        if PATH_LOAD_COMMANDS.contains(&cmd)
+            && let (Some(command), Some(name)) = (command, read(data, offset + 8))
+            && let Some(path) = command.get(name as usize..)
+        {
+            let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+            paths.extend_from_slice(&path[..end]);
+            paths.push(0);
+        }
+        offset += cmdsize as usize;
+    }
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Ad-hoc sign every unsigned Mach-O among `files` with `codesign`, in as few
+/// invocations as possible. Signed binaries and non-Mach-O files are never
+/// passed to the tool. Returns how many files were handed to `codesign`.
+pub(crate) fn sign_unsigned(files: &[PathBuf], codesign: &Path) -> usize {
+    let unsigned: Vec<&PathBuf> = files
+        .par_iter()
+        .filter(|path| {
+            fs::read(path).is_ok_and(|data| signature_state(&data) == Signature::Unsigned)
+        })
+        .collect();
+
+    sign_batches(&unsigned, codesign);
+    unsigned.len()
+}
+
+/// The Mach-O files among `files` without a valid signature, with their
+/// state, sorted by path. Files that can't be read are left out.
+pub(crate) fn invalid_signatures(files: &[PathBuf]) -> Vec<(PathBuf, Signature)> {
+    let mut invalid: Vec<(PathBuf, Signature)> = files
+        .par_iter()
+        .filter_map(|path| {
+            let state = signature_state(&fs::read(path).ok()?);
+            matches!(state, Signature::Unsigned | Signature::Broken).then(|| (path.clone(), state))
+        })
+        .collect();
+    invalid.sort_by(|a, b| a.0.cmp(&b.0));
+    invalid
+}
+
+/// The Mach-O files among `files` that macOS won't run as they are, sorted
+/// by path: those whose signature no longer matches and, when
+/// `require_signed_executables` is set as on Apple silicon, executables
+/// with no signature at all. Unsigned libraries are left out.
+pub(crate) fn unrunnable(files: &[PathBuf], require_signed_executables: bool) -> Vec<PathBuf> {
+    invalid_signatures(files)
+        .into_iter()
+        .filter(|(path, state)| {
+            *state == Signature::Broken
+                || (require_signed_executables
+                    && fs::read(path).is_ok_and(|data| is_executable(&data)))
+        })
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// Whether `data` is a Mach-O executable, or a universal binary with one.
+fn is_executable(data: &[u8]) -> bool {
+    if read_u32_be(data, 0) == Some(FAT_MAGIC) {
+        let nfat = read_u32_be(data, 4).unwrap_or(0).min(MAX_FAT_ARCHS) as usize;
+        return (0..nfat).any(|i| {
+            let entry = 8 + i * 20;
+            let (Some(offset), Some(size)) =
+                (read_u32_be(data, entry + 8), read_u32_be(data, entry + 12))
+            else {
+                return false;
+            };
+            data.get(offset as usize..offset as usize + size as usize)
+                .is_some_and(thin_is_executable)
+        });
+    }
+    thin_is_executable(data)
+}
+
+fn thin_is_executable(data: &[u8]) -> bool {
+    let filetype = match read_u32_le(data, 0) {
+        Some(MH_MAGIC | MH_MAGIC_64) => read_u32_le(data, 12),
+        _ => match read_u32_be(data, 0) {
+            Some(MH_MAGIC | MH_MAGIC_64) => read_u32_be(data, 12),
+            _ => None,
+        },
+    };
+    filetype == Some(MH_EXECUTE)
+}
+
+/// Verify the signatures of `files`, which were just modified, and re-sign
+/// every one that is missing or stale once more. Returns the files that
+/// still have no valid signature, which would be killed on launch.
+pub(crate) fn verify_signatures(files: &[PathBuf], codesign: &Path) -> Vec<PatchFailure> {
+    let invalid: Vec<PathBuf> = invalid_signatures(files)
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    if invalid.is_empty() {
+        return Vec::new();
+    }
+    sign_batches(&invalid, codesign);
+
+    invalid_signatures(&invalid)
+        .into_iter()
+        .map(|(path, state)| {
+            let error = match state {
+                Signature::Broken => "code signature does not match the file after re-signing",
+                _ => "no code signature after re-signing",
+            };
+            PatchFailure::new(&path, error)
+        })
+        .collect()
+}
+
+/// Ad-hoc sign `files` with `codesign`, in batches.
+fn sign_batches<P: AsRef<Path> + Sync>(files: &[P], codesign: &Path) {
+    files.par_chunks(SIGN_BATCH_SIZE).for_each(|batch| {
+        // codesign rewrites the file, so read-only binaries are made writable
+        // for the duration and restored afterwards.
+        let restore: Vec<(&Path, u32)> = batch
+            .iter()
+            .filter_map(|path| {
+                let path = path.as_ref();
+                let mode = fs::metadata(path).ok()?.permissions().mode();
+                if mode & 0o200 != 0 {
+                    return None;
+                }
+                fs::set_permissions(path, fs::Permissions::from_mode(mode | 0o200)).ok()?;
+                Some((path, mode))
+            })
+            .collect();
+
+        let status = Command::new(codesign)
+            .args(["--force", "--sign", "-"])
+            .args(batch.iter().map(|path| path.as_ref().as_os_str()))
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+        if !status.is_ok_and(|s| s.success()) {
+            warn!(files = batch.len(), "codesign failed for some binaries");
+        }
+
+        for (path, mode) in restore {
+            let _ = fs::set_permissions(path, fs::Permissions::from_mode(mode));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A 64-bit little-endian Mach-O with a single load command, optionally
+    /// `LC_CODE_SIGNATURE` pointing at a minimal superblob.
+    fn fake_macho(signed: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        for word in [MH_MAGIC_64, 0x0100000c, 0, 2, 1, 16, 0, 0] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        let (cmd, dataoff) = if signed {
+            (LC_CODE_SIGNATURE, 48u32)
+        } else {
+            (0x26, 48u32) // LC_FUNCTION_STARTS
+        };
+        for word in [cmd, 16, dataoff, 12] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        for word in [CSMAGIC_EMBEDDED_SIGNATURE, 12, 0] {
+            data.extend_from_slice(&word.to_be_bytes());
+        }
+        data
+    }
+
+    /// A Mach-O signed with a SHA-256 code directory over its first 64
+    /// bytes, which end in an embedded prefix.
+    fn fake_hashed_macho() -> Vec<u8> {
+        let mut data = Vec::new();
+        for word in [MH_MAGIC_64, 0x0100000c, 0, 2, 1, 16, 0, 0] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        for word in [LC_CODE_SIGNATURE, 16, 64, 96] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(b"/opt/homebrew/x\0");
+        let page_hash = Sha256::digest(&data);
+        for word in [CSMAGIC_EMBEDDED_SIGNATURE, 96, 1, CSSLOT_CODEDIRECTORY, 20] {
+            data.extend_from_slice(&word.to_be_bytes());
+        }
+        for word in [CSMAGIC_CODEDIRECTORY, 76, 0x20100, 0, 44, 0, 0, 1, 64] {
+            data.extend_from_slice(&word.to_be_bytes());
+        }
+        data.extend_from_slice(&[32, CS_HASHTYPE_SHA256, 0, 12, 0, 0, 0, 0]);
+        data.extend_from_slice(&page_hash);
+        data
+    }
+
+    /// Rewrite the embedded prefix without re-signing, as a patch pass
+    /// whose codesign step failed would.
+    fn patch_without_signing(data: &mut [u8]) {
+        data[48..64].copy_from_slice(b"/opt/zerobrew/x\0");
+    }
+
+    fn fat(slices: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+        data.extend_from_slice(&(slices.len() as u32).to_be_bytes());
+        let mut offset = 8 + 20 * slices.len();
+        for slice in slices {
+            for word in [0u32, 0, offset as u32, slice.len() as u32, 0] {
+                data.extend_from_slice(&word.to_be_bytes());
+            }
+            offset += slice.len();
+        }
+        for slice in slices {
+            data.extend_from_slice(slice);
+        }
+        data
+    }
+
+    #[test]
+    fn detects_embedded_signature() {
+        assert_eq!(signature_state(&fake_macho(true)), Signature::Signed);
+        assert_eq!(signature_state(&fake_macho(false)), Signature::Unsigned);
+        assert_eq!(signature_state(b"#!/bin/sh\n"), Signature::NotMachO);
+    }
+
+    #[test]
+    fn truncated_superblob_counts_as_unsigned() {
+        let mut data = fake_macho(true);
+        data.truncate(data.len() - 4);
+        assert_eq!(signature_state(&data), Signature::Unsigned);
+    }
+
+    #[test]
+    fn universal_binary_needs_every_slice_signed() {
+        let signed = fat(&[fake_macho(true), fake_macho(true)]);
+        let mixed = fat(&[fake_macho(true), fake_macho(false)]);
+        assert_eq!(signature_state(&signed), Signature::Signed);
+        assert_eq!(signature_state(&mixed), Signature::Unsigned);
+    }
+
+    #[test]
+    fn pages_changed_after_signing_break_the_signature() {
+        let signed = fake_hashed_macho();
+        assert_eq!(signature_state(&signed), Signature::Signed);
+
+        let mut patched = signed.clone();
+        patch_without_signing(&mut patched);
+        assert_eq!(signature_state(&patched), Signature::Broken);
+        assert_eq!(
+            signature_state(&fat(&[signed.clone(), patched])),
+            Signature::Broken
+        );
+
+        // The hashes themselves being overwritten breaks it just the same.
+        let mut corrupted = signed;
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        assert_eq!(signature_state(&corrupted), Signature::Broken);
+    }
+
+    #[test]
+    fn broken_signatures_are_resigned_once_and_leftovers_reported() {
+        let tmp = TempDir::new().unwrap();
+        let good = tmp.path().join("good");
+        fs::write(&good, fake_hashed_macho()).unwrap();
+        let tool = tmp.path().join("bin/tool");
+        fs::create_dir_all(tool.parent().unwrap()).unwrap();
+        let mut patched = fake_hashed_macho();
+        patch_without_signing(&mut patched);
+        fs::write(&tool, &patched).unwrap();
+        let files = vec![good.clone(), tool.clone()];
+
+        assert_eq!(
+            invalid_signatures(&files),
+            [(tool.clone(), Signature::Broken)]
+        );
+
+        let quote = |path: &Path| path.display().to_string().replace('\'', r"'\''");
+        let log = tmp.path().join("codesign.log");
+        let failing = tmp.path().join("codesign-fails");
+        fs::write(
+            &failing,
+            format!("#!/bin/sh\necho \"$#\" >> '{}'\nexit 1\n", quote(&log)),
+        )
+        .unwrap();
+        fs::set_permissions(&failing, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let failures = verify_signatures(&files, &failing);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].path, tool);
+        assert!(failures[0].error.contains("does not match"));
+        // A single retry, holding `--force --sign -` and the broken file.
+        assert_eq!(fs::read_to_string(&log).unwrap(), "4\n");
+
+        let working = tmp.path().join("codesign-works");
+        fs::write(
+            &working,
+            format!(
+                "#!/bin/sh\nshift 3\nfor f; do cp '{}' \"$f\"; done\n",
+                quote(&good)
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&working, fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert!(verify_signatures(&files, &working).is_empty());
+        assert!(invalid_signatures(&files).is_empty());
+    }
+
+    #[test]
+    fn only_unsigned_executables_need_a_signature() {
+        let tmp = TempDir::new().unwrap();
+        let tool = tmp.path().join("tool");
+        fs::write(&tool, fake_macho(false)).unwrap();
+        let mut dylib = fake_macho(false);
+        dylib[12..16].copy_from_slice(&6u32.to_le_bytes()); // MH_DYLIB
+        let library = tmp.path().join("libfoo.dylib");
+        fs::write(&library, &dylib).unwrap();
+        let universal = tmp.path().join("universal");
+        fs::write(&universal, fat(&[dylib, fake_macho(false)])).unwrap();
+        let mut patched = fake_hashed_macho();
+        patch_without_signing(&mut patched);
+        let broken = tmp.path().join("broken");
+        fs::write(&broken, patched).unwrap();
+        let signed = tmp.path().join("signed");
+        fs::write(&signed, fake_hashed_macho()).unwrap();
+        let files = vec![
+            tool.clone(),
+            library,
+            universal.clone(),
+            broken.clone(),
+            signed,
+        ];
+
+        assert_eq!(unrunnable(&files, false), std::slice::from_ref(&broken));
+        assert_eq!(unrunnable(&files, true), [broken, tool, universal]);
+    }
+
+    #[test]
+    fn java_class_magic_is_not_macho() {
+        let mut class = FAT_MAGIC.to_be_bytes().to_vec();
+        class.extend_from_slice(&[0, 0, 0, 52]);
+        assert_eq!(signature_state(&class), Signature::NotMachO);
+    }
+
+    #[test]
+    fn load_command_paths_skip_strings_outside_load_commands() {
+        let mut data = Vec::new();
+        for word in [MH_MAGIC_64, 0x0100000c, 0, 6, 3, 84, 0, 0] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        // LC_LOAD_DYLIB: name offset, timestamp, current and compat version.
+        for word in [0xcu32, 44, 24, 0, 0, 0] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(b"/opt/zb/lib/x.dylib\0");
+        for word in [0x8000001cu32, 24, 12] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(b"/opt/zb/lib\0");
+        for word in [0x26u32, 16, 0, 0] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(b"/opt/zb/etc/baked.conf\0");
+
+        let expected = b"/opt/zb/lib/x.dylib\0/opt/zb/lib\0".to_vec();
+        assert_eq!(load_command_paths(&data), expected);
+        assert_eq!(load_command_paths(&fat(&[data])), expected);
+        assert!(load_command_paths(b"/opt/zb/lib\0").is_empty());
+    }
+
+    #[test]
+    fn verify_pass_spawns_no_processes() {
+        let tmp = TempDir::new().unwrap();
+        let log = tmp.path().join("codesign.log");
+        let shim = tmp.path().join("codesign");
+        fs::write(
+            &shim,
+            format!(
+                "#!/bin/sh\necho \"$#\" >> '{}'\n",
+                log.display().to_string().replace('\'', r"'\''")
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let bin = tmp.path().join("keg/bin");
+        fs::create_dir_all(&bin).unwrap();
+        let mut files = Vec::new();
+        for i in 0..500 {
+            let path = bin.join(format!("tool{i}"));
+            // Every 100th binary is unsigned; one of them read-only.
+            fs::write(&path, fake_macho(i % 100 != 0)).unwrap();
+            files.push(path);
+        }
+        fs::set_permissions(&files[100], fs::Permissions::from_mode(0o555)).unwrap();
+
+        let start = std::time::Instant::now();
+        let signed = sign_unsigned(&files, &shim);
+        let elapsed = start.elapsed();
+
+        assert_eq!(signed, 5);
+        let invocations = fs::read_to_string(&log).unwrap();
+        // One batch holding `--force --sign -` plus the five unsigned files.
+        assert_eq!(invocations.lines().collect::<Vec<_>>(), ["8"]);
+        assert_eq!(
+            fs::metadata(&files[100]).unwrap().permissions().mode() & 0o777,
+            0o555
+        );
+        assert!(
+            elapsed < std::time::Duration::from_secs(5),
+            "verify pass took {elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn already_signed_keg_is_a_no_op() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("signed");
+        fs::write(&path, fake_macho(true)).unwrap();
+
+        assert_eq!(
+            sign_unsigned(&[path], Path::new("/nonexistent/codesign")),
+            0
+        );
+    }
+}
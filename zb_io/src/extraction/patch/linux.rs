@@ -1,31 +1,188 @@
 use std::fs;
-use std::io::Read;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use rayon::prelude::*;
-use tracing::warn;
-use zb_core::Error;
+use zb_core::{CELLAR_DIR, Error};
+
+use super::binary::{binaries_with_prefix, count_prefix_paths, patch_binary_prefixes};
+use super::needed::LibraryResolver;
+use super::scan::KegScan;
+use super::text::{PatchConfig, TextReplacements, patch_text_files};
+use super::version::VersionFixer;
+use super::{KegBoundary, PatchFailure, PatchReport, Relocation, create_patch_temp};
+use crate::progress::{MaterializeObserver, PatchCounter, Phase, check_cancelled};
 
 /// Patch @@HOMEBREW_CELLAR@@ and @@HOMEBREW_PREFIX@@ placeholders in both ELF binaries and text files.
+/// The RUNPATH and interpreter of every ELF file are retargeted whatever
+/// `relocation` says: Linux bottles point them at Homebrew's prefix even
+/// when nothing else in the binary needs relocating. RUNPATH entries into another, uninstalled version of the package are
+/// pointed at `pkg_version`. Files that can't be patched are listed in the
+/// report rather than failing the pass, as are ELF files needing a library
+/// that can't be found once patched.
 #[cfg(target_os = "linux")]
+#[allow(clippy::too_many_arguments)]
 pub fn patch_placeholders(
     keg_path: &Path,
     prefix_dir: &Path,
-    _pkg_name: &str,
-    _pkg_version: &str,
-) -> Result<(), Error> {
-    patch_elf_placeholders(keg_path, prefix_dir)?;
-    patch_text_placeholders(keg_path, prefix_dir)?;
-    Ok(())
+    pkg_name: &str,
+    pkg_version: &str,
+    config: &PatchConfig,
+    relocation: Relocation,
+    libraries: &LibraryResolver,
+    observer: &dyn MaterializeObserver,
+) -> Result<PatchReport, Error> {
+    let scan = KegScan::new(keg_path);
+    let counter = PatchCounter::new(scan.elf.len() + scan.files.len());
+    observer.on_patch_started(&counter);
+    observer.on_phase(Phase::PatchBinaries);
+    let fixer = VersionFixer::new(&prefix_dir.join(CELLAR_DIR), pkg_name, pkg_version);
+    let binary_failures = patch_elf_placeholders(
+        &scan.elf,
+        &KegBoundary::new(keg_path),
+        prefix_dir,
+        None,
+        &fixer,
+        observer,
+        &counter,
+    );
+    check_cancelled(observer)?;
+    let mut report = patch_text(
+        keg_path,
+        &scan.files,
+        prefix_dir,
+        pkg_name,
+        config,
+        &[],
+        relocation,
+        binary_failures,
+        observer,
+        &counter,
+    )?;
+    report.missing_libraries = libraries.missing(&scan.elf);
+    Ok(report)
+}
+
+/// Re-target a keg poured under `old_prefix` to `prefix_dir`. Paths
+/// compiled into binaries are rewritten in place when the new prefix fits;
+/// RUNPATH and interpreter entries and text files are rewritten at any
+/// length.
+#[cfg(target_os = "linux")]
+#[allow(clippy::too_many_arguments)]
+pub fn relocate_placeholders(
+    keg_path: &Path,
+    prefix_dir: &Path,
+    old_prefix: &Path,
+    pkg_name: &str,
+    pkg_version: &str,
+    config: &PatchConfig,
+    libraries: &LibraryResolver,
+    observer: &dyn MaterializeObserver,
+) -> Result<PatchReport, Error> {
+    let old_prefix = old_prefix.to_string_lossy();
+    let new_prefix = prefix_dir.to_string_lossy();
+
+    observer.on_phase(Phase::PatchBinaries);
+    let boundary = KegBoundary::new(keg_path);
+    let mut binary_failures: Vec<PatchFailure> = Vec::new();
+    if new_prefix.len() <= old_prefix.len() {
+        let binaries: Vec<PathBuf> = binaries_with_prefix(keg_path, &old_prefix)?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        binary_failures = binaries
+            .par_iter()
+            .filter_map(|path| {
+                if let Err(e) = boundary.check(path) {
+                    return Some(PatchFailure::new(path, e));
+                }
+                patch_binary_prefixes(path, &[&old_prefix], &new_prefix)
+                    .err()
+                    .map(|e| PatchFailure::new(path, e))
+            })
+            .collect();
+    }
+    let scan = KegScan::new(keg_path);
+    let counter = PatchCounter::new(scan.elf.len() + scan.files.len());
+    observer.on_patch_started(&counter);
+    let fixer = VersionFixer::new(&prefix_dir.join(CELLAR_DIR), pkg_name, pkg_version);
+    binary_failures.extend(patch_elf_placeholders(
+        &scan.elf,
+        &boundary,
+        prefix_dir,
+        Some(&old_prefix),
+        &fixer,
+        observer,
+        &counter,
+    ));
+    check_cancelled(observer)?;
+
+    let mut report = patch_text(
+        keg_path,
+        &scan.files,
+        prefix_dir,
+        pkg_name,
+        config,
+        &[&old_prefix],
+        Relocation::Full,
+        binary_failures,
+        observer,
+        &counter,
+    )?;
+    report.missing_libraries = libraries.missing(&scan.elf);
+    Ok(report)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn patch_text(
+    keg_path: &Path,
+    files: &[PathBuf],
+    prefix_dir: &Path,
+    pkg_name: &str,
+    config: &PatchConfig,
+    legacy_prefixes: &[&str],
+    relocation: Relocation,
+    mut binary_failures: Vec<PatchFailure>,
+    observer: &dyn MaterializeObserver,
+    counter: &PatchCounter,
+) -> Result<PatchReport, Error> {
+    observer.on_phase(Phase::PatchText);
+    let filter = config.filter_for(pkg_name)?;
+    let replacements = TextReplacements::new(prefix_dir, &prefix_dir.join(CELLAR_DIR))
+        .with_legacy_prefixes(legacy_prefixes);
+    let text = patch_text_files(keg_path, files, &filter, &replacements, observer, counter);
+    check_cancelled(observer)?;
+    text.warn_oversized(pkg_name);
+    binary_failures.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(PatchReport {
+        relocation,
+        text,
+        binary_failures,
+        signature_failures: Vec::new(),
+        missing_libraries: Vec::new(),
+    })
+}
+
+/// How many paths under `prefix` in an ELF file sit in its RUNPATH/RPATH or
+/// interpreter, which are rewritten whatever their length.
+pub(crate) fn dynamic_prefix_paths(contents: &[u8], prefix: &str) -> usize {
+    let Ok(elf) = arwen::elf::ElfContainer::parse(contents) else {
+        return 0;
+    };
+    let mut dynamic: Vec<u8> = elf.get_rpath().join(":").into_bytes();
+    if let Some(interpreter) = elf.inner.elf_interpreter() {
+        dynamic.push(0);
+        dynamic.extend_from_slice(interpreter);
+    }
+    count_prefix_paths(&dynamic, prefix)
 }
 
 /// Detect if zerobrew has installed its own glibc and return the path to its ld.so interpreter.
 /// Returns None if zerobrew's glibc is not found, indicating we should use the system ld.so.
 fn detect_zerobrew_glibc(prefix_dir: &Path) -> Option<PathBuf> {
-    let cellar = prefix_dir.join("Cellar").join("glibc");
+    let cellar = prefix_dir.join(CELLAR_DIR).join("glibc");
 
     if !cellar.exists() {
         return None;
@@ -119,7 +276,20 @@ fn find_system_ld_so() -> Option<PathBuf> {
 
 /// Patch @@HOMEBREW_CELLAR@@ and @@HOMEBREW_PREFIX@@ placeholders in ELF binaries.
 /// Uses `arwen` crate to natively update RPATH, RUNPATH, and optionally the ELF interpreter.
-fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Error> {
+/// Entries under `previous_prefix`, the prefix a relocated keg came from,
+/// are moved to `prefix_dir` too. Files outside `boundary` are left alone.
+/// Each file is counted in `counter` once done with. Returns the files that
+/// couldn't be patched.
+#[allow(clippy::too_many_arguments)]
+fn patch_elf_placeholders(
+    elf_files: &[PathBuf],
+    boundary: &KegBoundary,
+    prefix_dir: &Path,
+    previous_prefix: Option<&str>,
+    version_fixer: &VersionFixer,
+    observer: &dyn MaterializeObserver,
+    counter: &PatchCounter,
+) -> Vec<PatchFailure> {
     let lib_path = prefix_dir.join("lib").to_string_lossy().to_string();
 
     // Detect if zerobrew has installed its own glibc
@@ -135,38 +305,31 @@ fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Erro
         find_system_ld_so()
     };
 
-    // Collect all ELF files
-    let elf_files: Vec<PathBuf> = walkdir::WalkDir::new(keg_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| {
-            // Read only first 4 bytes to check magic
-            let mut file = match fs::File::open(e.path()) {
-                Ok(f) => f,
-                Err(_) => return false,
-            };
-            let mut magic = [0u8; 4];
-            if file.read_exact(&mut magic).is_ok() {
-                return magic == *b"\x7fELF";
-            }
-            false
-        })
-        .map(|e| e.path().to_path_buf())
-        .collect();
-
-    let patch_failures = AtomicUsize::new(0);
+    let patch_failures = Mutex::new(Vec::new());
     // Use a dashmap or similar for thread-safe inode tracking if needed,
     // but we can just collect and then process, or use a Mutex.
-    let processed_inodes = std::sync::Mutex::new(std::collections::HashSet::new());
+    let processed_inodes = Mutex::new(std::collections::HashSet::new());
 
     // Clone for use in parallel closure
     let target_interpreter = target_interpreter.clone();
     let old_prefix = "@@HOMEBREW_PREFIX@@";
     let new_prefix = prefix_dir.to_string_lossy().to_string();
+    let relocate = |path: &str| -> String {
+        match previous_prefix.and_then(|previous| path.strip_prefix(previous)) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{new_prefix}{rest}"),
+            _ => path.to_string(),
+        }
+    };
+
+    let patch_file = |path: &PathBuf| {
+        if let Err(e) = boundary.check(path) {
+            patch_failures
+                .lock()
+                .unwrap()
+                .push(PatchFailure::new(path, e));
+            return;
+        }
 
-    elf_files.par_iter().for_each(|path| {
         // Check hardlinks
         if let Ok(meta) = fs::metadata(path) {
             use std::os::unix::fs::MetadataExt;
@@ -189,12 +352,10 @@ fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Erro
             let mut perms = metadata.permissions();
             perms.set_mode(original_mode | 0o200);
             if let Err(e) = fs::set_permissions(path, perms) {
-                warn!(
-                    path = %path.display(),
-                    error = %e,
-                    "failed to make ELF writable for patching"
-                );
-                patch_failures.fetch_add(1, Ordering::Relaxed);
+                patch_failures
+                    .lock()
+                    .unwrap()
+                    .push(PatchFailure::new(path, format!("not writable: {e}")));
                 return;
             }
         }
@@ -225,7 +386,10 @@ fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Erro
             } else {
                 old_rpaths
                     .iter()
-                    .map(|r| r.replace(old_prefix, &new_prefix))
+                    .map(|r| {
+                        let r = relocate(&r.replace(old_prefix, &new_prefix));
+                        version_fixer.fix(&r).unwrap_or(r)
+                    })
                     .filter(|r| r.starts_with(&new_prefix) || r.starts_with("$ORIGIN"))
                     .collect()
             };
@@ -247,17 +411,19 @@ fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Erro
             if is_executable && let Some(current_interp_bytes) = elf.inner.elf_interpreter() {
                 let current_interp_str = String::from_utf8_lossy(current_interp_bytes);
 
-                let target_interp_path = if current_interp_str.contains(old_prefix) {
-                    let expanded = current_interp_str.replace(old_prefix, &new_prefix);
-                    let expanded_path = PathBuf::from(&expanded);
-                    if expanded_path.exists() {
-                        Some(expanded_path)
+                let relocated = relocate(&current_interp_str);
+                let target_interp_path =
+                    if current_interp_str.contains(old_prefix) || relocated != current_interp_str {
+                        let expanded = relocated.replace(old_prefix, &new_prefix);
+                        let expanded_path = PathBuf::from(&expanded);
+                        if expanded_path.exists() {
+                            Some(expanded_path)
+                        } else {
+                            find_system_ld_so()
+                        }
                     } else {
-                        find_system_ld_so()
-                    }
-                } else {
-                    target_interpreter.clone()
-                };
+                        target_interpreter.clone()
+                    };
 
                 if let Some(target_path) = target_interp_path {
                     let target_str = target_path.to_string_lossy();
@@ -266,11 +432,9 @@ fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Erro
             }
 
             // Atomic write
-            let temp_path = path.with_extension("tmp_patch");
-            {
-                let mut temp_file = fs::File::create(&temp_path)?;
-                elf.write(&mut temp_file)?;
-            }
+            let (temp_path, mut temp_file) = create_patch_temp(path)?;
+            elf.write(&mut temp_file)?;
+            drop(temp_file);
             fs::rename(temp_path, path)?;
 
             // Restore original permissions (including execute bit) after atomic write
@@ -282,110 +446,25 @@ fn patch_elf_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Erro
         })();
 
         if let Err(e) = result {
-            warn!(path = %path.display(), error = %e, "failed to patch ELF");
-            patch_failures.fetch_add(1, Ordering::Relaxed);
+            patch_failures
+                .lock()
+                .unwrap()
+                .push(PatchFailure::new(path, e));
         }
-    });
-
-    let failures = patch_failures.load(Ordering::Relaxed);
-    if failures > 0 {
-        warn!(
-            failures,
-            "failed to patch ELF files; packages may not work correctly until manually patched"
-        );
-    }
-
-    Ok(())
-}
-
-/// Patch text files containing @@HOMEBREW_...@@ placeholders
-fn patch_text_placeholders(keg_path: &Path, prefix_dir: &Path) -> Result<(), Error> {
-    let prefix_str = prefix_dir.to_string_lossy().to_string();
-    let cellar_str = prefix_dir.join("Cellar").to_string_lossy().to_string();
-
-    // We search for files that are text and contain the placeholders.
-    // To avoid reading every large file, we might filter by extension or size,
-    // but Homebrew generally patches everything that looks like text.
-    // For safety, we skip anything that looks like a binary (has null bytes in first 8kb).
-
-    let files: Vec<PathBuf> = walkdir::WalkDir::new(keg_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .map(|e| e.path().to_path_buf())
-        .collect();
-
-    let patch_failures = AtomicUsize::new(0);
-
-    files.par_iter().for_each(|path| {
-        let result = (|| -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-            // Check if file is likely text
-            let mut file = fs::File::open(path)?;
-            let mut buf = [0u8; 8192];
-            let n = file.read(&mut buf)?;
-            if buf[..n].contains(&0) {
-                // Determine if it is ELF - we already handled those, but other binaries should be skipped too
-                return Ok(());
-            }
-
-            // Read full content string
-            let content = match fs::read_to_string(path) {
-                Ok(c) => c,
-                Err(_) => return Ok(()), // Not valid UTF-8, skip
-            };
-
-            if !content.contains("@@HOMEBREW_") {
-                return Ok(());
-            }
-
-            let new_content = content
-                .replace("@@HOMEBREW_PREFIX@@", &prefix_str)
-                .replace("@@HOMEBREW_CELLAR@@", &cellar_str)
-                .replace("@@HOMEBREW_REPOSITORY@@", &prefix_str)
-                .replace("@@HOMEBREW_LIBRARY@@", &format!("{}/Library", prefix_str))
-                .replace("@@HOMEBREW_PERL@@", "/usr/bin/perl")
-                .replace("@@HOMEBREW_JAVA@@", "/usr/bin/java");
-
-            // Write back
-            // Check readonly
-            let metadata = fs::metadata(path)?;
-            let original_mode = metadata.permissions().mode();
-            let is_readonly = original_mode & 0o200 == 0;
-
-            if is_readonly {
-                let mut perms = metadata.permissions();
-                perms.set_mode(original_mode | 0o200);
-                fs::set_permissions(path, perms)?;
-            }
-
-            fs::write(path, new_content)?;
-
-            if is_readonly {
-                let mut perms = metadata.permissions();
-                perms.set_mode(original_mode);
-                fs::set_permissions(path, perms)?;
-            }
-
-            Ok(())
-        })();
+    };
 
-        if let Err(e) = result {
-            warn!(
-                path = %path.display(),
-                error = %e,
-                "failed to patch text file"
-            );
-            patch_failures.fetch_add(1, Ordering::Relaxed);
-        }
+    elf_files.par_iter().for_each(|path| {
+        patch_file(path);
+        counter.advance(observer);
     });
 
-    Ok(())
+    patch_failures.into_inner().unwrap()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::progress::NoopObserver;
     use std::fs;
 
     use std::process::Command;
@@ -431,7 +510,16 @@ mod tests {
         )
         .unwrap();
 
-        let result = patch_placeholders(&pkg_dir, &prefix, "testpkg", "1.0.0");
+        let result = patch_placeholders(
+            &pkg_dir,
+            &prefix,
+            "testpkg",
+            "1.0.0",
+            &PatchConfig::default(),
+            Relocation::Full,
+            &LibraryResolver::new(&prefix),
+            &NoopObserver,
+        );
         assert!(result.is_ok());
 
         let content = fs::read_to_string(&script_path).unwrap();
@@ -442,6 +530,53 @@ mod tests {
         assert!(!content.contains("@@HOMEBREW_"));
     }
 
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn text_patching_respects_size_limit() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let pkg_dir = prefix.join("Cellar/bigdata/1.0.0");
+        let share_dir = pkg_dir.join("share");
+        fs::create_dir_all(&share_dir).unwrap();
+
+        let plain = share_dir.join("plain.txt");
+        let plain_content = "x".repeat(64 * 1024);
+        fs::write(&plain, &plain_content).unwrap();
+
+        let marked = share_dir.join("marked.txt");
+        fs::write(&marked, "y".repeat(64 * 1024) + "\n@@HOMEBREW_PREFIX@@\n").unwrap();
+
+        let small = share_dir.join("small.txt");
+        fs::write(&small, "@@HOMEBREW_PREFIX@@").unwrap();
+
+        let config = PatchConfig {
+            max_text_size: 1024,
+            ..PatchConfig::default()
+        };
+        let report = patch_placeholders(
+            &pkg_dir,
+            &prefix,
+            "bigdata",
+            "1.0.0",
+            &config,
+            Relocation::Full,
+            &LibraryResolver::new(&prefix),
+            &NoopObserver,
+        )
+        .unwrap()
+        .text;
+
+        assert_eq!(report.skipped_by_size, 1);
+        assert_eq!(report.patched, 2);
+        assert_eq!(report.oversized_patched, vec![marked.clone()]);
+        assert_eq!(fs::read_to_string(&plain).unwrap(), plain_content);
+        assert!(
+            fs::read_to_string(&marked)
+                .unwrap()
+                .contains(prefix.to_str().unwrap())
+        );
+    }
+
     #[test]
     #[cfg(target_os = "linux")]
     fn patches_elf_file() {
@@ -468,7 +603,16 @@ mod tests {
             "compiled binary should be executable"
         );
 
-        let result = patch_placeholders(&pkg_dir, &prefix, "testpkg", "1.0.0");
+        let result = patch_placeholders(
+            &pkg_dir,
+            &prefix,
+            "testpkg",
+            "1.0.0",
+            &PatchConfig::default(),
+            Relocation::Full,
+            &LibraryResolver::new(&prefix),
+            &NoopObserver,
+        );
         assert!(result.is_ok());
 
         // Verify permissions are preserved after patching
@@ -480,6 +624,92 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn elf_patching_never_writes_outside_the_keg() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let pkg_dir = prefix.join("Cellar/testpkg/1.0.0");
+        let shared = tmp.path().join("shared");
+        fs::create_dir_all(pkg_dir.join("lib")).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+
+        let outside = match compile_dummy_elf(&shared, "testbin") {
+            Some(p) => p,
+            None => {
+                eprintln!("Skipping ELF boundary test: cc not found");
+                return;
+            }
+        };
+        let original = fs::read(&outside).unwrap();
+        std::os::unix::fs::symlink(&shared, pkg_dir.join("lib/shared")).unwrap();
+
+        // Reached through a symlinked directory, as a repair pass handed an
+        // explicit file list might see it.
+        let through_link = pkg_dir.join("lib/shared/testbin");
+        let failures = patch_elf_placeholders(
+            std::slice::from_ref(&through_link),
+            &KegBoundary::new(&pkg_dir),
+            &prefix,
+            None,
+            &VersionFixer::new(&prefix.join(CELLAR_DIR), "testpkg", "1.0.0"),
+            &NoopObserver,
+            &PatchCounter::new(1),
+        );
+
+        assert_eq!(failures.len(), 1);
+        assert!(
+            failures[0].error.contains("outside the keg"),
+            "{failures:?}"
+        );
+        assert_eq!(fs::read(&outside).unwrap(), original);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn skip_relocation_bottles_still_get_their_runpath_and_interpreter_retargeted() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let pkg_dir = prefix.join("Cellar/testpkg/1.0.0");
+        let bin_dir = pkg_dir.join("bin");
+        fs::create_dir_all(&bin_dir).unwrap();
+        let src = bin_dir.join("tool.c");
+        fs::write(&src, "int main() { return 0; }").unwrap();
+        let elf_path = bin_dir.join("tool");
+        let status = Command::new("cc")
+            .arg(&src)
+            .arg("-o")
+            .arg(&elf_path)
+            .arg("-Wl,-rpath,/home/linuxbrew/.linuxbrew/lib")
+            .arg("-Wl,--dynamic-linker=/home/linuxbrew/.linuxbrew/lib/ld.so")
+            .status()
+            .expect("cc is needed to build the ELF fixture");
+        assert!(status.success());
+        fs::remove_file(&src).unwrap();
+
+        let report = patch_placeholders(
+            &pkg_dir,
+            &prefix,
+            "testpkg",
+            "1.0.0",
+            &PatchConfig::default(),
+            Relocation::SkipBinaries,
+            &LibraryResolver::new(&prefix),
+            &NoopObserver,
+        )
+        .unwrap();
+        assert!(report.binary_failures.is_empty(), "{report:?}");
+
+        let content = fs::read(&elf_path).unwrap();
+        let elf = arwen::elf::ElfContainer::parse(&content).unwrap();
+        assert_eq!(
+            elf.get_rpath(),
+            [prefix.join("lib").to_string_lossy().to_string()]
+        );
+        let interpreter = String::from_utf8_lossy(elf.inner.elf_interpreter().unwrap());
+        assert!(!interpreter.contains("linuxbrew"), "{interpreter}");
+    }
+
     #[test]
     #[cfg(target_os = "linux")]
     fn test_glibc_detection() {
@@ -3,6 +3,14 @@ use std::path::{Path, PathBuf};
 use tracing::warn;
 use zb_core::Error;
 
+use super::binary::patch_binary_prefixes;
+use super::codesign;
+use super::scan::KegScan;
+use super::text::{PatchConfig, TextReplacements, patch_text_files};
+use super::version::VersionFixer;
+use super::{KegBoundary, PatchFailure, PatchReport, Relocation};
+use crate::progress::{MaterializeObserver, PatchCounter, Phase, check_cancelled};
+
 const HOMEBREW_PREFIXES: &[&str] = &[
     "/opt/homebrew",
     "/usr/local/Homebrew",
@@ -10,180 +18,33 @@ const HOMEBREW_PREFIXES: &[&str] = &[
     "/home/linuxbrew/.linuxbrew",
 ];
 
-/// Patch hardcoded Homebrew paths in text files.
-fn patch_text_file_strings(path: &Path, new_prefix: &str, new_cellar: &str) -> Result<(), Error> {
-    use std::os::unix::fs::PermissionsExt;
-
-    let mut file = match fs::File::open(path) {
-        Ok(f) => f,
-        Err(_) => return Ok(()),
-    };
-
-    let mut buf = [0u8; 8192];
-    let n = match std::io::Read::read(&mut file, &mut buf) {
-        Ok(n) => n,
-        Err(_) => return Ok(()),
-    };
-
-    if buf[..n].contains(&0) {
-        return Ok(());
-    }
-
-    let content = match fs::read_to_string(path) {
-        Ok(c) => c,
-        Err(_) => return Ok(()),
-    };
-
-    if !content.contains("@@HOMEBREW_")
-        && !content.contains("/opt/homebrew")
-        && !content.contains("/usr/local")
-        && !content.contains("/home/linuxbrew")
-    {
-        return Ok(());
-    }
-
-    let mut new_content = content.clone();
-    let mut changed = false;
-
-    new_content = new_content
-        .replace("@@HOMEBREW_PREFIX@@", new_prefix)
-        .replace("@@HOMEBREW_CELLAR@@", new_cellar)
-        .replace("@@HOMEBREW_REPOSITORY@@", new_prefix)
-        .replace("@@HOMEBREW_LIBRARY@@", &format!("{}/Library", new_prefix))
-        .replace("@@HOMEBREW_PERL@@", "/usr/bin/perl")
-        .replace("@@HOMEBREW_JAVA@@", "/usr/bin/java");
-
-    if new_content != content {
-        changed = true;
-    }
-
-    for old_prefix in HOMEBREW_PREFIXES {
-        if old_prefix == &new_prefix {
-            continue;
-        }
-        let replaced = new_content.replace(old_prefix, new_prefix);
-        if replaced != new_content {
-            new_content = replaced;
-            changed = true;
-        }
-    }
-
-    if !changed {
+/// Run `install_name_tool` with `args` on `path`. A non-zero exit is an
+/// error carrying the tool's stderr, since it leaves the file untouched.
+fn install_name_tool(args: &[&str], path: &Path) -> Result<(), String> {
+    let output = std::process::Command::new("install_name_tool")
+        .args(args)
+        .arg(path)
+        .output()
+        .map_err(|e| e.to_string())?;
+    if output.status.success() {
         return Ok(());
     }
-
-    let metadata = fs::metadata(path).map_err(Error::store("failed to read metadata"))?;
-    let original_mode = metadata.permissions().mode();
-    let is_readonly = original_mode & 0o200 == 0;
-
-    if is_readonly {
-        let mut perms = metadata.permissions();
-        perms.set_mode(original_mode | 0o200);
-        fs::set_permissions(path, perms).map_err(Error::store("failed to make writable"))?;
-    }
-
-    fs::write(path, new_content).map_err(Error::store("failed to write file"))?;
-
-    if is_readonly {
-        let mut perms = metadata.permissions();
-        perms.set_mode(original_mode);
-        fs::set_permissions(path, perms).map_err(Error::store("failed to restore permissions"))?;
-    }
-
-    Ok(())
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(match stderr.trim() {
+        "" => output.status.to_string(),
+        stderr => stderr.to_string(),
+    })
 }
 
-/// Patch hardcoded Homebrew paths in Mach-O binary data sections.
+/// Patch hardcoded paths under `old_prefixes` in Mach-O binary data sections.
 /// This handles paths like /opt/homebrew/opt/git/libexec/git-core that are baked into binaries.
-fn patch_macho_binary_strings(path: &Path, new_prefix: &str) -> Result<(), Error> {
-    use std::io::{Read as _, Write as _};
-    use std::os::unix::fs::PermissionsExt;
-
-    let metadata = fs::metadata(path).map_err(Error::store("failed to read metadata"))?;
-    let original_mode = metadata.permissions().mode();
-    let is_readonly = original_mode & 0o200 == 0;
-
-    if is_readonly {
-        let mut perms = metadata.permissions();
-        perms.set_mode(original_mode | 0o200);
-        fs::set_permissions(path, perms).map_err(Error::store("failed to make writable"))?;
-    }
-
-    let mut file = fs::File::open(path).map_err(Error::store("failed to open file"))?;
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents)
-        .map_err(Error::store("failed to read file"))?;
-    drop(file);
-
-    let original_contents = contents.clone();
-    let mut patched = false;
-
-    for old_prefix in HOMEBREW_PREFIXES {
-        if old_prefix == &new_prefix {
-            continue;
-        }
-
-        let old_bytes = old_prefix.as_bytes();
-        let new_bytes = new_prefix.as_bytes();
-
-        if new_bytes.len() > old_bytes.len() {
-            // Cannot expand shorter paths in-place in Mach-O binaries.
-            // Skip this prefix — the install_name_tool pass handles load
-            // command changes regardless of length, and many binaries
-            // legitimately reference shorter prefixes like /usr/local for
-            // system libraries (not Homebrew paths).
-            //
-            // See: https://github.com/lucasgelfond/zerobrew/issues/286
-            let has_old_paths = contents
-                .windows(old_bytes.len() + 1)
-                .any(|w| w[..old_bytes.len()] == *old_bytes && w[old_bytes.len()] == b'/');
-            if has_old_paths {
-                warn!(
-                    path = %path.display(),
-                    old_prefix = %old_prefix,
-                    new_prefix = %new_prefix,
-                    "binary contains hardcoded paths under {old_prefix} that \
-                    could not be rewritten to {new_prefix} (new path is longer). \
-                    this package may not work correctly
-                    tracking issue: https://github.com/lucasgelfond/zerobrew/issues/286
-                    ",
-                );
-            }
-            continue;
-        }
-
-        let mut i = 0;
-        while i + old_bytes.len() <= contents.len() {
-            if contents[i..i + old_bytes.len()] == *old_bytes
-                && matches!(
-                    contents.get(i + old_bytes.len()).copied(),
-                    None | Some(0) | Some(b'/')
-                )
-            {
-                contents[i..i + new_bytes.len()].copy_from_slice(new_bytes);
-                contents[i + new_bytes.len()..i + old_bytes.len()].fill(0);
-                patched = true;
-            }
-            i += 1;
-        }
-    }
-
-    if patched && contents != original_contents {
-        let temp_path = path.with_extension("tmp_patch");
-        let mut temp_file =
-            fs::File::create(&temp_path).map_err(Error::store("failed to create temp file"))?;
-        temp_file
-            .write_all(&contents)
-            .map_err(Error::store("failed to write temp file"))?;
-        drop(temp_file);
-
-        fs::rename(&temp_path, path).map_err(Error::store("failed to rename temp file"))?;
-
-        // Restore original permissions — fs::File::create uses 0644 by default,
-        // which drops the execute bit from patched binaries.
-        fs::set_permissions(path, metadata.permissions())
-            .map_err(Error::store("failed to restore permissions after patching"))?;
-
+/// Returns whether the file was modified.
+fn patch_macho_binary_strings(
+    path: &Path,
+    old_prefixes: &[&str],
+    new_prefix: &str,
+) -> Result<bool, Error> {
+    if patch_binary_prefixes(path, old_prefixes, new_prefix)? {
         match std::process::Command::new("codesign")
             .args(["--force", "--sign", "-", &path.to_string_lossy()])
             .output()
@@ -204,33 +65,90 @@ fn patch_macho_binary_strings(path: &Path, new_prefix: &str) -> Result<(), Error
             }
             _ => {}
         }
+        return Ok(true);
     }
 
-    if is_readonly {
-        let mut perms = metadata.permissions();
-        perms.set_mode(original_mode);
-        let _ = fs::set_permissions(path, perms);
-    }
-
-    Ok(())
+    Ok(false)
 }
 
 /// Patch @@HOMEBREW_CELLAR@@ and @@HOMEBREW_PREFIX@@ placeholders in Mach-O binaries.
-/// Also fixes version mismatches where a bottle references a different version of itself.
+/// Also fixes load commands that reference a different, uninstalled version of
+/// the bottle itself.
 /// Additionally patches hardcoded Homebrew paths in binary data sections and text files.
-/// Uses rayon for parallel processing.
+/// Uses rayon for parallel processing. Mach-O passes are skipped when
+/// `relocation` says the binaries need none. Files that can't be patched
+/// are listed in the report rather than failing the pass. `scan` is the
+/// keg's file list, shared with `codesign_and_strip_xattrs`.
+#[allow(clippy::too_many_arguments)]
 pub fn patch_homebrew_placeholders(
     keg_path: &Path,
+    scan: &KegScan,
     cellar_dir: &Path,
     pkg_name: &str,
     pkg_version: &str,
-) -> Result<(), Error> {
+    config: &PatchConfig,
+    relocation: Relocation,
+    observer: &dyn MaterializeObserver,
+) -> Result<PatchReport, Error> {
+    patch_keg(
+        keg_path,
+        scan,
+        cellar_dir,
+        pkg_name,
+        pkg_version,
+        config,
+        relocation,
+        None,
+        observer,
+    )
+}
+
+/// Re-target a keg poured under `old_prefix` to the prefix owning
+/// `cellar_dir`: binary strings, load commands and text files that point
+/// under `old_prefix` are rewritten like Homebrew paths are at pour time.
+#[allow(clippy::too_many_arguments)]
+pub fn relocate_placeholders(
+    keg_path: &Path,
+    scan: &KegScan,
+    cellar_dir: &Path,
+    old_prefix: &Path,
+    pkg_name: &str,
+    pkg_version: &str,
+    config: &PatchConfig,
+    observer: &dyn MaterializeObserver,
+) -> Result<PatchReport, Error> {
+    patch_keg(
+        keg_path,
+        scan,
+        cellar_dir,
+        pkg_name,
+        pkg_version,
+        config,
+        Relocation::Full,
+        Some(&old_prefix.to_string_lossy()),
+        observer,
+    )
+}
+
+/// Shared by pouring and relocation. `previous_prefix` is the zerobrew
+/// prefix the keg was last patched for; without one, the well-known
+/// Homebrew prefixes are rewritten instead.
+#[allow(clippy::too_many_arguments)]
+fn patch_keg(
+    keg_path: &Path,
+    scan: &KegScan,
+    cellar_dir: &Path,
+    pkg_name: &str,
+    pkg_version: &str,
+    config: &PatchConfig,
+    relocation: Relocation,
+    previous_prefix: Option<&str>,
+    observer: &dyn MaterializeObserver,
+) -> Result<PatchReport, Error> {
     use rayon::prelude::*;
-    use regex::Regex;
     use std::os::unix::fs::PermissionsExt;
     use std::process::Command;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::sync::{Arc, Mutex};
+    use std::sync::Mutex;
 
     // Derive prefix from cellar (cellar_dir is typically prefix/Cellar)
     let prefix = cellar_dir.parent().unwrap_or(Path::new("/opt/homebrew"));
@@ -238,66 +156,55 @@ pub fn patch_homebrew_placeholders(
     let cellar_str = cellar_dir.to_string_lossy().to_string();
     let prefix_str = prefix.to_string_lossy().to_string();
 
-    let version_pattern = format!(r"(/Cellar/{}/)([^/]+)(/)", regex::escape(pkg_name));
-    let version_regex = Regex::new(&version_pattern).ok();
-
-    // Collect all Mach-O files first (skip symlinks to avoid double-processing)
-    let macho_files: Vec<PathBuf> = walkdir::WalkDir::new(keg_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| {
-            // Skip symlinks - only process actual files
-            e.file_type().is_file()
-        })
-        .filter(|e| {
-            if let Ok(data) = fs::read(e.path())
-                && data.len() >= 4
-            {
-                let magic = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-                return matches!(
-                    magic,
-                    0xfeedface | 0xfeedfacf | 0xcafebabe | 0xcefaedfe | 0xcffaedfe
-                );
-            }
-            false
-        })
-        .map(|e| e.path().to_path_buf())
-        .collect();
+    let version_fixer = VersionFixer::new(cellar_dir, pkg_name, pkg_version);
+    let old_prefixes: Vec<&str> = match previous_prefix {
+        Some(previous) => vec![previous],
+        None => HOMEBREW_PREFIXES.to_vec(),
+    };
 
-    let patch_failures = AtomicUsize::new(0);
-    let first_patch_error: Arc<Mutex<Option<Error>>> = Arc::new(Mutex::new(None));
+    // The scan skips symlinks, so nothing is patched twice.
+    let macho_files: &[PathBuf] = if relocation.patches_binaries() {
+        &scan.macho
+    } else {
+        &[]
+    };
+    // Binaries are counted once, when their load commands are done.
+    let counter = PatchCounter::new(macho_files.len() + scan.files.len());
+    observer.on_patch_started(&counter);
+    if relocation.patches_binaries() {
+        observer.on_phase(Phase::PatchBinaries);
+    }
+
+    let patch_failures: Mutex<Vec<PatchFailure>> = Mutex::new(Vec::new());
+    let record_failure = |path: &Path, error: String| {
+        if let Ok(mut failures) = patch_failures.lock() {
+            failures.push(PatchFailure::new(path, error));
+        }
+    };
+    let boundary = KegBoundary::new(keg_path);
+    let modified: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    let record_modified = |path: &Path| {
+        if let Ok(mut modified) = modified.lock() {
+            modified.push(path.to_path_buf());
+        }
+    };
 
     // First pass: patch binary strings in Mach-O files
     macho_files.par_iter().for_each(|path| {
-        if let Err(e) = patch_macho_binary_strings(path, &prefix_str) {
-            patch_failures.fetch_add(1, Ordering::Relaxed);
-            if let Ok(mut guard) = first_patch_error.lock()
-                && guard.is_none()
-            {
-                *guard = Some(e);
-            }
+        if observer.should_cancel() {
+            return;
+        }
+        if let Err(e) = boundary.check(path) {
+            record_failure(path, e.to_string());
+            return;
+        }
+        match patch_macho_binary_strings(path, &old_prefixes, &prefix_str) {
+            Ok(true) => record_modified(path),
+            Ok(false) => {}
+            Err(e) => record_failure(path, e.to_string()),
         }
     });
-
-    if let Ok(mut guard) = first_patch_error.lock()
-        && let Some(e) = guard.take()
-    {
-        return Err(e);
-    }
-
-    // Second pass: patch text files
-    let text_files: Vec<PathBuf> = walkdir::WalkDir::new(keg_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .map(|e| e.path().to_path_buf())
-        .collect();
-
-    text_files.par_iter().for_each(|path| {
-        let _ = patch_text_file_strings(path, &prefix_str, &cellar_str);
-    });
+    check_cancelled(observer)?;
 
     // Helper to patch a single path reference
     let patch_path = |old_path: &str| -> Option<String> {
@@ -312,23 +219,19 @@ pub fn patch_homebrew_placeholders(
             changed = true;
         }
 
-        // Fix version mismatches for this package
-        if let Some(re) = &version_regex
-            && re.is_match(&new_path)
+        // Move paths under the prefix the keg was relocated from
+        if let Some(previous) = previous_prefix
+            && let Some(rest) = new_path.strip_prefix(previous)
+            && (rest.is_empty() || rest.starts_with('/'))
         {
-            let replacement = format!("/Cellar/{}/{}/", pkg_name, pkg_version);
-            let fixed = re.replace(&new_path, |caps: &regex::Captures| {
-                let matched_version = &caps[2];
-                if matched_version != pkg_version {
-                    replacement.clone()
-                } else {
-                    caps[0].to_string()
-                }
-            });
-            if fixed != new_path {
-                new_path = fixed.to_string();
-                changed = true;
-            }
+            new_path = format!("{prefix_str}{rest}");
+            changed = true;
+        }
+
+        // Fix version mismatches for this package
+        if let Some(fixed) = version_fixer.fix(&new_path) {
+            new_path = fixed;
+            changed = true;
         }
 
         if changed && new_path != old_path {
@@ -338,8 +241,12 @@ pub fn patch_homebrew_placeholders(
         }
     };
 
-    // Third pass: Process Mach-O files for install_name_tool patching
-    macho_files.par_iter().for_each(|path| {
+    // Second pass: Process Mach-O files for install_name_tool patching
+    let patch_load_commands = |path: &PathBuf| {
+        // The first pass already reported files outside the keg.
+        if boundary.check(path).is_err() {
+            return;
+        }
         // Get file permissions and make writable if needed
         let metadata = match fs::metadata(path) {
             Ok(m) => m,
@@ -352,8 +259,8 @@ pub fn patch_homebrew_placeholders(
         if is_readonly {
             let mut perms = metadata.permissions();
             perms.set_mode(original_mode | 0o200);
-            if fs::set_permissions(path, perms).is_err() {
-                patch_failures.fetch_add(1, Ordering::Relaxed);
+            if let Err(e) = fs::set_permissions(path, perms) {
+                record_failure(path, format!("not writable: {e}"));
                 return;
             }
         }
@@ -372,13 +279,9 @@ pub fn patch_homebrew_placeholders(
                 if let Some(old_path) = line.split_whitespace().next()
                     && let Some(new_path) = patch_path(old_path)
                 {
-                    let result = Command::new("install_name_tool")
-                        .args(["-change", old_path, &new_path, &path.to_string_lossy()])
-                        .output();
-                    if result.is_ok() {
-                        patched_any = true;
-                    } else {
-                        patch_failures.fetch_add(1, Ordering::Relaxed);
+                    match install_name_tool(&["-change", old_path, &new_path], path) {
+                        Ok(()) => patched_any = true,
+                        Err(e) => record_failure(path, format!("install_name_tool -change: {e}")),
                     }
                 }
             }
@@ -398,13 +301,9 @@ pub fn patch_homebrew_placeholders(
                     continue;
                 }
                 if let Some(new_id) = patch_path(line) {
-                    let result = Command::new("install_name_tool")
-                        .args(["-id", &new_id, &path.to_string_lossy()])
-                        .output();
-                    if result.is_ok() {
-                        patched_any = true;
-                    } else {
-                        patch_failures.fetch_add(1, Ordering::Relaxed);
+                    match install_name_tool(&["-id", &new_id], path) {
+                        Ok(()) => patched_any = true,
+                        Err(e) => record_failure(path, format!("install_name_tool -id: {e}")),
                     }
                 }
             }
@@ -415,6 +314,7 @@ pub fn patch_homebrew_placeholders(
             let _ = Command::new("codesign")
                 .args(["--force", "--sign", "-", &path.to_string_lossy()])
                 .output();
+            record_modified(path);
         }
 
         // Restore original permissions
@@ -423,28 +323,70 @@ pub fn patch_homebrew_placeholders(
             perms.set_mode(original_mode);
             let _ = fs::set_permissions(path, perms);
         }
+    };
+    macho_files.par_iter().for_each(|path| {
+        if observer.should_cancel() {
+            return;
+        }
+        patch_load_commands(path);
+        counter.advance(observer);
     });
 
-    let failures = patch_failures.load(Ordering::Relaxed);
-    if failures > 0 {
-        return Err(Error::StoreCorruption {
-            message: format!(
-                "failed to patch {} Mach-O files in {}",
-                failures,
-                keg_path.display()
-            ),
-        });
-    }
+    check_cancelled(observer)?;
+
+    // Re-signing above only warns when it fails, which leaves a binary
+    // macOS kills on launch. Check every modified binary in-process and
+    // give the broken ones one more try.
+    let mut modified = modified.into_inner().unwrap_or_default();
+    modified.sort();
+    modified.dedup();
+    let signature_failures = codesign::verify_signatures(&modified, Path::new("codesign"));
+
+    // Third pass: patch text files
+    observer.on_phase(Phase::PatchText);
+    let text_filter = config.filter_for(pkg_name)?;
+    let replacements =
+        TextReplacements::new(prefix, cellar_dir).with_legacy_prefixes(&old_prefixes);
+    let text_report = patch_text_files(
+        keg_path,
+        &scan.files,
+        &text_filter,
+        &replacements,
+        observer,
+        &counter,
+    );
+    check_cancelled(observer)?;
+    text_report.warn_oversized(pkg_name);
+
+    let mut binary_failures = patch_failures.into_inner().unwrap_or_default();
+    binary_failures.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(PatchReport {
+        relocation,
+        text: text_report,
+        binary_failures,
+        signature_failures,
+        missing_libraries: Vec::new(),
+    })
+}
 
-    Ok(())
+/// Mach-O files in `keg_path` whose code signature no longer matches their
+/// contents or, for executables on Apple silicon, is missing.
+pub(crate) fn invalid_signatures(keg_path: &Path) -> Vec<PathBuf> {
+    let scan = KegScan::new(keg_path);
+    codesign::unrunnable(&scan.macho, cfg!(target_arch = "aarch64"))
+}
+
+/// Ad-hoc sign `files` again. Returns those still without a valid
+/// signature.
+pub(crate) fn resign(files: &[PathBuf]) -> Vec<PatchFailure> {
+    codesign::verify_signatures(files, Path::new("codesign"))
 }
 
 /// Strip quarantine extended attributes and ad-hoc sign unsigned Mach-O binaries.
 /// Homebrew bottles from ghcr.io are already adhoc signed, so this is mostly a no-op.
-/// We use a fast heuristic: only process binaries that fail signature verification.
-pub fn codesign_and_strip_xattrs(keg_path: &Path) -> Result<(), Error> {
-    use rayon::prelude::*;
-    use std::os::unix::fs::PermissionsExt;
+/// Only binaries without an embedded signature are passed to `codesign`.
+pub fn codesign_and_strip_xattrs(keg_path: &Path, scan: &KegScan) -> Result<(), Error> {
     use std::process::Command;
 
     // First, do a quick recursive xattr strip (single command, very fast)
@@ -457,72 +399,19 @@ pub fn codesign_and_strip_xattrs(keg_path: &Path) -> Result<(), Error> {
         .stderr(std::process::Stdio::null())
         .output();
 
-    // Find executables in bin/ directories only (where signing matters)
-    // Skip dylibs and other Mach-O files - they inherit signing from their loader
-    let bin_files: Vec<PathBuf> = walkdir::WalkDir::new(keg_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| {
-            let path = e.path();
-            path.is_file() && path.to_string_lossy().contains("/bin/")
-        })
-        .map(|e| e.path().to_path_buf())
+    // Only executables in bin/ directories are signed (where signing matters);
+    // dylibs and other Mach-O files inherit signing from their loader.
+    // Signature presence is checked in-process; only unsigned binaries are
+    // handed to codesign, batched into as few invocations as possible.
+    // Symlinks in bin/ that lead out of the keg are not ours to sign.
+    let boundary = KegBoundary::new(keg_path);
+    let bin: Vec<PathBuf> = scan
+        .bin
+        .iter()
+        .filter(|path| boundary.check(path).is_ok())
+        .cloned()
         .collect();
-
-    // Only process files that need signing
-    bin_files.par_iter().for_each(|path| {
-        // Quick check: is it a Mach-O?
-        let data = match fs::read(path) {
-            Ok(d) if d.len() >= 4 => d,
-            _ => return,
-        };
-        let magic = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-        let is_macho = matches!(
-            magic,
-            0xfeedface | 0xfeedfacf | 0xcafebabe | 0xcefaedfe | 0xcffaedfe
-        );
-        if !is_macho {
-            return;
-        }
-
-        // Verify signature - if valid, skip
-        let verify = Command::new("codesign")
-            .args(["-v", &path.to_string_lossy()])
-            .stderr(std::process::Stdio::null())
-            .stdout(std::process::Stdio::null())
-            .status();
-
-        if verify.map(|s| s.success()).unwrap_or(false) {
-            return; // Already signed
-        }
-
-        // Get permissions and make writable
-        let metadata = match fs::metadata(path) {
-            Ok(m) => m,
-            Err(_) => return,
-        };
-        let original_mode = metadata.permissions().mode();
-        let is_readonly = original_mode & 0o200 == 0;
-
-        if is_readonly {
-            let mut perms = metadata.permissions();
-            perms.set_mode(original_mode | 0o200);
-            let _ = fs::set_permissions(path, perms);
-        }
-
-        // Sign the binary
-        let _ = Command::new("codesign")
-            .args(["--force", "--sign", "-", &path.to_string_lossy()])
-            .output();
-
-        // Restore permissions
-        if is_readonly {
-            let mut perms = metadata.permissions();
-            perms.set_mode(original_mode);
-            let _ = fs::set_permissions(path, perms);
-        }
-    });
+    codesign::sign_unsigned(&bin, Path::new("codesign"));
 
     Ok(())
 }
@@ -554,7 +443,7 @@ mod tests {
         perms.set_mode(0o755);
         fs::set_permissions(&test_file, perms).unwrap();
 
-        patch_macho_binary_strings(&test_file, new_prefix).unwrap();
+        patch_macho_binary_strings(&test_file, HOMEBREW_PREFIXES, new_prefix).unwrap();
 
         let mode = fs::metadata(&test_file).unwrap().permissions().mode();
         assert!(
@@ -584,7 +473,7 @@ mod tests {
 
         fs::write(&test_file, &contents).unwrap();
 
-        let result = patch_macho_binary_strings(&test_file, new_prefix);
+        let result = patch_macho_binary_strings(&test_file, HOMEBREW_PREFIXES, new_prefix);
         assert!(result.is_ok());
 
         let patched = fs::read(&test_file).unwrap();
@@ -615,7 +504,7 @@ mod tests {
         // Should succeed (skip) rather than error when the new prefix is
         // longer than the old one — install_name_tool handles load command
         // changes regardless of length.
-        let result = patch_macho_binary_strings(&test_file, new_prefix);
+        let result = patch_macho_binary_strings(&test_file, HOMEBREW_PREFIXES, new_prefix);
         assert!(
             result.is_ok(),
             "should skip when new prefix is longer than old prefix"
@@ -630,74 +519,19 @@ mod tests {
 
     #[test]
     fn test_version_regex_only_matches_cellar_paths() {
-        use regex::Regex;
-
-        let pkg_name = "mpdecimal";
-        let pkg_version = "4.0.1";
-        let pattern = format!(r"(/Cellar/{}/)([^/]+)(/)", regex::escape(pkg_name));
-        let re = Regex::new(&pattern).expect("version regex should compile");
+        let tmp = TempDir::new().unwrap();
+        let fixer = VersionFixer::new(&tmp.path().join("Cellar"), "mpdecimal", "4.0.1");
 
         let cellar_path = "/opt/zerobrew/Cellar/mpdecimal/3.9.0/lib/libmpdec.4.dylib";
-        assert!(re.is_match(cellar_path));
-
-        let replacement = format!("/Cellar/{}/{}/", pkg_name, pkg_version);
-        let fixed = re.replace(cellar_path, |caps: &regex::Captures| {
-            let matched_version = &caps[2];
-            if matched_version != pkg_version {
-                replacement.clone()
-            } else {
-                caps[0].to_string()
-            }
-        });
         assert_eq!(
-            fixed,
-            "/opt/zerobrew/Cellar/mpdecimal/4.0.1/lib/libmpdec.4.dylib"
+            fixer.fix(cellar_path).as_deref(),
+            Some("/opt/zerobrew/Cellar/mpdecimal/4.0.1/lib/libmpdec.4.dylib")
         );
 
         let opt_path = "/opt/zerobrew/opt/mpdecimal/lib/libmpdec.4.dylib";
-        assert!(!re.is_match(opt_path));
+        assert_eq!(fixer.fix(opt_path), None);
 
         let cellar_same_version = "/opt/zerobrew/Cellar/mpdecimal/4.0.1/lib/libmpdec.4.dylib";
-        let unchanged = re.replace(cellar_same_version, |caps: &regex::Captures| {
-            let matched_version = &caps[2];
-            if matched_version != pkg_version {
-                replacement.clone()
-            } else {
-                caps[0].to_string()
-            }
-        });
-        assert_eq!(unchanged, cellar_same_version);
-    }
-
-    #[test]
-    fn test_patch_text_file_strings() {
-        let tmp = TempDir::new().unwrap();
-        let test_file = tmp.path().join("test_script.sh");
-
-        let content = r#"#!/bin/bash
-export GIT_EXEC_PATH=/opt/homebrew/opt/git/libexec/git-core
-export PREFIX=@@HOMEBREW_PREFIX@@
-export CELLAR=@@HOMEBREW_CELLAR@@
-export LIBRARY=@@HOMEBREW_LIBRARY@@
-export PERL=@@HOMEBREW_PERL@@
-echo "Hello from $PREFIX"
-"#;
-
-        fs::write(&test_file, content).unwrap();
-
-        let new_prefix = "/opt/zerobrew/prefix";
-        let new_cellar = format!("{}/Cellar", new_prefix);
-
-        let result = patch_text_file_strings(&test_file, new_prefix, &new_cellar);
-        assert!(result.is_ok());
-
-        let patched = fs::read_to_string(&test_file).unwrap();
-        assert!(patched.contains(new_prefix));
-        assert!(!patched.contains("/opt/homebrew"));
-        assert!(!patched.contains("@@HOMEBREW_"));
-        assert!(patched.contains("/opt/zerobrew/prefix/opt/git/libexec/git-core"));
-        assert!(patched.contains("/opt/zerobrew/prefix/Cellar"));
-        assert!(patched.contains("/opt/zerobrew/prefix/Library"));
-        assert!(patched.contains("/usr/bin/perl"));
+        assert_eq!(fixer.fix(cellar_same_version), None);
     }
 }
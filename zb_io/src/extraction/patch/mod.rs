@@ -1,11 +1,442 @@
-#[cfg(target_os = "linux")]
+mod binary;
+
+#[cfg(any(all(target_os = "macos", feature = "patch-macho"), test))]
+mod codesign;
+
+#[cfg(all(target_os = "linux", feature = "patch-elf"))]
 pub mod linux;
 
-#[cfg(target_os = "macos")]
+#[cfg(all(target_os = "macos", feature = "patch-macho"))]
 pub mod macos;
 
-#[cfg(target_os = "linux")]
-pub use linux::patch_placeholders;
+#[cfg(all(target_os = "linux", feature = "patch-elf"))]
+pub mod needed;
+
+pub mod scan;
+pub mod text;
+
+pub(crate) mod version;
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Deserialize;
+use tracing::{debug, warn};
+use zb_core::{BottleCellar, Error};
+
+pub use binary::baked_prefix_files;
+
+#[cfg(all(target_os = "linux", feature = "patch-elf"))]
+pub use linux::{patch_placeholders, relocate_placeholders};
+
+#[cfg(all(target_os = "macos", feature = "patch-macho"))]
+pub use macos::{codesign_and_strip_xattrs, patch_homebrew_placeholders, relocate_placeholders};
+
+#[cfg(all(target_os = "macos", feature = "patch-macho"))]
+pub(crate) use macos::{invalid_signatures, resign};
+
+#[cfg(all(target_os = "linux", feature = "patch-elf"))]
+pub use needed::LibraryResolver;
+
+pub use text::{DEFAULT_MAX_TEXT_PATCH_SIZE, PatchConfig, PatchRules, TextPatchReport};
+
+/// Prefix of the hidden name a patched binary is written under, beside the
+/// original, before being renamed over it. Dot-prefixed and unique per
+/// write, so it can't collide with a file the bottle ships.
+pub(crate) const PATCH_TEMP_PREFIX: &str = ".zb-patch.";
+
+static NEXT_PATCH_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// Creates `.zb-patch.<pid>.<n>` in the directory of `path`, moving on to
+/// the next `n` while the name is taken, so a leftover from an earlier run
+/// with the same pid is never written through.
+pub(crate) fn create_patch_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    loop {
+        let n = NEXT_PATCH_TEMP.fetch_add(1, Ordering::Relaxed);
+        let temp_path =
+            path.with_file_name(format!("{PATCH_TEMP_PREFIX}{}.{n}", std::process::id()));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Files under `keg_path` still holding an `@@HOMEBREW_*@@` placeholder,
+/// sorted. Only a patch backend rewrites them, so without one such a keg
+/// can't work where it was poured.
+#[cfg(any(
+    not(any(
+        all(target_os = "linux", feature = "patch-elf"),
+        all(target_os = "macos", feature = "patch-macho")
+    )),
+    test
+))]
+pub(crate) fn placeholder_files(keg_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    const MARKER: &[u8] = b"@@HOMEBREW_";
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(keg_path).follow_links(false) {
+        let entry = entry.map_err(|e| Error::StoreCorruption {
+            message: format!("failed to walk {}: {e}", keg_path.display()),
+        })?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let ctx = format!("failed to read {}", entry.path().display());
+        let contents = std::fs::read(entry.path()).map_err(Error::store(ctx.as_str()))?;
+        if contents.windows(MARKER.len()).any(|w| w == MARKER) {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether `path` is a patch temp file, which a crash mid-patch can leave in
+/// a keg. Such files are never patch candidates themselves.
+pub(crate) fn is_patch_temp(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(PATCH_TEMP_PREFIX))
+}
+
+/// The keg a patch pass may write to. Patchers only ever modify files that
+/// resolve to somewhere strictly inside the keg being materialized, so a
+/// symlink into shared locations, such as a user's config in `etc/`, is
+/// never rewritten through.
+pub(crate) struct KegBoundary {
+    root: PathBuf,
+}
+
+impl KegBoundary {
+    pub(crate) fn new(keg_path: &Path) -> Self {
+        Self {
+            root: keg_path
+                .canonicalize()
+                .unwrap_or_else(|_| keg_path.to_path_buf()),
+        }
+    }
+
+    /// Fails unless `path`, with every symlink resolved, is inside the keg.
+    pub(crate) fn check(&self, path: &Path) -> io::Result<()> {
+        let resolved = path.canonicalize()?;
+        if resolved != self.root && resolved.starts_with(&self.root) {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "resolves to {}, outside the keg",
+                resolved.display()
+            )))
+        }
+    }
+}
+
+/// Whether a bottle's binaries need their embedded paths rewritten, derived
+/// from the bottle's `cellar` declaration and the Cellar we pour into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Relocation {
+    /// `cellar: :any` (or undeclared): run every patch pass.
+    Full,
+    /// `cellar: :any_skip_relocation`: only the text placeholder pass runs.
+    SkipBinaries,
+    /// Built for our exact Cellar path, so binaries are already correct.
+    CellarMatches,
+    /// Built for a different fixed Cellar; patched in full, which may not
+    /// catch every hardcoded path.
+    CellarMismatch { built_for: String },
+}
+
+impl Relocation {
+    pub fn for_bottle(declared: &BottleCellar, cellar_dir: &Path) -> Self {
+        match declared {
+            BottleCellar::Any => Self::Full,
+            BottleCellar::AnySkipRelocation => Self::SkipBinaries,
+            BottleCellar::Path(path) if Path::new(path) == cellar_dir => Self::CellarMatches,
+            BottleCellar::Path(path) => Self::CellarMismatch {
+                built_for: path.clone(),
+            },
+        }
+    }
+
+    pub fn patches_binaries(&self) -> bool {
+        matches!(self, Self::Full | Self::CellarMismatch { .. })
+    }
+}
+
+/// What to do when files in a keg can't be patched. Every platform and
+/// pass follows the same policy; set it in `patch.json` as `"policy"` or
+/// per install with `--strict-patching`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatchPolicy {
+    /// Fail the install and remove the keg.
+    Strict,
+    /// Keep the keg and warn about every file that wasn't patched.
+    #[default]
+    Warn,
+    /// Keep the keg; failures are only logged at debug level.
+    Silent,
+}
+
+/// A file a patch pass could not rewrite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl PatchFailure {
+    pub(crate) fn new(path: &Path, error: impl std::fmt::Display) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// An ELF file whose needed shared libraries the dynamic loader wouldn't
+/// find, by soname.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingLibraries {
+    pub path: PathBuf,
+    pub sonames: Vec<String>,
+}
+
+/// What the patch passes did to one keg.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchReport {
+    pub relocation: Relocation,
+    pub text: TextPatchReport,
+    /// Binaries whose load commands, RUNPATHs or embedded paths couldn't
+    /// be rewritten, sorted by path.
+    pub binary_failures: Vec<PatchFailure>,
+    /// Patched binaries still without a valid code signature after being
+    /// re-signed a second time, sorted by path. macOS kills them on launch.
+    pub signature_failures: Vec<PatchFailure>,
+    /// ELF files needing libraries found neither in the keg, the prefix nor
+    /// the system, sorted by path. Always empty on macOS.
+    pub missing_libraries: Vec<MissingLibraries>,
+}
+
+impl PatchReport {
+    /// Every file that wasn't patched: binaries, then binaries left
+    /// unsigned, then text files.
+    pub fn failures(&self) -> impl Iterator<Item = &PatchFailure> {
+        self.binary_failures
+            .iter()
+            .chain(&self.signature_failures)
+            .chain(&self.text.failures)
+    }
+
+    /// Apply `policy` to the failures of `formula`'s keg: an error under
+    /// [`PatchPolicy::Strict`] if there are any, a warning per file under
+    /// [`PatchPolicy::Warn`].
+    /// Missing libraries don't fail the keg under any policy; they are
+    /// warned about unless it is [`PatchPolicy::Silent`].
+    pub fn enforce(&self, formula: &str, policy: PatchPolicy) -> Result<(), Error> {
+        for missing in &self.missing_libraries {
+            if policy == PatchPolicy::Silent {
+                debug!(formula, path = %missing.path.display(), sonames = ?missing.sonames, "missing libraries");
+            } else {
+                warn!(
+                    formula,
+                    path = %missing.path.display(),
+                    "needs {}, which this system doesn't have; it will fail to start",
+                    missing.sonames.join(", ")
+                );
+            }
+        }
+
+        let failures: Vec<&PatchFailure> = self.failures().collect();
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        match policy {
+            PatchPolicy::Strict => Err(Error::PatchFailed {
+                name: formula.to_string(),
+                files: failures.iter().map(|f| f.path.clone()).collect(),
+            }),
+            PatchPolicy::Warn => {
+                for failure in &failures {
+                    warn!(
+                        formula,
+                        path = %failure.path.display(),
+                        error = %failure.error,
+                        "failed to patch file"
+                    );
+                }
+                warn!(
+                    formula,
+                    failures = failures.len(),
+                    "some files were not patched; the package may not work correctly"
+                );
+                Ok(())
+            }
+            PatchPolicy::Silent => {
+                debug!(formula, failures = failures.len(), "files were not patched");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_temps_skip_names_already_taken() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let binary = tmp.path().join("tool");
+        let next = NEXT_PATCH_TEMP.load(Ordering::Relaxed);
+        let taken: Vec<PathBuf> = (next..next + 8)
+            .map(|n| {
+                let path = tmp
+                    .path()
+                    .join(format!("{PATCH_TEMP_PREFIX}{}.{n}", std::process::id()));
+                fs::write(&path, "left over").unwrap();
+                path
+            })
+            .collect();
+
+        let (temp_path, _file) = create_patch_temp(&binary).unwrap();
+
+        assert!(!taken.contains(&temp_path));
+        assert!(is_patch_temp(&temp_path));
+        for path in &taken {
+            assert_eq!(fs::read_to_string(path).unwrap(), "left over");
+        }
+    }
+
+    #[test]
+    fn placeholder_files_lists_only_files_with_placeholders() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let keg = tmp.path();
+        std::fs::create_dir_all(keg.join("bin")).unwrap();
+        std::fs::create_dir_all(keg.join("lib")).unwrap();
+        std::fs::write(keg.join("bin/tool"), "exec @@HOMEBREW_CELLAR@@/x\n").unwrap();
+        std::fs::write(keg.join("lib/libx.so"), b"\0\x7f@@HOMEBREW_PREFIX@@/lib\0").unwrap();
+        std::fs::write(keg.join("bin/plain"), "echo @@NOT_HOMEBREW@@\n").unwrap();
+
+        assert_eq!(
+            placeholder_files(keg).unwrap(),
+            [keg.join("bin/tool"), keg.join("lib/libx.so")]
+        );
+    }
+
+    #[test]
+    fn keg_boundary_rejects_paths_that_resolve_outside_the_keg() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let keg = tmp.path().join("Cellar/foo/1.0");
+        let outside = tmp.path().join("etc");
+        std::fs::create_dir_all(keg.join("etc")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("foo.conf"), "edited").unwrap();
+        std::fs::write(keg.join("etc/own.conf"), "default").unwrap();
+        std::os::unix::fs::symlink(outside.join("foo.conf"), keg.join("etc/foo.conf")).unwrap();
+        std::os::unix::fs::symlink(&outside, keg.join("shared")).unwrap();
+
+        let boundary = KegBoundary::new(&keg);
+        assert!(boundary.check(&keg.join("etc/own.conf")).is_ok());
+        assert!(boundary.check(&keg.join("etc/foo.conf")).is_err());
+        assert!(boundary.check(&keg.join("shared/foo.conf")).is_err());
+        assert!(boundary.check(&keg).is_err());
+    }
+
+    #[test]
+    fn relocation_follows_bottle_cellar() {
+        let cellar = Path::new("/opt/zerobrew/prefix/Cellar");
+
+        assert_eq!(
+            Relocation::for_bottle(&BottleCellar::Any, cellar),
+            Relocation::Full
+        );
+        assert_eq!(
+            Relocation::for_bottle(&BottleCellar::AnySkipRelocation, cellar),
+            Relocation::SkipBinaries
+        );
+        assert_eq!(
+            Relocation::for_bottle(
+                &BottleCellar::Path("/opt/zerobrew/prefix/Cellar".to_string()),
+                cellar
+            ),
+            Relocation::CellarMatches
+        );
+        assert_eq!(
+            Relocation::for_bottle(
+                &BottleCellar::Path("/home/linuxbrew/.linuxbrew/Cellar".to_string()),
+                cellar
+            ),
+            Relocation::CellarMismatch {
+                built_for: "/home/linuxbrew/.linuxbrew/Cellar".to_string()
+            }
+        );
+
+        assert!(Relocation::Full.patches_binaries());
+        assert!(!Relocation::SkipBinaries.patches_binaries());
+        assert!(!Relocation::CellarMatches.patches_binaries());
+    }
+
+    fn report_with_failures() -> PatchReport {
+        PatchReport {
+            relocation: Relocation::Full,
+            text: TextPatchReport {
+                failures: vec![PatchFailure::new(Path::new("/k/bin/tool-config"), "denied")],
+                ..TextPatchReport::default()
+            },
+            binary_failures: vec![PatchFailure::new(Path::new("/k/lib/libx.so"), "bad ELF")],
+            signature_failures: vec![PatchFailure::new(
+                Path::new("/k/bin/tool"),
+                "code signature does not match the file after re-signing",
+            )],
+            missing_libraries: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn only_strict_policy_turns_failures_into_errors() {
+        let report = report_with_failures();
+
+        let err = report.enforce("x", PatchPolicy::Strict).unwrap_err();
+        assert_eq!(
+            err,
+            Error::PatchFailed {
+                name: "x".to_string(),
+                files: vec![
+                    PathBuf::from("/k/lib/libx.so"),
+                    PathBuf::from("/k/bin/tool"),
+                    PathBuf::from("/k/bin/tool-config")
+                ],
+            }
+        );
+        assert!(report.enforce("x", PatchPolicy::Warn).is_ok());
+        assert!(report.enforce("x", PatchPolicy::Silent).is_ok());
+
+        let clean = PatchReport {
+            binary_failures: Vec::new(),
+            signature_failures: Vec::new(),
+            text: TextPatchReport::default(),
+            missing_libraries: vec![MissingLibraries {
+                path: PathBuf::from("bin/x"),
+                sonames: vec!["libgone.so.1".to_string()],
+            }],
+            ..report
+        };
+        assert!(clean.enforce("x", PatchPolicy::Strict).is_ok());
+    }
 
-#[cfg(target_os = "macos")]
-pub use macos::{codesign_and_strip_xattrs, patch_homebrew_placeholders};
+    #[test]
+    fn policy_reads_from_lowercase_names() {
+        let policy: PatchPolicy = serde_json::from_str("\"strict\"").unwrap();
+        assert_eq!(policy, PatchPolicy::Strict);
+        assert_eq!(PatchPolicy::default(), PatchPolicy::Warn);
+    }
+}
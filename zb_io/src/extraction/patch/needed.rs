@@ -0,0 +1,393 @@
+//! Static check that the shared libraries an ELF file needs can be found,
+//! so a keg missing a host library is reported at install time instead of
+//! failing when it first runs. Nothing is executed.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use object::elf::{DT_NEEDED, DT_RPATH, DT_RUNPATH, FileHeader32, FileHeader64};
+use object::read::elf::{Dyn, FileHeader};
+use object::{Endianness, FileKind, ReadCache};
+use rayon::prelude::*;
+use zb_core::CELLAR_DIR;
+
+use super::MissingLibraries;
+
+/// Where the loader looks when neither RUNPATH nor ld.so.conf has it.
+const DEFAULT_LIBRARY_DIRS: &[&str] = &["/lib", "/usr/lib", "/lib64", "/usr/lib64"];
+
+const LD_SO_CONF: &str = "/etc/ld.so.conf";
+
+/// Resolves DT_NEEDED entries the way the dynamic loader would, minus
+/// `LD_LIBRARY_PATH`: the file's RPATH or RUNPATH with `$ORIGIN` expanded,
+/// the prefix's `lib`, the directories in ld.so.conf and the defaults.
+/// Libraries in kegs that are poured but not linked yet count as found, so
+/// a dependency installed in the same run isn't reported. Build one per run:
+/// the Cellar is indexed once and only kegs poured since are read later.
+pub struct LibraryResolver {
+    search_dirs: Vec<PathBuf>,
+    cellar: Mutex<CellarIndex>,
+}
+
+impl LibraryResolver {
+    pub fn new(prefix_dir: &Path) -> Self {
+        let mut search_dirs = vec![prefix_dir.join("lib")];
+        search_dirs.extend(ld_so_conf_dirs(Path::new(LD_SO_CONF), 0));
+        let arch = std::env::consts::ARCH;
+        for dir in DEFAULT_LIBRARY_DIRS {
+            search_dirs.push(PathBuf::from(dir));
+            search_dirs.push(PathBuf::from(format!("{dir}/{arch}-linux-gnu")));
+        }
+        search_dirs.dedup();
+
+        let mut cellar = CellarIndex::new(prefix_dir.join(CELLAR_DIR));
+        cellar.refresh();
+        Self {
+            search_dirs,
+            cellar: Mutex::new(cellar),
+        }
+    }
+
+    /// Each file in `elf_files` with a needed library that can't be found,
+    /// sorted by path. Files that aren't dynamic ELF are skipped.
+    pub fn missing(&self, elf_files: &[PathBuf]) -> Vec<MissingLibraries> {
+        let mut missing: Vec<MissingLibraries> = elf_files
+            .par_iter()
+            .filter_map(|path| {
+                let sonames = self.unresolved(path);
+                (!sonames.is_empty()).then(|| MissingLibraries {
+                    path: path.clone(),
+                    sonames,
+                })
+            })
+            .collect();
+        missing.sort_by(|a, b| a.path.cmp(&b.path));
+        missing
+    }
+
+    fn unresolved(&self, path: &Path) -> Vec<String> {
+        let Some(dynamic) = DynamicEntries::read(path) else {
+            return Vec::new();
+        };
+        let origin = path.parent().unwrap_or(Path::new("/"));
+        let runpath: Vec<PathBuf> = dynamic
+            .runpath
+            .iter()
+            .flat_map(|entries| entries.split(':'))
+            .filter_map(|entry| expand_origin(entry, origin))
+            .collect();
+
+        dynamic
+            .needed
+            .into_iter()
+            .filter(|soname| !self.resolves(soname, &runpath, origin))
+            .collect()
+    }
+
+    fn resolves(&self, soname: &str, runpath: &[PathBuf], origin: &Path) -> bool {
+        if soname.contains('/') {
+            return expand_origin(soname, origin).is_some_and(|path| path.exists());
+        }
+        runpath
+            .iter()
+            .chain(&self.search_dirs)
+            .any(|dir| dir.join(soname).exists())
+            || self.in_cellar(soname)
+    }
+
+    /// Whether a keg in the Cellar has `soname` in its `lib`, reading kegs
+    /// poured since the last look before giving up.
+    fn in_cellar(&self, soname: &str) -> bool {
+        let mut cellar = self.cellar.lock().unwrap_or_else(PoisonError::into_inner);
+        cellar.libraries.contains(soname) || (cellar.refresh() && cellar.libraries.contains(soname))
+    }
+}
+
+/// What the loader reads from an ELF file's dynamic section.
+#[derive(Debug, Default)]
+struct DynamicEntries {
+    needed: Vec<String>,
+    /// RPATH and RUNPATH values, each a `:`-separated list.
+    runpath: Vec<String>,
+}
+
+impl DynamicEntries {
+    /// The dynamic section of the ELF file at `path`, or `None` for anything
+    /// else. Only the header, the section table, the dynamic section and its
+    /// string table are read, not the whole file.
+    fn read(path: &Path) -> Option<Self> {
+        let data = ReadCache::new(File::open(path).ok()?);
+        match FileKind::parse(&data).ok()? {
+            FileKind::Elf32 => Self::parse::<FileHeader32<Endianness>>(&data),
+            FileKind::Elf64 => Self::parse::<FileHeader64<Endianness>>(&data),
+            _ => None,
+        }
+    }
+
+    fn parse<Elf: FileHeader<Endian = Endianness>>(data: &ReadCache<File>) -> Option<Self> {
+        let header = Elf::parse(data).ok()?;
+        let endian = header.endian().ok()?;
+        let sections = header.sections(endian, data).ok()?;
+        let (entries, strtab) = sections.dynamic(endian, data).ok()??;
+        let strings = sections.strings(endian, data, strtab).ok()?;
+
+        let mut dynamic = Self::default();
+        for entry in entries {
+            let list = match entry.tag32(endian) {
+                Some(DT_NEEDED) => &mut dynamic.needed,
+                Some(DT_RPATH | DT_RUNPATH) => &mut dynamic.runpath,
+                _ => continue,
+            };
+            if let Ok(value) = entry.string(endian, strings) {
+                list.push(String::from_utf8_lossy(value).into_owned());
+            }
+        }
+        Some(dynamic)
+    }
+}
+
+/// `entry` with `$ORIGIN` (or `${ORIGIN}`) replaced by `origin`. Entries
+/// using another loader variable can't be resolved statically.
+fn expand_origin(entry: &str, origin: &Path) -> Option<PathBuf> {
+    let origin = origin.to_string_lossy();
+    let expanded = entry
+        .replace("${ORIGIN}", &origin)
+        .replace("$ORIGIN", &origin);
+    (!expanded.is_empty() && !expanded.contains('$')).then(|| PathBuf::from(expanded))
+}
+
+/// Directories listed in an ld.so.conf, following `include` lines. Only
+/// `*`-suffixed file patterns are expanded, which is all distributions use.
+fn ld_so_conf_dirs(conf: &Path, depth: usize) -> Vec<PathBuf> {
+    let Ok(raw) = fs::read_to_string(conf) else {
+        return Vec::new();
+    };
+    let mut dirs = Vec::new();
+    for line in raw.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if let Some(pattern) = line.strip_prefix("include ") {
+            if depth < 4 {
+                for included in expand_include(pattern.trim()) {
+                    dirs.extend(ld_so_conf_dirs(&included, depth + 1));
+                }
+            }
+        } else if line.starts_with('/') {
+            dirs.push(PathBuf::from(line));
+        }
+    }
+    dirs
+}
+
+fn expand_include(pattern: &str) -> Vec<PathBuf> {
+    let pattern = Path::new(pattern);
+    let (Some(dir), Some(name)) = (pattern.parent(), pattern.file_name()) else {
+        return Vec::new();
+    };
+    let name = name.to_string_lossy();
+    let Some((prefix, suffix)) = name.split_once('*') else {
+        return vec![pattern.to_path_buf()];
+    };
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .map(|n| n.to_string_lossy())
+                .is_some_and(|n| n.starts_with(prefix) && n.ends_with(suffix))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// File names directly under `lib` in the Cellar's kegs.
+struct CellarIndex {
+    cellar: PathBuf,
+    kegs: HashSet<PathBuf>,
+    libraries: HashSet<String>,
+}
+
+impl CellarIndex {
+    fn new(cellar: PathBuf) -> Self {
+        Self {
+            cellar,
+            kegs: HashSet::new(),
+            libraries: HashSet::new(),
+        }
+    }
+
+    /// Add the libraries of kegs not indexed yet, returning whether there
+    /// were any.
+    fn refresh(&mut self) -> bool {
+        let mut added = false;
+        for formula in fs::read_dir(&self.cellar).into_iter().flatten().flatten() {
+            for keg in fs::read_dir(formula.path()).into_iter().flatten().flatten() {
+                if !self.kegs.insert(keg.path()) {
+                    continue;
+                }
+                added = true;
+                for lib in fs::read_dir(keg.path().join("lib"))
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .filter(|lib| lib.path().exists())
+                {
+                    self.libraries
+                        .insert(lib.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+        added
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// `keg/lib/libzbdummy.so` and `keg/bin/tool` linked against it with
+    /// `rpath`, or `None` if they can't be compiled.
+    fn build(keg: &Path, rpath: &str) -> Option<PathBuf> {
+        fs::create_dir_all(keg.join("lib")).ok()?;
+        fs::create_dir_all(keg.join("bin")).ok()?;
+        let lib_src = keg.join("dummy.c");
+        fs::write(&lib_src, "int dummy(void) { return 0; }").ok()?;
+        let built = Command::new("cc")
+            .args(["-shared", "-fPIC", "-Wl,-soname,libzbdummy.so.1", "-o"])
+            .arg(keg.join("lib/libzbdummy.so.1"))
+            .arg(&lib_src)
+            .status()
+            .ok()?;
+        if !built.success() {
+            return None;
+        }
+        std::os::unix::fs::symlink("libzbdummy.so.1", keg.join("lib/libzbdummy.so")).ok()?;
+
+        let main_src = keg.join("main.c");
+        fs::write(&main_src, "int dummy(void); int main() { return dummy(); }").ok()?;
+        let tool = keg.join("bin/tool");
+        let built = Command::new("cc")
+            .arg(&main_src)
+            .arg("-o")
+            .arg(&tool)
+            .arg(format!("-L{}", keg.join("lib").display()))
+            .arg("-lzbdummy")
+            .arg(format!("-Wl,-rpath,{rpath}"))
+            .status()
+            .ok()?;
+        built.success().then_some(tool)
+    }
+
+    #[test]
+    #[ignore = "needs a C compiler; run with --ignored"]
+    fn a_nonexistent_soname_is_reported() {
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path().join("prefix/Cellar/tool/1.0");
+        let tool = build(&keg, "/nonexistent/lib").expect("cc should build the test binaries");
+        fs::remove_file(keg.join("lib/libzbdummy.so.1")).unwrap();
+
+        let missing =
+            LibraryResolver::new(&tmp.path().join("prefix")).missing(std::slice::from_ref(&tool));
+
+        assert_eq!(
+            missing,
+            [MissingLibraries {
+                path: tool,
+                sonames: vec!["libzbdummy.so.1".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a C compiler; run with --ignored"]
+    fn origin_relative_runpaths_resolve() {
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path().join("keg");
+        let tool = build(&keg, "$ORIGIN/../lib").expect("cc should build the test binaries");
+
+        // Nothing under the prefix or the Cellar has the library.
+        let resolver = LibraryResolver::new(&tmp.path().join("prefix"));
+        assert!(resolver.missing(std::slice::from_ref(&tool)).is_empty());
+
+        fs::rename(keg.join("lib"), keg.join("elsewhere")).unwrap();
+        assert_eq!(resolver.missing(&[tool]).len(), 1);
+    }
+
+    #[test]
+    #[ignore = "needs a C compiler; run with --ignored"]
+    fn libraries_in_unlinked_kegs_count_as_found() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let keg = tmp.path().join("keg");
+        let tool = build(&keg, "/nonexistent/lib").expect("cc should build the test binaries");
+        let dep = prefix.join("Cellar/dummy/1.0/lib");
+        fs::create_dir_all(&dep).unwrap();
+        fs::rename(keg.join("lib/libzbdummy.so.1"), dep.join("libzbdummy.so.1")).unwrap();
+
+        assert!(LibraryResolver::new(&prefix).missing(&[tool]).is_empty());
+    }
+
+    #[test]
+    fn kegs_poured_after_indexing_are_found() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let lib = |keg: &str| prefix.join("Cellar").join(keg).join("lib");
+        fs::create_dir_all(lib("a/1.0")).unwrap();
+        fs::write(lib("a/1.0").join("liba.so.1"), "").unwrap();
+
+        let resolver = LibraryResolver::new(&prefix);
+        fs::create_dir_all(lib("b/2.0")).unwrap();
+        fs::write(lib("b/2.0").join("libb.so.2"), "").unwrap();
+
+        assert!(resolver.in_cellar("liba.so.1"));
+        assert!(resolver.in_cellar("libb.so.2"));
+        assert!(!resolver.in_cellar("libc.so.9"));
+    }
+
+    #[test]
+    fn ld_so_conf_includes_are_followed() {
+        let tmp = TempDir::new().unwrap();
+        let conf_d = tmp.path().join("ld.so.conf.d");
+        fs::create_dir_all(&conf_d).unwrap();
+        fs::write(conf_d.join("a.conf"), "/opt/a/lib # comment\n").unwrap();
+        fs::write(conf_d.join("b.conf"), "/opt/b/lib\n").unwrap();
+        fs::write(conf_d.join("ignored.txt"), "/opt/ignored\n").unwrap();
+        let conf = tmp.path().join("ld.so.conf");
+        fs::write(
+            &conf,
+            format!("include {}/*.conf\n/usr/local/lib\n", conf_d.display()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            ld_so_conf_dirs(&conf, 0),
+            [
+                PathBuf::from("/opt/a/lib"),
+                PathBuf::from("/opt/b/lib"),
+                PathBuf::from("/usr/local/lib"),
+            ]
+        );
+    }
+
+    #[test]
+    fn origin_is_expanded_and_other_variables_are_not() {
+        let origin = Path::new("/keg/bin");
+        assert_eq!(
+            expand_origin("$ORIGIN/../lib", origin),
+            Some(PathBuf::from("/keg/bin/../lib"))
+        );
+        assert_eq!(
+            expand_origin("${ORIGIN}/lib", origin),
+            Some(PathBuf::from("/keg/bin/lib"))
+        );
+        assert_eq!(expand_origin("$LIB/foo", origin), None);
+    }
+}
@@ -0,0 +1,180 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::is_patch_temp;
+
+const MACHO_MAGICS: &[u32] = &[0xfeedface, 0xfeedfacf, 0xcafebabe, 0xcefaedfe, 0xcffaedfe];
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+
+/// What a keg file is, decided from its first four bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileClass {
+    MachO,
+    Elf,
+    Other,
+}
+
+impl FileClass {
+    fn of(path: &Path) -> Self {
+        let mut magic = [0u8; 4];
+        let read = fs::File::open(path).and_then(|mut file| file.read_exact(&mut magic));
+        if read.is_err() {
+            return Self::Other;
+        }
+        if MACHO_MAGICS.contains(&u32::from_be_bytes(magic)) {
+            Self::MachO
+        } else if magic == ELF_MAGIC {
+            Self::Elf
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Every file the patch passes care about, from one walk of the keg. Each
+/// pass used to walk (and the Mach-O pass fully read) the whole keg on its
+/// own; large kegs paid for that three times over.
+#[derive(Debug, Default)]
+pub struct KegScan {
+    /// Regular files, all of which are text patching candidates.
+    pub files: Vec<PathBuf>,
+    pub macho: Vec<PathBuf>,
+    pub elf: Vec<PathBuf>,
+    /// Files under a `bin/` directory, including symlinks to files, which
+    /// is where signing matters.
+    pub bin: Vec<PathBuf>,
+}
+
+impl KegScan {
+    /// Walk `keg_path` once without following symlinks, so nothing is
+    /// classified (or patched) twice. Header reads run in parallel.
+    pub fn new(keg_path: &Path) -> Self {
+        use rayon::prelude::*;
+
+        let mut scan = Self::default();
+        for entry in walkdir::WalkDir::new(keg_path)
+            .follow_links(false)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let path = entry.path();
+            let file_type = entry.file_type();
+            let in_bin = path.to_string_lossy().contains("/bin/");
+            if file_type.is_symlink() {
+                if in_bin && path.is_file() {
+                    scan.bin.push(path.to_path_buf());
+                }
+                continue;
+            }
+            if !file_type.is_file() || is_patch_temp(path) {
+                continue;
+            }
+
+            if in_bin {
+                scan.bin.push(path.to_path_buf());
+            }
+            scan.files.push(path.to_path_buf());
+        }
+
+        let classes: Vec<FileClass> = scan.files.par_iter().map(|p| FileClass::of(p)).collect();
+        for (path, class) in scan.files.iter().zip(classes) {
+            match class {
+                FileClass::MachO => scan.macho.push(path.clone()),
+                FileClass::Elf => scan.elf.push(path.clone()),
+                FileClass::Other => {}
+            }
+        }
+        scan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn one_walk_classifies_every_file() {
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path().join("keg");
+        fs::create_dir_all(keg.join("bin")).unwrap();
+        fs::create_dir_all(keg.join("lib")).unwrap();
+        fs::create_dir_all(keg.join("libexec")).unwrap();
+        fs::write(keg.join("bin/tool"), b"\xcf\xfa\xed\xfe rest of a Mach-O").unwrap();
+        fs::write(keg.join("lib/libfoo.so"), b"\x7fELF rest of an ELF").unwrap();
+        fs::write(keg.join("lib/.zb-patch.4242.0"), b"\x7fELF left by a crash").unwrap();
+        fs::write(keg.join("lib/libfoo.tmp_patch"), b"\x7fELF shipped").unwrap();
+        fs::write(keg.join("lib/short"), b"ab").unwrap();
+        fs::write(keg.join("libexec/helper"), b"#!/bin/sh\n").unwrap();
+        std::os::unix::fs::symlink("../libexec/helper", keg.join("bin/helper")).unwrap();
+        std::os::unix::fs::symlink("missing", keg.join("bin/dangling")).unwrap();
+
+        let mut scan = KegScan::new(&keg);
+        scan.files.sort();
+        scan.bin.sort();
+        scan.elf.sort();
+
+        assert_eq!(scan.macho, [keg.join("bin/tool")]);
+        assert_eq!(
+            scan.elf,
+            [keg.join("lib/libfoo.so"), keg.join("lib/libfoo.tmp_patch")]
+        );
+        assert_eq!(
+            scan.files,
+            [
+                keg.join("bin/tool"),
+                keg.join("lib/libfoo.so"),
+                keg.join("lib/libfoo.tmp_patch"),
+                keg.join("lib/short"),
+                keg.join("libexec/helper"),
+            ]
+        );
+        assert_eq!(scan.bin, [keg.join("bin/helper"), keg.join("bin/tool")]);
+    }
+
+    /// The three walks the patch passes used to make (the Mach-O one reading
+    /// every file in full) against one scan, on a generated 20k-file keg.
+    #[test]
+    #[ignore = "benchmark: run with --release -- --ignored --nocapture"]
+    fn single_scan_beats_three_walks() {
+        use std::time::Instant;
+
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path().join("keg");
+        for dir in 0..200 {
+            let dir = keg.join(format!("share/d{dir}"));
+            fs::create_dir_all(&dir).unwrap();
+            for file in 0..100 {
+                fs::write(dir.join(format!("f{file}")), vec![b'x'; 4096]).unwrap();
+            }
+        }
+
+        let walk = || {
+            walkdir::WalkDir::new(&keg)
+                .follow_links(false)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.path().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+        let started = Instant::now();
+        let macho = walk()
+            .into_iter()
+            .filter(|path| fs::read(path).is_ok_and(|data| data.starts_with(b"\xcf\xfa\xed\xfe")))
+            .count();
+        let text = walk().len();
+        let bin = walk().len();
+        let three_walks = started.elapsed();
+
+        let started = Instant::now();
+        let scan = KegScan::new(&keg);
+        let one_scan = started.elapsed();
+
+        println!("three walks: {three_walks:?}, one scan: {one_scan:?}");
+        assert_eq!((scan.macho.len(), scan.files.len()), (macho, text));
+        assert_eq!(bin, 20_000);
+        assert!(one_scan < three_walks);
+    }
+}
@@ -0,0 +1,769 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{debug, warn};
+use zb_core::Error;
+
+use super::{KegBoundary, PatchFailure, PatchPolicy};
+use crate::progress::{MaterializeObserver, PatchCounter};
+
+/// Files larger than this are only stream-scanned for placeholders instead of
+/// being read into memory up front.
+pub const DEFAULT_MAX_TEXT_PATCH_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Extensions that are never text, even when their first 8 KiB contain no
+/// NUL byte (compressed payloads, bytecode, fonts, images, data blobs).
+const BINARY_EXTENSIONS: &[&str] = &[
+    "a", "bz2", "class", "dat", "db", "dylib", "egg", "gif", "gz", "icns", "ico", "jar", "jpeg",
+    "jpg", "lz", "lzma", "o", "otf", "pdf", "png", "pyc", "pyo", "so", "sqlite", "tar", "tgz",
+    "ttf", "wasm", "whl", "woff", "woff2", "xz", "zip", "zst",
+];
+
+const SCAN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Text patching settings, read from `patch.json` in the zerobrew root.
+///
+/// ```json
+/// {
+///   "max_text_size": 4194304,
+///   "policy": "strict",
+///   "formulas": {
+///     "icu4c": { "exclude": ["share/icu/**"] },
+///     "python@3.12": { "include": ["bin/*", "lib/pkgconfig/*.pc"] }
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PatchConfig {
+    pub max_text_size: u64,
+    /// What to do with files that can't be patched; `warn` by default.
+    pub policy: PatchPolicy,
+    pub formulas: BTreeMap<String, PatchRules>,
+}
+
+/// Per-formula path globs, relative to the keg root. A pattern without a `/`
+/// matches against the file name alone. When `include` is non-empty only
+/// matching files are text-patched; `exclude` always wins.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PatchRules {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Default for PatchConfig {
+    fn default() -> Self {
+        Self {
+            max_text_size: DEFAULT_MAX_TEXT_PATCH_SIZE,
+            policy: PatchPolicy::default(),
+            formulas: BTreeMap::new(),
+        }
+    }
+}
+
+impl PatchConfig {
+    /// Load the config at `path`, falling back to the defaults when it does
+    /// not exist.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(Error::FileError {
+                    message: format!("failed to read patch config {}: {e}", path.display()),
+                });
+            }
+        };
+
+        serde_json::from_str(&raw).map_err(|e| Error::InvalidArgument {
+            message: format!("invalid patch config {}: {e}", path.display()),
+        })
+    }
+
+    pub(crate) fn filter_for(&self, formula: &str) -> Result<TextPatchFilter, Error> {
+        let rules = self.formulas.get(formula).cloned().unwrap_or_default();
+        Ok(TextPatchFilter {
+            max_text_size: self.max_text_size,
+            include: compile_globs(&rules.include)?,
+            exclude: compile_globs(&rules.exclude)?,
+        })
+    }
+}
+
+/// Outcome counters for one keg's text patching pass.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TextPatchReport {
+    pub patched: usize,
+    pub skipped_binary: usize,
+    pub skipped_by_rule: usize,
+    pub skipped_by_size: usize,
+    /// Files that couldn't be rewritten, sorted by path.
+    pub failures: Vec<PatchFailure>,
+    /// Files over the size threshold that did contain a placeholder and were
+    /// therefore read and patched in full.
+    pub oversized_patched: Vec<PathBuf>,
+}
+
+impl TextPatchReport {
+    /// Warn about large files that had to be rewritten in full; they are
+    /// usually data that should be excluded in `patch.json`.
+    pub fn warn_oversized(&self, formula: &str) {
+        for path in &self.oversized_patched {
+            warn!(
+                formula,
+                path = %path.display(),
+                "patched placeholder in file above the text size limit"
+            );
+        }
+        debug!(
+            formula,
+            patched = self.patched,
+            skipped_binary = self.skipped_binary,
+            skipped_by_rule = self.skipped_by_rule,
+            skipped_by_size = self.skipped_by_size,
+            failed = self.failures.len(),
+            "text placeholder patching finished"
+        );
+    }
+}
+
+/// Thread-safe accumulator for [`TextPatchReport`] used from rayon workers.
+#[derive(Default)]
+pub(crate) struct TextPatchCounters {
+    patched: AtomicUsize,
+    skipped_binary: AtomicUsize,
+    skipped_by_rule: AtomicUsize,
+    skipped_by_size: AtomicUsize,
+    failures: Mutex<Vec<PatchFailure>>,
+    oversized_patched: Mutex<Vec<PathBuf>>,
+}
+
+impl TextPatchCounters {
+    pub(crate) fn record_skip(&self, reason: SkipReason) {
+        let counter = match reason {
+            SkipReason::Binary => &self.skipped_binary,
+            SkipReason::Rule => &self.skipped_by_rule,
+            SkipReason::Size => &self.skipped_by_size,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_patched(&self, path: &Path, oversized: bool) {
+        self.patched.fetch_add(1, Ordering::Relaxed);
+        if oversized && let Ok(mut paths) = self.oversized_patched.lock() {
+            paths.push(path.to_path_buf());
+        }
+    }
+
+    pub(crate) fn record_failure(&self, path: &Path, error: impl std::fmt::Display) {
+        if let Ok(mut failures) = self.failures.lock() {
+            failures.push(PatchFailure::new(path, error));
+        }
+    }
+
+    pub(crate) fn into_report(self) -> TextPatchReport {
+        let mut oversized_patched = self.oversized_patched.into_inner().unwrap_or_default();
+        oversized_patched.sort();
+        let mut failures = self.failures.into_inner().unwrap_or_default();
+        failures.sort_by(|a, b| a.path.cmp(&b.path));
+        TextPatchReport {
+            patched: self.patched.into_inner(),
+            skipped_binary: self.skipped_binary.into_inner(),
+            skipped_by_rule: self.skipped_by_rule.into_inner(),
+            skipped_by_size: self.skipped_by_size.into_inner(),
+            failures,
+            oversized_patched,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SkipReason {
+    Binary,
+    Rule,
+    Size,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TextCandidate {
+    Skip(SkipReason),
+    Patch { oversized: bool },
+}
+
+pub(crate) struct TextPatchFilter {
+    max_text_size: u64,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl TextPatchFilter {
+    /// Decide whether `path` should be read and patched. Cheap checks
+    /// (rules, extension, NUL sniff) come first; files over the size limit
+    /// are then scanned in fixed-size chunks for any of `needles` so they
+    /// are never held in memory unless a rewrite is actually needed.
+    pub(crate) fn classify(
+        &self,
+        keg_path: &Path,
+        path: &Path,
+        needles: &[&str],
+    ) -> io::Result<TextCandidate> {
+        let relative = path.strip_prefix(keg_path).unwrap_or(path);
+        if !self.allows(relative) {
+            return Ok(TextCandidate::Skip(SkipReason::Rule));
+        }
+
+        if has_binary_extension(path) {
+            return Ok(TextCandidate::Skip(SkipReason::Binary));
+        }
+
+        let mut file = fs::File::open(path)?;
+        let mut head = [0u8; 8192];
+        let n = file.read(&mut head)?;
+        if head[..n].contains(&0) {
+            return Ok(TextCandidate::Skip(SkipReason::Binary));
+        }
+
+        let size = file.metadata()?.len();
+        if size <= self.max_text_size {
+            return Ok(TextCandidate::Patch { oversized: false });
+        }
+
+        let mut reader = io::Cursor::new(&head[..n]).chain(file);
+        if stream_contains_any(&mut reader, needles)? {
+            Ok(TextCandidate::Patch { oversized: true })
+        } else {
+            Ok(TextCandidate::Skip(SkipReason::Size))
+        }
+    }
+
+    fn allows(&self, relative: &Path) -> bool {
+        let rel = relative.to_string_lossy().replace('\\', "/");
+        let name = relative
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let matches = |re: &Regex| re.is_match(&rel) || re.is_match(&name);
+
+        if self.exclude.iter().any(matches) {
+            return false;
+        }
+        self.include.is_empty() || self.include.iter().any(matches)
+    }
+}
+
+/// Literal substitutions applied to text files: the `@@HOMEBREW_*@@`
+/// placeholders, plus optionally hardcoded Homebrew prefixes rewritten to
+/// the new prefix.
+pub(crate) struct TextReplacements {
+    placeholders: Vec<(&'static str, String)>,
+    legacy_prefixes: Vec<String>,
+    prefix: String,
+}
+
+impl TextReplacements {
+    pub(crate) fn new(prefix: &Path, cellar: &Path) -> Self {
+        let prefix = prefix.to_string_lossy().into_owned();
+        let placeholders = vec![
+            ("@@HOMEBREW_PREFIX@@", prefix.clone()),
+            ("@@HOMEBREW_CELLAR@@", cellar.to_string_lossy().into_owned()),
+            ("@@HOMEBREW_REPOSITORY@@", prefix.clone()),
+            ("@@HOMEBREW_LIBRARY@@", format!("{prefix}/Library")),
+            ("@@HOMEBREW_PERL@@", "/usr/bin/perl".to_string()),
+            ("@@HOMEBREW_JAVA@@", "/usr/bin/java".to_string()),
+        ];
+        Self {
+            placeholders,
+            legacy_prefixes: Vec::new(),
+            prefix,
+        }
+    }
+
+    /// Also rewrite these literal prefixes, tried in order, so list a prefix
+    /// before any shorter prefix of it. One equal to the new prefix is skipped.
+    pub(crate) fn with_legacy_prefixes(mut self, prefixes: &[&str]) -> Self {
+        self.legacy_prefixes = prefixes
+            .iter()
+            .filter(|old| **old != self.prefix)
+            .map(|old| old.to_string())
+            .collect();
+        self
+    }
+
+    /// Substrings that make a file worth reading.
+    pub(crate) fn needles(&self) -> Vec<&str> {
+        std::iter::once("@@HOMEBREW_")
+            .chain(self.legacy_prefixes.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// `content` with every substitution applied, or `None` if nothing
+    /// changed.
+    pub(crate) fn apply(&self, content: &str) -> Option<String> {
+        let mut patched = content.to_string();
+        if patched.contains("@@HOMEBREW_") {
+            for (placeholder, value) in &self.placeholders {
+                patched = patched.replace(placeholder, value);
+            }
+        }
+        for old in &self.legacy_prefixes {
+            if patched.contains(old) {
+                patched = patched.replace(old, &self.prefix);
+            }
+        }
+        (patched != content).then_some(patched)
+    }
+}
+
+/// Rewrite every file in `files`, from a scan of `keg_path`, that `filter`
+/// lets through.
+/// Files that are not valid UTF-8 are left alone; read-only files are made
+/// writable for the rewrite and restored afterwards. Each file is counted in
+/// `counter` once done with. Files that resolve outside `keg_path` are
+/// reported as failures rather than written. Files not yet started when
+/// `observer` asks to cancel are skipped; the caller reports it.
+pub(crate) fn patch_text_files(
+    keg_path: &Path,
+    files: &[PathBuf],
+    filter: &TextPatchFilter,
+    replacements: &TextReplacements,
+    observer: &dyn MaterializeObserver,
+    counter: &PatchCounter,
+) -> TextPatchReport {
+    use rayon::prelude::*;
+
+    let needles = replacements.needles();
+    let counters = TextPatchCounters::default();
+    let boundary = KegBoundary::new(keg_path);
+
+    files.par_iter().for_each(|path| {
+        if observer.should_cancel() {
+            return;
+        }
+        let result =
+            filter
+                .classify(keg_path, path, &needles)
+                .and_then(|candidate| match candidate {
+                    TextCandidate::Skip(reason) => {
+                        counters.record_skip(reason);
+                        Ok(())
+                    }
+                    TextCandidate::Patch { oversized } => {
+                        boundary.check(path)?;
+                        if patch_text_file(path, replacements)? {
+                            counters.record_patched(path, oversized);
+                        }
+                        Ok(())
+                    }
+                });
+
+        if let Err(e) = result {
+            counters.record_failure(path, e);
+        }
+        counter.advance(observer);
+    });
+
+    counters.into_report()
+}
+
+fn patch_text_file(path: &Path, replacements: &TextReplacements) -> io::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Ok(content) = fs::read_to_string(path) else {
+        return Ok(false);
+    };
+    let Some(patched) = replacements.apply(&content) else {
+        return Ok(false);
+    };
+
+    let permissions = fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    let readonly = mode & 0o200 == 0;
+    if readonly {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode | 0o200))?;
+    }
+
+    let written = fs::write(path, patched);
+
+    if readonly {
+        fs::set_permissions(path, permissions)?;
+    }
+    written.map(|()| true)
+}
+
+fn has_binary_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            BINARY_EXTENSIONS
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(ext))
+        })
+}
+
+/// Search `reader` for any of `needles` without buffering more than one
+/// chunk plus the longest needle. Stops reading at the first match.
+pub(crate) fn stream_contains_any<R: Read>(reader: &mut R, needles: &[&str]) -> io::Result<bool> {
+    let overlap = needles.iter().map(|n| n.len()).max().unwrap_or(0);
+    if overlap == 0 {
+        return Ok(false);
+    }
+
+    let mut window: Vec<u8> = Vec::with_capacity(SCAN_CHUNK_SIZE + overlap);
+    let mut chunk = vec![0u8; SCAN_CHUNK_SIZE];
+
+    loop {
+        let n = reader.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(false);
+        }
+        window.extend_from_slice(&chunk[..n]);
+
+        if needles
+            .iter()
+            .any(|needle| contains_bytes(&window, needle.as_bytes()))
+        {
+            return Ok(true);
+        }
+
+        let keep = window.len().min(overlap - 1);
+        window.drain(..window.len() - keep);
+    }
+}
+
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    !needle.is_empty() && haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn compile_globs(patterns: &[String]) -> Result<Vec<Regex>, Error> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(&glob_to_regex(pattern)).map_err(|e| Error::InvalidArgument {
+                message: format!("invalid patch glob '{pattern}': {e}"),
+            })
+        })
+        .collect()
+}
+
+/// Translate a path glob into an anchored regex. Supports `**`, `*` and `?`.
+fn glob_to_regex(glob: &str) -> String {
+    let mut out = String::from("^");
+    let mut chars = glob.trim_start_matches('/').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    out.push_str("(?:.*/)?");
+                } else {
+                    out.push_str(".*");
+                }
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            other => out.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+
+    out.push('$');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::NoopObserver;
+    use tempfile::TempDir;
+
+    struct CountingReader<R> {
+        inner: R,
+        bytes_read: usize,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.bytes_read += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn glob_translation_handles_wildcards() {
+        let re = Regex::new(&glob_to_regex("share/**/*.dat")).unwrap();
+        assert!(re.is_match("share/icu/74/icudt74l.dat"));
+        assert!(re.is_match("share/x.dat"));
+        assert!(!re.is_match("lib/x.dat"));
+
+        let re = Regex::new(&glob_to_regex("bin/?z")).unwrap();
+        assert!(re.is_match("bin/xz"));
+        assert!(!re.is_match("bin/sub/xz"));
+    }
+
+    #[test]
+    fn stream_search_finds_needle_across_chunk_boundary() {
+        let mut data = vec![b'a'; SCAN_CHUNK_SIZE - 5];
+        data.extend_from_slice(b"@@HOMEBREW_PREFIX@@");
+        data.extend(vec![b'b'; 100]);
+
+        assert!(stream_contains_any(&mut io::Cursor::new(&data), &["@@HOMEBREW_"]).unwrap());
+        assert!(!stream_contains_any(&mut io::Cursor::new(&data), &["/opt/homebrew"]).unwrap());
+    }
+
+    #[test]
+    fn stream_search_stops_at_first_match() {
+        let mut data = b"#!/bin/sh\n@@HOMEBREW_PREFIX@@\n".to_vec();
+        data.extend(vec![b'x'; 16 * SCAN_CHUNK_SIZE]);
+        let mut reader = CountingReader {
+            inner: io::Cursor::new(data),
+            bytes_read: 0,
+        };
+
+        assert!(stream_contains_any(&mut reader, &["@@HOMEBREW_"]).unwrap());
+        assert_eq!(reader.bytes_read, SCAN_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn classify_applies_size_extension_and_rules() {
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path();
+        fs::create_dir_all(keg.join("share/icu")).unwrap();
+        fs::create_dir_all(keg.join("bin")).unwrap();
+
+        fs::write(keg.join("bin/small"), "@@HOMEBREW_PREFIX@@").unwrap();
+        fs::write(keg.join("bin/big"), "x".repeat(2048)).unwrap();
+        fs::write(
+            keg.join("bin/big-marked"),
+            "x".repeat(2048) + "@@HOMEBREW_CELLAR@@",
+        )
+        .unwrap();
+        fs::write(keg.join("bin/module.pyc"), "@@HOMEBREW_PREFIX@@").unwrap();
+        fs::write(keg.join("share/icu/notes.txt"), "@@HOMEBREW_PREFIX@@").unwrap();
+
+        let config = PatchConfig {
+            max_text_size: 1024,
+            policy: PatchPolicy::Warn,
+            formulas: BTreeMap::from([(
+                "icu4c".to_string(),
+                PatchRules {
+                    include: Vec::new(),
+                    exclude: vec!["share/icu/**".to_string()],
+                },
+            )]),
+        };
+        let filter = config.filter_for("icu4c").unwrap();
+        let needles = ["@@HOMEBREW_"];
+        let classify = |rel: &str| filter.classify(keg, &keg.join(rel), &needles).unwrap();
+
+        assert_eq!(
+            classify("bin/small"),
+            TextCandidate::Patch { oversized: false }
+        );
+        assert_eq!(classify("bin/big"), TextCandidate::Skip(SkipReason::Size));
+        assert_eq!(
+            classify("bin/big-marked"),
+            TextCandidate::Patch { oversized: true }
+        );
+        assert_eq!(
+            classify("bin/module.pyc"),
+            TextCandidate::Skip(SkipReason::Binary)
+        );
+        assert_eq!(
+            classify("share/icu/notes.txt"),
+            TextCandidate::Skip(SkipReason::Rule)
+        );
+    }
+
+    #[test]
+    fn include_rules_restrict_candidates() {
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path();
+        fs::create_dir_all(keg.join("lib/pkgconfig")).unwrap();
+        fs::write(
+            keg.join("lib/pkgconfig/foo.pc"),
+            "prefix=@@HOMEBREW_PREFIX@@",
+        )
+        .unwrap();
+        fs::write(keg.join("lib/README"), "@@HOMEBREW_PREFIX@@").unwrap();
+
+        let config: PatchConfig =
+            serde_json::from_str(r#"{"formulas":{"foo":{"include":["*.pc"]}}}"#).unwrap();
+        assert_eq!(config.max_text_size, DEFAULT_MAX_TEXT_PATCH_SIZE);
+
+        let filter = config.filter_for("foo").unwrap();
+        let needles = ["@@HOMEBREW_"];
+        assert_eq!(
+            filter
+                .classify(keg, &keg.join("lib/pkgconfig/foo.pc"), &needles)
+                .unwrap(),
+            TextCandidate::Patch { oversized: false }
+        );
+        assert_eq!(
+            filter
+                .classify(keg, &keg.join("lib/README"), &needles)
+                .unwrap(),
+            TextCandidate::Skip(SkipReason::Rule)
+        );
+    }
+
+    fn replacements() -> TextReplacements {
+        TextReplacements::new(
+            Path::new("/opt/zerobrew/prefix"),
+            Path::new("/opt/zerobrew/prefix/Cellar"),
+        )
+    }
+
+    #[test]
+    fn placeholder_table_covers_every_homebrew_variable() {
+        let patched = replacements()
+            .apply(
+                "PREFIX=@@HOMEBREW_PREFIX@@\n\
+                 CELLAR=@@HOMEBREW_CELLAR@@\n\
+                 REPO=@@HOMEBREW_REPOSITORY@@\n\
+                 LIBRARY=@@HOMEBREW_LIBRARY@@\n\
+                 PERL=@@HOMEBREW_PERL@@\n\
+                 JAVA=@@HOMEBREW_JAVA@@\n",
+            )
+            .unwrap();
+
+        assert_eq!(
+            patched,
+            "PREFIX=/opt/zerobrew/prefix\n\
+             CELLAR=/opt/zerobrew/prefix/Cellar\n\
+             REPO=/opt/zerobrew/prefix\n\
+             LIBRARY=/opt/zerobrew/prefix/Library\n\
+             PERL=/usr/bin/perl\n\
+             JAVA=/usr/bin/java\n"
+        );
+    }
+
+    #[test]
+    fn legacy_prefixes_are_only_rewritten_when_configured() {
+        let content = "GIT_EXEC_PATH=/opt/homebrew/opt/git/libexec/git-core\n\
+                       TAP=/usr/local/Homebrew/Library/Taps\n";
+
+        assert_eq!(replacements().apply(content), None);
+
+        let patched = replacements()
+            .with_legacy_prefixes(&["/opt/homebrew", "/usr/local/Homebrew", "/usr/local"])
+            .apply(content)
+            .unwrap();
+        assert_eq!(
+            patched,
+            "GIT_EXEC_PATH=/opt/zerobrew/prefix/opt/git/libexec/git-core\n\
+             TAP=/opt/zerobrew/prefix/Library/Taps\n"
+        );
+    }
+
+    #[test]
+    fn legacy_prefix_equal_to_new_prefix_is_ignored() {
+        let replacements = TextReplacements::new(
+            Path::new("/opt/homebrew"),
+            Path::new("/opt/homebrew/Cellar"),
+        )
+        .with_legacy_prefixes(&["/opt/homebrew"]);
+
+        assert_eq!(replacements.needles(), ["@@HOMEBREW_"]);
+        assert_eq!(replacements.apply("/opt/homebrew/bin/jq"), None);
+    }
+
+    #[test]
+    fn patch_text_files_handles_readonly_and_binary_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path().join("keg");
+        fs::create_dir_all(keg.join("bin")).unwrap();
+        fs::create_dir_all(keg.join("lib")).unwrap();
+
+        let script = keg.join("bin/tool");
+        fs::write(
+            &script,
+            "#!/bin/sh\nexec @@HOMEBREW_PREFIX@@/libexec/tool\n",
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o555)).unwrap();
+        let binary = keg.join("lib/blob");
+        fs::write(&binary, b"\0\0@@HOMEBREW_PREFIX@@").unwrap();
+        let untouched = keg.join("lib/README");
+        fs::write(&untouched, "nothing to see").unwrap();
+
+        let filter = PatchConfig::default().filter_for("tool").unwrap();
+        let scan = crate::extraction::patch::scan::KegScan::new(&keg);
+        let report = patch_text_files(
+            &keg,
+            &scan.files,
+            &filter,
+            &replacements(),
+            &NoopObserver,
+            &PatchCounter::new(scan.files.len()),
+        );
+
+        assert_eq!(report.patched, 1);
+        assert_eq!(report.skipped_binary, 1);
+        assert!(report.failures.is_empty());
+        assert_eq!(
+            fs::read_to_string(&script).unwrap(),
+            "#!/bin/sh\nexec /opt/zerobrew/prefix/libexec/tool\n"
+        );
+        assert_eq!(
+            fs::metadata(&script).unwrap().permissions().mode() & 0o777,
+            0o555
+        );
+        assert_eq!(fs::read(&binary).unwrap(), b"\0\0@@HOMEBREW_PREFIX@@");
+    }
+
+    #[test]
+    fn patch_text_files_never_writes_outside_the_keg() {
+        let tmp = TempDir::new().unwrap();
+        let keg = tmp.path().join("keg");
+        let shared = tmp.path().join("shared");
+        fs::create_dir_all(keg.join("etc")).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        let edited = shared.join("tool.conf");
+        fs::write(&edited, "root=@@HOMEBREW_PREFIX@@ # edited\n").unwrap();
+        std::os::unix::fs::symlink(&shared, keg.join("etc/shared")).unwrap();
+
+        // A candidate reached through a symlinked directory, as a repair
+        // pass handed an explicit file list might see it.
+        let through_link = keg.join("etc/shared/tool.conf");
+        let filter = PatchConfig::default().filter_for("tool").unwrap();
+        let report = patch_text_files(
+            &keg,
+            std::slice::from_ref(&through_link),
+            &filter,
+            &replacements(),
+            &NoopObserver,
+            &PatchCounter::new(1),
+        );
+
+        assert_eq!(report.patched, 0);
+        assert_eq!(report.failures.len(), 1);
+        assert!(
+            report.failures[0].error.contains("outside the keg"),
+            "{:?}",
+            report.failures
+        );
+        assert_eq!(
+            fs::read_to_string(&edited).unwrap(),
+            "root=@@HOMEBREW_PREFIX@@ # edited\n"
+        );
+    }
+
+    #[test]
+    fn missing_config_file_uses_defaults() {
+        let tmp = TempDir::new().unwrap();
+        let config = PatchConfig::load(&tmp.path().join("patch.json")).unwrap();
+        assert_eq!(config.max_text_size, DEFAULT_MAX_TEXT_PATCH_SIZE);
+        assert!(config.formulas.is_empty());
+    }
+}
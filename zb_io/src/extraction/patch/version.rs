@@ -0,0 +1,44 @@
+use std::path::{Path, PathBuf};
+
+use regex::{Captures, Regex};
+use zb_core::CELLAR_DIR;
+
+/// Rewrites paths a bottle has into another version of its own keg, such as
+/// `/Cellar/ffmpeg/8.0.1_1/` while pouring `8.0.1_2`. Only load commands and
+/// RUNPATH entries go through this; text files keep historical versions.
+pub(crate) struct VersionFixer {
+    regex: Regex,
+    cellar_dir: PathBuf,
+    pkg_name: String,
+    pkg_version: String,
+}
+
+impl VersionFixer {
+    pub(crate) fn new(cellar_dir: &Path, pkg_name: &str, pkg_version: &str) -> Self {
+        let pattern = format!(r"(/{}/{}/)([^/]+)(/)", CELLAR_DIR, regex::escape(pkg_name));
+        Self {
+            regex: Regex::new(&pattern).expect("escaped formula name is a valid pattern"),
+            cellar_dir: cellar_dir.to_path_buf(),
+            pkg_name: pkg_name.to_string(),
+            pkg_version: pkg_version.to_string(),
+        }
+    }
+
+    /// `path` pointing at the version being poured, or `None` if it already
+    /// does. Versions whose keg is installed next to this one are left alone.
+    pub(crate) fn fix(&self, path: &str) -> Option<String> {
+        let fixed = self.regex.replace_all(path, |caps: &Captures| {
+            let version = &caps[2];
+            if version == self.pkg_version || self.is_installed(version) {
+                caps[0].to_string()
+            } else {
+                format!("{}{}{}", &caps[1], self.pkg_version, &caps[3])
+            }
+        });
+        (fixed != path).then(|| fixed.into_owned())
+    }
+
+    fn is_installed(&self, version: &str) -> bool {
+        self.cellar_dir.join(&self.pkg_name).join(version).is_dir()
+    }
+}
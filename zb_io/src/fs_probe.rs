@@ -0,0 +1,165 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use zb_core::Error;
+
+/// What a directory's filesystem turned out to support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsCapabilities {
+    /// Hard links between files in the directory work. Without them the
+    /// Cellar falls back to plain copies.
+    pub hardlinks: bool,
+}
+
+/// Exercise the filesystem behind `dir` the way installs will: create a
+/// symlink, an executable file and a hard link in a scratch directory.
+///
+/// Missing symlinks or permission bits are fatal and reported by name, since
+/// every install would otherwise fail deep inside extraction or linking. The
+/// scratch directory is removed whether or not the probes succeed.
+pub fn probe_filesystem(dir: &Path) -> Result<FsCapabilities, Error> {
+    let scratch = dir.join(format!(".zb_fs_probe-{}", std::process::id()));
+    let result = run_probes(dir, &scratch);
+    let _ = fs::remove_dir_all(&scratch);
+    result
+}
+
+fn run_probes(dir: &Path, scratch: &Path) -> Result<FsCapabilities, Error> {
+    let _ = fs::remove_dir_all(scratch);
+    fs::create_dir(scratch).map_err(|e| Error::FileError {
+        message: format!("cannot write to {}: {e}", dir.display()),
+    })?;
+    check_capabilities(dir, scratch)
+}
+
+fn check_capabilities(dir: &Path, scratch: &Path) -> Result<FsCapabilities, Error> {
+    let file = scratch.join("file");
+    fs::write(&file, b"#!/bin/sh\n").map_err(|e| Error::FileError {
+        message: format!("cannot write to {}: {e}", dir.display()),
+    })?;
+
+    std::os::unix::fs::symlink("file", scratch.join("symlink"))
+        .map_err(|e| unsupported(dir, "symlinks", e))?;
+
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o755))
+        .map_err(|e| unsupported(dir, "file permissions", e))?;
+    let mode = fs::metadata(&file)
+        .map_err(|e| unsupported(dir, "file permissions", e))?
+        .permissions()
+        .mode();
+    if mode & 0o777 != 0o755 {
+        return Err(unsupported(
+            dir,
+            "file permissions",
+            io::Error::other(format!("mode 0o755 was stored as {:#o}", mode & 0o777)),
+        ));
+    }
+
+    let hardlinks = fs::hard_link(&file, scratch.join("hardlink")).is_ok();
+
+    Ok(FsCapabilities { hardlinks })
+}
+
+fn unsupported(dir: &Path, feature: &str, err: io::Error) -> Error {
+    Error::FileError {
+        message: format!(
+            "filesystem at {} does not support {feature} ({err}); zerobrew requires \
+             symlinks and POSIX permissions, so FAT and exFAT volumes cannot hold \
+             ZEROBREW_ROOT or ZEROBREW_PREFIX",
+            dir.display()
+        ),
+    }
+}
+
+/// Whether `a` and `b` live on the same filesystem. Paths that do not exist
+/// yet are judged by their nearest existing ancestor.
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    match (device_of(a), device_of(b)) {
+        (Some(a), Some(b)) => a == b,
+        // Can't tell; assume the common case.
+        _ => true,
+    }
+}
+
+fn device_of(path: &Path) -> Option<u64> {
+    let mut current: PathBuf = path.to_path_buf();
+    loop {
+        if let Ok(metadata) = fs::metadata(&current) {
+            return Some(metadata.dev());
+        }
+        if !current.pop() {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn probe_succeeds_and_cleans_up() {
+        let tmp = TempDir::new().unwrap();
+
+        let caps = probe_filesystem(tmp.path()).unwrap();
+
+        assert!(caps.hardlinks);
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn probe_reports_unwritable_directory() {
+        // Root ignores directory permissions, so there is nothing to observe.
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+
+        let tmp = TempDir::new().unwrap();
+        let readonly = tmp.path().join("readonly");
+        fs::create_dir(&readonly).unwrap();
+        fs::set_permissions(&readonly, fs::Permissions::from_mode(0o555)).unwrap();
+
+        let err = probe_filesystem(&readonly).unwrap_err();
+
+        fs::set_permissions(&readonly, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(err.to_string().contains(&readonly.display().to_string()));
+        assert_eq!(fs::read_dir(&readonly).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn symlink_failure_names_the_missing_feature() {
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+
+        // The probe file already exists and stays writable, but no new
+        // entries can be created next to it, so the symlink probe fails first.
+        let tmp = TempDir::new().unwrap();
+        let scratch = tmp.path().join("scratch");
+        fs::create_dir(&scratch).unwrap();
+        fs::write(scratch.join("file"), b"").unwrap();
+        fs::set_permissions(&scratch, fs::Permissions::from_mode(0o555)).unwrap();
+
+        let result = check_capabilities(tmp.path(), &scratch);
+
+        fs::set_permissions(&scratch, fs::Permissions::from_mode(0o755)).unwrap();
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("does not support symlinks"));
+        assert!(message.contains(&tmp.path().display().to_string()));
+    }
+
+    #[test]
+    fn missing_paths_use_nearest_existing_ancestor() {
+        let tmp = TempDir::new().unwrap();
+        let existing = tmp.path().join("root");
+        fs::create_dir(&existing).unwrap();
+
+        assert!(same_filesystem(
+            &existing,
+            &tmp.path().join("prefix/not/yet")
+        ));
+    }
+}
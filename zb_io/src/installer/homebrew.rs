@@ -1,5 +1,6 @@
 use std::process::Command;
 
+use serde::Deserialize;
 use zb_core::Error;
 
 /// Represents a Homebrew package that can be migrated
@@ -20,29 +21,43 @@ pub struct HomebrewMigrationPackages {
     pub casks: Vec<HomebrewPackage>,
 }
 
-/// Parse Homebrew formulas from JSON output of `brew info --json=v1 --installed`
-pub fn parse_formulas_from_json(json: &serde_json::Value) -> Vec<HomebrewPackage> {
-    let mut packages = Vec::new();
-
-    if let Some(formulas) = json.as_array() {
-        for formula in formulas {
-            if let Some(name) = formula.get("name").and_then(|n| n.as_str()) {
-                let tap = formula
-                    .get("tap")
-                    .and_then(|t| t.as_str())
-                    .unwrap_or("homebrew/core")
-                    .to_string();
-
-                packages.push(HomebrewPackage {
-                    name: name.to_string(),
-                    tap,
-                    is_cask: false,
-                });
-            }
-        }
-    }
+/// Shown as the tap of a formula whose tap Homebrew no longer knows.
+pub const UNKNOWN_TAP: &str = "unknown tap";
+
+/// The fields of a `brew info --json=v1` entry that migration needs.
+#[derive(Debug, Deserialize)]
+struct BrewInfoFormula {
+    name: String,
+    /// Older `brew` versions leave the field out for core formulas; a
+    /// formula from a tap that has since been removed has it set to null.
+    #[serde(default = "core_tap")]
+    tap: Option<String>,
+}
 
-    packages
+fn core_tap() -> Option<String> {
+    Some("homebrew/core".to_string())
+}
+
+/// Parse Homebrew formulas from JSON output of `brew info --json=v1 --installed`
+pub fn parse_formulas_from_json(json: &[u8]) -> Result<Vec<HomebrewPackage>, Error> {
+    let mut deserializer = serde_json::Deserializer::from_slice(json);
+    let formulas: Vec<BrewInfoFormula> = serde_path_to_error::deserialize(&mut deserializer)
+        .map_err(|e| Error::ExecutionError {
+            message: format!(
+                "failed to parse brew info JSON at `{}`: {}",
+                e.path(),
+                e.inner()
+            ),
+        })?;
+
+    Ok(formulas
+        .into_iter()
+        .map(|formula| HomebrewPackage {
+            name: formula.name,
+            tap: formula.tap.unwrap_or_else(|| UNKNOWN_TAP.to_string()),
+            is_cask: false,
+        })
+        .collect())
 }
 
 /// Parse Homebrew casks from plain text output of `brew list --cask`
@@ -60,7 +75,7 @@ pub fn parse_casks_from_plain_text(output: &str) -> Vec<HomebrewPackage> {
 
 /// Categorize Homebrew packages for migration
 ///
-/// Returns a struct with separate lists for:
+/// Returns a struct with separate lists, each sorted by name:
 /// - Formulas from homebrew/core (migratable)
 /// - Formulas from other taps (not migratable)
 /// - Cask packages (not migratable)
@@ -79,6 +94,10 @@ pub fn categorize_packages(packages: Vec<HomebrewPackage>) -> HomebrewMigrationP
         }
     }
 
+    for list in [&mut formulas, &mut non_core_formulas, &mut casks] {
+        list.sort_by(|a, b| (&a.name, &a.tap).cmp(&(&b.name, &b.tap)));
+    }
+
     HomebrewMigrationPackages {
         formulas,
         non_core_formulas,
@@ -102,10 +121,7 @@ pub fn get_homebrew_packages() -> Result<HomebrewMigrationPackages, Error> {
         )));
     }
 
-    let formulas_json: serde_json::Value = serde_json::from_slice(&formulas_output.stdout)
-        .map_err(Error::exec("failed to parse brew info JSON"))?;
-
-    let formulas = parse_formulas_from_json(&formulas_json);
+    let formulas = parse_formulas_from_json(&formulas_output.stdout)?;
 
     let casks_output = Command::new("brew")
         .args(["list", "--cask"])
@@ -127,6 +143,44 @@ pub fn get_homebrew_packages() -> Result<HomebrewMigrationPackages, Error> {
 mod tests {
     use super::*;
 
+    fn package(name: &str, tap: &str, is_cask: bool) -> HomebrewPackage {
+        HomebrewPackage {
+            name: name.to_string(),
+            tap: tap.to_string(),
+            is_cask,
+        }
+    }
+
+    #[test]
+    fn categorized_packages_are_sorted_by_name() {
+        let packages = vec![
+            package("wget", "homebrew/core", false),
+            package("iterm2", "homebrew/cask", true),
+            package("mytool", "acme/tools", false),
+            package("curl", "homebrew/core", false),
+            package("firefox", "homebrew/cask", true),
+            package("another", "acme/tools", false),
+        ];
+        let mut reversed = packages.clone();
+        reversed.reverse();
+
+        let names = |list: &[HomebrewPackage]| -> Vec<String> {
+            list.iter().map(|p| p.name.clone()).collect()
+        };
+        let first = categorize_packages(packages);
+        let second = categorize_packages(reversed);
+
+        assert_eq!(names(&first.formulas), ["curl", "wget"]);
+        assert_eq!(names(&first.non_core_formulas), ["another", "mytool"]);
+        assert_eq!(names(&first.casks), ["firefox", "iterm2"]);
+        assert_eq!(names(&second.formulas), names(&first.formulas));
+        assert_eq!(
+            names(&second.non_core_formulas),
+            names(&first.non_core_formulas)
+        );
+        assert_eq!(names(&second.casks), names(&first.casks));
+    }
+
     #[test]
     fn test_parse_formulas_from_json() {
         let brew_output = r#"[
@@ -142,8 +196,7 @@ mod tests {
             }
         ]"#;
 
-        let formulas_json: serde_json::Value = serde_json::from_str(brew_output).unwrap();
-        let packages = parse_formulas_from_json(&formulas_json);
+        let packages = parse_formulas_from_json(brew_output.as_bytes()).unwrap();
 
         assert_eq!(packages.len(), 2);
         assert_eq!(packages[0].name, "git");
@@ -159,14 +212,36 @@ mod tests {
             {"name": "no-tap-formula"}
         ]"#;
 
-        let formulas_json: serde_json::Value = serde_json::from_str(brew_output).unwrap();
-        let packages = parse_formulas_from_json(&formulas_json);
+        let packages = parse_formulas_from_json(brew_output.as_bytes()).unwrap();
 
         assert_eq!(packages.len(), 1);
         assert_eq!(packages[0].name, "no-tap-formula");
         assert_eq!(packages[0].tap, "homebrew/core");
     }
 
+    #[test]
+    fn null_tap_is_not_treated_as_core() {
+        let brew_output = r#"[
+            {"name": "removed-tap-formula", "tap": null}
+        ]"#;
+
+        let packages = parse_formulas_from_json(brew_output.as_bytes()).unwrap();
+        let categorized = categorize_packages(packages);
+
+        assert!(categorized.formulas.is_empty());
+        assert_eq!(categorized.non_core_formulas[0].tap, UNKNOWN_TAP);
+    }
+
+    #[test]
+    fn malformed_brew_info_names_the_field() {
+        let brew_output = r#"[{"name": "git"}, {"tap": "homebrew/core"}]"#;
+
+        let err = parse_formulas_from_json(brew_output.as_bytes()).unwrap_err();
+
+        assert!(err.to_string().contains("`[1]`"), "{err}");
+        assert!(err.to_string().contains("missing field `name`"), "{err}");
+    }
+
     #[test]
     fn test_parse_casks_from_plain_text() {
         // Simulate brew list --cask output
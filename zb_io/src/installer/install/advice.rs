@@ -0,0 +1,353 @@
+use std::path::Path;
+
+use zb_core::{Error, Lifecycle, is_newer_release_line, versioned_base};
+
+use super::{InstallPlan, Installer};
+use crate::storage::db::KegFileKind;
+
+/// Something worth telling the user about a formula they asked for before
+/// it is installed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallAdvice {
+    /// A versioned formula was requested while a newer release line exists.
+    NewerRelease {
+        requested: String,
+        newer: String,
+        version: String,
+    },
+    Deprecated {
+        name: String,
+        reason: Option<String>,
+    },
+    Disabled {
+        name: String,
+        reason: Option<String>,
+    },
+    /// A requested name is an alias of `canonical`, which was planned in its
+    /// place, or left alone when it is already installed.
+    Alias {
+        alias: String,
+        canonical: String,
+        installed: bool,
+    },
+    /// A requested name is provided by several sources; `chosen` won over
+    /// `shadowed`.
+    TapChoice {
+        name: String,
+        chosen: String,
+        shadowed: Vec<String>,
+    },
+    /// Another release line of a requested formula is installed and already
+    /// links these executables, so linking `name` will collide with them.
+    SiblingLinked {
+        name: String,
+        sibling: String,
+        files: Vec<String>,
+    },
+}
+
+impl Installer {
+    /// Advice for the explicitly requested formulas of `plan`. Looking up a
+    /// newer release is best effort: any failure just leaves the note out.
+    pub async fn install_advice(&self, plan: &InstallPlan) -> Vec<InstallAdvice> {
+        let mut advice: Vec<InstallAdvice> = plan
+            .aliases
+            .iter()
+            .map(|(alias, canonical)| InstallAdvice::Alias {
+                alias: alias.clone(),
+                canonical: canonical.clone(),
+                installed: self.is_installed(canonical),
+            })
+            .collect();
+        advice.extend(
+            plan.tap_choices
+                .iter()
+                .map(|choice| InstallAdvice::TapChoice {
+                    name: choice.name.clone(),
+                    chosen: choice.chosen.clone(),
+                    shadowed: choice.shadowed.clone(),
+                }),
+        );
+        advice.extend(self.sibling_advice(plan));
+
+        let explicit: Vec<_> = plan.items.iter().filter(|item| item.explicit).collect();
+        // One lookup per versioned request, all at once.
+        let newer = futures::future::join_all(explicit.iter().map(|item| async {
+            let base = versioned_base(&item.install_name)?;
+            self.newer_release(&item.install_name, base).await
+        }))
+        .await;
+
+        for (item, newer) in explicit.into_iter().zip(newer) {
+            let name = &item.install_name;
+            match item.formula.lifecycle() {
+                Lifecycle::Active => {}
+                Lifecycle::Deprecated { reason } => advice.push(InstallAdvice::Deprecated {
+                    name: name.clone(),
+                    reason,
+                }),
+                Lifecycle::Disabled { reason } => advice.push(InstallAdvice::Disabled {
+                    name: name.clone(),
+                    reason,
+                }),
+            }
+
+            if let Some((newer, version)) = newer {
+                advice.push(InstallAdvice::NewerRelease {
+                    requested: name.clone(),
+                    newer,
+                    version,
+                });
+            }
+        }
+        advice
+    }
+
+    /// Requested formulas that share a base with an installed keg (`node`
+    /// and `node@22`), with the executables that keg links. Kegs without
+    /// linked executables cannot collide and are left out.
+    fn sibling_advice(&self, plan: &InstallPlan) -> Vec<InstallAdvice> {
+        let requested: Vec<&str> = plan
+            .items
+            .iter()
+            .filter(|item| item.explicit && !self.is_installed(&item.install_name))
+            .map(|item| item.install_name.as_str())
+            .collect();
+        if requested.is_empty() {
+            return Vec::new();
+        }
+        let (Ok(installed), Ok(keg_files)) = (self.db.list_installed(), self.db.list_keg_files())
+        else {
+            return Vec::new();
+        };
+
+        let bin_dirs = [self.prefix.join("bin"), self.prefix.join("sbin")];
+        let mut advice = Vec::new();
+        for name in requested {
+            for keg in installed
+                .iter()
+                .filter(|keg| keg.name != name && release_family(&keg.name) == release_family(name))
+            {
+                let files: Vec<String> = keg_files
+                    .iter()
+                    .filter(|record| {
+                        record.name == keg.name
+                            && matches!(record.kind, KegFileKind::Symlink | KegFileKind::Wrapper)
+                    })
+                    .map(|record| Path::new(&record.linked_path))
+                    .filter(|path| bin_dirs.iter().any(|dir| path.starts_with(dir)))
+                    .filter_map(|path| path.strip_prefix(&self.prefix).ok())
+                    .map(|path| path.display().to_string())
+                    .collect();
+                if !files.is_empty() {
+                    advice.push(InstallAdvice::SiblingLinked {
+                        name: name.to_string(),
+                        sibling: keg.name.clone(),
+                        files,
+                    });
+                }
+            }
+        }
+        advice
+    }
+
+    /// `base` is usually a formula of its own (`node`), but for some it is
+    /// only an alias of the newest versioned formula (`python`).
+    async fn newer_release(&self, requested: &str, base: &str) -> Option<(String, String)> {
+        let formula = match self.api_client.get_formula(base).await {
+            Ok(formula) => formula,
+            Err(Error::MissingFormula { .. }) => {
+                let canonical = self.api_client.resolve_alias(base).await.ok()??;
+                if canonical == requested {
+                    return None;
+                }
+                self.api_client.get_formula(&canonical).await.ok()?
+            }
+            Err(_) => return None,
+        };
+
+        (formula.name != requested && is_newer_release_line(requested, &formula.versions.stable))
+            .then_some((formula.name, formula.versions.stable))
+    }
+}
+
+/// The unversioned name every release line of a formula shares.
+fn release_family(name: &str) -> &str {
+    versioned_base(name).unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::installer::install::test_support::*;
+
+    #[tokio::test]
+    async fn advice_covers_newer_releases_and_upstream_status() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "node@18", "18.20.4", &[], None).await;
+        mount_formula(&mock_server, "node", "22.9.0", &[], None).await;
+        mount_formula(&mock_server, "python@3.11", "3.11.10", &[], None).await;
+        mount_formula(&mock_server, "python@3.13", "3.13.0", &[], None).await;
+        let sha256 = "0".repeat(64);
+        let mut oldtool = formula_json(&mock_server, "oldtool", "1.0", &[], &sha256);
+        oldtool["deprecated"] = true.into();
+        oldtool["deprecation_reason"] = "unmaintained".into();
+        mount_metadata(&mock_server, &oldtool).await;
+        let mut deadtool = formula_json(&mock_server, "deadtool", "1.0", &[], &sha256);
+        deadtool["disabled"] = true.into();
+        mount_metadata(&mock_server, &deadtool).await;
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(
+                    r#"[{"name": "python@3.13", "aliases": ["python", "python3"]}]"#,
+                ),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let installer = test_installer(&mock_server, &tmp);
+        let names: Vec<String> = ["node@18", "python@3.11", "oldtool", "deadtool"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let plan = installer.plan(&names).await.unwrap();
+
+        let mut advice = installer.install_advice(&plan).await;
+        advice.sort_by_key(|a| format!("{a:?}"));
+        assert_eq!(
+            advice,
+            [
+                InstallAdvice::Deprecated {
+                    name: "oldtool".to_string(),
+                    reason: Some("unmaintained".to_string()),
+                },
+                InstallAdvice::Disabled {
+                    name: "deadtool".to_string(),
+                    reason: None,
+                },
+                InstallAdvice::NewerRelease {
+                    requested: "node@18".to_string(),
+                    newer: "node".to_string(),
+                    version: "22.9.0".to_string(),
+                },
+                InstallAdvice::NewerRelease {
+                    requested: "python@3.11".to_string(),
+                    newer: "python@3.13".to_string(),
+                    version: "3.13.0".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn current_release_line_gets_no_advice() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "node@22", "22.9.0", &[], None).await;
+        mount_formula(&mock_server, "node", "22.10.0", &[], None).await;
+
+        let installer = test_installer(&mock_server, &tmp);
+        let plan = installer.plan(&["node@22".to_string()]).await.unwrap();
+
+        assert!(installer.install_advice(&plan).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn alias_of_installed_formula_is_not_planned_again() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "go", "1.24.0", &[], None).await;
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"[{"name": "go", "aliases": ["golang"]}]"#),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let mut installer = test_installer(&mock_server, &tmp);
+        let names = vec!["golang".to_string()];
+
+        let plan = installer.plan(&names).await.unwrap();
+        assert_eq!(plan.items.len(), 1);
+        assert_eq!(plan.items[0].install_name, "go");
+        assert!(plan.items[0].explicit);
+        assert_eq!(
+            installer.install_advice(&plan).await,
+            [InstallAdvice::Alias {
+                alias: "golang".to_string(),
+                canonical: "go".to_string(),
+                installed: false,
+            }]
+        );
+
+        let tx = installer.db.transaction().unwrap();
+        tx.record_install("go", "1.24.0", "abc").unwrap();
+        tx.commit().unwrap();
+
+        let plan = installer.plan(&names).await.unwrap();
+        assert!(plan.items.is_empty());
+        assert_eq!(
+            installer.install_advice(&plan).await,
+            [InstallAdvice::Alias {
+                alias: "golang".to_string(),
+                canonical: "go".to_string(),
+                installed: true,
+            }]
+        );
+
+        let isolated = installer.plan_isolated(&names, false).await.unwrap();
+        assert!(isolated.plan.items.is_empty());
+        assert!(isolated.not_planned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn installed_release_line_with_linked_executables_is_flagged() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_formula(&mock_server, "node", "22.10.0", &[], None).await;
+
+        let mut installer = test_installer(&mock_server, &tmp);
+        let prefix = tmp.path().join("homebrew");
+        let keg = tmp.path().join("zerobrew/Cellar/node@22/22.9.0");
+        let tx = installer.db.transaction().unwrap();
+        tx.record_install("node@22", "22.9.0", "abc").unwrap();
+        for (linked, kind) in [
+            ("bin/node", KegFileKind::Symlink),
+            ("bin/npm", KegFileKind::Symlink),
+            ("share/man/man1/node.1", KegFileKind::Symlink),
+        ] {
+            tx.record_linked_file(
+                "node@22",
+                "22.9.0",
+                &prefix.join(linked).to_string_lossy(),
+                &keg.join(linked).to_string_lossy(),
+                None,
+                kind,
+            )
+            .unwrap();
+        }
+        tx.commit().unwrap();
+
+        let plan = installer.plan(&["node".to_string()]).await.unwrap();
+        assert_eq!(
+            installer.install_advice(&plan).await,
+            [InstallAdvice::SiblingLinked {
+                name: "node".to_string(),
+                sibling: "node@22".to_string(),
+                files: vec!["bin/node".to_string(), "bin/npm".to_string()],
+            }]
+        );
+    }
+}
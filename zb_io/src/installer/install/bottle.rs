@@ -1,14 +1,15 @@
 use std::fs;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use tracing::warn;
-use zb_core::{Error, InstallMethod, formula_token};
+use zb_core::{BottleCellar, Error, InstallMethod, formula_token};
 
 use crate::cellar::link::Linker;
 use crate::cellar::materialize::Cellar;
 use crate::installer::cask::resolve_cask;
 use crate::network::download::{DownloadProgressCallback, DownloadRequest, DownloadResult};
-use crate::progress::InstallProgress;
+use crate::progress::{InstallProgress, MaterializeObserver, PatchCounter, Phase};
 
 use super::{Installer, MAX_CORRUPTION_RETRIES, PlannedInstall};
 
@@ -16,40 +17,75 @@ impl Installer {
     pub(super) async fn process_bottle_item(
         &mut self,
         item: &PlannedInstall,
-        download: &DownloadResult,
+        download: Option<&DownloadResult>,
         download_progress: &Option<DownloadProgressCallback>,
         link: bool,
-        report: &impl Fn(InstallProgress),
+        report: &(impl Fn(InstallProgress) + Sync),
     ) -> Result<(), Error> {
         let InstallMethod::Bottle(ref bottle) = item.method else {
             unreachable!()
         };
         let install_name = &item.install_name;
         let formula_name = &item.formula.name;
-        let version = item.formula.effective_version();
+        let formula_version = item.formula.effective_version();
         let store_key = &bottle.sha256;
 
         report(InstallProgress::UnpackStarted {
             name: formula_name.clone(),
         });
 
-        let store_entry = self
-            .extract_with_retry(download, &item.formula, bottle, download_progress.clone())
-            .await?;
+        // No download means the entry was expanded from its archive.
+        let store_entry = match download {
+            Some(download) => {
+                self.extract_with_retry(download, &item.formula, bottle, download_progress.clone())
+                    .await?
+            }
+            None => self.store.path_for(store_key),
+        };
 
-        let keg_path = self
-            .cellar
-            .materialize(formula_name, &version, &store_entry)?;
+        let bottle_cellar = BottleCellar::for_bottle(&item.formula, &bottle.tag);
+        let observer = UnpackObserver::new(formula_name, report);
+        let materialized = self.cellar.materialize_with_observer(
+            formula_name,
+            &formula_version,
+            &store_entry,
+            &bottle_cellar,
+            &observer,
+        )?;
+        let keg_path = materialized.path;
+        // The bottle's directory, which names the keg from here on.
+        let version = materialized.version;
 
         report(InstallProgress::UnpackCompleted {
             name: formula_name.clone(),
         });
 
+        let previous = self.get_installed(install_name).inspect_err(|_| {
+            Self::cleanup_materialized(&self.cellar, formula_name, &version);
+        })?;
+        let previously_explicit = previous.as_ref().is_some_and(|keg| keg.explicit);
+        let previously_linked = previous.as_ref().is_some_and(|keg| {
+            keg.version != version && self.has_links(install_name, &keg.version)
+        });
         let tx = self.db.transaction().inspect_err(|_| {
             Self::cleanup_materialized(&self.cellar, formula_name, &version);
         })?;
 
         tx.record_install(install_name, &version, store_key)
+            .and_then(|()| {
+                if version == formula_version {
+                    Ok(())
+                } else {
+                    tx.record_formula_version(install_name, &formula_version)
+                }
+            })
+            .and_then(|()| match materialized.strategy {
+                Some(strategy) => tx.record_copy_strategy(install_name, strategy),
+                None => Ok(()),
+            })
+            .and_then(|()| {
+                Self::record_install_details(&tx, item, previously_explicit, &self.prefix)
+            })
             .inspect_err(|_| {
                 Self::cleanup_materialized(&self.cellar, formula_name, &version);
             })?;
@@ -58,6 +94,8 @@ impl Installer {
             Self::cleanup_materialized(&self.cellar, formula_name, &version);
         })?;
 
+        self.keep_metadata(install_name, &version, &item.formula);
+        self.unlink_replaced(previous.as_ref(), &version);
         if let Err(e) = self.linker.link_opt(&keg_path) {
             warn!(formula = %install_name, error = %e, "failed to create opt link");
         }
@@ -75,6 +113,17 @@ impl Installer {
                 }
                 Err(e) => {
                     let _ = self.linker.unlink_keg(&keg_path);
+                    if let Some(version) = self.restore_replaced(
+                        install_name,
+                        previous.as_ref(),
+                        previously_linked,
+                        &version,
+                    ) {
+                        report(InstallProgress::LinkRestored {
+                            name: formula_name.clone(),
+                            version,
+                        });
+                    }
                     report(InstallProgress::InstallCompleted {
                         name: formula_name.clone(),
                     });
@@ -93,6 +142,7 @@ impl Installer {
             });
         }
 
+        self.prune_retained_after_install(install_name);
         report(InstallProgress::InstallCompleted {
             name: formula_name.clone(),
         });
@@ -111,10 +161,10 @@ impl Installer {
         let mut last_error = None;
 
         for attempt in 0..MAX_CORRUPTION_RETRIES {
-            match self.store.ensure_entry(&bottle.sha256, &blob_path) {
+            match self.store.ensure_entry(&bottle.sha256, &blob_path).await {
                 Ok(entry) => return Ok(entry),
                 Err(Error::StoreCorruption { message }) => {
-                    self.downloader.remove_blob(&bottle.sha256);
+                    self.remove_blob(&bottle.sha256);
 
                     if attempt + 1 < MAX_CORRUPTION_RETRIES {
                         warn!(
@@ -136,6 +186,7 @@ impl Installer {
                             .await
                         {
                             Ok(new_path) => {
+                                self.index_blob(&bottle.sha256, &formula.name, true);
                                 blob_path = new_path;
                             }
                             Err(e) => {
@@ -167,20 +218,12 @@ impl Installer {
         &mut self,
         name: &str,
         version: &str,
-        linked_files: &[crate::cellar::link::LinkedFile],
+        linked_files: &[crate::cellar::LinkedFile],
     ) {
         if let Ok(tx) = self.db.transaction() {
             let mut ok = true;
             for linked in linked_files {
-                if tx
-                    .record_linked_file(
-                        name,
-                        version,
-                        &linked.link_path.to_string_lossy(),
-                        &linked.target_path.to_string_lossy(),
-                    )
-                    .is_err()
-                {
+                if tx.record_linked(name, version, linked).is_err() {
                     ok = false;
                     break;
                 }
@@ -237,6 +280,7 @@ impl Installer {
                 None,
             )
             .await?;
+        self.index_blob(&cask.sha256, &cask.install_name, false);
 
         let keg_path = self.cellar.keg_path(&cask.install_name, &cask.version);
         let mut cleanup = FailedInstallGuard::new(
@@ -249,7 +293,7 @@ impl Installer {
         );
 
         if crate::extraction::is_archive(&blob_path)? {
-            let extracted = self.store.ensure_entry(&cask.sha256, &blob_path)?;
+            let extracted = self.store.ensure_entry(&cask.sha256, &blob_path).await?;
             stage_cask_binaries(&extracted, &keg_path, &cask)?;
         } else {
             stage_raw_cask_binary(&blob_path, &keg_path, &cask)?;
@@ -263,13 +307,9 @@ impl Installer {
 
         let tx = self.db.transaction()?;
         tx.record_install(&cask.install_name, &cask.version, &cask.sha256)?;
+        tx.claim_prefix(&self.prefix)?;
         for linked in &linked_files {
-            tx.record_linked_file(
-                &cask.install_name,
-                &cask.version,
-                &linked.link_path.to_string_lossy(),
-                &linked.target_path.to_string_lossy(),
-            )?;
+            tx.record_linked(&cask.install_name, &cask.version, linked)?;
         }
         tx.commit()?;
 
@@ -339,6 +379,74 @@ impl Drop for FailedInstallGuard<'_> {
     }
 }
 
+/// Files between [`InstallProgress::UnpackProgress`] events, so kegs with
+/// tens of thousands of files don't flood the progress callback.
+const UNPACK_PROGRESS_INTERVAL: u64 = 256;
+
+/// Files between [`InstallProgress::PatchProgress`] events.
+const PATCH_PROGRESS_INTERVAL: usize = 256;
+
+/// Forwards materialize progress for one formula to the install callback.
+struct UnpackObserver<'a, F> {
+    name: &'a str,
+    report: &'a F,
+    files: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl<'a, F> UnpackObserver<'a, F> {
+    fn new(name: &'a str, report: &'a F) -> Self {
+        Self {
+            name,
+            report,
+            files: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<F: Fn(InstallProgress)> UnpackObserver<'_, F> {
+    fn report_patched(&self, files: usize, total: usize) {
+        (self.report)(InstallProgress::PatchProgress {
+            name: self.name.to_string(),
+            files: files as u64,
+            total: total as u64,
+        });
+    }
+}
+
+impl<F: Fn(InstallProgress) + Sync> MaterializeObserver for UnpackObserver<'_, F> {
+    fn on_file_copied(&self, _path: &Path, bytes: u64) {
+        let files = self.files.fetch_add(1, Ordering::Relaxed) + 1;
+        let bytes = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if files.is_multiple_of(UNPACK_PROGRESS_INTERVAL) {
+            (self.report)(InstallProgress::UnpackProgress {
+                name: self.name.to_string(),
+                files,
+                bytes,
+            });
+        }
+    }
+
+    fn on_phase(&self, phase: Phase) {
+        (self.report)(InstallProgress::UnpackPhase {
+            name: self.name.to_string(),
+            phase,
+        });
+    }
+
+    fn on_patch_started(&self, counter: &PatchCounter) {
+        self.report_patched(0, counter.total());
+    }
+
+    fn on_file_patched(&self, counter: &PatchCounter) {
+        let done = counter.done();
+        if done.is_multiple_of(PATCH_PROGRESS_INTERVAL) || done == counter.total() {
+            self.report_patched(done, counter.total());
+        }
+    }
+}
+
 fn stage_cask_binaries(
     extracted_root: &Path,
     keg_path: &Path,
@@ -484,7 +592,7 @@ mod tests {
         let cellar = Cellar::new(tmp.path()).unwrap();
         let path = dependency_cellar_path(&cellar, "hashicorp/tap/terraform", "1.10.0");
 
-        assert!(path.ends_with("cellar/terraform/1.10.0"));
+        assert!(path.ends_with("Cellar/terraform/1.10.0"));
     }
 
     #[test]
@@ -493,7 +601,7 @@ mod tests {
         let cellar = Cellar::new(tmp.path()).unwrap();
         let path = dependency_cellar_path(&cellar, "openssl@3", "3.3.2");
 
-        assert!(path.ends_with("cellar/openssl@3/3.3.2"));
+        assert!(path.ends_with("Cellar/openssl@3/3.3.2"));
     }
 
     #[test]
@@ -508,10 +616,13 @@ mod tests {
             .unwrap();
         tx.commit().unwrap();
 
-        let keg = db.get_installed("hashicorp/tap/terraform").unwrap();
+        let keg = db
+            .get_installed("hashicorp/tap/terraform")
+            .unwrap()
+            .unwrap();
         let path = dependency_cellar_path(&cellar, &keg.name, &keg.version);
 
-        assert!(path.ends_with("cellar/terraform/1.10.0"));
+        assert!(path.ends_with("Cellar/terraform/1.10.0"));
     }
 
     #[test]
@@ -0,0 +1,634 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use zb_core::{BuildId, ChangeKind, Error, Formula, Lifecycle, formula_token};
+
+use super::plan::FetchedFormulas;
+use super::{InstallPlan, Installer};
+
+/// One Brewfile formula entry and what the combined plan does with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleEntry {
+    pub name: String,
+    pub state: BundleEntryState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleEntryState {
+    /// Not installed; planned.
+    Install,
+    /// Installed at the current version.
+    Present,
+    /// Installed at an older version and left alone.
+    PresentOlder {
+        installed: String,
+        available: String,
+    },
+    /// Installed at an older version and planned for upgrade.
+    Upgrade {
+        installed: String,
+        available: String,
+    },
+    /// Installed at an older version of a formula upstream has disabled,
+    /// and left alone because disabled upgrades weren't accepted.
+    UpgradeDisabled {
+        installed: String,
+        available: String,
+    },
+    /// Installed at an older version and left alone because it is pinned.
+    UpgradePinned {
+        installed: String,
+        available: String,
+    },
+    /// Installed, but the current formula couldn't be fetched, so whether
+    /// it is behind is unknown. Left alone.
+    Unchecked { installed: String, reason: String },
+}
+
+impl BundleEntryState {
+    pub fn is_planned(&self) -> bool {
+        matches!(self, Self::Install | Self::Upgrade { .. })
+    }
+}
+
+/// Every formula entry of a Brewfile resolved into a single plan, so shared
+/// dependencies are fetched and installed once.
+pub struct BundlePlan {
+    pub plan: InstallPlan,
+    pub entries: Vec<BundleEntry>,
+}
+
+impl Installer {
+    /// Resolve all of `names` together. Entries installed at an older
+    /// version than the current formula are upgraded when `upgrade` is set
+    /// and otherwise left alone like any other installed entry; entries
+    /// only behind by a rebuild also need `include_rebuilds`, entries
+    /// upstream has disabled also need `accept_disabled`, and pinned ones
+    /// are never upgraded. Installed entries whose formula can't be fetched
+    /// are left alone and reported as unchecked. Installed dependencies are
+    /// never touched.
+    pub async fn plan_bundle(
+        &self,
+        names: &[String],
+        build_from_source: bool,
+        upgrade: bool,
+        include_rebuilds: bool,
+        accept_disabled: bool,
+    ) -> Result<BundlePlan, Error> {
+        check_versioned_conflicts(names)?;
+
+        let mut installed = BTreeMap::new();
+        for name in names {
+            if let Some(keg) = self.get_installed(name)? {
+                installed.insert(name.clone(), keg);
+            }
+        }
+        let installed_names: Vec<String> = installed.keys().cloned().collect();
+        let mut fetched = if installed_names.is_empty() {
+            FetchedFormulas::default()
+        } else {
+            self.fetch_all_formulas(&installed_names).await
+        };
+
+        let mut entries = Vec::with_capacity(names.len());
+        for name in names {
+            let Some(keg) = installed.get(name) else {
+                entries.push(BundleEntry {
+                    name: name.clone(),
+                    state: BundleEntryState::Install,
+                });
+                continue;
+            };
+            let canonical = fetched.aliases.get(name).unwrap_or(name);
+            let newer = match fetched.formulas.get(canonical) {
+                Some(formula) => Ok(self.newer_build(name, formula, &keg.build())),
+                None => match fetched.errors.iter().position(|(n, _)| n == canonical) {
+                    Some(pos) => Err(fetched.errors.remove(pos).1),
+                    // Nothing installable here, so nothing to upgrade to.
+                    None => Ok(None),
+                },
+            };
+            let state = match newer {
+                Ok(Some(newer))
+                    if upgrade && (include_rebuilds || newer.kind == ChangeKind::Version) =>
+                {
+                    let installed = keg.build().to_string();
+                    let available = newer.available;
+                    if keg.pinned {
+                        BundleEntryState::UpgradePinned {
+                            installed,
+                            available,
+                        }
+                    } else if newer.disabled && !accept_disabled {
+                        BundleEntryState::UpgradeDisabled {
+                            installed,
+                            available,
+                        }
+                    } else {
+                        BundleEntryState::Upgrade {
+                            installed,
+                            available,
+                        }
+                    }
+                }
+                Ok(Some(newer)) => BundleEntryState::PresentOlder {
+                    installed: keg.build().to_string(),
+                    available: newer.available,
+                },
+                Ok(None) => BundleEntryState::Present,
+                Err(e) => BundleEntryState::Unchecked {
+                    installed: keg.build().to_string(),
+                    reason: e.to_string(),
+                },
+            };
+            entries.push(BundleEntry {
+                name: name.clone(),
+                state,
+            });
+        }
+
+        let pending: Vec<String> = entries
+            .iter()
+            .filter(|entry| entry.state.is_planned())
+            .map(|entry| entry.name.clone())
+            .collect();
+
+        let mut plan = if pending.is_empty() {
+            InstallPlan::default()
+        } else {
+            self.plan_with_options(&pending, build_from_source).await?
+        };
+        plan.items.retain(|item| {
+            !self.is_installed(&item.install_name) || pending.contains(&item.install_name)
+        });
+
+        Ok(BundlePlan { plan, entries })
+    }
+
+    /// The build of `formula`, the current one for the installed `name`,
+    /// when it is newer than `installed`.
+    fn newer_build(
+        &self,
+        name: &str,
+        formula: &Formula,
+        installed: &BuildId,
+    ) -> Option<NewerBuild> {
+        self.note_upstream_status(name, formula);
+        let available = BuildId::bottle(formula);
+        (available.compare(installed) == Ordering::Greater).then(|| NewerBuild {
+            kind: ChangeKind::between(&installed.version, &available.version),
+            available: available.to_string(),
+            disabled: matches!(formula.lifecycle(), Lifecycle::Disabled { .. }),
+        })
+    }
+}
+
+struct NewerBuild {
+    available: String,
+    kind: ChangeKind,
+    disabled: bool,
+}
+
+/// Two versioned variants of one formula (`python@3.11` and `python@3.12`)
+/// would fight over the same links, so a Brewfile may list only one.
+fn check_versioned_conflicts(names: &[String]) -> Result<(), Error> {
+    let mut variants: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for name in names {
+        let token = formula_token(name);
+        if let Some((base, _)) = token.split_once('@') {
+            let listed = variants.entry(base).or_default();
+            if !listed.contains(&name.as_str()) {
+                listed.push(name);
+            }
+        }
+    }
+
+    let conflicts: Vec<String> = variants
+        .into_iter()
+        .filter(|(_, listed)| listed.len() > 1)
+        .map(|(base, listed)| format!("{base} ({})", listed.join(", ")))
+        .collect();
+
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument {
+            message: format!(
+                "Brewfile lists more than one version of: {}",
+                conflicts.join("; ")
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use zb_core::revision_of;
+
+    use super::*;
+    use crate::installer::install::test_support::*;
+    use crate::progress::{InstallProgress, ProgressCallback};
+
+    #[tokio::test]
+    async fn bundle_plan_downloads_shared_dependency_once() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_bottled(&mock_server, "libshared", &[]).await;
+        mount_bottled(&mock_server, "app1", &["libshared"]).await;
+        mount_bottled(&mock_server, "app2", &["libshared"]).await;
+        mount_bottled(&mock_server, "present", &[]).await;
+
+        let mut installer = test_installer(&mock_server, &tmp);
+        installer
+            .install(&["present".to_string()], true)
+            .await
+            .unwrap();
+
+        let brewfile: Vec<String> = ["app1", "app2", "present"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let bundle = installer
+            .plan_bundle(&brewfile, false, false, false, false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            bundle
+                .entries
+                .iter()
+                .map(|e| (e.name.as_str(), e.state.clone()))
+                .collect::<Vec<_>>(),
+            [
+                ("app1", BundleEntryState::Install),
+                ("app2", BundleEntryState::Install),
+                ("present", BundleEntryState::Present)
+            ]
+        );
+        let mut planned: Vec<&str> = bundle
+            .plan
+            .items
+            .iter()
+            .map(|item| item.install_name.as_str())
+            .collect();
+        planned.sort();
+        assert_eq!(planned, ["app1", "app2", "libshared"]);
+
+        // Racing connections may issue several requests for one blob, so
+        // count downloads the way the progress UI sees them.
+        let started: Arc<Mutex<Vec<String>>> = Arc::default();
+        let recorder = started.clone();
+        let progress: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
+            if let InstallProgress::DownloadStarted { name, .. } = event {
+                recorder.lock().unwrap().push(name);
+            }
+        }));
+
+        let result = installer
+            .execute_with_progress(bundle.plan, true, Some(progress))
+            .await
+            .unwrap();
+        assert_eq!(result.installed, 3);
+        assert!(installer.is_installed("libshared"));
+
+        let mut started = started.lock().unwrap().clone();
+        started.sort();
+        assert_eq!(started, ["app1", "app2", "libshared"]);
+    }
+
+    /// Publish `version` of `name`, where a `_N` suffix is the revision.
+    async fn release(server: &MockServer, name: &str, version: &str) {
+        release_rebuild(server, name, version, 0).await;
+    }
+
+    async fn release_rebuild(server: &MockServer, name: &str, version: &str, rebuild: u32) {
+        release_with(server, name, version, rebuild, serde_json::json!({})).await;
+    }
+
+    /// A release whose metadata also carries `extra` fields.
+    async fn release_with(
+        server: &MockServer,
+        name: &str,
+        version: &str,
+        rebuild: u32,
+        extra: serde_json::Value,
+    ) {
+        let tag = get_test_bottle_tag();
+        let revision = revision_of(version);
+        let stable = version
+            .strip_suffix(&format!("_{revision}"))
+            .unwrap_or(version);
+        let bottle = versioned_bottle(name, version, &[(&format!("bin/{name}"), b"#!/bin/sh\n")]);
+        let bottle_path = format!("/bottles/{name}-{version}.{rebuild}.tar.gz");
+        let mut json = serde_json::json!({
+            "name": name,
+            "versions": { "stable": stable },
+            "revision": revision,
+            "dependencies": [],
+            "bottle": { "stable": { "rebuild": rebuild, "files": { tag: {
+                "url": format!("{}{bottle_path}", server.uri()),
+                "sha256": sha256_hex(&bottle),
+            }}}}
+        });
+        if let (Some(json), serde_json::Value::Object(extra)) = (json.as_object_mut(), extra) {
+            json.extend(extra);
+        }
+        Mock::given(method("GET"))
+            .and(path(format!("/formula/{name}.json")))
+            .respond_with(ResponseTemplate::new(200).set_body_string(json.to_string()))
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(bottle_path))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn older_entries_are_upgraded_only_when_asked() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        release(&server, "stale", "1.0.0").await;
+        release(&server, "current", "1.0.0").await;
+        let names = ["stale".to_string(), "current".to_string()];
+        installer.install(&names, true).await.unwrap();
+
+        server.reset().await;
+        release(&server, "stale", "1.2.0").await;
+        release(&server, "current", "1.0.0").await;
+
+        let kept = installer
+            .plan_bundle(&names, false, false, false, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            kept.entries.iter().map(|e| &e.state).collect::<Vec<_>>(),
+            [
+                &BundleEntryState::PresentOlder {
+                    installed: "1.0.0".to_string(),
+                    available: "1.2.0".to_string(),
+                },
+                &BundleEntryState::Present,
+            ]
+        );
+        assert!(kept.plan.items.is_empty());
+
+        let upgrade = installer
+            .plan_bundle(&names, false, true, false, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            upgrade.entries[0].state,
+            BundleEntryState::Upgrade {
+                installed: "1.0.0".to_string(),
+                available: "1.2.0".to_string(),
+            }
+        );
+        assert_eq!(upgrade.entries[1].state, BundleEntryState::Present);
+        let planned: Vec<&str> = upgrade
+            .plan
+            .items
+            .iter()
+            .map(|item| item.install_name.as_str())
+            .collect();
+        assert_eq!(planned, ["stale"]);
+
+        installer.execute(upgrade.plan, true).await.unwrap();
+        assert_eq!(
+            installer.get_installed("stale").unwrap().unwrap().version,
+            "1.2.0"
+        );
+        assert_eq!(
+            installer.get_installed("current").unwrap().unwrap().version,
+            "1.0.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn entries_that_cannot_be_fetched_are_unchecked() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        release(&server, "gone", "1.0.0").await;
+        release(&server, "stale", "1.0.0").await;
+        let names = ["gone".to_string(), "stale".to_string()];
+        installer.install(&names, true).await.unwrap();
+
+        server.reset().await;
+        release(&server, "stale", "1.2.0").await;
+
+        let bundle = installer
+            .plan_bundle(&names, false, true, false, false)
+            .await
+            .unwrap();
+        let BundleEntryState::Unchecked { installed, reason } = &bundle.entries[0].state else {
+            panic!("{:?}", bundle.entries[0].state);
+        };
+        assert_eq!(installed, "1.0.0");
+        assert!(reason.contains("gone"), "{reason}");
+        assert!(matches!(
+            bundle.entries[1].state,
+            BundleEntryState::Upgrade { .. }
+        ));
+        let planned: Vec<&str> = bundle
+            .plan
+            .items
+            .iter()
+            .map(|item| item.install_name.as_str())
+            .collect();
+        assert_eq!(planned, ["stale"]);
+    }
+
+    #[tokio::test]
+    async fn rebuild_only_upgrades_need_to_be_included() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        release(&server, "rebuilt", "1.0.0").await;
+        let names = ["rebuilt".to_string()];
+        installer.install(&names, true).await.unwrap();
+
+        server.reset().await;
+        release(&server, "rebuilt", "1.0.0_1").await;
+
+        let versions_only = installer
+            .plan_bundle(&names, false, true, false, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            versions_only.entries[0].state,
+            BundleEntryState::PresentOlder {
+                installed: "1.0.0".to_string(),
+                available: "1.0.0_1".to_string(),
+            }
+        );
+        assert!(versions_only.plan.items.is_empty());
+
+        let rebuilds = installer
+            .plan_bundle(&names, false, true, true, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            rebuilds.entries[0].state,
+            BundleEntryState::Upgrade {
+                installed: "1.0.0".to_string(),
+                available: "1.0.0_1".to_string(),
+            }
+        );
+
+        // A new bottle of the installed revision is a rebuild too.
+        installer.install(&names, true).await.unwrap();
+        let keg = installer.get_installed("rebuilt").unwrap().unwrap();
+        assert_eq!((keg.revision, keg.rebuild), (1, Some(0)));
+        server.reset().await;
+        release_rebuild(&server, "rebuilt", "1.0.0_1", 1).await;
+
+        let current = installer
+            .plan_bundle(&names, false, true, false, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            current.entries[0].state,
+            BundleEntryState::PresentOlder {
+                installed: "1.0.0_1".to_string(),
+                available: "1.0.0_1 (rebuild 1)".to_string(),
+            }
+        );
+        let rebuilds = installer
+            .plan_bundle(&names, false, true, true, false)
+            .await
+            .unwrap();
+        assert!(rebuilds.entries[0].state.is_planned());
+    }
+
+    #[tokio::test]
+    async fn installed_set_follows_installs_made_by_the_same_installer() {
+        let server = MockServer::start().await;
+        mount_bottled(&server, "early", &[]).await;
+        mount_bottled(&server, "late", &["early"]).await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        installer
+            .install(&["early".to_string()], true)
+            .await
+            .unwrap();
+
+        let before = installer.installed_set().unwrap();
+        assert!(std::rc::Rc::ptr_eq(
+            &before,
+            &installer.installed_set().unwrap()
+        ));
+        assert_eq!(before.version("early"), Some("1.0.0"));
+        assert!(!before.contains("late"));
+
+        let names = ["early".to_string(), "late".to_string()];
+        let bundle = installer
+            .plan_bundle(&names, false, false, false, false)
+            .await
+            .unwrap();
+        let planned: Vec<&str> = bundle
+            .plan
+            .items
+            .iter()
+            .map(|item| item.install_name.as_str())
+            .collect();
+        assert_eq!(planned, ["late"]);
+        installer.execute(bundle.plan, true).await.unwrap();
+
+        let after = installer.installed_set().unwrap();
+        assert!(!std::rc::Rc::ptr_eq(&before, &after));
+        assert!(after.contains("late"));
+        assert!(installer.is_installed("late"));
+        assert_eq!(
+            installer.get_installed("late").unwrap().unwrap().version,
+            "1.0.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_upgrades_need_to_be_accepted() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        release(&server, "sunset", "1.0.0").await;
+        let names = ["sunset".to_string()];
+        installer.install(&names, true).await.unwrap();
+
+        server.reset().await;
+        let flags = serde_json::json!({ "disabled": true, "disable_date": "2026-01-01" });
+        release_with(&server, "sunset", "1.1.0", 0, flags).await;
+
+        let held = installer
+            .plan_bundle(&names, false, true, false, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            held.entries[0].state,
+            BundleEntryState::UpgradeDisabled {
+                installed: "1.0.0".to_string(),
+                available: "1.1.0".to_string(),
+            }
+        );
+        assert!(held.plan.items.is_empty());
+        let statuses = installer.upstream_statuses().unwrap();
+        assert_eq!(statuses[0].lifecycle, Lifecycle::Disabled { reason: None });
+        assert_eq!(statuses[0].since.as_deref(), Some("2026-01-01"));
+
+        let accepted = installer
+            .plan_bundle(&names, false, true, false, true)
+            .await
+            .unwrap();
+        assert!(accepted.entries[0].state.is_planned());
+    }
+
+    #[tokio::test]
+    async fn pinned_entries_are_not_upgraded() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        release(&server, "jq", "1.0.0").await;
+        let names = ["jq".to_string()];
+        installer.install(&names, true).await.unwrap();
+        installer.set_pinned("jq", true).unwrap();
+
+        server.reset().await;
+        release(&server, "jq", "1.1.0").await;
+
+        let bundle = installer
+            .plan_bundle(&names, false, true, true, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            bundle.entries[0].state,
+            BundleEntryState::UpgradePinned {
+                installed: "1.0.0".to_string(),
+                available: "1.1.0".to_string(),
+            }
+        );
+        assert!(bundle.plan.items.is_empty());
+    }
+
+    #[test]
+    fn versioned_variants_of_one_formula_conflict() {
+        let names: Vec<String> = ["python@3.11", "jq", "python@3.12", "openssl@3"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+
+        let err = check_versioned_conflicts(&names).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("python (python@3.11, python@3.12)")
+        );
+        assert!(!err.to_string().contains("openssl"));
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use zb_core::{Error, formula_token};
+
+use super::{ExecuteResult, Installer};
+
+/// A recorded dependency of an installed formula that isn't usable: it has
+/// no install record, or its recorded keg is gone from the Cellar.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MissingDependency {
+    /// The installed formula that depends on it.
+    pub formula: String,
+    pub dependency: String,
+    /// The version recorded as installed whose keg was removed; `None` when
+    /// the dependency isn't installed at all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// The runtime dependencies of one formula, followed through its metadata
+/// whether it is installed or not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyGraph {
+    /// The formula asked about, with an alias replaced by its formula.
+    pub root: String,
+    /// Every formula reached, `root` included, with the dependencies its
+    /// metadata lists in that order. Dependencies with nothing installable
+    /// for this platform are left out, as installing `root` would.
+    pub edges: BTreeMap<String, Vec<String>>,
+    /// The formulas reached that are installed, by name, with the version
+    /// recorded and whether its keg is still in the Cellar.
+    pub installed: BTreeMap<String, (String, bool)>,
+}
+
+impl DependencyGraph {
+    /// Every formula `root` depends on, directly or not, sorted. A cycle
+    /// back to `root` doesn't list it.
+    pub fn closure(&self) -> Vec<&str> {
+        let mut seen: BTreeSet<&str> = BTreeSet::new();
+        let mut pending: Vec<&str> = self.dependencies_of(&self.root).collect();
+        while let Some(name) = pending.pop() {
+            if name != self.root && seen.insert(name) {
+                pending.extend(self.dependencies_of(name));
+            }
+        }
+        seen.into_iter().collect()
+    }
+
+    pub fn dependencies_of(&self, name: &str) -> impl Iterator<Item = &str> {
+        self.edges
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// Installed, with its keg in the Cellar.
+    pub fn is_installed(&self, name: &str) -> bool {
+        self.installed
+            .get(name)
+            .is_some_and(|(_, present)| *present)
+    }
+
+    /// The dependencies in [`Self::closure`] that aren't installed or whose
+    /// keg is gone, each with the first formula, by name, that needs it.
+    pub fn missing(&self) -> Vec<MissingDependency> {
+        self.closure()
+            .into_iter()
+            .filter(|name| !self.is_installed(name))
+            .map(|name| MissingDependency {
+                formula: self
+                    .edges
+                    .iter()
+                    .find(|(_, deps)| deps.iter().any(|dep| dep == name))
+                    .map(|(formula, _)| formula.clone())
+                    .unwrap_or_else(|| self.root.clone()),
+                dependency: name.to_string(),
+                version: self.installed.get(name).map(|(version, _)| version.clone()),
+            })
+            .collect()
+    }
+}
+
+impl Installer {
+    /// Follow the runtime dependencies of `name` through formula metadata,
+    /// whether it is installed or not. Fails when `name` or any formula it
+    /// reaches can't be fetched.
+    pub async fn dependency_graph(&self, name: &str) -> Result<DependencyGraph, Error> {
+        let resolution = self.resolve_taps(&[name.to_string()]).await?;
+        let requested = resolution.names[0].clone();
+        let mut fetched = self.fetch_all_formulas(&resolution.names).await;
+        if !fetched.errors.is_empty() {
+            return Err(fetched.errors.swap_remove(0).1);
+        }
+        let root = fetched
+            .aliases
+            .get(&requested)
+            .unwrap_or(&requested)
+            .clone();
+        if !fetched.formulas.contains_key(&root) {
+            return Err(Error::UnsupportedBottle { name: root });
+        }
+
+        let edges: BTreeMap<String, Vec<String>> = fetched
+            .formulas
+            .iter()
+            .map(|(name, formula)| {
+                let deps = formula
+                    .dependencies
+                    .iter()
+                    .filter(|dep| fetched.formulas.contains_key(*dep))
+                    .cloned()
+                    .collect();
+                (name.clone(), deps)
+            })
+            .collect();
+        let installed_set = self.installed_set()?;
+        let installed = edges
+            .keys()
+            .filter_map(|name| {
+                let keg = installed_set.get(name)?;
+                let present = self
+                    .cellar
+                    .keg_path(formula_token(name), &keg.version)
+                    .is_dir();
+                Some((name.clone(), (keg.version.clone(), present)))
+            })
+            .collect();
+
+        Ok(DependencyGraph {
+            root,
+            edges,
+            installed,
+        })
+    }
+
+    /// The dependencies recorded when `name` was installed, sorted.
+    pub fn dependencies(&self, name: &str) -> Result<Vec<String>, Error> {
+        if self.db.get_installed(name)?.is_none() {
+            return Err(Error::NotInstalled {
+                name: name.to_string(),
+            });
+        }
+        Ok(self
+            .db
+            .list_dependency_edges()?
+            .into_iter()
+            .filter(|(formula, _)| formula == name)
+            .map(|(_, dependency)| dependency)
+            .collect())
+    }
+
+    /// Walk the recorded dependency edges of every installed formula, or
+    /// just of `name`, and return those that point at nothing usable. Such
+    /// gaps are left by deleting kegs by hand or by interrupted cascades,
+    /// and otherwise only show up as load errors at run time.
+    pub fn missing_dependencies(
+        &self,
+        name: Option<&str>,
+    ) -> Result<Vec<MissingDependency>, Error> {
+        let installed: HashMap<String, String> = self
+            .db
+            .list_installed()?
+            .into_iter()
+            .map(|keg| (keg.name, keg.version))
+            .collect();
+        if let Some(name) = name
+            && !installed.contains_key(name)
+        {
+            return Err(Error::NotInstalled {
+                name: name.to_string(),
+            });
+        }
+
+        let mut missing = Vec::new();
+        for (formula, dependency) in self.db.list_dependency_edges()? {
+            if name.is_some_and(|name| name != formula) || !installed.contains_key(&formula) {
+                continue;
+            }
+            let version = match installed.get(&dependency) {
+                None => None,
+                Some(version)
+                    if !self
+                        .cellar
+                        .keg_path(formula_token(&dependency), version)
+                        .is_dir() =>
+                {
+                    Some(version.clone())
+                }
+                Some(_) => continue,
+            };
+            missing.push(MissingDependency {
+                formula,
+                dependency,
+                version,
+            });
+        }
+        Ok(missing)
+    }
+
+    /// Install the dependencies in `missing` again, linked and recorded as
+    /// dependencies rather than as explicit installs.
+    pub async fn install_missing_dependencies(
+        &mut self,
+        missing: &[MissingDependency],
+    ) -> Result<ExecuteResult, Error> {
+        let mut names: Vec<String> = missing.iter().map(|m| m.dependency.clone()).collect();
+        names.sort();
+        names.dedup();
+
+        let mut plan = self.plan(&names).await?;
+        for item in &mut plan.items {
+            item.explicit = false;
+        }
+        self.execute(plan, true).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+    use wiremock::MockServer;
+
+    use super::*;
+    use crate::installer::install::test_support::*;
+    use crate::network::api::ApiClient;
+
+    #[test]
+    fn reports_dependencies_without_a_record_or_a_keg() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(ApiClient::new(), &root, &prefix, root.join("Cellar"));
+
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("git", "2.0", "k1").unwrap();
+            tx.record_dependencies(
+                "git",
+                &[
+                    "gettext".to_string(),
+                    "pcre2".to_string(),
+                    "openssl@3".to_string(),
+                ],
+            )
+            .unwrap();
+            tx.record_install("gettext", "0.22", "k2").unwrap();
+            tx.record_install("openssl@3", "3.3.0", "k3").unwrap();
+            tx.record_install("curl", "8.0", "k4").unwrap();
+            tx.record_dependencies("curl", &["openssl@3".to_string()])
+                .unwrap();
+            // Edges left by a formula that is no longer installed.
+            tx.record_dependencies("gone", &["zlib".to_string()])
+                .unwrap();
+            tx.commit().unwrap();
+        }
+        for (name, version) in [("git", "2.0"), ("gettext", "0.22"), ("curl", "8.0")] {
+            fs::create_dir_all(installer.keg_path(name, version)).unwrap();
+        }
+
+        let missing = installer.missing_dependencies(None).unwrap();
+        assert_eq!(
+            missing,
+            vec![
+                MissingDependency {
+                    formula: "curl".to_string(),
+                    dependency: "openssl@3".to_string(),
+                    version: Some("3.3.0".to_string()),
+                },
+                MissingDependency {
+                    formula: "git".to_string(),
+                    dependency: "openssl@3".to_string(),
+                    version: Some("3.3.0".to_string()),
+                },
+                MissingDependency {
+                    formula: "git".to_string(),
+                    dependency: "pcre2".to_string(),
+                    version: None,
+                },
+            ]
+        );
+
+        let for_curl = installer.missing_dependencies(Some("curl")).unwrap();
+        assert_eq!(for_curl, missing[..1]);
+        assert!(matches!(
+            installer.missing_dependencies(Some("gone")),
+            Err(Error::NotInstalled { .. })
+        ));
+        assert_eq!(
+            installer.dependencies("git").unwrap(),
+            ["gettext", "openssl@3", "pcre2"]
+        );
+
+        fs::create_dir_all(installer.keg_path("openssl@3", "3.3.0")).unwrap();
+        assert_eq!(installer.missing_dependencies(Some("curl")).unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn dependency_graph_follows_metadata_through_cycles() {
+        let server = MockServer::start().await;
+        mount_formula(&server, "app", "1.0", &["lib", "zlib"], None).await;
+        mount_formula(&server, "lib", "1.0", &["util"], None).await;
+        mount_formula(&server, "util", "1.0", &["lib", "app"], None).await;
+        mount_formula(&server, "zlib", "1.0", &[], None).await;
+
+        let tmp = TempDir::new().unwrap();
+        let _root = tmp.path().join("zerobrew");
+        let mut installer = test_installer(&server, &tmp);
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("zlib", "1.0", "k1").unwrap();
+            tx.record_install("lib", "0.9", "k2").unwrap();
+            tx.commit().unwrap();
+        }
+        fs::create_dir_all(installer.keg_path("zlib", "1.0")).unwrap();
+
+        let graph = installer.dependency_graph("app").await.unwrap();
+        assert_eq!(graph.root, "app");
+        assert_eq!(graph.closure(), ["lib", "util", "zlib"]);
+        assert!(graph.is_installed("zlib"));
+        assert!(!graph.is_installed("lib"));
+        assert_eq!(
+            graph.missing(),
+            vec![
+                MissingDependency {
+                    formula: "app".to_string(),
+                    dependency: "lib".to_string(),
+                    version: Some("0.9".to_string()),
+                },
+                MissingDependency {
+                    formula: "lib".to_string(),
+                    dependency: "util".to_string(),
+                    version: None,
+                },
+            ]
+        );
+
+        let graph = installer.dependency_graph("util").await.unwrap();
+        assert_eq!(graph.closure(), ["app", "lib", "zlib"]);
+    }
+}
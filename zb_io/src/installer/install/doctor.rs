@@ -1,12 +1,16 @@
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use zb_core::{Error, formula_token};
 
-use crate::storage::db::StoreRef;
+use crate::extraction::patch::MissingLibraries;
+use crate::storage::db::{DriftedKeg, KegFileKind, StoreRef};
 
 use super::Installer;
+use super::deps::MissingDependency;
 
+/// Every list is sorted, kegs by name then version, so two runs over the
+/// same state report identically.
 #[derive(Debug, Default)]
 pub struct DiagnosticReport {
     pub orphaned_cellar_kegs: Vec<OrphanedKeg>,
@@ -14,6 +18,23 @@ pub struct DiagnosticReport {
     pub orphaned_store_entries: Vec<String>,
     pub stale_store_refs: Vec<StaleStoreRef>,
     pub broken_symlinks: Vec<PathBuf>,
+    /// Links under the prefix that point back into themselves. They are
+    /// reported but never removed, since zerobrew did not make them.
+    pub symlink_loops: Vec<PathBuf>,
+    /// Kegs patched for another prefix. Only `zb relocate` (or going back
+    /// to that prefix) fixes them, so repair leaves them alone.
+    pub prefix_drift: Vec<DriftedKeg>,
+    /// Recorded dependencies that aren't installed or lost their keg.
+    /// Repair doesn't download anything; `zb deps --missing --fix` does.
+    pub missing_dependencies: Vec<MissingDependency>,
+    /// Mach-O files in installed kegs whose code signature is stale or
+    /// missing, which macOS kills on launch. Repair re-signs them. Always
+    /// empty on other platforms.
+    pub invalid_signatures: Vec<PathBuf>,
+    /// ELF files in installed kegs needing a shared library the system
+    /// doesn't have. Repair can't install it. Always empty on other
+    /// platforms.
+    pub missing_libraries: Vec<MissingLibraries>,
     pub stale_keg_file_records: usize,
 }
 
@@ -46,6 +67,11 @@ impl DiagnosticReport {
             && self.orphaned_store_entries.is_empty()
             && self.stale_store_refs.is_empty()
             && self.broken_symlinks.is_empty()
+            && self.symlink_loops.is_empty()
+            && self.prefix_drift.is_empty()
+            && self.missing_dependencies.is_empty()
+            && self.invalid_signatures.is_empty()
+            && self.missing_libraries.is_empty()
             && self.stale_keg_file_records == 0
     }
 }
@@ -55,6 +81,7 @@ impl Installer {
         let mut report = DiagnosticReport::default();
 
         let installed = self.db.list_installed()?;
+        let retained = self.db.list_retained(None)?;
         let db_store_refs = self.db.list_store_refs()?;
         let disk_store_entries = self.store.list_entries()?;
         let cellar_kegs = self.cellar.list_kegs()?;
@@ -93,8 +120,12 @@ impl Installer {
 
         let store_keys_used: HashMap<&str, i64> = {
             let mut map = HashMap::new();
-            for keg in &installed {
-                *map.entry(keg.store_key.as_str()).or_insert(0) += 1;
+            let store_keys = installed
+                .iter()
+                .map(|keg| keg.store_key.as_str())
+                .chain(retained.iter().map(|keg| keg.store_key.as_str()));
+            for store_key in store_keys {
+                *map.entry(store_key).or_insert(0) += 1;
             }
             map
         };
@@ -112,7 +143,9 @@ impl Installer {
                 .get(store_ref.store_key.as_str())
                 .copied()
                 .unwrap_or(0);
-            let on_disk = disk_store_set.contains(store_ref.store_key.as_str());
+            // An archived entry is on disk in compressed form.
+            let on_disk = disk_store_set.contains(store_ref.store_key.as_str())
+                || self.store.is_archived(&store_ref.store_key);
 
             if store_ref.refcount != actual_count || !on_disk {
                 report.stale_store_refs.push(StaleStoreRef {
@@ -124,6 +157,19 @@ impl Installer {
             }
         }
 
+        // A key kegs use but with no row at all would be counted from zero
+        // by the next install and collected while still in use.
+        for store_key in store_keys_used.keys() {
+            if !store_keys_in_db.contains(store_key) {
+                report.stale_store_refs.push(StaleStoreRef {
+                    store_key: (*store_key).to_owned(),
+                    refcount: 0,
+                    on_disk: disk_store_set.contains(store_key),
+                    referenced_by_any_keg: true,
+                });
+            }
+        }
+
         let keg_files = self.db.list_keg_files()?;
         let installed_set: HashSet<(&str, &str)> = installed
             .iter()
@@ -144,17 +190,63 @@ impl Installer {
         }
 
         for record in &keg_files {
-            if !installed_set.contains(&(record.name.as_str(), record.version.as_str())) {
+            // Configs belong to the user once written, and directories and
+            // wrappers are real files, so only symlinks can dangle.
+            if record.kind != KegFileKind::Symlink
+                || !installed_set.contains(&(record.name.as_str(), record.version.as_str()))
+            {
                 continue;
             }
+            // A link that now resolves somewhere else still dangles if the
+            // keg file it was recorded for is gone.
             let link = PathBuf::from(&record.linked_path);
-            if link.is_symlink() && !link.exists() && !report.broken_symlinks.contains(&link) {
+            let dangling = !link.exists() || !Path::new(&record.target_path).exists();
+            if link.is_symlink() && dangling && !report.broken_symlinks.contains(&link) {
                 report.broken_symlinks.push(link);
             }
         }
 
+        report.symlink_loops = self.linker.symlink_loops();
+        report.prefix_drift = self.prefix_drift()?;
+        report.missing_dependencies = self.missing_dependencies(None)?;
+        #[cfg(all(target_os = "macos", feature = "patch-macho"))]
+        for keg in &installed {
+            let keg_path = self.cellar.keg_path(formula_token(&keg.name), &keg.version);
+            if keg_path.is_dir() {
+                report
+                    .invalid_signatures
+                    .extend(crate::extraction::patch::invalid_signatures(&keg_path));
+            }
+        }
+        #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+        {
+            let resolver = self.cellar.libraries()?;
+            for keg in &installed {
+                let keg_path = self.cellar.keg_path(formula_token(&keg.name), &keg.version);
+                if keg_path.is_dir() {
+                    let scan = crate::extraction::patch::scan::KegScan::new(&keg_path);
+                    report.missing_libraries.extend(resolver.missing(&scan.elf));
+                }
+            }
+        }
         report.stale_keg_file_records = self.db.count_stale_keg_file_records()?;
 
+        report
+            .orphaned_cellar_kegs
+            .sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        report
+            .missing_cellar_kegs
+            .sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        report.orphaned_store_entries.sort();
+        report
+            .stale_store_refs
+            .sort_by(|a, b| a.store_key.cmp(&b.store_key));
+        report.broken_symlinks.sort();
+        report.broken_symlinks.dedup();
+        report.symlink_loops.sort();
+        report.invalid_signatures.sort();
+        report.missing_libraries.sort_by(|a, b| a.path.cmp(&b.path));
+
         Ok(report)
     }
 
@@ -164,6 +256,7 @@ impl Installer {
         for orphan in &report.orphaned_cellar_kegs {
             self.linker.unlink_keg(&orphan.path).ok();
             self.cellar.remove_keg(&orphan.name, &orphan.version)?;
+            self.forget_metadata(&orphan.name, &orphan.version);
             summary.removed_orphaned_kegs += 1;
         }
 
@@ -179,9 +272,14 @@ impl Installer {
 
         if needs_refcount_recompute {
             let installed = self.db.list_installed()?;
+            let retained = self.db.list_retained(None)?;
             let mut corrected: HashMap<&str, i64> = HashMap::new();
-            for keg in &installed {
-                *corrected.entry(keg.store_key.as_str()).or_insert(0) += 1;
+            let store_keys = installed
+                .iter()
+                .map(|keg| keg.store_key.as_str())
+                .chain(retained.iter().map(|keg| keg.store_key.as_str()));
+            for store_key in store_keys {
+                *corrected.entry(store_key).or_insert(0) += 1;
             }
 
             let corrected_refs: Vec<StoreRef> = corrected
@@ -198,7 +296,7 @@ impl Installer {
         }
 
         for key in &report.orphaned_store_entries {
-            self.store.remove_entry(key)?;
+            self.store.remove(key)?;
             summary.removed_orphaned_store_entries += 1;
         }
 
@@ -211,6 +309,15 @@ impl Installer {
             summary.pruned_keg_file_records = self.db.prune_stale_keg_file_records()?;
         }
 
+        #[cfg(all(target_os = "macos", feature = "patch-macho"))]
+        if !report.invalid_signatures.is_empty() {
+            let still_invalid = crate::extraction::patch::resign(&report.invalid_signatures);
+            for failure in &still_invalid {
+                tracing::warn!(path = %failure.path.display(), "failed to re-sign binary");
+            }
+            summary.resigned_binaries = report.invalid_signatures.len() - still_invalid.len();
+        }
+
         Ok(summary)
     }
 }
@@ -223,6 +330,7 @@ pub struct RepairSummary {
     pub removed_orphaned_store_entries: usize,
     pub removed_broken_symlinks: usize,
     pub pruned_keg_file_records: usize,
+    pub resigned_binaries: usize,
 }
 
 impl RepairSummary {
@@ -233,5 +341,105 @@ impl RepairSummary {
             + self.removed_orphaned_store_entries
             + self.removed_broken_symlinks
             + self.pruned_keg_file_records
+            + self.resigned_binaries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::installer::install::test_support::*;
+    use crate::network::api::ApiClient;
+
+    #[test]
+    fn findings_are_sorted_and_stable_across_runs() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("prefix");
+        let mut installer = installer_at(ApiClient::new(), &root, &prefix, root.join("Cellar"));
+
+        for (name, version) in [
+            ("zlib", "1.3"),
+            ("abc", "2.0"),
+            ("zlib", "1.2"),
+            ("mpfr", "4.2"),
+        ] {
+            fs::create_dir_all(root.join("Cellar").join(name).join(version)).unwrap();
+        }
+        for key in ["ffff", "0000", "8888", "4444"] {
+            fs::create_dir_all(root.join("store").join(key)).unwrap();
+        }
+
+        let first = installer.doctor().unwrap();
+        let second = installer.doctor().unwrap();
+
+        let kegs: Vec<_> = first
+            .orphaned_cellar_kegs
+            .iter()
+            .map(|k| format!("{}/{}", k.name, k.version))
+            .collect();
+        assert_eq!(kegs, ["abc/2.0", "mpfr/4.2", "zlib/1.2", "zlib/1.3"]);
+        assert_eq!(
+            first.orphaned_store_entries,
+            ["0000", "4444", "8888", "ffff"]
+        );
+        assert_eq!(format!("{first:?}"), format!("{second:?}"));
+    }
+
+    #[test]
+    fn store_key_in_use_without_a_row_is_reported_and_repaired() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("prefix");
+        let mut installer = installer_at(ApiClient::new(), &root, &prefix, root.join("Cellar"));
+        let tx = installer.db.transaction().unwrap();
+        tx.record_install("foo", "1.0", "inuse").unwrap();
+        tx.commit().unwrap();
+        installer.db.replace_store_refs(&[]).unwrap();
+        fs::create_dir_all(root.join("store/inuse")).unwrap();
+        fs::create_dir_all(root.join("Cellar/foo/1.0")).unwrap();
+
+        let report = installer.doctor().unwrap();
+        let stale: Vec<_> = report
+            .stale_store_refs
+            .iter()
+            .map(|r| (r.store_key.as_str(), r.on_disk, r.referenced_by_any_keg))
+            .collect();
+        assert_eq!(stale, [("inuse", true, true)]);
+
+        installer.repair(&report).unwrap();
+        assert_eq!(installer.db.get_store_refcount("inuse").unwrap(), 1);
+        assert!(installer.doctor().unwrap().is_healthy());
+    }
+
+    #[test]
+    fn symlink_loops_are_reported_and_left_alone() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("prefix");
+        let mut installer = installer_at(ApiClient::new(), &root, &prefix, root.join("Cellar"));
+        fs::create_dir_all(prefix.join("share")).unwrap();
+        std::os::unix::fs::symlink(&prefix, prefix.join("share/bad")).unwrap();
+        std::os::unix::fs::symlink("ping", prefix.join("bin/pong")).unwrap();
+        std::os::unix::fs::symlink("pong", prefix.join("bin/ping")).unwrap();
+
+        let started = std::time::Instant::now();
+        let report = installer.doctor().unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        assert!(!report.is_healthy());
+        assert_eq!(
+            report.symlink_loops,
+            [
+                prefix.join("bin/ping"),
+                prefix.join("bin/pong"),
+                prefix.join("share/bad"),
+            ]
+        );
+        installer.repair(&report).unwrap();
+        assert!(prefix.join("share/bad").is_symlink());
     }
 }
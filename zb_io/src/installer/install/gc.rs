@@ -0,0 +1,685 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::warn;
+use zb_core::{Error, formula_token};
+
+use super::Installer;
+use crate::storage::blob::{BlobEntry, IndexRebuild};
+use crate::storage::store::StoreArchive;
+
+/// What `gc_with` cleans up. The default only removes unreferenced store
+/// entries, which is what plain `zb gc` has always done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcOptions {
+    /// Store entries no installed keg references.
+    pub store_entries: bool,
+    /// Downloaded bottles no installed keg was extracted from.
+    pub blobs: bool,
+    /// Cached formula API responses.
+    pub metadata: bool,
+    /// Keg directories in the cellar that are neither the installed version
+    /// nor a previous version kept for rollback.
+    pub old_kegs: bool,
+    /// Partial downloads and half-built store entries.
+    pub temp_files: bool,
+    /// Compress unreferenced store entries into archives instead of
+    /// deleting them; an install that needs one expands it again.
+    pub archive: bool,
+    /// Report what would be removed without touching anything.
+    pub dry_run: bool,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        Self {
+            store_entries: true,
+            blobs: false,
+            metadata: false,
+            old_kegs: false,
+            temp_files: false,
+            archive: false,
+            dry_run: false,
+        }
+    }
+}
+
+impl GcOptions {
+    /// Every category at once.
+    pub fn aggressive() -> Self {
+        Self {
+            store_entries: true,
+            blobs: true,
+            metadata: true,
+            old_kegs: true,
+            temp_files: true,
+            archive: false,
+            dry_run: false,
+        }
+    }
+}
+
+/// How much one category gave back. Sizes are apparent file sizes, so files
+/// hard-linked between the store and the cellar count in both.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Reclaimed {
+    pub items: usize,
+    pub bytes: u64,
+}
+
+impl Reclaimed {
+    fn add(&mut self, bytes: u64) {
+        self.items += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// What a gc pass removed, or would remove on a dry run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GcReport {
+    pub store_entries: Reclaimed,
+    pub blobs: Reclaimed,
+    pub metadata: Reclaimed,
+    pub old_kegs: Reclaimed,
+    pub temp_files: Reclaimed,
+    /// Store entries compressed into archives; the bytes are what that saved.
+    pub archived_entries: Reclaimed,
+    /// Keys of the unreferenced store entries that were deleted, sorted.
+    pub removed_store_keys: Vec<String>,
+    /// Keys of the unreferenced store entries that were archived, sorted.
+    pub archived_store_keys: Vec<String>,
+}
+
+impl GcReport {
+    /// Each category with a label, in the order gc reports them.
+    pub fn categories(&self) -> [(&'static str, Reclaimed); 6] {
+        [
+            ("store entries", self.store_entries),
+            ("archived store entries", self.archived_entries),
+            ("cached bottles", self.blobs),
+            ("cached metadata", self.metadata),
+            ("old keg versions", self.old_kegs),
+            ("temporary files", self.temp_files),
+        ]
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.categories().iter().map(|(_, r)| r.bytes).sum()
+    }
+}
+
+impl Installer {
+    pub fn gc(&mut self) -> Result<Vec<String>, Error> {
+        Ok(self.gc_with(&GcOptions::default())?.removed_store_keys)
+    }
+
+    /// Delete archived store entries, only those archived before `before`
+    /// when given, under the install lock so none is being expanded.
+    pub fn clean_archives(
+        &mut self,
+        before: Option<std::time::SystemTime>,
+    ) -> Result<Vec<StoreArchive>, Error> {
+        let _lock = self.lock_install()?;
+
+        let mut removed = Vec::new();
+        for archive in self.store.list_archives()? {
+            if before.is_some_and(|before| archive.archived_at >= before) {
+                continue;
+            }
+            self.store.remove_archive(&archive.store_key)?;
+            self.db.delete_archived_store_ref(&archive.store_key)?;
+            removed.push(archive);
+        }
+        let bytes = removed.iter().map(|archive| archive.bytes).sum();
+        self.record_run("cache-clean", removed.len(), bytes);
+        Ok(removed)
+    }
+
+    /// Rebuild the blob cache index from the cached bottles, under the
+    /// install lock so no download is recorded halfway through.
+    pub fn rebuild_blob_index(&mut self) -> Result<IndexRebuild, Error> {
+        let _lock = self.lock_install()?;
+
+        self.downloader.blob_cache().rebuild_index(&mut self.db)
+    }
+
+    /// Delete cached bottles last used before `before`, or all of them
+    /// when not given, judged by the blob index. Runs under the install
+    /// lock so none is being downloaded, and keeps the bottles installed
+    /// kegs were extracted from, as `zb gc` does.
+    pub fn clean_bottles(&mut self, before: Option<SystemTime>) -> Result<Vec<BlobEntry>, Error> {
+        let _lock = self.lock_install()?;
+
+        let cutoff = before.map(|before| {
+            before
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |age| age.as_secs() as i64)
+        });
+        let referenced: HashSet<String> = self
+            .db
+            .list_installed()?
+            .into_iter()
+            .map(|keg| keg.store_key)
+            .collect();
+        let entries = self
+            .downloader
+            .blob_cache()
+            .entries(&self.db)
+            .map_err(Error::store("failed to read blob cache"))?;
+
+        let mut removed = Vec::new();
+        for entry in entries {
+            if cutoff.is_some_and(|cutoff| entry.last_access >= cutoff)
+                || referenced.contains(&entry.sha256)
+            {
+                continue;
+            }
+            self.remove_blob(&entry.sha256);
+            removed.push(entry);
+        }
+        let bytes = removed.iter().map(|entry| entry.size).sum();
+        self.record_run("cache-clean", removed.len(), bytes);
+        Ok(removed)
+    }
+
+    /// Index the cached blob `sha256` as used just now for `formula`. The
+    /// index only steers cache cleaning, so failing to write it never
+    /// fails an install; `zb cache rebuild-index` catches up.
+    pub(super) fn index_blob(&self, sha256: &str, formula: &str, verified: bool) {
+        let recorded = fs::metadata(self.downloader.blob_cache().blob_path(sha256))
+            .map_err(Error::store("failed to read cached blob"))
+            .and_then(|metadata| {
+                self.db
+                    .record_blob(sha256, metadata.len(), Some(formula), verified)
+            });
+        if let Err(e) = recorded {
+            warn!(sha256, error = %e, "failed to index cached blob");
+        }
+    }
+
+    /// Remove a cached blob and its index entry.
+    pub(super) fn remove_blob(&self, sha256: &str) {
+        self.downloader.remove_blob(sha256);
+        if let Err(e) = self.db.delete_blob(sha256) {
+            warn!(sha256, error = %e, "failed to drop cached blob from the index");
+        }
+    }
+
+    /// Remove whatever `options` selects under the install lock, so nothing
+    /// an install in progress is writing gets swept up. Previous versions
+    /// beyond the number kept for rollback are always removed, and counted
+    /// as old keg versions.
+    pub fn gc_with(&mut self, options: &GcOptions) -> Result<GcReport, Error> {
+        let _lock = self.lock_install()?;
+
+        let mut report = GcReport::default();
+        let installed = self.db.list_installed()?;
+
+        if options.temp_files {
+            let blob_cache = self.downloader.blob_cache();
+            let mut temp = blob_cache
+                .temp_files()
+                .map_err(Error::store("failed to read download temp directory"))?;
+            temp.extend(self.store.temp_entries()?);
+            for path in temp {
+                report.temp_files.add(disk_usage(&path));
+                if !options.dry_run {
+                    remove_path(&path)?;
+                }
+            }
+        }
+
+        for keg in self.prune_retained(None, true)? {
+            let keg_path = self.cellar.keg_path(formula_token(&keg.name), &keg.version);
+            report.old_kegs.add(disk_usage(&keg_path));
+        }
+        if !options.dry_run {
+            self.prune_retained(None, false)?;
+        }
+
+        if options.old_kegs {
+            let retained = self.db.list_retained(None)?;
+            let current: HashSet<(&str, &str)> = installed
+                .iter()
+                .map(|keg| (formula_token(&keg.name), keg.version.as_str()))
+                .chain(
+                    retained
+                        .iter()
+                        .map(|keg| (formula_token(&keg.name), keg.version.as_str())),
+                )
+                .collect();
+            for keg in self.cellar.list_kegs()? {
+                if current.contains(&(keg.name.as_str(), keg.version.as_str())) {
+                    continue;
+                }
+                report.old_kegs.add(disk_usage(&keg.path));
+                if !options.dry_run {
+                    self.linker.unlink_keg(&keg.path)?;
+                    self.cellar.remove_keg(&keg.name, &keg.version)?;
+                    self.forget_metadata(&keg.name, &keg.version);
+                }
+            }
+        }
+
+        if options.store_entries {
+            for store_key in self.db.get_unreferenced_store_keys()? {
+                let entry_path = self.store.path_for(&store_key);
+                let size = disk_usage(&entry_path);
+                // An entry already gone from disk has nothing to archive.
+                if options.archive && entry_path.exists() {
+                    let mut saved = size;
+                    if !options.dry_run {
+                        saved = size.saturating_sub(self.store.archive(&store_key)?);
+                        self.db.set_store_archived(&store_key, true)?;
+                    }
+                    report.archived_entries.add(saved);
+                    report.archived_store_keys.push(store_key);
+                    continue;
+                }
+                report.store_entries.add(size);
+                if !options.dry_run {
+                    self.store.remove(&store_key)?;
+                    self.db.delete_store_ref(&store_key)?;
+                }
+                report.removed_store_keys.push(store_key);
+            }
+        }
+
+        if options.blobs {
+            let referenced: HashSet<&str> =
+                installed.iter().map(|keg| keg.store_key.as_str()).collect();
+            let blobs = self
+                .downloader
+                .blob_cache()
+                .list_blobs()
+                .map_err(Error::store("failed to read blob cache"))?;
+            for (sha256, size) in blobs {
+                if referenced.contains(sha256.as_str()) {
+                    continue;
+                }
+                report.blobs.add(size);
+                if !options.dry_run {
+                    self.remove_blob(&sha256);
+                }
+            }
+        }
+
+        if options.metadata {
+            let (entries, bytes) = self.api_client.cache_usage()?;
+            report.metadata = Reclaimed {
+                items: entries,
+                bytes,
+            };
+            if !options.dry_run {
+                self.api_client.clear_cache()?;
+            }
+        }
+
+        if !options.dry_run {
+            let entries = report.categories().iter().map(|(_, r)| r.items).sum();
+            self.record_run("gc", entries, report.total_bytes());
+        }
+        Ok(report)
+    }
+
+    /// Add a `kind` run to `zb history`. The cleanup already happened, so a
+    /// failure to record it is only logged.
+    fn record_run(&self, kind: &str, entries: usize, bytes: u64) {
+        let details = serde_json::json!({ "entries": entries, "bytes": bytes });
+        if let Err(e) = self
+            .db
+            .record_event(kind, invoking_user().as_deref(), Some(&details))
+        {
+            warn!(kind, error = %e, "failed to record run in history");
+        }
+    }
+}
+
+/// The person behind the command: the user who ran sudo, if it was run
+/// through sudo, otherwise the login name from the environment.
+fn invoking_user() -> Option<String> {
+    ["SUDO_USER", "USER", "LOGNAME"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|user| !user.is_empty())
+}
+
+/// Apparent size of the files under `path`, not following symlinks.
+pub(super) fn disk_usage(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+pub(super) fn remove_path(path: &Path) -> Result<(), Error> {
+    let ctx = format!("failed to remove {}", path.display());
+    let result = if path.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        other => other.map_err(Error::store(ctx.as_str())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::installer::install::test_support::*;
+    use crate::network::api::ApiClient;
+    use crate::network::cache::{ApiCache, CacheEntry};
+    use crate::storage::blob::BlobCache;
+    use crate::storage::store::tree_hash;
+
+    fn names_in(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn aggressive_gc_leaves_only_what_installed_kegs_need() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        let keep_sha = mount_bottled(&server, "keep", &[]).await;
+        let drop_sha = mount_bottled(&server, "dropme", &[]).await;
+
+        let cache_path = root.join("cache/api-cache.sqlite");
+        fs::create_dir_all(root.join("cache")).unwrap();
+        let api_client = ApiClient::with_base_url(format!("{}/formula", server.uri()))
+            .unwrap()
+            .with_cache(ApiCache::open(&cache_path).unwrap());
+        let mut installer = installer_at(api_client, &root, &prefix, root.join("Cellar"));
+
+        installer
+            .install(&["keep".to_string(), "dropme".to_string()], true)
+            .await
+            .unwrap();
+        installer.uninstall("dropme").unwrap();
+
+        let seeded = ApiCache::open(&cache_path).unwrap();
+        seeded
+            .put(
+                "https://formulae.brew.sh/api/formula/keep.json",
+                &CacheEntry {
+                    etag: Some("\"abc\"".to_string()),
+                    last_modified: None,
+                    body: "{}".to_string(),
+                },
+            )
+            .unwrap();
+        fs::write(root.join("cache/blobs/stale.tar.gz"), b"old bottle").unwrap();
+        fs::write(root.join("cache/tmp/.tmpPartial"), b"half a download").unwrap();
+        fs::create_dir_all(root.join("store/.tmpExtract")).unwrap();
+        fs::write(root.join("store/.tmpExtract/file"), b"half a keg").unwrap();
+        fs::create_dir_all(root.join("Cellar/keep/0.9/bin")).unwrap();
+        fs::write(root.join("Cellar/keep/0.9/bin/keep"), b"old keg").unwrap();
+
+        let dry_run = installer
+            .gc_with(&GcOptions {
+                dry_run: true,
+                ..GcOptions::aggressive()
+            })
+            .unwrap();
+
+        assert_eq!(dry_run.store_entries.items, 1);
+        assert_eq!(dry_run.removed_store_keys, vec![drop_sha.clone()]);
+        assert_eq!(dry_run.blobs.items, 2);
+        assert_eq!(dry_run.old_kegs, Reclaimed { items: 1, bytes: 7 });
+        assert_eq!(
+            dry_run.temp_files,
+            Reclaimed {
+                items: 2,
+                bytes: 25
+            }
+        );
+        assert!(dry_run.metadata.items >= 1);
+        assert!(root.join("Cellar/keep/0.9").exists());
+        assert!(root.join("cache/blobs/stale.tar.gz").exists());
+        assert!(root.join("store").join(&drop_sha).exists());
+        assert!(installer.db.list_events().unwrap().is_empty());
+
+        let report = installer.gc_with(&GcOptions::aggressive()).unwrap();
+
+        assert_eq!(report, dry_run);
+        let events = installer.db.list_events().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, "gc");
+        let details = events[0].details.as_ref().unwrap();
+        assert_eq!(details["bytes"], report.total_bytes());
+        assert_eq!(
+            details["entries"],
+            report
+                .categories()
+                .iter()
+                .map(|(_, r)| r.items)
+                .sum::<usize>()
+        );
+        assert_eq!(names_in(&root.join("store")), vec![keep_sha.clone()]);
+        assert_eq!(
+            names_in(&root.join("cache/blobs")),
+            [format!("{keep_sha}.tar.gz")]
+        );
+        assert!(names_in(&root.join("cache/tmp")).is_empty());
+        assert_eq!(names_in(&root.join("Cellar")), ["keep"]);
+        assert_eq!(names_in(&root.join("Cellar/keep")), ["1.0.0"]);
+        assert_eq!(seeded.usage().unwrap(), (0, 0));
+        assert!(prefix.join("bin/keep").exists());
+    }
+
+    #[tokio::test]
+    async fn archived_entries_are_reinstalled_without_downloading() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let sha = mount_bottled(&server, "toolchain", &[]).await;
+        let mut installer = installer_at(api_client(&server), &root, &prefix, root.join("Cellar"));
+        let names = ["toolchain".to_string()];
+        let keg = root.join("Cellar/toolchain/1.0.0");
+
+        installer.install(&names, true).await.unwrap();
+        let installed = tree_hash(&keg).unwrap();
+        installer.uninstall("toolchain").unwrap();
+
+        let options = GcOptions {
+            archive: true,
+            ..GcOptions::default()
+        };
+        let report = installer.gc_with(&options).unwrap();
+        assert_eq!(report.archived_store_keys, std::slice::from_ref(&sha));
+        assert_eq!(report.archived_entries.items, 1);
+        assert!(report.removed_store_keys.is_empty());
+        assert!(!root.join("store").join(&sha).exists());
+        assert!(root.join(format!("archive/{sha}.tar.zst")).exists());
+        // Archived entries aren't collected again by a later gc.
+        assert_eq!(installer.gc().unwrap(), Vec::<String>::new());
+
+        // Nothing left to download from: the bottle is gone upstream and
+        // from the blob cache.
+        server.reset().await;
+        let json = serde_json::json!({
+            "name": "toolchain",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": { get_test_bottle_tag(): {
+                "url": format!("{}/gone.tar.gz", server.uri()),
+                "sha256": sha,
+            }}}}
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/toolchain.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(json.to_string()))
+            .mount(&server)
+            .await;
+        BlobCache::new(&root.join("cache"))
+            .unwrap()
+            .remove_blob(&sha)
+            .unwrap();
+
+        installer.install(&names, true).await.unwrap();
+
+        assert_eq!(tree_hash(&keg).unwrap(), installed);
+        assert!(prefix.join("bin/toolchain").exists());
+        assert!(!root.join(format!("archive/{sha}.tar.zst")).exists());
+        assert_eq!(installer.db.get_store_refcount(&sha).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn cleaning_archives_forgets_their_entries() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let sha = mount_bottled(&server, "monthly", &[]).await;
+        let mut installer = installer_at(
+            api_client(&server),
+            &root,
+            &tmp.path().join("prefix"),
+            root.join("Cellar"),
+        );
+        installer
+            .install(&["monthly".to_string()], true)
+            .await
+            .unwrap();
+        installer.uninstall("monthly").unwrap();
+        installer
+            .gc_with(&GcOptions {
+                archive: true,
+                ..GcOptions::default()
+            })
+            .unwrap();
+
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        assert!(
+            installer
+                .clean_archives(Some(an_hour_ago))
+                .unwrap()
+                .is_empty()
+        );
+        let removed = installer.clean_archives(None).unwrap();
+
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].store_key, sha);
+        assert!(names_in(&root.join("archive")).is_empty());
+        assert!(installer.db.list_store_refs().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cleaning_bottles_goes_by_their_last_use_in_the_index() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let kept = mount_bottled(&server, "kept", &[]).await;
+        let recent = mount_bottled(&server, "recent", &[]).await;
+        let mut installer = installer_at(
+            api_client(&server),
+            &root,
+            &tmp.path().join("prefix"),
+            root.join("Cellar"),
+        );
+        installer
+            .install(&["kept".to_string(), "recent".to_string()], true)
+            .await
+            .unwrap();
+        installer.uninstall("recent").unwrap();
+        fs::write(root.join("cache/blobs/unindexed.tar.gz"), b"old bottle").unwrap();
+
+        // Every file looks old; only the index knows `recent` was just used.
+        let ten_days_ago = SystemTime::now() - std::time::Duration::from_secs(10 * 86400);
+        for sha in [kept.as_str(), recent.as_str(), "unindexed"] {
+            fs::File::options()
+                .write(true)
+                .open(root.join(format!("cache/blobs/{sha}.tar.gz")))
+                .unwrap()
+                .set_modified(ten_days_ago)
+                .unwrap();
+        }
+        let indexed = installer.db.list_blob_entries().unwrap();
+        assert_eq!(indexed.len(), 2);
+        assert!(indexed.iter().all(|entry| entry.verified));
+
+        let five_days_ago = SystemTime::now() - std::time::Duration::from_secs(5 * 86400);
+        let removed = installer.clean_bottles(Some(five_days_ago)).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].sha256, "unindexed");
+
+        let removed = installer.clean_bottles(None).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].sha256, recent);
+        assert_eq!(
+            names_in(&root.join("cache/blobs")),
+            [format!("{kept}.tar.gz")]
+        );
+        let indexed = installer.db.list_blob_entries().unwrap();
+        assert_eq!(indexed.len(), 1);
+        assert_eq!(indexed[0].formula.as_deref(), Some("kept"));
+    }
+
+    #[tokio::test]
+    async fn default_gc_only_touches_the_store() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let mut installer = installer_at(
+            ApiClient::new(),
+            &root,
+            &tmp.path().join("prefix"),
+            root.join("Cellar"),
+        );
+        fs::write(root.join("cache/blobs/stale.tar.gz"), b"old bottle").unwrap();
+        fs::write(root.join("cache/tmp/.tmpPartial"), b"half").unwrap();
+        fs::create_dir_all(root.join("Cellar/orphan/1.0")).unwrap();
+
+        let report = installer.gc_with(&GcOptions::default()).unwrap();
+
+        assert_eq!(report.total_bytes(), 0);
+        assert_eq!(installer.db.list_events().unwrap().len(), 1);
+        assert!(root.join("cache/blobs/stale.tar.gz").exists());
+        assert!(root.join("cache/tmp/.tmpPartial").exists());
+        assert!(root.join("Cellar/orphan/1.0").exists());
+    }
+
+    #[test]
+    fn gc_deletes_rows_for_store_entries_already_gone() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let mut installer = installer_at(
+            ApiClient::new(),
+            &root,
+            &tmp.path().join("prefix"),
+            root.join("Cellar"),
+        );
+        installer
+            .db
+            .replace_store_refs(&[crate::storage::db::StoreRef {
+                store_key: "vanished".to_string(),
+                refcount: -3,
+            }])
+            .unwrap();
+
+        assert_eq!(installer.gc().unwrap(), ["vanished"]);
+        assert!(installer.db.list_store_refs().unwrap().is_empty());
+        assert!(installer.gc().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,312 @@
+//! User hook scripts run around installs and uninstalls. Every executable
+//! file in `<dir>/<point>.d/` runs, in name order, with the formula in its
+//! environment:
+//!
+//! - `ZEROBREW_HOOK_ACTION`: the hook point, e.g. `post-install`
+//! - `ZEROBREW_HOOK_FORMULA`: the formula name
+//! - `ZEROBREW_HOOK_VERSION`: the version installed or removed
+//! - `ZEROBREW_HOOK_KEG`: the keg directory
+//!
+//! Hooks never run while the install lock is held, so a hook may call zb
+//! itself.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+use zb_core::{Error, formula_token};
+
+use super::Installer;
+
+/// How long a single hook script may run before it is killed.
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPoint {
+    PreInstall,
+    PostInstall,
+    PreUninstall,
+    PostUninstall,
+}
+
+impl HookPoint {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::PreInstall => "pre-install",
+            Self::PostInstall => "post-install",
+            Self::PreUninstall => "pre-uninstall",
+            Self::PostUninstall => "post-uninstall",
+        }
+    }
+
+    /// Pre hooks can veto the operation; post hooks run after it is done.
+    pub fn is_pre(self) -> bool {
+        matches!(self, Self::PreInstall | Self::PreUninstall)
+    }
+}
+
+/// Where hook scripts live and how long each may run.
+#[derive(Debug, Clone)]
+pub struct Hooks {
+    dir: PathBuf,
+    timeout: Duration,
+}
+
+impl Hooks {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            timeout: DEFAULT_HOOK_TIMEOUT,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The executable files in `point`'s directory, sorted by name. A
+    /// missing directory has none.
+    pub fn scripts(&self, point: HookPoint) -> Result<Vec<PathBuf>, Error> {
+        let dir = self.dir.join(format!("{}.d", point.as_str()));
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(Error::FileError {
+                    message: format!("failed to read hook directory {}: {e}", dir.display()),
+                });
+            }
+        };
+
+        let mut scripts: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| is_executable(path))
+            .collect();
+        scripts.sort();
+        Ok(scripts)
+    }
+
+    /// Run `point`'s scripts for one keg. A pre hook that fails stops the
+    /// rest and is returned. Post hooks all run; their failures are only
+    /// logged as warnings, since the operation is already done.
+    pub fn run(
+        &self,
+        point: HookPoint,
+        name: &str,
+        version: &str,
+        keg: &Path,
+    ) -> Result<(), Error> {
+        let scripts = match self.scripts(point) {
+            Ok(scripts) => scripts,
+            Err(e) if point.is_pre() => return Err(e),
+            Err(e) => {
+                warn!(error = %e, "skipping {} hooks", point.as_str());
+                return Ok(());
+            }
+        };
+        for script in scripts {
+            if let Err(e) = self.run_script(&script, point, name, version, keg) {
+                if point.is_pre() {
+                    return Err(e);
+                }
+                warn!("{e}");
+            }
+        }
+        Ok(())
+    }
+
+    fn run_script(
+        &self,
+        script: &Path,
+        point: HookPoint,
+        name: &str,
+        version: &str,
+        keg: &Path,
+    ) -> Result<(), Error> {
+        let describe = || format!("{} hook {}", point.as_str(), script.display());
+        // Hook output goes to stderr so it never mixes with zb's own
+        // (possibly machine-readable) stdout.
+        let mut child = Command::new(script)
+            .env("ZEROBREW_HOOK_ACTION", point.as_str())
+            .env("ZEROBREW_HOOK_FORMULA", name)
+            .env("ZEROBREW_HOOK_VERSION", version)
+            .env("ZEROBREW_HOOK_KEG", keg)
+            .stdin(Stdio::null())
+            .stdout(std::io::stderr())
+            .spawn()
+            .map_err(|e| Error::ExecutionError {
+                message: format!("failed to run {}: {e}", describe()),
+            })?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let status = child.try_wait().map_err(|e| Error::ExecutionError {
+                message: format!("failed waiting for {}: {e}", describe()),
+            })?;
+            match status {
+                Some(status) if status.success() => return Ok(()),
+                Some(status) => {
+                    return Err(Error::ExecutionError {
+                        message: format!("{} for {name} failed ({status})", describe()),
+                    });
+                }
+                None if Instant::now() >= deadline => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(Error::ExecutionError {
+                        message: format!(
+                            "{} for {name} timed out after {:?}",
+                            describe(),
+                            self.timeout
+                        ),
+                    });
+                }
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+}
+
+impl Installer {
+    /// Run hook scripts around installs and uninstalls; `None` (the
+    /// default) runs none.
+    pub fn set_hooks(&mut self, hooks: Option<Hooks>) {
+        self.hooks = hooks;
+    }
+
+    /// [`Self::run_hooks`] on a blocking thread, so a slow hook doesn't
+    /// stall the async install it runs around.
+    pub(crate) async fn run_hooks_async(
+        &self,
+        point: HookPoint,
+        name: &str,
+        version: &str,
+    ) -> Result<(), Error> {
+        let Some(hooks) = self.hooks.clone() else {
+            return Ok(());
+        };
+        let keg = self.cellar.keg_path(formula_token(name), version);
+        let (name, version) = (name.to_string(), version.to_string());
+        tokio::task::spawn_blocking(move || hooks.run(point, &name, &version, &keg))
+            .await
+            .map_err(Error::exec("hook runner stopped unexpectedly"))?
+    }
+
+    /// Run `point`'s hooks for `version` of the installed formula `name`.
+    /// Must not be called while holding the install lock.
+    pub(crate) fn run_hooks(
+        &self,
+        point: HookPoint,
+        name: &str,
+        version: &str,
+    ) -> Result<(), Error> {
+        let Some(hooks) = &self.hooks else {
+            return Ok(());
+        };
+        let keg = self.cellar.keg_path(formula_token(name), version);
+        hooks.run(point, name, version, &keg)
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn script(hooks: &Path, point: HookPoint, name: &str, body: &str) -> PathBuf {
+        let dir = hooks.join(format!("{}.d", point.as_str()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn runs_executable_scripts_in_order_with_the_keg_in_the_environment() {
+        let tmp = TempDir::new().unwrap();
+        let log = tmp.path().join("log");
+        let record = format!(
+            "echo \"$0 $ZEROBREW_HOOK_ACTION $ZEROBREW_HOOK_FORMULA $ZEROBREW_HOOK_VERSION $ZEROBREW_HOOK_KEG\" >> {}",
+            log.display()
+        );
+        let second = script(tmp.path(), HookPoint::PostInstall, "20-second", &record);
+        let first = script(tmp.path(), HookPoint::PostInstall, "10-first", &record);
+        let disabled = tmp.path().join("post-install.d/15-disabled");
+        fs::write(&disabled, "#!/bin/sh\nexit 1\n").unwrap();
+        fs::set_permissions(&disabled, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let hooks = Hooks::new(tmp.path());
+        assert_eq!(
+            hooks.scripts(HookPoint::PostInstall).unwrap(),
+            [first.clone(), second.clone()]
+        );
+        assert!(hooks.scripts(HookPoint::PreInstall).unwrap().is_empty());
+
+        hooks
+            .run(HookPoint::PostInstall, "jq", "1.7.1", Path::new("/keg/jq"))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&log).unwrap(),
+            format!(
+                "{} post-install jq 1.7.1 /keg/jq\n{} post-install jq 1.7.1 /keg/jq\n",
+                first.display(),
+                second.display()
+            )
+        );
+    }
+
+    #[test]
+    fn a_failing_pre_hook_stops_the_rest_but_post_hook_failures_only_warn() {
+        let tmp = TempDir::new().unwrap();
+        let ran = tmp.path().join("ran");
+        let touch = format!("touch {}", ran.display());
+        script(tmp.path(), HookPoint::PreUninstall, "10-veto", "exit 3");
+        script(tmp.path(), HookPoint::PreUninstall, "20-after", &touch);
+        script(tmp.path(), HookPoint::PostUninstall, "10-fails", "exit 3");
+        script(tmp.path(), HookPoint::PostUninstall, "20-after", &touch);
+        let hooks = Hooks::new(tmp.path());
+
+        let err = hooks
+            .run(HookPoint::PreUninstall, "jq", "1.7.1", tmp.path())
+            .unwrap_err();
+        assert!(err.to_string().contains("10-veto"), "{err}");
+        assert!(!ran.exists());
+
+        hooks
+            .run(HookPoint::PostUninstall, "jq", "1.7.1", tmp.path())
+            .unwrap();
+        assert!(ran.exists());
+    }
+
+    #[test]
+    fn a_hook_that_hangs_is_killed_after_the_timeout() {
+        let tmp = TempDir::new().unwrap();
+        script(tmp.path(), HookPoint::PreInstall, "hang", "exec sleep 30");
+        let hooks = Hooks::new(tmp.path()).with_timeout(Duration::from_millis(200));
+
+        let started = Instant::now();
+        let err = hooks
+            .run(HookPoint::PreInstall, "jq", "1.7.1", tmp.path())
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -1,12 +1,31 @@
+pub mod advice;
 mod bottle;
+pub mod bundle;
+pub mod deps;
 pub mod doctor;
+pub mod gc;
+pub mod hooks;
 mod outdated;
 mod plan;
+pub mod prefetch;
+pub mod relocate;
+pub mod rollback;
+pub mod runtime;
+pub mod saved_plan;
+pub mod search;
 mod source;
-mod uninstall;
-
+pub mod state;
+pub mod sweep;
+pub mod taps;
+pub mod uninstall;
+pub mod upgrade;
+pub mod why;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::Arc;
 
 use fs4::fs_std::FileExt;
@@ -14,17 +33,26 @@ use tracing::warn;
 
 use crate::cellar::link::Linker;
 use crate::cellar::materialize::Cellar;
+use crate::extraction::patch::{PatchConfig, PatchPolicy};
+use crate::extraction::{ExtractionBudget, ExtractionLimits, ExtractionStats};
 use crate::network::api::ApiClient;
 use crate::network::cache::ApiCache;
-use crate::network::download::{DownloadProgressCallback, DownloadRequest, ParallelDownloader};
+use crate::network::client::{ConnectionStats, HttpClient};
+use crate::network::download::{
+    BlobSource, DownloadProgressCallback, DownloadRequest, DownloadResult, ParallelDownloader,
+    RegistryCredentials,
+};
+use crate::network::snapshot::FormulaSnapshot;
 use crate::progress::{InstallProgress, ProgressCallback};
 use crate::storage::blob::BlobCache;
-use crate::storage::db::Database;
-use crate::storage::store::Store;
+use crate::storage::db::{Database, InstallTransaction, InstalledSet, SystemRecord};
+use crate::storage::metadata::KegMetadata;
+use crate::storage::store::{Store, StoreKey};
 
-use zb_core::{Error, Formula, InstallMethod};
+use zb_core::{BuildId, CELLAR_DIR, ChangeKind, Error, Formula, InstallMethod};
 
 use bottle::dependency_cellar_path;
+use hooks::HookPoint;
 
 const MAX_CORRUPTION_RETRIES: usize = 3;
 
@@ -35,8 +63,16 @@ pub struct Installer {
     cellar: Cellar,
     linker: Linker,
     pub(crate) db: Database,
+    /// The formula metadata each keg was installed from.
+    metadata: KegMetadata,
     prefix: PathBuf,
+    /// The zerobrew root everything but the Cellar and links lives under.
+    root: PathBuf,
     locks_dir: PathBuf,
+    keep_versions: usize,
+    hooks: Option<hooks::Hooks>,
+    /// The installed set last read, reused until the database changes.
+    installed: RefCell<Option<Rc<InstalledSet>>>,
 }
 
 #[derive(Debug)]
@@ -44,15 +80,91 @@ pub struct PlannedInstall {
     pub install_name: String,
     pub formula: Formula,
     pub method: InstallMethod,
+    /// True when the user asked for this formula rather than it being
+    /// pulled in as a dependency.
+    pub explicit: bool,
 }
 
-#[derive(Debug)]
+impl PlannedInstall {
+    /// What the keg will be built from once installed.
+    pub fn build(&self) -> BuildId {
+        match self.method {
+            InstallMethod::Bottle(_) => BuildId::bottle(&self.formula),
+            InstallMethod::Source(_) => BuildId::source(&self.formula),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct InstallPlan {
     pub items: Vec<PlannedInstall>,
+    /// Requested names that were aliases, mapped to the formula each stands
+    /// for. Aliases of an installed formula are not planned again.
+    pub aliases: BTreeMap<String, String>,
+    /// Requested names more than one source provides, and the one used.
+    pub tap_choices: Vec<taps::TapChoice>,
+    /// Pinned dependencies left at their installed version rather than
+    /// replaced, mapped to that version.
+    pub pinned: BTreeMap<String, String>,
+}
+
+impl InstallPlan {
+    /// Install names in the order the plan is installed and linked:
+    /// dependencies before dependents, ties broken by name.
+    pub fn link_order(&self) -> Result<Vec<String>, Error> {
+        let dependencies: BTreeMap<String, Vec<String>> = self
+            .items
+            .iter()
+            .map(|item| (item.install_name.clone(), item.formula.dependencies.clone()))
+            .collect();
+        zb_core::topological_order(&dependencies)
+    }
 }
 
 pub struct ExecuteResult {
     pub installed: usize,
+    /// What happened to each planned formula, in link order.
+    pub outcomes: Vec<(String, FormulaOutcome)>,
+    /// Whether each bottle came from the download cache or the network.
+    pub blob_sources: BTreeMap<String, BlobSource>,
+}
+
+#[derive(Debug, Clone)]
+pub enum FormulaOutcome {
+    Installed,
+    /// Not attempted because `dependency` failed.
+    Skipped {
+        dependency: String,
+    },
+    Failed(Error),
+}
+
+/// A plan built with [`Installer::plan_isolated`], together with the
+/// requested formulas that could not be planned.
+#[derive(Debug)]
+pub struct IsolatedPlan {
+    pub plan: InstallPlan,
+    pub not_planned: Vec<(String, FormulaOutcome)>,
+}
+
+/// The failed or skipped planned dependency that keeps `item` from being
+/// installed, if any.
+fn blocked_by(
+    item: &PlannedInstall,
+    planned: &HashSet<String>,
+    outcomes: &HashMap<String, FormulaOutcome>,
+) -> Option<String> {
+    for dep in &item.formula.dependencies {
+        if !planned.contains(dep) {
+            continue;
+        }
+        match outcomes.get(dep) {
+            None | Some(FormulaOutcome::Installed) => {}
+            Some(FormulaOutcome::Skipped { dependency }) => return Some(dependency.clone()),
+            Some(FormulaOutcome::Failed(_)) => return Some(dep.clone()),
+        }
+    }
+    None
 }
 
 /// A package that has a newer version available upstream.
@@ -61,15 +173,46 @@ pub struct OutdatedPackage {
     pub name: String,
     pub installed_version: String,
     pub current_version: String,
+    pub change_kind: ChangeKind,
     #[serde(skip)]
     pub installed_sha256: String,
     #[serde(skip)]
     pub current_sha256: String,
+    /// Where the current bottle is downloaded from; empty for source builds.
+    #[serde(skip)]
+    pub current_url: String,
     #[serde(skip)]
     pub is_source_build: bool,
 }
 
+/// An installed package whose upstream version couldn't be looked up.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UncheckedPackage {
+    pub name: String,
+    pub installed_version: String,
+    /// Why the lookup failed.
+    pub reason: String,
+}
+
+impl std::fmt::Display for UncheckedPackage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.reason)
+    }
+}
+
+/// An installed formula whose cached metadata names a different version.
+/// The cache may be stale; `fetched_at` is when it was last refreshed.
+#[derive(Debug, Clone)]
+pub struct CachedUpdate {
+    pub name: String,
+    pub installed_version: String,
+    pub cached_version: String,
+    pub fetched_at: std::time::SystemTime,
+}
+
 impl Installer {
+    /// An installer for the zerobrew root `root`, which holds the locks,
+    /// keg metadata and search index.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         api_client: ApiClient,
@@ -79,41 +222,160 @@ impl Installer {
         linker: Linker,
         db: Database,
         prefix: PathBuf,
-        locks_dir: PathBuf,
+        root: PathBuf,
     ) -> Self {
+        let downloader =
+            ParallelDownloader::with_client(blob_cache, api_client.http_client().clone());
         Self {
             api_client,
-            downloader: ParallelDownloader::new(blob_cache),
+            downloader,
             store,
             cellar,
             linker,
             db,
+            metadata: KegMetadata::new(&root),
             prefix,
-            locks_dir,
+            locks_dir: root.join("locks"),
+            root,
+            keep_versions: rollback::DEFAULT_KEEP_VERSIONS,
+            hooks: None,
+            installed: RefCell::new(None),
         }
     }
 
+    /// Wait for and take the install lock, which is held until the returned
+    /// file is dropped.
+    pub(crate) fn lock_install(&self) -> Result<File, Error> {
+        let lock_file = File::create(self.locks_dir.join("install.lock"))
+            .map_err(Error::store("failed to create install lock"))?;
+        lock_file
+            .lock_exclusive()
+            .map_err(Error::store("failed to acquire install lock"))?;
+        Ok(lock_file)
+    }
+
+    /// [`Self::lock_install`] without waiting: `None` while another zb
+    /// holds the lock.
+    pub(crate) fn try_lock_install(&self) -> Result<Option<File>, Error> {
+        let lock_file = File::create(self.locks_dir.join("install.lock"))
+            .map_err(Error::store("failed to create install lock"))?;
+        let locked = lock_file
+            .try_lock_exclusive()
+            .map_err(Error::store("failed to acquire install lock"))?;
+        Ok(locked.then_some(lock_file))
+    }
+
     pub fn clear_api_cache(&self) -> Result<usize, Error> {
         self.api_client.clear_cache()
     }
 
+    /// Requests made so far and the connections they needed. Metadata
+    /// fetches and downloads share one pool, so this covers both.
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.api_client.connection_stats()
+    }
+
+    /// The memory budget bottles are unpacked within, and what it has
+    /// admitted so far.
+    pub fn extraction_budget(&self) -> (ExtractionLimits, ExtractionStats) {
+        let budget = self.store.extraction_budget();
+        (budget.limits(), budget.stats())
+    }
+
     pub async fn execute(&mut self, plan: InstallPlan, link: bool) -> Result<ExecuteResult, Error> {
         self.execute_with_progress(plan, link, None).await
     }
 
+    /// Run `plan`, stopping at and returning the first failure: formulas
+    /// after it in link order are not attempted. Use
+    /// [`Self::execute_isolated`] to keep going past failures.
     pub async fn execute_with_progress(
         &mut self,
         plan: InstallPlan,
         link: bool,
         progress: Option<Arc<ProgressCallback>>,
     ) -> Result<ExecuteResult, Error> {
-        let lock_path = self.locks_dir.join("install.lock");
-        let lock_file =
-            File::create(&lock_path).map_err(Error::store("failed to create install lock"))?;
-        lock_file
-            .lock_exclusive()
-            .map_err(Error::store("failed to acquire install lock"))?;
-        let _lock = lock_file;
+        let result = self.execute_hooked(plan, link, progress, true).await?;
+        if let Some(err) = result
+            .outcomes
+            .iter()
+            .find_map(|(_, outcome)| match outcome {
+                FormulaOutcome::Failed(err) => Some(err.clone()),
+                _ => None,
+            })
+        {
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    /// Run `plan`, recording an outcome per formula instead of stopping at
+    /// the first failure. A formula is only installed once its planned
+    /// dependencies are; if one of them failed it is skipped. Errors are
+    /// only returned for problems that affect the whole plan.
+    ///
+    /// Pre-install hooks run before the install lock is taken, and a
+    /// formula whose hook fails counts as failed. Post-install hooks run
+    /// for each installed formula once the lock is released.
+    pub async fn execute_isolated(
+        &mut self,
+        plan: InstallPlan,
+        link: bool,
+        progress: Option<Arc<ProgressCallback>>,
+    ) -> Result<ExecuteResult, Error> {
+        self.execute_hooked(plan, link, progress, false).await
+    }
+
+    /// Run `plan` between the hooks of its requested formulas. With
+    /// `fail_fast`, nothing more is attempted once a formula has failed.
+    async fn execute_hooked(
+        &mut self,
+        plan: InstallPlan,
+        link: bool,
+        progress: Option<Arc<ProgressCallback>>,
+        fail_fast: bool,
+    ) -> Result<ExecuteResult, Error> {
+        let mut vetoed = HashMap::new();
+        let mut versions = HashMap::new();
+        // Hooks are about what was asked for; dependencies installed along
+        // the way run none.
+        for item in plan.items.iter().filter(|item| item.explicit) {
+            let version = item.formula.effective_version();
+            if let Err(e) = self
+                .run_hooks_async(HookPoint::PreInstall, &item.install_name, &version)
+                .await
+            {
+                vetoed.insert(item.install_name.clone(), FormulaOutcome::Failed(e));
+            }
+            versions.insert(item.install_name.clone(), version);
+        }
+
+        let result = self
+            .execute_locked(plan, link, progress, vetoed, fail_fast)
+            .await?;
+
+        for (name, outcome) in &result.outcomes {
+            if let (FormulaOutcome::Installed, Some(version)) = (outcome, versions.get(name)) {
+                // Post hook failures are logged and don't fail the install.
+                let _ = self
+                    .run_hooks_async(HookPoint::PostInstall, name, version)
+                    .await;
+            }
+        }
+        Ok(result)
+    }
+
+    /// [`Self::execute_hooked`] under the install lock. Formulas already
+    /// in `outcomes` are not attempted.
+    async fn execute_locked(
+        &mut self,
+        plan: InstallPlan,
+        link: bool,
+        progress: Option<Arc<ProgressCallback>>,
+        mut outcomes: HashMap<String, FormulaOutcome>,
+        fail_fast: bool,
+    ) -> Result<ExecuteResult, Error> {
+        let _lock = self.lock_install()?;
 
         let report = |event: InstallProgress| {
             if let Some(ref cb) = progress {
@@ -121,93 +383,213 @@ impl Installer {
             }
         };
 
-        let (bottle_items, source_items): (Vec<_>, Vec<_>) = plan
+        // Items are installed and linked strictly in link order, so the
+        // result doesn't depend on which download finishes first.
+        let order = plan.link_order()?;
+        let mut items: HashMap<String, PlannedInstall> = plan
             .items
             .into_iter()
-            .partition(|item| matches!(item.method, InstallMethod::Bottle(_)));
+            .map(|item| (item.install_name.clone(), item))
+            .collect();
+        let items: Vec<PlannedInstall> =
+            order.iter().filter_map(|name| items.remove(name)).collect();
 
-        if bottle_items.is_empty() && source_items.is_empty() {
-            return Ok(ExecuteResult { installed: 0 });
-        }
+        let planned: HashSet<String> = order.iter().cloned().collect();
+        let mut blob_sources = BTreeMap::new();
 
-        let mut installed = 0usize;
-        let mut error: Option<Error> = None;
+        let download_progress: Option<DownloadProgressCallback> = progress.clone().map(|cb| {
+            Arc::new(move |event: InstallProgress| {
+                cb(event);
+            }) as DownloadProgressCallback
+        });
+
+        // Store entries `zb gc --archive` compressed are expanded again
+        // instead of downloaded.
+        let mut restored = HashSet::new();
+        for item in &items {
+            let InstallMethod::Bottle(ref bottle) = item.method else {
+                continue;
+            };
+            if outcomes.contains_key(&item.install_name)
+                || self.store.contains(&bottle.sha256)
+                || !self.store.is_archived(&bottle.sha256)
+            {
+                continue;
+            }
+            let restore = self
+                .store
+                .restore(&bottle.sha256)
+                .await
+                .and_then(|_| self.db.set_store_archived(&bottle.sha256, false));
+            match restore {
+                Ok(()) => {
+                    restored.insert(item.install_name.clone());
+                }
+                Err(e) => warn!(
+                    formula = %item.formula.name,
+                    error = %e,
+                    "failed to expand archived store entry; downloading the bottle instead"
+                ),
+            }
+        }
 
-        if !bottle_items.is_empty() {
-            let requests: Vec<DownloadRequest> = bottle_items
-                .iter()
-                .map(|item| {
-                    let InstallMethod::Bottle(ref bottle) = item.method else {
-                        unreachable!()
-                    };
-                    DownloadRequest {
-                        url: bottle.url.clone(),
-                        sha256: bottle.sha256.clone(),
-                        name: item.formula.name.clone(),
+        // Every other bottle is downloaded up front; `bottle_positions` maps
+        // a download's index back to its item.
+        let bottle_positions: Vec<usize> = items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| {
+                matches!(item.method, InstallMethod::Bottle(_))
+                    && !outcomes.contains_key(&item.install_name)
+                    && !restored.contains(&item.install_name)
+            })
+            .map(|(position, _)| position)
+            .collect();
+        let requests: Vec<DownloadRequest> = bottle_positions
+            .iter()
+            .map(|&position| {
+                let item = &items[position];
+                let InstallMethod::Bottle(ref bottle) = item.method else {
+                    unreachable!()
+                };
+                DownloadRequest {
+                    url: bottle.url.clone(),
+                    sha256: bottle.sha256.clone(),
+                    name: item.formula.name.clone(),
+                }
+            })
+            .collect();
+        let mut rx = (!requests.is_empty()).then(|| {
+            self.downloader
+                .download_streaming(requests, download_progress.clone())
+        });
+        let mut downloads: HashMap<usize, DownloadResult> = HashMap::new();
+        // Bottles are unpacked into the store as soon as they arrive, while
+        // earlier items are installed, as many at once as the extraction
+        // budget admits.
+        let mut extractions: HashMap<usize, tokio::task::JoinHandle<()>> = HashMap::new();
+
+        for (position, item) in items.iter().enumerate() {
+            // Checked before every formula, so a failure stops the ones
+            // still queued behind it, not just the next download.
+            if fail_fast
+                && outcomes
+                    .values()
+                    .any(|outcome| matches!(outcome, FormulaOutcome::Failed(_)))
+            {
+                break;
+            }
+            if outcomes.contains_key(&item.install_name) {
+                continue;
+            }
+            let outcome = match item.method {
+                InstallMethod::Bottle(_) => {
+                    // Downloads finish in any order; wait for this one.
+                    while !restored.contains(&item.install_name)
+                        && !downloads.contains_key(&position)
+                        && !outcomes.contains_key(&item.install_name)
+                    {
+                        let received = match rx.as_mut() {
+                            Some(rx) => rx.recv().await,
+                            None => None,
+                        };
+                        let Some((index, result)) = received else {
+                            break;
+                        };
+                        let downloaded = &items[bottle_positions[index]].install_name;
+                        match result {
+                            Ok(download) => {
+                                self.index_blob(
+                                    &download.sha256,
+                                    &items[bottle_positions[index]].formula.name,
+                                    matches!(download.source, BlobSource::Downloaded { .. }),
+                                );
+                                blob_sources.insert(downloaded.clone(), download.source);
+                                let store = self.store.clone();
+                                let (sha256, blob) =
+                                    (download.sha256.clone(), download.blob_path.clone());
+                                extractions.insert(
+                                    bottle_positions[index],
+                                    tokio::spawn(async move {
+                                        let _ = store.ensure_entry(&sha256, &blob).await;
+                                    }),
+                                );
+                                downloads.insert(bottle_positions[index], download);
+                            }
+                            Err(e) => {
+                                outcomes.insert(downloaded.clone(), FormulaOutcome::Failed(e));
+                            }
+                        }
                     }
-                })
-                .collect();
-
-            let download_progress: Option<DownloadProgressCallback> = progress.clone().map(|cb| {
-                Arc::new(move |event: InstallProgress| {
-                    cb(event);
-                }) as DownloadProgressCallback
-            });
-
-            let mut rx = self
-                .downloader
-                .download_streaming(requests, download_progress.clone());
-
-            while let Some(result) = rx.recv().await {
-                match result {
-                    Ok(download) => {
-                        match self
+                    let download = downloads.remove(&position);
+                    if download.is_none() && !restored.contains(&item.install_name) {
+                        continue;
+                    }
+                    // A failed early extraction is tried again below, which
+                    // downloads a corrupt bottle anew.
+                    if let Some(extraction) = extractions.remove(&position) {
+                        let _ = extraction.await;
+                    }
+
+                    match blocked_by(item, &planned, &outcomes) {
+                        Some(dependency) => FormulaOutcome::Skipped { dependency },
+                        None => match self
                             .process_bottle_item(
-                                &bottle_items[download.index],
-                                &download,
+                                item,
+                                download.as_ref(),
                                 &download_progress,
                                 link,
                                 &report,
                             )
                             .await
                         {
-                            Ok(()) => installed += 1,
-                            Err(e) => error = Some(e),
-                        }
+                            Ok(()) => FormulaOutcome::Installed,
+                            Err(e) => FormulaOutcome::Failed(e),
+                        },
                     }
-                    Err(e) => {
-                        error = Some(e);
+                }
+                InstallMethod::Source(ref build_plan) => {
+                    match blocked_by(item, &planned, &outcomes) {
+                        Some(dependency) => FormulaOutcome::Skipped { dependency },
+                        None => {
+                            report(InstallProgress::UnpackStarted {
+                                name: item.formula.name.clone(),
+                            });
+                            match self
+                                .install_from_source(item, build_plan, link, &report)
+                                .await
+                            {
+                                Ok(()) => FormulaOutcome::Installed,
+                                Err(e) => FormulaOutcome::Failed(e),
+                            }
+                        }
                     }
                 }
-            }
-        }
-
-        for item in &source_items {
-            let InstallMethod::Source(ref build_plan) = item.method else {
-                unreachable!()
             };
-
-            report(InstallProgress::UnpackStarted {
-                name: item.formula.name.clone(),
-            });
-
-            match self
-                .install_from_source(item, build_plan, link, &report)
-                .await
-            {
-                Ok(()) => installed += 1,
-                Err(e) => {
-                    error = Some(e);
-                    continue;
-                }
-            }
+            outcomes.insert(item.install_name.clone(), outcome);
         }
-
-        if let Some(e) = error {
-            return Err(e);
+        // Bottles of skipped items may still be unpacking.
+        for (_, extraction) in extractions {
+            let _ = extraction.await;
         }
 
-        Ok(ExecuteResult { installed })
+        let installed = outcomes
+            .values()
+            .filter(|outcome| matches!(outcome, FormulaOutcome::Installed))
+            .count();
+        let outcomes = order
+            .into_iter()
+            .filter_map(|name| {
+                let outcome = outcomes.remove(&name)?;
+                Some((name, outcome))
+            })
+            .collect();
+
+        Ok(ExecuteResult {
+            installed,
+            outcomes,
+            blob_sources,
+        })
     }
 
     pub async fn install(&mut self, names: &[String], link: bool) -> Result<ExecuteResult, Error> {
@@ -216,18 +598,27 @@ impl Installer {
             .cloned()
             .partition(|name| name.starts_with("cask:"));
 
-        let mut installed = 0usize;
+        let mut result = ExecuteResult {
+            installed: 0,
+            outcomes: Vec::new(),
+            blob_sources: BTreeMap::new(),
+        };
 
         if !formulas.is_empty() {
             let plan = self.plan(&formulas).await?;
-            installed += self.execute(plan, link).await?.installed;
+            let executed = self.execute(plan, link).await?;
+            result.installed += executed.installed;
+            result.outcomes.extend(executed.outcomes);
+            result.blob_sources.extend(executed.blob_sources);
         }
 
         if !casks.is_empty() {
-            installed += self.install_casks(&casks, link).await?.installed;
+            let executed = self.install_casks(&casks, link).await?;
+            result.installed += executed.installed;
+            result.outcomes.extend(executed.outcomes);
         }
 
-        Ok(ExecuteResult { installed })
+        Ok(result)
     }
 
     pub async fn install_casks(
@@ -235,33 +626,166 @@ impl Installer {
         names: &[String],
         link: bool,
     ) -> Result<ExecuteResult, Error> {
-        let mut installed = 0usize;
+        let mut outcomes = Vec::with_capacity(names.len());
         for name in names {
             let token = name
                 .strip_prefix("cask:")
                 .expect("install_casks expects cask: prefixed names");
             self.install_single_cask(token, link).await?;
-            installed += 1;
+            outcomes.push((name.clone(), FormulaOutcome::Installed));
         }
-        Ok(ExecuteResult { installed })
+        Ok(ExecuteResult {
+            installed: outcomes.len(),
+            outcomes,
+            blob_sources: BTreeMap::new(),
+        })
     }
 
+    /// For filtering plans, where a database error surfaces as soon as the
+    /// install is recorded anyway. Use [`Installer::get_installed`] when a
+    /// failed lookup must not read as "not installed".
     pub fn is_installed(&self, name: &str) -> bool {
-        self.db.get_installed(name).is_some()
+        self.installed_set()
+            .is_ok_and(|installed| installed.contains(name))
     }
 
-    pub fn get_installed(&self, name: &str) -> Option<crate::storage::db::InstalledKeg> {
-        self.db.get_installed(name)
+    pub fn get_installed(
+        &self,
+        name: &str,
+    ) -> Result<Option<crate::storage::db::InstalledKeg>, Error> {
+        Ok(self.installed_set()?.get(name).cloned())
+    }
+
+    /// Every installed keg, read once and reread only after something was
+    /// written to the database, so checking hundreds of names costs one
+    /// query rather than one each.
+    pub fn installed_set(&self) -> Result<Rc<InstalledSet>, Error> {
+        let mut cached = self.installed.borrow_mut();
+        if let Some(set) = cached.as_ref()
+            && self.db.is_current(set)
+        {
+            return Ok(Rc::clone(set));
+        }
+        let set = Rc::new(self.db.load_installed_set()?);
+        *cached = Some(Rc::clone(&set));
+        Ok(set)
     }
 
     pub fn list_installed(&self) -> Result<Vec<crate::storage::db::InstalledKeg>, Error> {
         self.db.list_installed()
     }
 
+    /// Pin `name` so upgrades skip it, or unpin it. Fails with
+    /// [`Error::NotInstalled`] when it isn't installed.
+    pub fn set_pinned(&self, name: &str, pinned: bool) -> Result<(), Error> {
+        self.db.set_pinned(name, pinned)
+    }
+
+    /// Check that this root belongs to the running platform, recording
+    /// `zb_version` as the latest zb to use it. Returns the previously
+    /// recorded version when it differs.
+    pub fn check_system(&mut self, zb_version: &str) -> Result<Option<String>, Error> {
+        self.db.check_system(&SystemRecord::current(zb_version))
+    }
+
+    pub fn system_record(&self) -> Result<Option<SystemRecord>, Error> {
+        self.db.system_record()
+    }
+
     pub fn keg_path(&self, name: &str, version: &str) -> PathBuf {
         self.cellar.keg_path(name, version)
     }
 
+    /// The prefix paths recorded for `version` of `name` when it was linked.
+    pub fn keg_files(
+        &self,
+        name: &str,
+        version: &str,
+    ) -> Result<Vec<crate::storage::db::KegFileRecord>, Error> {
+        self.db.list_keg_files_for(name, version)
+    }
+
+    /// Add the tree at `src` to the store and register it, unreferenced, so
+    /// it can be materialized like a bottle's entry or collected by gc.
+    pub fn import_store_dir(&self, src: &Path) -> Result<StoreKey, Error> {
+        let store_key = self.store.add_dir(src)?;
+        self.db.register_store_entry(&store_key)?;
+        Ok(store_key)
+    }
+
+    /// Override the `patch.json` policy for files that can't be patched.
+    pub fn set_patch_policy(&mut self, policy: PatchPolicy) {
+        self.cellar.set_patch_policy(policy);
+    }
+
+    /// Keep group/other write bits from bottles when materializing kegs.
+    pub fn set_preserve_permissions(&mut self, preserve: bool) {
+        self.cellar.set_preserve_permissions(preserve);
+    }
+
+    /// Snapshot formula metadata for `names`, or for every installed formula
+    /// when `names` is empty.
+    pub async fn create_snapshot(&self, names: &[String]) -> Result<FormulaSnapshot, Error> {
+        let names = if names.is_empty() {
+            self.db
+                .list_installed()?
+                .into_iter()
+                .map(|keg| keg.name)
+                .collect()
+        } else {
+            names.to_vec()
+        };
+        self.api_client.create_snapshot(&names).await
+    }
+
+    fn record_install_details(
+        tx: &InstallTransaction<'_>,
+        item: &PlannedInstall,
+        previously_explicit: bool,
+        prefix: &Path,
+    ) -> Result<(), Error> {
+        tx.record_install_reason(&item.install_name, item.explicit || previously_explicit)?;
+        tx.record_patched_prefix(&item.install_name, prefix)?;
+        tx.claim_prefix(prefix)?;
+        tx.record_license(&item.install_name, item.formula.license.as_deref())?;
+        tx.record_upstream_status(
+            &item.install_name,
+            &item.formula.lifecycle(),
+            item.formula.lifecycle_date(),
+        )?;
+        if let Some(rebuild) = item.build().rebuild {
+            tx.record_rebuild(&item.install_name, rebuild)?;
+        }
+        tx.record_dependencies(&item.install_name, &item.formula.dependencies)
+    }
+
+    /// The metadata the installed version of `name` was installed from,
+    /// which decisions about the installed keg prefer over the live API.
+    /// `None` when `name` isn't installed or was installed before metadata
+    /// was kept.
+    #[cfg(test)]
+    pub(crate) fn installed_formula(&self, name: &str) -> Result<Option<Formula>, Error> {
+        match self.db.get_installed(name)? {
+            Some(keg) => self.metadata.read(name, &keg.version),
+            None => Ok(None),
+        }
+    }
+
+    /// Keep the metadata `version` of `name` was installed from. Failing to
+    /// keep it never fails the install.
+    fn keep_metadata(&self, name: &str, version: &str, formula: &Formula) {
+        if let Err(e) = self.metadata.write(name, version, formula) {
+            warn!(formula = %name, version = %version, error = %e, "failed to keep formula metadata");
+        }
+    }
+
+    /// Drop the metadata kept for a keg that was just removed.
+    fn forget_metadata(&self, name: &str, version: &str) {
+        if let Err(e) = self.metadata.remove(name, version) {
+            warn!(formula = %name, version = %version, error = %e, "failed to remove formula metadata");
+        }
+    }
+
     fn cleanup_materialized(cellar: &Cellar, name: &str, version: &str) {
         if let Err(e) = cellar.remove_keg(name, version) {
             warn!(
@@ -274,10 +798,37 @@ impl Installer {
     }
 }
 
+/// Why zb won't use `prefix` for a root whose formulas are installed under
+/// `recorded`, and how to fix it.
+pub(crate) fn prefix_conflict_error(recorded: &Path, prefix: &Path) -> Error {
+    Error::InvalidArgument {
+        message: format!(
+            "this root's formulas are installed under prefix {}, but zb is using {}; \
+             mixing the two would leave the database describing links that aren't there.\n\
+             To keep using them, pass --prefix {} or set ZEROBREW_PREFIX={} \
+             ('zb --prefix {} init' updates your shell). \
+             To move them, run 'zb --prefix {} relocate --prefix {}'. \
+             To go ahead anyway, pass --accept-prefix-change.",
+            recorded.display(),
+            prefix.display(),
+            recorded.display(),
+            recorded.display(),
+            recorded.display(),
+            recorded.display(),
+            prefix.display()
+        ),
+    }
+}
+
+/// Open the installer for `root` and `prefix`. A root whose formulas were
+/// installed under another prefix is refused unless `accept_prefix_change`
+/// is set, since the database would describe links that aren't there.
 pub fn create_installer(
     root: &Path,
     prefix: &Path,
+    cache_dir: &Path,
     concurrency: usize,
+    accept_prefix_change: bool,
 ) -> Result<Installer, Error> {
     if !root.exists() {
         fs::create_dir_all(root).map_err(|e| {
@@ -301,32 +852,64 @@ pub fn create_installer(
 
     fs::create_dir_all(root.join("db")).map_err(Error::store("failed to create db directory"))?;
 
-    fs::create_dir_all(root.join("cache"))
-        .map_err(Error::store("failed to create cache directory"))?;
+    fs::create_dir_all(cache_dir).map_err(Error::store("failed to create cache directory"))?;
 
-    let api_cache_path = root.join("cache/api-cache.sqlite");
-    let api_cache =
-        ApiCache::open(&api_cache_path).map_err(Error::store("failed to open API cache"))?;
+    // One pool for metadata and bottles, so connections to the same hosts
+    // are reused for the whole run.
+    let http = HttpClient::new();
+    let mut api_client = configured_api_client(cache_dir, http.clone())?;
 
-    let api_client = match std::env::var("ZEROBREW_API_URL") {
-        Ok(url) => ApiClient::with_base_url(url)?,
-        Err(_) => ApiClient::new(),
+    if let Some(path) = std::env::var_os("ZEROBREW_SNAPSHOT").filter(|p| !p.is_empty()) {
+        api_client = api_client.with_snapshot(FormulaSnapshot::read(Path::new(&path))?);
     }
-    .with_cache(api_cache);
 
     let blob_cache =
-        BlobCache::new(&root.join("cache")).map_err(Error::store("failed to create blob cache"))?;
-    let store = Store::new(root).map_err(Error::store("failed to create store"))?;
+        BlobCache::new(cache_dir).map_err(Error::store("failed to create blob cache"))?;
+    let mut store = Store::new(root).map_err(Error::store("failed to create store"))?;
+    if let Ok(value) = std::env::var("ZEROBREW_EXTRACT_MEMORY_MB") {
+        match value.trim().parse::<u64>() {
+            Ok(mb) => {
+                let bytes = mb.checked_mul(1 << 20).unwrap_or_else(|| {
+                    warn!(value = %value, "ZEROBREW_EXTRACT_MEMORY_MB is too large; using no limit");
+                    u64::MAX
+                });
+                store.set_extraction_budget(ExtractionBudget::new(bytes));
+            }
+            Err(e) => {
+                warn!(value = %value, error = %e, "ignoring ZEROBREW_EXTRACT_MEMORY_MB; expected a number of megabytes")
+            }
+        }
+    }
     // Use prefix/Cellar so bottles' hardcoded rpaths work
-    let cellar =
-        Cellar::new_at(prefix.join("Cellar")).map_err(Error::store("failed to create cellar"))?;
+    // The Cellar compares devices per store entry and copies when the store
+    // (under root) is on another filesystem.
+    let cellar = Cellar::new_at(prefix.join(CELLAR_DIR))
+        .map_err(Error::store("failed to create cellar"))?
+        .with_patch_config(PatchConfig::load(&root.join("patch.json"))?)
+        .with_thread_pool(Arc::new(patch_thread_pool(concurrency)?));
     let linker = Linker::new(prefix).map_err(Error::store("failed to create linker"))?;
     let db = Database::open(&root.join("db/zb.sqlite3"))?;
+    match db.prefix_conflict(prefix)? {
+        Some(recorded) if !accept_prefix_change => {
+            return Err(prefix_conflict_error(&recorded, prefix));
+        }
+        Some(_) => {}
+        None => db.release_prefix(prefix)?,
+    }
 
     let locks_dir = root.join("locks");
     fs::create_dir_all(&locks_dir).map_err(Error::store("failed to create locks directory"))?;
 
-    let parallel_downloader = ParallelDownloader::with_concurrency(blob_cache, concurrency);
+    let mut parallel_downloader =
+        ParallelDownloader::with_concurrency(blob_cache, concurrency, http)
+            .with_credentials(RegistryCredentials::from_env()?);
+    if let Some(secs) = std::env::var("ZEROBREW_MAX_RATE_LIMIT_WAIT")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+    {
+        parallel_downloader =
+            parallel_downloader.with_max_rate_limit_wait(std::time::Duration::from_secs(secs));
+    }
 
     Ok(Installer {
         api_client,
@@ -335,13 +918,159 @@ pub fn create_installer(
         cellar,
         linker,
         db,
+        metadata: KegMetadata::new(root),
         prefix: prefix.to_path_buf(),
+        root: root.to_path_buf(),
         locks_dir,
+        keep_versions: rollback::DEFAULT_KEEP_VERSIONS,
+        hooks: None,
+        installed: RefCell::new(None),
     })
 }
 
+/// The metadata client for `ZEROBREW_API_URL` (or the default API), with
+/// its responses cached in `cache_dir`.
+fn configured_api_client(cache_dir: &Path, http: HttpClient) -> Result<ApiClient, Error> {
+    let api_cache_path = cache_dir.join("api-cache.sqlite");
+    let api_cache =
+        ApiCache::open(&api_cache_path).map_err(Error::store("failed to open API cache"))?;
+    let api_client = match std::env::var("ZEROBREW_API_URL") {
+        Ok(url) => ApiClient::with_base_url(url)?,
+        Err(_) => ApiClient::new(),
+    };
+    Ok(api_client.with_http_client(http).with_cache(api_cache))
+}
+
+/// Threads for patching and other per-file work: `concurrency`, but never
+/// more than the machine has cores.
+fn patch_thread_pool(concurrency: usize) -> Result<rayon::ThreadPool, Error> {
+    let cores = std::thread::available_parallelism().map_or(1, usize::from);
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(concurrency.clamp(1, cores))
+        .thread_name(|index| format!("zb-worker-{index}"))
+        .build()
+        .map_err(Error::store("failed to start worker threads"))
+}
+
 #[cfg(test)]
 mod test_support {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::Installer;
+    use crate::cellar::link::Linker;
+    use crate::cellar::materialize::Cellar;
+    use crate::network::api::ApiClient;
+    use crate::storage::blob::BlobCache;
+    use crate::storage::db::Database;
+    use crate::storage::store::Store;
+
+    /// An API client for the formulas `server` serves under `/formula`.
+    pub fn api_client(server: &MockServer) -> ApiClient {
+        ApiClient::with_base_url(format!("{}/formula", server.uri())).unwrap()
+    }
+
+    /// An installer with its root at `zerobrew` and its prefix at
+    /// `homebrew` in `tmp`, fetching formulas from `server`.
+    pub fn test_installer(server: &MockServer, tmp: &TempDir) -> Installer {
+        let root = tmp.path().join("zerobrew");
+        installer_at(
+            api_client(server),
+            &root,
+            &tmp.path().join("homebrew"),
+            root.join("Cellar"),
+        )
+    }
+
+    /// An installer with its root, prefix and Cellar where a test needs
+    /// them.
+    pub fn installer_at(
+        api_client: ApiClient,
+        root: &Path,
+        prefix: &Path,
+        cellar_dir: PathBuf,
+    ) -> Installer {
+        fs::create_dir_all(root.join("db")).unwrap();
+        Installer::new(
+            api_client,
+            BlobCache::new(&root.join("cache")).unwrap(),
+            Store::new(root).unwrap(),
+            Cellar::new_at(cellar_dir).unwrap(),
+            Linker::new(prefix).unwrap(),
+            Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+            prefix.to_path_buf(),
+            root.to_path_buf(),
+        )
+    }
+
+    /// The API's metadata for `name` at `version` depending on `deps`, with
+    /// a bottle for this platform at `/bottles/{name}.tar.gz` on `server`.
+    pub fn formula_json(
+        server: &MockServer,
+        name: &str,
+        version: &str,
+        deps: &[&str],
+        sha256: &str,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "name": name,
+            "versions": { "stable": version },
+            "dependencies": deps,
+            "bottle": { "stable": { "files": { get_test_bottle_tag(): {
+                "url": format!("{}/bottles/{name}.tar.gz", server.uri()),
+                "sha256": sha256,
+            } } } }
+        })
+    }
+
+    /// Serve `formula` as the metadata of the formula it names.
+    pub async fn mount_metadata(server: &MockServer, formula: &serde_json::Value) {
+        let name = formula["name"].as_str().unwrap();
+        Mock::given(method("GET"))
+            .and(path(format!("/formula/{name}.json")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula))
+            .mount(server)
+            .await;
+    }
+
+    /// Serve `name` at `version` depending on `deps`, with `bottle` as its
+    /// bottle, or only its metadata without one. Returns the bottle's
+    /// sha256.
+    pub async fn mount_formula(
+        server: &MockServer,
+        name: &str,
+        version: &str,
+        deps: &[&str],
+        bottle: Option<Vec<u8>>,
+    ) -> String {
+        let sha256 = bottle.as_deref().map_or_else(|| "0".repeat(64), sha256_hex);
+        mount_metadata(server, &formula_json(server, name, version, deps, &sha256)).await;
+        if let Some(bottle) = bottle {
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+                .mount(server)
+                .await;
+        }
+        sha256
+    }
+
+    /// Serve `name` 1.0.0 depending on `deps` with the stock test bottle.
+    pub async fn mount_bottled(server: &MockServer, name: &str, deps: &[&str]) -> String {
+        mount_formula(
+            server,
+            name,
+            "1.0.0",
+            deps,
+            Some(create_bottle_tarball(name)),
+        )
+        .await
+    }
+
     pub fn create_bottle_tarball(formula_name: &str) -> Vec<u8> {
         use flate2::Compression;
         use flate2::write::GzEncoder;
@@ -368,6 +1097,35 @@ mod test_support {
         encoder.finish().unwrap()
     }
 
+    /// A bottle for `formula_name` 1.0.0 holding `files`, given relative to
+    /// the keg as `(path, contents)`.
+    pub fn bottle_with_files(formula_name: &str, files: &[(&str, &[u8])]) -> Vec<u8> {
+        versioned_bottle(formula_name, "1.0.0", files)
+    }
+
+    pub fn versioned_bottle(formula_name: &str, version: &str, files: &[(&str, &[u8])]) -> Vec<u8> {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header
+                .set_path(format!("{formula_name}/{version}/{path}"))
+                .unwrap();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append(&header, *content).unwrap();
+        }
+        let tar_data = builder.into_inner().unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tar_data).unwrap();
+        encoder.finish().unwrap()
+    }
+
     pub fn sha256_hex(data: &[u8]) -> String {
         use sha2::{Digest, Sha256};
         let mut hasher = Sha256::new();
@@ -399,8 +1157,10 @@ mod tests {
 
     use crate::cellar::Cellar;
     use crate::network::api::ApiClient;
+    use crate::network::download::BlobSource;
+    use crate::progress::{InstallProgress, ProgressCallback};
     use crate::storage::blob::BlobCache;
-    use crate::storage::db::Database;
+    use crate::storage::db::{Database, KegFileKind};
     use crate::storage::store::Store;
     use crate::{Installer, Linker};
 
@@ -454,25 +1214,12 @@ mod tests {
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
 
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         installer
@@ -480,32 +1227,101 @@ mod tests {
             .await
             .unwrap();
 
-        assert!(root.join("cellar/testpkg/1.0.0").exists());
+        assert!(root.join("Cellar/testpkg/1.0.0").exists());
         assert!(prefix.join("bin/testpkg").exists());
 
-        let installed = installer.db.get_installed("testpkg");
+        let installed = installer.db.get_installed("testpkg").unwrap();
         assert!(installed.is_some());
         assert_eq!(installed.unwrap().version, "1.0.0");
     }
 
     #[tokio::test]
-    async fn install_with_dependencies() {
+    async fn keg_follows_the_bottles_version_directory() {
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
 
-        let dep_bottle = create_bottle_tarball("deplib");
-        let dep_sha = sha256_hex(&dep_bottle);
-        let main_bottle = create_bottle_tarball("mainpkg");
-        let main_sha = sha256_hex(&main_bottle);
-
+        let bottle = versioned_bottle("testpkg", "1.0.0_1", &[("bin/testpkg", b"#!/bin/sh\n")]);
+        let bottle_sha = sha256_hex(&bottle);
         let tag = get_test_bottle_tag();
-        let dep_json = format!(
-            r#"{{"name":"deplib","versions":{{"stable":"1.0.0"}},"dependencies":[],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/deplib-1.0.0.{}.bottle.tar.gz","sha256":"{}"}}}}}}}}}}"#,
-            tag,
-            mock_server.uri(),
-            tag,
-            dep_sha
-        );
+        let formula_json = format!(
+            r#"{{
+                "name": "testpkg",
+                "versions": {{ "stable": "1.0.0" }},
+                "dependencies": [],
+                "bottle": {{
+                    "stable": {{
+                        "rebuild": 1,
+                        "files": {{
+                            "{tag}": {{
+                                "url": "{}/bottles/testpkg-1.0.0.{tag}.bottle.1.tar.gz",
+                                "sha256": "{bottle_sha}"
+                            }}
+                        }}
+                    }}
+                }}
+            }}"#,
+            mock_server.uri(),
+        );
+        Mock::given(method("GET"))
+            .and(path("/formula/testpkg.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(&formula_json))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/bottles/testpkg-1.0.0.{tag}.bottle.1.tar.gz"
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+
+        installer
+            .install(&["testpkg".to_string()], true)
+            .await
+            .unwrap();
+
+        let keg = root.join("Cellar/testpkg/1.0.0_1");
+        assert!(keg.join("bin/testpkg").exists());
+        assert!(!root.join("Cellar/testpkg/1.0.0").exists());
+        assert_eq!(
+            fs::canonicalize(prefix.join("bin/testpkg")).unwrap(),
+            fs::canonicalize(keg.join("bin/testpkg")).unwrap()
+        );
+
+        let installed = installer.db.get_installed("testpkg").unwrap().unwrap();
+        assert_eq!(installed.version, "1.0.0_1");
+        assert_eq!(installed.formula_version.as_deref(), Some("1.0.0"));
+        assert_eq!(installed.build().to_string(), "1.0.0 (rebuild 1)");
+        assert!(installer.is_outdated("testpkg").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn install_with_dependencies() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let dep_bottle = create_bottle_tarball("deplib");
+        let dep_sha = sha256_hex(&dep_bottle);
+        let main_bottle = create_bottle_tarball("mainpkg");
+        let main_sha = sha256_hex(&main_bottle);
+
+        let tag = get_test_bottle_tag();
+        let dep_json = format!(
+            r#"{{"name":"deplib","versions":{{"stable":"1.0.0"}},"dependencies":[],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/deplib-1.0.0.{}.bottle.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+            tag,
+            mock_server.uri(),
+            tag,
+            dep_sha
+        );
         let main_json = format!(
             r#"{{"name":"mainpkg","versions":{{"stable":"2.0.0"}},"dependencies":["deplib"],"bottle":{{"stable":{{"files":{{"{}":{{"url":"{}/bottles/mainpkg-2.0.0.{}.bottle.tar.gz","sha256":"{}"}}}}}}}}}}"#,
             tag,
@@ -540,25 +1356,12 @@ mod tests {
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
 
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         installer
@@ -566,8 +1369,47 @@ mod tests {
             .await
             .unwrap();
 
-        assert!(installer.db.get_installed("mainpkg").is_some());
-        assert!(installer.db.get_installed("deplib").is_some());
+        assert!(installer.db.get_installed("mainpkg").unwrap().is_some());
+        assert!(installer.db.get_installed("deplib").unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn install_hooks_run_only_for_requested_formulas() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_bottled(&server, "deplib", &[]).await;
+        mount_bottled(&server, "mainpkg", &["deplib"]).await;
+
+        let hooks = tmp.path().join("hooks");
+        let log = tmp.path().join("hooks.log");
+        for point in ["pre-install", "post-install"] {
+            let script = hooks.join(format!("{point}.d/log"));
+            fs::create_dir_all(script.parent().unwrap()).unwrap();
+            fs::write(
+                &script,
+                format!(
+                    "#!/bin/sh\necho \"$ZEROBREW_HOOK_ACTION $ZEROBREW_HOOK_FORMULA\" >> {}\n",
+                    log.display()
+                ),
+            )
+            .unwrap();
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let mut installer = test_installer(&server, &tmp);
+        installer.set_hooks(Some(super::hooks::Hooks::new(hooks)));
+        installer
+            .install(&["mainpkg".to_string()], true)
+            .await
+            .unwrap();
+
+        assert!(installer.db.get_installed("deplib").unwrap().is_some());
+        assert_eq!(
+            fs::read_to_string(&log).unwrap(),
+            "pre-install mainpkg\npost-install mainpkg\n"
+        );
     }
 
     #[tokio::test]
@@ -603,14 +1445,14 @@ mod tests {
 
         let bad_json = format!(
             r#"{{
-                "name": "badpkg",
+                "name": "zbadpkg",
                 "versions": {{ "stable": "1.0.0" }},
                 "dependencies": [],
                 "bottle": {{
                     "stable": {{
                         "files": {{
                             "{}": {{
-                                "url": "{}/bottles/badpkg-1.0.0.{}.bottle.tar.gz",
+                                "url": "{}/bottles/zbadpkg-1.0.0.{}.bottle.tar.gz",
                                 "sha256": "{}"
                             }}
                         }}
@@ -629,7 +1471,7 @@ mod tests {
             .mount(&mock_server)
             .await;
         Mock::given(method("GET"))
-            .and(path("/formula/badpkg.json"))
+            .and(path("/formula/zbadpkg.json"))
             .respond_with(ResponseTemplate::new(200).set_body_string(&bad_json))
             .mount(&mock_server)
             .await;
@@ -642,7 +1484,10 @@ mod tests {
             .mount(&mock_server)
             .await;
         Mock::given(method("GET"))
-            .and(path(format!("/bottles/badpkg-1.0.0.{}.bottle.tar.gz", tag)))
+            .and(path(format!(
+                "/bottles/zbadpkg-1.0.0.{}.bottle.tar.gz",
+                tag
+            )))
             .respond_with(
                 ResponseTemplate::new(500)
                     .set_delay(Duration::from_millis(100))
@@ -653,35 +1498,24 @@ mod tests {
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
+        // zbadpkg is installed after goodpkg, so its failure stops the run
+        // only once goodpkg is in.
         let result = installer
-            .install(&["goodpkg".to_string(), "badpkg".to_string()], false)
+            .install(&["goodpkg".to_string(), "zbadpkg".to_string()], false)
             .await;
         assert!(result.is_err());
 
-        assert!(installer.db.get_installed("goodpkg").is_some());
-        assert!(installer.db.get_installed("badpkg").is_none());
-        assert!(root.join("cellar/goodpkg/1.0.0").exists());
+        assert!(installer.db.get_installed("goodpkg").unwrap().is_some());
+        assert!(installer.db.get_installed("zbadpkg").unwrap().is_none());
+        assert!(root.join("Cellar/goodpkg/1.0.0").exists());
     }
 
     #[tokio::test]
@@ -750,27 +1584,103 @@ mod tests {
             linker,
             db,
             prefix.clone(),
-            root.join("locks"),
+            root.to_path_buf(),
         );
 
+        let plan = installer.plan(&["rollbackme".to_string()]).await.unwrap();
         let conn = rusqlite::Connection::open(&db_path).unwrap();
         conn.execute("DROP TABLE installed_kegs", []).unwrap();
 
-        let result = installer.install(&["rollbackme".to_string()], true).await;
+        let result = installer.execute(plan, true).await;
         assert!(result.is_err());
 
-        assert!(!root.join("cellar/rollbackme/1.0.0").exists());
+        assert!(!root.join("Cellar/rollbackme/1.0.0").exists());
         assert!(!prefix.join("bin/rollbackme").exists());
         assert!(!prefix.join("opt/rollbackme").exists());
         assert!(root.join("store").join(&bottle_sha).exists());
     }
 
+    #[tokio::test]
+    async fn installs_tap_bottles_hosted_as_plain_files() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        let bottle = versioned_bottle(
+            "terraform",
+            "1.10.0",
+            &[("bin/terraform", b"#!/bin/sh\necho terraform")],
+        );
+        let bottle_sha = sha256_hex(&bottle);
+        let tag = get_test_bottle_tag();
+
+        let tap_formula_rb = format!(
+            r#"
+class Terraform < Formula
+  version "1.10.0"
+  bottle do
+    root_url "{}/releases/download/terraform-1.10.0"
+    sha256 {}: "{}"
+  end
+end
+"#,
+            mock_server.uri(),
+            tag,
+            bottle_sha
+        );
+
+        Mock::given(method("GET"))
+            .and(path("/hashicorp/homebrew-tap/main/Formula/terraform.rb"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(tap_formula_rb))
+            .mount(&mock_server)
+            .await;
+        // Release hosting redirects to object storage.
+        Mock::given(path(format!(
+            "/releases/download/terraform-1.10.0/terraform-1.10.0.{tag}.bottle.tar.gz"
+        )))
+        .respond_with(ResponseTemplate::new(302).insert_header(
+            "Location",
+            format!("{}/objects/terraform", mock_server.uri()),
+        ))
+        .mount(&mock_server)
+        .await;
+        Mock::given(method("GET"))
+            .and(path("/objects/terraform"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+
+        let mut installer = installer_at(
+            ApiClient::with_base_url(format!("{}/formula", mock_server.uri()))
+                .unwrap()
+                .with_tap_raw_base_url(mock_server.uri()),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+
+        installer
+            .install(&["hashicorp/tap/terraform".to_string()], true)
+            .await
+            .unwrap();
+
+        assert!(installer.is_installed("hashicorp/tap/terraform"));
+        assert!(root.join("Cellar/terraform/1.10.0").exists());
+        assert!(root.join("store").join(&bottle_sha).exists());
+    }
+
     #[tokio::test]
     async fn db_persist_failure_cleans_materialized_tap_formula_keg() {
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
 
-        let bottle = create_bottle_tarball("terraform");
+        let bottle = versioned_bottle(
+            "terraform",
+            "1.10.0",
+            &[("bin/terraform", b"#!/bin/sh\necho terraform")],
+        );
         let bottle_sha = sha256_hex(&bottle);
         let tag = get_test_bottle_tag();
 
@@ -824,18 +1734,20 @@ end
             linker,
             db,
             prefix.clone(),
-            root.join("locks"),
+            root.to_path_buf(),
         );
 
+        let plan = installer
+            .plan(&["hashicorp/tap/terraform".to_string()])
+            .await
+            .unwrap();
         let conn = rusqlite::Connection::open(&db_path).unwrap();
         conn.execute("DROP TABLE installed_kegs", []).unwrap();
 
-        let result = installer
-            .install(&["hashicorp/tap/terraform".to_string()], true)
-            .await;
+        let result = installer.execute(plan, true).await;
         assert!(result.is_err());
 
-        assert!(!root.join("cellar/terraform/1.10.0").exists());
+        assert!(!root.join("Cellar/terraform/1.10.0").exists());
         assert!(!prefix.join("bin/terraform").exists());
         assert!(!prefix.join("opt/terraform").exists());
         assert!(root.join("store").join(&bottle_sha).exists());
@@ -918,25 +1830,12 @@ end
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
 
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         installer
@@ -944,11 +1843,11 @@ end
             .await
             .unwrap();
 
-        assert!(installer.db.get_installed("root").is_some());
-        assert!(installer.db.get_installed("mid1").is_some());
-        assert!(installer.db.get_installed("mid2").is_some());
-        assert!(installer.db.get_installed("leaf1").is_some());
-        assert!(installer.db.get_installed("leaf2").is_some());
+        assert!(installer.db.get_installed("root").unwrap().is_some());
+        assert!(installer.db.get_installed("mid1").unwrap().is_some());
+        assert!(installer.db.get_installed("mid2").unwrap().is_some());
+        assert!(installer.db.get_installed("leaf1").unwrap().is_some());
+        assert!(installer.db.get_installed("leaf2").unwrap().is_some());
     }
 
     #[tokio::test]
@@ -1002,25 +1901,12 @@ end
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
 
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         installer
@@ -1028,14 +1914,74 @@ end
             .await
             .unwrap();
 
-        assert!(installer.db.get_installed("fastpkg").is_some());
-        assert!(installer.db.get_installed("slowpkg").is_some());
-        assert!(root.join("cellar/fastpkg/1.0.0").exists());
-        assert!(root.join("cellar/slowpkg/1.0.0").exists());
+        assert!(installer.db.get_installed("fastpkg").unwrap().is_some());
+        assert!(installer.db.get_installed("slowpkg").unwrap().is_some());
+        assert!(root.join("Cellar/fastpkg/1.0.0").exists());
+        assert!(root.join("Cellar/slowpkg/1.0.0").exists());
         assert!(prefix.join("bin/fastpkg").exists());
         assert!(prefix.join("bin/slowpkg").exists());
     }
 
+    #[tokio::test]
+    async fn links_in_dependency_order_regardless_of_download_order() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        // `aaa` comes first in link order but its bottle arrives last.
+        for (name, deps, delay) in [("aaa", "", 200), ("zlib", "", 0), ("app", r#""zlib""#, 0)] {
+            let bottle = create_bottle_tarball(name);
+            let json = format!(
+                r#"{{"name":"{name}","versions":{{"stable":"1.0.0"}},"dependencies":[{deps}],"bottle":{{"stable":{{"files":{{"{tag}":{{"url":"{}/bottles/{name}.tar.gz","sha256":"{}"}}}}}}}}}}"#,
+                mock_server.uri(),
+                sha256_hex(&bottle)
+            );
+            Mock::given(method("GET"))
+                .and(path(format!("/formula/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_string(json))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_bytes(bottle)
+                        .set_delay(Duration::from_millis(delay)),
+                )
+                .mount(&mock_server)
+                .await;
+        }
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+
+        let plan = installer
+            .plan(&["app".to_string(), "aaa".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(plan.link_order().unwrap(), ["aaa", "zlib", "app"]);
+
+        let linked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = linked.clone();
+        let progress: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
+            if let InstallProgress::LinkStarted { name } = event {
+                seen.lock().unwrap().push(name);
+            }
+        }));
+        installer
+            .execute_with_progress(plan, true, Some(progress))
+            .await
+            .unwrap();
+
+        assert_eq!(*linked.lock().unwrap(), ["aaa", "zlib", "app"]);
+    }
+
     #[tokio::test]
     async fn retries_on_corrupted_download() {
         let mock_server = MockServer::start().await;
@@ -1091,25 +2037,12 @@ end
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
 
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         installer
@@ -1118,7 +2051,7 @@ end
             .unwrap();
 
         assert!(installer.is_installed("retrypkg"));
-        assert!(root.join("cellar/retrypkg/1.0.0").exists());
+        assert!(root.join("Cellar/retrypkg/1.0.0").exists());
         assert!(prefix.join("bin/retrypkg").exists());
     }
 
@@ -1127,4 +2060,432 @@ end
         // Validates the retry mechanism structure -- proper integration test
         // would need injection of corruption between download and extraction.
     }
+
+    #[tokio::test]
+    async fn installs_from_snapshot_after_registry_shutdown() {
+        let registry = MockServer::start().await;
+        let bottles = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        let mut index = Vec::new();
+        for (name, deps) in [("libdep", ""), ("app", r#""libdep""#)] {
+            let bottle = create_bottle_tarball(name);
+            let json = format!(
+                r#"{{
+                    "name": "{name}",
+                    "versions": {{ "stable": "1.0.0" }},
+                    "dependencies": [{deps}],
+                    "bottle": {{ "stable": {{ "files": {{
+                        "{tag}": {{
+                            "url": "{uri}/bottles/{name}.tar.gz",
+                            "sha256": "{sha}"
+                        }}
+                    }} }} }}
+                }}"#,
+                uri = bottles.uri(),
+                sha = sha256_hex(&bottle),
+            );
+            Mock::given(method("GET"))
+                .and(path(format!("/formula/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_string(json.clone()))
+                .mount(&registry)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+                .mount(&bottles)
+                .await;
+            index.push(json);
+        }
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(format!("[{}]", index.join(","))),
+            )
+            .mount(&registry)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        let registry_url = format!("{}/formula", registry.uri());
+        let snapshot_path = tmp.path().join("core.snapshot");
+
+        let snapshot = ApiClient::with_base_url(registry_url.clone())
+            .unwrap()
+            .create_snapshot(&["app".to_string()])
+            .await
+            .unwrap();
+        snapshot.write(&snapshot_path).unwrap();
+        drop(registry);
+
+        let snapshot = crate::FormulaSnapshot::read(&snapshot_path).unwrap();
+        assert_eq!(snapshot.formula_count(), 2);
+
+        let mut installer = installer_at(
+            ApiClient::with_base_url(registry_url)
+                .unwrap()
+                .with_snapshot(snapshot),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+
+        let result = installer.install(&["app".to_string()], true).await.unwrap();
+        assert_eq!(result.installed, 2);
+        assert!(installer.is_installed("app"));
+        assert!(installer.is_installed("libdep"));
+    }
+
+    #[tokio::test]
+    async fn reinstall_after_gc_reuses_cached_bottles() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        for name in ["cachedapp", "cachedlib"] {
+            let bottle = create_bottle_tarball(name);
+            let sha = sha256_hex(&bottle);
+            let deps = if name == "cachedapp" {
+                r#"["cachedlib"]"#
+            } else {
+                "[]"
+            };
+            let json = format!(
+                r#"{{
+                    "name": "{name}",
+                    "versions": {{ "stable": "1.0.0" }},
+                    "dependencies": {deps},
+                    "bottle": {{ "stable": {{ "files": {{
+                        "{tag}": {{
+                            "url": "{uri}/bottles/{name}.tar.gz",
+                            "sha256": "{sha}"
+                        }}
+                    }} }} }}
+                }}"#,
+                uri = mock_server.uri(),
+            );
+            Mock::given(method("GET"))
+                .and(path(format!("/formula/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_string(json))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+                .mount(&mock_server)
+                .await;
+        }
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+        let names = ["cachedapp".to_string()];
+
+        let first = installer.install(&names, true).await.unwrap();
+        assert!(
+            first
+                .blob_sources
+                .values()
+                .all(|source| matches!(source, BlobSource::Downloaded { bytes } if *bytes > 0))
+        );
+
+        installer.uninstall("cachedapp").unwrap();
+        installer.uninstall("cachedlib").unwrap();
+        assert_eq!(installer.gc().unwrap().len(), 2);
+        let bottle_requests_before = bottle_requests(&mock_server).await;
+
+        let second = installer.install(&names, true).await.unwrap();
+
+        assert_eq!(second.installed, 2);
+        assert_eq!(
+            second.blob_sources.into_iter().collect::<Vec<_>>(),
+            [
+                ("cachedapp".to_string(), BlobSource::Cached),
+                ("cachedlib".to_string(), BlobSource::Cached),
+            ]
+        );
+        assert_eq!(bottle_requests(&mock_server).await, bottle_requests_before);
+    }
+
+    async fn bottle_requests(mock_server: &MockServer) -> usize {
+        mock_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.url.path().starts_with("/bottles/"))
+            .count()
+    }
+
+    /// A keg with one file that can't be patched: an ELF header followed by
+    /// garbage, which the ELF pass fails to parse.
+    #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+    async fn install_with_unpatchable_file(policy: crate::PatchPolicy) -> (TempDir, bool) {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let bottle = bottle_with_files(
+            "patchfail",
+            &[
+                ("bin/patchfail", b"#!/bin/sh\necho patchfail\n"),
+                ("lib/libbroken.so", b"\x7fELF not really an ELF file"),
+            ],
+        );
+        let tag = get_test_bottle_tag();
+        let formula_json = serde_json::json!({
+            "name": "patchfail",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": { tag: {
+                "url": format!("{}/bottles/patchfail.tar.gz", mock_server.uri()),
+                "sha256": sha256_hex(&bottle),
+            }}}}
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/patchfail.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(formula_json.to_string()))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bottles/patchfail.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+        installer.set_patch_policy(policy);
+
+        let result = installer.install(&["patchfail".to_string()], true).await;
+        if policy == crate::PatchPolicy::Strict {
+            let Err(err) = result else {
+                panic!("strict patching should fail the install");
+            };
+            assert!(
+                matches!(err, zb_core::Error::PatchFailed { ref files, .. }
+                    if files.len() == 1 && files[0].ends_with("lib/libbroken.so")),
+                "unexpected error: {err}"
+            );
+        } else {
+            result.unwrap();
+        }
+        let installed = installer.is_installed("patchfail");
+        (tmp, installed)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+    #[tokio::test]
+    async fn strict_patch_policy_rolls_back_the_keg() {
+        let (tmp, installed) = install_with_unpatchable_file(crate::PatchPolicy::Strict).await;
+
+        assert!(!installed);
+        assert!(!tmp.path().join("zerobrew/Cellar/patchfail").exists());
+        assert!(!tmp.path().join("homebrew/bin/patchfail").exists());
+    }
+
+    #[cfg(all(target_os = "linux", feature = "patch-elf"))]
+    #[tokio::test]
+    async fn warn_and_silent_patch_policies_keep_the_keg() {
+        for policy in [crate::PatchPolicy::Warn, crate::PatchPolicy::Silent] {
+            let (tmp, installed) = install_with_unpatchable_file(policy).await;
+
+            assert!(installed, "{policy:?}");
+            assert!(
+                tmp.path()
+                    .join("zerobrew/Cellar/patchfail/1.0.0/lib/libbroken.so")
+                    .exists()
+            );
+            assert!(tmp.path().join("homebrew/bin/patchfail").exists());
+        }
+    }
+
+    /// The Cellar's name is baked into patched files, so on a case-sensitive
+    /// filesystem every path install writes or records must use the same
+    /// spelling to resolve.
+    #[tokio::test]
+    #[cfg(any(
+        all(target_os = "linux", feature = "patch-elf"),
+        all(target_os = "macos", feature = "patch-macho")
+    ))]
+    async fn installed_paths_resolve_on_case_sensitive_filesystem() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir(tmp.path().join("probe")).unwrap();
+        if tmp.path().join("PROBE").exists() {
+            // Case-insensitive: the mismatch this guards against can't happen.
+            return;
+        }
+
+        let bottle = bottle_with_files(
+            "casepkg",
+            &[
+                (
+                    "bin/casepkg",
+                    b"#!/bin/sh\nexec @@HOMEBREW_CELLAR@@/casepkg/1.0.0/libexec/helper @@HOMEBREW_PREFIX@@/opt/casepkg\n",
+                ),
+                ("libexec/helper", b"#!/bin/sh\necho \"$1\"\n"),
+            ],
+        );
+        let tag = get_test_bottle_tag();
+        let formula_json = serde_json::json!({
+            "name": "casepkg",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": { tag: {
+                "url": format!("{}/bottles/casepkg.tar.gz", mock_server.uri()),
+                "sha256": sha256_hex(&bottle),
+            }}}}
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/casepkg.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(formula_json.to_string()))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bottles/casepkg.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        let mut installer = Installer::new(
+            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap(),
+            BlobCache::new(&root.join("cache")).unwrap(),
+            Store::new(&root).unwrap(),
+            Cellar::new(&prefix).unwrap(),
+            Linker::new(&prefix).unwrap(),
+            Database::open(&root.join("db/zb.sqlite3")).unwrap(),
+            prefix.clone(),
+            root.to_path_buf(),
+        );
+        installer
+            .install(&["casepkg".to_string()], true)
+            .await
+            .unwrap();
+
+        let keg = prefix.join("Cellar/casepkg/1.0.0");
+        assert!(keg.is_dir());
+        let records = installer.db.list_keg_files().unwrap();
+        assert!(!records.is_empty());
+        for record in &records {
+            assert!(
+                std::path::Path::new(&record.linked_path).exists(),
+                "{record:?}"
+            );
+            assert!(
+                std::path::Path::new(&record.target_path).exists(),
+                "{record:?}"
+            );
+        }
+
+        let script = fs::read_to_string(keg.join("bin/casepkg")).unwrap();
+        let patched: Vec<&str> = script
+            .split_whitespace()
+            .filter(|word| word.starts_with('/') && *word != "/bin/sh")
+            .collect();
+        assert_eq!(patched.len(), 2, "{script}");
+        for path in patched {
+            assert!(std::path::Path::new(path).exists(), "{path} in {script}");
+        }
+        let output = std::process::Command::new(prefix.join("bin/casepkg"))
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            prefix.join("opt/casepkg").to_string_lossy()
+        );
+    }
+
+    #[tokio::test]
+    async fn formula_with_wrapper_rule_gets_an_exec_wrapper() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let bottle = bottle_with_files(
+            "git",
+            &[
+                ("bin/git", b"#!/bin/sh\necho \"$GIT_EXEC_PATH\"\n"),
+                ("libexec/git-core/git-remote-https", b"#!/bin/sh\n"),
+            ],
+        );
+        let tag = get_test_bottle_tag();
+        let formula_json = serde_json::json!({
+            "name": "git",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": { tag: {
+                "url": format!("{}/bottles/git.tar.gz", mock_server.uri()),
+                "sha256": sha256_hex(&bottle),
+            }}}}
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/git.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(formula_json.to_string()))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bottles/git.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+        installer.install(&["git".to_string()], true).await.unwrap();
+
+        let keg = root.join("Cellar/git/1.0.0");
+        let wrapper = prefix.join("bin/git");
+        assert!(!wrapper.is_symlink());
+        let script = fs::read_to_string(&wrapper).unwrap();
+        assert!(script.contains(&format!(
+            "export GIT_EXEC_PATH='{}'\n",
+            keg.join("libexec/git-core").display()
+        )));
+        assert!(script.contains(&format!(
+            "exec '{}' \"$@\"\n",
+            keg.join("bin/git").display()
+        )));
+
+        let output = std::process::Command::new(&wrapper).output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            keg.join("libexec/git-core").to_string_lossy()
+        );
+
+        let records = installer.db.list_keg_files().unwrap();
+        assert!(
+            records
+                .iter()
+                .any(|r| r.kind == KegFileKind::Wrapper
+                    && r.linked_path == wrapper.to_string_lossy())
+        );
+        // Symlinks outside bin stay symlinks.
+        assert!(
+            prefix
+                .join("libexec/git-core/git-remote-https")
+                .is_symlink()
+        );
+
+        installer.uninstall("git").unwrap();
+        assert!(wrapper.symlink_metadata().is_err());
+    }
 }
@@ -1,16 +1,21 @@
 use std::collections::HashMap;
 
-use zb_core::{Error, select_bottle};
+use futures::stream::{self, StreamExt};
+use tracing::{debug, warn};
+use zb_core::{ChangeKind, Error, Formula, select_bottle};
 
-use super::{Installer, OutdatedPackage};
+use super::{CachedUpdate, Installer, OutdatedPackage, UncheckedPackage};
+use crate::network::snapshot::index_entry_name;
+use crate::storage::db::{InstalledKeg, UpstreamStatus};
 
 impl Installer {
     pub async fn is_outdated(&self, name: &str) -> Result<Option<OutdatedPackage>, Error> {
-        let installed = self.db.get_installed(name).ok_or(Error::NotInstalled {
+        let installed = self.get_installed(name)?.ok_or(Error::NotInstalled {
             name: name.to_string(),
         })?;
 
         let formula = self.api_client.get_formula(name).await?;
+        self.note_upstream_status(name, &formula);
         let is_source = installed.store_key.starts_with("source:");
 
         if is_source {
@@ -20,10 +25,12 @@ impl Installer {
             } else {
                 Ok(Some(OutdatedPackage {
                     name: name.to_string(),
+                    change_kind: ChangeKind::between(&installed.version, &current_version),
                     installed_version: installed.version,
                     installed_sha256: installed.store_key,
                     current_version,
                     current_sha256: String::new(),
+                    current_url: String::new(),
                     is_source_build: true,
                 }))
             }
@@ -32,19 +39,32 @@ impl Installer {
             if installed.store_key == bottle.sha256 {
                 Ok(None)
             } else {
+                let current_version = formula.effective_version();
                 Ok(Some(OutdatedPackage {
                     name: name.to_string(),
+                    change_kind: ChangeKind::between(
+                        installed.upstream_version(),
+                        &current_version,
+                    ),
                     installed_version: installed.version,
                     installed_sha256: installed.store_key,
-                    current_version: formula.effective_version(),
+                    current_version,
                     current_sha256: bottle.sha256,
+                    current_url: bottle.url,
                     is_source_build: false,
                 }))
             }
         }
     }
 
-    pub async fn check_outdated(&self) -> Result<(Vec<OutdatedPackage>, Vec<String>), Error> {
+    /// Every installed formula with a newer build upstream, sorted by name,
+    /// and those whose upstream version couldn't be looked up. Core
+    /// formulas come from the bulk index; tap formulas, and all of them when
+    /// the index can't be fetched, are looked up one at a time, as many at
+    /// once as downloads run.
+    pub async fn check_outdated(
+        &self,
+    ) -> Result<(Vec<OutdatedPackage>, Vec<UncheckedPackage>), Error> {
         let installed = self.db.list_installed()?;
         if installed.is_empty() {
             return Ok((Vec::new(), Vec::new()));
@@ -53,38 +73,65 @@ impl Installer {
         let installed_names: std::collections::HashSet<&str> =
             installed.iter().map(|k| k.name.as_str()).collect();
 
-        let bulk_raw = self.api_client.get_all_formulas_raw().await?;
-        let bulk_values: Vec<serde_json::Value> = serde_json::from_str(&bulk_raw)
-            .map_err(Error::network("failed to parse bulk formula JSON"))?;
-
         let mut bulk_map: HashMap<String, zb_core::Formula> = HashMap::new();
-        for val in bulk_values {
-            let name = match val.get("name").and_then(|n| n.as_str()) {
-                Some(n) if installed_names.contains(n) => n.to_string(),
-                _ => continue,
-            };
-            if let Ok(f) = serde_json::from_value(val) {
-                bulk_map.insert(name, f);
+        match self.bulk_index_values().await {
+            Ok(bulk_values) => {
+                for val in bulk_values {
+                    // Installed formulas missing from the index because of a
+                    // nameless entry are looked up on their own below.
+                    let name = match index_entry_name(&val) {
+                        Ok(name) => name,
+                        Err(e) => {
+                            warn!(error = %e, "skipping bulk index entry");
+                            continue;
+                        }
+                    };
+                    if !installed_names.contains(name.as_str()) {
+                        continue;
+                    }
+                    // A bad bulk entry falls back to fetching the formula on its own.
+                    match zb_core::Formula::from_value(&name, val) {
+                        Ok(f) => {
+                            bulk_map.insert(name, f);
+                        }
+                        Err(e) => warn!(formula = %name, error = %e, "skipping bulk index entry"),
+                    }
+                }
             }
+            Err(e) => debug!(error = %e, "bulk index unavailable; looking formulas up one by one"),
         }
 
+        let api_client = &self.api_client;
+        let mut looked_up: HashMap<String, Result<Formula, Error>> = stream::iter(
+            installed
+                .iter()
+                .filter(|keg| keg.name.contains('/') || !bulk_map.contains_key(&keg.name)),
+        )
+        .map(|keg| async move { (keg.name.clone(), api_client.get_formula(&keg.name).await) })
+        .buffer_unordered(self.downloader.concurrency())
+        .collect()
+        .await;
+
         let mut outdated = Vec::new();
-        let mut warnings = Vec::new();
+        let mut unchecked = Vec::new();
+        let mut skip = |keg: &InstalledKeg, e: Error| {
+            unchecked.push(UncheckedPackage {
+                name: keg.name.clone(),
+                installed_version: keg.version.clone(),
+                reason: e.to_string(),
+            });
+        };
 
         for keg in &installed {
-            let is_tap = keg.name.contains('/');
-
-            let formula = if is_tap || !bulk_map.contains_key(&keg.name) {
-                match self.api_client.get_formula(&keg.name).await {
-                    Ok(f) => f,
-                    Err(e) => {
-                        warnings.push(format!("{}: {}", keg.name, e));
-                        continue;
-                    }
+            let formula = match looked_up.remove(&keg.name) {
+                Some(Ok(f)) => f,
+                Some(Err(e)) => {
+                    skip(keg, e);
+                    continue;
                 }
-            } else {
-                bulk_map.remove(&keg.name).unwrap()
+                None => bulk_map.remove(&keg.name).unwrap(),
             };
+            self.note_upstream_status(&keg.name, &formula);
 
             let is_source = keg.store_key.starts_with("source:");
 
@@ -93,10 +140,12 @@ impl Installer {
                 if keg.version != current_version {
                     outdated.push(OutdatedPackage {
                         name: keg.name.clone(),
+                        change_kind: ChangeKind::between(&keg.version, &current_version),
                         installed_version: keg.version.clone(),
                         installed_sha256: keg.store_key.clone(),
                         current_version,
                         current_sha256: String::new(),
+                        current_url: String::new(),
                         is_source_build: true,
                     });
                 }
@@ -104,23 +153,75 @@ impl Installer {
                 match select_bottle(&formula) {
                     Ok(bottle) => {
                         if keg.store_key != bottle.sha256 {
+                            let current_version = formula.effective_version();
                             outdated.push(OutdatedPackage {
                                 name: keg.name.clone(),
+                                change_kind: ChangeKind::between(
+                                    keg.upstream_version(),
+                                    &current_version,
+                                ),
                                 installed_version: keg.version.clone(),
                                 installed_sha256: keg.store_key.clone(),
-                                current_version: formula.effective_version(),
+                                current_version,
                                 current_sha256: bottle.sha256,
+                                current_url: bottle.url,
                                 is_source_build: false,
                             });
                         }
                     }
-                    Err(e) => warnings.push(format!("{}: {}", keg.name, e)),
+                    Err(e) => skip(keg, e),
                 }
             }
         }
 
         outdated.sort_by(|a, b| a.name.cmp(&b.name));
-        Ok((outdated, warnings))
+        unchecked.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok((outdated, unchecked))
+    }
+
+    async fn bulk_index_values(&self) -> Result<Vec<serde_json::Value>, Error> {
+        let bulk_raw = self.api_client.get_all_formulas_raw().await?;
+        serde_json::from_str(&bulk_raw).map_err(Error::network("failed to parse bulk formula JSON"))
+    }
+
+    /// Installed formulas upstream deprecated or disabled, as of the last
+    /// command that fetched their metadata.
+    pub fn upstream_statuses(&self) -> Result<Vec<UpstreamStatus>, Error> {
+        self.db.list_upstream_statuses()
+    }
+
+    /// Remember whether upstream deprecated or disabled `name`, so it can be
+    /// shown offline. Failing to record it never fails the caller.
+    pub(crate) fn note_upstream_status(&self, name: &str, formula: &Formula) {
+        if let Err(e) =
+            self.db
+                .record_upstream_status(name, &formula.lifecycle(), formula.lifecycle_date())
+        {
+            debug!(formula = name, error = %e, "could not record upstream status");
+        }
+    }
+
+    /// Installed formulas whose cached metadata has a version other than
+    /// the installed one, read from the API cache only. Nothing is fetched,
+    /// so the result is only as fresh as the last command that refreshed it.
+    pub fn cached_updates(&self) -> Result<Vec<CachedUpdate>, Error> {
+        let installed = self.db.list_installed()?;
+        let names: Vec<&str> = installed.iter().map(|keg| keg.name.as_str()).collect();
+        let mut cached = self.api_client.cached_formulas(&names);
+
+        Ok(installed
+            .into_iter()
+            .filter_map(|keg| {
+                let (formula, fetched_at) = cached.remove(&keg.name)?;
+                let cached_version = formula.effective_version();
+                (cached_version != keg.upstream_version()).then_some(CachedUpdate {
+                    name: keg.name,
+                    installed_version: keg.version,
+                    cached_version,
+                    fetched_at,
+                })
+            })
+            .collect())
     }
 
     pub async fn suggest_formulas(&self, query: &str, limit: usize) -> Result<Vec<String>, Error> {
@@ -131,19 +232,27 @@ impl Installer {
 #[cfg(test)]
 mod tests {
     use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
 
     use tempfile::TempDir;
     use wiremock::matchers::{method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
+    use zb_core::Lifecycle;
 
-    use crate::cellar::Cellar;
     use crate::network::api::ApiClient;
-    use crate::storage::blob::BlobCache;
-    use crate::storage::db::Database;
-    use crate::storage::store::Store;
-    use crate::{Installer, Linker};
+    use crate::network::cache::{ApiCache, CacheEntry};
 
-    use super::super::test_support::get_test_bottle_tag;
+    use crate::Installer;
+
+    use crate::installer::install::test_support::{api_client, get_test_bottle_tag, installer_at};
+
+    fn entry(body: &str) -> CacheEntry {
+        CacheEntry {
+            etag: None,
+            last_modified: None,
+            body: body.to_string(),
+        }
+    }
 
     fn formula_json(name: &str, version: &str, sha256: &str) -> String {
         let tag = get_test_bottle_tag();
@@ -172,25 +281,12 @@ mod tests {
         let tmp = TempDir::new().unwrap();
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix,
-            root.join("locks"),
+        let installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
         (installer, mock_server, tmp)
     }
@@ -214,25 +310,12 @@ mod tests {
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
 
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix,
-            root.join("locks"),
+        let installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         let suggestions = installer.suggest_formulas("pythn", 3).await.unwrap();
@@ -286,9 +369,34 @@ mod tests {
         assert_eq!(result.name, "jq");
         assert_eq!(result.installed_version, "1.7.0");
         assert_eq!(result.current_version, "1.7.1");
+        assert_eq!(result.change_kind, zb_core::ChangeKind::Version);
         assert!(!result.is_source_build);
     }
 
+    #[tokio::test]
+    async fn is_outdated_marks_a_new_bottle_of_the_same_version_as_a_rebuild() {
+        let (mut installer, mock_server, _tmp) = test_installer().await;
+
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("jq", "1.7.1", "old_sha256").unwrap();
+            tx.commit().unwrap();
+        }
+
+        Mock::given(method("GET"))
+            .and(path("/formula/jq.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(formula_json(
+                "jq",
+                "1.7.1",
+                "new_sha256",
+            )))
+            .mount(&mock_server)
+            .await;
+
+        let result = installer.is_outdated("jq").await.unwrap().unwrap();
+        assert_eq!(result.change_kind, zb_core::ChangeKind::Rebuild);
+    }
+
     #[tokio::test]
     async fn is_outdated_errors_for_not_installed() {
         let (installer, _mock_server, _tmp) = test_installer().await;
@@ -347,6 +455,102 @@ mod tests {
         assert!(result.is_source_build);
     }
 
+    #[tokio::test]
+    async fn cached_updates_reads_only_the_seeded_cache() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        fs::create_dir_all(root.join("cache")).unwrap();
+
+        // Nothing listens here; any request would fail the lookups below.
+        let base = "http://127.0.0.1:9/formula";
+        let cache_path = root.join("cache/api-cache.sqlite");
+        let seeded = ApiCache::open(&cache_path).unwrap();
+        let bulk_time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let bulk = format!(
+            "[{},{}]",
+            formula_json("jq", "1.7.0", "a"),
+            formula_json("wget", "1.25.0", "b")
+        );
+        seeded
+            .put_at(&format!("{base}.json"), &entry(&bulk), bulk_time)
+            .unwrap();
+        let jq_time = bulk_time + Duration::from_secs(3600);
+        seeded
+            .put_at(
+                &format!("{base}/jq.json"),
+                &entry(&formula_json("jq", "1.8.0", "c")),
+                jq_time,
+            )
+            .unwrap();
+
+        let api_client = ApiClient::with_base_url(base.to_string())
+            .unwrap()
+            .with_cache(ApiCache::open(&cache_path).unwrap());
+        let mut installer = installer_at(api_client, &root, &prefix, root.join("Cellar"));
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("jq", "1.7.0", "a").unwrap();
+            tx.record_install("wget", "1.25.0", "b").unwrap();
+            tx.record_install("uncached", "1.0", "d").unwrap();
+            tx.commit().unwrap();
+        }
+
+        let updates = installer.cached_updates().unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].name, "jq");
+        assert_eq!(updates[0].installed_version, "1.7.0");
+        assert_eq!(updates[0].cached_version, "1.8.0");
+        assert_eq!(updates[0].fetched_at, jq_time);
+    }
+
+    #[tokio::test]
+    async fn preview_upgrade_sizes_bottles_with_head_requests() {
+        let (mut installer, mock_server, _tmp) = test_installer().await;
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("jq", "1.6", "old_sha256").unwrap();
+            tx.commit().unwrap();
+        }
+
+        let tag = get_test_bottle_tag();
+        let formula = format!(
+            r#"{{
+                "name": "jq",
+                "versions": {{ "stable": "1.7.1" }},
+                "dependencies": [],
+                "bottle": {{ "stable": {{ "files": {{ "{tag}": {{
+                    "url": "{}/bottles/jq-1.7.1.tar.gz",
+                    "sha256": "new_sha256"
+                }} }} }} }}
+            }}"#,
+            mock_server.uri()
+        );
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!("[{formula}]")))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/bottles/jq-1.7.1.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0u8; 4096]))
+            .mount(&mock_server)
+            .await;
+
+        let (preview, warnings) = installer.preview_upgrade(&[], false, false).await.unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(preview.groups.len(), 1);
+        assert_eq!(preview.groups[0].bump, zb_core::VersionBump::Minor);
+        assert_eq!(preview.groups[0].formulas[0].download_bytes, Some(4096));
+
+        let err = installer
+            .preview_upgrade(&["wget".to_string()], false, false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, zb_core::Error::NotInstalled { .. }));
+    }
+
     #[tokio::test]
     async fn check_outdated_empty_when_nothing_installed() {
         let (installer, _mock_server, _tmp) = test_installer().await;
@@ -367,7 +571,10 @@ mod tests {
             tx.commit().unwrap();
         }
 
-        let bulk = format!("[{}]", formula_json("good", "2.0.0", "new_sha"));
+        let bulk = format!(
+            r#"[{{"desc": "no name"}}, {}]"#,
+            formula_json("good", "2.0.0", "new_sha")
+        );
         Mock::given(method("GET"))
             .and(path("/formula.json"))
             .respond_with(ResponseTemplate::new(200).set_body_string(bulk))
@@ -384,7 +591,195 @@ mod tests {
         assert_eq!(outdated.len(), 1);
         assert_eq!(outdated[0].name, "good");
         assert_eq!(warnings.len(), 1);
-        assert!(warnings[0].contains("bad"));
+        assert_eq!(warnings[0].name, "bad");
+        assert_eq!(warnings[0].installed_version, "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn check_outdated_skips_malformed_entries_of_a_real_index() {
+        let (mut installer, mock_server, _tmp) = test_installer().await;
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("jq", "1.7", "old_sha").unwrap();
+            tx.record_install("sqlite", "3.47.1", "old_sha").unwrap();
+            tx.record_install("terraform", "1.5.6", "old_sha").unwrap();
+            tx.commit().unwrap();
+        }
+
+        let jq = include_str!("../../../../zb_core/fixtures/formula_api_jq.json");
+        let sqlite = include_str!("../../../../zb_core/fixtures/formula_api_sqlite.json");
+        let terraform = include_str!("../../../../zb_core/fixtures/formula_api_terraform.json");
+        let mut bad_sqlite: serde_json::Value = serde_json::from_str(sqlite).unwrap();
+        bad_sqlite["versions"]["stable"] = serde_json::Value::Null;
+        let mut nameless: serde_json::Value = serde_json::from_str(terraform).unwrap();
+        nameless.as_object_mut().unwrap().remove("name");
+
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(format!("[{jq},{bad_sqlite},{nameless}]")),
+            )
+            .mount(&mock_server)
+            .await;
+        // The bad entry is looked up on its own; the nameless one can't be.
+        Mock::given(method("GET"))
+            .and(path("/formula/sqlite.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(sqlite))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/formula/terraform.json"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let (outdated, warnings) = installer.check_outdated().await.unwrap();
+        let names: Vec<_> = outdated.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["jq", "sqlite"]);
+        assert_eq!(outdated[1].current_version, "3.47.2");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].name, "terraform");
+    }
+
+    #[tokio::test]
+    async fn check_outdated_looks_formulas_up_when_the_bulk_index_fails() {
+        let (mut installer, mock_server, _tmp) = test_installer().await;
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("good", "1.0.0", "old_sha").unwrap();
+            tx.record_install("current", "1.0.0", "sha").unwrap();
+            tx.record_install("bad", "1.0.0", "old_sha").unwrap();
+            tx.commit().unwrap();
+        }
+
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        for (name, version, sha) in [("good", "2.0.0", "new_sha"), ("current", "1.0.0", "sha")] {
+            Mock::given(method("GET"))
+                .and(path(format!("/formula/{name}.json")))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_string(formula_json(name, version, sha)),
+                )
+                .mount(&mock_server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/formula/bad.json"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let (outdated, unchecked) = installer.check_outdated().await.unwrap();
+        let names: Vec<&str> = outdated.iter().map(|pkg| pkg.name.as_str()).collect();
+        assert_eq!(names, ["good"]);
+        assert_eq!(outdated[0].current_version, "2.0.0");
+        let names: Vec<&str> = unchecked.iter().map(|pkg| pkg.name.as_str()).collect();
+        assert_eq!(names, ["bad"]);
+    }
+
+    /// `formula_json` with `flags` spliced into the top-level object.
+    fn flagged_json(name: &str, flags: &str) -> String {
+        let json = formula_json(name, "1.0.0", "sha");
+        let end = json.rfind('}').unwrap();
+        format!("{}, {flags} }}", &json[..end])
+    }
+
+    #[tokio::test]
+    async fn refreshes_record_what_upstream_deprecated_or_disabled() {
+        let (mut installer, mock_server, _tmp) = test_installer().await;
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("jq", "1.0.0", "sha").unwrap();
+            tx.record_install("sunset", "1.0.0", "sha").unwrap();
+            tx.commit().unwrap();
+        }
+
+        let bulk = format!(
+            "[{}, {}]",
+            formula_json("jq", "1.0.0", "sha"),
+            flagged_json(
+                "sunset",
+                r#""deprecated": true, "deprecation_reason": "unmaintained",
+                   "deprecation_date": "2026-01-01""#
+            )
+        );
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(bulk))
+            .mount(&mock_server)
+            .await;
+        installer.check_outdated().await.unwrap();
+
+        let statuses = installer.upstream_statuses().unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].name, "sunset");
+        assert_eq!(
+            statuses[0].lifecycle,
+            Lifecycle::Deprecated {
+                reason: Some("unmaintained".to_string())
+            }
+        );
+        assert_eq!(statuses[0].since.as_deref(), Some("2026-01-01"));
+
+        mock_server.reset().await;
+        let bulk = format!(
+            "[{}, {}]",
+            flagged_json("jq", r#""deprecated": true"#),
+            flagged_json(
+                "sunset",
+                r#""deprecated": true, "disabled": true, "disable_date": "2026-07-01""#
+            )
+        );
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(bulk))
+            .mount(&mock_server)
+            .await;
+        installer.check_outdated().await.unwrap();
+
+        let statuses: Vec<(String, Lifecycle, Option<String>)> = installer
+            .upstream_statuses()
+            .unwrap()
+            .into_iter()
+            .map(|status| (status.name, status.lifecycle, status.since))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (
+                    "jq".to_string(),
+                    Lifecycle::Deprecated { reason: None },
+                    None
+                ),
+                (
+                    "sunset".to_string(),
+                    Lifecycle::Disabled { reason: None },
+                    Some("2026-07-01".to_string())
+                ),
+            ]
+        );
+
+        mock_server.reset().await;
+        Mock::given(method("GET"))
+            .and(path("/formula/jq.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(formula_json("jq", "1.0.0", "sha")),
+            )
+            .mount(&mock_server)
+            .await;
+        installer.is_outdated("jq").await.unwrap();
+
+        let names: Vec<String> = installer
+            .upstream_statuses()
+            .unwrap()
+            .into_iter()
+            .map(|status| status.name)
+            .collect();
+        assert_eq!(names, ["sunset"]);
     }
 
     #[tokio::test]
@@ -413,6 +808,6 @@ mod tests {
         let (outdated, warnings) = installer.check_outdated().await.unwrap();
         assert!(outdated.is_empty());
         assert_eq!(warnings.len(), 1);
-        assert!(warnings[0].contains("nobottle"));
+        assert!(warnings[0].to_string().starts_with("nobottle: "));
     }
 }
@@ -1,9 +1,13 @@
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use tracing::warn;
-use zb_core::{BuildPlan, Error, Formula, InstallMethod, select_bottle};
+use zb_core::{
+    BuildId, BuildPlan, Error, Formula, InstallMethod, blocked_by_failures, select_bottle,
+};
 
-use super::{InstallPlan, Installer, PlannedInstall};
+use super::{FormulaOutcome, InstallPlan, Installer, IsolatedPlan, PlannedInstall};
+use crate::storage::db::InstalledKeg;
 
 impl Installer {
     pub async fn plan(&self, names: &[String]) -> Result<InstallPlan, Error> {
@@ -15,12 +19,118 @@ impl Installer {
         names: &[String],
         build_from_source: bool,
     ) -> Result<InstallPlan, Error> {
-        let formulas = self.fetch_all_formulas(names).await?;
-        let ordered = zb_core::resolve_closure(names, &formulas)?;
+        let resolution = self.resolve_taps(names).await?;
+        let names = &resolution.names;
+        let mut fetched = self.fetch_all_formulas(names).await;
+        if !fetched.errors.is_empty() {
+            return Err(fetched.errors.swap_remove(0).1);
+        }
+        let names = self.requested_names(names, &fetched.aliases);
+        let mut plan = self.build_plan(&names, &fetched.formulas, build_from_source)?;
+        plan.aliases = fetched.aliases;
+        plan.tap_choices = resolution.choices;
+        Ok(plan)
+    }
+
+    /// Plan `names` so that one unusable formula does not sink the rest.
+    ///
+    /// Requested formulas that cannot be fetched or have neither a bottle nor
+    /// a source for this platform are reported as failed, and requested
+    /// formulas depending on a formula that could not be fetched are reported
+    /// as skipped. Everything else is planned as usual.
+    pub async fn plan_isolated(
+        &self,
+        names: &[String],
+        build_from_source: bool,
+    ) -> Result<IsolatedPlan, Error> {
+        let resolution = self.resolve_taps(names).await?;
+        let fetched = self.fetch_all_formulas(&resolution.names).await;
+        let names = &self.requested_names(&resolution.names, &fetched.aliases);
+
+        let mut errors: BTreeMap<String, Error> = fetched.errors.into_iter().collect();
+        for name in names {
+            if fetched.unavailable.contains(name) {
+                errors.insert(
+                    name.clone(),
+                    Error::UnsupportedBottle { name: name.clone() },
+                );
+            }
+        }
+
+        let mut dependencies: BTreeMap<String, Vec<String>> = fetched
+            .formulas
+            .iter()
+            .map(|(name, formula)| (name.clone(), formula.dependencies.clone()))
+            .collect();
+        for name in errors.keys() {
+            dependencies.entry(name.clone()).or_default();
+        }
+        let failed: BTreeSet<String> = errors.keys().cloned().collect();
+        let blocked = blocked_by_failures(&dependencies, &failed);
+
+        let installed = self.installed_set()?;
+        let mut outcomes = Vec::new();
+        let mut plannable = Vec::new();
+        for name in names {
+            if let Some(err) = errors.get(name) {
+                outcomes.push((name.clone(), FormulaOutcome::Failed(err.clone())));
+            } else if let Some(err) = fetched.formulas.get(name).and_then(|formula| {
+                installed
+                    .get(name)
+                    .and_then(|keg| pinned_refusal(keg, formula))
+            }) {
+                outcomes.push((name.clone(), FormulaOutcome::Failed(err)));
+            } else if let Some(dependency) = blocked.get(name) {
+                outcomes.push((
+                    name.clone(),
+                    FormulaOutcome::Skipped {
+                        dependency: dependency.clone(),
+                    },
+                ));
+            } else {
+                plannable.push(name.clone());
+            }
+        }
+
+        let mut plan = if plannable.is_empty() {
+            InstallPlan::default()
+        } else {
+            self.build_plan(&plannable, &fetched.formulas, build_from_source)?
+        };
+        plan.aliases = fetched.aliases;
+        plan.tap_choices = resolution.choices;
+
+        Ok(IsolatedPlan {
+            plan,
+            not_planned: outcomes,
+        })
+    }
+
+    /// Plan `names` and their dependencies. A pinned keg is never replaced
+    /// by a different build: naming it is an error, and as a dependency it
+    /// is left out of the plan and kept at its version.
+    fn build_plan(
+        &self,
+        names: &[String],
+        formulas: &BTreeMap<String, Formula>,
+        build_from_source: bool,
+    ) -> Result<InstallPlan, Error> {
+        let ordered = zb_core::resolve_closure(names, formulas)?;
+        let installed = self.installed_set()?;
 
         let mut items = Vec::with_capacity(ordered.len());
+        let mut pinned = BTreeMap::new();
         for install_name in ordered {
             let formula = formulas.get(&install_name).cloned().unwrap();
+            if let Some(keg) = installed.get(&install_name)
+                && let Some(err) = pinned_refusal(keg, &formula)
+            {
+                if names.contains(&install_name) {
+                    return Err(err);
+                }
+                pinned.insert(install_name, keg.build().to_string());
+                continue;
+            }
             let method = if build_from_source {
                 match BuildPlan::from_formula(&formula, &self.prefix) {
                     Some(plan) => InstallMethod::Source(plan),
@@ -47,22 +157,44 @@ impl Installer {
                 }
             };
             items.push(PlannedInstall {
+                explicit: names.contains(&install_name),
                 install_name,
                 formula,
                 method,
             });
         }
 
-        Ok(InstallPlan { items })
+        Ok(InstallPlan {
+            items,
+            pinned,
+            ..InstallPlan::default()
+        })
     }
 
-    async fn fetch_all_formulas(
-        &self,
-        names: &[String],
-    ) -> Result<BTreeMap<String, Formula>, Error> {
-        use std::collections::HashSet;
+    /// `names` with aliases replaced by their formula. An alias of an
+    /// installed formula is dropped so it does not get a second keg under
+    /// another name; naming the formula itself still reinstalls it.
+    fn requested_names(&self, names: &[String], aliases: &BTreeMap<String, String>) -> Vec<String> {
+        let mut requested: Vec<String> = Vec::with_capacity(names.len());
+        for name in names {
+            let name = match aliases.get(name) {
+                Some(canonical) if self.is_installed(canonical) => continue,
+                Some(canonical) => canonical,
+                None => name,
+            };
+            if !requested.contains(name) {
+                requested.push(name.clone());
+            }
+        }
+        requested
+    }
 
-        let mut formulas = BTreeMap::new();
+    /// Fetch `names` and their dependency closure. Fetch failures are
+    /// collected rather than returned so callers can decide how far they
+    /// reach; formulas with nothing installable for this platform are left
+    /// out, which silently drops platform-specific dependencies.
+    pub(super) async fn fetch_all_formulas(&self, names: &[String]) -> FetchedFormulas {
+        let mut fetched_formulas = FetchedFormulas::default();
         let mut fetched: HashSet<String> = HashSet::new();
         let mut to_fetch: Vec<String> = names.to_vec();
 
@@ -90,7 +222,20 @@ impl Installer {
             for (i, result) in results.into_iter().enumerate() {
                 let formula = match result {
                     Ok(f) => f,
-                    Err(e) => return Err(e),
+                    Err(e) => {
+                        if matches!(e, Error::MissingFormula { .. })
+                            && names.contains(&batch[i])
+                            && let Some(canonical) = self.alias_target(&batch[i]).await
+                        {
+                            if !fetched.contains(&canonical) && !to_fetch.contains(&canonical) {
+                                to_fetch.push(canonical.clone());
+                            }
+                            fetched_formulas.aliases.insert(batch[i].clone(), canonical);
+                        } else {
+                            fetched_formulas.errors.push((batch[i].clone(), e));
+                        }
+                        continue;
+                    }
                 };
 
                 if select_bottle(&formula).is_err() && !formula.has_source_url() {
@@ -98,6 +243,7 @@ impl Installer {
                         formula = %formula.name,
                         "skipping formula with no bottle or source available for this platform"
                     );
+                    fetched_formulas.unavailable.insert(batch[i].clone());
                     continue;
                 }
 
@@ -107,29 +253,120 @@ impl Installer {
                     }
                 }
 
-                formulas.insert(batch[i].clone(), formula);
+                fetched_formulas.formulas.insert(batch[i].clone(), formula);
+            }
+        }
+
+        fetched_formulas
+    }
+
+    /// The formula a requested name that is not a formula of its own is an
+    /// alias or old name of. Best effort: without the bulk index the name
+    /// is simply missing.
+    pub(crate) async fn alias_target(&self, name: &str) -> Option<String> {
+        let canonical = self.api_client.resolve_alias(name).await.ok()??;
+        (canonical != name).then_some(canonical)
+    }
+}
+
+impl IsolatedPlan {
+    /// Take the planned formulas in `refused` out of the plan, reporting
+    /// each as failed with its error. Requested formulas depending on one
+    /// are reported as skipped, and dependencies nothing left in the plan
+    /// needs are dropped with them.
+    pub fn refuse(&mut self, refused: Vec<(String, Error)>) {
+        if refused.is_empty() {
+            return;
+        }
+        let dependencies: BTreeMap<String, Vec<String>> = self
+            .plan
+            .items
+            .iter()
+            .map(|item| (item.install_name.clone(), item.formula.dependencies.clone()))
+            .collect();
+        let failed: BTreeSet<String> = refused.iter().map(|(name, _)| name.clone()).collect();
+        let blocked = blocked_by_failures(&dependencies, &failed);
+
+        for (name, err) in refused {
+            self.not_planned.push((name, FormulaOutcome::Failed(err)));
+        }
+        let mut keep = BTreeSet::new();
+        let mut queue: Vec<&str> = Vec::new();
+        for item in self.plan.items.iter().filter(|item| item.explicit) {
+            let name = &item.install_name;
+            if failed.contains(name) {
+                continue;
+            }
+            if let Some(dependency) = blocked.get(name) {
+                self.not_planned.push((
+                    name.clone(),
+                    FormulaOutcome::Skipped {
+                        dependency: dependency.clone(),
+                    },
+                ));
+            } else {
+                queue.push(name);
             }
         }
+        while let Some(name) = queue.pop() {
+            if keep.insert(name.to_string()) {
+                queue.extend(
+                    dependencies
+                        .get(name)
+                        .into_iter()
+                        .flatten()
+                        .map(String::as_str),
+                );
+            }
+        }
+        self.plan
+            .items
+            .retain(|item| keep.contains(&item.install_name));
+    }
+}
+
+#[derive(Default)]
+pub(super) struct FetchedFormulas {
+    pub(super) formulas: BTreeMap<String, Formula>,
+    /// Fetch failures in the order they happened.
+    pub(super) errors: Vec<(String, Error)>,
+    /// Fetched, but nothing installable for this platform.
+    pub(super) unavailable: BTreeSet<String>,
+    /// Requested names that resolved to another formula through the alias
+    /// index, which was fetched in their place.
+    pub(super) aliases: BTreeMap<String, String>,
+}
 
-        Ok(formulas)
+/// Why the pinned `keg` can't be replaced by `formula`'s build, or `None`
+/// when it isn't pinned or already is that build.
+pub(super) fn pinned_refusal(keg: &InstalledKeg, formula: &Formula) -> Option<Error> {
+    if !keg.pinned || keg.build().compare(&BuildId::bottle(formula)) == Ordering::Equal {
+        return None;
     }
+    Some(Error::InvalidArgument {
+        message: format!(
+            "'{}' is pinned at {} (run `zb unpin {}` to replace it with {})",
+            keg.name,
+            keg.build(),
+            keg.name,
+            formula.effective_version()
+        ),
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use std::fs;
+    use std::collections::{BTreeMap, BTreeSet};
 
     use tempfile::TempDir;
     use wiremock::matchers::{method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
-    use crate::cellar::Cellar;
+    use zb_core::Error;
+
+    use crate::installer::install::FormulaOutcome;
     use crate::installer::install::test_support::*;
     use crate::network::api::ApiClient;
-    use crate::storage::blob::BlobCache;
-    use crate::storage::db::Database;
-    use crate::storage::store::Store;
-    use crate::{Installer, Linker};
 
     #[tokio::test]
     async fn plans_tapped_formula_with_core_dependency() {
@@ -190,26 +427,14 @@ end
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()))
-            .unwrap()
-            .with_tap_raw_base_url(mock_server.uri());
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.to_path_buf(),
-            root.join("locks"),
+
+        let installer = installer_at(
+            ApiClient::with_base_url(format!("{}/formula", mock_server.uri()))
+                .unwrap()
+                .with_tap_raw_base_url(mock_server.uri()),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
         let plan = installer
             .plan(&["hashicorp/tap/terraform".to_string()])
@@ -253,25 +478,12 @@ end
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+
+        let installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         let plan = installer.plan(&["nobottle".to_string()]).await.unwrap();
@@ -330,25 +542,12 @@ end
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+
+        let installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         let plan = installer.plan(&["hasboth".to_string()]).await.unwrap();
@@ -380,25 +579,12 @@ end
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+
+        let installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         let result = installer.plan(&["nothing".to_string()]).await;
@@ -408,4 +594,238 @@ end
             zb_core::Error::MissingFormula { .. }
         ));
     }
+
+    async fn mount_bottled(mock_server: &MockServer, name: &str, deps: &[&str], corrupt: bool) {
+        let tag = get_test_bottle_tag();
+        let bottle = create_bottle_tarball(name);
+        let sha = sha256_hex(&bottle);
+        let deps = deps
+            .iter()
+            .map(|d| format!("\"{d}\""))
+            .collect::<Vec<_>>()
+            .join(",");
+        let json = format!(
+            r#"{{
+                "name": "{name}",
+                "versions": {{ "stable": "1.0.0" }},
+                "dependencies": [{deps}],
+                "bottle": {{ "stable": {{ "files": {{
+                    "{tag}": {{
+                        "url": "{uri}/bottles/{name}.tar.gz",
+                        "sha256": "{sha}"
+                    }}
+                }} }} }}
+            }}"#,
+            uri = mock_server.uri(),
+        );
+        let body = if corrupt {
+            b"not the bottle".to_vec()
+        } else {
+            bottle
+        };
+
+        Mock::given(method("GET"))
+            .and(path(format!("/formula/{name}.json")))
+            .respond_with(ResponseTemplate::new(200).set_body_string(json))
+            .mount(mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/bottles/{name}.tar.gz")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(body))
+            .mount(mock_server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn isolated_install_completes_independent_formulas() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+
+        mount_bottled(&mock_server, "good1", &[], false).await;
+        mount_bottled(&mock_server, "good2", &[], false).await;
+        mount_bottled(&mock_server, "usesnothing", &["nothing"], false).await;
+        mount_bottled(&mock_server, "libbroken", &[], true).await;
+        mount_bottled(&mock_server, "usesbroken", &["libbroken"], false).await;
+        Mock::given(method("GET"))
+            .and(path("/formula/nothing.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{
+                    "name": "nothing",
+                    "versions": { "stable": "1.0.0" },
+                    "dependencies": [],
+                    "bottle": { "stable": { "files": {} } }
+                }"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+
+        let names: Vec<String> = ["good1", "nothing", "usesnothing", "good2", "usesbroken"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let isolated = installer.plan_isolated(&names, false).await.unwrap();
+
+        assert!(matches!(
+            isolated.not_planned.as_slice(),
+            [
+                (failed, FormulaOutcome::Failed(Error::UnsupportedBottle { .. })),
+                (skipped, FormulaOutcome::Skipped { dependency }),
+            ] if failed == "nothing" && skipped == "usesnothing" && dependency == "nothing"
+        ));
+
+        let result = installer
+            .execute_isolated(isolated.plan, true, None)
+            .await
+            .unwrap();
+
+        let outcomes: BTreeMap<&str, &FormulaOutcome> = result
+            .outcomes
+            .iter()
+            .map(|(name, outcome)| (name.as_str(), outcome))
+            .collect();
+        assert_eq!(result.installed, 2);
+        assert!(matches!(outcomes["good1"], FormulaOutcome::Installed));
+        assert!(matches!(outcomes["good2"], FormulaOutcome::Installed));
+        assert!(matches!(outcomes["libbroken"], FormulaOutcome::Failed(_)));
+        assert!(matches!(
+            outcomes["usesbroken"],
+            FormulaOutcome::Skipped { dependency } if dependency == "libbroken"
+        ));
+
+        assert!(installer.is_installed("good1"));
+        assert!(installer.is_installed("good2"));
+        assert!(!installer.is_installed("usesbroken"));
+        assert!(prefix.join("bin/good1").exists());
+    }
+
+    #[tokio::test]
+    async fn failing_install_stops_the_formulas_queued_behind_it() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_bottled(&mock_server, "abroken", &[], true).await;
+        mount_bottled(&mock_server, "bgood", &[], false).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+
+        let names = vec!["abroken".to_string(), "bgood".to_string()];
+        let plan = installer.plan(&names).await.unwrap();
+        let Err(err) = installer.execute_with_progress(plan, true, None).await else {
+            panic!("a failed bottle fails the install");
+        };
+        assert!(matches!(err, Error::ChecksumMismatch { .. }), "{err}");
+        assert!(!installer.is_installed("bgood"));
+
+        // The isolated run carries on past the same failure.
+        let plan = installer.plan(&names).await.unwrap();
+        let result = installer.execute_isolated(plan, true, None).await.unwrap();
+        assert_eq!(result.installed, 1);
+        assert!(installer.is_installed("bgood"));
+    }
+
+    #[tokio::test]
+    async fn refused_formulas_leave_the_plan_with_what_only_they_need() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_bottled(&mock_server, "onlylib", &[], false).await;
+        mount_bottled(&mock_server, "sharedlib", &[], false).await;
+        mount_bottled(&mock_server, "refused", &["onlylib", "sharedlib"], false).await;
+        mount_bottled(&mock_server, "usesrefused", &["refused"], false).await;
+        mount_bottled(&mock_server, "other", &["sharedlib"], false).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+
+        let names: Vec<String> = ["refused", "usesrefused", "other"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let mut isolated = installer.plan_isolated(&names, false).await.unwrap();
+        isolated.refuse(vec![(
+            "refused".to_string(),
+            Error::InvalidArgument {
+                message: "disabled".to_string(),
+            },
+        )]);
+
+        let planned: BTreeSet<&str> = isolated
+            .plan
+            .items
+            .iter()
+            .map(|item| item.install_name.as_str())
+            .collect();
+        assert_eq!(planned, BTreeSet::from(["other", "sharedlib"]));
+        assert!(matches!(
+            isolated.not_planned.as_slice(),
+            [
+                (failed, FormulaOutcome::Failed(Error::InvalidArgument { .. })),
+                (skipped, FormulaOutcome::Skipped { dependency }),
+            ] if failed == "refused" && skipped == "usesrefused" && dependency == "refused"
+        ));
+    }
+
+    #[tokio::test]
+    async fn pinned_kegs_are_never_replaced_by_a_plan() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_bottled(&mock_server, "lib", &[], false).await;
+        mount_bottled(&mock_server, "app", &["lib"], false).await;
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("lib", "0.9.0", "oldsha").unwrap();
+            tx.commit().unwrap();
+        }
+        installer.set_pinned("lib", true).unwrap();
+
+        let plan = installer.plan(&["app".to_string()]).await.unwrap();
+        let planned: Vec<&str> = plan
+            .items
+            .iter()
+            .map(|item| item.install_name.as_str())
+            .collect();
+        assert_eq!(planned, ["app"]);
+        assert_eq!(plan.pinned["lib"], "0.9.0");
+
+        let err = installer.plan(&["lib".to_string()]).await.unwrap_err();
+        assert!(err.to_string().contains("zb unpin lib"), "{err}");
+
+        let names = vec!["lib".to_string(), "app".to_string()];
+        let isolated = installer.plan_isolated(&names, false).await.unwrap();
+        assert!(matches!(
+            isolated.not_planned.as_slice(),
+            [(name, FormulaOutcome::Failed(Error::InvalidArgument { .. }))] if name == "lib"
+        ));
+        assert_eq!(isolated.plan.items.len(), 1);
+        assert_eq!(isolated.plan.items[0].install_name, "app");
+    }
 }
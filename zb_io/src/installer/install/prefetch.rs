@@ -0,0 +1,140 @@
+//! Fetching the bulk formula index before the first command that needs it,
+//! so the first install or suggestion after init doesn't pay for the
+//! download. The index goes into the metadata cache like any other
+//! response, and is revalidated by the same rules.
+//!
+//! A prefetch marks itself pending in the cache directory until it has
+//! finished, so one cut short by the process exiting, or failing, is
+//! picked up by the next command.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use zb_core::Error;
+
+use super::configured_api_client;
+use crate::network::api::ApiClient;
+use crate::network::client::HttpClient;
+
+/// Marks a prefetch that was started but not finished.
+pub const INDEX_PREFETCH_MARKER: &str = "index-prefetch-pending";
+
+/// How long `zb init` waits for the prefetch before leaving it to the next
+/// command.
+pub const INDEX_PREFETCH_BUDGET: Duration = Duration::from_secs(1);
+
+/// A prefetch running on its own thread. It has its own client and cache
+/// connection, so it never holds up the installer; dropping this handle
+/// leaves it running until the process exits, which abandons it to the
+/// next command.
+pub struct IndexPrefetch {
+    done: mpsc::Receiver<Result<(), Error>>,
+}
+
+impl IndexPrefetch {
+    /// Start fetching the index into the metadata cache in `cache_dir`,
+    /// marking it pending until it succeeds. Nothing is fetched while
+    /// formula metadata comes from a snapshot.
+    pub fn spawn(cache_dir: PathBuf) -> Result<Self, Error> {
+        mark_index_prefetch_pending(&cache_dir)?;
+        let (sender, done) = mpsc::channel();
+        let snapshot = std::env::var_os("ZEROBREW_SNAPSHOT").is_some_and(|p| !p.is_empty());
+        thread::spawn(move || {
+            let result = if snapshot {
+                clear_pending(&cache_dir)
+            } else {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(Error::exec("failed to start prefetch runtime"))
+                    .and_then(|runtime| {
+                        runtime.block_on(async {
+                            fs::create_dir_all(&cache_dir)
+                                .map_err(Error::file("failed to create cache directory"))?;
+                            let client = configured_api_client(&cache_dir, HttpClient::new())?;
+                            prefetch_with(&client, &cache_dir).await
+                        })
+                    })
+            };
+            let _ = sender.send(result);
+        });
+        Ok(Self { done })
+    }
+
+    /// Wait up to `timeout` for the prefetch. `None` when it is still
+    /// running.
+    pub fn wait(&self, timeout: Duration) -> Option<Result<(), Error>> {
+        match self.done.recv_timeout(timeout) {
+            Ok(result) => Some(result),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => Some(Err(Error::ExecutionError {
+                message: "formula index prefetch stopped unexpectedly".to_string(),
+            })),
+        }
+    }
+}
+
+/// Leave the prefetch to the next command that runs.
+pub fn mark_index_prefetch_pending(cache_dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(cache_dir).map_err(Error::file("failed to create cache directory"))?;
+    fs::write(cache_dir.join(INDEX_PREFETCH_MARKER), b"")
+        .map_err(Error::file("failed to write prefetch marker"))
+}
+
+pub fn index_prefetch_pending(cache_dir: &Path) -> bool {
+    cache_dir.join(INDEX_PREFETCH_MARKER).exists()
+}
+
+async fn prefetch_with(client: &ApiClient, cache_dir: &Path) -> Result<(), Error> {
+    client.get_all_formulas_raw().await?;
+    clear_pending(cache_dir)
+}
+
+fn clear_pending(cache_dir: &Path) -> Result<(), Error> {
+    match fs::remove_file(cache_dir.join(INDEX_PREFETCH_MARKER)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::FileError {
+            message: format!("failed to remove prefetch marker: {e}"),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::network::cache::ApiCache;
+
+    #[tokio::test]
+    async fn prefetch_caches_the_index_and_clears_the_marker() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"[{"name":"jq"}]"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let tmp = TempDir::new().unwrap();
+        mark_index_prefetch_pending(tmp.path()).unwrap();
+        assert!(index_prefetch_pending(tmp.path()));
+
+        let url = format!("{}/formula", server.uri());
+        let client = ApiClient::with_base_url(url.clone())
+            .unwrap()
+            .with_cache(ApiCache::open(&tmp.path().join("api-cache.sqlite")).unwrap());
+        prefetch_with(&client, tmp.path()).await.unwrap();
+
+        assert!(!index_prefetch_pending(tmp.path()));
+        let cache = ApiCache::open(&tmp.path().join("api-cache.sqlite")).unwrap();
+        assert_eq!(
+            cache.get(&format!("{url}.json")).unwrap().body,
+            r#"[{"name":"jq"}]"#
+        );
+    }
+}
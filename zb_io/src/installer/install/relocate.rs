@@ -0,0 +1,532 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+use zb_core::{CELLAR_DIR, Error, formula_token};
+
+use crate::cellar::link::Linker;
+use crate::extraction::patch::baked_prefix_files;
+use crate::storage::db::{DriftedKeg, KegFileKind, KegFileRecord};
+
+use super::Installer;
+
+/// What `zb relocate` is about to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocationPlan {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Kegs still to move, as `(name, version)`.
+    pub pending: Vec<(String, String)>,
+    /// Whether this picks up a relocation that was interrupted.
+    pub resuming: bool,
+}
+
+impl Installer {
+    /// Installed kegs patched for a prefix other than the one zb is using,
+    /// which is what changing `ZEROBREW_PREFIX` without `zb relocate` leaves.
+    pub fn prefix_drift(&self) -> Result<Vec<DriftedKeg>, Error> {
+        self.db.kegs_patched_for_other_prefix(&self.prefix)
+    }
+
+    /// Check that the installation can move to `new_prefix` and list the
+    /// kegs left to move. Picks up an interrupted relocation to the same
+    /// prefix; one to another prefix has to be finished first.
+    pub fn plan_relocation(&self, new_prefix: &Path) -> Result<RelocationPlan, Error> {
+        let refuse = |message: String| Err(Error::InvalidArgument { message });
+
+        if !new_prefix.is_absolute() {
+            return refuse(format!(
+                "new prefix must be an absolute path: {}",
+                new_prefix.display()
+            ));
+        }
+
+        let (resuming, relocated) = match self.db.relocation_state()? {
+            Some(state) if state.to != new_prefix => {
+                return refuse(format!(
+                    "a relocation to {} is in progress; run 'zb relocate --prefix {}' to finish it first",
+                    state.to.display(),
+                    state.to.display()
+                ));
+            }
+            Some(state) if state.from != self.prefix => {
+                return refuse(format!(
+                    "the interrupted relocation started from {}, but zb is using {}; \
+                     run it again with ZEROBREW_PREFIX={}",
+                    state.from.display(),
+                    self.prefix.display(),
+                    state.from.display()
+                ));
+            }
+            Some(state) => (true, state.relocated.into_iter().collect()),
+            None => (false, BTreeSet::new()),
+        };
+
+        if !resuming {
+            if new_prefix == self.prefix {
+                return refuse(format!("already using {}", new_prefix.display()));
+            }
+            if new_prefix.starts_with(&self.prefix) || self.prefix.starts_with(new_prefix) {
+                return refuse(format!(
+                    "{} and {} must not contain one another",
+                    self.prefix.display(),
+                    new_prefix.display()
+                ));
+            }
+            let occupied = fs::read_dir(new_prefix)
+                .map(|mut entries| entries.next().is_some())
+                .unwrap_or(false);
+            if occupied {
+                return refuse(format!(
+                    "{} is not empty; relocate into an empty or new directory",
+                    new_prefix.display()
+                ));
+            }
+        }
+
+        let pending: Vec<(String, String)> = self
+            .db
+            .list_installed()?
+            .into_iter()
+            .filter(|keg| !relocated.contains(&keg.name))
+            .map(|keg| (keg.name, keg.version))
+            .collect();
+
+        // Strings compiled into binaries are rewritten in place, which only
+        // works while the new prefix fits where the old one was.
+        let grows = new_prefix.as_os_str().len() > self.prefix.as_os_str().len();
+        if grows {
+            let mut baked = Vec::new();
+            for (name, version) in &pending {
+                let keg_path = self.cellar.keg_path(formula_token(name), version);
+                if keg_path.exists() && !baked_prefix_files(&keg_path, &self.prefix)?.is_empty() {
+                    baked.push(name.as_str());
+                }
+            }
+            if !baked.is_empty() {
+                return refuse(format!(
+                    "{} is longer than {}, and these formulas have it compiled into binaries \
+                     that can't be rewritten to a longer path: {}\n\
+                     pick a prefix of at most {} characters, or reinstall them under the new prefix",
+                    new_prefix.display(),
+                    self.prefix.display(),
+                    baked.join(", "),
+                    self.prefix.as_os_str().len()
+                ));
+            }
+        }
+
+        Ok(RelocationPlan {
+            from: self.prefix.clone(),
+            to: new_prefix.to_path_buf(),
+            pending,
+            resuming,
+        })
+    }
+
+    /// Move every keg in `plan` to the new prefix, relinking what was
+    /// linked, then remove the old kegs and links and record the new prefix.
+    /// Each keg is committed on its own, so an interrupted run resumes where
+    /// it stopped. `on_keg` is called before each keg is moved.
+    pub fn relocate(
+        &mut self,
+        plan: &RelocationPlan,
+        mut on_keg: impl FnMut(&str, &str),
+    ) -> Result<(), Error> {
+        let _lock = self.lock_install()?;
+
+        if !plan.resuming {
+            self.db.begin_relocation(&plan.from, &plan.to)?;
+        }
+
+        let old_cellar_dir = self.cellar.cellar_dir().to_path_buf();
+        let cellar = self
+            .cellar
+            .relocated(plan.to.join(CELLAR_DIR))
+            .map_err(Error::store("failed to create cellar"))?;
+        let linker = Linker::new(&plan.to).map_err(Error::store("failed to create linker"))?;
+
+        let mut records: BTreeMap<String, Vec<KegFileRecord>> = BTreeMap::new();
+        for record in self.db.list_keg_files()? {
+            records.entry(record.name.clone()).or_default().push(record);
+        }
+
+        for (name, version) in &plan.pending {
+            if !self.cellar.has_keg(formula_token(name), version) {
+                warn!(formula = %name, version = %version, "keg is missing; not relocating it");
+                let tx = self.db.transaction()?;
+                // Nothing is left that points at the old prefix.
+                tx.record_patched_prefix(name, &plan.to)?;
+                tx.record_keg_relocated(name)?;
+                tx.commit()?;
+                continue;
+            }
+
+            on_keg(name, version);
+            let keg_path =
+                cellar.relocate_keg(formula_token(name), version, &old_cellar_dir, &plan.from)?;
+
+            if let Err(e) = linker.link_opt(&keg_path) {
+                warn!(formula = %name, error = %e, "failed to create opt link");
+            }
+
+            let records = records.remove(name).unwrap_or_default();
+            let was_linked = records.iter().any(|r| r.kind == KegFileKind::Symlink);
+            let linked_files = if was_linked {
+                linker.link_keg(&keg_path)?
+            } else {
+                Vec::new()
+            };
+
+            let tx = self.db.transaction()?;
+            tx.clear_keg_file_records(name)?;
+            for linked in &linked_files {
+                tx.record_linked(name, version, linked)?;
+            }
+            for record in records.iter().filter(|r| r.kind == KegFileKind::Config) {
+                let linked_path = move_prefix_file(&record.linked_path, &plan.from, &plan.to)?;
+                let target_path = rebase(
+                    Path::new(&record.target_path),
+                    &old_cellar_dir,
+                    cellar.cellar_dir(),
+                );
+                tx.record_linked_file(
+                    name,
+                    version,
+                    &linked_path.to_string_lossy(),
+                    &target_path.to_string_lossy(),
+                    None,
+                    record.kind,
+                )?;
+            }
+            tx.record_patched_prefix(name, &plan.to)?;
+            tx.record_keg_relocated(name)?;
+            tx.commit()?;
+        }
+
+        // Every keg now lives under the new prefix; drop the old copies.
+        let old_linker =
+            Linker::new(&plan.from).map_err(Error::store("failed to open old prefix"))?;
+        for keg in self.db.list_installed()? {
+            let old_keg = self.cellar.keg_path(formula_token(&keg.name), &keg.version);
+            if let Err(e) = old_linker.unlink_keg(&old_keg) {
+                warn!(formula = %keg.name, error = %e, "failed to unlink old keg");
+            }
+            self.cellar
+                .remove_keg(formula_token(&keg.name), &keg.version)?;
+        }
+
+        // Versions kept for rollback still point into the old prefix, so
+        // they are dropped rather than moved.
+        let retained = self.db.list_retained(None)?;
+        if !retained.is_empty() {
+            let tx = self.db.transaction()?;
+            for keg in &retained {
+                tx.drop_retained(&keg.name, &keg.version)?;
+            }
+            tx.commit()?;
+            for keg in &retained {
+                self.cellar
+                    .remove_keg(formula_token(&keg.name), &keg.version)?;
+            }
+        }
+
+        self.db.finish_relocation(&plan.to)?;
+        self.prefix = plan.to.clone();
+        self.cellar = cellar;
+        self.linker = linker;
+        Ok(())
+    }
+}
+
+/// `path` moved from under `from` to the same place under `to`; anything
+/// else is returned unchanged.
+fn rebase(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    path.strip_prefix(from)
+        .map(|rest| to.join(rest))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Copy a file the keg owns in the old prefix, like a config file, to the
+/// new prefix and return its new path. The old file is left in place.
+fn move_prefix_file(linked_path: &str, from: &Path, to: &Path) -> Result<PathBuf, Error> {
+    let old_path = Path::new(linked_path);
+    let new_path = rebase(old_path, from, to);
+    if new_path != old_path && old_path.is_file() && !new_path.exists() {
+        if let Some(parent) = new_path.parent() {
+            fs::create_dir_all(parent).map_err(Error::store("failed to create directory"))?;
+        }
+        let ctx = format!("failed to copy {}", old_path.display());
+        fs::copy(old_path, &new_path).map_err(Error::store(ctx.as_str()))?;
+    }
+    Ok(new_path)
+}
+
+// Every test pours prefix placeholders, which need a patch backend.
+#[cfg(all(
+    test,
+    any(
+        all(target_os = "linux", feature = "patch-elf"),
+        all(target_os = "macos", feature = "patch-macho")
+    )
+))]
+mod tests {
+    use super::*;
+    use crate::installer::install::test_support::*;
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A bottle whose script prints a path under the prefix it was poured
+    /// into, and optionally a "binary" with a path under `baked` compiled in.
+    fn prefix_aware_bottle(name: &str, baked: Option<&Path>) -> Vec<u8> {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut append = |path: String, content: &[u8], mode: u32| {
+            let mut header = tar::Header::new_gnu();
+            header.set_path(path).unwrap();
+            header.set_size(content.len() as u64);
+            header.set_mode(mode);
+            header.set_cksum();
+            builder.append(&header, content).unwrap();
+        };
+        append(
+            format!("{name}/1.0.0/bin/{name}"),
+            format!("#!/bin/sh\necho @@HOMEBREW_PREFIX@@/share/{name}\n").as_bytes(),
+            0o755,
+        );
+        if let Some(prefix) = baked {
+            append(
+                format!("{name}/1.0.0/libexec/{name}-helper"),
+                format!("\0\x7fhelper\0{}/etc/helper.conf\0", prefix.display()).as_bytes(),
+                0o755,
+            );
+        }
+        let tar_data = builder.into_inner().unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tar_data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn relocate_moves_kegs_links_and_records() {
+        let server = MockServer::start().await;
+        mount_formula(
+            &server,
+            "movable",
+            "1.0.0",
+            &[],
+            Some(prefix_aware_bottle("movable", None)),
+        )
+        .await;
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let old_prefix = tmp.path().join("old-prefix");
+        let new_prefix = tmp.path().join("new");
+        let mut installer = installer_at(
+            api_client(&server),
+            &root,
+            &old_prefix,
+            old_prefix.join("Cellar"),
+        );
+        installer
+            .install(&["movable".to_string()], true)
+            .await
+            .unwrap();
+
+        let plan = installer.plan_relocation(&new_prefix).unwrap();
+        assert_eq!(plan.pending, [("movable".to_string(), "1.0.0".to_string())]);
+        assert!(!plan.resuming);
+
+        let mut moved = Vec::new();
+        installer
+            .relocate(&plan, |name, _| moved.push(name.to_string()))
+            .unwrap();
+
+        assert_eq!(moved, ["movable"]);
+        let output = std::process::Command::new(new_prefix.join("bin/movable"))
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            format!("{}/share/movable", new_prefix.display())
+        );
+        assert!(new_prefix.join("opt/movable").exists());
+        assert!(!old_prefix.join("Cellar/movable").exists());
+        assert!(old_prefix.join("bin/movable").symlink_metadata().is_err());
+
+        let records = installer.db.list_keg_files().unwrap();
+        assert!(!records.is_empty());
+        assert!(
+            records
+                .iter()
+                .all(|r| Path::new(&r.linked_path).starts_with(&new_prefix))
+        );
+        assert_eq!(installer.db.relocation_state().unwrap(), None);
+        assert_eq!(installer.db.recorded_prefix().unwrap(), Some(new_prefix));
+    }
+
+    #[tokio::test]
+    async fn relocate_moves_tap_formula_kegs_by_their_token() {
+        let server = MockServer::start().await;
+        let bottle = prefix_aware_bottle("foo", None);
+        let tag = get_test_bottle_tag();
+        let tap_formula_rb = format!(
+            r#"
+class Foo < Formula
+  version "1.0.0"
+  bottle do
+    root_url "{}/bottles"
+    sha256 {}: "{}"
+  end
+end
+"#,
+            server.uri(),
+            tag,
+            sha256_hex(&bottle)
+        );
+        Mock::given(method("GET"))
+            .and(path("/user/homebrew-tap/main/Formula/foo.rb"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(tap_formula_rb))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/bottles/foo-1.0.0.{tag}.bottle.tar.gz")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let old_prefix = tmp.path().join("old-prefix");
+        let new_prefix = tmp.path().join("new");
+        let mut installer = installer_at(
+            api_client(&server).with_tap_raw_base_url(server.uri()),
+            &root,
+            &old_prefix,
+            old_prefix.join("Cellar"),
+        );
+        installer
+            .install(&["user/tap/foo".to_string()], true)
+            .await
+            .unwrap();
+        assert!(old_prefix.join("Cellar/foo/1.0.0").exists());
+
+        let plan = installer.plan_relocation(&new_prefix).unwrap();
+        installer.relocate(&plan, |_, _| {}).unwrap();
+
+        assert!(new_prefix.join("Cellar/foo/1.0.0/bin/foo").exists());
+        assert!(!old_prefix.join("Cellar/foo").exists());
+        let output = std::process::Command::new(new_prefix.join("bin/foo"))
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            format!("{}/share/foo", new_prefix.display())
+        );
+    }
+
+    #[tokio::test]
+    async fn relocate_resumes_after_an_interruption() {
+        let server = MockServer::start().await;
+        mount_formula(
+            &server,
+            "first",
+            "1.0.0",
+            &[],
+            Some(prefix_aware_bottle("first", None)),
+        )
+        .await;
+        mount_formula(
+            &server,
+            "second",
+            "1.0.0",
+            &[],
+            Some(prefix_aware_bottle("second", None)),
+        )
+        .await;
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let old_prefix = tmp.path().join("old-prefix");
+        let new_prefix = tmp.path().join("new");
+        let mut installer = installer_at(
+            api_client(&server),
+            &root,
+            &old_prefix,
+            old_prefix.join("Cellar"),
+        );
+        installer
+            .install(&["first".to_string(), "second".to_string()], true)
+            .await
+            .unwrap();
+
+        // Stand in for a run that moved `first` and then died.
+        installer
+            .db
+            .begin_relocation(&old_prefix, &new_prefix)
+            .unwrap();
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_keg_relocated("first").unwrap();
+            tx.commit().unwrap();
+        }
+
+        let elsewhere = installer.plan_relocation(&tmp.path().join("other"));
+        assert!(matches!(elsewhere, Err(Error::InvalidArgument { .. })));
+
+        let plan = installer.plan_relocation(&new_prefix).unwrap();
+        assert!(plan.resuming);
+        assert_eq!(plan.pending, [("second".to_string(), "1.0.0".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn longer_prefix_is_refused_when_binaries_have_it_compiled_in() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let old_prefix = tmp.path().join("p");
+        mount_formula(
+            &server,
+            "baked",
+            "1.0.0",
+            &[],
+            Some(prefix_aware_bottle("baked", Some(&old_prefix))),
+        )
+        .await;
+        mount_formula(
+            &server,
+            "scripted",
+            "1.0.0",
+            &[],
+            Some(prefix_aware_bottle("scripted", None)),
+        )
+        .await;
+
+        let mut installer = installer_at(
+            api_client(&server),
+            &root,
+            &old_prefix,
+            old_prefix.join("Cellar"),
+        );
+        installer
+            .install(&["baked".to_string(), "scripted".to_string()], true)
+            .await
+            .unwrap();
+
+        let err = installer
+            .plan_relocation(&tmp.path().join("a-much-longer-prefix"))
+            .unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("baked"), "{message}");
+        assert!(!message.contains("scripted"), "{message}");
+        assert!(installer.plan_relocation(&tmp.path().join("q")).is_ok());
+    }
+}
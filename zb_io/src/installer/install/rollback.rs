@@ -0,0 +1,791 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use tracing::warn;
+use zb_core::{Error, formula_token};
+
+use crate::storage::db::{InstalledKeg, KegFileKind, RetainedKeg};
+
+use super::Installer;
+use super::hooks::HookPoint;
+
+/// Previous versions kept per formula after an upgrade unless configured
+/// otherwise.
+pub const DEFAULT_KEEP_VERSIONS: usize = 1;
+
+/// What `zb rollback` switched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rollback {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// What `zb uninstall --version` removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRemoval {
+    pub name: String,
+    pub version: String,
+    /// The retained version made active in place of the removed one, when
+    /// the removed version was the active one.
+    pub activated: Option<String>,
+}
+
+impl Installer {
+    /// How many previous versions of each formula upgrades keep around for
+    /// `rollback`. Older ones are removed after the next install or gc.
+    pub fn set_keep_versions(&mut self, keep: usize) {
+        self.keep_versions = keep;
+    }
+
+    /// Previous versions of `name` that can be rolled back to, newest first.
+    pub fn retained_versions(&self, name: &str) -> Result<Vec<RetainedKeg>, Error> {
+        self.db.list_retained(Some(name))
+    }
+
+    /// Make a retained version of `name` active again: `version`, or the
+    /// most recently replaced one. The active keg is unlinked and retained
+    /// in its place; if the retained keg cannot be linked, the active one is
+    /// linked back and nothing changes.
+    pub fn rollback(&mut self, name: &str, version: Option<&str>) -> Result<Rollback, Error> {
+        let _lock = self.lock_install()?;
+
+        let installed = self.db.get_installed(name)?.ok_or(Error::NotInstalled {
+            name: name.to_string(),
+        })?;
+        let retained = self.db.list_retained(Some(name))?;
+        let target = match version {
+            Some(version) => retained.iter().find(|keg| keg.version == version),
+            None => retained.first(),
+        };
+        let Some(target) = target else {
+            let message = match version {
+                Some(version) if !retained.is_empty() => {
+                    let kept: Vec<&str> = retained.iter().map(|keg| keg.version.as_str()).collect();
+                    format!(
+                        "{name} {version} is not kept; kept versions: {}",
+                        kept.join(", ")
+                    )
+                }
+                _ => format!("no previous version of {name} is kept to roll back to"),
+            };
+            return Err(Error::InvalidArgument { message });
+        };
+
+        let token = formula_token(name);
+        let current_keg = self.cellar.keg_path(token, &installed.version);
+        let target_keg = self.cellar.keg_path(token, &target.version);
+        if !target_keg.is_dir() {
+            return Err(Error::InvalidArgument {
+                message: format!(
+                    "the keg for {name} {} is missing from {}",
+                    target.version,
+                    target_keg.display()
+                ),
+            });
+        }
+
+        let was_linked = self
+            .db
+            .list_keg_files()?
+            .iter()
+            .any(|record| record.name == name && record.kind == KegFileKind::Symlink);
+
+        self.linker.unlink_keg(&current_keg)?;
+        let linked = if was_linked && !self.kept_keg_only(name, &target.version) {
+            self.linker.link_keg(&target_keg)
+        } else {
+            self.linker.link_opt(&target_keg).map(|()| Vec::new())
+        };
+        let linked_files = match linked {
+            Ok(files) => files,
+            Err(e) => {
+                self.restore_links(name, &target_keg, &current_keg, was_linked);
+                return Err(e);
+            }
+        };
+
+        let recorded = self.db.transaction().and_then(|tx| {
+            tx.activate_retained(name, &target.version)?;
+            for linked in &linked_files {
+                tx.record_linked(name, &target.version, linked)?;
+            }
+            tx.commit()
+        });
+        if let Err(e) = recorded {
+            self.restore_links(name, &target_keg, &current_keg, was_linked);
+            return Err(e);
+        }
+
+        Ok(Rollback {
+            name: name.to_string(),
+            from: installed.version,
+            to: target.version.clone(),
+        })
+    }
+
+    /// Remove one version of `name`, active or retained, and leave the
+    /// others installed. Removing the active version is refused unless
+    /// `force` is set, in which case the newest retained version is linked
+    /// in its place. When `version` is the only one left, this is a plain
+    /// uninstall. Uninstall hooks run for the removed version.
+    pub fn uninstall_version(
+        &mut self,
+        name: &str,
+        version: &str,
+        force: bool,
+    ) -> Result<VersionRemoval, Error> {
+        // Checked up front so hooks never run for a removal that is refused,
+        // and again under the lock.
+        self.check_version_removal(name, version, force)?;
+        self.run_hooks(HookPoint::PreUninstall, name, version)?;
+        let removal = self.remove_version(name, version, force)?;
+        // Post hook failures are logged and don't fail the uninstall.
+        let _ = self.run_hooks(HookPoint::PostUninstall, name, version);
+        Ok(removal)
+    }
+
+    /// Refuse to remove a version that isn't installed, or the active one
+    /// without `force` while others are kept.
+    fn check_version_removal(&self, name: &str, version: &str, force: bool) -> Result<(), Error> {
+        let installed = self.db.get_installed(name)?.ok_or(Error::NotInstalled {
+            name: name.to_string(),
+        })?;
+        let retained = self.db.list_retained(Some(name))?;
+
+        if installed.version != version {
+            if !retained.iter().any(|keg| keg.version == version) {
+                let mut versions = vec![installed.version.as_str()];
+                versions.extend(retained.iter().map(|keg| keg.version.as_str()));
+                return Err(Error::InvalidArgument {
+                    message: format!(
+                        "{name} {version} is not installed; installed versions: {}",
+                        versions.join(", ")
+                    ),
+                });
+            }
+        } else if let Some(target) = retained.first()
+            && !force
+        {
+            return Err(Error::InvalidArgument {
+                message: format!(
+                    "{name} {version} is the active version; pass --force to remove it \
+                     and switch to {}",
+                    target.version
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    fn remove_version(
+        &mut self,
+        name: &str,
+        version: &str,
+        force: bool,
+    ) -> Result<VersionRemoval, Error> {
+        let _lock = self.lock_install()?;
+
+        self.check_version_removal(name, version, force)?;
+        let installed = self.db.get_installed(name)?.ok_or(Error::NotInstalled {
+            name: name.to_string(),
+        })?;
+        let retained = self.db.list_retained(Some(name))?;
+        let removal = VersionRemoval {
+            name: name.to_string(),
+            version: version.to_string(),
+            activated: None,
+        };
+        let token = formula_token(name);
+
+        if installed.version != version {
+            let tx = self.db.transaction()?;
+            tx.drop_retained(name, version)?;
+            tx.commit()?;
+            self.cellar.remove_keg(token, version)?;
+            self.forget_metadata(token, version);
+            return Ok(removal);
+        }
+
+        let Some(target) = retained.first() else {
+            // The caller runs the hooks for this version.
+            let plan = self.plan_uninstall(&[name.to_string()], false)?;
+            self.execute_uninstall_with(plan, false)?
+                .pop()
+                .map_or(Ok(()), |outcome| outcome.result)?;
+            return Ok(removal);
+        };
+
+        let current_keg = self.cellar.keg_path(token, version);
+        let target_keg = self.cellar.keg_path(token, &target.version);
+        if !target_keg.is_dir() {
+            return Err(Error::InvalidArgument {
+                message: format!(
+                    "the keg for {name} {} is missing from {}",
+                    target.version,
+                    target_keg.display()
+                ),
+            });
+        }
+
+        let was_linked = self
+            .db
+            .list_keg_files()?
+            .iter()
+            .any(|record| record.name == name && record.kind == KegFileKind::Symlink);
+
+        self.linker.unlink_keg(&current_keg)?;
+        let linked = if was_linked && !self.kept_keg_only(name, &target.version) {
+            self.linker.link_keg(&target_keg)
+        } else {
+            self.linker.link_opt(&target_keg).map(|()| Vec::new())
+        };
+        let linked_files = match linked {
+            Ok(files) => files,
+            Err(e) => {
+                self.restore_links(name, &target_keg, &current_keg, was_linked);
+                return Err(e);
+            }
+        };
+
+        // Activating retains the removed version, which is then dropped
+        // along with its store reference.
+        let recorded = self.db.transaction().and_then(|tx| {
+            tx.activate_retained(name, &target.version)?;
+            tx.drop_retained(name, version)?;
+            for linked in &linked_files {
+                tx.record_linked(name, &target.version, linked)?;
+            }
+            tx.commit()
+        });
+        if let Err(e) = recorded {
+            self.restore_links(name, &target_keg, &current_keg, was_linked);
+            return Err(e);
+        }
+
+        self.cellar.remove_keg(token, version)?;
+        self.forget_metadata(token, version);
+        Ok(VersionRemoval {
+            activated: Some(target.version.clone()),
+            ..removal
+        })
+    }
+
+    /// Whether the metadata `version` of `name` was installed from made it
+    /// keg-only, so it gets only its `opt` link even when the keg it
+    /// replaces was linked. Without kept metadata the links are carried over.
+    fn kept_keg_only(&self, name: &str, version: &str) -> bool {
+        matches!(self.metadata.read(name, version), Ok(Some(formula)) if formula.is_keg_only())
+    }
+
+    fn restore_links(&self, name: &str, target_keg: &Path, current_keg: &Path, was_linked: bool) {
+        let _ = self.linker.unlink_keg(target_keg);
+        let restored = if was_linked {
+            self.linker.link_keg(current_keg).map(|_| ())
+        } else {
+            self.linker.link_opt(current_keg)
+        };
+        if let Err(e) = restored {
+            warn!(formula = %name, error = %e, "failed to relink keg after rollback error");
+        }
+    }
+
+    /// Whether `version` of `name` has links recorded in the prefix, checked
+    /// before an install replaces it and its records are cleared.
+    pub(super) fn has_links(&self, name: &str, version: &str) -> bool {
+        self.db
+            .list_keg_files_for(name, version)
+            .is_ok_and(|records| {
+                records
+                    .iter()
+                    .any(|record| record.kind == KegFileKind::Symlink)
+            })
+    }
+
+    /// Put back the keg an install of `version` replaced when the new keg
+    /// could not be linked, so an upgrade that fails halfway leaves the old
+    /// version active and linked as before. The new keg is removed, which
+    /// releases its store reference. Returns the version put back, if any.
+    pub(super) fn restore_replaced(
+        &mut self,
+        name: &str,
+        previous: Option<&InstalledKeg>,
+        previously_linked: bool,
+        version: &str,
+    ) -> Option<String> {
+        let previous = previous.filter(|keg| keg.version != version)?;
+        let token = formula_token(name);
+        let previous_keg = self.cellar.keg_path(token, &previous.version);
+        let linked = if previously_linked {
+            self.linker.link_keg(&previous_keg)
+        } else {
+            self.linker.link_opt(&previous_keg).map(|()| Vec::new())
+        };
+        let restored = linked.and_then(|linked_files| {
+            let tx = self.db.transaction()?;
+            tx.activate_retained(name, &previous.version)?;
+            tx.drop_retained(name, version)?;
+            for linked in &linked_files {
+                tx.record_linked(name, &previous.version, linked)?;
+            }
+            tx.commit()
+        });
+        if let Err(e) = restored {
+            warn!(
+                formula = %name,
+                version = %previous.version,
+                error = %e,
+                "failed to restore the version an install replaced"
+            );
+            return None;
+        }
+        if let Err(e) = self.cellar.remove_keg(token, version) {
+            warn!(formula = %name, version = %version, error = %e, "failed to remove unlinked keg");
+        }
+        self.forget_metadata(token, version);
+        Some(previous.version.clone())
+    }
+
+    /// Unlink the keg an install of `version` replaced, which record_install
+    /// has just retained, so the new keg can take over its links.
+    pub(super) fn unlink_replaced(&self, previous: Option<&InstalledKeg>, version: &str) {
+        let Some(previous) = previous.filter(|keg| keg.version != version) else {
+            return;
+        };
+        let keg_path = self
+            .cellar
+            .keg_path(formula_token(&previous.name), &previous.version);
+        if let Err(e) = self.linker.unlink_keg(&keg_path) {
+            warn!(
+                formula = %previous.name,
+                version = %previous.version,
+                error = %e,
+                "failed to unlink replaced keg"
+            );
+        }
+    }
+
+    /// Drop retained versions beyond the configured number, oldest first,
+    /// for `name` or for every formula. Returns the dropped kegs.
+    pub(super) fn prune_retained(
+        &mut self,
+        name: Option<&str>,
+        dry_run: bool,
+    ) -> Result<Vec<RetainedKeg>, Error> {
+        let keep = self.keep_versions;
+        let mut kept: HashMap<String, usize> = HashMap::new();
+        let excess: Vec<RetainedKeg> = self
+            .db
+            .list_retained(name)?
+            .into_iter()
+            .filter(|keg| {
+                let count = kept.entry(keg.name.clone()).or_default();
+                *count += 1;
+                *count > keep
+            })
+            .collect();
+        if dry_run || excess.is_empty() {
+            return Ok(excess);
+        }
+
+        let tx = self.db.transaction()?;
+        for keg in &excess {
+            tx.drop_retained(&keg.name, &keg.version)?;
+        }
+        tx.commit()?;
+
+        for keg in &excess {
+            self.cellar
+                .remove_keg(formula_token(&keg.name), &keg.version)?;
+            self.forget_metadata(&keg.name, &keg.version);
+        }
+        Ok(excess)
+    }
+
+    /// Run [`prune_retained`](Self::prune_retained) for `name` after an
+    /// install; failing to prune never fails the install.
+    pub(super) fn prune_retained_after_install(&mut self, name: &str) {
+        if let Err(e) = self.prune_retained(Some(name), false) {
+            warn!(formula = %name, error = %e, "failed to remove old retained versions");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    use crate::installer::install::test_support::*;
+
+    use crate::progress::{InstallProgress, ProgressCallback};
+
+    fn bottle(name: &str, version: &str) -> Vec<u8> {
+        let content = format!("#!/bin/sh\necho {name} {version}\n");
+        let mut header = tar::Header::new_gnu();
+        header
+            .set_path(format!("{name}/{version}/bin/{name}"))
+            .unwrap();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append(&header, content.as_bytes()).unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Serve `version` of `name` as the current formula, with a bottle that
+    /// may be downloaded exactly once.
+    async fn release(server: &MockServer, name: &str, version: &str) {
+        publish(server, name, version, false).await;
+    }
+
+    async fn publish(server: &MockServer, name: &str, version: &str, keg_only: bool) {
+        serve(server, name, version, keg_only, bottle(name, version)).await;
+    }
+
+    async fn serve(
+        server: &MockServer,
+        name: &str,
+        version: &str,
+        keg_only: bool,
+        bottle: Vec<u8>,
+    ) {
+        server.reset().await;
+        let tag = get_test_bottle_tag();
+        let bottle_path = format!("/bottles/{name}-{version}.{tag}.bottle.tar.gz");
+        let formula_json = serde_json::json!({
+            "name": name,
+            "versions": { "stable": version },
+            "dependencies": [],
+            "keg_only": keg_only,
+            "bottle": { "stable": { "files": { tag: {
+                "url": format!("{}{bottle_path}", server.uri()),
+                "sha256": sha256_hex(&bottle),
+            }}}}
+        });
+        Mock::given(method("GET"))
+            .and(path(format!("/formula/{name}.json")))
+            .respond_with(ResponseTemplate::new(200).set_body_string(formula_json.to_string()))
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(bottle_path))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    async fn install(installer: &mut Installer, name: &str) {
+        let plan = installer.plan(&[name.to_string()]).await.unwrap();
+        installer.execute(plan, true).await.unwrap();
+    }
+
+    fn linked_script(tmp: &TempDir, name: &str) -> String {
+        fs::read_to_string(tmp.path().join("homebrew/bin").join(name)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rollback_relinks_the_kept_version_without_downloading() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+
+        release(&server, "tool", "1.0.0").await;
+        install(&mut installer, "tool").await;
+        server.verify().await;
+        let old_key = installer.get_installed("tool").unwrap().unwrap().store_key;
+
+        release(&server, "tool", "2.0.0").await;
+        install(&mut installer, "tool").await;
+        server.verify().await;
+        assert_eq!(linked_script(&tmp, "tool"), "#!/bin/sh\necho tool 2.0.0\n");
+        assert!(installer.keg_path("tool", "1.0.0").is_dir());
+        assert_eq!(installer.db.get_store_refcount(&old_key).unwrap(), 1);
+
+        // Nothing may be fetched from here on.
+        server.reset().await;
+        let rollback = installer.rollback("tool", None).unwrap();
+        assert_eq!(
+            rollback,
+            Rollback {
+                name: "tool".to_string(),
+                from: "2.0.0".to_string(),
+                to: "1.0.0".to_string(),
+            }
+        );
+        assert_eq!(linked_script(&tmp, "tool"), "#!/bin/sh\necho tool 1.0.0\n");
+        assert_eq!(
+            installer.get_installed("tool").unwrap().unwrap().version,
+            "1.0.0"
+        );
+        let retained = installer.retained_versions("tool").unwrap();
+        assert_eq!(retained.len(), 1);
+        assert_eq!(retained[0].version, "2.0.0");
+        assert!(server.received_requests().await.unwrap().is_empty());
+
+        // Rolling back again returns to the upgrade.
+        installer.rollback("tool", Some("2.0.0")).unwrap();
+        assert_eq!(linked_script(&tmp, "tool"), "#!/bin/sh\necho tool 2.0.0\n");
+    }
+
+    #[tokio::test]
+    async fn rollback_links_by_the_metadata_the_kept_version_was_installed_from() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        let bin = tmp.path().join("homebrew/bin/tool");
+
+        publish(&server, "tool", "1.0.0", true).await;
+        install(&mut installer, "tool").await;
+        assert!(!bin.exists());
+        let kept = installer.installed_formula("tool").unwrap().unwrap();
+        assert!(kept.is_keg_only());
+
+        // Upstream stopped making it keg-only, so 2.0.0 was linked.
+        release(&server, "tool", "2.0.0").await;
+        install(&mut installer, "tool").await;
+        assert!(bin.exists());
+        assert!(
+            !installer
+                .installed_formula("tool")
+                .unwrap()
+                .unwrap()
+                .is_keg_only()
+        );
+
+        server.reset().await;
+        installer.rollback("tool", None).unwrap();
+        assert!(!bin.exists());
+        assert!(tmp.path().join("homebrew/opt/tool").exists());
+        assert!(
+            installer
+                .installed_formula("tool")
+                .unwrap()
+                .unwrap()
+                .is_keg_only()
+        );
+
+        let metadata = tmp.path().join("zerobrew/metadata");
+        assert!(metadata.join("tool-1.0.0.json.zst").is_file());
+        assert!(metadata.join("tool-2.0.0.json.zst").is_file());
+        installer.uninstall("tool").unwrap();
+        assert!(!metadata.join("tool-1.0.0.json.zst").exists());
+        assert!(!metadata.join("tool-2.0.0.json.zst").exists());
+    }
+
+    #[tokio::test]
+    async fn an_upgrade_that_cannot_link_leaves_the_old_version_linked() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+
+        release(&server, "tool", "1.0.0").await;
+        install(&mut installer, "tool").await;
+        let old_key = installer.get_installed("tool").unwrap().unwrap().store_key;
+
+        // 2.0.0 adds a file something else already put in the prefix.
+        fs::write(tmp.path().join("homebrew/bin/tool-extra"), "not ours").unwrap();
+        let upgrade = versioned_bottle(
+            "tool",
+            "2.0.0",
+            &[
+                ("bin/tool", b"#!/bin/sh\necho tool 2.0.0\n"),
+                ("bin/tool-extra", b""),
+            ],
+        );
+        serve(&server, "tool", "2.0.0", false, upgrade).await;
+        let plan = installer.plan(&["tool".to_string()]).await.unwrap();
+        let restored = Arc::new(Mutex::new(Vec::new()));
+        let seen = restored.clone();
+        let progress: Arc<ProgressCallback> = Arc::new(Box::new(move |event| {
+            if let InstallProgress::LinkRestored { name, version } = event {
+                seen.lock().unwrap().push(format!("{name} {version}"));
+            }
+        }));
+        let result = installer
+            .execute_with_progress(plan, true, Some(progress))
+            .await;
+        assert!(matches!(result, Err(Error::LinkConflict { .. })));
+        assert_eq!(*restored.lock().unwrap(), ["tool 1.0.0"]);
+
+        assert_eq!(linked_script(&tmp, "tool"), "#!/bin/sh\necho tool 1.0.0\n");
+        let installed = installer.get_installed("tool").unwrap().unwrap();
+        assert_eq!(installed.version, "1.0.0");
+        assert_eq!(installer.db.get_store_refcount(&old_key).unwrap(), 1);
+        assert!(retained(&installer).is_empty());
+        assert!(!installer.keg_path("tool", "2.0.0").exists());
+        assert!(
+            fs::read_link(tmp.path().join("homebrew/opt/tool"))
+                .unwrap()
+                .ends_with("1.0.0")
+        );
+        assert_eq!(installer.keg_files("tool", "1.0.0").unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rollback_requires_a_kept_version() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+
+        release(&server, "tool", "1.0.0").await;
+        install(&mut installer, "tool").await;
+
+        let err = installer.rollback("tool", None).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("no previous version of tool is kept")
+        );
+
+        release(&server, "tool", "2.0.0").await;
+        install(&mut installer, "tool").await;
+
+        let err = installer.rollback("tool", Some("0.9.0")).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("tool 0.9.0 is not kept; kept versions: 1.0.0")
+        );
+        assert_eq!(
+            installer.get_installed("tool").unwrap().unwrap().version,
+            "2.0.0"
+        );
+    }
+
+    /// Install 1.0.0, 2.0.0 and 3.0.0 of `tool`, keeping both older ones.
+    async fn three_versions(server: &MockServer, installer: &mut Installer) -> Vec<String> {
+        installer.set_keep_versions(2);
+        let mut keys = Vec::new();
+        for version in ["1.0.0", "2.0.0", "3.0.0"] {
+            release(server, "tool", version).await;
+            install(installer, "tool").await;
+            keys.push(installer.get_installed("tool").unwrap().unwrap().store_key);
+        }
+        keys
+    }
+
+    fn retained(installer: &Installer) -> Vec<String> {
+        installer
+            .retained_versions("tool")
+            .unwrap()
+            .into_iter()
+            .map(|keg| keg.version)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn uninstalling_a_kept_version_leaves_the_active_one_linked() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        let keys = three_versions(&server, &mut installer).await;
+
+        let removal = installer.uninstall_version("tool", "1.0.0", false).unwrap();
+        assert_eq!(removal.activated, None);
+        assert!(!installer.keg_path("tool", "1.0.0").exists());
+        assert_eq!(installer.db.get_store_refcount(&keys[0]).unwrap(), 0);
+        assert_eq!(installer.db.get_store_refcount(&keys[1]).unwrap(), 1);
+        assert_eq!(retained(&installer), ["2.0.0"]);
+        assert_eq!(
+            installer.get_installed("tool").unwrap().unwrap().version,
+            "3.0.0"
+        );
+        assert_eq!(linked_script(&tmp, "tool"), "#!/bin/sh\necho tool 3.0.0\n");
+
+        let err = installer
+            .uninstall_version("tool", "0.9.0", false)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("tool 0.9.0 is not installed; installed versions: 3.0.0, 2.0.0")
+        );
+    }
+
+    #[tokio::test]
+    async fn uninstalling_the_active_version_needs_force_and_activates_the_newest_kept() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        let keys = three_versions(&server, &mut installer).await;
+
+        let err = installer
+            .uninstall_version("tool", "3.0.0", false)
+            .unwrap_err();
+        assert!(err.to_string().contains("pass --force"));
+        assert!(installer.keg_path("tool", "3.0.0").is_dir());
+        assert_eq!(linked_script(&tmp, "tool"), "#!/bin/sh\necho tool 3.0.0\n");
+
+        let removal = installer.uninstall_version("tool", "3.0.0", true).unwrap();
+        assert_eq!(removal.activated.as_deref(), Some("2.0.0"));
+        assert!(!installer.keg_path("tool", "3.0.0").exists());
+        assert_eq!(installer.db.get_store_refcount(&keys[2]).unwrap(), 0);
+        assert_eq!(installer.db.get_store_refcount(&keys[1]).unwrap(), 1);
+        assert_eq!(
+            installer.get_installed("tool").unwrap().unwrap().version,
+            "2.0.0"
+        );
+        assert_eq!(retained(&installer), ["1.0.0"]);
+        assert_eq!(linked_script(&tmp, "tool"), "#!/bin/sh\necho tool 2.0.0\n");
+    }
+
+    #[tokio::test]
+    async fn uninstalling_the_last_version_removes_the_formula() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+
+        release(&server, "tool", "1.0.0").await;
+        install(&mut installer, "tool").await;
+        let key = installer.get_installed("tool").unwrap().unwrap().store_key;
+
+        let removal = installer.uninstall_version("tool", "1.0.0", false).unwrap();
+        assert_eq!(removal.activated, None);
+        assert!(installer.get_installed("tool").unwrap().is_none());
+        assert!(!installer.keg_path("tool", "1.0.0").exists());
+        assert_eq!(installer.db.get_store_refcount(&key).unwrap(), 0);
+        assert!(!tmp.path().join("homebrew/bin/tool").exists());
+    }
+
+    #[tokio::test]
+    async fn upgrades_keep_only_the_configured_number_of_versions() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+
+        for version in ["1.0.0", "2.0.0", "3.0.0"] {
+            release(&server, "tool", version).await;
+            install(&mut installer, "tool").await;
+        }
+
+        let retained: Vec<String> = installer
+            .retained_versions("tool")
+            .unwrap()
+            .into_iter()
+            .map(|keg| keg.version)
+            .collect();
+        assert_eq!(retained, vec!["2.0.0".to_string()]);
+        assert!(!installer.keg_path("tool", "1.0.0").exists());
+        assert!(installer.keg_path("tool", "2.0.0").is_dir());
+
+        // Lowering the limit is enforced by the next gc.
+        installer.set_keep_versions(0);
+        let report = installer.gc_with(&crate::GcOptions::default()).unwrap();
+        assert_eq!(report.old_kegs.items, 1);
+        assert!(installer.retained_versions("tool").unwrap().is_empty());
+        assert!(!installer.keg_path("tool", "2.0.0").exists());
+        assert_eq!(report.removed_store_keys.len(), 2);
+    }
+}
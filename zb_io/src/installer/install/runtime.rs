@@ -0,0 +1,351 @@
+//! `zb doctor --check-runtime`: run each installed formula's main
+//! executable once to catch what static checks miss, such as a library the
+//! dynamic loader can't find or an interpreter that isn't there.
+
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zb_core::{Error, formula_token};
+
+use super::Installer;
+
+/// How long one executable may run before it is killed.
+pub const DEFAULT_RUNTIME_TIMEOUT: Duration = Duration::from_secs(10);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Most stderr kept from a failed run.
+const STDERR_LIMIT: usize = 4096;
+
+/// Executables that serve, or take over the terminal, instead of printing
+/// a version and exiting.
+const SKIPPED_EXECUTABLES: &[&str] = &[
+    "cmatrix",
+    "mongod",
+    "mysqld",
+    "redis-server",
+    "sl",
+    "telnet",
+];
+
+/// Executables that print their version with something other than
+/// `--version`.
+const VERSION_ARGS: &[(&str, &str)] = &[("go", "version"), ("gofmt", "-h"), ("java", "-version")];
+
+/// What the loaders print when they can't start a program.
+const LOADER_ERRORS: &[&str] = &[
+    "error while loading shared libraries",
+    "dyld: Library not loaded",
+    "dyld[",
+    "bad interpreter",
+    "cannot execute",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeOutcome {
+    Passed,
+    /// The executable couldn't be started, was killed by a signal, or the
+    /// loader reported an error. `stderr` is what it printed, cut short.
+    Failed {
+        reason: String,
+        stderr: String,
+    },
+    /// Still running at the deadline; it was killed.
+    TimedOut,
+    Skipped {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeCheck {
+    pub name: String,
+    pub version: String,
+    /// The executable that was run, if one was found.
+    pub executable: Option<PathBuf>,
+    pub outcome: RuntimeOutcome,
+}
+
+impl RuntimeCheck {
+    pub fn failed(&self) -> bool {
+        matches!(
+            self.outcome,
+            RuntimeOutcome::Failed { .. } | RuntimeOutcome::TimedOut
+        )
+    }
+}
+
+impl Installer {
+    /// Run the main executable of every installed formula, or just of
+    /// `formula`, with `--version`, killing any still running after
+    /// `timeout`. Sorted by name.
+    pub fn check_runtime(
+        &self,
+        formula: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Vec<RuntimeCheck>, Error> {
+        let installed = self.db.list_installed()?;
+        if let Some(formula) = formula
+            && !installed.iter().any(|keg| keg.name == formula)
+        {
+            return Err(Error::NotInstalled {
+                name: formula.to_string(),
+            });
+        }
+        Ok(installed
+            .iter()
+            .filter(|keg| formula.is_none_or(|name| keg.name == name))
+            .map(|keg| {
+                let keg_path = self.cellar.keg_path(formula_token(&keg.name), &keg.version);
+                check_keg(&keg.name, &keg.version, &keg_path, timeout)
+            })
+            .collect())
+    }
+}
+
+pub(crate) fn check_keg(name: &str, version: &str, keg: &Path, timeout: Duration) -> RuntimeCheck {
+    let check = |executable, outcome| RuntimeCheck {
+        name: name.to_string(),
+        version: version.to_string(),
+        executable,
+        outcome,
+    };
+    let Some(executable) = main_executable(formula_token(name), keg) else {
+        return check(
+            None,
+            RuntimeOutcome::Skipped {
+                reason: "no executables in bin".to_string(),
+            },
+        );
+    };
+    let file_name = executable
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    if SKIPPED_EXECUTABLES.contains(&file_name) {
+        return check(
+            Some(executable),
+            RuntimeOutcome::Skipped {
+                reason: "interactive or a server".to_string(),
+            },
+        );
+    }
+    let arg = VERSION_ARGS
+        .iter()
+        .find(|(exe, _)| *exe == file_name)
+        .map_or("--version", |(_, arg)| arg);
+    let outcome = run(&executable, arg, timeout);
+    check(Some(executable), outcome)
+}
+
+/// `bin/<name>` when the keg has it, otherwise its first executable in
+/// `bin` by name.
+fn main_executable(token: &str, keg: &Path) -> Option<PathBuf> {
+    let bin = keg.join("bin");
+    let named = bin.join(token);
+    if is_executable(&named) {
+        return Some(named);
+    }
+    let mut executables: Vec<PathBuf> = fs::read_dir(&bin)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| is_executable(path))
+        .collect();
+    executables.sort();
+    executables.into_iter().next()
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Run `executable arg` with no stdin. It gets a process group of its own so
+/// whatever it started is killed with it at the deadline.
+fn run(executable: &Path, arg: &str, timeout: Duration) -> RuntimeOutcome {
+    let spawned = Command::new(executable)
+        .arg(arg)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            return RuntimeOutcome::Failed {
+                reason: format!("failed to start: {e}"),
+                stderr: String::new(),
+            };
+        }
+    };
+    let group = child.id() as libc::pid_t;
+    let kill_group = || {
+        // SAFETY: kill(2) with a negative pid signals every process in the
+        // group; it has no memory-safety preconditions.
+        unsafe {
+            libc::kill(-group, libc::SIGKILL);
+        }
+    };
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let (sender, captured) = mpsc::channel();
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = (&mut stderr)
+            .take(STDERR_LIMIT as u64)
+            .read_to_end(&mut bytes);
+        // Keep draining so a chatty child never blocks on a full pipe.
+        let _ = std::io::copy(&mut stderr, &mut std::io::sink());
+        let _ = sender.send(String::from_utf8_lossy(&bytes).trim_end().to_string());
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            _ => {
+                kill_group();
+                let _ = child.wait();
+                return RuntimeOutcome::TimedOut;
+            }
+        }
+    };
+    // Whatever it left running in its group still holds stderr open, which
+    // also keeps the group's id from being reused.
+    let stderr = match captured.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(stderr) => stderr,
+        Err(_) => {
+            kill_group();
+            captured
+                .recv_timeout(Duration::from_secs(1))
+                .unwrap_or_default()
+        }
+    };
+
+    if let Some(signal) = status.signal() {
+        return RuntimeOutcome::Failed {
+            reason: format!("killed by signal {signal}"),
+            stderr,
+        };
+    }
+    if LOADER_ERRORS.iter().any(|marker| stderr.contains(marker)) {
+        return RuntimeOutcome::Failed {
+            reason: format!("the loader failed ({status})"),
+            stderr,
+        };
+    }
+    // Shells use these when the program or its interpreter is missing or
+    // can't be run. Any other status means it loaded, even if it doesn't
+    // know the flag.
+    if matches!(status.code(), Some(126 | 127)) {
+        return RuntimeOutcome::Failed {
+            reason: format!("could not be run ({status})"),
+            stderr,
+        };
+    }
+    RuntimeOutcome::Passed
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn keg(tmp: &TempDir, name: &str, script: &str) -> PathBuf {
+        let keg = tmp.path().join(name).join("1.0");
+        fs::create_dir_all(keg.join("bin")).unwrap();
+        let exe = keg.join("bin").join(name);
+        fs::write(&exe, script).unwrap();
+        fs::set_permissions(&exe, fs::Permissions::from_mode(0o755)).unwrap();
+        keg
+    }
+
+    fn outcome(tmp: &TempDir, name: &str, script: &str) -> RuntimeOutcome {
+        let keg = keg(tmp, name, script);
+        check_keg(name, "1.0", &keg, Duration::from_secs(5)).outcome
+    }
+
+    #[test]
+    fn a_binary_that_runs_passes_whatever_its_status() {
+        let tmp = TempDir::new().unwrap();
+        assert_eq!(
+            outcome(&tmp, "works", "#!/bin/sh\necho works 1.0\n"),
+            RuntimeOutcome::Passed
+        );
+        assert_eq!(
+            outcome(
+                &tmp,
+                "noflag",
+                "#!/bin/sh\necho 'unknown option' >&2\nexit 2\n"
+            ),
+            RuntimeOutcome::Passed
+        );
+    }
+
+    #[test]
+    fn loader_errors_and_missing_interpreters_fail_with_stderr() {
+        let tmp = TempDir::new().unwrap();
+        let RuntimeOutcome::Failed { stderr, .. } = outcome(
+            &tmp,
+            "nolib",
+            "#!/bin/sh\necho 'nolib: error while loading shared libraries: libgone.so.1: \
+             cannot open shared object file' >&2\nexit 127\n",
+        ) else {
+            panic!("expected a failure");
+        };
+        assert!(stderr.contains("libgone.so.1"), "{stderr}");
+
+        let RuntimeOutcome::Failed { reason, .. } =
+            outcome(&tmp, "nointerp", "#!/nonexistent/interpreter\n")
+        else {
+            panic!("expected a failure");
+        };
+        assert!(reason.starts_with("failed to start"), "{reason}");
+    }
+
+    #[test]
+    fn hangers_are_killed_with_what_they_started() {
+        let tmp = TempDir::new().unwrap();
+        // The background sleep keeps stderr open after the script is gone.
+        let keg = keg(&tmp, "hangs", "#!/bin/sh\nsleep 30 &\nsleep 30\n");
+
+        let started = Instant::now();
+        let check = check_keg("hangs", "1.0", &keg, Duration::from_millis(200));
+        assert_eq!(check.outcome, RuntimeOutcome::TimedOut);
+        assert!(check.failed());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn nothing_reads_stdin() {
+        let tmp = TempDir::new().unwrap();
+        assert_eq!(
+            outcome(&tmp, "reads", "#!/bin/sh\nread line\nexit 0\n"),
+            RuntimeOutcome::Passed
+        );
+    }
+
+    #[test]
+    fn servers_and_kegs_without_executables_are_skipped() {
+        let tmp = TempDir::new().unwrap();
+        assert!(matches!(
+            outcome(&tmp, "redis-server", "#!/bin/sh\nsleep 30\n"),
+            RuntimeOutcome::Skipped { .. }
+        ));
+
+        let empty = tmp.path().join("libonly/1.0");
+        fs::create_dir_all(empty.join("lib")).unwrap();
+        let check = check_keg("libonly", "1.0", &empty, Duration::from_secs(5));
+        assert_eq!(check.executable, None);
+        assert!(matches!(check.outcome, RuntimeOutcome::Skipped { .. }));
+    }
+}
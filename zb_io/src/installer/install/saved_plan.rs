@@ -0,0 +1,397 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use zb_core::{BuildPlan, Error, Formula, InstallMethod, SelectedBottle};
+
+use super::plan::pinned_refusal;
+use super::{InstallPlan, Installer, PlannedInstall};
+
+/// Bumped whenever the on-disk plan layout changes incompatibly.
+pub const SAVED_PLAN_FORMAT_VERSION: u32 = 1;
+
+/// A resolved install plan that can be written to disk, reviewed, and
+/// executed later with `zb apply` without re-resolving against the API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPlan {
+    pub format_version: u32,
+    /// Items in dependency order: each item only depends on earlier ones.
+    pub items: Vec<SavedPlanItem>,
+    /// Install names in the order they are installed and linked, as given
+    /// by [`InstallPlan::link_order`]. Applying a plan recomputes it from
+    /// the items' dependencies.
+    #[serde(default)]
+    pub link_order: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPlanItem {
+    pub install_name: String,
+    pub name: String,
+    pub version: String,
+    /// Whether the formula was requested directly rather than as a dependency.
+    #[serde(default)]
+    pub explicit: bool,
+    pub dependencies: Vec<String>,
+    pub method: SavedInstallMethod,
+    /// Size of the bottle when it is already in the local blob cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_size: Option<u64>,
+    pub formula: Formula,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SavedInstallMethod {
+    Bottle {
+        tag: String,
+        url: String,
+        sha256: String,
+    },
+    Source {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checksum: Option<String>,
+    },
+}
+
+impl SavedPlan {
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(|e| Error::InvalidArgument {
+            message: format!("failed to serialize install plan: {e}"),
+        })
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let plan: Self = serde_json::from_str(json).map_err(|e| Error::InvalidArgument {
+            message: format!("failed to parse saved install plan: {e}"),
+        })?;
+
+        if plan.format_version != SAVED_PLAN_FORMAT_VERSION {
+            return Err(Error::InvalidArgument {
+                message: format!(
+                    "unsupported saved plan format version {} (expected {})",
+                    plan.format_version, SAVED_PLAN_FORMAT_VERSION
+                ),
+            });
+        }
+
+        Ok(plan)
+    }
+}
+
+impl SavedPlanItem {
+    /// Rebuild the in-memory install method, checking that the recorded
+    /// bottle still matches the formula metadata stored alongside it.
+    fn to_install_method(&self, prefix: &std::path::Path) -> Result<InstallMethod, Error> {
+        match &self.method {
+            SavedInstallMethod::Bottle { tag, url, sha256 } => {
+                let recorded = self.formula.bottle.stable.files.get(tag);
+                if recorded.is_none_or(|file| &file.sha256 != sha256 || &file.url != url) {
+                    return Err(Error::ChecksumMismatch {
+                        expected: sha256.clone(),
+                        actual: recorded
+                            .map(|file| file.sha256.clone())
+                            .unwrap_or_else(|| "<missing bottle>".to_string()),
+                    });
+                }
+
+                Ok(InstallMethod::Bottle(SelectedBottle {
+                    tag: tag.clone(),
+                    url: url.clone(),
+                    sha256: sha256.clone(),
+                }))
+            }
+            SavedInstallMethod::Source { .. } => BuildPlan::from_formula(&self.formula, prefix)
+                .map(InstallMethod::Source)
+                .ok_or_else(|| Error::UnsupportedFormula {
+                    name: self.name.clone(),
+                    reason: "saved plan requests a source build but the formula has no source URL"
+                        .to_string(),
+                }),
+        }
+    }
+}
+
+impl Installer {
+    /// Snapshot a resolved plan so it can be saved and applied later.
+    pub fn save_plan(&self, plan: &InstallPlan) -> SavedPlan {
+        let items = plan
+            .items
+            .iter()
+            .map(|item| {
+                let (method, estimated_size) = match &item.method {
+                    InstallMethod::Bottle(bottle) => (
+                        SavedInstallMethod::Bottle {
+                            tag: bottle.tag.clone(),
+                            url: bottle.url.clone(),
+                            sha256: bottle.sha256.clone(),
+                        },
+                        self.downloader.cached_blob_size(&bottle.sha256),
+                    ),
+                    InstallMethod::Source(build) => (
+                        SavedInstallMethod::Source {
+                            url: build.source_url.clone(),
+                            checksum: build.source_checksum.clone(),
+                        },
+                        None,
+                    ),
+                };
+
+                SavedPlanItem {
+                    install_name: item.install_name.clone(),
+                    name: item.formula.name.clone(),
+                    version: item.formula.effective_version(),
+                    explicit: item.explicit,
+                    dependencies: item.formula.dependencies.clone(),
+                    method,
+                    estimated_size,
+                    formula: item.formula.clone(),
+                }
+            })
+            .collect();
+
+        SavedPlan {
+            format_version: SAVED_PLAN_FORMAT_VERSION,
+            items,
+            link_order: plan.link_order().unwrap_or_else(|_| {
+                plan.items
+                    .iter()
+                    .map(|item| item.install_name.clone())
+                    .collect()
+            }),
+        }
+    }
+
+    /// Turn a saved plan back into an executable one.
+    ///
+    /// Items already installed at the planned version are skipped. A formula
+    /// installed at a different version is an error unless `reconcile` is
+    /// set, in which case executing the plan replaces it like an upgrade:
+    /// the old keg stays installed until the new one is in place, and is
+    /// put back if the new one can't be linked. A pinned formula at a
+    /// different version is always an error.
+    pub fn prepare_saved_plan(
+        &mut self,
+        saved: SavedPlan,
+        reconcile: bool,
+    ) -> Result<InstallPlan, Error> {
+        let mut items = Vec::with_capacity(saved.items.len());
+
+        for item in saved.items {
+            let planned = PlannedInstall {
+                method: item.to_install_method(&self.prefix)?,
+                install_name: item.install_name,
+                formula: item.formula,
+                explicit: item.explicit,
+            };
+
+            if let Some(installed) = self.get_installed(&planned.install_name)? {
+                let build = planned.build();
+                if installed.build().compare(&build) == Ordering::Equal {
+                    continue;
+                }
+                if let Some(err) = pinned_refusal(&installed, &planned.formula) {
+                    return Err(err);
+                }
+                if !reconcile {
+                    return Err(Error::InvalidArgument {
+                        message: format!(
+                            "'{}' is installed at {} but the plan expects {} (pass --reconcile to replace it)",
+                            planned.install_name,
+                            installed.build(),
+                            build
+                        ),
+                    });
+                }
+            }
+
+            items.push(planned);
+        }
+
+        Ok(InstallPlan {
+            items,
+            ..InstallPlan::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+    use wiremock::MockServer;
+
+    use super::*;
+    use crate::installer::install::test_support::*;
+
+    async fn mount_release(mock_server: &MockServer, name: &str, version: &str, deps: &[&str]) {
+        let script = format!("#!/bin/sh\necho {name}");
+        let bin = format!("bin/{name}");
+        let bottle = versioned_bottle(name, version, &[(&bin, script.as_bytes())]);
+        mount_formula(mock_server, name, version, deps, Some(bottle)).await;
+    }
+
+    #[tokio::test]
+    async fn saved_plan_round_trips_through_json() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_release(&mock_server, "libdep", "1.0.0", &[]).await;
+        mount_release(&mock_server, "app", "2.0.0", &["libdep"]).await;
+
+        let installer = test_installer(&mock_server, &tmp);
+        let plan = installer.plan(&["app".to_string()]).await.unwrap();
+        let saved = installer.save_plan(&plan);
+
+        let decoded = SavedPlan::from_json(&saved.to_json().unwrap()).unwrap();
+
+        assert_eq!(decoded, saved);
+        let names: Vec<_> = decoded.items.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["libdep", "app"]);
+        assert_eq!(decoded.link_order, ["libdep", "app"]);
+        assert!(matches!(
+            decoded.items[1].method,
+            SavedInstallMethod::Bottle { .. }
+        ));
+    }
+
+    #[test]
+    fn from_json_rejects_unknown_format_version() {
+        let err = SavedPlan::from_json(r#"{"format_version": 99, "items": []}"#).unwrap_err();
+        assert!(err.to_string().contains("format version 99"));
+    }
+
+    #[tokio::test]
+    async fn apply_executes_saved_plan() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_release(&mock_server, "libdep", "1.0.0", &[]).await;
+        mount_release(&mock_server, "app", "2.0.0", &["libdep"]).await;
+
+        let mut installer = test_installer(&mock_server, &tmp);
+        let plan = installer.plan(&["app".to_string()]).await.unwrap();
+        let json = installer.save_plan(&plan).to_json().unwrap();
+
+        let saved = SavedPlan::from_json(&json).unwrap();
+        let plan = installer.prepare_saved_plan(saved, false).unwrap();
+        let result = installer.execute(plan, true).await.unwrap();
+
+        assert_eq!(result.installed, 2);
+        assert!(installer.is_installed("app"));
+        assert!(installer.is_installed("libdep"));
+    }
+
+    #[tokio::test]
+    async fn apply_rejects_tampered_bottle_sha() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_release(&mock_server, "app", "2.0.0", &[]).await;
+
+        let mut installer = test_installer(&mock_server, &tmp);
+        let plan = installer.plan(&["app".to_string()]).await.unwrap();
+        let mut saved = installer.save_plan(&plan);
+        if let SavedInstallMethod::Bottle { sha256, .. } = &mut saved.items[0].method {
+            *sha256 = "0".repeat(64);
+        }
+
+        let err = installer.prepare_saved_plan(saved, false).unwrap_err();
+        assert!(matches!(err, Error::ChecksumMismatch { .. }));
+    }
+
+    #[tokio::test]
+    async fn apply_requires_reconcile_for_version_drift() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_release(&mock_server, "app", "2.0.0", &[]).await;
+
+        let mut installer = test_installer(&mock_server, &tmp);
+        let plan = installer.plan(&["app".to_string()]).await.unwrap();
+        let saved = installer.save_plan(&plan);
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("app", "1.0.0", "oldsha").unwrap();
+            tx.commit().unwrap();
+        }
+
+        let err = installer
+            .prepare_saved_plan(saved.clone(), false)
+            .unwrap_err();
+        assert!(err.to_string().contains("--reconcile"));
+
+        let plan = installer.prepare_saved_plan(saved, true).unwrap();
+        assert_eq!(plan.items.len(), 1);
+        assert!(installer.is_installed("app"));
+
+        installer.execute(plan, true).await.unwrap();
+        assert_eq!(
+            installer.get_installed("app").unwrap().unwrap().version,
+            "2.0.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn reconcile_never_replaces_a_pinned_version() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_release(&mock_server, "app", "2.0.0", &[]).await;
+
+        let mut installer = test_installer(&mock_server, &tmp);
+        let plan = installer.plan(&["app".to_string()]).await.unwrap();
+        let saved = installer.save_plan(&plan);
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("app", "1.0.0", "oldsha").unwrap();
+            tx.commit().unwrap();
+        }
+        installer.set_pinned("app", true).unwrap();
+
+        let err = installer.prepare_saved_plan(saved, true).unwrap_err();
+        assert!(err.to_string().contains("zb unpin app"), "{err}");
+        assert_eq!(
+            installer.get_installed("app").unwrap().unwrap().version,
+            "1.0.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn reconcile_keeps_the_installed_version_when_the_new_one_fails() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_release(&mock_server, "app", "2.0.0", &[]).await;
+
+        let mut installer = test_installer(&mock_server, &tmp);
+        let plan = installer.plan(&["app".to_string()]).await.unwrap();
+        let saved = installer.save_plan(&plan);
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("app", "1.0.0", "oldsha").unwrap();
+            tx.commit().unwrap();
+        }
+        fs::create_dir_all(installer.keg_path("app", "1.0.0")).unwrap();
+        mock_server.reset().await;
+
+        let plan = installer.prepare_saved_plan(saved, true).unwrap();
+        assert!(installer.execute(plan, true).await.is_err());
+
+        assert_eq!(
+            installer.get_installed("app").unwrap().unwrap().version,
+            "1.0.0"
+        );
+        assert!(installer.keg_path("app", "1.0.0").is_dir());
+    }
+
+    #[tokio::test]
+    async fn apply_skips_items_already_installed_at_planned_version() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        mount_release(&mock_server, "app", "2.0.0", &[]).await;
+
+        let mut installer = test_installer(&mock_server, &tmp);
+        let plan = installer.plan(&["app".to_string()]).await.unwrap();
+        let saved = installer.save_plan(&plan);
+        installer.execute(plan, true).await.unwrap();
+
+        let plan = installer.prepare_saved_plan(saved, false).unwrap();
+        assert!(plan.items.is_empty());
+    }
+}
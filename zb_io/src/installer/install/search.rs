@@ -0,0 +1,170 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tracing::warn;
+use zb_core::Error;
+
+use super::Installer;
+use crate::network::search::{MatchKind, SearchIndex};
+
+/// How long the search index is used before it is fetched again. An older
+/// one is still searched when the formula API can't be reached.
+pub const SEARCH_INDEX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A formula matching a search, with whether it is installed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SearchResult {
+    pub name: String,
+    pub desc: Option<String>,
+    pub matched: MatchKind,
+    pub installed: bool,
+}
+
+impl Installer {
+    /// Formulas whose name, alias or description matches `query`, best
+    /// first; see [`SearchIndex::search`]. The index is cached under the
+    /// root for [`SEARCH_INDEX_TTL`].
+    pub async fn search(&self, query: &str) -> Result<Vec<SearchResult>, Error> {
+        let index = self.search_index(SEARCH_INDEX_TTL).await?;
+        let installed = self.installed_set()?;
+        Ok(index
+            .search(query)
+            .into_iter()
+            .map(|(entry, matched)| SearchResult {
+                installed: installed.contains(&entry.name),
+                name: entry.name.clone(),
+                desc: entry.desc.clone(),
+                matched,
+            })
+            .collect())
+    }
+
+    fn search_index_path(&self) -> PathBuf {
+        self.root.join("search-index.json")
+    }
+
+    /// The cached index when it is younger than `max_age`, else a fresh one
+    /// from the bulk formula index, falling back to the cached one however
+    /// old when that can't be fetched.
+    async fn search_index(&self, max_age: Duration) -> Result<SearchIndex, Error> {
+        let path = self.search_index_path();
+        let cached = read_search_index(&path);
+        if let Some((index, written)) = &cached
+            && SystemTime::now()
+                .duration_since(*written)
+                .is_ok_and(|age| age < max_age)
+        {
+            return Ok(index.clone());
+        }
+
+        let fetched = match self.api_client.get_all_formulas_raw().await {
+            Ok(raw) => SearchIndex::from_bulk(&raw),
+            Err(e) => Err(e),
+        };
+        match (fetched, cached) {
+            (Ok(index), _) => {
+                if let Err(e) = write_search_index(&path, &index) {
+                    warn!(path = %path.display(), error = %e, "failed to cache search index");
+                }
+                Ok(index)
+            }
+            (Err(e), Some((index, _))) => {
+                warn!(error = %e, "formula index unavailable; searching the cached one");
+                Ok(index)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+}
+
+fn read_search_index(path: &std::path::Path) -> Option<(SearchIndex, SystemTime)> {
+    let written = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let index = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+    Some((index, written))
+}
+
+fn write_search_index(path: &std::path::Path, index: &SearchIndex) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(index)?)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::installer::install::test_support::*;
+
+    const BULK: &str = r#"[
+        {"name": "jq", "desc": "Lightweight and flexible command-line JSON processor"},
+        {"name": "openssl@3", "aliases": ["openssl"], "desc": "Cryptography and SSL/TLS Toolkit"}
+    ]"#;
+
+    #[tokio::test]
+    async fn search_marks_installed_formulas_and_caches_the_index() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = test_installer(&server, &tmp);
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("jq", "1.7.1", "sha").unwrap();
+            tx.commit().unwrap();
+        }
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(BULK))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let results = installer.search("JSON").await.unwrap();
+        assert_eq!(
+            results,
+            [SearchResult {
+                name: "jq".to_string(),
+                desc: Some("Lightweight and flexible command-line JSON processor".to_string()),
+                matched: MatchKind::Description,
+                installed: true,
+            }]
+        );
+        assert!(tmp.path().join("zerobrew/search-index.json").is_file());
+
+        let results = installer.search("openssl").await.unwrap();
+        assert_eq!(results[0].name, "openssl@3");
+        assert!(!results[0].installed);
+    }
+
+    #[tokio::test]
+    async fn a_stale_index_is_searched_when_the_api_is_down() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let installer = test_installer(&server, &tmp);
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        assert!(installer.search("jq").await.is_err());
+
+        let cached = tmp.path().join("zerobrew/search-index.json");
+        write_search_index(&cached, &SearchIndex::from_bulk(BULK).unwrap()).unwrap();
+        File::options()
+            .write(true)
+            .open(&cached)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * SEARCH_INDEX_TTL)
+            .unwrap();
+
+        let results = installer.search("jq").await.unwrap();
+        assert_eq!(results[0].name, "jq");
+    }
+}
@@ -42,7 +42,7 @@ impl Installer {
 
         let mut installed_deps = std::collections::HashMap::new();
         for dep_name in &build_plan.runtime_dependencies {
-            if let Some(keg) = self.db.get_installed(dep_name) {
+            if let Some(keg) = self.get_installed(dep_name)? {
                 installed_deps.insert(
                     dep_name.clone(),
                     crate::build::DepInfo {
@@ -82,11 +82,23 @@ impl Installer {
 
         let store_key = format!("source:{formula_name}:{version}");
 
+        let previous = self.db.get_installed(install_name).inspect_err(|_| {
+            Self::cleanup_materialized(&self.cellar, formula_name, &version);
+        })?;
+        let previously_explicit = previous.as_ref().is_some_and(|keg| keg.explicit);
+        let previously_linked = previous.as_ref().is_some_and(|keg| {
+            keg.version != version && self.has_links(install_name, &keg.version)
+        });
         let tx = self.db.transaction().inspect_err(|_| {
             Self::cleanup_materialized(&self.cellar, formula_name, &version);
         })?;
 
-        if let Err(e) = tx.record_install(install_name, &version, &store_key) {
+        if let Err(e) = tx
+            .record_install(install_name, &version, &store_key)
+            .and_then(|()| {
+                Self::record_install_details(&tx, item, previously_explicit, &self.prefix)
+            })
+        {
             drop(tx);
             Self::cleanup_materialized(&self.cellar, formula_name, &version);
             return Err(e);
@@ -97,6 +109,8 @@ impl Installer {
             return Err(e);
         }
 
+        self.keep_metadata(install_name, &version, &item.formula);
+        self.unlink_replaced(previous.as_ref(), &version);
         if let Err(e) = self.linker.link_opt(&keg_path) {
             warn!(formula = %install_name, error = %e, "failed to create opt link");
         }
@@ -117,15 +131,7 @@ impl Installer {
                     {
                         let mut ok = true;
                         for linked in &files {
-                            if tx
-                                .record_linked_file(
-                                    install_name,
-                                    &version,
-                                    &linked.link_path.to_string_lossy(),
-                                    &linked.target_path.to_string_lossy(),
-                                )
-                                .is_err()
-                            {
+                            if tx.record_linked(install_name, &version, linked).is_err() {
                                 ok = false;
                                 break;
                             }
@@ -137,6 +143,17 @@ impl Installer {
                 }
                 Err(e) => {
                     let _ = self.linker.unlink_keg(&keg_path);
+                    if let Some(version) = self.restore_replaced(
+                        install_name,
+                        previous.as_ref(),
+                        previously_linked,
+                        &version,
+                    ) {
+                        report(InstallProgress::LinkRestored {
+                            name: formula_name.clone(),
+                            version,
+                        });
+                    }
                     report(InstallProgress::InstallCompleted {
                         name: formula_name.clone(),
                     });
@@ -155,6 +172,7 @@ impl Installer {
             });
         }
 
+        self.prune_retained_after_install(install_name);
         report(InstallProgress::InstallCompleted {
             name: formula_name.clone(),
         });
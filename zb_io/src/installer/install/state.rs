@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+
+use zb_core::{CELLAR_DIR, Error, Formula};
+
+use super::prefix_conflict_error;
+use super::why::{ListFilter, filter_installed};
+use crate::storage::db::{
+    Database, DriftedKeg, HistoryEvent, InstalledKeg, KegFileRecord, SystemRecord, UpstreamStatus,
+};
+use crate::storage::metadata::KegMetadata;
+
+/// What is installed under a root, read without building an [`Installer`]
+/// or creating anything on disk. A root that was never initialized reads as
+/// having nothing installed.
+///
+/// [`Installer`]: super::Installer
+pub struct InstalledState {
+    db: Option<Database>,
+    metadata: KegMetadata,
+    prefix: PathBuf,
+    cellar_dir: PathBuf,
+}
+
+impl InstalledState {
+    pub fn open(root: &Path, prefix: &Path) -> Result<Self, Error> {
+        let db_path = root.join("db/zb.sqlite3");
+        let db = if db_path.exists() {
+            Some(Database::open_read_only(&db_path)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            db,
+            metadata: KegMetadata::new(root),
+            prefix: prefix.to_path_buf(),
+            cellar_dir: prefix.join(CELLAR_DIR),
+        })
+    }
+
+    pub fn list_installed(&self) -> Result<Vec<InstalledKeg>, Error> {
+        match &self.db {
+            Some(db) => db.list_installed(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The installed formulas `filter` selects, sorted by name.
+    pub fn list_filtered(&self, filter: &ListFilter) -> Result<Vec<InstalledKeg>, Error> {
+        match &self.db {
+            Some(db) => {
+                filter_installed(db.list_installed()?, &db.list_dependency_edges()?, filter)
+            }
+            None => filter_installed(Vec::new(), &[], filter),
+        }
+    }
+
+    pub fn get_installed(&self, name: &str) -> Result<Option<InstalledKeg>, Error> {
+        match &self.db {
+            Some(db) => db.get_installed(name),
+            None => Ok(None),
+        }
+    }
+
+    /// The metadata `version` of `name` was installed from, if it was kept.
+    pub fn installed_formula(&self, name: &str, version: &str) -> Result<Option<Formula>, Error> {
+        self.metadata.read(name, version)
+    }
+
+    /// The prefix paths recorded for `version` of `name` when it was linked.
+    pub fn keg_files(&self, name: &str, version: &str) -> Result<Vec<KegFileRecord>, Error> {
+        match &self.db {
+            Some(db) => db.list_keg_files_for(name, version),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn keg_path(&self, name: &str, version: &str) -> PathBuf {
+        self.cellar_dir.join(name).join(version)
+    }
+
+    /// Installed kegs whose files were patched for a prefix other than this one.
+    pub fn prefix_drift(&self) -> Result<Vec<DriftedKeg>, Error> {
+        match &self.db {
+            Some(db) => db.kegs_patched_for_other_prefix(&self.prefix),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Installed formulas last seen deprecated or disabled upstream.
+    pub fn upstream_statuses(&self) -> Result<Vec<UpstreamStatus>, Error> {
+        match &self.db {
+            Some(db) => db.list_upstream_statuses(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Recorded gc and cache-clean runs, oldest first.
+    pub fn history(&self) -> Result<Vec<HistoryEvent>, Error> {
+        match &self.db {
+            Some(db) => db.list_events(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The prefix this root's formulas were installed under, when it isn't
+    /// this one. That is refused the same way `create_installer` refuses it
+    /// unless `accept_prefix_change` is set.
+    pub fn prefix_conflict(&self, accept_prefix_change: bool) -> Result<Option<PathBuf>, Error> {
+        let Some(db) = &self.db else {
+            return Ok(None);
+        };
+        match db.prefix_conflict(&self.prefix)? {
+            Some(recorded) if !accept_prefix_change => {
+                Err(prefix_conflict_error(&recorded, &self.prefix))
+            }
+            conflict => Ok(conflict),
+        }
+    }
+
+    pub fn system_record(&self) -> Result<Option<SystemRecord>, Error> {
+        match &self.db {
+            Some(db) => db.system_record(),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn an_uninitialized_root_reads_as_empty_and_stays_absent() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        let prefix = tmp.path().join("prefix");
+
+        let state = InstalledState::open(&root, &prefix).unwrap();
+
+        assert!(state.list_installed().unwrap().is_empty());
+        assert!(state.get_installed("jq").unwrap().is_none());
+        assert!(!root.exists());
+        assert!(!prefix.exists());
+    }
+
+    #[test]
+    fn recorded_installs_are_read_from_an_existing_database() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir_all(root.join("db")).unwrap();
+        {
+            let mut db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
+            let tx = db.transaction().unwrap();
+            tx.record_install("jq", "1.7.1", "key").unwrap();
+            tx.commit().unwrap();
+        }
+
+        let state = InstalledState::open(&root, tmp.path()).unwrap();
+
+        let installed = state.list_installed().unwrap();
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].version, "1.7.1");
+        assert_eq!(
+            state.keg_path("jq", "1.7.1"),
+            tmp.path().join(CELLAR_DIR).join("jq/1.7.1")
+        );
+    }
+}
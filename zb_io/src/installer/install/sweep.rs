@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use tracing::{info, warn};
+use zb_core::{Error, formula_token};
+
+use super::Installer;
+use super::gc::remove_path;
+use crate::extraction::patch::is_patch_temp;
+
+/// How old a leftover has to be before the startup sweep removes it. Younger
+/// ones may still belong to a zb that is running.
+pub const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+impl Installer {
+    /// Remove temporary files a crashed (or older) zb left behind and that
+    /// haven't been touched for `max_age`: partial downloads in the cache,
+    /// half-built store entries, and `.zb-patch.*` files in kegs that were
+    /// never recorded as installed. Nothing is removed while another zb
+    /// holds the install lock, and leftovers that can't be removed are
+    /// logged and skipped. Returns the removed paths, sorted.
+    pub fn sweep_stale_temp(&self, max_age: Duration) -> Result<Vec<PathBuf>, Error> {
+        let Some(_lock) = self.try_lock_install()? else {
+            return Ok(Vec::new());
+        };
+
+        let mut candidates = self
+            .downloader
+            .blob_cache()
+            .temp_files()
+            .map_err(Error::store("failed to read download temp directory"))?;
+        candidates.extend(self.store.temp_entries()?);
+
+        let recorded: HashSet<(String, String)> = self
+            .db
+            .list_installed()?
+            .into_iter()
+            .map(|keg| (keg.name, keg.version))
+            .chain(
+                self.db
+                    .list_retained(None)?
+                    .into_iter()
+                    .map(|keg| (keg.name, keg.version)),
+            )
+            .map(|(name, version)| (formula_token(&name).to_string(), version))
+            .collect();
+        for keg in self.cellar.list_kegs()? {
+            if recorded.contains(&(keg.name, keg.version)) {
+                continue;
+            }
+            candidates.extend(
+                walkdir::WalkDir::new(&keg.path)
+                    .follow_links(false)
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .filter(|entry| entry.file_type().is_file() && is_patch_temp(entry.path()))
+                    .map(|entry| entry.into_path()),
+            );
+        }
+
+        let now = SystemTime::now();
+        let mut removed = Vec::new();
+        for path in candidates {
+            let stale = path
+                .symlink_metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| now.duration_since(modified).is_ok_and(|age| age >= max_age));
+            if !stale {
+                continue;
+            }
+            // One leftover that can't be removed shouldn't keep the rest.
+            if let Err(e) = remove_path(&path) {
+                warn!(path = %path.display(), error = %e, "failed to remove stale temporary file");
+                continue;
+            }
+            info!(path = %path.display(), "removed stale temporary file");
+            removed.push(path);
+        }
+
+        removed.sort();
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use fs4::fs_std::FileExt;
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    use crate::installer::install::test_support::*;
+    use crate::network::api::ApiClient;
+
+    fn age(path: &std::path::Path, by: Duration) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - by).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sweep_removes_only_stale_leftovers() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+
+        let bottle = create_bottle_tarball("kept");
+        let tag = get_test_bottle_tag();
+        let formula_json = serde_json::json!({
+            "name": "kept",
+            "versions": { "stable": "1.0.0" },
+            "dependencies": [],
+            "bottle": { "stable": { "files": { tag: {
+                "url": format!("{}/bottles/kept.tar.gz", server.uri()),
+                "sha256": sha256_hex(&bottle),
+            }}}}
+        });
+        Mock::given(method("GET"))
+            .and(path("/formula/kept.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(formula_json))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bottles/kept.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+            .mount(&server)
+            .await;
+
+        let mut installer = installer_at(api_client(&server), &root, &prefix, root.join("Cellar"));
+        installer
+            .install(&["kept".to_string()], true)
+            .await
+            .unwrap();
+
+        let hour = Duration::from_secs(60 * 60);
+        let seed = |path: PathBuf, stale: bool| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, b"leftover").unwrap();
+            if stale {
+                age(&path, 2 * hour);
+            }
+            path
+        };
+        let stale_download = seed(root.join("cache/tmp/.tmpAbC123"), true);
+        let stale_partial = seed(root.join("cache/blobs/abc.tar.gz.partial"), true);
+        let fresh_partial = seed(root.join("cache/blobs/def.tar.gz.partial"), false);
+        let stale_store = seed(root.join("store/.tmpXyZ789"), true);
+        let incomplete = installer.keg_path("crashed", "2.0.0");
+        let stale_patch = seed(incomplete.join("bin/.zb-patch.4242.0"), true);
+        let installed_patch = seed(
+            installer
+                .keg_path("kept", "1.0.0")
+                .join("bin/.zb-patch.4242.1"),
+            true,
+        );
+        let unrelated = seed(incomplete.join("lib/libcrashed.so"), true);
+        let lookalike = seed(incomplete.join("share/crashed.tmp_patch"), true);
+
+        let removed = installer.sweep_stale_temp(hour).unwrap();
+
+        let mut expected = vec![stale_download, stale_partial, stale_store, stale_patch];
+        expected.sort();
+        assert_eq!(removed, expected);
+        for path in &expected {
+            assert!(!path.exists(), "{} should be removed", path.display());
+        }
+        assert!(fresh_partial.exists());
+        assert!(installed_patch.exists());
+        assert!(unrelated.exists());
+        assert!(lookalike.exists());
+        assert!(
+            installer
+                .keg_path("kept", "1.0.0")
+                .join("bin/kept")
+                .exists()
+        );
+        assert!(installer.is_installed("kept"));
+    }
+
+    #[test]
+    fn sweep_is_skipped_while_another_zb_holds_the_lock() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        fs::create_dir_all(root.join("locks")).unwrap();
+
+        let installer = installer_at(
+            ApiClient::with_base_url("http://127.0.0.1:9".to_string()).unwrap(),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+        let leftover = root.join("cache/tmp/.tmpAbC123");
+        fs::write(&leftover, b"leftover").unwrap();
+        age(&leftover, Duration::from_secs(2 * 60 * 60));
+
+        let held = File::create(root.join("locks/install.lock")).unwrap();
+        held.lock_exclusive().unwrap();
+
+        assert!(
+            installer
+                .sweep_stale_temp(STALE_TEMP_AGE)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(leftover.exists());
+    }
+}
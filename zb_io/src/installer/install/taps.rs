@@ -0,0 +1,398 @@
+//! Third-party taps that unqualified formula names resolve against.
+//!
+//! A name without a tap resolves to homebrew/core when core has it (as a
+//! formula or an alias), and otherwise to the first configured tap, in
+//! priority order, that has it. A fully qualified `owner/repo/name` is used
+//! as is, and `homebrew/core/name` always means the core formula. Formulas
+//! from a tap are installed under their qualified name, so the installed
+//! record keeps the tap and upgrades resolve against it again.
+
+use tracing::warn;
+use zb_core::Error;
+
+use super::Installer;
+
+/// The source unqualified names resolve against first.
+pub const CORE_TAP: &str = "homebrew/core";
+
+/// A requested name more than one source provides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapChoice {
+    /// The name as requested, without a tap.
+    pub name: String,
+    /// The source it resolved to: [`CORE_TAP`] or a configured tap.
+    pub chosen: String,
+    /// The other sources that have it, in priority order.
+    pub shadowed: Vec<String>,
+}
+
+/// Requested names with their source applied.
+#[derive(Debug, Default)]
+pub(crate) struct TapResolution {
+    pub names: Vec<String>,
+    pub choices: Vec<TapChoice>,
+}
+
+/// Check that `tap` is an `owner/repo` tap other than homebrew/core. A
+/// `homebrew-` prefix on the repo is dropped, as brew does.
+pub fn normalize_tap_name(tap: &str) -> Result<String, Error> {
+    let invalid = || Error::InvalidArgument {
+        message: format!("'{tap}' is not a tap; expected OWNER/REPO, e.g. hashicorp/tap"),
+    };
+    let (owner, repo) = tap.trim().split_once('/').ok_or_else(invalid)?;
+    let repo = repo.strip_prefix("homebrew-").unwrap_or(repo);
+    let valid = |part: &str| {
+        !part.is_empty()
+            && !part.starts_with('.')
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    if !valid(owner) || !valid(repo) {
+        return Err(invalid());
+    }
+
+    let tap = format!(
+        "{}/{}",
+        owner.to_ascii_lowercase(),
+        repo.to_ascii_lowercase()
+    );
+    if tap == CORE_TAP {
+        return Err(Error::InvalidArgument {
+            message: format!("{CORE_TAP} is always searched first and can't be configured"),
+        });
+    }
+    Ok(tap)
+}
+
+/// The source to install `name` from, given whether core has it and which
+/// configured taps (in priority order) do. `None` when nothing has it.
+fn choose_source(name: &str, in_core: bool, in_taps: &[String]) -> Option<TapChoice> {
+    let mut sources = in_core
+        .then(|| CORE_TAP.to_string())
+        .into_iter()
+        .chain(in_taps.iter().cloned());
+    let chosen = sources.next()?;
+    Some(TapChoice {
+        name: name.to_string(),
+        chosen,
+        shadowed: sources.collect(),
+    })
+}
+
+impl Installer {
+    /// Configured taps, highest priority first.
+    pub fn taps(&self) -> Result<Vec<String>, Error> {
+        self.db.list_taps()
+    }
+
+    /// Add `tap`, last in priority unless `first` is set. Returns the
+    /// normalized name, or `None` when it was already configured, in which
+    /// case its priority is left alone.
+    pub fn add_tap(&mut self, tap: &str, first: bool) -> Result<Option<String>, Error> {
+        let tap = normalize_tap_name(tap)?;
+        let mut taps = self.db.list_taps()?;
+        if taps.contains(&tap) {
+            return Ok(None);
+        }
+        if first {
+            taps.insert(0, tap.clone());
+        } else {
+            taps.push(tap.clone());
+        }
+        self.db.replace_taps(&taps)?;
+        Ok(Some(tap))
+    }
+
+    /// Stop resolving unqualified names against `tap`. Formulas already
+    /// installed from it keep their qualified name and stay installed.
+    pub fn remove_tap(&mut self, tap: &str) -> Result<String, Error> {
+        let tap = normalize_tap_name(tap)?;
+        let mut taps = self.db.list_taps()?;
+        let before = taps.len();
+        taps.retain(|configured| *configured != tap);
+        if taps.len() == before {
+            return Err(Error::InvalidArgument {
+                message: format!("tap {tap} is not configured"),
+            });
+        }
+        self.db.replace_taps(&taps)?;
+        Ok(tap)
+    }
+
+    /// Move `first` to the top of the tap priority order, in the order
+    /// given; the other taps keep their relative order after them. Returns
+    /// the new order.
+    pub fn prioritize_taps(&mut self, first: &[String]) -> Result<Vec<String>, Error> {
+        let current = self.db.list_taps()?;
+        let mut order = Vec::with_capacity(current.len());
+        for tap in first {
+            let tap = normalize_tap_name(tap)?;
+            if !current.contains(&tap) {
+                return Err(Error::InvalidArgument {
+                    message: format!("tap {tap} is not configured; add it with 'zb tap add {tap}'"),
+                });
+            }
+            if !order.contains(&tap) {
+                order.push(tap);
+            }
+        }
+        for tap in current {
+            if !order.contains(&tap) {
+                order.push(tap);
+            }
+        }
+        self.db.replace_taps(&order)?;
+        Ok(order)
+    }
+
+    /// Apply the resolution order to `names`. Names that nothing provides
+    /// are left as they are, so fetching them reports the usual error. The
+    /// taps are read once, and every unqualified name is looked up in them
+    /// at the same time.
+    pub(crate) async fn resolve_taps(&self, names: &[String]) -> Result<TapResolution, Error> {
+        let taps = self.db.list_taps()?;
+        let unqualified =
+            |name: &String| !(taps.is_empty() || name.contains('/') || name.starts_with("cask:"));
+        let mut sources = futures::future::join_all(
+            names
+                .iter()
+                .filter(|name| unqualified(name))
+                .map(|name| self.sources_of(name, &taps)),
+        )
+        .await
+        .into_iter();
+
+        let mut resolution = TapResolution::default();
+        for name in names {
+            if let Some(core) = name.strip_prefix("homebrew/core/") {
+                resolution.names.push(core.to_string());
+                continue;
+            }
+            if !unqualified(name) {
+                resolution.names.push(name.clone());
+                continue;
+            }
+
+            let (in_core, in_taps) = sources.next().expect("one lookup per unqualified name");
+            let Some(choice) = choose_source(name, in_core, &in_taps) else {
+                resolution.names.push(name.clone());
+                continue;
+            };
+            resolution.names.push(if choice.chosen == CORE_TAP {
+                name.clone()
+            } else {
+                format!("{}/{name}", choice.chosen)
+            });
+            if !choice.shadowed.is_empty() {
+                resolution.choices.push(choice);
+            }
+        }
+        Ok(resolution)
+    }
+
+    /// Whether core has `name`, and which of `taps` do. Core counts as
+    /// having it when the lookup fails for any reason but a missing
+    /// formula, so an outage never silently moves a name to a tap.
+    async fn sources_of(&self, name: &str, taps: &[String]) -> (bool, Vec<String>) {
+        let core = async {
+            match self.api_client.get_formula(name).await {
+                Err(Error::MissingFormula { .. }) => self.alias_target(name).await.is_some(),
+                _ => true,
+            }
+        };
+        let lookups = taps.iter().map(|tap| async move {
+            match self.api_client.get_formula(&format!("{tap}/{name}")).await {
+                Ok(_) => true,
+                Err(Error::MissingFormula { .. }) => false,
+                Err(e) => {
+                    warn!(tap = %tap, formula = %name, error = %e, "failed to look up formula in tap");
+                    false
+                }
+            }
+        });
+        let (in_core, found) = futures::join!(core, futures::future::join_all(lookups));
+        let in_taps = taps
+            .iter()
+            .zip(found)
+            .filter(|(_, found)| *found)
+            .map(|(tap, _)| tap.clone())
+            .collect();
+        (in_core, in_taps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    use crate::installer::install::test_support::*;
+    use crate::network::api::ApiClient;
+
+    fn installer(server: &MockServer, tmp: &TempDir) -> Installer {
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        fs::create_dir_all(root.join("db")).unwrap();
+        installer_at(
+            ApiClient::with_base_url(format!("{}/formula", server.uri()))
+                .unwrap()
+                .with_tap_raw_base_url(server.uri()),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        )
+    }
+
+    async fn core_formula(server: &MockServer, name: &str) {
+        let tag = get_test_bottle_tag();
+        Mock::given(method("GET"))
+            .and(path(format!("/formula/{name}.json")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "name": name,
+                "versions": { "stable": "1.0.0" },
+                "dependencies": [],
+                "bottle": { "stable": { "files": { tag: {
+                    "url": format!("{}/bottles/{name}.tar.gz", server.uri()),
+                    "sha256": "a".repeat(64),
+                }}}}
+            })))
+            .mount(server)
+            .await;
+    }
+
+    async fn tap_formula(server: &MockServer, tap: &str, name: &str) {
+        let (owner, repo) = tap.split_once('/').unwrap();
+        let rb = format!(
+            "class Jq < Formula\n  version \"2.0.0\"\n  bottle do\n    root_url \"{}/ghcr/{tap}\"\n    sha256 {}: \"{}\"\n  end\nend\n",
+            server.uri(),
+            get_test_bottle_tag(),
+            "b".repeat(64)
+        );
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/{owner}/homebrew-{repo}/main/Formula/{name}.rb"
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_string(rb))
+            .mount(server)
+            .await;
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn tap_names_are_normalized_and_core_is_refused() {
+        assert_eq!(
+            normalize_tap_name("Internal/homebrew-Tools").unwrap(),
+            "internal/tools"
+        );
+        for bad in ["internal", "internal/", "a/b/c", "homebrew/core", "../x"] {
+            assert!(normalize_tap_name(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn priority_moves_named_taps_first_and_keeps_the_rest_in_order() {
+        let server_tmp = TempDir::new().unwrap();
+        let root = server_tmp.path().join("zerobrew");
+        let mut installer = installer_at(
+            ApiClient::new(),
+            &root,
+            &server_tmp.path().join("homebrew"),
+            root.join("Cellar"),
+        );
+
+        for tap in ["a/one", "b/two", "c/three"] {
+            installer.add_tap(tap, false).unwrap();
+        }
+        assert_eq!(installer.add_tap("b/homebrew-two", true).unwrap(), None);
+        assert_eq!(
+            installer.add_tap("d/four", true).unwrap().as_deref(),
+            Some("d/four")
+        );
+        assert_eq!(
+            installer.taps().unwrap(),
+            ["d/four", "a/one", "b/two", "c/three"]
+        );
+
+        assert_eq!(
+            installer
+                .prioritize_taps(&names(&["c/three", "a/one"]))
+                .unwrap(),
+            ["c/three", "a/one", "d/four", "b/two"]
+        );
+        assert!(installer.prioritize_taps(&names(&["e/five"])).is_err());
+
+        installer.remove_tap("a/one").unwrap();
+        assert_eq!(installer.taps().unwrap(), ["c/three", "d/four", "b/two"]);
+        assert!(installer.remove_tap("a/one").is_err());
+    }
+
+    #[tokio::test]
+    async fn core_shadows_taps() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = installer(&server, &tmp);
+        core_formula(&server, "jq").await;
+        tap_formula(&server, "internal/tools", "jq").await;
+        installer.add_tap("internal/tools", true).unwrap();
+
+        let resolution = installer.resolve_taps(&names(&["jq"])).await.unwrap();
+        assert_eq!(resolution.names, ["jq"]);
+        assert_eq!(
+            resolution.choices,
+            [TapChoice {
+                name: "jq".to_string(),
+                chosen: CORE_TAP.to_string(),
+                shadowed: vec!["internal/tools".to_string()],
+            }]
+        );
+
+        // Qualified names always win, in both directions.
+        let resolution = installer
+            .resolve_taps(&names(&["internal/tools/jq", "homebrew/core/jq"]))
+            .await
+            .unwrap();
+        assert_eq!(resolution.names, ["internal/tools/jq", "jq"]);
+        assert!(resolution.choices.is_empty());
+    }
+
+    #[tokio::test]
+    async fn taps_shadow_lower_priority_taps() {
+        let server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let mut installer = installer(&server, &tmp);
+        tap_formula(&server, "internal/tools", "jq").await;
+        tap_formula(&server, "acme/extras", "jq").await;
+        installer.add_tap("acme/extras", false).unwrap();
+        installer.add_tap("internal/tools", false).unwrap();
+
+        let resolution = installer.resolve_taps(&names(&["jq"])).await.unwrap();
+        assert_eq!(resolution.names, ["acme/extras/jq"]);
+        assert_eq!(resolution.choices[0].shadowed, ["internal/tools"]);
+
+        installer
+            .prioritize_taps(&names(&["internal/tools"]))
+            .unwrap();
+        let resolution = installer.resolve_taps(&names(&["jq"])).await.unwrap();
+        assert_eq!(resolution.names, ["internal/tools/jq"]);
+        assert_eq!(resolution.choices[0].shadowed, ["acme/extras"]);
+
+        // A name only one tap has resolves to it without a choice to report,
+        // and one nothing has is left for the fetch to report.
+        installer.remove_tap("acme/extras").unwrap();
+        let resolution = installer
+            .resolve_taps(&names(&["jq", "nosuch"]))
+            .await
+            .unwrap();
+        assert_eq!(resolution.names, ["internal/tools/jq", "nosuch"]);
+        assert!(resolution.choices.is_empty());
+    }
+}
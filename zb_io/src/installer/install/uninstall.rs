@@ -1,40 +1,407 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
 use zb_core::{Error, formula_token};
 
 use super::Installer;
+use super::gc::disk_usage;
+use super::hooks::HookPoint;
+use crate::extraction::extract::normalize_path;
+use crate::storage::db::{KegFileKind, KegFileRecord};
+
+/// Result of uninstalling one formula as part of a batch.
+#[derive(Debug)]
+pub struct UninstallOutcome {
+    pub name: String,
+    pub result: Result<(), Error>,
+    /// Config files left in the prefix because they were not purged.
+    pub kept_configs: Vec<PathBuf>,
+}
+
+/// Everything uninstalling a batch of formulas would touch, gathered
+/// without changing anything. [`Installer::execute_uninstall`] carries out
+/// exactly this plan, so a dry run can't promise something else.
+#[derive(Debug)]
+pub struct UninstallPlan {
+    /// One entry per requested name, in the order given; names that aren't
+    /// installed are `Error::NotInstalled`.
+    pub formulas: Vec<Result<KegRemoval, Error>>,
+    /// Whether recorded config files are removed too.
+    pub purge_config: bool,
+}
+
+/// What uninstalling one formula removes.
+#[derive(Debug, Clone)]
+pub struct KegRemoval {
+    pub name: String,
+    /// The installed keg, followed by any versions kept for rollback.
+    pub kegs: Vec<PlannedKeg>,
+    /// The keg's linked-file receipts, plus links into the keg that have
+    /// none. Directories another installed keg still claims are left out, as
+    /// uninstall never removes them.
+    pub linked_files: Vec<KegFileRecord>,
+    /// The formula's `opt` link, when it points at the installed keg.
+    pub opt_link: Option<PathBuf>,
+    /// Store entries no installed or retained keg uses once the batch is
+    /// uninstalled, so the next `zb gc` removes them.
+    pub unreferenced_store_keys: Vec<String>,
+    /// Installed formulas outside the batch that depend on this one.
+    pub dependents: Vec<String>,
+    keg_name: String,
+}
+
+/// A keg directory uninstall removes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedKeg {
+    pub version: String,
+    pub path: PathBuf,
+    /// Apparent size of the files in the keg.
+    pub bytes: u64,
+}
 
 impl Installer {
     pub fn uninstall(&mut self, name: &str) -> Result<(), Error> {
-        let installed = self.db.get_installed(name).ok_or(Error::NotInstalled {
-            name: name.to_string(),
-        })?;
-        let keg_name = formula_token(&installed.name);
+        self.uninstall_many(&[name.to_string()], false)?
+            .pop()
+            .map_or(Ok(()), |outcome| outcome.result)
+    }
+
+    /// Uninstall several formulas at once. Symlinks and kegs are removed in
+    /// parallel and all database updates share one transaction. A failure
+    /// for one formula does not stop the others; each gets its own outcome,
+    /// in the order given. Only a failure to commit aborts the whole batch.
+    ///
+    /// Config files the kegs placed in the prefix are kept, and listed in the
+    /// outcome, unless `purge_config` is set.
+    pub fn uninstall_many(
+        &mut self,
+        names: &[String],
+        purge_config: bool,
+    ) -> Result<Vec<UninstallOutcome>, Error> {
+        let plan = self.plan_uninstall(names, purge_config)?;
+        self.execute_uninstall(plan)
+    }
+
+    /// Gather what uninstalling `names` would remove, from the database and
+    /// the Cellar, without modifying either.
+    pub fn plan_uninstall(
+        &self,
+        names: &[String],
+        purge_config: bool,
+    ) -> Result<UninstallPlan, Error> {
+        let batch: HashSet<&str> = names.iter().map(String::as_str).collect();
+
+        // Previous versions kept for rollback go with the formula.
+        let mut retained: HashMap<&str, Vec<(String, String)>> = HashMap::new();
+        for keg in self.db.list_retained(None)? {
+            if let Some(name) = names.iter().find(|n| **n == keg.name) {
+                retained
+                    .entry(name.as_str())
+                    .or_default()
+                    .push((keg.version, keg.store_key));
+            }
+        }
+
+        let mut recorded_files: HashMap<&str, Vec<KegFileRecord>> = HashMap::new();
+        // Directories still claimed by kegs that stay installed.
+        let mut shared_dirs: HashSet<String> = HashSet::new();
+        for record in self.db.list_keg_files()? {
+            if let Some(name) = names.iter().find(|n| **n == record.name) {
+                recorded_files
+                    .entry(name.as_str())
+                    .or_default()
+                    .push(record);
+            } else if record.kind == KegFileKind::Dir {
+                shared_dirs.insert(record.linked_path);
+            }
+        }
+
+        let mut dependents: HashMap<&str, BTreeSet<String>> = HashMap::new();
+        for (name, dependency) in self.db.list_dependency_edges()? {
+            if !batch.contains(name.as_str())
+                && let Some(dependency) = names.iter().find(|n| **n == dependency)
+            {
+                dependents
+                    .entry(dependency.as_str())
+                    .or_default()
+                    .insert(name);
+            }
+        }
+
+        let mut formulas: Vec<Result<KegRemoval, Error>> = Vec::with_capacity(names.len());
+        let mut store_keys: Vec<Vec<String>> = Vec::with_capacity(names.len());
+        for name in names {
+            let Some(installed) = self.db.get_installed(name)? else {
+                formulas.push(Err(Error::NotInstalled { name: name.clone() }));
+                store_keys.push(Vec::new());
+                continue;
+            };
+            let keg_name = formula_token(&installed.name).to_string();
+            let mut versions = vec![(installed.version, installed.store_key)];
+            versions.extend(retained.remove(name.as_str()).unwrap_or_default());
+
+            let kegs: Vec<PlannedKeg> = versions
+                .iter()
+                .map(|(version, _)| {
+                    let path = self.cellar.keg_path(&keg_name, version);
+                    PlannedKeg {
+                        version: version.clone(),
+                        bytes: disk_usage(&path),
+                        path,
+                    }
+                })
+                .collect();
+            let mut linked_files: Vec<KegFileRecord> = recorded_files
+                .remove(name.as_str())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|record| {
+                    record.kind != KegFileKind::Dir || !shared_dirs.contains(&record.linked_path)
+                })
+                .collect();
+            // `unlink_keg` also removes links it finds that were never
+            // recorded, e.g. from before receipts were kept.
+            let keg_path = &kegs[0].path;
+            for live in self.linker.collect_linked_files(keg_path)? {
+                let link_path = live.link_path.to_string_lossy().into_owned();
+                if !linked_files.iter().any(|r| r.linked_path == link_path) {
+                    linked_files.push(KegFileRecord {
+                        name: name.clone(),
+                        version: versions[0].0.clone(),
+                        linked_path: link_path,
+                        target_path: live.target_path.to_string_lossy().into_owned(),
+                        link_target: live
+                            .link_target
+                            .map(|target| target.to_string_lossy().into_owned()),
+                        kind: live.kind,
+                    });
+                }
+            }
+            linked_files.sort_by(|a, b| a.linked_path.cmp(&b.linked_path));
+            let opt_link = self.linker.opt_link(keg_path);
+            store_keys.push(versions.into_iter().map(|(_, key)| key).collect());
+            formulas.push(Ok(KegRemoval {
+                name: name.clone(),
+                kegs,
+                linked_files,
+                opt_link,
+                unreferenced_store_keys: Vec::new(),
+                dependents: dependents
+                    .remove(name.as_str())
+                    .map(|names| names.into_iter().collect())
+                    .unwrap_or_default(),
+                keg_name,
+            }));
+        }
+
+        // A store entry is left unreferenced once every keg using it is in
+        // the batch.
+        let mut released: HashMap<&str, i64> = HashMap::new();
+        for key in store_keys.iter().flatten() {
+            *released.entry(key.as_str()).or_default() += 1;
+        }
+        let refcounts: HashMap<String, i64> = self
+            .db
+            .list_store_refs()?
+            .into_iter()
+            .map(|store_ref| (store_ref.store_key, store_ref.refcount))
+            .collect();
+        for (removal, keys) in formulas.iter_mut().zip(&store_keys) {
+            let Ok(removal) = removal else { continue };
+            let mut unreferenced: Vec<String> = keys
+                .iter()
+                .filter(|key| {
+                    refcounts.get(key.as_str()).copied().unwrap_or(0) <= released[key.as_str()]
+                })
+                .cloned()
+                .collect();
+            unreferenced.sort();
+            unreferenced.dedup();
+            removal.unreferenced_store_keys = unreferenced;
+        }
+
+        Ok(UninstallPlan {
+            formulas,
+            purge_config,
+        })
+    }
 
-        let keg_path = self.cellar.keg_path(keg_name, &installed.version);
-        self.linker.unlink_keg(&keg_path)?;
+    /// Carry out a plan from [`Installer::plan_uninstall`]. A formula whose
+    /// pre-uninstall hook fails is left installed, with the hook's error as
+    /// its outcome; post-uninstall hooks run once its kegs are gone.
+    pub fn execute_uninstall(
+        &mut self,
+        plan: UninstallPlan,
+    ) -> Result<Vec<UninstallOutcome>, Error> {
+        self.execute_uninstall_with(plan, true)
+    }
 
+    /// [`Self::execute_uninstall`], running hooks only if `hooks` is set.
+    pub(crate) fn execute_uninstall_with(
+        &mut self,
+        plan: UninstallPlan,
+        hooks: bool,
+    ) -> Result<Vec<UninstallOutcome>, Error> {
+        let UninstallPlan {
+            formulas,
+            purge_config,
+        } = plan;
+        let mut results: Vec<Option<Result<(), Error>>> = Vec::with_capacity(formulas.len());
+        let mut names = Vec::with_capacity(formulas.len());
+        let mut kegs = Vec::new();
+        for (index, formula) in formulas.into_iter().enumerate() {
+            match formula {
+                Ok(removal) => {
+                    names.push(removal.name.clone());
+                    let vetoed = if hooks {
+                        self.run_hooks(
+                            HookPoint::PreUninstall,
+                            &removal.name,
+                            &removal.kegs[0].version,
+                        )
+                        .err()
+                    } else {
+                        None
+                    };
+                    match vetoed {
+                        Some(err) => results.push(Some(Err(err))),
+                        None => {
+                            kegs.push((index, removal));
+                            results.push(None);
+                        }
+                    }
+                }
+                Err(err) => {
+                    names.push(match &err {
+                        Error::NotInstalled { name } => name.clone(),
+                        _ => String::new(),
+                    });
+                    results.push(Some(Err(err)));
+                }
+            }
+        }
+
+        let linker = &self.linker;
+        let unlinked: Vec<Result<Vec<PathBuf>, Error>> = self.cellar.in_pool(|| {
+            kegs.par_iter()
+                .map(|(_, removal)| {
+                    linker.unlink_keg(&removal.kegs[0].path)?;
+                    Ok(remove_recorded_files(&removal.linked_files, purge_config))
+                })
+                .collect()
+        });
+
+        let mut kept_configs: Vec<Vec<PathBuf>> = vec![Vec::new(); names.len()];
         {
             let tx = self.db.transaction()?;
-            tx.record_uninstall(name)?;
+            for ((index, _), unlink) in kegs.iter().zip(unlinked) {
+                let result = unlink.and_then(|kept| {
+                    kept_configs[*index] = kept;
+                    tx.record_uninstall(&names[*index]).map(|_| ())
+                });
+                results[*index] = Some(result);
+            }
             tx.commit()?;
         }
 
-        self.cellar.remove_keg(keg_name, &installed.version)?;
+        let cellar = &self.cellar;
+        let removed: Vec<(usize, Result<(), Error>)> = cellar.in_pool(|| {
+            kegs.par_iter()
+                .filter(|(index, _)| matches!(results[*index], Some(Ok(()))))
+                .map(|(index, removal)| {
+                    let result = removal
+                        .kegs
+                        .iter()
+                        .try_for_each(|keg| cellar.remove_keg(&removal.keg_name, &keg.version));
+                    (*index, result)
+                })
+                .collect()
+        });
+        for (index, result) in removed {
+            results[index] = Some(result);
+        }
+        for (index, removal) in &kegs {
+            if matches!(results[*index], Some(Ok(()))) {
+                for keg in &removal.kegs {
+                    self.forget_metadata(&removal.keg_name, &keg.version);
+                }
+            }
+        }
 
-        Ok(())
-    }
+        if hooks {
+            for (index, removal) in &kegs {
+                if matches!(results[*index], Some(Ok(()))) {
+                    // Post hook failures are logged and don't fail the uninstall.
+                    let _ = self.run_hooks(
+                        HookPoint::PostUninstall,
+                        &removal.name,
+                        &removal.kegs[0].version,
+                    );
+                }
+            }
+        }
 
-    pub fn gc(&mut self) -> Result<Vec<String>, Error> {
-        let unreferenced = self.db.get_unreferenced_store_keys()?;
-        let mut removed = Vec::new();
+        Ok(names
+            .into_iter()
+            .zip(results)
+            .zip(kept_configs)
+            .map(|((name, result), kept_configs)| UninstallOutcome {
+                name,
+                result: result.unwrap_or(Ok(())),
+                kept_configs,
+            })
+            .collect())
+    }
+}
 
-        for store_key in unreferenced {
-            self.store.remove_entry(&store_key)?;
-            self.db.delete_store_ref(&store_key)?;
-            removed.push(store_key);
+/// Remove what a keg recorded in the prefix, by kind, and return the configs
+/// that were kept.
+///
+/// Symlinks are removed only while they still point at their keg target;
+/// `unlink_keg` already handles everything present in the keg, so this
+/// catches links whose keg entries have since disappeared. Wrappers are
+/// removed outright. Directories are removed, deepest first, once empty;
+/// the plan has already dropped those other kegs still claim.
+fn remove_recorded_files(records: &[KegFileRecord], purge_config: bool) -> Vec<PathBuf> {
+    let mut kept = Vec::new();
+    let mut dirs = Vec::new();
+
+    for record in records {
+        let path = PathBuf::from(&record.linked_path);
+        match record.kind {
+            KegFileKind::Symlink => {
+                if let Ok(current) = fs::read_link(&path) {
+                    let resolved =
+                        normalize_path(&path.parent().unwrap_or(Path::new("")).join(current));
+                    if resolved == Path::new(&record.target_path) {
+                        let _ = fs::remove_file(&path);
+                    }
+                }
+            }
+            KegFileKind::Wrapper => {
+                let _ = fs::remove_file(&path);
+            }
+            KegFileKind::Config if purge_config => {
+                let _ = fs::remove_file(&path);
+            }
+            KegFileKind::Config => {
+                if path.symlink_metadata().is_ok() {
+                    kept.push(path);
+                }
+            }
+            KegFileKind::Dir => dirs.push(path),
         }
+    }
 
-        Ok(removed)
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in dirs {
+        // Fails, and is left alone, while anything is still inside.
+        let _ = fs::remove_dir(&dir);
     }
+
+    kept
 }
 
 #[cfg(test)]
@@ -45,13 +412,12 @@ mod tests {
     use wiremock::matchers::{method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
-    use crate::cellar::Cellar;
     use crate::installer::install::test_support::*;
     use crate::network::api::ApiClient;
-    use crate::storage::blob::BlobCache;
-    use crate::storage::db::Database;
-    use crate::storage::store::Store;
-    use crate::{Installer, Linker};
+
+    use crate::storage::db::KegFileKind;
+
+    use crate::Installer;
 
     #[tokio::test]
     async fn uninstall_cleans_everything() {
@@ -101,25 +467,12 @@ mod tests {
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         installer
@@ -128,13 +481,13 @@ mod tests {
             .unwrap();
 
         assert!(installer.is_installed("uninstallme"));
-        assert!(root.join("cellar/uninstallme/1.0.0").exists());
+        assert!(root.join("Cellar/uninstallme/1.0.0").exists());
         assert!(prefix.join("bin/uninstallme").exists());
 
         installer.uninstall("uninstallme").unwrap();
 
         assert!(!installer.is_installed("uninstallme"));
-        assert!(!root.join("cellar/uninstallme/1.0.0").exists());
+        assert!(!root.join("Cellar/uninstallme/1.0.0").exists());
         assert!(!prefix.join("bin/uninstallme").exists());
     }
 
@@ -183,25 +536,12 @@ mod tests {
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         installer
@@ -274,25 +614,12 @@ mod tests {
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.clone(),
-            root.join("locks"),
+
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         installer
@@ -313,7 +640,11 @@ mod tests {
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
 
-        let bottle = create_bottle_tarball("terraform");
+        let bottle = versioned_bottle(
+            "terraform",
+            "1.10.0",
+            &[("bin/terraform", b"#!/bin/sh\necho terraform")],
+        );
         let sha = sha256_hex(&bottle);
         let tag = get_test_bottle_tag();
 
@@ -348,26 +679,14 @@ end
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri()))
-            .unwrap()
-            .with_tap_raw_base_url(mock_server.uri());
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.to_path_buf(),
-            root.join("locks"),
+
+        let mut installer = installer_at(
+            ApiClient::with_base_url(format!("{}/formula", mock_server.uri()))
+                .unwrap()
+                .with_tap_raw_base_url(mock_server.uri()),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
 
         installer
@@ -377,10 +696,10 @@ end
 
         assert!(installer.is_installed("hashicorp/tap/terraform"));
         assert!(!installer.is_installed("terraform"));
-        assert!(root.join("cellar/terraform/1.10.0").exists());
+        assert!(root.join("Cellar/terraform/1.10.0").exists());
         installer.uninstall("hashicorp/tap/terraform").unwrap();
         assert!(!installer.is_installed("hashicorp/tap/terraform"));
-        assert!(!root.join("cellar/terraform/1.10.0").exists());
+        assert!(!root.join("Cellar/terraform/1.10.0").exists());
     }
 
     #[tokio::test]
@@ -388,7 +707,11 @@ end
         let mock_server = MockServer::start().await;
         let tmp = TempDir::new().unwrap();
 
-        let bottle = create_bottle_tarball("terraform");
+        let bottle = versioned_bottle(
+            "terraform",
+            "1.10.0",
+            &[("bin/terraform", b"#!/bin/sh\necho terraform")],
+        );
         let sha = sha256_hex(&bottle);
         let tag = get_test_bottle_tag();
         let core_json = format!(
@@ -430,25 +753,12 @@ end
 
         let root = tmp.path().join("zerobrew");
         let prefix = tmp.path().join("homebrew");
-        fs::create_dir_all(root.join("db")).unwrap();
-
-        let api_client =
-            ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
-        let blob_cache = BlobCache::new(&root.join("cache")).unwrap();
-        let store = Store::new(&root).unwrap();
-        let cellar = Cellar::new(&root).unwrap();
-        let linker = Linker::new(&prefix).unwrap();
-        let db = Database::open(&root.join("db/zb.sqlite3")).unwrap();
-
-        let mut installer = Installer::new(
-            api_client,
-            blob_cache,
-            store,
-            cellar,
-            linker,
-            db,
-            prefix.to_path_buf(),
-            root.join("locks"),
+
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
         );
         installer
             .install(&["terraform".to_string()], true)
@@ -460,4 +770,309 @@ end
         assert!(matches!(err, zb_core::Error::NotInstalled { .. }));
         assert!(installer.is_installed("terraform"));
     }
+
+    #[tokio::test]
+    async fn uninstall_many_isolates_failures_and_keeps_prefix_consistent() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+        let names = ["alpha", "beta", "gamma", "keep"];
+
+        let mut shas = std::collections::HashMap::new();
+        for name in names {
+            let bottle = create_bottle_tarball(name);
+            let sha = sha256_hex(&bottle);
+            let json = format!(
+                r#"{{
+                    "name": "{name}",
+                    "versions": {{ "stable": "1.0.0" }},
+                    "dependencies": [],
+                    "bottle": {{ "stable": {{ "files": {{
+                        "{tag}": {{
+                            "url": "{uri}/bottles/{name}.tar.gz",
+                            "sha256": "{sha}"
+                        }}
+                    }} }} }}
+                }}"#,
+                uri = mock_server.uri(),
+            );
+            Mock::given(method("GET"))
+                .and(path(format!("/formula/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_string(json))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+                .mount(&mock_server)
+                .await;
+            shas.insert(name, sha);
+        }
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+        let all: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        installer.install(&all, true).await.unwrap();
+
+        let batch: Vec<String> = ["alpha", "missing", "beta", "gamma"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let outcomes = installer.uninstall_many(&batch, false).unwrap();
+
+        let summary: Vec<(&str, bool)> = outcomes
+            .iter()
+            .map(|o| (o.name.as_str(), o.result.is_ok()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("alpha", true),
+                ("missing", false),
+                ("beta", true),
+                ("gamma", true)
+            ]
+        );
+        assert!(matches!(
+            outcomes[1].result,
+            Err(zb_core::Error::NotInstalled { .. })
+        ));
+
+        for name in ["alpha", "beta", "gamma"] {
+            assert!(!installer.is_installed(name));
+            assert!(!root.join("Cellar").join(name).exists());
+            assert!(!prefix.join("bin").join(name).exists());
+            assert_eq!(installer.db.get_store_refcount(&shas[name]).unwrap(), 0);
+        }
+        assert!(installer.is_installed("keep"));
+        assert!(prefix.join("bin/keep").exists());
+        assert_eq!(installer.db.get_store_refcount(&shas["keep"]).unwrap(), 1);
+
+        for entry in walkdir::WalkDir::new(&prefix) {
+            let entry = entry.unwrap();
+            if entry.path_is_symlink() {
+                assert!(
+                    entry.path().exists(),
+                    "dangling symlink left behind: {}",
+                    entry.path().display()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn config_files_survive_uninstall_unless_purged() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        for name in ["keepconf", "purgeconf"] {
+            let bottle = create_bottle_tarball(name);
+            let sha = sha256_hex(&bottle);
+            let json = format!(
+                r#"{{
+                    "name": "{name}",
+                    "versions": {{ "stable": "1.0.0" }},
+                    "dependencies": [],
+                    "bottle": {{ "stable": {{ "files": {{
+                        "{tag}": {{
+                            "url": "{uri}/bottles/{name}.tar.gz",
+                            "sha256": "{sha}"
+                        }}
+                    }} }} }}
+                }}"#,
+                uri = mock_server.uri(),
+            );
+            Mock::given(method("GET"))
+                .and(path(format!("/formula/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_string(json))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+                .mount(&mock_server)
+                .await;
+        }
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+        installer
+            .install(&["keepconf".to_string(), "purgeconf".to_string()], true)
+            .await
+            .unwrap();
+
+        // Stand in for a post-install step copying default configs.
+        fs::create_dir_all(prefix.join("etc")).unwrap();
+        {
+            let tx = installer.db.transaction().unwrap();
+            for name in ["keepconf", "purgeconf"] {
+                let config = prefix.join(format!("etc/{name}.conf"));
+                fs::write(&config, "edited = true\n").unwrap();
+                tx.record_linked_file(
+                    name,
+                    "1.0.0",
+                    &config.to_string_lossy(),
+                    &root
+                        .join(format!("Cellar/{name}/1.0.0/etc/{name}.conf"))
+                        .to_string_lossy(),
+                    None,
+                    KegFileKind::Config,
+                )
+                .unwrap();
+            }
+            tx.commit().unwrap();
+        }
+
+        let kept = installer
+            .uninstall_many(&["keepconf".to_string()], false)
+            .unwrap();
+        assert!(kept[0].result.is_ok());
+        assert_eq!(kept[0].kept_configs, [prefix.join("etc/keepconf.conf")]);
+        assert!(prefix.join("etc/keepconf.conf").exists());
+        assert!(!prefix.join("bin/keepconf").exists());
+
+        let purged = installer
+            .uninstall_many(&["purgeconf".to_string()], true)
+            .unwrap();
+        assert!(purged[0].result.is_ok());
+        assert!(purged[0].kept_configs.is_empty());
+        assert!(!prefix.join("etc/purgeconf.conf").exists());
+
+        assert!(installer.db.list_keg_files().unwrap().is_empty());
+    }
+
+    fn prefix_tree(prefix: &std::path::Path) -> std::collections::BTreeSet<std::path::PathBuf> {
+        walkdir::WalkDir::new(prefix)
+            .follow_links(false)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|entry| entry.into_path())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn dry_run_plan_matches_real_uninstall() {
+        let mock_server = MockServer::start().await;
+        let tmp = TempDir::new().unwrap();
+        let tag = get_test_bottle_tag();
+
+        for (name, deps) in [("planlib", "[]"), ("planapp", r#"["planlib"]"#)] {
+            let bin = format!("bin/{name}");
+            let man = format!("share/man/man1/{name}.1");
+            let bottle =
+                bottle_with_files(name, &[(&bin, b"#!/bin/sh\n"), (&man, b".TH PLAN 1\n")]);
+            let json = format!(
+                r#"{{
+                    "name": "{name}",
+                    "versions": {{ "stable": "1.0.0" }},
+                    "dependencies": {deps},
+                    "bottle": {{ "stable": {{ "files": {{
+                        "{tag}": {{
+                            "url": "{uri}/bottles/{name}.tar.gz",
+                            "sha256": "{sha}"
+                        }}
+                    }} }} }}
+                }}"#,
+                uri = mock_server.uri(),
+                sha = sha256_hex(&bottle),
+            );
+            Mock::given(method("GET"))
+                .and(path(format!("/formula/{name}.json")))
+                .respond_with(ResponseTemplate::new(200).set_body_string(json))
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/bottles/{name}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(bottle))
+                .mount(&mock_server)
+                .await;
+        }
+
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(
+            api_client(&mock_server),
+            &root,
+            &prefix,
+            root.join("Cellar"),
+        );
+        installer
+            .install(&["planapp".to_string()], true)
+            .await
+            .unwrap();
+
+        let db_state = |installer: &Installer| {
+            format!(
+                "{:?} {:?} {:?}",
+                installer.db.list_installed().unwrap(),
+                installer.db.list_keg_files().unwrap(),
+                installer.db.list_store_refs().unwrap()
+            )
+        };
+        let state_before = db_state(&installer);
+        let tree_before = prefix_tree(&prefix);
+
+        let lib_plan = installer
+            .plan_uninstall(&["planlib".to_string()], false)
+            .unwrap();
+        let lib = lib_plan.formulas[0].as_ref().unwrap();
+        assert_eq!(lib.dependents, ["planapp"]);
+
+        let plan = installer
+            .plan_uninstall(&["planapp".to_string()], false)
+            .unwrap();
+        assert_eq!(db_state(&installer), state_before);
+        assert_eq!(prefix_tree(&prefix), tree_before);
+
+        let app = plan.formulas[0].as_ref().unwrap().clone();
+        assert!(app.dependents.is_empty());
+        assert_eq!(app.kegs.len(), 1);
+        assert_eq!(app.kegs[0].path, root.join("Cellar/planapp/1.0.0"));
+        assert!(app.kegs[0].bytes > 0);
+        let planned_links: std::collections::BTreeSet<_> = app
+            .linked_files
+            .iter()
+            .filter(|file| matches!(file.kind, KegFileKind::Symlink | KegFileKind::Wrapper))
+            .map(|file| std::path::PathBuf::from(&file.linked_path))
+            .chain(app.opt_link.clone())
+            .collect();
+        assert!(planned_links.contains(&prefix.join("bin/planapp")));
+        assert!(planned_links.contains(&prefix.join("opt/planapp")));
+        let planned_paths: std::collections::BTreeSet<_> = app
+            .linked_files
+            .iter()
+            .map(|file| std::path::PathBuf::from(&file.linked_path))
+            .chain(app.opt_link.clone())
+            .collect();
+
+        for outcome in installer.execute_uninstall(plan).unwrap() {
+            outcome.result.unwrap();
+        }
+
+        let tree_after = prefix_tree(&prefix);
+        let removed: std::collections::BTreeSet<_> =
+            tree_before.difference(&tree_after).cloned().collect();
+        assert!(planned_links.is_subset(&removed));
+        assert!(removed.is_subset(&planned_paths));
+        assert!(!app.kegs[0].path.exists());
+        assert_eq!(
+            installer.db.get_unreferenced_store_keys().unwrap(),
+            app.unreferenced_store_keys
+        );
+        assert!(installer.is_installed("planlib"));
+    }
 }
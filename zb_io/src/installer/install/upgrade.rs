@@ -0,0 +1,307 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use zb_core::{Error, Lifecycle, VersionBump};
+
+use super::{InstallPlan, Installer};
+use crate::network::download::DownloadRequest;
+
+/// An installed formula with a newer build upstream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpgradeCandidate {
+    pub name: String,
+    pub installed_version: String,
+    pub current_version: String,
+    pub bump: VersionBump,
+    /// Bytes the upgrade downloads: 0 when the bottle is already cached,
+    /// `None` for source builds and servers that don't say.
+    pub download_bytes: Option<u64>,
+    pub deprecated: bool,
+    pub disabled: bool,
+    pub pinned: bool,
+}
+
+/// Candidates sharing a [`VersionBump`], sorted by name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpgradeGroup {
+    pub bump: VersionBump,
+    pub formulas: Vec<UpgradeCandidate>,
+    /// The known download sizes added up.
+    pub download_bytes: u64,
+}
+
+/// What an upgrade would do, grouped major first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UpgradePreview {
+    /// Only groups with formulas in them.
+    pub groups: Vec<UpgradeGroup>,
+    /// Upgrades upstream has disabled, left out unless accepted.
+    pub held: Vec<UpgradeCandidate>,
+    /// Upgrades of formulas `zb pin` holds, always left out.
+    pub pinned: Vec<UpgradeCandidate>,
+    /// Rebuild-only upgrades left out unless included.
+    pub hidden_rebuilds: usize,
+}
+
+impl UpgradePreview {
+    /// Group `candidates` by bump. Rebuilds are left out unless
+    /// `include_rebuilds` is set, pinned formulas are always left out, and
+    /// disabled formulas are held unless `accept_disabled` is.
+    pub fn new(
+        candidates: Vec<UpgradeCandidate>,
+        include_rebuilds: bool,
+        accept_disabled: bool,
+    ) -> Self {
+        let mut preview = Self::default();
+        let mut by_bump: HashMap<VersionBump, Vec<UpgradeCandidate>> = HashMap::new();
+        for candidate in candidates {
+            // A pinned rebuild isn't hidden: --include-rebuilds wouldn't
+            // upgrade it either.
+            if candidate.pinned {
+                preview.pinned.push(candidate);
+            } else if candidate.bump == VersionBump::Rebuild && !include_rebuilds {
+                preview.hidden_rebuilds += 1;
+            } else if candidate.disabled && !accept_disabled {
+                preview.held.push(candidate);
+            } else {
+                by_bump.entry(candidate.bump).or_default().push(candidate);
+            }
+        }
+
+        for bump in [
+            VersionBump::Major,
+            VersionBump::Minor,
+            VersionBump::Patch,
+            VersionBump::Rebuild,
+        ] {
+            let Some(mut formulas) = by_bump.remove(&bump) else {
+                continue;
+            };
+            formulas.sort_by(|a, b| a.name.cmp(&b.name));
+            let download_bytes = formulas.iter().filter_map(|c| c.download_bytes).sum();
+            preview.groups.push(UpgradeGroup {
+                bump,
+                formulas,
+                download_bytes,
+            });
+        }
+        preview.held.sort_by(|a, b| a.name.cmp(&b.name));
+        preview.pinned.sort_by(|a, b| a.name.cmp(&b.name));
+        preview
+    }
+
+    /// The formulas the upgrade installs, in group order.
+    pub fn names(&self) -> Vec<String> {
+        self.groups
+            .iter()
+            .flat_map(|group| group.formulas.iter().map(|c| c.name.clone()))
+            .collect()
+    }
+
+    pub fn download_bytes(&self) -> u64 {
+        self.groups.iter().map(|group| group.download_bytes).sum()
+    }
+}
+
+impl Installer {
+    /// Check `names` (every installed formula when empty) for newer builds
+    /// and group them with [`UpgradePreview::new`]. Download sizes come from
+    /// the blob cache or a HEAD request per bottle. Formulas that couldn't
+    /// be checked are returned as warnings, as `check_outdated` does.
+    pub async fn preview_upgrade(
+        &self,
+        names: &[String],
+        include_rebuilds: bool,
+        accept_disabled: bool,
+    ) -> Result<(UpgradePreview, Vec<String>), Error> {
+        for name in names {
+            if !self.is_installed(name) {
+                return Err(Error::NotInstalled { name: name.clone() });
+            }
+        }
+        let wanted: HashSet<&str> = names.iter().map(String::as_str).collect();
+
+        let (mut outdated, unchecked) = self.check_outdated().await?;
+        let warnings = unchecked.iter().map(ToString::to_string).collect();
+        if !wanted.is_empty() {
+            outdated.retain(|pkg| wanted.contains(pkg.name.as_str()));
+        }
+
+        // Unlike in `zb outdated`, an unreadable status fails the preview:
+        // without it disabled formulas would no longer be held back.
+        let lifecycles: HashMap<String, Lifecycle> = self
+            .upstream_statuses()?
+            .into_iter()
+            .map(|status| (status.name, status.lifecycle))
+            .collect();
+        let installed = self.installed_set()?;
+        let (indices, requests): (Vec<usize>, Vec<DownloadRequest>) = outdated
+            .iter()
+            .enumerate()
+            .filter(|(_, pkg)| !pkg.is_source_build)
+            .map(|(index, pkg)| {
+                let request = DownloadRequest {
+                    url: pkg.current_url.clone(),
+                    sha256: pkg.current_sha256.clone(),
+                    name: pkg.name.clone(),
+                };
+                (index, request)
+            })
+            .unzip();
+        let sizes = self.downloader.download_sizes(&requests).await;
+        let download_bytes: HashMap<usize, u64> = indices
+            .into_iter()
+            .zip(sizes)
+            .filter_map(|(index, size)| Some((index, size?)))
+            .collect();
+
+        let candidates = outdated
+            .into_iter()
+            .enumerate()
+            .map(|(index, pkg)| {
+                let lifecycle = lifecycles.get(&pkg.name);
+                UpgradeCandidate {
+                    bump: VersionBump::between(&pkg.installed_version, &pkg.current_version),
+                    download_bytes: download_bytes.get(&index).copied(),
+                    deprecated: matches!(lifecycle, Some(Lifecycle::Deprecated { .. })),
+                    disabled: matches!(lifecycle, Some(Lifecycle::Disabled { .. })),
+                    pinned: installed.get(&pkg.name).is_some_and(|keg| keg.pinned),
+                    name: pkg.name,
+                    installed_version: pkg.installed_version,
+                    current_version: pkg.current_version,
+                }
+            })
+            .collect();
+
+        Ok((
+            UpgradePreview::new(candidates, include_rebuilds, accept_disabled),
+            warnings,
+        ))
+    }
+
+    /// Plan installing the new builds of the formulas `preview` upgrades,
+    /// along with any dependencies they gained. Other installed dependencies
+    /// are left at their version.
+    pub async fn plan_upgrade(&self, preview: &UpgradePreview) -> Result<InstallPlan, Error> {
+        let names = preview.names();
+        if names.is_empty() {
+            return Ok(InstallPlan::default());
+        }
+        let mut plan = self.plan_with_options(&names, false).await?;
+        plan.items.retain(|item| {
+            !self.is_installed(&item.install_name) || names.contains(&item.install_name)
+        });
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str, from: &str, to: &str, bytes: Option<u64>) -> UpgradeCandidate {
+        UpgradeCandidate {
+            name: name.to_string(),
+            installed_version: from.to_string(),
+            current_version: to.to_string(),
+            bump: VersionBump::between(from, to),
+            download_bytes: bytes,
+            deprecated: false,
+            disabled: false,
+            pinned: false,
+        }
+    }
+
+    #[test]
+    fn candidates_are_grouped_major_first_with_totals() {
+        let preview = UpgradePreview::new(
+            vec![
+                candidate("wget", "1.21.3", "1.21.4", Some(100)),
+                candidate("node", "20.1.0", "22.3.0", Some(3_000)),
+                candidate("jq", "1.6", "1.7.1", Some(50)),
+                candidate("curl", "8.1.0", "8.1.2", None),
+            ],
+            false,
+            false,
+        );
+
+        let groups: Vec<(VersionBump, Vec<&str>, u64)> = preview
+            .groups
+            .iter()
+            .map(|g| {
+                let names = g.formulas.iter().map(|c| c.name.as_str()).collect();
+                (g.bump, names, g.download_bytes)
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                (VersionBump::Major, vec!["node"], 3_000),
+                (VersionBump::Minor, vec!["jq"], 50),
+                (VersionBump::Patch, vec!["curl", "wget"], 100),
+            ]
+        );
+        assert_eq!(preview.download_bytes(), 3_150);
+        assert_eq!(preview.names(), ["node", "jq", "curl", "wget"]);
+    }
+
+    #[test]
+    fn rebuilds_are_hidden_unless_included() {
+        let rebuild = || vec![candidate("jq", "1.7.1", "1.7.1_1", Some(10))];
+
+        let hidden = UpgradePreview::new(rebuild(), false, false);
+        assert!(hidden.groups.is_empty());
+        assert_eq!(hidden.hidden_rebuilds, 1);
+
+        let included = UpgradePreview::new(rebuild(), true, false);
+        assert_eq!(included.groups[0].bump, VersionBump::Rebuild);
+        assert_eq!(included.hidden_rebuilds, 0);
+    }
+
+    #[test]
+    fn disabled_formulas_are_held_unless_accepted() {
+        let candidates = || {
+            let mut sunset = candidate("sunset", "1.0", "2.0", Some(5));
+            sunset.disabled = true;
+            let mut old = candidate("old", "1.0", "1.1", Some(5));
+            old.deprecated = true;
+            vec![sunset, old]
+        };
+
+        let held = UpgradePreview::new(candidates(), false, false);
+        assert_eq!(held.names(), ["old"]);
+        assert_eq!(held.held.len(), 1);
+        assert_eq!(held.held[0].name, "sunset");
+
+        let accepted = UpgradePreview::new(candidates(), false, true);
+        assert_eq!(accepted.names(), ["sunset", "old"]);
+        assert!(accepted.held.is_empty());
+    }
+
+    #[test]
+    fn pinned_formulas_are_always_left_out() {
+        let mut jq = candidate("jq", "1.6", "1.7.1", Some(5));
+        jq.pinned = true;
+        jq.disabled = true;
+        let preview = UpgradePreview::new(
+            vec![jq, candidate("wget", "1.21.3", "1.21.4", Some(5))],
+            true,
+            true,
+        );
+        assert_eq!(preview.names(), ["wget"]);
+        assert_eq!(preview.pinned.len(), 1);
+        assert_eq!(preview.pinned[0].name, "jq");
+        assert!(preview.held.is_empty());
+    }
+
+    #[test]
+    fn pinned_rebuilds_are_listed_as_pinned_not_hidden() {
+        let mut jq = candidate("jq", "1.7.1", "1.7.1_1", Some(5));
+        jq.pinned = true;
+        let preview = UpgradePreview::new(vec![jq], false, false);
+        assert_eq!(preview.hidden_rebuilds, 0);
+        assert_eq!(preview.pinned.len(), 1);
+        assert_eq!(preview.pinned[0].name, "jq");
+        assert!(preview.groups.is_empty());
+    }
+}
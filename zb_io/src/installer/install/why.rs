@@ -0,0 +1,536 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use zb_core::Error;
+
+use super::{InstallPlan, Installer};
+use crate::storage::db::InstalledKeg;
+
+/// Why a formula is installed.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum InstallReason {
+    /// Requested directly by the user.
+    Explicit,
+    /// Pulled in by explicit formulas. Each path starts at an explicit
+    /// formula and ends at the queried one, e.g. `["git", "gettext"]`.
+    Dependency { paths: Vec<Vec<String>> },
+    /// Nothing installed requires it; a candidate for removal.
+    Unrequired,
+}
+
+/// Which installed formulas to list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ListFilter {
+    #[default]
+    All,
+    /// Formulas installed on request, including those other formulas also
+    /// depend on.
+    Explicit,
+    /// Formulas only installed as dependencies.
+    Dependencies,
+    /// The dependencies, direct or not, that were pulled in for the named
+    /// formula and weren't requested themselves.
+    InstalledBy(String),
+}
+
+/// The kegs of `installed` that `filter` selects, in their original order.
+/// `edges` are the recorded `(formula, dependency)` pairs.
+pub fn filter_installed(
+    installed: Vec<InstalledKeg>,
+    edges: &[(String, String)],
+    filter: &ListFilter,
+) -> Result<Vec<InstalledKeg>, Error> {
+    let kegs = match filter {
+        ListFilter::All => installed,
+        ListFilter::Explicit => installed.into_iter().filter(|keg| keg.explicit).collect(),
+        ListFilter::Dependencies => installed.into_iter().filter(|keg| !keg.explicit).collect(),
+        ListFilter::InstalledBy(name) => {
+            if !installed.iter().any(|keg| &keg.name == name) {
+                return Err(Error::NotInstalled { name: name.clone() });
+            }
+            let closure = dependency_closure(name, edges);
+            installed
+                .into_iter()
+                .filter(|keg| !keg.explicit && closure.contains(keg.name.as_str()))
+                .collect()
+        }
+    };
+    Ok(kegs)
+}
+
+/// Everything `root` depends on through `edges`, not including `root`.
+fn dependency_closure<'a>(root: &str, edges: &'a [(String, String)]) -> BTreeSet<&'a str> {
+    let mut dependencies: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (name, dependency) in edges {
+        dependencies
+            .entry(name.as_str())
+            .or_default()
+            .push(dependency.as_str());
+    }
+    let mut reached = BTreeSet::new();
+    let mut queue = VecDeque::from([root]);
+    while let Some(current) = queue.pop_front() {
+        for &dependency in dependencies.get(current).into_iter().flatten() {
+            if dependency != root && reached.insert(dependency) {
+                queue.push_back(dependency);
+            }
+        }
+    }
+    reached
+}
+
+impl Installer {
+    /// The installed formulas `filter` selects, sorted by name.
+    pub fn list_filtered(&self, filter: &ListFilter) -> Result<Vec<InstalledKeg>, Error> {
+        filter_installed(
+            self.db.list_installed()?,
+            &self.db.list_dependency_edges()?,
+            filter,
+        )
+    }
+
+    pub fn why(&self, name: &str, all_paths: bool) -> Result<InstallReason, Error> {
+        let installed = self.db.list_installed()?;
+        let target = installed
+            .iter()
+            .find(|keg| keg.name == name)
+            .ok_or_else(|| Error::NotInstalled {
+                name: name.to_string(),
+            })?;
+
+        if target.explicit {
+            return Ok(InstallReason::Explicit);
+        }
+
+        let explicit: BTreeSet<String> = installed
+            .iter()
+            .filter(|keg| keg.explicit)
+            .map(|keg| keg.name.clone())
+            .collect();
+        let edges = self.db.list_dependency_edges()?;
+
+        let paths = if all_paths {
+            all_dependency_paths(name, &explicit, &edges)
+        } else {
+            shortest_dependency_paths(name, &explicit, &edges)
+        };
+
+        if paths.is_empty() {
+            Ok(InstallReason::Unrequired)
+        } else {
+            Ok(InstallReason::Dependency { paths })
+        }
+    }
+}
+
+impl InstallPlan {
+    /// Why each planned dependency is part of the plan. For every formula
+    /// pulled in as a dependency, one chain per planned formula that depends
+    /// on it directly; each chain starts at a requested formula and ends at
+    /// that direct dependent, e.g. `["git", "gettext"]` for `libiconv`.
+    pub fn required_by(&self) -> BTreeMap<String, Vec<Vec<String>>> {
+        let planned: BTreeSet<&str> = self
+            .items
+            .iter()
+            .map(|item| item.install_name.as_str())
+            .collect();
+        let explicit: BTreeSet<String> = self
+            .items
+            .iter()
+            .filter(|item| item.explicit)
+            .map(|item| item.install_name.clone())
+            .collect();
+        let edges: Vec<(String, String)> = self
+            .items
+            .iter()
+            .flat_map(|item| {
+                item.formula
+                    .dependencies
+                    .iter()
+                    .filter(|dep| planned.contains(dep.as_str()))
+                    .map(|dep| (item.install_name.clone(), dep.clone()))
+            })
+            .collect();
+        let dependents = dependents_index(&edges);
+
+        let mut required_by = BTreeMap::new();
+        for item in self.items.iter().filter(|item| !item.explicit) {
+            let chains = dependents
+                .get(item.install_name.as_str())
+                .into_iter()
+                .flatten()
+                .filter_map(|&parent| {
+                    if explicit.contains(parent) {
+                        return Some(vec![parent.to_string()]);
+                    }
+                    shortest_dependency_paths(parent, &explicit, &edges)
+                        .into_iter()
+                        .next()
+                })
+                .collect();
+            required_by.insert(item.install_name.clone(), chains);
+        }
+        required_by
+    }
+}
+
+fn dependents_index(edges: &[(String, String)]) -> BTreeMap<&str, BTreeSet<&str>> {
+    let mut dependents: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (name, dependency) in edges {
+        dependents
+            .entry(dependency.as_str())
+            .or_default()
+            .insert(name.as_str());
+    }
+    dependents
+}
+
+/// For every explicit formula that depends on `target`, the shortest chain
+/// leading to it. Walks the dependency edges backwards from `target` so each
+/// explicit root is reached first along its shortest path.
+pub(crate) fn shortest_dependency_paths(
+    target: &str,
+    explicit: &BTreeSet<String>,
+    edges: &[(String, String)],
+) -> Vec<Vec<String>> {
+    let dependents = dependents_index(edges);
+    let mut next_hop: BTreeMap<&str, &str> = BTreeMap::new();
+    let mut queue = VecDeque::from([target]);
+    let mut paths = Vec::new();
+
+    while let Some(current) = queue.pop_front() {
+        for &parent in dependents.get(current).into_iter().flatten() {
+            if parent == target || next_hop.contains_key(parent) {
+                continue;
+            }
+            next_hop.insert(parent, current);
+
+            if explicit.contains(parent) {
+                let mut path = vec![parent.to_string()];
+                let mut node = parent;
+                while let Some(&child) = next_hop.get(node) {
+                    path.push(child.to_string());
+                    node = child;
+                }
+                paths.push(path);
+            }
+            queue.push_back(parent);
+        }
+    }
+
+    paths.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    paths
+}
+
+/// Every simple chain from an explicit formula down to `target`.
+pub(crate) fn all_dependency_paths(
+    target: &str,
+    explicit: &BTreeSet<String>,
+    edges: &[(String, String)],
+) -> Vec<Vec<String>> {
+    fn walk<'a>(
+        node: &'a str,
+        dependents: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+        explicit: &BTreeSet<String>,
+        chain: &mut Vec<&'a str>,
+        paths: &mut Vec<Vec<String>>,
+    ) {
+        for &parent in dependents.get(node).into_iter().flatten() {
+            if chain.contains(&parent) {
+                continue;
+            }
+            chain.push(parent);
+            if explicit.contains(parent) {
+                paths.push(chain.iter().rev().map(|s| s.to_string()).collect());
+            }
+            walk(parent, dependents, explicit, chain, paths);
+            chain.pop();
+        }
+    }
+
+    let dependents = dependents_index(edges);
+    let mut paths = Vec::new();
+    walk(target, &dependents, explicit, &mut vec![target], &mut paths);
+
+    paths.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edges(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    fn explicit(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn finds_direct_dependency() {
+        let paths = shortest_dependency_paths(
+            "gettext",
+            &explicit(&["git"]),
+            &edges(&[("git", "gettext")]),
+        );
+        assert_eq!(paths, vec![vec!["git", "gettext"]]);
+    }
+
+    #[test]
+    fn reports_one_shortest_path_per_explicit_parent() {
+        let graph = edges(&[
+            ("git", "gettext"),
+            ("wget", "libidn2"),
+            ("libidn2", "gettext"),
+            ("wget", "gettext"),
+        ]);
+
+        let paths = shortest_dependency_paths("gettext", &explicit(&["git", "wget"]), &graph);
+
+        assert_eq!(paths, vec![vec!["git", "gettext"], vec!["wget", "gettext"]]);
+    }
+
+    #[test]
+    fn all_paths_includes_longer_chains_and_multiple_parents() {
+        let graph = edges(&[
+            ("git", "gettext"),
+            ("wget", "libidn2"),
+            ("libidn2", "gettext"),
+            ("wget", "gettext"),
+        ]);
+
+        let paths = all_dependency_paths("gettext", &explicit(&["git", "wget"]), &graph);
+
+        assert_eq!(
+            paths,
+            vec![
+                vec!["git", "gettext"],
+                vec!["wget", "gettext"],
+                vec!["wget", "libidn2", "gettext"],
+            ]
+        );
+    }
+
+    #[test]
+    fn cycles_do_not_loop_forever() {
+        let graph = edges(&[("a", "b"), ("b", "a"), ("root", "a")]);
+
+        assert_eq!(
+            shortest_dependency_paths("b", &explicit(&["root"]), &graph),
+            vec![vec!["root", "a", "b"]]
+        );
+        assert_eq!(
+            all_dependency_paths("b", &explicit(&["root"]), &graph),
+            vec![vec!["root", "a", "b"]]
+        );
+    }
+
+    fn kegs(explicit: &[&str], dependencies: &[&str]) -> Vec<InstalledKeg> {
+        let keg = |name: &str, explicit: bool| InstalledKeg {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            store_key: format!("{name}-key"),
+            installed_at: 0,
+            explicit,
+            copy_strategy: None,
+            license: None,
+            revision: 0,
+            rebuild: None,
+            formula_version: None,
+            pinned: false,
+        };
+        let mut kegs: Vec<_> = explicit
+            .iter()
+            .map(|name| keg(name, true))
+            .chain(dependencies.iter().map(|name| keg(name, false)))
+            .collect();
+        kegs.sort_by(|a, b| a.name.cmp(&b.name));
+        kegs
+    }
+
+    fn names(kegs: &[InstalledKeg]) -> Vec<&str> {
+        kegs.iter().map(|keg| keg.name.as_str()).collect()
+    }
+
+    #[test]
+    fn explicit_and_dependency_filters_split_by_install_reason() {
+        // pcre2 was requested and is also a dependency of git.
+        let installed = kegs(&["git", "pcre2"], &["gettext", "libiconv"]);
+        let graph = edges(&[
+            ("git", "gettext"),
+            ("git", "pcre2"),
+            ("gettext", "libiconv"),
+        ]);
+
+        let explicit = filter_installed(installed.clone(), &graph, &ListFilter::Explicit).unwrap();
+        assert_eq!(names(&explicit), ["git", "pcre2"]);
+        let deps = filter_installed(installed.clone(), &graph, &ListFilter::Dependencies).unwrap();
+        assert_eq!(names(&deps), ["gettext", "libiconv"]);
+        let all = filter_installed(installed, &graph, &ListFilter::All).unwrap();
+        assert_eq!(all.len(), 4);
+    }
+
+    #[test]
+    fn installed_by_follows_edges_transitively_and_skips_requested_formulas() {
+        let installed = kegs(
+            &["git", "wget", "pcre2"],
+            &["gettext", "libiconv", "libidn2"],
+        );
+        let graph = edges(&[
+            ("git", "gettext"),
+            ("git", "pcre2"),
+            ("gettext", "libiconv"),
+            ("wget", "libidn2"),
+            ("libidn2", "gettext"),
+            // Recorded but no longer installed.
+            ("git", "expat"),
+        ]);
+
+        let by_git = filter_installed(
+            installed.clone(),
+            &graph,
+            &ListFilter::InstalledBy("git".into()),
+        )
+        .unwrap();
+        assert_eq!(names(&by_git), ["gettext", "libiconv"]);
+
+        let by_wget = filter_installed(
+            installed.clone(),
+            &graph,
+            &ListFilter::InstalledBy("wget".into()),
+        )
+        .unwrap();
+        assert_eq!(names(&by_wget), ["gettext", "libiconv", "libidn2"]);
+
+        let by_leaf = filter_installed(
+            installed.clone(),
+            &graph,
+            &ListFilter::InstalledBy("pcre2".into()),
+        )
+        .unwrap();
+        assert!(by_leaf.is_empty());
+
+        assert!(matches!(
+            filter_installed(installed, &graph, &ListFilter::InstalledBy("jq".into())),
+            Err(Error::NotInstalled { name }) if name == "jq"
+        ));
+    }
+
+    #[test]
+    fn installed_by_survives_dependency_cycles() {
+        let installed = kegs(&["root"], &["a", "b"]);
+        let graph = edges(&[("root", "a"), ("a", "b"), ("b", "a"), ("b", "root")]);
+
+        let closure =
+            filter_installed(installed, &graph, &ListFilter::InstalledBy("root".into())).unwrap();
+        assert_eq!(names(&closure), ["a", "b"]);
+    }
+
+    #[test]
+    fn no_explicit_parent_yields_no_paths() {
+        let graph = edges(&[("libidn2", "gettext")]);
+        assert!(shortest_dependency_paths("gettext", &explicit(&[]), &graph).is_empty());
+    }
+
+    #[test]
+    fn why_reports_reason_from_database() {
+        use tempfile::TempDir;
+
+        use crate::installer::install::test_support::installer_at;
+        use crate::network::api::ApiClient;
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("zerobrew");
+        let prefix = tmp.path().join("homebrew");
+        let mut installer = installer_at(ApiClient::new(), &root, &prefix, root.join("Cellar"));
+
+        {
+            let tx = installer.db.transaction().unwrap();
+            tx.record_install("git", "2.0", "k1").unwrap();
+            tx.record_install_reason("git", true).unwrap();
+            tx.record_dependencies("git", &["gettext".to_string()])
+                .unwrap();
+            tx.record_install("gettext", "0.22", "k2").unwrap();
+            tx.record_install_reason("gettext", false).unwrap();
+            tx.record_install("orphan", "1.0", "k3").unwrap();
+            tx.record_install_reason("orphan", false).unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert_eq!(
+            installer.why("git", false).unwrap(),
+            InstallReason::Explicit
+        );
+        assert_eq!(
+            installer.why("gettext", false).unwrap(),
+            InstallReason::Dependency {
+                paths: vec![vec!["git".to_string(), "gettext".to_string()]]
+            }
+        );
+        assert_eq!(
+            installer.why("orphan", false).unwrap(),
+            InstallReason::Unrequired
+        );
+        assert!(matches!(
+            installer.why("missing", false),
+            Err(Error::NotInstalled { .. })
+        ));
+    }
+
+    fn planned(
+        name: &str,
+        deps: &[&str],
+        explicit: bool,
+    ) -> crate::installer::install::PlannedInstall {
+        let json = serde_json::json!({
+            "name": name,
+            "versions": { "stable": "1.0" },
+            "dependencies": deps,
+            "bottle": { "stable": { "files": {} } },
+        });
+        crate::installer::install::PlannedInstall {
+            install_name: name.to_string(),
+            formula: zb_core::Formula::from_value(name, json).unwrap(),
+            method: zb_core::InstallMethod::Bottle(zb_core::SelectedBottle {
+                tag: "all".to_string(),
+                url: String::new(),
+                sha256: String::new(),
+            }),
+            explicit,
+        }
+    }
+
+    #[test]
+    fn required_by_chains_end_at_each_direct_dependent() {
+        // git -> gettext -> libiconv, git -> pcre2, and curl -> libiconv
+        // where libiconv is also needed by the already installed zlib.
+        let plan = InstallPlan {
+            items: vec![
+                planned("libiconv", &[], false),
+                planned("pcre2", &[], false),
+                planned("gettext", &["libiconv"], false),
+                planned("git", &["gettext", "pcre2"], true),
+                planned("curl", &["libiconv", "zlib"], true),
+            ],
+            ..Default::default()
+        };
+
+        let required_by = plan.required_by();
+
+        assert_eq!(required_by.len(), 3);
+        assert_eq!(
+            required_by["libiconv"],
+            [
+                vec!["curl".to_string()],
+                vec!["git".into(), "gettext".into()]
+            ]
+        );
+        assert_eq!(required_by["pcre2"], [vec!["git".to_string()]]);
+        assert_eq!(required_by["gettext"], [vec!["git".to_string()]]);
+    }
+}
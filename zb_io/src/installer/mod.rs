@@ -6,5 +6,27 @@ pub use homebrew::{
     HomebrewMigrationPackages, HomebrewPackage, categorize_packages, get_homebrew_packages,
     parse_casks_from_plain_text, parse_formulas_from_json,
 };
+pub use install::advice::InstallAdvice;
+pub use install::bundle::{BundleEntry, BundleEntryState, BundlePlan};
+pub use install::deps::{DependencyGraph, MissingDependency};
 pub use install::doctor::{DiagnosticReport, RepairSummary};
-pub use install::{ExecuteResult, InstallPlan, Installer, OutdatedPackage, create_installer};
+pub use install::gc::{GcOptions, GcReport, Reclaimed};
+pub use install::hooks::{DEFAULT_HOOK_TIMEOUT, HookPoint, Hooks};
+pub use install::prefetch::{
+    INDEX_PREFETCH_BUDGET, IndexPrefetch, index_prefetch_pending, mark_index_prefetch_pending,
+};
+pub use install::relocate::RelocationPlan;
+pub use install::rollback::{DEFAULT_KEEP_VERSIONS, Rollback, VersionRemoval};
+pub use install::runtime::{DEFAULT_RUNTIME_TIMEOUT, RuntimeCheck, RuntimeOutcome};
+pub use install::saved_plan::{SavedInstallMethod, SavedPlan, SavedPlanItem};
+pub use install::search::{SEARCH_INDEX_TTL, SearchResult};
+pub use install::state::InstalledState;
+pub use install::sweep::STALE_TEMP_AGE;
+pub use install::taps::{CORE_TAP, TapChoice};
+pub use install::uninstall::{KegRemoval, PlannedKeg, UninstallOutcome, UninstallPlan};
+pub use install::upgrade::{UpgradeCandidate, UpgradeGroup, UpgradePreview};
+pub use install::why::{InstallReason, ListFilter};
+pub use install::{
+    CachedUpdate, ExecuteResult, FormulaOutcome, InstallPlan, Installer, IsolatedPlan,
+    OutdatedPackage, UncheckedPackage, create_installer,
+};
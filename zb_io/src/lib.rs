@@ -1,25 +1,63 @@
+#[cfg(feature = "materialize")]
 pub mod build;
+#[cfg(feature = "materialize")]
 pub mod cellar;
+#[cfg(feature = "materialize")]
 pub(crate) mod checksum;
 pub mod extraction;
+pub mod fs_probe;
+#[cfg(feature = "materialize")]
 pub mod installer;
+#[cfg(feature = "materialize")]
 pub mod network;
 pub mod path;
+#[cfg(feature = "materialize")]
 pub mod progress;
 pub mod ssl;
 pub mod storage;
 
+#[cfg(feature = "materialize")]
 pub use build::{BuildExecutor, DepInfo};
-pub use cellar::{Cellar, LinkedFile, Linker, MaterializedKeg};
-pub use extraction::extract_tarball;
+#[cfg(feature = "materialize")]
+pub use cellar::{
+    Cellar, EnvValue, LinkClass, LinkDiff, LinkPlan, Linker, Materialized, MaterializedKeg,
+    PlannedLink, WRAPPER_RULES, WrapperRule,
+};
+#[cfg(feature = "materialize")]
+pub use extraction::patch::{
+    MissingLibraries, PatchConfig, PatchFailure, PatchPolicy, PatchReport, PatchRules,
+};
+pub use extraction::{
+    ExtractionBudget, ExtractionLimits, ExtractionStats, PathLimits, extract_tarball,
+};
+pub use fs_probe::{FsCapabilities, probe_filesystem, same_filesystem};
+#[cfg(feature = "materialize")]
 pub use installer::{
-    DiagnosticReport, ExecuteResult, HomebrewMigrationPackages, HomebrewPackage, InstallPlan,
-    Installer, OutdatedPackage, RepairSummary, create_installer, get_homebrew_packages,
+    BundleEntry, BundleEntryState, BundlePlan, CORE_TAP, CachedUpdate, DEFAULT_HOOK_TIMEOUT,
+    DEFAULT_KEEP_VERSIONS, DEFAULT_RUNTIME_TIMEOUT, DependencyGraph, DiagnosticReport,
+    ExecuteResult, FormulaOutcome, GcOptions, GcReport, HomebrewMigrationPackages, HomebrewPackage,
+    HookPoint, Hooks, INDEX_PREFETCH_BUDGET, IndexPrefetch, InstallAdvice, InstallPlan,
+    InstallReason, InstalledState, Installer, IsolatedPlan, KegRemoval, ListFilter,
+    MissingDependency, OutdatedPackage, Reclaimed, RelocationPlan, RepairSummary, Rollback,
+    RuntimeCheck, RuntimeOutcome, SEARCH_INDEX_TTL, STALE_TEMP_AGE, SavedPlan, SearchResult,
+    TapChoice, UncheckedPackage, UninstallPlan, UpgradeCandidate, UpgradeGroup, UpgradePreview,
+    VersionRemoval, create_installer, get_homebrew_packages, index_prefetch_pending,
+    mark_index_prefetch_pending,
 };
+#[cfg(feature = "materialize")]
 pub use network::{
-    ApiCache, ApiClient, DownloadProgressCallback, DownloadRequest, Downloader, ParallelDownloader,
+    ApiCache, ApiClient, BlobSource, ConnectionStats, DownloadProgressCallback, DownloadRequest,
+    Downloader, FormulaSnapshot, HttpClient, MatchKind, ParallelDownloader, RegistryCredentials,
+    SearchEntry, SearchIndex,
 };
 pub use path::validate_privileged_path;
-pub use progress::{InstallProgress, ProgressCallback};
+#[cfg(feature = "materialize")]
+pub use progress::{
+    InstallProgress, MaterializeObserver, NoopObserver, PatchCounter, Phase, ProgressCallback,
+};
 pub use ssl::{find_ca_bundle_from_prefix, find_ca_dir};
-pub use storage::{BlobCache, Database, InstalledKeg, KegFileRecord, Store, StoreRef};
+pub use storage::{
+    BlobCache, BlobEntry, CopyStrategy, Database, DriftedKeg, HistoryEvent, IndexRebuild,
+    InstalledKeg, InstalledSet, KegFileKind, KegFileRecord, LinkedFile, RelocationState,
+    RetainedKeg, Store, StoreArchive, StoreKey, StoreRef, SystemRecord, UpstreamStatus,
+};
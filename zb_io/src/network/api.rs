@@ -1,7 +1,12 @@
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
 use crate::checksum::verify_sha256_bytes;
 use crate::network::cache::{ApiCache, CacheEntry};
+use crate::network::client::{ConnectionStats, HttpClient};
+use crate::network::failure::{request_error, status_error};
+use crate::network::snapshot::{FormulaSnapshot, index_entry_name};
 use crate::network::suggest::rank_formula_suggestions;
 use crate::network::tap_formula::{parse_tap_formula_ref, parse_tap_formula_ruby};
 use futures_util::stream::{self, StreamExt};
@@ -67,14 +72,23 @@ struct FormulaSuggestionEntry {
     oldnames: Vec<String>,
 }
 
+/// Names from the bulk formula index, plus a lookup from aliases and old
+/// names to their canonical formula.
+#[derive(Debug, Default)]
+struct FormulaIndex {
+    candidates: Vec<String>,
+    aliases: std::collections::HashMap<String, String>,
+}
+
 #[derive(Debug)]
 pub struct ApiClient {
     base_url: String,
     cask_base_url: String,
     tap_raw_base_url: String,
-    client: reqwest::Client,
+    client: HttpClient,
     cache: Option<ApiCache>,
-    formula_candidates: RwLock<Option<Arc<[String]>>>,
+    formula_index: RwLock<Option<Arc<FormulaIndex>>>,
+    snapshot: Option<Arc<FormulaSnapshot>>,
 }
 
 impl ApiClient {
@@ -107,19 +121,14 @@ impl ApiClient {
     }
 
     fn build_client(base_url: String) -> Self {
-        let client = reqwest::Client::builder()
-            .user_agent("zerobrew/0.1")
-            .pool_max_idle_per_host(20)
-            .build()
-            .unwrap_or_else(|_| reqwest::Client::new());
-
         Self {
             base_url,
             cask_base_url: "https://formulae.brew.sh/api/cask".to_string(),
             tap_raw_base_url: "https://raw.githubusercontent.com".to_string(),
-            client,
+            client: HttpClient::new(),
             cache: None,
-            formula_candidates: RwLock::new(None),
+            formula_index: RwLock::new(None),
+            snapshot: None,
         }
     }
 
@@ -135,11 +144,33 @@ impl ApiClient {
         self
     }
 
+    /// Fetch through `client`, sharing its connection pool with whatever
+    /// else uses it (normally the bottle downloader).
+    pub fn with_http_client(mut self, client: HttpClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub(crate) fn http_client(&self) -> &HttpClient {
+        &self.client
+    }
+
+    /// Requests and connections made through this client's pool so far.
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.client.stats()
+    }
+
     pub fn with_cache(mut self, cache: ApiCache) -> Self {
         self.cache = Some(cache);
         self
     }
 
+    /// Resolve all formula metadata from `snapshot` instead of the network.
+    pub fn with_snapshot(mut self, snapshot: FormulaSnapshot) -> Self {
+        self.snapshot = Some(Arc::new(snapshot));
+        self
+    }
+
     /// Clear all cached API responses. Returns the number removed.
     pub fn clear_cache(&self) -> Result<usize, Error> {
         match &self.cache {
@@ -150,6 +181,16 @@ impl ApiClient {
         }
     }
 
+    /// Number of cached API responses and the bytes they hold.
+    pub fn cache_usage(&self) -> Result<(usize, u64), Error> {
+        match &self.cache {
+            Some(cache) => cache
+                .usage()
+                .map_err(Error::store("failed to read API cache")),
+            None => Ok((0, 0)),
+        }
+    }
+
     pub async fn fetch_formula_rb(
         &self,
         ruby_source_path: &str,
@@ -189,12 +230,10 @@ impl ApiClient {
             .get(url)
             .send()
             .await
-            .map_err(Error::network("failed to fetch formula rb"))?;
+            .map_err(|e| request_error(&e, url))?;
 
         if !response.status().is_success() {
-            return Err(Error::NetworkFailure {
-                message: format!("formula rb fetch returned HTTP {}", response.status()),
-            });
+            return Err(status_error(response.status(), url));
         }
 
         let body = response
@@ -247,9 +286,7 @@ impl ApiClient {
             }
         }
 
-        let response = request.send().await.map_err(|e| Error::NetworkFailure {
-            message: e.to_string(),
-        })?;
+        let response = request.send().await.map_err(|e| request_error(&e, url))?;
 
         if response.status() == reqwest::StatusCode::NOT_MODIFIED
             && let Some(entry) = cached_entry
@@ -278,6 +315,10 @@ impl ApiClient {
     }
 
     pub async fn get_formula(&self, name: &str) -> Result<Formula, Error> {
+        if let Some(snapshot) = &self.snapshot {
+            return snapshot.formula(name);
+        }
+
         if let Some(spec) = parse_tap_formula_ref(name) {
             return self.get_tap_formula(&spec).await;
         }
@@ -293,9 +334,7 @@ impl ApiClient {
                     });
                 }
                 if !response.status().is_success() {
-                    return Err(Error::NetworkFailure {
-                        message: format!("HTTP {}", response.status()),
-                    });
+                    return Err(status_error(response.status(), &url));
                 }
 
                 let etag = response
@@ -319,19 +358,21 @@ impl ApiClient {
             }
         };
 
-        serde_json::from_str(&body).map_err(Error::network("failed to parse formula JSON"))
+        Formula::from_json(name, &body)
     }
 
     pub async fn get_all_formulas_raw(&self) -> Result<String, Error> {
+        if let Some(snapshot) = &self.snapshot {
+            return Ok(snapshot.raw_index().to_string());
+        }
+
         let url = format!("{}.json", self.base_url);
 
         match self.cached_get(&url).await? {
             CachedGetResult::Cached(body) => Ok(body),
             CachedGetResult::Fresh(response) => {
                 if !response.status().is_success() {
-                    return Err(Error::NetworkFailure {
-                        message: format!("bulk formula fetch returned HTTP {}", response.status()),
-                    });
+                    return Err(status_error(response.status(), &url));
                 }
 
                 let etag = response
@@ -356,6 +397,60 @@ impl ApiClient {
         }
     }
 
+    /// Metadata for `names` as last cached, with when it was fetched, without
+    /// touching the network. A formula's own response wins over the bulk
+    /// index when it is the more recent of the two; names with neither, and
+    /// tap formulas, are left out.
+    pub fn cached_formulas(&self, names: &[&str]) -> HashMap<String, (Formula, SystemTime)> {
+        let mut found = HashMap::new();
+        let Some(cache) = &self.cache else {
+            return found;
+        };
+
+        let bulk = cache
+            .get_with_time(&format!("{}.json", self.base_url))
+            .and_then(|(entry, fetched)| {
+                let values: Vec<serde_json::Value> = serde_json::from_str(&entry.body).ok()?;
+                Some((values, fetched))
+            });
+        if let Some((values, fetched)) = bulk {
+            for value in values {
+                let Ok(name) = index_entry_name(&value) else {
+                    continue;
+                };
+                if !names.contains(&name.as_str()) {
+                    continue;
+                }
+                if let Ok(formula) = Formula::from_value(&name, value) {
+                    found.insert(name, (formula, fetched));
+                }
+            }
+        }
+
+        for name in names
+            .iter()
+            .filter(|name| parse_tap_formula_ref(name).is_none())
+        {
+            let Some((entry, fetched)) =
+                cache.get_with_time(&format!("{}/{}.json", self.base_url, name))
+            else {
+                continue;
+            };
+            if found
+                .get(*name)
+                .is_some_and(|(_, bulk_fetched)| *bulk_fetched >= fetched)
+            {
+                continue;
+            }
+            if let Ok(formula) = Formula::from_json(name, &entry.body) {
+                found.insert(name.to_string(), (formula, fetched));
+            }
+        }
+        found
+    }
+
+    /// Rank likely matches for a formula name that was not found. An exact
+    /// alias or old-name hit is returned first, ahead of fuzzy matches.
     pub async fn suggest_formulas(&self, query: &str, limit: usize) -> Result<Vec<String>, Error> {
         if limit == 0 || query.trim().is_empty() {
             return Ok(Vec::new());
@@ -365,45 +460,72 @@ impl ApiClient {
             return Ok(Vec::new());
         }
 
-        let candidates = self.formula_candidates().await?;
-        Ok(rank_formula_suggestions(query, &candidates, limit))
+        let index = self.formula_index().await?;
+        let query = query.trim();
+        let mut suggestions = Vec::with_capacity(limit);
+        if let Some(canonical) = index.aliases.get(&query.to_ascii_lowercase()) {
+            suggestions.push(canonical.clone());
+        }
+
+        for candidate in rank_formula_suggestions(query, &index.candidates, limit + 1) {
+            if suggestions.len() >= limit {
+                break;
+            }
+            if candidate.eq_ignore_ascii_case(query) || suggestions.contains(&candidate) {
+                continue;
+            }
+            suggestions.push(candidate);
+        }
+
+        Ok(suggestions)
+    }
+
+    /// The canonical formula an alias or old name refers to, from the bulk
+    /// index: `python` -> `python@3.13`.
+    pub async fn resolve_alias(&self, name: &str) -> Result<Option<String>, Error> {
+        let index = self.formula_index().await?;
+        Ok(index.aliases.get(&name.to_ascii_lowercase()).cloned())
     }
 
-    async fn formula_candidates(&self) -> Result<Arc<[String]>, Error> {
-        if let Some(candidates) = self.formula_candidates.read().ok().and_then(|c| c.clone()) {
-            return Ok(candidates);
+    async fn formula_index(&self) -> Result<Arc<FormulaIndex>, Error> {
+        if let Some(index) = self.formula_index.read().ok().and_then(|c| c.clone()) {
+            return Ok(index);
         }
 
         let raw = self.get_all_formulas_raw().await?;
-        let candidates: Arc<[String]> = Self::extract_formula_candidates(&raw)?.into();
-        if let Ok(mut cached) = self.formula_candidates.write() {
-            *cached = Some(Arc::clone(&candidates));
+        let index = Arc::new(Self::extract_formula_index(&raw)?);
+        if let Ok(mut cached) = self.formula_index.write() {
+            *cached = Some(Arc::clone(&index));
         }
-        Ok(candidates)
+        Ok(index)
     }
 
-    fn extract_formula_candidates(raw: &str) -> Result<Vec<String>, Error> {
+    fn extract_formula_index(raw: &str) -> Result<FormulaIndex, Error> {
         use std::collections::HashSet;
 
         let entries: Vec<FormulaSuggestionEntry> = serde_json::from_str(raw)
             .map_err(Error::network("failed to parse bulk formula JSON"))?;
 
         let mut seen = HashSet::new();
-        let mut candidates = Vec::new();
+        let mut index = FormulaIndex::default();
 
         for entry in entries {
-            Self::push_candidate(&mut candidates, &mut seen, entry.name.as_deref());
-
-            for alias in &entry.aliases {
-                Self::push_candidate(&mut candidates, &mut seen, Some(alias.as_str()));
-            }
-
-            for oldname in &entry.oldnames {
-                Self::push_candidate(&mut candidates, &mut seen, Some(oldname.as_str()));
+            Self::push_candidate(&mut index.candidates, &mut seen, entry.name.as_deref());
+
+            for alias in entry.aliases.iter().chain(&entry.oldnames) {
+                Self::push_candidate(&mut index.candidates, &mut seen, Some(alias.as_str()));
+                if let Some(name) = entry.name.as_deref().map(str::trim)
+                    && !name.is_empty()
+                {
+                    index
+                        .aliases
+                        .entry(alias.trim().to_ascii_lowercase())
+                        .or_insert_with(|| name.to_string());
+                }
             }
         }
 
-        Ok(candidates)
+        Ok(index)
     }
 
     fn push_candidate(
@@ -424,16 +546,45 @@ impl ApiClient {
         }
     }
 
+    /// Capture the bulk index plus `names` and their dependency closure.
+    pub async fn create_snapshot(&self, names: &[String]) -> Result<FormulaSnapshot, Error> {
+        let index = self.get_all_formulas_raw().await?;
+
+        let mut formulas = std::collections::BTreeMap::new();
+        let mut pending: Vec<String> = names.to_vec();
+        while let Some(name) = pending.pop() {
+            if formulas.contains_key(&name) {
+                continue;
+            }
+            let formula = self.get_formula(&name).await?;
+            pending.extend(
+                formula
+                    .dependencies
+                    .iter()
+                    .filter(|dep| !formulas.contains_key(*dep))
+                    .cloned(),
+            );
+            formulas.insert(name, formula);
+        }
+
+        FormulaSnapshot::new(index, formulas)
+    }
+
     pub async fn get_cask(&self, token: &str) -> Result<serde_json::Value, Error> {
+        if self.snapshot.is_some() {
+            return Err(Error::UnsupportedFormula {
+                name: format!("cask:{token}"),
+                reason: "casks cannot be resolved from a formula snapshot".to_string(),
+            });
+        }
+
         let url = format!("{}/{}.json", self.cask_base_url, token);
         let response = self
             .client
             .get(&url)
             .send()
             .await
-            .map_err(|e| Error::NetworkFailure {
-                message: e.to_string(),
-            })?;
+            .map_err(|e| request_error(&e, &url))?;
 
         if response.status() == reqwest::StatusCode::NOT_FOUND {
             return Err(Error::MissingFormula {
@@ -442,9 +593,7 @@ impl ApiClient {
         }
 
         if !response.status().is_success() {
-            return Err(Error::NetworkFailure {
-                message: format!("HTTP {}", response.status()),
-            });
+            return Err(status_error(response.status(), &url));
         }
 
         response
@@ -517,9 +666,7 @@ impl ApiClient {
                             last_status = Some(status);
                         }
                         Err(e) => {
-                            last_network_error = Some(Error::NetworkFailure {
-                                message: e.to_string(),
-                            });
+                            last_network_error = Some(request_error(&e, &url));
                         }
                     }
                 }
@@ -1199,7 +1346,8 @@ end
             {"name":"ripgrep","aliases":["rg"]}
         ]"#;
 
-        let candidates = ApiClient::extract_formula_candidates(bulk).unwrap();
+        let index = ApiClient::extract_formula_index(bulk).unwrap();
+        let candidates = &index.candidates;
         assert!(candidates.contains(&"python".to_string()));
         assert!(candidates.contains(&"python@3.13".to_string()));
         assert!(candidates.contains(&"python3".to_string()));
@@ -1228,6 +1376,43 @@ end
         assert_eq!(suggestions.first().map(String::as_str), Some("python"));
     }
 
+    #[test]
+    fn extract_formula_index_maps_aliases_to_canonical_names() {
+        let bulk = r#"[
+            {"name":"python","aliases":["python@3.13"],"oldnames":["python3"]},
+            {"name":"ripgrep","aliases":["rg"]}
+        ]"#;
+
+        let index = ApiClient::extract_formula_index(bulk).unwrap();
+        assert_eq!(index.aliases.get("rg").map(String::as_str), Some("ripgrep"));
+        assert_eq!(
+            index.aliases.get("python3").map(String::as_str),
+            Some("python")
+        );
+        assert!(!index.aliases.contains_key("python"));
+    }
+
+    #[tokio::test]
+    async fn suggest_formulas_puts_alias_target_first() {
+        let mock_server = MockServer::start().await;
+        let bulk = r#"[
+            {"name":"ripgrep","aliases":["rg"]},
+            {"name":"rgb"}
+        ]"#;
+
+        Mock::given(method("GET"))
+            .and(path("/formula.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(bulk))
+            .mount(&mock_server)
+            .await;
+
+        let client = ApiClient::with_base_url(format!("{}/formula", mock_server.uri())).unwrap();
+        let suggestions = client.suggest_formulas("rg", 3).await.unwrap();
+
+        assert_eq!(suggestions.first().map(String::as_str), Some("ripgrep"));
+        assert!(!suggestions.contains(&"rg".to_string()));
+    }
+
     #[tokio::test]
     async fn suggest_formulas_reuses_cached_candidates_across_calls() {
         let mock_server = MockServer::start().await;
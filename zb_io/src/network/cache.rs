@@ -1,5 +1,6 @@
 use rusqlite::{Connection, params};
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub struct ApiCache {
     conn: Connection,
@@ -100,22 +101,64 @@ impl ApiCache {
             .ok()
     }
 
-    /// Clear all cached entries. Returns the number of entries removed.
+    /// Like [`ApiCache::get`], also returning when the entry was fetched.
+    pub fn get_with_time(&self, url: &str) -> Option<(CacheEntry, SystemTime)> {
+        self.conn
+            .query_row(
+                "SELECT etag, last_modified, body, cached_at FROM api_cache WHERE url = ?1",
+                params![url],
+                |row| {
+                    let entry = CacheEntry {
+                        etag: row.get(0)?,
+                        last_modified: row.get(1)?,
+                        body: row.get(2)?,
+                    };
+                    let cached_at =
+                        UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(3)?.max(0) as u64);
+                    Ok((entry, cached_at))
+                },
+            )
+            .ok()
+    }
+
+    /// Clear all cached entries and give their space back to the file
+    /// system. Returns the number of entries removed.
     pub fn clear(&self) -> Result<usize, rusqlite::Error> {
         let removed = self.conn.execute("DELETE FROM api_cache", [])?;
+        self.conn.execute_batch("VACUUM")?;
         Ok(removed)
     }
 
+    /// Number of cached responses and the bytes they hold.
+    pub fn usage(&self) -> Result<(usize, u64), rusqlite::Error> {
+        self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(LENGTH(url) + LENGTH(body)
+                 + COALESCE(LENGTH(etag), 0) + COALESCE(LENGTH(last_modified), 0)), 0)
+             FROM api_cache",
+            [],
+            |row| Ok((row.get::<_, i64>(0)? as usize, row.get::<_, i64>(1)? as u64)),
+        )
+    }
+
     pub fn put(&self, url: &str, entry: &CacheEntry) -> Result<(), rusqlite::Error> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
+        self.put_at(url, entry, SystemTime::now())
+    }
+
+    pub(crate) fn put_at(
+        &self,
+        url: &str,
+        entry: &CacheEntry,
+        cached_at: SystemTime,
+    ) -> Result<(), rusqlite::Error> {
+        let cached_at = cached_at
+            .duration_since(UNIX_EPOCH)
             .map(|d| d.as_secs() as i64)
             .unwrap_or(0);
 
         self.conn.execute(
             "INSERT OR REPLACE INTO api_cache (url, etag, last_modified, body, cached_at)
              VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![url, entry.etag, entry.last_modified, entry.body, now],
+            params![url, entry.etag, entry.last_modified, entry.body, cached_at],
         )?;
         Ok(())
     }
@@ -142,6 +185,24 @@ mod tests {
         assert_eq!(retrieved.body, r#"{"name":"foo"}"#);
     }
 
+    #[test]
+    fn get_with_time_returns_fetch_time() {
+        let cache = ApiCache::in_memory().unwrap();
+        let entry = CacheEntry {
+            etag: None,
+            last_modified: None,
+            body: "{}".to_string(),
+        };
+        let fetched = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        cache
+            .put_at("https://example.com/a.json", &entry, fetched)
+            .unwrap();
+
+        let (_, cached_at) = cache.get_with_time("https://example.com/a.json").unwrap();
+        assert_eq!(cached_at, fetched);
+        assert!(cache.get_with_time("https://example.com/b.json").is_none());
+    }
+
     #[test]
     fn returns_none_for_missing_entry() {
         let cache = ApiCache::in_memory().unwrap();
@@ -158,9 +219,11 @@ mod tests {
         };
         cache.put("https://example.com/a.json", &entry).unwrap();
         cache.put("https://example.com/b.json", &entry).unwrap();
+        assert_eq!(cache.usage().unwrap(), (2, 56));
 
         let removed = cache.clear().unwrap();
         assert_eq!(removed, 2);
+        assert_eq!(cache.usage().unwrap(), (0, 0));
         assert!(cache.get("https://example.com/a.json").is_none());
         assert!(cache.get("https://example.com/b.json").is_none());
     }
@@ -0,0 +1,226 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::failure::ca_bundle_certificates;
+
+/// Idle pooled connections are kept this long, so the gaps between one
+/// bottle and the next (extraction, linking) don't cost a new handshake.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// HTTP/2 pings keep idle connections alive through NATs and load balancers
+/// for the same reason.
+const HTTP2_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many requests went out and how many connections they needed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub requests: u64,
+    pub connections_opened: u64,
+}
+
+impl ConnectionStats {
+    /// Requests served over a connection that was already open.
+    pub fn reused(&self) -> u64 {
+        self.requests.saturating_sub(self.connections_opened)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: AtomicU64,
+    connections_opened: AtomicU64,
+}
+
+/// One pooled HTTP client for everything an install fetches. Formula
+/// metadata and bottles come from a couple of hosts, so sharing the pool
+/// (and HTTP/2 multiplexing where the server offers it) saves a TLS
+/// handshake per request. Clones share the pool and the counters.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    counters: Arc<Counters>,
+}
+
+impl HttpClient {
+    pub fn new() -> Self {
+        let counters = Arc::new(Counters::default());
+        let client = reqwest::Client::builder()
+            .user_agent("zerobrew/0.1")
+            .pool_max_idle_per_host(20)
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .tcp_nodelay(true)
+            .tcp_keepalive(Duration::from_secs(60))
+            .connect_timeout(Duration::from_secs(30))
+            .timeout(Duration::from_secs(300))
+            .http2_adaptive_window(true)
+            .http2_initial_stream_window_size(Some(2 * 1024 * 1024))
+            .http2_initial_connection_window_size(Some(4 * 1024 * 1024))
+            .http2_keep_alive_interval(HTTP2_KEEP_ALIVE_INTERVAL)
+            .http2_keep_alive_while_idle(true)
+            .tls_certs_merge(ca_bundle_certificates())
+            .connector_layer(CountConnections(counters.clone()))
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+
+        Self { client, counters }
+    }
+
+    pub fn get<U: reqwest::IntoUrl>(&self, url: U) -> reqwest::RequestBuilder {
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        self.client.get(url)
+    }
+
+    pub fn head<U: reqwest::IntoUrl>(&self, url: U) -> reqwest::RequestBuilder {
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        self.client.head(url)
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            requests: self.counters.requests.load(Ordering::Relaxed),
+            connections_opened: self.counters.connections_opened.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Connector layer that counts every connection the pool had to open.
+#[derive(Clone)]
+struct CountConnections(Arc<Counters>);
+
+impl<S> Layer<S> for CountConnections {
+    type Service = CountedConnector<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CountedConnector {
+            inner,
+            counters: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CountedConnector<S> {
+    inner: S,
+    counters: Arc<Counters>,
+}
+
+impl<S, R> Service<R> for CountedConnector<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let counters = self.counters.clone();
+        let connecting = self.inner.call(request);
+        Box::pin(async move {
+            let connection = connecting.await?;
+            counters.connections_opened.fetch_add(1, Ordering::Relaxed);
+            Ok(connection)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::api::ApiClient;
+    use crate::network::download::Downloader;
+    use crate::storage::blob::BlobCache;
+    use sha2::{Digest, Sha256};
+    use tempfile::TempDir;
+    use tokio::net::{TcpListener, TcpStream};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Forwards TCP connections to `upstream`, counting how many it accepts.
+    async fn counting_proxy(upstream: std::net::SocketAddr) -> (String, Arc<AtomicU64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicU64::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((mut inbound, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut outbound = TcpStream::connect(upstream).await.unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                });
+            }
+        });
+        (format!("http://{addr}"), accepted)
+    }
+
+    #[tokio::test]
+    async fn metadata_and_downloads_reuse_pooled_connections() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/formula/foo.json"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../../../zb_core/fixtures/formula_foo.json")),
+            )
+            .mount(&server)
+            .await;
+
+        let mut bottles = Vec::new();
+        for i in 0..20 {
+            let body = format!("bottle {i}").into_bytes();
+            let sha256 = format!("{:x}", Sha256::digest(&body));
+            Mock::given(path(format!("/bottles/{i}.tar.gz")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(body))
+                .mount(&server)
+                .await;
+            bottles.push((i, sha256));
+        }
+
+        let (proxy, accepted) = counting_proxy(*server.address()).await;
+        let http = HttpClient::new();
+        let api = ApiClient::with_base_url(format!("{proxy}/formula"))
+            .unwrap()
+            .with_http_client(http.clone());
+        let tmp = TempDir::new().unwrap();
+        let downloader =
+            Downloader::new(BlobCache::new(tmp.path()).unwrap()).with_http_client(http.clone());
+
+        for (i, sha256) in &bottles {
+            api.get_formula("foo").await.unwrap();
+            downloader
+                .download(&format!("{proxy}/bottles/{i}.tar.gz"), sha256)
+                .await
+                .unwrap();
+        }
+
+        let stats = http.stats();
+        let accepted = accepted.load(Ordering::SeqCst);
+        assert!(stats.requests >= 60, "{stats:?}");
+        assert_eq!(stats.connections_opened, accepted);
+        assert!(
+            accepted <= 4,
+            "{accepted} connections for {} requests",
+            stats.requests
+        );
+        assert_eq!(stats.reused(), stats.requests - accepted);
+        assert_eq!(api.connection_stats(), stats);
+    }
+}
@@ -9,7 +9,11 @@ use tokio::sync::RwLock;
 
 use zb_core::Error;
 
-use super::MAX_CHUNK_RETRIES;
+use super::credentials::RegistryCredentials;
+use super::rate_limit::{MAX_RATE_LIMIT_RETRIES, RateLimiter};
+use super::{DownloadProgressCallback, MAX_CHUNK_RETRIES};
+use crate::network::client::HttpClient;
+use crate::network::failure::{is_retryable, request_error, status_error};
 
 pub(crate) fn bearer_header(token: &str) -> Result<HeaderValue, Error> {
     HeaderValue::from_str(&format!("Bearer {token}")).map_err(|_| Error::NetworkFailure {
@@ -17,9 +21,17 @@ pub(crate) fn bearer_header(token: &str) -> Result<HeaderValue, Error> {
     })
 }
 
+/// How long a bearer token is reused when the registry doesn't say.
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(240);
+
+/// Tokens are dropped this long before the registry says they expire.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
 #[derive(Deserialize)]
 struct TokenResponse {
+    #[serde(alias = "access_token")]
     token: String,
+    expires_in: Option<u64>,
 }
 
 pub(crate) struct CachedToken {
@@ -27,48 +39,109 @@ pub(crate) struct CachedToken {
     pub(crate) expires_at: Instant,
 }
 
-pub(crate) type TokenCache = Arc<RwLock<HashMap<String, CachedToken>>>;
+/// Bearer tokens per repository scope, kept for the rest of the run, and
+/// the registry credentials used to obtain them.
+#[derive(Clone, Default)]
+pub(crate) struct TokenCache {
+    tokens: Arc<RwLock<HashMap<String, CachedToken>>>,
+    credentials: Arc<RegistryCredentials>,
+}
+
+impl TokenCache {
+    pub(crate) fn new(credentials: RegistryCredentials) -> Self {
+        Self {
+            tokens: Arc::default(),
+            credentials: Arc::new(credentials),
+        }
+    }
+}
 
 pub(crate) async fn fetch_download_response_internal(
-    client: &reqwest::Client,
+    client: &HttpClient,
     token_cache: &TokenCache,
+    rate_limiter: &RateLimiter,
     url: &str,
+    progress: Option<&DownloadProgressCallback>,
 ) -> Result<reqwest::Response, Error> {
-    let cached_token = get_cached_token_for_url_internal(token_cache, url).await;
+    let mut rate_limited = 0;
+    loop {
+        rate_limiter.wait_for(url).await;
+        let cached_token = get_cached_token_for_url_internal(token_cache, url).await;
 
-    let mut request = client.get(url);
-    if let Some(token) = &cached_token {
-        request = request.header(AUTHORIZATION, bearer_header(token)?);
-    }
+        let mut request = client.get(url);
+        if let Some(token) = &cached_token {
+            request = request.header(AUTHORIZATION, bearer_header(token)?);
+        }
 
-    let response = request.send().await.map_err(|e| Error::NetworkFailure {
-        message: e.to_string(),
-    })?;
+        let response = request.send().await.map_err(|e| request_error(&e, url))?;
 
-    let response = if response.status() == StatusCode::UNAUTHORIZED {
-        handle_auth_challenge_internal(client, token_cache, url, response).await?
-    } else {
-        response
-    };
+        let response = if response.status() == StatusCode::UNAUTHORIZED {
+            handle_auth_challenge_internal(client, token_cache, url, response).await?
+        } else {
+            response
+        };
 
-    if !response.status().is_success() {
-        return Err(Error::NetworkFailure {
-            message: format!("HTTP {}", response.status()),
-        });
+        if rate_limiter.check(url, response.status(), response.headers(), progress)?
+            && rate_limited < MAX_RATE_LIMIT_RETRIES
+        {
+            rate_limited += 1;
+            continue;
+        }
+
+        if !response.status().is_success() {
+            return Err(status_error(response.status(), url));
+        }
+
+        return Ok(response);
     }
+}
 
-    Ok(response)
+/// A HEAD request for `url`, authenticated like a download: with the cached
+/// token for its scope, or with a fresh one if the registry challenges the
+/// request. Unlike a download, any other status is returned as is.
+pub(crate) async fn fetch_head_response_internal(
+    client: &HttpClient,
+    token_cache: &TokenCache,
+    rate_limiter: &RateLimiter,
+    url: &str,
+) -> Result<reqwest::Response, Error> {
+    let head = async |token: Option<String>| {
+        let mut request = client.head(url);
+        if let Some(token) = &token {
+            request = request.header(AUTHORIZATION, bearer_header(token)?);
+        }
+        request.send().await.map_err(|e| request_error(&e, url))
+    };
+
+    rate_limiter.wait_for(url).await;
+    let cached_token = get_cached_token_for_url_internal(token_cache, url).await;
+    let response = head(cached_token).await?;
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+    let Some(challenge) = response
+        .headers()
+        .get(WWW_AUTHENTICATE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Ok(response);
+    };
+    let token = fetch_bearer_token_internal(client, token_cache, url, challenge).await?;
+    head(Some(token)).await
 }
 
 pub(crate) async fn fetch_range_response_internal(
-    client: &reqwest::Client,
+    client: &HttpClient,
     token_cache: &TokenCache,
+    rate_limiter: &RateLimiter,
     url: &str,
     range: &str,
+    progress: Option<&DownloadProgressCallback>,
 ) -> Result<reqwest::Response, Error> {
     let mut last_error = None;
 
     for attempt in 0..=MAX_CHUNK_RETRIES {
+        rate_limiter.wait_for(url).await;
         let cached_token = get_cached_token_for_url_internal(token_cache, url).await;
 
         let mut request = client.get(url).header("Range", range);
@@ -88,11 +161,16 @@ pub(crate) async fn fetch_range_response_internal(
                 };
 
                 if !response.status().is_success() {
-                    let err = Error::NetworkFailure {
-                        message: format!("HTTP {}", response.status()),
-                    };
+                    let err = status_error(response.status(), url);
 
-                    if response.status().is_server_error() && attempt < MAX_CHUNK_RETRIES {
+                    // A rate limit pauses the host; the next attempt waits it out.
+                    if rate_limiter.check(url, response.status(), response.headers(), progress)?
+                        && attempt < MAX_CHUNK_RETRIES
+                    {
+                        last_error = Some(err);
+                        continue;
+                    }
+                    if is_retryable(&err) && attempt < MAX_CHUNK_RETRIES {
                         last_error = Some(err);
                         tokio::time::sleep(Duration::from_millis(100 * (1 << attempt))).await;
                         continue;
@@ -103,9 +181,11 @@ pub(crate) async fn fetch_range_response_internal(
                 return Ok(response);
             }
             Err(e) => {
-                last_error = Some(Error::NetworkFailure {
-                    message: e.to_string(),
-                });
+                let err = request_error(&e, url);
+                if !is_retryable(&err) {
+                    return Err(err);
+                }
+                last_error = Some(err);
 
                 if attempt < MAX_CHUNK_RETRIES {
                     tokio::time::sleep(Duration::from_millis(100 * (1 << attempt))).await;
@@ -125,7 +205,7 @@ pub(crate) async fn get_cached_token_for_url_internal(
     url: &str,
 ) -> Option<String> {
     let scope = extract_scope_for_url(url)?;
-    let cache = token_cache.read().await;
+    let cache = token_cache.tokens.read().await;
     let now = Instant::now();
 
     cache
@@ -135,7 +215,7 @@ pub(crate) async fn get_cached_token_for_url_internal(
 }
 
 pub(crate) async fn handle_auth_challenge_internal(
-    client: &reqwest::Client,
+    client: &HttpClient,
     token_cache: &TokenCache,
     url: &str,
     response: reqwest::Response,
@@ -155,16 +235,14 @@ pub(crate) async fn handle_auth_challenge_internal(
         }
     };
 
-    let token = fetch_bearer_token_internal(client, token_cache, www_auth).await?;
+    let token = fetch_bearer_token_internal(client, token_cache, url, www_auth).await?;
 
     let response = client
         .get(url)
         .header(AUTHORIZATION, bearer_header(&token)?)
         .send()
         .await
-        .map_err(|e| Error::NetworkFailure {
-            message: e.to_string(),
-        })?;
+        .map_err(|e| request_error(&e, url))?;
 
     if response.status() == StatusCode::UNAUTHORIZED {
         return Err(Error::NetworkFailure {
@@ -175,15 +253,19 @@ pub(crate) async fn handle_auth_challenge_internal(
     Ok(response)
 }
 
+/// Exchanges a `WWW-Authenticate` challenge from the registry serving `url`
+/// for a bearer token: anonymously, or with the registry's configured
+/// credentials. Neither the credentials nor the token are ever logged.
 pub(crate) async fn fetch_bearer_token_internal(
-    client: &reqwest::Client,
+    client: &HttpClient,
     token_cache: &TokenCache,
+    url: &str,
     www_authenticate: &str,
 ) -> Result<String, Error> {
     let (realm, service, scope) = parse_www_authenticate(www_authenticate)?;
 
     {
-        let cache = token_cache.read().await;
+        let cache = token_cache.tokens.read().await;
         if let Some(cached) = cache.get(&scope)
             && cached.expires_at > Instant::now()
         {
@@ -195,16 +277,32 @@ pub(crate) async fn fetch_bearer_token_internal(
         reqwest::Url::parse_with_params(&realm, &[("service", &service), ("scope", &scope)])
             .map_err(Error::network("failed to construct token URL"))?;
 
-    let response = client
-        .get(token_url)
+    let credentials = token_cache.credentials.basic_header_for(url);
+    let authenticated = credentials.is_some();
+    let token_url = token_url.to_string();
+    let mut request = client.get(&token_url);
+    if let Some(credentials) = credentials {
+        request = request.header(AUTHORIZATION, credentials);
+    }
+
+    let response = request
         .send()
         .await
-        .map_err(Error::network("token request failed"))?;
+        .map_err(|e| request_error(&e, &token_url))?;
 
-    if !response.status().is_success() {
-        return Err(Error::NetworkFailure {
-            message: format!("token request returned HTTP {}", response.status()),
-        });
+    let status = response.status();
+    if !status.is_success() {
+        let message = match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN if authenticated => {
+                format!("registry rejected the configured credentials for {scope} (HTTP {status})")
+            }
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => format!(
+                "anonymous access to {scope} was denied (HTTP {status}); \
+                 set ZEROBREW_GITHUB_TOKEN or ZEROBREW_DOCKER_CONFIG to authenticate"
+            ),
+            _ => return Err(status_error(status, &token_url)),
+        };
+        return Err(Error::NetworkFailure { message });
     }
 
     let token_response: TokenResponse = response
@@ -212,13 +310,18 @@ pub(crate) async fn fetch_bearer_token_internal(
         .await
         .map_err(Error::network("failed to parse token response"))?;
 
+    let lifetime = token_response
+        .expires_in
+        .map(|secs| Duration::from_secs(secs).saturating_sub(TOKEN_EXPIRY_MARGIN))
+        .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+
     {
-        let mut cache = token_cache.write().await;
+        let mut cache = token_cache.tokens.write().await;
         cache.insert(
             scope,
             CachedToken {
                 token: token_response.token.clone(),
-                expires_at: Instant::now() + Duration::from_secs(240),
+                expires_at: Instant::now() + lifetime,
             },
         );
     }
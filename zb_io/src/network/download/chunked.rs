@@ -18,15 +18,19 @@ use super::auth::{
     TokenCache, bearer_header, fetch_bearer_token_internal, fetch_download_response_internal,
     fetch_range_response_internal, get_cached_token_for_url_internal,
 };
+use super::rate_limit::RateLimiter;
 use super::single::download_response_internal;
 use super::{DownloadProgressCallback, MAX_CHUNK_RETRIES, MAX_CONCURRENT_CHUNKS};
+use crate::network::client::HttpClient;
+use crate::network::failure::{is_retryable, request_error, status_error};
 
 const MIN_CHUNK_SIZE: u64 = 5 * 1024 * 1024;
 const MAX_CHUNK_SIZE: u64 = 20 * 1024 * 1024;
 
 struct ChunkDownloadContext<'a> {
-    client: &'a reqwest::Client,
+    client: &'a HttpClient,
     token_cache: &'a TokenCache,
+    rate_limiter: &'a RateLimiter,
     url: &'a str,
     progress: Option<DownloadProgressCallback>,
     name: Option<String>,
@@ -36,8 +40,9 @@ struct ChunkDownloadContext<'a> {
 
 pub(crate) struct ChunkedDownloadContext<'a> {
     pub(crate) blob_cache: &'a BlobCache,
-    pub(crate) client: &'a reqwest::Client,
+    pub(crate) client: &'a HttpClient,
     pub(crate) token_cache: &'a TokenCache,
+    pub(crate) rate_limiter: &'a RateLimiter,
     pub(crate) url: &'a str,
     pub(crate) expected_sha256: &'a str,
     pub(crate) name: Option<String>,
@@ -93,6 +98,7 @@ async fn download_chunk(
     let mut last_error = None;
 
     for attempt in 0..=MAX_CHUNK_RETRIES {
+        ctx.rate_limiter.wait_for(ctx.url).await;
         let cached_token = get_cached_token_for_url_internal(ctx.token_cache, ctx.url).await;
 
         let mut request = ctx
@@ -119,7 +125,14 @@ async fn download_chunk(
                         }
                     };
 
-                    match fetch_bearer_token_internal(ctx.client, ctx.token_cache, www_auth).await {
+                    match fetch_bearer_token_internal(
+                        ctx.client,
+                        ctx.token_cache,
+                        ctx.url,
+                        www_auth,
+                    )
+                    .await
+                    {
                         Ok(_new_token) => {
                             last_error = Some(Error::NetworkFailure {
                                 message: "token expired, retrying with new token".to_string(),
@@ -151,11 +164,20 @@ async fn download_chunk(
                 }
 
                 if !response.status().is_success() {
-                    let err = Error::NetworkFailure {
-                        message: format!("chunk download returned HTTP {}", response.status()),
-                    };
-
-                    if response.status().is_server_error() && attempt < MAX_CHUNK_RETRIES {
+                    let err = status_error(response.status(), ctx.url);
+
+                    // A rate limit pauses the host; the next attempt waits it out.
+                    if ctx.rate_limiter.check(
+                        ctx.url,
+                        response.status(),
+                        response.headers(),
+                        ctx.progress.as_ref(),
+                    )? && attempt < MAX_CHUNK_RETRIES
+                    {
+                        last_error = Some(err);
+                        continue;
+                    }
+                    if is_retryable(&err) && attempt < MAX_CHUNK_RETRIES {
                         last_error = Some(err);
                         tokio::time::sleep(Duration::from_millis(100 * (1 << attempt))).await;
                         continue;
@@ -196,7 +218,11 @@ async fn download_chunk(
                 return Ok(chunk_data);
             }
             Err(e) => {
-                last_error = Some(Error::network("chunk download failed")(e));
+                let err = request_error(&e, ctx.url);
+                if !is_retryable(&err) {
+                    return Err(err);
+                }
+                last_error = Some(err);
 
                 if attempt < MAX_CHUNK_RETRIES {
                     tokio::time::sleep(Duration::from_millis(100 * (1 << attempt))).await;
@@ -215,8 +241,14 @@ pub(crate) async fn download_with_chunks(
     ctx: &ChunkedDownloadContext<'_>,
 ) -> Result<PathBuf, Error> {
     if !validate_range_support(ctx).await? {
-        let response =
-            fetch_download_response_internal(ctx.client, ctx.token_cache, ctx.url).await?;
+        let response = fetch_download_response_internal(
+            ctx.client,
+            ctx.token_cache,
+            ctx.rate_limiter,
+            ctx.url,
+            ctx.progress.as_ref(),
+        )
+        .await?;
         return download_response_internal(
             ctx.blob_cache,
             response,
@@ -254,6 +286,7 @@ pub(crate) async fn download_with_chunks(
     for chunk in chunks {
         let client = ctx.client.clone();
         let token_cache = ctx.token_cache.clone();
+        let rate_limiter = ctx.rate_limiter.clone();
         let url = ctx.url.to_string();
         let global_semaphore = ctx.global_semaphore.clone();
         let total_downloaded = total_downloaded.clone();
@@ -264,6 +297,8 @@ pub(crate) async fn download_with_chunks(
         let writer = writer.clone();
 
         let handle = tokio::spawn(async move {
+            // Don't hold a download slot while the host is paused.
+            rate_limiter.wait_for(&url).await;
             let _permit = global_semaphore
                 .acquire()
                 .await
@@ -272,6 +307,7 @@ pub(crate) async fn download_with_chunks(
             let chunk_ctx = ChunkDownloadContext {
                 client: &client,
                 token_cache: &token_cache,
+                rate_limiter: &rate_limiter,
                 url: &url,
                 progress: progress.clone(),
                 name: name.clone(),
@@ -401,8 +437,15 @@ pub(crate) async fn download_with_chunks(
 }
 
 async fn validate_range_support(ctx: &ChunkedDownloadContext<'_>) -> Result<bool, Error> {
-    let response =
-        fetch_range_response_internal(ctx.client, ctx.token_cache, ctx.url, "bytes=0-0").await?;
+    let response = fetch_range_response_internal(
+        ctx.client,
+        ctx.token_cache,
+        ctx.rate_limiter,
+        ctx.url,
+        "bytes=0-0",
+        ctx.progress.as_ref(),
+    )
+    .await?;
 
     if response.status() != StatusCode::PARTIAL_CONTENT {
         return Ok(false);
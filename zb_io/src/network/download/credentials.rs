@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::header::HeaderValue;
+use serde::Deserialize;
+use zb_core::Error;
+
+/// Registry host that `ZEROBREW_GITHUB_TOKEN` authenticates against.
+const GHCR_HOST: &str = "ghcr.io";
+
+/// Any non-empty user name works with a GitHub token; GHCR only checks the token.
+const GITHUB_TOKEN_USERNAME: &str = "zerobrew";
+
+/// Credentials for private registries, keyed by registry host (`ghcr.io`,
+/// or `host:port` for registries on a non-default port). They are only ever
+/// sent to a registry's token endpoint to exchange for a short-lived bearer
+/// token, and are redacted from `Debug` output.
+#[derive(Clone, Default)]
+pub struct RegistryCredentials {
+    by_host: HashMap<String, BasicAuth>,
+}
+
+#[derive(Clone)]
+struct BasicAuth {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuth>,
+}
+
+#[derive(Deserialize)]
+struct DockerAuth {
+    auth: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl RegistryCredentials {
+    /// Credentials from `ZEROBREW_DOCKER_CONFIG` (a Docker `config.json`, or
+    /// the directory holding one) and `ZEROBREW_GITHUB_TOKEN`. The token wins
+    /// over a `ghcr.io` entry in the Docker config.
+    pub fn from_env() -> Result<Self, Error> {
+        let mut credentials =
+            match std::env::var_os("ZEROBREW_DOCKER_CONFIG").filter(|path| !path.is_empty()) {
+                Some(path) => Self::from_docker_config(Path::new(&path))?,
+                None => Self::default(),
+            };
+
+        if let Ok(token) = std::env::var("ZEROBREW_GITHUB_TOKEN") {
+            let token = token.trim();
+            if !token.is_empty() {
+                credentials.insert(GHCR_HOST, GITHUB_TOKEN_USERNAME, token);
+            }
+        }
+
+        Ok(credentials)
+    }
+
+    /// Reads the `auths` section of a Docker config. Credential helpers
+    /// (`credsStore`, `credHelpers`) are not consulted.
+    pub fn from_docker_config(path: &Path) -> Result<Self, Error> {
+        let path: PathBuf = if path.is_dir() {
+            path.join("config.json")
+        } else {
+            path.to_path_buf()
+        };
+        let ctx = format!("failed to read Docker config {}", path.display());
+        let contents = std::fs::read_to_string(&path).map_err(Error::file(ctx.as_str()))?;
+        let config: DockerConfig =
+            serde_json::from_str(&contents).map_err(|e| Error::InvalidArgument {
+                message: format!("invalid Docker config {}: {e}", path.display()),
+            })?;
+
+        let mut credentials = Self::default();
+        for (registry, auth) in config.auths {
+            // Only say which registry is broken; never echo the entry.
+            let invalid = || Error::InvalidArgument {
+                message: format!(
+                    "invalid credentials for {registry} in Docker config {}",
+                    path.display()
+                ),
+            };
+            let (username, password) = match (auth.auth, auth.username, auth.password) {
+                (Some(encoded), _, _) => {
+                    let decoded = STANDARD.decode(encoded.trim()).map_err(|_| invalid())?;
+                    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+                    let (username, password) = decoded.split_once(':').ok_or_else(invalid)?;
+                    (username.to_string(), password.to_string())
+                }
+                (None, Some(username), Some(password)) => (username, password),
+                _ => continue,
+            };
+            credentials.insert(&registry, &username, &password);
+        }
+
+        Ok(credentials)
+    }
+
+    /// Adds credentials for `registry`, given as a host or as a URL.
+    pub fn insert(&mut self, registry: &str, username: &str, password: &str) {
+        self.by_host.insert(
+            registry_host(registry),
+            BasicAuth {
+                username: username.to_string(),
+                password: password.to_string(),
+            },
+        );
+    }
+
+    /// `Authorization` header for the registry serving `url`, marked
+    /// sensitive so it never shows up in HTTP debug logs.
+    pub(crate) fn basic_header_for(&self, url: &str) -> Option<HeaderValue> {
+        let auth = self.by_host.get(&registry_host(url))?;
+        let encoded = STANDARD.encode(format!("{}:{}", auth.username, auth.password));
+        let mut value = HeaderValue::from_str(&format!("Basic {encoded}")).ok()?;
+        value.set_sensitive(true);
+        Some(value)
+    }
+}
+
+impl fmt::Debug for RegistryCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut hosts: Vec<_> = self.by_host.keys().collect();
+        hosts.sort();
+        f.debug_struct("RegistryCredentials")
+            .field("registries", &hosts)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `host` or `host:port` of a registry given as a bare host or as a URL,
+/// the way Docker config keys may be written either way.
+fn registry_host(registry: &str) -> String {
+    let with_scheme = if registry.contains("://") {
+        registry.to_string()
+    } else {
+        format!("https://{registry}")
+    };
+    match reqwest::Url::parse(&with_scheme) {
+        Ok(url) => match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            _ => registry.to_string(),
+        },
+        Err(_) => registry.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn docker_config_entries_match_by_registry_host() {
+        let tmp = TempDir::new().unwrap();
+        let config = serde_json::json!({
+            "auths": {
+                "https://ghcr.io": { "auth": STANDARD.encode("octocat:ghp_secret") },
+                "127.0.0.1:5000": { "username": "ci", "password": "hunter2" },
+                "docker.io": { "identitytoken": "ignored" }
+            },
+            "credsStore": "desktop"
+        });
+        std::fs::write(tmp.path().join("config.json"), config.to_string()).unwrap();
+
+        let credentials = RegistryCredentials::from_docker_config(tmp.path()).unwrap();
+
+        let ghcr = credentials
+            .basic_header_for("https://ghcr.io/v2/org/mirror/wget/blobs/sha256:abc")
+            .unwrap();
+        assert_eq!(
+            ghcr.to_str().unwrap(),
+            format!("Basic {}", STANDARD.encode("octocat:ghp_secret"))
+        );
+        assert!(ghcr.is_sensitive());
+        assert!(
+            credentials
+                .basic_header_for("http://127.0.0.1:5000/v2/a/b/c/blobs/sha256:abc")
+                .is_some()
+        );
+        assert!(
+            credentials
+                .basic_header_for("http://127.0.0.1:5001/v2/a/b/c/blobs/sha256:abc")
+                .is_none()
+        );
+        assert!(
+            credentials
+                .basic_header_for("https://docker.io/v2/library/alpine")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn malformed_auth_is_reported_without_its_contents() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("config.json");
+        std::fs::write(
+            &path,
+            r#"{"auths": {"ghcr.io": {"auth": "bm8tY29sb24taGVyZQ=="}}}"#,
+        )
+        .unwrap();
+
+        let err = RegistryCredentials::from_docker_config(&path).unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("ghcr.io"));
+        assert!(!message.contains("bm8tY29sb24taGVyZQ"));
+        assert!(!message.contains("no-colon-here"));
+    }
+
+    #[test]
+    fn debug_output_never_shows_secrets() {
+        let mut credentials = RegistryCredentials::default();
+        credentials.insert("ghcr.io", "octocat", "ghp_secret");
+
+        let debug = format!("{credentials:?}");
+
+        assert!(debug.contains("ghcr.io"));
+        assert!(!debug.contains("octocat"));
+        assert!(!debug.contains("ghp_secret"));
+    }
+}
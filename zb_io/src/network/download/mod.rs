@@ -1,6 +1,8 @@
 mod auth;
 mod chunked;
+mod credentials;
 mod parallel;
+mod rate_limit;
 mod single;
 
 use std::path::PathBuf;
@@ -10,7 +12,10 @@ use crate::progress::InstallProgress;
 
 pub type DownloadProgressCallback = Arc<dyn Fn(InstallProgress) + Send + Sync>;
 
-const RACING_CONNECTIONS: usize = 3;
+/// Attempts at each URL's request before giving up on it. All downloads
+/// share one pooled client, so the attempts follow each other instead of
+/// racing identical requests.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
 const RACING_STAGGER_MS: u64 = 200;
 
 /// Minimum file size to use chunked downloads (10MB)
@@ -36,7 +41,17 @@ pub struct DownloadResult {
     pub sha256: String,
     pub blob_path: PathBuf,
     pub index: usize,
+    pub source: BlobSource,
 }
 
+/// Whether a blob was already in the `BlobCache` or had to be fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobSource {
+    Cached,
+    Downloaded { bytes: u64 },
+}
+
+pub use credentials::RegistryCredentials;
 pub use parallel::{DownloadRequest, ParallelDownloader};
+pub use rate_limit::DEFAULT_MAX_RATE_LIMIT_WAIT;
 pub use single::Downloader;
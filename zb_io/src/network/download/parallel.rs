@@ -1,14 +1,18 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::sync::{Mutex, Semaphore, mpsc};
 
+use crate::network::client::HttpClient;
 use crate::storage::blob::BlobCache;
 use zb_core::Error;
 
+use super::auth::TokenCache;
+use super::credentials::RegistryCredentials;
 use super::single::Downloader;
-use super::{DownloadProgressCallback, DownloadResult, GLOBAL_DOWNLOAD_CONCURRENCY};
+use super::{BlobSource, DownloadProgressCallback, DownloadResult, GLOBAL_DOWNLOAD_CONCURRENCY};
 
 pub struct DownloadRequest {
     pub url: String,
@@ -21,38 +25,84 @@ type InflightMap = HashMap<String, Arc<tokio::sync::broadcast::Sender<Result<Pat
 pub struct ParallelDownloader {
     downloader: Arc<Downloader>,
     semaphore: Arc<Semaphore>,
+    concurrency: usize,
     inflight: Arc<Mutex<InflightMap>>,
 }
 
 impl ParallelDownloader {
     pub fn new(blob_cache: BlobCache) -> Self {
-        let semaphore = Arc::new(Semaphore::new(GLOBAL_DOWNLOAD_CONCURRENCY));
-        Self {
-            downloader: Arc::new(Downloader::with_semaphore(
-                blob_cache,
-                Some(semaphore.clone()),
-            )),
-            semaphore,
-            inflight: Arc::new(Mutex::new(HashMap::new())),
-        }
+        Self::with_client(blob_cache, HttpClient::new())
+    }
+
+    /// Download through `client`, sharing its connection pool.
+    pub fn with_client(blob_cache: BlobCache, client: HttpClient) -> Self {
+        Self::with_concurrency(blob_cache, GLOBAL_DOWNLOAD_CONCURRENCY, client)
     }
 
-    pub fn with_concurrency(blob_cache: BlobCache, concurrency: usize) -> Self {
+    /// Run `concurrency` downloads at once through `client`.
+    pub fn with_concurrency(blob_cache: BlobCache, concurrency: usize, client: HttpClient) -> Self {
         let semaphore = Arc::new(Semaphore::new(concurrency));
         Self {
-            downloader: Arc::new(Downloader::with_semaphore(
+            downloader: Arc::new(Downloader::with_client(
                 blob_cache,
                 Some(semaphore.clone()),
+                client,
             )),
             semaphore,
+            concurrency,
             inflight: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// How many downloads run at once.
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    /// Credentials for private registries. Must be set before any download
+    /// starts.
+    pub fn with_credentials(mut self, credentials: RegistryCredentials) -> Self {
+        let downloader = Arc::get_mut(&mut self.downloader)
+            .expect("credentials are configured before downloads start");
+        downloader.token_cache = TokenCache::new(credentials);
+        self
+    }
+
+    /// Longest `Retry-After` a rate-limited download waits out before
+    /// failing. Must be set before any download starts.
+    pub fn with_max_rate_limit_wait(mut self, max_wait: Duration) -> Self {
+        let downloader = Arc::get_mut(&mut self.downloader)
+            .expect("rate limit ceiling is configured before downloads start");
+        downloader.rate_limiter.set_max_wait(max_wait);
+        self
+    }
+
     pub fn remove_blob(&self, sha256: &str) -> bool {
         self.downloader.remove_blob(sha256)
     }
 
+    pub(crate) fn blob_cache(&self) -> &BlobCache {
+        &self.downloader.blob_cache
+    }
+
+    /// Size in bytes of an already-downloaded blob, if it is cached.
+    pub fn cached_blob_size(&self, sha256: &str) -> Option<u64> {
+        std::fs::metadata(self.downloader.blob_cache.blob_path(sha256))
+            .ok()
+            .map(|metadata| metadata.len())
+    }
+
+    /// [`Downloader::download_size`] of each request, in order.
+    pub async fn download_sizes(&self, requests: &[DownloadRequest]) -> Vec<Option<u64>> {
+        let sizes = requests.iter().map(|request| async {
+            let _permit = self.semaphore.acquire().await.ok()?;
+            self.downloader
+                .download_size(&request.url, &request.sha256)
+                .await
+        });
+        futures::future::join_all(sizes).await
+    }
+
     pub async fn download_single(
         &self,
         request: DownloadRequest,
@@ -66,6 +116,7 @@ impl ParallelDownloader {
             progress,
         )
         .await
+        .map(|(path, _)| path)
     }
 
     pub async fn download_all(
@@ -96,18 +147,20 @@ impl ParallelDownloader {
 
         let mut results = Vec::with_capacity(handles.len());
         for handle in handles {
-            let result = handle.await.map_err(Error::network("task join error"))??;
-            results.push(result);
+            let (path, _) = handle.await.map_err(Error::network("task join error"))??;
+            results.push(path);
         }
 
         Ok(results)
     }
 
+    /// Download `requests` concurrently, sending each result as soon as it
+    /// is ready, tagged with the index of its request.
     pub fn download_streaming(
         &self,
         requests: Vec<DownloadRequest>,
         progress: Option<DownloadProgressCallback>,
-    ) -> mpsc::Receiver<Result<DownloadResult, Error>> {
+    ) -> mpsc::Receiver<(usize, Result<DownloadResult, Error>)> {
         let (tx, rx) = mpsc::channel(requests.len().max(1));
 
         for (index, req) in requests.into_iter().enumerate() {
@@ -123,12 +176,16 @@ impl ParallelDownloader {
                 let result =
                     Self::download_with_dedup(downloader, semaphore, inflight, req, progress).await;
                 let _ = tx
-                    .send(result.map(|blob_path| DownloadResult {
-                        name,
-                        sha256,
-                        blob_path,
+                    .send((
                         index,
-                    }))
+                        result.map(|(blob_path, source)| DownloadResult {
+                            name,
+                            sha256,
+                            blob_path,
+                            index,
+                            source,
+                        }),
+                    ))
                     .await;
             });
         }
@@ -142,7 +199,7 @@ impl ParallelDownloader {
         inflight: Arc<Mutex<InflightMap>>,
         req: DownloadRequest,
         progress: Option<DownloadProgressCallback>,
-    ) -> Result<PathBuf, Error> {
+    ) -> Result<(PathBuf, BlobSource), Error> {
         let mut receiver = {
             let mut map = inflight.lock().await;
 
@@ -161,9 +218,15 @@ impl ParallelDownloader {
                 .await
                 .map_err(Error::network("broadcast recv error"))?;
 
-            return result.map_err(|msg| Error::NetworkFailure { message: msg });
+            // The blob is fetched once; everyone else waiting on it reuses it.
+            return result
+                .map(|path| (path, BlobSource::Cached))
+                .map_err(|msg| Error::NetworkFailure { message: msg });
         }
 
+        // Downloads from a rate-limited host queue here without taking a
+        // slot from downloads that go elsewhere.
+        downloader.rate_limiter.wait_for(&req.url).await;
         let _permit = semaphore
             .acquire()
             .await
@@ -177,7 +240,7 @@ impl ParallelDownloader {
             let mut map = inflight.lock().await;
             if let Some(sender) = map.remove(&req.sha256) {
                 let broadcast_result = match &result {
-                    Ok(path) => Ok(path.clone()),
+                    Ok((path, _)) => Ok(path.clone()),
                     Err(e) => Err(e.to_string()),
                 };
                 let _ = sender.send(broadcast_result);
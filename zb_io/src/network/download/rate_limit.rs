@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use tokio::time::Instant;
+use tracing::warn;
+use zb_core::Error;
+
+use crate::progress::InstallProgress;
+
+use super::DownloadProgressCallback;
+
+/// Longest rate-limit pause a download waits out before failing.
+pub const DEFAULT_MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(120);
+
+/// Pause used when a rate-limited response carries no usable `Retry-After`.
+const FALLBACK_RATE_LIMIT_WAIT: Duration = Duration::from_secs(10);
+
+/// How many rate-limited responses a single request waits out.
+pub(crate) const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+
+/// Per-host pauses shared by every download of a `Downloader`. A host that
+/// answered with a rate limit gets no new requests until its pause is over,
+/// while other hosts carry on.
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    paused_until: Arc<Mutex<HashMap<String, Instant>>>,
+    max_wait: Duration,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(max_wait: Duration) -> Self {
+        Self {
+            paused_until: Arc::new(Mutex::new(HashMap::new())),
+            max_wait,
+        }
+    }
+
+    pub(crate) fn set_max_wait(&mut self, max_wait: Duration) {
+        self.max_wait = max_wait;
+    }
+
+    /// Sleep until `url`'s host is no longer paused.
+    pub(crate) async fn wait_for(&self, url: &str) {
+        let host = host_of(url);
+        loop {
+            let until = self.paused_until.lock().unwrap().get(&host).copied();
+            match until {
+                Some(until) if until > Instant::now() => tokio::time::sleep_until(until).await,
+                _ => return,
+            }
+        }
+    }
+
+    /// Whether the response to a request for `url` is a rate limit. If so,
+    /// the host is paused for as long as the server asked, and the pause is
+    /// reported once through `progress`. Fails when the server asks for a
+    /// longer pause than this limiter waits out.
+    pub(crate) fn check(
+        &self,
+        url: &str,
+        status: StatusCode,
+        headers: &HeaderMap,
+        progress: Option<&DownloadProgressCallback>,
+    ) -> Result<bool, Error> {
+        if !is_rate_limited(status, headers) {
+            return Ok(false);
+        }
+
+        let host = host_of(url);
+        let wait = retry_after(headers, SystemTime::now()).unwrap_or(FALLBACK_RATE_LIMIT_WAIT);
+        if wait > self.max_wait {
+            return Err(Error::NetworkFailure {
+                message: format!(
+                    "rate limited by {host} for {}s, longer than the {}s zb waits; \
+                     try again later with a lower --concurrency, or authenticate to raise the limit",
+                    whole_secs(wait),
+                    whole_secs(self.max_wait)
+                ),
+            });
+        }
+
+        let now = Instant::now();
+        let until = now + wait;
+        let newly_paused = {
+            let mut paused = self.paused_until.lock().unwrap();
+            let current = paused.get(&host).copied().filter(|&current| current > now);
+            if current.is_none_or(|current| current < until) {
+                paused.insert(host.clone(), until);
+            }
+            current.is_none()
+        };
+
+        if newly_paused {
+            warn!(host = %host, wait_secs = whole_secs(wait), "rate limited; pausing downloads");
+            if let Some(cb) = progress {
+                cb(InstallProgress::RateLimited {
+                    host,
+                    wait_secs: whole_secs(wait),
+                });
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// 429, or a 403 that GitHub marks as a rate limit rather than a denial.
+fn is_rate_limited(status: StatusCode, headers: &HeaderMap) -> bool {
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return true;
+    }
+    status == StatusCode::FORBIDDEN
+        && (headers.contains_key(RETRY_AFTER)
+            || headers
+                .get("x-ratelimit-remaining")
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.trim() == "0"))
+}
+
+/// `Retry-After` as either delay-seconds or an HTTP-date relative to `now`.
+/// Dates in the past mean no wait at all.
+fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+fn host_of(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| url.to_string())
+}
+
+fn whole_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", "37")]), now),
+            Some(Duration::from_secs(37))
+        );
+        assert_eq!(
+            retry_after(
+                &headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:37 GMT")]),
+                now
+            ),
+            Some(Duration::from_secs(37))
+        );
+        assert_eq!(
+            retry_after(
+                &headers(&[("retry-after", "Wed, 21 Oct 2015 07:27:00 GMT")]),
+                now
+            ),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after(&headers(&[("retry-after", "soon")]), now), None);
+        assert_eq!(retry_after(&HeaderMap::new(), now), None);
+    }
+
+    #[test]
+    fn only_rate_limit_responses_are_recognised() {
+        assert!(is_rate_limited(
+            StatusCode::TOO_MANY_REQUESTS,
+            &HeaderMap::new()
+        ));
+        assert!(is_rate_limited(
+            StatusCode::FORBIDDEN,
+            &headers(&[("x-ratelimit-remaining", "0")])
+        ));
+        assert!(is_rate_limited(
+            StatusCode::FORBIDDEN,
+            &headers(&[("retry-after", "5")])
+        ));
+        assert!(!is_rate_limited(StatusCode::FORBIDDEN, &HeaderMap::new()));
+        assert!(!is_rate_limited(
+            StatusCode::SERVICE_UNAVAILABLE,
+            &headers(&[("retry-after", "5")])
+        ));
+    }
+
+    #[tokio::test]
+    async fn waits_longer_than_the_ceiling_fail_with_advice() {
+        let limiter = RateLimiter::new(Duration::from_secs(60));
+        let err = limiter
+            .check(
+                "https://ghcr.io/v2/homebrew/core/wget/blobs/sha256:abc",
+                StatusCode::TOO_MANY_REQUESTS,
+                &headers(&[("retry-after", "3600")]),
+                None,
+            )
+            .unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("rate limited by ghcr.io for 3600s"));
+        assert!(message.contains("--concurrency"));
+    }
+
+    #[tokio::test]
+    async fn pauses_only_the_limited_host() {
+        let limiter = RateLimiter::new(DEFAULT_MAX_RATE_LIMIT_WAIT);
+        let limited = limiter
+            .check(
+                "https://ghcr.io/v2/a",
+                StatusCode::TOO_MANY_REQUESTS,
+                &headers(&[("retry-after", "30")]),
+                None,
+            )
+            .unwrap();
+        assert!(limited);
+
+        let other = tokio::time::timeout(
+            Duration::from_millis(100),
+            limiter.wait_for("https://mirror.example.com/v2/a"),
+        )
+        .await;
+        assert!(other.is_ok());
+
+        let paused = tokio::time::timeout(
+            Duration::from_millis(100),
+            limiter.wait_for("https://ghcr.io/v2/b"),
+        )
+        .await;
+        assert!(paused.is_err());
+    }
+}
@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use std::io::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -7,24 +6,36 @@ use std::time::Duration;
 
 use futures_util::StreamExt;
 use futures_util::future::select_all;
-use reqwest::header::{AUTHORIZATION, CONTENT_LENGTH};
+use reqwest::header::CONTENT_LENGTH;
 use sha2::{Digest, Sha256};
-use tokio::sync::{Notify, RwLock, Semaphore};
+use tokio::sync::{Notify, Semaphore};
 use tracing::warn;
 
+use crate::network::client::HttpClient;
+use crate::network::failure::is_retryable;
 use crate::progress::InstallProgress;
 use crate::storage::blob::BlobCache;
 use zb_core::Error;
 
-use super::auth::{
-    TokenCache, bearer_header, fetch_download_response_internal, get_cached_token_for_url_internal,
-};
+use super::auth::{TokenCache, fetch_download_response_internal, fetch_head_response_internal};
 use super::chunked::{ChunkedDownloadContext, download_with_chunks, server_supports_ranges};
+use super::credentials::RegistryCredentials;
+use super::rate_limit::{DEFAULT_MAX_RATE_LIMIT_WAIT, RateLimiter};
 use super::{
-    CHUNKED_DOWNLOAD_THRESHOLD, DownloadProgressCallback, GLOBAL_DOWNLOAD_CONCURRENCY,
-    RACING_CONNECTIONS, RACING_STAGGER_MS,
+    BlobSource, CHUNKED_DOWNLOAD_THRESHOLD, DOWNLOAD_ATTEMPTS, DownloadProgressCallback,
+    GLOBAL_DOWNLOAD_CONCURRENCY, RACING_STAGGER_MS,
 };
 
+/// The `Content-Length` header itself: reqwest's own accessor reports the
+/// empty body of a HEAD response.
+fn content_length(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok())
+}
+
 fn get_alternate_urls(primary_url: &str) -> Vec<String> {
     let mut alternates = Vec::new();
 
@@ -50,56 +61,12 @@ fn transform_url_to_mirror(url: &str, mirror_domain: &str) -> Option<String> {
     }
 }
 
-pub(crate) fn build_rustls_config() -> Option<rustls::ClientConfig> {
-    let provider = rustls::crypto::aws_lc_rs::default_provider();
-
-    let mut root_store = rustls::RootCertStore::empty();
-
-    let cert_result = rustls_native_certs::load_native_certs();
-    if !cert_result.errors.is_empty() {
-        let details = cert_result
-            .errors
-            .iter()
-            .take(3)
-            .map(std::string::ToString::to_string)
-            .collect::<Vec<_>>()
-            .join("; ");
-        warn!(
-            errors = cert_result.errors.len(),
-            details = %details,
-            "failed to load native certificates"
-        );
-    }
-
-    for cert in cert_result.certs {
-        let _ = root_store.add(cert);
-    }
-
-    let builder = rustls::ClientConfig::builder_with_provider(provider.into());
-    let builder = match builder.with_safe_default_protocol_versions() {
-        Ok(builder) => builder,
-        Err(e) => {
-            warn!(
-                error = %e,
-                "failed to configure rustls protocol versions; falling back to reqwest default TLS"
-            );
-            return None;
-        }
-    };
-
-    Some(
-        builder
-            .with_root_certificates(root_store)
-            .with_no_client_auth(),
-    )
-}
-
 pub struct Downloader {
-    client: reqwest::Client,
+    pub(crate) client: HttpClient,
     pub(crate) blob_cache: BlobCache,
     pub(crate) token_cache: TokenCache,
     pub(crate) global_semaphore: Option<Arc<Semaphore>>,
-    tls_config: Option<Arc<rustls::ClientConfig>>,
+    pub(crate) rate_limiter: RateLimiter,
 }
 
 impl Downloader {
@@ -108,45 +75,41 @@ impl Downloader {
     }
 
     pub fn with_semaphore(blob_cache: BlobCache, semaphore: Option<Arc<Semaphore>>) -> Self {
-        let tls_config = build_rustls_config().map(Arc::new);
+        Self::with_client(blob_cache, semaphore, HttpClient::new())
+    }
 
+    pub(crate) fn with_client(
+        blob_cache: BlobCache,
+        semaphore: Option<Arc<Semaphore>>,
+        client: HttpClient,
+    ) -> Self {
         Self {
-            client: reqwest::Client::builder()
-                .user_agent("zerobrew/0.1")
-                .pool_max_idle_per_host(10)
-                .tcp_nodelay(true)
-                .tcp_keepalive(Duration::from_secs(60))
-                .connect_timeout(Duration::from_secs(30))
-                .timeout(Duration::from_secs(300))
-                .http2_adaptive_window(true)
-                .http2_initial_stream_window_size(Some(2 * 1024 * 1024))
-                .http2_initial_connection_window_size(Some(4 * 1024 * 1024))
-                .build()
-                .unwrap_or_else(|_| reqwest::Client::new()),
+            client,
             blob_cache,
-            token_cache: Arc::new(RwLock::new(HashMap::new())),
+            token_cache: TokenCache::default(),
             global_semaphore: semaphore,
-            tls_config,
+            rate_limiter: RateLimiter::new(DEFAULT_MAX_RATE_LIMIT_WAIT),
         }
     }
 
-    fn create_isolated_client(&self) -> reqwest::Client {
-        let mut builder = reqwest::Client::builder().user_agent("zerobrew/0.1");
-        if let Some(tls_config) = &self.tls_config {
-            builder = builder.use_preconfigured_tls(tls_config.clone());
-        }
+    /// Download through `client`, sharing its connection pool with whatever
+    /// else uses it (normally the formula API client).
+    pub fn with_http_client(mut self, client: HttpClient) -> Self {
+        self.client = client;
+        self
+    }
 
-        builder
-            .pool_max_idle_per_host(0)
-            .tcp_nodelay(true)
-            .tcp_keepalive(Duration::from_secs(60))
-            .connect_timeout(Duration::from_secs(30))
-            .timeout(Duration::from_secs(300))
-            .http2_adaptive_window(true)
-            .http2_initial_stream_window_size(Some(2 * 1024 * 1024))
-            .http2_initial_connection_window_size(Some(4 * 1024 * 1024))
-            .build()
-            .unwrap_or_else(|_| reqwest::Client::new())
+    /// Credentials for private registries, exchanged for bearer tokens when a
+    /// registry challenges a download.
+    pub fn with_credentials(mut self, credentials: RegistryCredentials) -> Self {
+        self.token_cache = TokenCache::new(credentials);
+        self
+    }
+
+    /// Longest `Retry-After` a rate-limited download waits out before failing.
+    pub fn with_max_rate_limit_wait(mut self, max_wait: Duration) -> Self {
+        self.rate_limiter.set_max_wait(max_wait);
+        self
     }
 
     pub fn remove_blob(&self, sha256: &str) -> bool {
@@ -156,6 +119,7 @@ impl Downloader {
     pub async fn download(&self, url: &str, expected_sha256: &str) -> Result<PathBuf, Error> {
         self.download_with_progress(url, expected_sha256, None, None)
             .await
+            .map(|(path, _)| path)
     }
 
     pub async fn download_with_progress(
@@ -164,21 +128,41 @@ impl Downloader {
         expected_sha256: &str,
         name: Option<String>,
         progress: Option<DownloadProgressCallback>,
-    ) -> Result<PathBuf, Error> {
+    ) -> Result<(PathBuf, BlobSource), Error> {
         if self.blob_cache.has_blob(expected_sha256) {
             if let (Some(cb), Some(n)) = (&progress, &name) {
-                cb(InstallProgress::DownloadCompleted {
-                    name: n.clone(),
-                    total_bytes: 0,
-                });
+                cb(InstallProgress::DownloadCached { name: n.clone() });
             }
-            return Ok(self.blob_cache.blob_path(expected_sha256));
+            return Ok((
+                self.blob_cache.blob_path(expected_sha256),
+                BlobSource::Cached,
+            ));
         }
 
         let alternates = get_alternate_urls(url);
 
-        self.download_with_racing(url, &alternates, expected_sha256, name, progress)
-            .await
+        let path = self
+            .download_with_racing(url, &alternates, expected_sha256, name, progress)
+            .await?;
+        let bytes = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        Ok((path, BlobSource::Downloaded { bytes }))
+    }
+
+    /// Bytes downloading `url` would fetch: none when the blob is cached,
+    /// otherwise the `Content-Length` of the HEAD request a download starts
+    /// with. `None` when the server doesn't say.
+    pub async fn download_size(&self, url: &str, expected_sha256: &str) -> Option<u64> {
+        if self.blob_cache.has_blob(expected_sha256) {
+            return Some(0);
+        }
+        match self.head(url).await {
+            Ok(response) if response.status().is_success() => content_length(&response),
+            _ => None,
+        }
+    }
+
+    async fn head(&self, url: &str) -> Result<reqwest::Response, Error> {
+        fetch_head_response_internal(&self.client, &self.token_cache, &self.rate_limiter, url).await
     }
 
     async fn download_with_racing(
@@ -190,21 +174,9 @@ impl Downloader {
         progress: Option<DownloadProgressCallback>,
     ) -> Result<PathBuf, Error> {
         let (use_chunked, file_size) = {
-            let cached_token =
-                get_cached_token_for_url_internal(&self.token_cache, primary_url).await;
-
-            let mut request = self.client.head(primary_url);
-            if let Some(token) = &cached_token {
-                request = request.header(AUTHORIZATION, bearer_header(token)?);
-            }
-
-            match request.send().await {
+            match self.head(primary_url).await {
                 Ok(response) if response.status().is_success() => {
-                    let content_length = response
-                        .headers()
-                        .get(CONTENT_LENGTH)
-                        .and_then(|v| v.to_str().ok())
-                        .and_then(|s| s.parse::<u64>().ok());
+                    let content_length = content_length(&response);
 
                     let supports_ranges = server_supports_ranges(&response);
 
@@ -237,6 +209,7 @@ impl Downloader {
                     blob_cache: &self.blob_cache,
                     client: &self.client,
                     token_cache: &self.token_cache,
+                    rate_limiter: &self.rate_limiter,
                     url: url.as_str(),
                     expected_sha256,
                     name: name.clone(),
@@ -264,23 +237,16 @@ impl Downloader {
         let done_notify = Arc::new(Notify::new());
         let body_download_gate = Arc::new(Semaphore::new(1));
 
-        let mut all_urls: Vec<String> = Vec::new();
-
-        for _ in 0..RACING_CONNECTIONS {
-            all_urls.push(primary_url.to_string());
-        }
-
+        // Mirrors race the primary URL, one request each.
+        let mut all_urls = vec![primary_url.to_string()];
         all_urls.extend(alternate_urls.iter().cloned());
 
         let mut handles = Vec::new();
         for (idx, url) in all_urls.into_iter().enumerate() {
-            let downloader_client = if idx < RACING_CONNECTIONS {
-                self.create_isolated_client()
-            } else {
-                self.client.clone()
-            };
+            let downloader_client = self.client.clone();
             let blob_cache = self.blob_cache.clone();
             let token_cache = self.token_cache.clone();
+            let rate_limiter = self.rate_limiter.clone();
             let expected_sha256 = expected_sha256.to_string();
             let name = name.clone();
             let progress = progress.clone();
@@ -312,9 +278,29 @@ impl Downloader {
                     return Ok(blob_cache.blob_path(&expected_sha256));
                 }
 
-                let response =
-                    fetch_download_response_internal(&downloader_client, &token_cache, &url)
-                        .await?;
+                let mut attempt = 1;
+                let response = loop {
+                    match fetch_download_response_internal(
+                        &downloader_client,
+                        &token_cache,
+                        &rate_limiter,
+                        &url,
+                        progress.as_ref(),
+                    )
+                    .await
+                    {
+                        Ok(response) => break response,
+                        Err(err)
+                            if is_retryable(&err)
+                                && attempt < DOWNLOAD_ATTEMPTS
+                                && !done.load(Ordering::Acquire) =>
+                        {
+                            tokio::time::sleep(Duration::from_millis(100 * (1 << attempt))).await;
+                            attempt += 1;
+                        }
+                        Err(err) => return Err(err),
+                    }
+                };
 
                 let _permit = tokio::select! {
                     permit = body_download_gate.acquire_owned() => permit.map_err(|_| Error::NetworkFailure {
@@ -463,15 +449,12 @@ pub(crate) async fn download_response_internal(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
     use tempfile::TempDir;
-    use wiremock::matchers::{method, path};
+    use wiremock::matchers::{header, method, path, path_regex};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
-    #[test]
-    fn build_rustls_config_does_not_panic() {
-        let _ = build_rustls_config();
-    }
-
     #[tokio::test]
     async fn valid_checksum_passes() {
         let mock_server = MockServer::start().await;
@@ -497,6 +480,41 @@ mod tests {
         assert_eq!(std::fs::read(&blob_path).unwrap(), content);
     }
 
+    #[tokio::test]
+    async fn sends_one_request_and_retries_it_on_failure() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let downloader = Downloader::new(BlobCache::new(tmp.path()).unwrap());
+
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let blob_path = downloader.download(&url, sha256).await.unwrap();
+        assert_eq!(std::fs::read(&blob_path).unwrap(), content);
+
+        let gets = mock_server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.method == wiremock::http::Method::GET)
+            .count();
+        assert_eq!(gets, 2);
+    }
+
     #[tokio::test]
     async fn mismatch_deletes_blob_and_errors() {
         let mock_server = MockServer::start().await;
@@ -533,6 +551,218 @@ mod tests {
         assert!(!tmp_path.exists());
     }
 
+    #[tokio::test]
+    async fn waits_out_rate_limit_and_reports_it() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "1"))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let downloader = Downloader::new(BlobCache::new(tmp.path()).unwrap());
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events_clone = events.clone();
+        let progress: DownloadProgressCallback = Arc::new(move |event| {
+            if let InstallProgress::RateLimited { wait_secs, .. } = event {
+                events_clone.lock().unwrap().push(wait_secs);
+            }
+        });
+
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let started = std::time::Instant::now();
+        let (blob_path, _) = downloader
+            .download_with_progress(&url, sha256, Some("test".to_string()), Some(progress))
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&blob_path).unwrap(), content);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(*events.lock().unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_with_http_date_is_waited_out() {
+        let mock_server = MockServer::start().await;
+        let content = b"hello world";
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        let retry_at =
+            httpdate::fmt_http_date(std::time::SystemTime::now() + Duration::from_secs(1));
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(
+                ResponseTemplate::new(429).insert_header("retry-after", retry_at.as_str()),
+            )
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let downloader = Downloader::new(BlobCache::new(tmp.path()).unwrap());
+
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let blob_path = downloader.download(&url, sha256).await.unwrap();
+
+        assert_eq!(std::fs::read(&blob_path).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_beyond_ceiling_fails() {
+        let mock_server = MockServer::start().await;
+        let sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        Mock::given(method("GET"))
+            .and(path("/test.tar.gz"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "3600"))
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let downloader = Downloader::new(BlobCache::new(tmp.path()).unwrap())
+            .with_max_rate_limit_wait(Duration::from_secs(5));
+
+        let url = format!("{}/test.tar.gz", mock_server.uri());
+        let err = downloader.download(&url, sha256).await.unwrap_err();
+
+        assert!(err.to_string().contains("rate limited"), "{err}");
+    }
+
+    /// A registry that challenges every blob request under `acme/mirror/test`
+    /// and only hands out a token for the `ci:hunter2` credentials.
+    async fn mount_private_registry(mock_server: &MockServer, token_requests: u64) {
+        let challenge = format!(
+            r#"Bearer realm="{}/token",service="mock-registry",scope="repository:acme/mirror/test:pull""#,
+            mock_server.uri()
+        );
+
+        Mock::given(method("GET"))
+            .and(path("/token"))
+            .and(header(
+                "authorization",
+                format!("Basic {}", STANDARD.encode("ci:hunter2")).as_str(),
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(
+                    serde_json::json!({ "token": "short-lived", "expires_in": 300 }),
+                ),
+            )
+            .expect(token_requests)
+            .mount(mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/v2/acme/mirror/test/blobs/"))
+            .respond_with(
+                ResponseTemplate::new(401).insert_header("www-authenticate", challenge.as_str()),
+            )
+            .mount(mock_server)
+            .await;
+    }
+
+    async fn mount_private_blob(mock_server: &MockServer, blob: &str, content: &[u8]) {
+        Mock::given(method("GET"))
+            .and(path(format!("/v2/acme/mirror/test/blobs/{blob}")))
+            .and(header("authorization", "Bearer short-lived"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+            .with_priority(1)
+            .mount(mock_server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn private_registry_exchanges_credentials_for_a_cached_token() {
+        let mock_server = MockServer::start().await;
+        let first = b"hello world";
+        let second = b"goodbye world";
+        mount_private_registry(&mock_server, 1).await;
+        mount_private_blob(&mock_server, "a", first).await;
+        mount_private_blob(&mock_server, "b", second).await;
+
+        let mut credentials = RegistryCredentials::default();
+        credentials.insert(&mock_server.uri(), "ci", "hunter2");
+        let tmp = TempDir::new().unwrap();
+        let downloader =
+            Downloader::new(BlobCache::new(tmp.path()).unwrap()).with_credentials(credentials);
+
+        for (blob, content) in [("a", &first[..]), ("b", &second[..])] {
+            let url = format!("{}/v2/acme/mirror/test/blobs/{blob}", mock_server.uri());
+            let sha256 = format!("{:x}", Sha256::digest(content));
+            let blob_path = downloader.download(&url, &sha256).await.unwrap();
+            assert_eq!(std::fs::read(blob_path).unwrap(), content);
+        }
+        // Dropping the server verifies the token was only requested once.
+    }
+
+    #[tokio::test]
+    async fn private_registry_without_credentials_explains_how_to_authenticate() {
+        let mock_server = MockServer::start().await;
+        mount_private_registry(&mock_server, 0).await;
+        mount_private_blob(&mock_server, "a", b"hello world").await;
+
+        let tmp = TempDir::new().unwrap();
+        let downloader = Downloader::new(BlobCache::new(tmp.path()).unwrap());
+
+        let url = format!("{}/v2/acme/mirror/test/blobs/a", mock_server.uri());
+        let err = downloader
+            .download(
+                &url,
+                "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            )
+            .await
+            .unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("ZEROBREW_GITHUB_TOKEN"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn private_registry_reports_rejected_credentials() {
+        let mock_server = MockServer::start().await;
+        mount_private_registry(&mock_server, 0).await;
+
+        let mut credentials = RegistryCredentials::default();
+        credentials.insert(&mock_server.uri(), "ci", "wrong-password");
+        let tmp = TempDir::new().unwrap();
+        let downloader =
+            Downloader::new(BlobCache::new(tmp.path()).unwrap()).with_credentials(credentials);
+
+        let url = format!("{}/v2/acme/mirror/test/blobs/a", mock_server.uri());
+        let err = downloader
+            .download(
+                &url,
+                "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            )
+            .await
+            .unwrap_err();
+
+        let message = err.to_string();
+        assert!(
+            message.contains("rejected the configured credentials"),
+            "{message}"
+        );
+        assert!(!message.contains("wrong-password"));
+    }
+
     #[tokio::test]
     async fn skips_download_if_blob_exists() {
         let mock_server = MockServer::start().await;
@@ -559,4 +789,41 @@ mod tests {
 
         assert!(result.is_ok());
     }
+
+    #[tokio::test]
+    async fn download_size_answers_the_registry_challenge() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/v2/homebrew/core/jq/blobs/sha256:abc"))
+            .and(header("Authorization", "Bearer size-token"))
+            .respond_with(ResponseTemplate::new(200).append_header("Content-Length", "1234"))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/v2/homebrew/core/jq/blobs/sha256:abc"))
+            .respond_with(ResponseTemplate::new(401).append_header(
+                "WWW-Authenticate",
+                format!(
+                    "Bearer realm=\"{}/token\",service=\"ghcr.io\",scope=\"repository:homebrew/core/jq:pull\"",
+                    mock_server.uri()
+                ),
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/token"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "token": "size-token" })),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let tmp = TempDir::new().unwrap();
+        let downloader = Downloader::new(BlobCache::new(tmp.path()).unwrap());
+        let url = format!("{}/v2/homebrew/core/jq/blobs/sha256:abc", mock_server.uri());
+
+        assert_eq!(downloader.download_size(&url, "abc").await, Some(1234));
+    }
 }
@@ -0,0 +1,237 @@
+use std::error::Error as StdError;
+
+use reqwest::StatusCode;
+use tracing::warn;
+use zb_core::Error;
+
+/// PEM file of extra root certificates trusted on top of the system store,
+/// for networks whose proxy re-signs HTTPS traffic.
+pub const CA_BUNDLE_ENV: &str = "ZEROBREW_CA_BUNDLE";
+
+/// Turn a failed `send()` for `url` into the error class the user sees.
+pub(crate) fn request_error(err: &reqwest::Error, url: &str) -> Error {
+    if err.is_timeout() {
+        return Error::NetworkTimeout {
+            url: url.to_string(),
+        };
+    }
+    classify_chain(err, url).unwrap_or_else(|| Error::NetworkFailure {
+        message: format!("request to {url} failed: {}", describe(err)),
+    })
+}
+
+/// The error for a response to `url` that came back with `status`.
+pub(crate) fn status_error(status: StatusCode, url: &str) -> Error {
+    Error::HttpStatus {
+        url: url.to_string(),
+        status: status.as_u16(),
+    }
+}
+
+/// Whether a request that failed with `err` is worth sending again. Timeouts,
+/// dropped connections and server-side statuses can clear up on their own;
+/// DNS, TLS and client errors will fail the same way every time.
+pub(crate) fn is_retryable(err: &Error) -> bool {
+    match err {
+        Error::NetworkTimeout { .. } | Error::NetworkFailure { .. } => true,
+        Error::HttpStatus { status, .. } => {
+            *status >= 500
+                || *status == StatusCode::TOO_MANY_REQUESTS.as_u16()
+                || *status == StatusCode::REQUEST_TIMEOUT.as_u16()
+        }
+        _ => false,
+    }
+}
+
+/// Walk the source chain of a transport error looking for DNS, TLS and
+/// timeout causes. reqwest only exposes coarse flags, so the lower layers
+/// (hyper-util, rustls, the OS resolver) are recognised by type or message.
+fn classify_chain(err: &(dyn StdError + 'static), url: &str) -> Option<Error> {
+    let host = host_of(url);
+    let mut current = Some(err);
+    while let Some(cause) = current {
+        if let Some(tls) = cause.downcast_ref::<rustls::Error>() {
+            return Some(Error::TlsFailure {
+                host,
+                message: tls.to_string(),
+            });
+        }
+        let io = cause.downcast_ref::<std::io::Error>();
+        if io.is_some_and(|io| io.kind() == std::io::ErrorKind::TimedOut) {
+            return Some(Error::NetworkTimeout {
+                url: url.to_string(),
+            });
+        }
+
+        let message = cause.to_string().to_ascii_lowercase();
+        if message.starts_with("dns error")
+            || message.contains("failed to lookup address")
+            || message.contains("name or service not known")
+            || message.contains("nodename nor servname")
+            || message.contains("no such host")
+        {
+            return Some(Error::DnsFailure { host });
+        }
+        if message.contains("certificate") || message.contains("handshake") {
+            return Some(Error::TlsFailure {
+                host,
+                message: cause.to_string(),
+            });
+        }
+        // `io::Error::source` skips the error it wraps, so step into it.
+        current = match io.and_then(std::io::Error::get_ref) {
+            Some(inner) => Some(inner as &(dyn StdError + 'static)),
+            None => cause.source(),
+        };
+    }
+    None
+}
+
+fn host_of(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.host_str().map(str::to_string))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// reqwest's own message is usually just "error sending request"; the root
+/// cause is the useful part.
+fn describe(err: &(dyn StdError + 'static)) -> String {
+    let mut root = err;
+    while let Some(source) = root.source() {
+        root = source;
+    }
+    root.to_string()
+}
+
+fn ca_bundle_pem() -> Option<Vec<u8>> {
+    let path = std::env::var_os(CA_BUNDLE_ENV).filter(|path| !path.is_empty())?;
+    match std::fs::read(&path) {
+        Ok(pem) => Some(pem),
+        Err(e) => {
+            warn!(path = %path.to_string_lossy(), error = %e, "failed to read {CA_BUNDLE_ENV}");
+            None
+        }
+    }
+}
+
+/// Certificates from `ZEROBREW_CA_BUNDLE`, trusted on top of the system roots.
+pub(crate) fn ca_bundle_certificates() -> Vec<reqwest::Certificate> {
+    let Some(pem) = ca_bundle_pem() else {
+        return Vec::new();
+    };
+    reqwest::Certificate::from_pem_bundle(&pem).unwrap_or_else(|e| {
+        warn!(error = %e, "failed to parse {CA_BUNDLE_ENV}");
+        Vec::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn slow_server_is_a_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let url = format!("{}/jq.json", server.uri());
+        let err = client.get(&url).send().await.unwrap_err();
+
+        let classified = request_error(&err, &url);
+        assert_eq!(classified, Error::NetworkTimeout { url });
+        assert!(is_retryable(&classified));
+    }
+
+    #[tokio::test]
+    async fn plain_http_server_is_a_tls_failure() {
+        let server = MockServer::start().await;
+        let url = server.uri().replacen("http://", "https://", 1);
+
+        let err = reqwest::Client::new().get(&url).send().await.unwrap_err();
+
+        let classified = request_error(&err, &url);
+        assert!(
+            matches!(&classified, Error::TlsFailure { host, .. } if host == "127.0.0.1"),
+            "{classified:?}"
+        );
+        assert!(!is_retryable(&classified));
+    }
+
+    #[test]
+    fn untrusted_certificate_is_a_tls_failure() {
+        let cause = std::io::Error::other(rustls::Error::InvalidCertificate(
+            rustls::CertificateError::UnknownIssuer,
+        ));
+
+        let classified = classify_chain(&cause, "https://ghcr.io/v2/").unwrap();
+        assert!(matches!(classified, Error::TlsFailure { ref host, .. } if host == "ghcr.io"));
+        assert!(classified.to_string().contains(CA_BUNDLE_ENV));
+    }
+
+    #[test]
+    fn resolver_failure_is_a_dns_failure() {
+        let cause = std::io::Error::other(
+            "failed to lookup address information: Name or service not known",
+        );
+
+        let classified =
+            classify_chain(&cause, "https://formulae.brew.sh/api/formula/jq.json").unwrap();
+        assert_eq!(
+            classified,
+            Error::DnsFailure {
+                host: "formulae.brew.sh".to_string()
+            }
+        );
+        assert!(
+            classified
+                .to_string()
+                .contains("cannot resolve formulae.brew.sh")
+        );
+        assert!(!is_retryable(&classified));
+    }
+
+    #[test]
+    fn refused_connection_stays_a_retryable_network_failure() {
+        let cause = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+
+        assert!(classify_chain(&cause, "http://127.0.0.1:1/").is_none());
+        assert!(is_retryable(&Error::NetworkFailure {
+            message: cause.to_string()
+        }));
+    }
+
+    #[tokio::test]
+    async fn error_status_carries_the_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/jq.json", server.uri());
+        let response = reqwest::get(&url).await.unwrap();
+
+        let classified = status_error(response.status(), &url);
+        assert_eq!(
+            classified,
+            Error::HttpStatus {
+                url: url.clone(),
+                status: 503
+            }
+        );
+        assert!(classified.to_string().contains(&url));
+        assert!(is_retryable(&classified));
+        assert!(!is_retryable(&status_error(StatusCode::FORBIDDEN, &url)));
+    }
+}
@@ -1,11 +1,19 @@
 pub mod api;
 pub mod cache;
+pub mod client;
 pub mod download;
+pub mod failure;
+pub mod search;
+pub mod snapshot;
 pub mod suggest;
 pub mod tap_formula;
 
 pub use api::ApiClient;
 pub use cache::{ApiCache, CacheEntry};
+pub use client::{ConnectionStats, HttpClient};
 pub use download::{
-    DownloadProgressCallback, DownloadRequest, DownloadResult, Downloader, ParallelDownloader,
+    BlobSource, DownloadProgressCallback, DownloadRequest, DownloadResult, Downloader,
+    ParallelDownloader, RegistryCredentials,
 };
+pub use search::{MatchKind, SearchEntry, SearchIndex};
+pub use snapshot::FormulaSnapshot;
@@ -0,0 +1,207 @@
+//! Names, aliases and descriptions from the bulk formula index, kept small
+//! enough to search without parsing the whole index each time.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use zb_core::Error;
+
+use crate::network::suggest::rank_formula_suggestions;
+
+/// How many near misses are offered when nothing contains the query.
+const FUZZY_MATCH_LIMIT: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchEntry {
+    pub name: String,
+    /// Aliases and old names.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub desc: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchIndex {
+    pub entries: Vec<SearchEntry>,
+}
+
+/// Why a formula matched, best first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    /// The name or one of its aliases, ignoring case.
+    Exact,
+    /// The name starts with the query (`openssl` finds `openssl@3`).
+    Prefix,
+    /// The query is somewhere in the name or an alias.
+    Name,
+    /// The query is somewhere in the description.
+    Description,
+    /// The name is a few edits away from the query, which nothing contains.
+    Fuzzy,
+}
+
+impl SearchIndex {
+    /// Keep what search needs from the raw bulk formula index.
+    pub fn from_bulk(raw: &str) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct BulkEntry {
+            name: String,
+            #[serde(default)]
+            aliases: Vec<String>,
+            #[serde(default)]
+            oldnames: Vec<String>,
+            #[serde(default)]
+            desc: Option<String>,
+        }
+
+        let bulk: Vec<BulkEntry> = serde_json::from_str(raw)
+            .map_err(Error::network("failed to parse bulk formula JSON"))?;
+        let entries = bulk
+            .into_iter()
+            .map(|entry| SearchEntry {
+                name: entry.name,
+                aliases: entry.aliases.into_iter().chain(entry.oldnames).collect(),
+                desc: entry.desc.filter(|desc| !desc.trim().is_empty()),
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// Formulas whose name, alias or description contains `query`, ignoring
+    /// case, sorted by [`MatchKind`] then name. When none do, names a few
+    /// edits away from it, closest first.
+    pub fn search(&self, query: &str) -> Vec<(&SearchEntry, MatchKind)> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut matches: Vec<(&SearchEntry, MatchKind)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| Some((entry, match_kind(entry, &query)?)))
+            .collect();
+        if matches.is_empty() {
+            return self.near_misses(&query);
+        }
+
+        matches.sort_by(|a, b| match a.1.cmp(&b.1) {
+            Ordering::Equal => a.0.name.cmp(&b.0.name),
+            order => order,
+        });
+        matches
+    }
+
+    /// Closest first.
+    fn near_misses(&self, query: &str) -> Vec<(&SearchEntry, MatchKind)> {
+        let by_name: HashMap<&str, &SearchEntry> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry))
+            .collect();
+        let names: Vec<String> = self.entries.iter().map(|e| e.name.clone()).collect();
+        rank_formula_suggestions(query, &names, FUZZY_MATCH_LIMIT)
+            .iter()
+            .filter_map(|name| Some((*by_name.get(name.as_str())?, MatchKind::Fuzzy)))
+            .collect()
+    }
+}
+
+/// `query` is already lowercase.
+fn match_kind(entry: &SearchEntry, query: &str) -> Option<MatchKind> {
+    let name = entry.name.to_lowercase();
+    let aliases: Vec<String> = entry.aliases.iter().map(|a| a.to_lowercase()).collect();
+    if name == query || aliases.iter().any(|alias| alias == query) {
+        Some(MatchKind::Exact)
+    } else if name.starts_with(query) {
+        Some(MatchKind::Prefix)
+    } else if name.contains(query) || aliases.iter().any(|alias| alias.contains(query)) {
+        Some(MatchKind::Name)
+    } else if entry
+        .desc
+        .as_ref()
+        .is_some_and(|desc| desc.to_lowercase().contains(query))
+    {
+        Some(MatchKind::Description)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> SearchIndex {
+        SearchIndex::from_bulk(
+            r#"[
+                {"name": "openssl@3", "aliases": ["openssl"], "desc": "Cryptography and SSL/TLS Toolkit"},
+                {"name": "openssl@1.1", "oldnames": ["openssl11"], "desc": "Cryptography and SSL/TLS Toolkit"},
+                {"name": "libressl", "desc": "Version of the SSL/TLS protocol forked from OpenSSL"},
+                {"name": "jq", "desc": "Lightweight and flexible command-line JSON processor"},
+                {"name": "jless", "desc": ""},
+                {"name": "wget", "desc": "Internet file retriever"}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    fn names<'a>(matches: &[(&'a SearchEntry, MatchKind)]) -> Vec<(&'a str, MatchKind)> {
+        matches
+            .iter()
+            .map(|(e, kind)| (e.name.as_str(), *kind))
+            .collect()
+    }
+
+    #[test]
+    fn bulk_entries_keep_aliases_old_names_and_descriptions() {
+        let index = index();
+        assert_eq!(index.entries[1].aliases, ["openssl11"]);
+        assert_eq!(index.entries[4].desc, None);
+        assert_eq!(
+            index.entries[3].desc.as_deref(),
+            Some("Lightweight and flexible command-line JSON processor")
+        );
+    }
+
+    #[test]
+    fn matches_are_ranked_exact_then_prefix_then_name_then_description() {
+        let index = index();
+        assert_eq!(
+            names(&index.search("OpenSSL")),
+            [
+                ("openssl@3", MatchKind::Exact),
+                ("openssl@1.1", MatchKind::Prefix),
+                ("libressl", MatchKind::Description),
+            ]
+        );
+        assert_eq!(
+            names(&index.search("openssl@3")),
+            [("openssl@3", MatchKind::Exact)]
+        );
+        assert_eq!(
+            names(&index.search("ssl11")),
+            [("openssl@1.1", MatchKind::Name)]
+        );
+        assert_eq!(
+            names(&index.search("json")),
+            [("jq", MatchKind::Description)]
+        );
+    }
+
+    #[test]
+    fn near_misses_are_matched_fuzzily() {
+        assert_eq!(names(&index().search("wgte")), [("wget", MatchKind::Fuzzy)]);
+        assert_eq!(
+            names(&index().search("opensl@3")),
+            [
+                ("openssl@3", MatchKind::Fuzzy),
+                ("openssl@1.1", MatchKind::Fuzzy)
+            ]
+        );
+        assert!(index().search("  ").is_empty());
+    }
+}
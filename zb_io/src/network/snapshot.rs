@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use zb_core::{Error, Formula};
+
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+const SNAPSHOT_COMPRESSION_LEVEL: i32 = 9;
+
+/// A point-in-time copy of formula metadata. While one is loaded every
+/// formula lookup is answered from it, so installs cannot drift when
+/// homebrew/core moves on. Bottle shas come from the snapshotted metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormulaSnapshot {
+    pub format_version: u32,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    /// The bulk formula index exactly as served by the API.
+    index: String,
+    /// Metadata fetched individually at creation time: the requested
+    /// formulas and their dependency closure, including tap formulas.
+    formulas: BTreeMap<String, Formula>,
+    #[serde(skip)]
+    index_entries: HashMap<String, serde_json::Value>,
+}
+
+impl FormulaSnapshot {
+    pub fn new(index: String, formulas: BTreeMap<String, Formula>) -> Result<Self, Error> {
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let mut snapshot = Self {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            created_at,
+            index,
+            formulas,
+            index_entries: HashMap::new(),
+        };
+        snapshot.index_entries = parse_index(&snapshot.index)?;
+        Ok(snapshot)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let ctx = format!("failed to read snapshot {}", path.display());
+        let compressed = fs::read(path).map_err(Error::file(ctx.as_str()))?;
+        let json = zstd::decode_all(compressed.as_slice()).map_err(Error::file(ctx.as_str()))?;
+
+        let mut snapshot: Self =
+            serde_json::from_slice(&json).map_err(|e| Error::InvalidArgument {
+                message: format!("invalid snapshot {}: {e}", path.display()),
+            })?;
+        if snapshot.format_version != SNAPSHOT_FORMAT_VERSION {
+            return Err(Error::InvalidArgument {
+                message: format!(
+                    "unsupported snapshot format version {} (expected {SNAPSHOT_FORMAT_VERSION})",
+                    snapshot.format_version
+                ),
+            });
+        }
+        snapshot.index_entries = parse_index(&snapshot.index)?;
+        Ok(snapshot)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_vec(self).map_err(|e| Error::StoreCorruption {
+            message: format!("failed to serialize snapshot: {e}"),
+        })?;
+        let ctx = format!("failed to write snapshot {}", path.display());
+        let compressed = zstd::encode_all(json.as_slice(), SNAPSHOT_COMPRESSION_LEVEL)
+            .map_err(Error::file(ctx.as_str()))?;
+
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, compressed).map_err(Error::file(ctx.as_str()))?;
+        fs::rename(&tmp, path).map_err(Error::file(ctx.as_str()))
+    }
+
+    /// Number of distinct formulas the snapshot can resolve.
+    pub fn formula_count(&self) -> usize {
+        self.index_entries.len()
+            + self
+                .formulas
+                .keys()
+                .filter(|name| !self.index_entries.contains_key(*name))
+                .count()
+    }
+
+    pub fn raw_index(&self) -> &str {
+        &self.index
+    }
+
+    pub fn formula(&self, name: &str) -> Result<Formula, Error> {
+        if let Some(formula) = self.formulas.get(name) {
+            return Ok(formula.clone());
+        }
+
+        let entry = self
+            .index_entries
+            .get(name)
+            .ok_or_else(|| Error::MissingFormula {
+                name: name.to_string(),
+            })?;
+        Formula::from_value(name, entry.clone())
+    }
+}
+
+/// The field the bulk formula index is keyed by. The rest of an entry is
+/// only parsed, as a [`Formula`], when that formula is looked up.
+#[derive(Deserialize)]
+struct IndexEntryName {
+    name: String,
+}
+
+/// The name of a bulk index entry.
+pub(crate) fn index_entry_name(entry: &serde_json::Value) -> Result<String, Error> {
+    IndexEntryName::deserialize(entry)
+        .map(|entry| entry.name)
+        .map_err(|e| Error::InvalidArgument {
+            message: format!("formula index entry has no name: {e}"),
+        })
+}
+
+fn parse_index(raw: &str) -> Result<HashMap<String, serde_json::Value>, Error> {
+    let entries: Vec<serde_json::Value> =
+        serde_json::from_str(raw).map_err(|e| Error::InvalidArgument {
+            message: format!("snapshot formula index is not valid JSON: {e}"),
+        })?;
+
+    // One malformed entry shouldn't cost the rest of the index; it's only
+    // missing from lookups.
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| match index_entry_name(&entry) {
+            Ok(name) => Some((name, entry)),
+            Err(e) => {
+                warn!(error = %e, "skipping formula index entry");
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const JQ: &str = include_str!("../../../zb_core/fixtures/formula_api_jq.json");
+    const SQLITE: &str = include_str!("../../../zb_core/fixtures/formula_api_sqlite.json");
+    const TERRAFORM: &str = include_str!("../../../zb_core/fixtures/formula_api_terraform.json");
+
+    #[test]
+    fn round_trips_through_compressed_file() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("core.snapshot");
+        let index = format!("[{JQ},{SQLITE}]");
+        let mut tap: serde_json::Value = serde_json::from_str(TERRAFORM).unwrap();
+        tap["name"] = "user/tap/tool".into();
+        let tap = Formula::from_value("user/tap/tool", tap).unwrap();
+
+        let snapshot =
+            FormulaSnapshot::new(index, BTreeMap::from([("user/tap/tool".to_string(), tap)]))
+                .unwrap();
+        snapshot.write(&path).unwrap();
+
+        let loaded = FormulaSnapshot::read(&path).unwrap();
+        assert_eq!(loaded.created_at, snapshot.created_at);
+        assert_eq!(loaded.formula_count(), 3);
+        assert_eq!(loaded.formula("jq").unwrap().name, "jq");
+        assert_eq!(
+            loaded.formula("user/tap/tool").unwrap().name,
+            "user/tap/tool"
+        );
+        assert!(matches!(
+            loaded.formula("missing"),
+            Err(Error::MissingFormula { .. })
+        ));
+    }
+
+    #[test]
+    fn skips_index_entries_without_a_name() {
+        let mut nameless: serde_json::Value = serde_json::from_str(TERRAFORM).unwrap();
+        nameless.as_object_mut().unwrap().remove("name");
+        let index = format!("[{JQ},{nameless},{SQLITE}]");
+
+        let snapshot = FormulaSnapshot::new(index, BTreeMap::new()).unwrap();
+        assert_eq!(snapshot.formula_count(), 2);
+        assert_eq!(snapshot.formula("sqlite").unwrap().name, "sqlite");
+        assert!(matches!(
+            snapshot.formula("terraform"),
+            Err(Error::MissingFormula { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_format_version() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("future.snapshot");
+        let json = r#"{"format_version":99,"created_at":0,"index":"[]","formulas":{}}"#;
+        fs::write(&path, zstd::encode_all(json.as_bytes(), 0).unwrap()).unwrap();
+
+        let err = FormulaSnapshot::read(&path).unwrap_err();
+        assert!(err.to_string().contains("format version 99"));
+    }
+}
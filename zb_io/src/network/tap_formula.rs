@@ -25,6 +25,9 @@ static URL_VERSION_RE: LazyLock<Regex> = LazyLock::new(|| {
 static REVISION_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r#"(?m)^\s*revision\s+(\d+)\s*$"#).expect("REVISION_RE must compile")
 });
+static LICENSE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?m)^\s*license\s+["']([^"']+)["']\s*$"#).expect("LICENSE_RE must compile")
+});
 static DEPENDS_ON_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r#"(?m)^\s*depends_on\s+["']([^"']+)["'](.*)$"#).expect("DEPENDS_ON_RE must compile")
 });
@@ -55,6 +58,9 @@ static ROOT_URL_RE: LazyLock<Regex> = LazyLock::new(|| {
 static REBUILD_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r#"(?m)^\s*rebuild\s+(\d+)\s*$"#).expect("REBUILD_RE must compile")
 });
+static BOTTLE_CELLAR_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\bcellar:\s*(:[a-z_]+|"[^"]+")"#).expect("BOTTLE_CELLAR_RE must compile")
+});
 static BOTTLE_SHA_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r#"([a-z0-9_]+):\s*"([0-9a-f]{64})""#).expect("BOTTLE_SHA_RE must compile")
 });
@@ -385,6 +391,13 @@ pub fn parse_tap_formula_ruby(spec: &TapFormulaRef, source: &str) -> Result<Form
         uses_from_macos: Vec::new(),
         requirements: Vec::new(),
         variations: None,
+        deprecated: false,
+        deprecation_reason: None,
+        deprecation_date: None,
+        disabled: false,
+        disable_reason: None,
+        disable_date: None,
+        license: parse_license(&source),
     })
 }
 
@@ -421,6 +434,14 @@ fn parse_revision(source: &str) -> Option<u32> {
         .and_then(|m| m.as_str().parse::<u32>().ok())
 }
 
+/// Only a plain `license "MIT"`; `any_of:` and friends are left unknown.
+fn parse_license(source: &str) -> Option<String> {
+    LICENSE_RE
+        .captures(source)
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str().to_string())
+}
+
 fn parse_runtime_dependencies(source: &str) -> Vec<String> {
     let mut deps = Vec::new();
     let body = extract_formula_class_body(source).unwrap_or(source);
@@ -638,24 +659,32 @@ fn parse_bottle_files(
 ) -> BTreeMap<String, BottleFile> {
     let mut files = BTreeMap::new();
 
-    for cap in BOTTLE_SHA_RE.captures_iter(block) {
-        let Some(tag) = cap.get(1).map(|m| m.as_str()) else {
-            continue;
-        };
-        let Some(sha) = cap.get(2).map(|m| m.as_str()) else {
-            continue;
-        };
-        if tag == "cellar" {
-            continue;
+    for line in block.lines() {
+        let cellar = BOTTLE_CELLAR_RE
+            .captures(line)
+            .and_then(|c| c.get(1))
+            .map(|m| m.as_str().trim_matches('"').to_string());
+
+        for cap in BOTTLE_SHA_RE.captures_iter(line) {
+            let Some(tag) = cap.get(1).map(|m| m.as_str()) else {
+                continue;
+            };
+            let Some(sha) = cap.get(2).map(|m| m.as_str()) else {
+                continue;
+            };
+            if tag == "cellar" {
+                continue;
+            }
+            let url = build_bottle_url(spec, root_url, stable, revision, rebuild, tag, sha);
+            files.insert(
+                tag.to_string(),
+                BottleFile {
+                    url,
+                    sha256: sha.to_string(),
+                    cellar: cellar.clone(),
+                },
+            );
         }
-        let url = build_bottle_url(spec, root_url, stable, revision, rebuild, tag, sha);
-        files.insert(
-            tag.to_string(),
-            BottleFile {
-                url,
-                sha256: sha.to_string(),
-            },
-        );
     }
 
     files
@@ -671,7 +700,7 @@ fn build_bottle_url(
     sha: &str,
 ) -> String {
     let normalized = root_url.trim_end_matches('/');
-    if normalized.contains("/v2/") {
+    if is_registry_root(normalized) {
         return format!("{}/{}/blobs/sha256:{}", normalized, spec.formula, sha);
     }
 
@@ -681,17 +710,38 @@ fn build_bottle_url(
         stable.to_string()
     };
 
-    if rebuild > 0 {
-        format!(
-            "{}/{}-{}.{}.{}.bottle.tar.gz",
-            normalized, spec.formula, effective_version, rebuild, tag
-        )
+    // Homebrew's bottle file name: the rebuild goes after `bottle`.
+    let rebuild = if rebuild > 0 {
+        format!(".{rebuild}")
     } else {
-        format!(
-            "{}/{}-{}.{}.bottle.tar.gz",
-            normalized, spec.formula, effective_version, tag
-        )
+        String::new()
+    };
+    let file_name = format!(
+        "{}-{effective_version}.{tag}.bottle{rebuild}.tar.gz",
+        spec.formula
+    );
+    format!("{normalized}/{}", url_encode(&file_name))
+}
+
+/// Whether `root_url` is an OCI registry's repository, whose bottles are
+/// blobs named by digest (`https://ghcr.io/v2/<owner>/<tap>`), rather than
+/// a directory of bottle files on plain HTTPS hosting.
+fn is_registry_root(root_url: &str) -> bool {
+    reqwest::Url::parse(root_url).is_ok_and(|url| url.path().starts_with("/v2/"))
+}
+
+/// Percent-encode everything but unreserved characters, as Homebrew does
+/// for bottle file names (`openssl@3` becomes `openssl%403`).
+fn url_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
     }
+    encoded
 }
 
 #[cfg(test)]
@@ -746,6 +796,12 @@ end
         assert_eq!(formula.build_dependencies, vec!["go".to_string()]);
         assert!(formula.bottle.stable.files.contains_key("arm64_sonoma"));
         assert!(formula.bottle.stable.files.contains_key("x86_64_linux"));
+        assert_eq!(
+            formula.bottle.stable.files["x86_64_linux"]
+                .cellar
+                .as_deref(),
+            Some(":any_skip_relocation")
+        );
     }
 
     #[test]
@@ -796,6 +852,43 @@ end
         );
     }
 
+    #[test]
+    fn static_bottle_urls_follow_homebrew_file_naming() {
+        let source = r#"
+class OpensslAT3 < Formula
+  version "3.4.0"
+  revision 1
+  bottle do
+    root_url "https://bottles.example.com/v2-archive/"
+    rebuild 2
+    sha256 x86_64_linux: "054859a821b01d3dd7236e71fbf106f7a694ded54ae6aaaed221b59d3b554c42"
+  end
+end
+"#;
+        let spec = TapFormulaRef {
+            owner: "acme".to_string(),
+            repo: "tools".to_string(),
+            formula: "openssl@3".to_string(),
+        };
+        let formula = parse_tap_formula_ruby(&spec, source).unwrap();
+        assert_eq!(
+            formula.bottle.stable.files["x86_64_linux"].url,
+            "https://bottles.example.com/v2-archive/openssl%403-3.4.0_1.x86_64_linux.bottle.2.tar.gz"
+        );
+    }
+
+    #[test]
+    fn only_registry_roots_use_blob_urls() {
+        assert!(is_registry_root("https://ghcr.io/v2/hashicorp/tap"));
+        assert!(is_registry_root("http://127.0.0.1:8080/v2/acme/tools"));
+        assert!(!is_registry_root(
+            "https://example.com/mirror/v2/acme/tools"
+        ));
+        assert!(!is_registry_root(
+            "https://github.com/messense/homebrew-tap/releases/download/ttfb-1.3.0"
+        ));
+    }
+
     #[test]
     fn infers_version_from_url_when_version_field_missing() {
         let source = r#"
@@ -1226,6 +1319,7 @@ end
         let formula = parse_tap_formula_ruby(&spec, source).unwrap();
         assert_eq!(formula.name, "sag");
         assert_eq!(formula.versions.stable, "0.2.2");
+        assert_eq!(formula.license.as_deref(), Some("MIT"));
 
         let stable = formula
             .urls
@@ -1,5 +1,12 @@
-/// Progress events during installation
-#[derive(Debug, Clone)]
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use zb_core::Error;
+
+/// Progress events during installation. Serialized with the variant name,
+/// kebab-cased, under `event` (`{"event":"download-started",...}`).
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
 pub enum InstallProgress {
     /// Starting to download a package (with total size if known)
     DownloadStarted {
@@ -14,8 +21,26 @@ pub enum InstallProgress {
     },
     /// Download completed for a package
     DownloadCompleted { name: String, total_bytes: u64 },
+    /// The package's bottle was already in the download cache
+    DownloadCached { name: String },
+    /// A host answered with a rate limit; downloads from it pause
+    RateLimited { host: String, wait_secs: u64 },
     /// Starting to unpack/materialize a package
     UnpackStarted { name: String },
+    /// Files copied into the Cellar so far while materializing a package
+    UnpackProgress {
+        name: String,
+        files: u64,
+        bytes: u64,
+    },
+    /// Files patched so far out of the keg's patch candidates
+    PatchProgress {
+        name: String,
+        files: u64,
+        total: u64,
+    },
+    /// Materializing a package moved on to another phase
+    UnpackPhase { name: String, phase: Phase },
     /// Unpacking completed for a package
     UnpackCompleted { name: String },
     /// Starting to link a package
@@ -24,9 +49,98 @@ pub enum InstallProgress {
     LinkCompleted { name: String },
     /// Linking skipped (keg-only or conflict)
     LinkSkipped { name: String, reason: String },
+    /// The new keg couldn't be linked, so the `version` it replaced was
+    /// linked again and kept installed
+    LinkRestored { name: String, version: String },
     /// Installation completed for a package (final state)
     InstallCompleted { name: String },
 }
 
 /// Callback type for progress reporting
 pub type ProgressCallback = Box<dyn Fn(InstallProgress) + Send + Sync>;
+
+/// Stages of [`Cellar::materialize_with_observer`](crate::Cellar::materialize_with_observer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// Copying the store entry into the Cellar
+    Copy,
+    /// Clearing unsafe permission bits
+    Permissions,
+    /// Rewriting install names and RPATHs in binaries
+    PatchBinaries,
+    /// Rewriting placeholders in text files
+    PatchText,
+    /// Ad-hoc signing Mach-O binaries
+    Codesign,
+}
+
+/// Hooks into a single materialization. Every method has a no-op default.
+/// Methods may be called from several threads at once while patching.
+pub trait MaterializeObserver: Sync {
+    /// Called after each regular file is copied or hard-linked. Clonefile
+    /// copies the whole tree at once and only reports [`Phase::Copy`].
+    fn on_file_copied(&self, _path: &Path, _bytes: u64) {}
+
+    fn on_phase(&self, _phase: Phase) {}
+
+    /// Called once the keg scan has counted the files the patch passes
+    /// will go through, before any is patched.
+    fn on_patch_started(&self, _counter: &PatchCounter) {}
+
+    /// Called after each candidate file is done with, patched or not. Read
+    /// the count from `counter`; calls from different workers can arrive
+    /// out of order.
+    fn on_file_patched(&self, _counter: &PatchCounter) {}
+
+    /// Polled between files and phases. Returning `true` stops the
+    /// materialization, removes the partial keg and fails with
+    /// [`Error::Cancelled`](zb_core::Error::Cancelled).
+    fn should_cancel(&self) -> bool {
+        false
+    }
+}
+
+/// Observer that ignores every event and never cancels.
+pub struct NoopObserver;
+
+impl MaterializeObserver for NoopObserver {}
+
+/// Files the patch passes have finished out of `total`, shared by the
+/// workers patching them.
+#[derive(Debug, Default)]
+pub struct PatchCounter {
+    total: usize,
+    done: AtomicUsize,
+}
+
+impl PatchCounter {
+    pub fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    /// Count one more file and tell `observer`.
+    pub(crate) fn advance(&self, observer: &dyn MaterializeObserver) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        observer.on_file_patched(self);
+    }
+}
+
+pub(crate) fn check_cancelled(observer: &dyn MaterializeObserver) -> Result<(), Error> {
+    if observer.should_cancel() {
+        Err(Error::Cancelled)
+    } else {
+        Ok(())
+    }
+}
@@ -1,11 +1,13 @@
 use crate::storage::db::Database;
 use std::path::{Path, PathBuf};
+use zb_core::CELLAR_DIR;
 
 pub fn find_ca_bundle(prefix: &Path, db: &Database) -> Option<PathBuf> {
-    let installed = db.get_installed("ca-certificates")?;
+    // Best effort: without a readable database there is simply no bundle.
+    let installed = db.get_installed("ca-certificates").ok()??;
 
     let keg_path = prefix
-        .join("Cellar")
+        .join(CELLAR_DIR)
         .join("ca-certificates")
         .join(&installed.version);
 
@@ -1,16 +1,47 @@
+use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 use tempfile::NamedTempFile;
+use tracing::warn;
 use zb_core::Error;
 
+use super::db::Database;
+
 #[derive(Clone)]
 pub struct BlobCache {
     blobs_dir: PathBuf,
     tmp_dir: PathBuf,
 }
 
+/// What the cache knows about one blob, kept in the `blobs` table of the
+/// database. The files are the source of truth: a blob the index has no
+/// entry for is described from the file alone, with no formula and not
+/// verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobEntry {
+    pub sha256: String,
+    pub size: u64,
+    pub formula: Option<String>,
+    pub added_at: i64,
+    pub last_access: i64,
+    /// The content was checked against its sha256 when it was written.
+    pub verified: bool,
+}
+
+/// What [`BlobCache::rebuild_index`] changed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IndexRebuild {
+    /// Blobs in the cache, all of them indexed now.
+    pub blobs: usize,
+    /// Entries added for blobs the index didn't know about.
+    pub added: usize,
+    /// Entries dropped because their blob is gone or has changed size.
+    pub removed: usize,
+}
+
 impl BlobCache {
     pub fn new(cache_root: &Path) -> io::Result<Self> {
         let blobs_dir = cache_root.join("blobs");
@@ -41,6 +72,85 @@ impl BlobCache {
         }
     }
 
+    /// Every cached blob, sorted by sha256, as the index in `db` describes
+    /// it. Blobs the index is missing or disagrees with, and all of them
+    /// when it can't be read, are described from their file.
+    pub fn entries(&self, db: &Database) -> io::Result<Vec<BlobEntry>> {
+        let mut indexed: HashMap<String, BlobEntry> = db
+            .list_blob_entries()
+            .unwrap_or_else(|e| {
+                warn!(error = %e, "failed to read the blob cache index");
+                Vec::new()
+            })
+            .into_iter()
+            .map(|entry| (entry.sha256.clone(), entry))
+            .collect();
+        self.list_blobs()?
+            .into_iter()
+            .map(|(sha256, size)| match indexed.remove(&sha256) {
+                Some(entry) if entry.size == size => Ok(entry),
+                _ => self.file_entry(&sha256),
+            })
+            .collect()
+    }
+
+    /// Make the index in `db` match the blobs on disk: entries are added
+    /// for blobs it doesn't know about and dropped for blobs that are gone
+    /// or changed size.
+    pub fn rebuild_index(&self, db: &mut Database) -> Result<IndexRebuild, Error> {
+        let on_disk = self
+            .list_blobs()
+            .and_then(|blobs| {
+                blobs
+                    .iter()
+                    .map(|(sha256, _)| self.file_entry(sha256))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .map_err(Error::store("failed to read blob cache"))?;
+        let (added, removed) = db.reconcile_blob_entries(&on_disk)?;
+        Ok(IndexRebuild {
+            blobs: on_disk.len(),
+            added,
+            removed,
+        })
+    }
+
+    /// Every cached blob as `(sha256, size in bytes)`, sorted by sha256.
+    pub fn list_blobs(&self) -> io::Result<Vec<(String, u64)>> {
+        let mut blobs = Vec::new();
+        for entry in fs::read_dir(&self.blobs_dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if let Some(sha256) = name.strip_suffix(".tar.gz") {
+                blobs.push((sha256.to_string(), metadata.len()));
+            }
+        }
+        blobs.sort();
+        Ok(blobs)
+    }
+
+    /// Partial downloads left behind by interrupted runs, sorted. Older
+    /// versions wrote them next to the blobs as `*.partial`.
+    pub fn temp_files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = fs::read_dir(&self.tmp_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        for entry in fs::read_dir(&self.blobs_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "partial") {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
     pub fn start_write(&self, sha256: &str) -> io::Result<BlobWriter> {
         let final_path = self.blob_path(sha256);
         let temp_file = NamedTempFile::new_in(&self.tmp_dir)?;
@@ -49,6 +159,24 @@ impl BlobCache {
             final_path,
         })
     }
+
+    /// Describe the cached blob `sha256` from its file alone.
+    fn file_entry(&self, sha256: &str) -> io::Result<BlobEntry> {
+        let metadata = fs::metadata(self.blob_path(sha256))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |age| age.as_secs() as i64);
+        Ok(BlobEntry {
+            sha256: sha256.to_string(),
+            size: metadata.len(),
+            formula: None,
+            added_at: modified,
+            last_access: modified,
+            verified: false,
+        })
+    }
 }
 
 pub struct BlobWriter {
@@ -161,4 +289,84 @@ mod tests {
         let removed = cache.remove_blob("nonexistent").unwrap();
         assert!(!removed);
     }
+
+    #[test]
+    fn list_blobs_reports_sizes_and_skips_partial_writes() {
+        let tmp = TempDir::new().unwrap();
+        let cache = BlobCache::new(tmp.path()).unwrap();
+
+        for (sha, body) in [("bbb", "two"), ("aaa", "one!")] {
+            let mut writer = cache.start_write(sha).unwrap();
+            writer.write_all(body.as_bytes()).unwrap();
+            writer.commit().unwrap();
+        }
+        let mut partial = cache.start_write("ccc").unwrap();
+        partial.write_all(b"half").unwrap();
+
+        assert_eq!(
+            cache.list_blobs().unwrap(),
+            vec![("aaa".to_string(), 4), ("bbb".to_string(), 3)]
+        );
+        assert_eq!(cache.temp_files().unwrap().len(), 1);
+    }
+
+    fn write(cache: &BlobCache, sha: &str, body: &[u8]) {
+        let mut writer = cache.start_write(sha).unwrap();
+        writer.write_all(body).unwrap();
+        writer.commit().unwrap();
+    }
+
+    #[test]
+    fn entries_come_from_the_index_unless_it_disagrees_with_the_file() {
+        let tmp = TempDir::new().unwrap();
+        let cache = BlobCache::new(tmp.path()).unwrap();
+        let db = Database::open(&tmp.path().join("zb.sqlite3")).unwrap();
+        write(&cache, "aaa", b"jq bottle");
+        write(&cache, "bbb", b"wget bottle");
+        db.record_blob("aaa", 9, Some("jq"), true).unwrap();
+        db.record_blob("bbb", 99, Some("wget"), true).unwrap();
+        db.record_blob("gone", 1, None, false).unwrap();
+
+        let entries = cache.entries(&db).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].formula.as_deref(), Some("jq"));
+        assert!(entries[0].verified);
+        // The file changed size since it was indexed.
+        assert_eq!(entries[1].size, 11);
+        assert_eq!(entries[1].formula, None);
+        assert!(!entries[1].verified);
+    }
+
+    #[test]
+    fn rebuilding_the_index_matches_it_to_the_files() {
+        let tmp = TempDir::new().unwrap();
+        let cache = BlobCache::new(tmp.path()).unwrap();
+        let mut db = Database::open(&tmp.path().join("zb.sqlite3")).unwrap();
+        write(&cache, "aaa", b"jq bottle");
+        write(&cache, "bbb", b"wget bottle");
+        db.record_blob("aaa", 9, Some("jq"), true).unwrap();
+        db.record_blob("gone", 1, None, false).unwrap();
+
+        assert_eq!(
+            cache.rebuild_index(&mut db).unwrap(),
+            IndexRebuild {
+                blobs: 2,
+                added: 1,
+                removed: 1,
+            }
+        );
+        let indexed = db.list_blob_entries().unwrap();
+        assert_eq!(indexed.len(), 2);
+        assert_eq!(indexed[0].formula.as_deref(), Some("jq"));
+        assert_eq!(indexed[1].size, 11);
+
+        assert_eq!(
+            cache.rebuild_index(&mut db).unwrap(),
+            IndexRebuild {
+                blobs: 2,
+                added: 0,
+                removed: 0,
+            }
+        );
+    }
 }
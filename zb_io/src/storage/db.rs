@@ -1,11 +1,21 @@
-use std::path::Path;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction, params};
 
-use zb_core::Error;
+use tracing::warn;
+use zb_core::{BuildId, Error, Lifecycle, revision_of};
+
+use super::blob::BlobEntry;
+use crate::extraction::extract::normalize_path;
 
 pub struct Database {
     conn: Connection,
+    /// Bumped by every committed change to an installed keg, so an
+    /// [`InstalledSet`] can tell it is stale without querying.
+    generation: Cell<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -14,6 +24,87 @@ pub struct InstalledKeg {
     pub version: String,
     pub store_key: String,
     pub installed_at: i64,
+    /// False when the keg was only pulled in as a dependency.
+    pub explicit: bool,
+    /// How the keg was copied out of the store; `None` for source builds
+    /// and kegs installed before this was recorded.
+    pub copy_strategy: Option<CopyStrategy>,
+    /// SPDX license expression from the formula metadata; `None` when the
+    /// formula has none or was installed before licenses were recorded.
+    pub license: Option<String>,
+    /// The formula revision, also the `_N` suffix of `version`.
+    pub revision: u32,
+    /// The bottle rebuild; `None` for source builds and kegs installed
+    /// before rebuilds were recorded.
+    pub rebuild: Option<u32>,
+    /// The formula's version when the bottle named its keg directory
+    /// differently (`1.2.3` for a `1.2.3_1` keg); `None` when they agree.
+    /// `version` is always the keg directory.
+    pub formula_version: Option<String>,
+    /// Held at this version by `zb pin`; upgrades leave it alone.
+    pub pinned: bool,
+}
+
+impl InstalledKeg {
+    /// The version to compare with the formula's.
+    pub fn upstream_version(&self) -> &str {
+        self.formula_version.as_deref().unwrap_or(&self.version)
+    }
+
+    /// The build as the formula names it, to compare with the formula's.
+    pub fn build(&self) -> BuildId {
+        BuildId {
+            version: self.upstream_version().to_string(),
+            revision: self.revision,
+            rebuild: self.rebuild,
+        }
+    }
+}
+
+/// Every installed keg by name, read in one query. It is a snapshot: check
+/// [`Database::is_current`] before trusting it after the database may have
+/// changed.
+#[derive(Debug, Clone, Default)]
+pub struct InstalledSet {
+    kegs: HashMap<String, InstalledKeg>,
+    /// The database's generation when the set was read.
+    generation: u64,
+}
+
+impl InstalledSet {
+    pub fn contains(&self, name: &str) -> bool {
+        self.kegs.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&InstalledKeg> {
+        self.kegs.get(name)
+    }
+
+    pub fn version(&self, name: &str) -> Option<&str> {
+        self.kegs.get(name).map(|keg| keg.version.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.kegs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kegs.is_empty()
+    }
+}
+
+/// A previous version of an installed formula, kept unlinked after an
+/// upgrade so it can be rolled back to. It holds a store reference of its
+/// own until it is dropped.
+#[derive(Debug, Clone)]
+pub struct RetainedKeg {
+    pub name: String,
+    pub version: String,
+    pub store_key: String,
+    pub installed_at: i64,
+    /// When a newer version replaced it.
+    pub retained_at: i64,
+    pub copy_strategy: Option<CopyStrategy>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -22,28 +113,194 @@ pub struct StoreRef {
     pub refcount: i64,
 }
 
+/// Which zb created a root, which one used it last, and the platform its
+/// kegs were built for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemRecord {
+    pub created_by: String,
+    pub last_used_by: String,
+    pub os: String,
+    pub arch: String,
+}
+
+impl SystemRecord {
+    /// The running binary, identified by `zb_version`.
+    pub fn current(zb_version: &str) -> Self {
+        Self {
+            created_by: zb_version.to_string(),
+            last_used_by: zb_version.to_string(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+        }
+    }
+}
+
+/// An installed formula upstream has deprecated or disabled, as of the last
+/// time its metadata was fetched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamStatus {
+    pub name: String,
+    /// Never `Lifecycle::Active`; active formulas have no status.
+    pub lifecycle: Lifecycle,
+    /// When upstream deprecated or disabled it, as `YYYY-MM-DD`.
+    pub since: Option<String>,
+    pub checked_at: i64,
+}
+
+/// A maintenance run recorded for `zb history`, such as a gc.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEvent {
+    pub at: i64,
+    /// What ran: `gc` or `cache-clean`.
+    pub kind: String,
+    /// Who ran it, when that could be told.
+    pub user: Option<String>,
+    /// What it did, as the command that ran recorded it.
+    pub details: Option<serde_json::Value>,
+}
+
+/// A `zb relocate` that started and has not finished yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocationState {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Formulas whose kegs already live under `to`.
+    pub relocated: Vec<String>,
+}
+
+/// An installed keg whose files were patched for a prefix other than the
+/// one zb is using now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriftedKeg {
+    pub name: String,
+    pub version: String,
+    pub patched_prefix: PathBuf,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KegFileRecord {
     pub name: String,
     pub version: String,
     pub linked_path: String,
+    /// The absolute keg path the file resolves to.
     pub target_path: String,
+    /// What a symlink literally holds, as `read_link` returns it; `None`
+    /// for other kinds and for links whose target was never known.
+    pub link_target: Option<String>,
+    pub kind: KegFileKind,
+}
+
+/// What a keg put at a recorded prefix path, which decides how uninstall
+/// removes it and how doctor verifies it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KegFileKind {
+    /// A symlink into the keg.
+    Symlink,
+    /// A default config copied into the prefix. It may have been edited, so
+    /// it outlives the keg unless explicitly purged.
+    Config,
+    /// A generated script that execs into the keg.
+    Wrapper,
+    /// A real directory holding links; shared between kegs.
+    Dir,
+}
+
+impl KegFileKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Symlink => "symlink",
+            Self::Config => "config",
+            Self::Wrapper => "wrapper",
+            Self::Dir => "dir",
+        }
+    }
+
+    /// Unknown kinds, written by a newer zb, are treated as symlinks: only
+    /// removed when they still point at their target.
+    fn from_column(value: &str) -> Self {
+        match value {
+            "config" => Self::Config,
+            "wrapper" => Self::Wrapper,
+            "dir" => Self::Dir,
+            _ => Self::Symlink,
+        }
+    }
+}
+
+/// A path linked into the prefix for a keg, as recorded for it.
+#[derive(Debug, Clone)]
+pub struct LinkedFile {
+    pub link_path: PathBuf,
+    /// The absolute keg path the link resolves to.
+    pub target_path: PathBuf,
+    /// What the symlink at `link_path` holds, as `read_link` returns it;
+    /// `None` for directories and wrappers.
+    pub link_target: Option<PathBuf>,
+    pub kind: KegFileKind,
+}
+
+/// How a keg was copied out of its store entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyStrategy {
+    Clonefile,
+    Hardlink,
+    Copy,
+}
+
+impl CopyStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CopyStrategy::Clonefile => "clonefile",
+            CopyStrategy::Hardlink => "hardlink",
+            CopyStrategy::Copy => "copy",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "clonefile" => Some(CopyStrategy::Clonefile),
+            "hardlink" => Some(CopyStrategy::Hardlink),
+            "copy" => Some(CopyStrategy::Copy),
+            _ => None,
+        }
+    }
 }
 
 impl Database {
-    const SCHEMA_VERSION: u32 = 1;
+    const SCHEMA_VERSION: u32 = 17;
 
     pub fn open(path: &Path) -> Result<Self, Error> {
         let conn = Connection::open(path).map_err(Error::store("failed to open database"))?;
         Self::migrate(&conn)?;
-        Ok(Self { conn })
+        Ok(Self::with_connection(conn))
+    }
+
+    /// Open the database without write access, for commands that only
+    /// report what is installed. A database from an older zb still gets
+    /// migrated, which needs a writable open.
+    pub fn open_read_only(path: &Path) -> Result<Self, Error> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(Error::store("failed to open database"))?;
+        if Self::get_schema_version(&conn)? < Self::SCHEMA_VERSION {
+            drop(conn);
+            return Self::open(path);
+        }
+        Self::migrate(&conn)?;
+        Ok(Self::with_connection(conn))
+    }
+
+    fn with_connection(conn: Connection) -> Self {
+        Self {
+            conn,
+            generation: Cell::new(0),
+        }
     }
 
     pub fn in_memory() -> Result<Self, Error> {
         let conn =
             Connection::open_in_memory().map_err(Error::store("failed to open in-memory db"))?;
         Self::migrate(&conn)?;
-        Ok(Self { conn })
+        Ok(Self::with_connection(conn))
     }
 
     fn get_schema_version(conn: &Connection) -> Result<u32, Error> {
@@ -89,6 +346,22 @@ impl Database {
     fn migrate_to_version(conn: &Connection, version: u32) -> Result<(), Error> {
         match version {
             1 => Self::migrate_to_v1(conn),
+            2 => Self::migrate_to_v2(conn),
+            3 => Self::migrate_to_v3(conn),
+            4 => Self::migrate_to_v4(conn),
+            5 => Self::migrate_to_v5(conn),
+            6 => Self::migrate_to_v6(conn),
+            7 => Self::migrate_to_v7(conn),
+            8 => Self::migrate_to_v8(conn),
+            9 => Self::migrate_to_v9(conn),
+            10 => Self::migrate_to_v10(conn),
+            11 => Self::migrate_to_v11(conn),
+            12 => Self::migrate_to_v12(conn),
+            13 => Self::migrate_to_v13(conn),
+            14 => Self::migrate_to_v14(conn),
+            15 => Self::migrate_to_v15(conn),
+            16 => Self::migrate_to_v16(conn),
+            17 => Self::migrate_to_v17(conn),
             _ => Err(Error::StoreCorruption {
                 message: format!("unknown migration version {}", version),
             }),
@@ -124,70 +397,417 @@ impl Database {
         Ok(())
     }
 
+    /// Track why a keg is installed. Kegs recorded before this migration
+    /// are assumed to be explicit so nothing becomes an autoremove candidate.
+    fn migrate_to_v2(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            ALTER TABLE installed_kegs ADD COLUMN explicit INTEGER NOT NULL DEFAULT 1;
+
+            CREATE TABLE IF NOT EXISTS keg_dependencies (
+                name TEXT NOT NULL,
+                dependency TEXT NOT NULL,
+                PRIMARY KEY (name, dependency)
+            );
+            ",
+        )
+        .map_err(Error::store("failed to add dependency tracking"))?;
+
+        Ok(())
+    }
+
+    /// Key/value facts about the root itself, such as which zb and platform
+    /// created it.
+    fn migrate_to_v3(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS meta (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            ",
+        )
+        .map_err(Error::store("failed to create meta table"))?;
+
+        Ok(())
+    }
+
+    /// Record what kind of file each keg_files entry is. Everything linked
+    /// before this migration was a symlink.
+    fn migrate_to_v4(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "ALTER TABLE keg_files ADD COLUMN kind TEXT NOT NULL DEFAULT 'symlink';",
+        )
+        .map_err(Error::store("failed to add keg file kinds"))?;
+
+        Ok(())
+    }
+
+    fn migrate_to_v5(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch("ALTER TABLE installed_kegs ADD COLUMN copy_strategy TEXT;")
+            .map_err(Error::store("failed to add keg copy strategies"))?;
+
+        Ok(())
+    }
+
+    /// Previous versions kept after an upgrade. `installed_kegs` keeps one
+    /// row per formula for the active version; the rest live here.
+    fn migrate_to_v6(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS retained_kegs (
+                name TEXT NOT NULL,
+                version TEXT NOT NULL,
+                store_key TEXT NOT NULL,
+                installed_at INTEGER NOT NULL,
+                retained_at INTEGER NOT NULL,
+                copy_strategy TEXT,
+                PRIMARY KEY (name, version)
+            );
+            ",
+        )
+        .map_err(Error::store("failed to create retained kegs table"))?;
+
+        Ok(())
+    }
+
+    /// The prefix each keg's files were patched for. Kegs installed before
+    /// this was recorded have none.
+    fn migrate_to_v7(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            ALTER TABLE installed_kegs ADD COLUMN patched_prefix TEXT;
+            ALTER TABLE retained_kegs ADD COLUMN patched_prefix TEXT;
+            ",
+        )
+        .map_err(Error::store("failed to add patched prefixes"))?;
+
+        Ok(())
+    }
+
+    /// Third-party taps unqualified names resolve against, lowest priority
+    /// value first.
+    fn migrate_to_v8(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS taps (
+                name TEXT PRIMARY KEY,
+                priority INTEGER NOT NULL
+            );
+            ",
+        )
+        .map_err(Error::store("failed to create taps table"))?;
+
+        Ok(())
+    }
+
+    /// Keep a symlink's literal target apart from the absolute path it
+    /// resolves to. Older rows could hold either in `target_path`: relative
+    /// ones are resolved against the link's directory, and the literal
+    /// target is read back from links that are still there.
+    fn migrate_to_v9(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch("ALTER TABLE keg_files ADD COLUMN link_target TEXT;")
+            .map_err(Error::store("failed to add keg file link targets"))?;
+
+        let rows: Vec<(String, String, String)> = conn
+            .prepare("SELECT name, linked_path, target_path FROM keg_files WHERE kind = 'symlink'")
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                    .collect()
+            })
+            .map_err(Error::store("failed to read keg files"))?;
+
+        for (name, linked_path, target_path) in rows {
+            let link = Path::new(&linked_path);
+            let target = Path::new(&target_path);
+            let resolved = if target.is_relative() {
+                normalize_path(&link.parent().unwrap_or(Path::new("")).join(target))
+            } else {
+                target.to_path_buf()
+            };
+            let literal = fs::read_link(link)
+                .ok()
+                .or_else(|| target.is_relative().then(|| target.to_path_buf()));
+            conn.execute(
+                "UPDATE keg_files SET target_path = ?1, link_target = ?2
+                 WHERE name = ?3 AND linked_path = ?4",
+                params![
+                    resolved.to_string_lossy(),
+                    literal.map(|p| p.to_string_lossy().into_owned()),
+                    name,
+                    linked_path
+                ],
+            )
+            .map_err(Error::store("failed to migrate keg file targets"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Each keg's license, as the formula metadata gave it at install time.
+    fn migrate_to_v10(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            ALTER TABLE installed_kegs ADD COLUMN license TEXT;
+            ALTER TABLE retained_kegs ADD COLUMN license TEXT;
+            ",
+        )
+        .map_err(Error::store("failed to add keg licenses"))?;
+
+        Ok(())
+    }
+
+    /// The formula revision and bottle rebuild each keg was installed from.
+    /// Revisions of existing kegs are read off their version; their rebuild
+    /// is unknown.
+    fn migrate_to_v11(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            ALTER TABLE installed_kegs ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE installed_kegs ADD COLUMN rebuild INTEGER;
+            ALTER TABLE retained_kegs ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE retained_kegs ADD COLUMN rebuild INTEGER;
+            ",
+        )
+        .map_err(Error::store("failed to add keg revisions"))?;
+
+        for table in ["installed_kegs", "retained_kegs"] {
+            let mut stmt = conn
+                .prepare(&format!("SELECT DISTINCT version FROM {table}"))
+                .map_err(Error::store("failed to prepare statement"))?;
+            let versions = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(Error::store("failed to query keg versions"))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(Error::store("failed to collect results"))?;
+            for version in versions {
+                let revision = revision_of(&version);
+                if revision > 0 {
+                    conn.execute(
+                        &format!("UPDATE {table} SET revision = ?2 WHERE version = ?1"),
+                        params![version, revision],
+                    )
+                    .map_err(Error::store("failed to migrate keg revisions"))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Store entries `zb gc --archive` compressed instead of deleting.
+    fn migrate_to_v12(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "ALTER TABLE store_refs ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;",
+        )
+        .map_err(Error::store("failed to add archived store entries"))?;
+
+        Ok(())
+    }
+
+    /// Formulas upstream deprecated or disabled, kept so they can be pointed
+    /// out without fetching anything.
+    fn migrate_to_v13(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            CREATE TABLE upstream_status (
+                name TEXT PRIMARY KEY,
+                disabled INTEGER NOT NULL,
+                reason TEXT,
+                since TEXT,
+                checked_at INTEGER NOT NULL
+            );
+            ",
+        )
+        .map_err(Error::store("failed to create upstream status table"))?;
+
+        Ok(())
+    }
+
+    fn migrate_to_v14(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            CREATE TABLE events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                at INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                user TEXT,
+                details TEXT
+            );
+            ",
+        )
+        .map_err(Error::store("failed to create events table"))?;
+
+        Ok(())
+    }
+
+    /// The formula's version for kegs whose bottle named its directory
+    /// differently. Existing kegs are assumed to agree.
+    fn migrate_to_v15(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            ALTER TABLE installed_kegs ADD COLUMN formula_version TEXT;
+            ALTER TABLE retained_kegs ADD COLUMN formula_version TEXT;
+            ",
+        )
+        .map_err(Error::store("failed to add keg formula versions"))?;
+
+        Ok(())
+    }
+
+    /// Formulas `zb pin` holds at their installed version.
+    fn migrate_to_v16(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "ALTER TABLE installed_kegs ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;",
+        )
+        .map_err(Error::store("failed to add pinned kegs"))?;
+
+        Ok(())
+    }
+
+    /// The index of downloaded bottles in the blob cache.
+    fn migrate_to_v17(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(
+            "
+            CREATE TABLE blobs (
+                sha256 TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                formula TEXT,
+                added_at INTEGER NOT NULL,
+                last_access INTEGER NOT NULL,
+                verified INTEGER NOT NULL DEFAULT 0
+            );
+            ",
+        )
+        .map_err(Error::store("failed to create blob index table"))?;
+
+        Ok(())
+    }
+
     pub fn transaction(&mut self) -> Result<InstallTransaction<'_>, Error> {
         let tx = self
             .conn
             .transaction()
             .map_err(Error::store("failed to start transaction"))?;
 
-        Ok(InstallTransaction { tx })
+        Ok(InstallTransaction {
+            tx,
+            generation: &self.generation,
+            touched: Cell::new(false),
+        })
     }
 
-    pub fn get_installed(&self, name: &str) -> Option<InstalledKeg> {
+    /// `None` only when `name` has no row; a query that fails is an error,
+    /// so a damaged database is never mistaken for a missing keg.
+    pub fn get_installed(&self, name: &str) -> Result<Option<InstalledKeg>, Error> {
         self.conn
             .query_row(
-                "SELECT name, version, store_key, installed_at FROM installed_kegs WHERE name = ?1",
+                "SELECT name, version, store_key, installed_at, explicit, copy_strategy, license,
+                        revision, rebuild, formula_version, pinned
+                 FROM installed_kegs WHERE name = ?1",
                 params![name],
-                |row| {
-                    Ok(InstalledKeg {
-                        name: row.get(0)?,
-                        version: row.get(1)?,
-                        store_key: row.get(2)?,
-                        installed_at: row.get(3)?,
-                    })
-                },
+                installed_keg_from_row,
             )
-            .ok()
+            .optional()
+            .map_err(Error::store("failed to query installed keg"))
     }
 
     pub fn list_installed(&self) -> Result<Vec<InstalledKeg>, Error> {
         let mut stmt = self
             .conn
             .prepare(
-                "SELECT name, version, store_key, installed_at FROM installed_kegs ORDER BY name",
+                "SELECT name, version, store_key, installed_at, explicit, copy_strategy, license,
+                        revision, rebuild, formula_version, pinned
+                 FROM installed_kegs ORDER BY name",
             )
             .map_err(Error::store("failed to prepare statement"))?;
 
         let kegs = stmt
-            .query_map([], |row| {
-                Ok(InstalledKeg {
+            .query_map([], installed_keg_from_row)
+            .map_err(Error::store("failed to query installed kegs"))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::store("failed to collect results"))?;
+
+        Ok(kegs)
+    }
+
+    pub fn load_installed_set(&self) -> Result<InstalledSet, Error> {
+        Ok(InstalledSet {
+            generation: self.generation.get(),
+            kegs: self
+                .list_installed()?
+                .into_iter()
+                .map(|keg| (keg.name.clone(), keg))
+                .collect(),
+        })
+    }
+
+    /// Whether no installed keg changed through this database since `set`
+    /// was read.
+    pub fn is_current(&self, set: &InstalledSet) -> bool {
+        set.generation == self.generation.get()
+    }
+
+    /// Previous versions kept for rollback, newest first per formula, or
+    /// only those of `name` when given.
+    pub fn list_retained(&self, name: Option<&str>) -> Result<Vec<RetainedKeg>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT name, version, store_key, installed_at, retained_at, copy_strategy
+                 FROM retained_kegs
+                 WHERE ?1 IS NULL OR name = ?1
+                 ORDER BY name, retained_at DESC, rowid DESC",
+            )
+            .map_err(Error::store("failed to prepare statement"))?;
+
+        let kegs = stmt
+            .query_map(params![name], |row| {
+                let copy_strategy: Option<String> = row.get(5)?;
+                Ok(RetainedKeg {
                     name: row.get(0)?,
                     version: row.get(1)?,
                     store_key: row.get(2)?,
                     installed_at: row.get(3)?,
+                    retained_at: row.get(4)?,
+                    copy_strategy: copy_strategy.as_deref().and_then(CopyStrategy::parse),
                 })
             })
-            .map_err(Error::store("failed to query installed kegs"))?
+            .map_err(Error::store("failed to query retained kegs"))?
             .collect::<Result<Vec<_>, _>>()
             .map_err(Error::store("failed to collect results"))?;
 
         Ok(kegs)
     }
 
-    pub fn get_store_refcount(&self, store_key: &str) -> i64 {
-        self.conn
+    pub fn get_store_refcount(&self, store_key: &str) -> Result<i64, Error> {
+        let refcount = self
+            .conn
             .query_row(
                 "SELECT refcount FROM store_refs WHERE store_key = ?1",
                 params![store_key],
                 |row| row.get(0),
             )
-            .unwrap_or(0)
+            .optional()
+            .map_err(Error::store("failed to query store ref"))?;
+        Ok(refcount.unwrap_or(0))
     }
 
+    /// Store keys with no references left. A key an installed or retained
+    /// keg still uses is never returned, even if its count says otherwise,
+    /// so a miscounted row can't get a live entry collected. Archived
+    /// entries are left to `zb cache clean --archives`.
     pub fn get_unreferenced_store_keys(&self) -> Result<Vec<String>, Error> {
         let mut stmt = self
             .conn
-            .prepare("SELECT store_key FROM store_refs WHERE refcount <= 0")
+            .prepare(
+                "SELECT store_key FROM store_refs
+                 WHERE refcount <= 0
+                   AND archived = 0
+                   AND store_key NOT IN (SELECT store_key FROM installed_kegs)
+                   AND store_key NOT IN (SELECT store_key FROM retained_kegs)
+                 ORDER BY store_key",
+            )
             .map_err(Error::store("failed to prepare statement"))?;
 
         let keys = stmt
@@ -199,25 +819,272 @@ impl Database {
         Ok(keys)
     }
 
-    pub fn delete_store_ref(&self, store_key: &str) -> Result<(), Error> {
+    /// Track a store entry nothing references yet. It stays eligible for gc
+    /// until an install records it.
+    pub fn register_store_entry(&self, store_key: &str) -> Result<(), Error> {
         self.conn
             .execute(
-                "DELETE FROM store_refs WHERE store_key = ?1",
+                "INSERT OR IGNORE INTO store_refs (store_key, refcount) VALUES (?1, 0)",
                 params![store_key],
             )
-            .map_err(Error::store("failed to delete store ref"))?;
+            .map_err(Error::store("failed to register store entry"))?;
         Ok(())
     }
 
-    pub fn list_store_refs(&self) -> Result<Vec<StoreRef>, Error> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT store_key, refcount FROM store_refs ORDER BY store_key")
-            .map_err(Error::store("failed to prepare statement"))?;
+    /// Mark the entry for `store_key` as compressed into an archive, or as
+    /// expanded again.
+    pub fn set_store_archived(&self, store_key: &str, archived: bool) -> Result<(), Error> {
+        self.conn
+            .execute(
+                "INSERT INTO store_refs (store_key, refcount, archived) VALUES (?1, 0, ?2)
+                 ON CONFLICT(store_key) DO UPDATE SET archived = excluded.archived",
+                params![store_key, archived],
+            )
+            .map_err(Error::store("failed to record archived store entry"))?;
+        Ok(())
+    }
 
-        let refs = stmt
-            .query_map([], |row| {
-                Ok(StoreRef {
+    /// Hold `name` at its installed version, or let upgrades replace it
+    /// again. The pin lasts until `name` is uninstalled.
+    pub fn set_pinned(&self, name: &str, pinned: bool) -> Result<(), Error> {
+        let updated = self
+            .conn
+            .execute(
+                "UPDATE installed_kegs SET pinned = ?2 WHERE name = ?1",
+                params![name, pinned],
+            )
+            .map_err(Error::store("failed to record pin"))?;
+        if updated == 0 {
+            return Err(Error::NotInstalled {
+                name: name.to_string(),
+            });
+        }
+        self.generation.set(self.generation.get() + 1);
+        Ok(())
+    }
+
+    /// Record what upstream says about `name` now; an active formula's
+    /// status is cleared.
+    pub fn record_upstream_status(
+        &self,
+        name: &str,
+        lifecycle: &Lifecycle,
+        since: Option<&str>,
+    ) -> Result<(), Error> {
+        write_upstream_status(&self.conn, name, lifecycle, since)
+    }
+
+    /// Append a `kind` event, stamped with the current time.
+    pub fn record_event(
+        &self,
+        kind: &str,
+        user: Option<&str>,
+        details: Option<&serde_json::Value>,
+    ) -> Result<(), Error> {
+        self.conn
+            .execute(
+                "INSERT INTO events (at, kind, user, details) VALUES (?1, ?2, ?3, ?4)",
+                params![unix_now(), kind, user, details.map(|d| d.to_string())],
+            )
+            .map_err(Error::store("failed to record event"))?;
+        Ok(())
+    }
+
+    /// Every recorded event, oldest first. Details that don't parse are
+    /// dropped rather than failing the listing.
+    pub fn list_events(&self) -> Result<Vec<HistoryEvent>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT at, kind, user, details FROM events ORDER BY id")
+            .map_err(Error::store("failed to prepare statement"))?;
+        stmt.query_map([], |row| {
+            let details: Option<String> = row.get(3)?;
+            Ok(HistoryEvent {
+                at: row.get(0)?,
+                kind: row.get(1)?,
+                user: row.get(2)?,
+                details: details.and_then(|raw| serde_json::from_str(&raw).ok()),
+            })
+        })
+        .map_err(Error::store("failed to query events"))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::store("failed to collect events"))
+    }
+
+    /// Installed formulas last seen deprecated or disabled upstream, by name.
+    pub fn list_upstream_statuses(&self) -> Result<Vec<UpstreamStatus>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT s.name, s.disabled, s.reason, s.since, s.checked_at
+                 FROM upstream_status s JOIN installed_kegs k ON k.name = s.name
+                 ORDER BY s.name",
+            )
+            .map_err(Error::store("failed to prepare statement"))?;
+        stmt.query_map([], |row| {
+            let reason: Option<String> = row.get(2)?;
+            Ok(UpstreamStatus {
+                name: row.get(0)?,
+                lifecycle: if row.get(1)? {
+                    Lifecycle::Disabled { reason }
+                } else {
+                    Lifecycle::Deprecated { reason }
+                },
+                since: row.get(3)?,
+                checked_at: row.get(4)?,
+            })
+        })
+        .map_err(Error::store("failed to query upstream statuses"))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::store("failed to collect upstream statuses"))
+    }
+
+    /// Note that the cached blob `sha256` of `size` bytes was used just
+    /// now, for `formula` when known. A formula already recorded is kept
+    /// when none is given, and `verified` only ever turns the flag on.
+    pub fn record_blob(
+        &self,
+        sha256: &str,
+        size: u64,
+        formula: Option<&str>,
+        verified: bool,
+    ) -> Result<(), Error> {
+        self.conn
+            .execute(
+                "INSERT INTO blobs (sha256, size, formula, added_at, last_access, verified)
+                 VALUES (?1, ?2, ?3, ?4, ?4, ?5)
+                 ON CONFLICT(sha256) DO UPDATE SET
+                    size = excluded.size,
+                    formula = COALESCE(excluded.formula, blobs.formula),
+                    last_access = excluded.last_access,
+                    verified = MAX(blobs.verified, excluded.verified)",
+                params![sha256, size as i64, formula, unix_now(), verified],
+            )
+            .map_err(Error::store("failed to record cached blob"))?;
+        Ok(())
+    }
+
+    pub fn delete_blob(&self, sha256: &str) -> Result<(), Error> {
+        self.conn
+            .execute("DELETE FROM blobs WHERE sha256 = ?1", params![sha256])
+            .map_err(Error::store("failed to delete cached blob"))?;
+        Ok(())
+    }
+
+    /// Every indexed blob, sorted by sha256.
+    pub fn list_blob_entries(&self) -> Result<Vec<BlobEntry>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT sha256, size, formula, added_at, last_access, verified
+                 FROM blobs ORDER BY sha256",
+            )
+            .map_err(Error::store("failed to prepare statement"))?;
+        stmt.query_map([], |row| {
+            Ok(BlobEntry {
+                sha256: row.get(0)?,
+                size: row.get::<_, i64>(1)?.max(0) as u64,
+                formula: row.get(2)?,
+                added_at: row.get(3)?,
+                last_access: row.get(4)?,
+                verified: row.get(5)?,
+            })
+        })
+        .map_err(Error::store("failed to query cached blobs"))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::store("failed to collect cached blobs"))
+    }
+
+    /// Index exactly the blobs in `on_disk`, in one transaction: entries
+    /// for blobs that are gone or changed size are dropped, and the
+    /// missing ones added as given. Returns how many were added and
+    /// dropped.
+    pub fn reconcile_blob_entries(
+        &mut self,
+        on_disk: &[BlobEntry],
+    ) -> Result<(usize, usize), Error> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(Error::store("failed to start transaction"))?;
+        let indexed: HashMap<String, i64> = {
+            let mut stmt = tx
+                .prepare("SELECT sha256, size FROM blobs")
+                .map_err(Error::store("failed to prepare statement"))?;
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(Error::store("failed to query cached blobs"))?
+                .collect::<Result<_, _>>()
+                .map_err(Error::store("failed to collect cached blobs"))?
+        };
+        let sizes: HashMap<&str, i64> = on_disk
+            .iter()
+            .map(|entry| (entry.sha256.as_str(), entry.size as i64))
+            .collect();
+
+        let mut removed = 0;
+        for (sha256, size) in &indexed {
+            if sizes.get(sha256.as_str()) != Some(size) {
+                tx.execute("DELETE FROM blobs WHERE sha256 = ?1", params![sha256])
+                    .map_err(Error::store("failed to delete cached blob"))?;
+                removed += 1;
+            }
+        }
+        let mut added = 0;
+        for entry in on_disk {
+            if indexed.get(&entry.sha256) == Some(&(entry.size as i64)) {
+                continue;
+            }
+            tx.execute(
+                "INSERT INTO blobs (sha256, size, formula, added_at, last_access, verified)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    entry.sha256,
+                    entry.size as i64,
+                    entry.formula,
+                    entry.added_at,
+                    entry.last_access,
+                    entry.verified
+                ],
+            )
+            .map_err(Error::store("failed to record cached blob"))?;
+            added += 1;
+        }
+        tx.commit()
+            .map_err(Error::store("failed to commit transaction"))?;
+        Ok((added, removed))
+    }
+
+    /// Forget an archived entry whose archive was deleted. A row an install
+    /// has counted since is kept.
+    pub fn delete_archived_store_ref(&self, store_key: &str) -> Result<(), Error> {
+        self.conn
+            .execute(
+                "DELETE FROM store_refs WHERE store_key = ?1 AND archived = 1 AND refcount <= 0",
+                params![store_key],
+            )
+            .map_err(Error::store("failed to delete archived store ref"))?;
+        Ok(())
+    }
+
+    pub fn delete_store_ref(&self, store_key: &str) -> Result<(), Error> {
+        self.conn
+            .execute(
+                "DELETE FROM store_refs WHERE store_key = ?1",
+                params![store_key],
+            )
+            .map_err(Error::store("failed to delete store ref"))?;
+        Ok(())
+    }
+
+    pub fn list_store_refs(&self) -> Result<Vec<StoreRef>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT store_key, refcount FROM store_refs ORDER BY store_key")
+            .map_err(Error::store("failed to prepare statement"))?;
+
+        let refs = stmt
+            .query_map([], |row| {
+                Ok(StoreRef {
                     store_key: row.get(0)?,
                     refcount: row.get(1)?,
                 })
@@ -233,21 +1100,39 @@ impl Database {
         let mut stmt = self
             .conn
             .prepare(
-                "SELECT name, version, linked_path, target_path
+                "SELECT name, version, linked_path, target_path, kind, link_target
                  FROM keg_files
                  ORDER BY name, version, linked_path",
             )
             .map_err(Error::store("failed to prepare statement"))?;
 
         let records = stmt
-            .query_map([], |row| {
-                Ok(KegFileRecord {
-                    name: row.get(0)?,
-                    version: row.get(1)?,
-                    linked_path: row.get(2)?,
-                    target_path: row.get(3)?,
-                })
-            })
+            .query_map([], keg_file_from_row)
+            .map_err(Error::store("failed to query keg files"))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::store("failed to collect results"))?;
+
+        Ok(records)
+    }
+
+    /// What one keg put into the prefix, sorted by path.
+    pub fn list_keg_files_for(
+        &self,
+        name: &str,
+        version: &str,
+    ) -> Result<Vec<KegFileRecord>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT name, version, linked_path, target_path, kind, link_target
+                 FROM keg_files
+                 WHERE name = ?1 AND version = ?2
+                 ORDER BY linked_path",
+            )
+            .map_err(Error::store("failed to prepare statement"))?;
+
+        let records = stmt
+            .query_map(params![name, version], keg_file_from_row)
             .map_err(Error::store("failed to query keg files"))?
             .collect::<Result<Vec<_>, _>>()
             .map_err(Error::store("failed to collect results"))?;
@@ -255,18 +1140,75 @@ impl Database {
         Ok(records)
     }
 
+    /// All recorded `(name, dependency)` edges between installed kegs.
+    pub fn list_dependency_edges(&self) -> Result<Vec<(String, String)>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, dependency FROM keg_dependencies ORDER BY name, dependency")
+            .map_err(Error::store("failed to prepare statement"))?;
+
+        let edges = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(Error::store("failed to query dependency edges"))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::store("failed to collect results"))?;
+
+        Ok(edges)
+    }
+
+    /// Configured taps, highest priority first.
+    pub fn list_taps(&self) -> Result<Vec<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM taps ORDER BY priority, name")
+            .map_err(Error::store("failed to prepare statement"))?;
+
+        let taps = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(Error::store("failed to query taps"))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::store("failed to collect results"))?;
+
+        Ok(taps)
+    }
+
+    /// Replace the configured taps with `taps`, highest priority first.
+    pub fn replace_taps(&mut self, taps: &[String]) -> Result<(), Error> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(Error::store("failed to start transaction"))?;
+        tx.execute("DELETE FROM taps", [])
+            .map_err(Error::store("failed to clear taps"))?;
+        for (priority, tap) in taps.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO taps (name, priority) VALUES (?1, ?2)",
+                params![tap, priority as i64],
+            )
+            .map_err(Error::store("failed to insert tap"))?;
+        }
+        tx.commit()
+            .map_err(Error::store("failed to commit transaction"))
+    }
+
     pub fn replace_store_refs(&self, store_refs: &[StoreRef]) -> Result<(), Error> {
         let tx = self
             .conn
             .unchecked_transaction()
             .map_err(Error::store("failed to start transaction"))?;
 
-        tx.execute("DELETE FROM store_refs", [])
+        // Archived entries are counted by nothing, so only a new count for
+        // the same key replaces their row.
+        tx.execute("DELETE FROM store_refs WHERE archived = 0", [])
             .map_err(Error::store("failed to clear store refs"))?;
 
         {
             let mut stmt = tx
-                .prepare("INSERT INTO store_refs (store_key, refcount) VALUES (?1, ?2)")
+                .prepare(
+                    "INSERT INTO store_refs (store_key, refcount) VALUES (?1, ?2)
+                     ON CONFLICT(store_key) DO UPDATE SET refcount = excluded.refcount,
+                                                          archived = 0",
+                )
                 .map_err(Error::store("failed to prepare statement"))?;
 
             for store_ref in store_refs {
@@ -311,38 +1253,357 @@ impl Database {
             )
             .map_err(Error::store("failed to prune stale keg file records"))
     }
+
+    fn get_meta(&self, key: &str) -> Result<Option<String>, Error> {
+        self.conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Error::store("failed to read meta"))
+    }
+
+    fn set_meta(&self, key: &str, value: &str) -> Result<(), Error> {
+        self.conn
+            .execute(
+                "INSERT INTO meta (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )
+            .map_err(Error::store("failed to write meta"))?;
+        Ok(())
+    }
+
+    /// Installed kegs patched for a prefix other than `prefix`, sorted by
+    /// name. Kegs with no recorded prefix are left out, and so is everything
+    /// while a relocation is in progress, since half the kegs have moved.
+    pub fn kegs_patched_for_other_prefix(&self, prefix: &Path) -> Result<Vec<DriftedKeg>, Error> {
+        if self.get_meta("relocation_to")?.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT name, version, patched_prefix FROM installed_kegs
+                 WHERE patched_prefix IS NOT NULL AND patched_prefix != ?1
+                 ORDER BY name",
+            )
+            .map_err(Error::store("failed to prepare statement"))?;
+        stmt.query_map(params![prefix.to_string_lossy()], |row| {
+            Ok(DriftedKeg {
+                name: row.get(0)?,
+                version: row.get(1)?,
+                patched_prefix: PathBuf::from(row.get::<_, String>(2)?),
+            })
+        })
+        .map_err(Error::store("failed to query patched prefixes"))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::store("failed to collect patched prefixes"))
+    }
+
+    /// The prefix this root's kegs and links were put under, as recorded by
+    /// the first install or the last `zb relocate`.
+    pub fn recorded_prefix(&self) -> Result<Option<PathBuf>, Error> {
+        Ok(self.get_meta("prefix")?.map(PathBuf::from))
+    }
+
+    /// The recorded prefix when it isn't `prefix` and formulas are installed
+    /// under it, so using `prefix` would mix two sets of links. Nothing is
+    /// reported while a relocation is in progress.
+    pub fn prefix_conflict(&self, prefix: &Path) -> Result<Option<PathBuf>, Error> {
+        let Some(recorded) = self.recorded_prefix()? else {
+            return Ok(None);
+        };
+        if recorded == prefix
+            || self.get_meta("relocation_to")?.is_some()
+            || self.list_installed()?.is_empty()
+        {
+            return Ok(None);
+        }
+        Ok(Some(recorded))
+    }
+
+    /// Forget a recorded prefix other than `prefix` once nothing is
+    /// installed under it, so the next install records `prefix` instead.
+    pub fn release_prefix(&self, prefix: &Path) -> Result<(), Error> {
+        if !self.list_installed()?.is_empty() {
+            return Ok(());
+        }
+        self.conn
+            .execute(
+                "DELETE FROM meta WHERE key = 'prefix' AND value != ?1",
+                params![prefix.to_string_lossy()],
+            )
+            .map_err(Error::store("failed to clear recorded prefix"))?;
+        Ok(())
+    }
+
+    pub fn relocation_state(&self) -> Result<Option<RelocationState>, Error> {
+        let (Some(from), Some(to)) = (
+            self.get_meta("relocation_from")?,
+            self.get_meta("relocation_to")?,
+        ) else {
+            return Ok(None);
+        };
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT substr(key, 11) FROM meta WHERE key LIKE 'relocated:%' ORDER BY key")
+            .map_err(Error::store("failed to prepare statement"))?;
+        let relocated = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(Error::store("failed to query relocated kegs"))?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(Error::store("failed to collect relocated kegs"))?;
+
+        Ok(Some(RelocationState {
+            from: PathBuf::from(from),
+            to: PathBuf::from(to),
+            relocated,
+        }))
+    }
+
+    pub fn begin_relocation(&mut self, from: &Path, to: &Path) -> Result<(), Error> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(Error::store("failed to start transaction"))?;
+        for (key, value) in [("relocation_from", from), ("relocation_to", to)] {
+            tx.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+                params![key, value.to_string_lossy()],
+            )
+            .map_err(Error::store("failed to write meta"))?;
+        }
+        tx.commit()
+            .map_err(Error::store("failed to commit transaction"))
+    }
+
+    /// Record `prefix` as the root's prefix and forget the relocation.
+    pub fn finish_relocation(&mut self, prefix: &Path) -> Result<(), Error> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(Error::store("failed to start transaction"))?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('prefix', ?1)",
+            params![prefix.to_string_lossy()],
+        )
+        .map_err(Error::store("failed to write meta"))?;
+        tx.execute(
+            "DELETE FROM meta
+             WHERE key IN ('relocation_from', 'relocation_to') OR key LIKE 'relocated:%'",
+            [],
+        )
+        .map_err(Error::store("failed to clear relocation state"))?;
+        tx.commit()
+            .map_err(Error::store("failed to commit transaction"))
+    }
+
+    /// The zb version and platform recorded for this root, if any.
+    pub fn system_record(&self) -> Result<Option<SystemRecord>, Error> {
+        let (Some(created_by), Some(os), Some(arch)) = (
+            self.get_meta("created_zb_version")?,
+            self.get_meta("os")?,
+            self.get_meta("arch")?,
+        ) else {
+            return Ok(None);
+        };
+        let last_used_by = self
+            .get_meta("zb_version")?
+            .unwrap_or_else(|| created_by.clone());
+
+        Ok(Some(SystemRecord {
+            created_by,
+            last_used_by,
+            os,
+            arch,
+        }))
+    }
+
+    /// Compare the recorded platform with `current`, recording it on first
+    /// use. Kegs are not portable, so a different OS or architecture is an
+    /// error. When the zb version changed, the previous one is returned once
+    /// and the record is updated.
+    pub fn check_system(&mut self, current: &SystemRecord) -> Result<Option<String>, Error> {
+        let Some(recorded) = self.system_record()? else {
+            let tx = self
+                .conn
+                .transaction()
+                .map_err(Error::store("failed to start transaction"))?;
+            for (key, value) in [
+                ("created_zb_version", current.created_by.as_str()),
+                ("zb_version", current.last_used_by.as_str()),
+                ("os", current.os.as_str()),
+                ("arch", current.arch.as_str()),
+            ] {
+                tx.execute(
+                    "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+                    params![key, value],
+                )
+                .map_err(Error::store("failed to write meta"))?;
+            }
+            tx.commit()
+                .map_err(Error::store("failed to commit transaction"))?;
+            return Ok(None);
+        };
+
+        if recorded.os != current.os || recorded.arch != current.arch {
+            return Err(Error::StoreCorruption {
+                message: format!(
+                    "this zerobrew root was created on {}/{} but zb is running on {}/{}; \
+                     installed kegs are not portable between platforms. Use a separate \
+                     ZEROBREW_ROOT or run 'zb reset'",
+                    recorded.os, recorded.arch, current.os, current.arch
+                ),
+            });
+        }
+
+        if recorded.last_used_by == current.last_used_by {
+            return Ok(None);
+        }
+        self.set_meta("zb_version", &current.last_used_by)?;
+        Ok(Some(recorded.last_used_by))
+    }
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn write_upstream_status(
+    conn: &Connection,
+    name: &str,
+    lifecycle: &Lifecycle,
+    since: Option<&str>,
+) -> Result<(), Error> {
+    let (disabled, reason) = match lifecycle {
+        Lifecycle::Active => {
+            conn.execute("DELETE FROM upstream_status WHERE name = ?1", params![name])
+                .map_err(Error::store("failed to clear upstream status"))?;
+            return Ok(());
+        }
+        Lifecycle::Deprecated { reason } => (false, reason),
+        Lifecycle::Disabled { reason } => (true, reason),
+    };
+    conn.execute(
+        "INSERT OR REPLACE INTO upstream_status (name, disabled, reason, since, checked_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![name, disabled, reason, since, unix_now()],
+    )
+    .map_err(Error::store("failed to record upstream status"))?;
+
+    Ok(())
+}
+
+/// Columns as selected by `get_installed` and `list_installed`.
+fn installed_keg_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<InstalledKeg> {
+    let copy_strategy: Option<String> = row.get(5)?;
+    Ok(InstalledKeg {
+        name: row.get(0)?,
+        version: row.get(1)?,
+        store_key: row.get(2)?,
+        installed_at: row.get(3)?,
+        explicit: row.get(4)?,
+        copy_strategy: copy_strategy.as_deref().and_then(CopyStrategy::parse),
+        license: row.get(6)?,
+        revision: row.get(7)?,
+        rebuild: row.get(8)?,
+        formula_version: row.get(9)?,
+        pinned: row.get(10)?,
+    })
+}
+
+fn keg_file_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<KegFileRecord> {
+    Ok(KegFileRecord {
+        name: row.get(0)?,
+        version: row.get(1)?,
+        linked_path: row.get(2)?,
+        target_path: row.get(3)?,
+        link_target: row.get(5)?,
+        kind: KegFileKind::from_column(&row.get::<_, String>(4)?),
+    })
 }
 
 pub struct InstallTransaction<'a> {
     tx: Transaction<'a>,
+    generation: &'a Cell<u64>,
+    /// Whether an installed keg was written, bumping the generation on
+    /// commit.
+    touched: Cell<bool>,
 }
 
 impl<'a> InstallTransaction<'a> {
+    fn touch(&self) {
+        self.touched.set(true);
+    }
+
+    /// Record `version` of `name` as the active keg. A different version
+    /// that was active is retained, keeping its store reference, until
+    /// [`drop_retained`](Self::drop_retained) lets it go.
     pub fn record_install(&self, name: &str, version: &str, store_key: &str) -> Result<(), Error> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs() as i64)
-            .unwrap_or(0);
+        self.touch();
+        let now = unix_now();
+
+        // Installing a retained version again makes it active instead.
+        self.drop_retained(name, version)?;
 
-        let previous_store_key: Option<String> = self
+        let previous: Option<(String, String)> = self
             .tx
             .query_row(
-                "SELECT store_key FROM installed_kegs WHERE name = ?1",
+                "SELECT version, store_key FROM installed_kegs WHERE name = ?1",
                 params![name],
-                |row| row.get(0),
+                |row| Ok((row.get(0)?, row.get(1)?)),
             )
             .optional()
             .map_err(Error::store("failed to query previous store key"))?;
 
+        let previous_store_key = match previous {
+            // The retained row keeps the reference the previous version held.
+            Some((previous_version, _)) if previous_version != version => {
+                // Its links are replaced by the new version's.
+                self.tx
+                    .execute(
+                        "DELETE FROM keg_files WHERE name = ?1 AND version = ?2",
+                        params![name, previous_version],
+                    )
+                    .map_err(Error::store("failed to clear keg files records"))?;
+                self.tx
+                    .execute(
+                        "INSERT INTO retained_kegs
+                             (name, version, store_key, installed_at, retained_at, copy_strategy,
+                              patched_prefix, license, revision, rebuild, formula_version)
+                         SELECT name, version, store_key, installed_at, ?2, copy_strategy,
+                                patched_prefix, license, revision, rebuild, formula_version
+                         FROM installed_kegs WHERE name = ?1",
+                        params![name, now],
+                    )
+                    .map_err(Error::store("failed to retain previous version"))?;
+                None
+            }
+            previous => previous.map(|(_, key)| key),
+        };
+
         self.tx
             .execute(
-                "INSERT INTO installed_kegs (name, version, store_key, installed_at)
-                 VALUES (?1, ?2, ?3, ?4)
+                "INSERT INTO installed_kegs (name, version, store_key, installed_at, revision)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
                  ON CONFLICT(name) DO UPDATE SET
                      version = excluded.version,
                      store_key = excluded.store_key,
-                     installed_at = excluded.installed_at",
-                params![name, version, store_key, now],
+                     installed_at = excluded.installed_at,
+                     patched_prefix = NULL,
+                     license = NULL,
+                     revision = excluded.revision,
+                     rebuild = NULL,
+                     formula_version = NULL",
+                params![name, version, store_key, now, revision_of(version)],
             )
             .map_err(Error::store("failed to record install"))?;
 
@@ -350,18 +1611,14 @@ impl<'a> InstallTransaction<'a> {
             Some(previous) if previous == store_key => {}
             other => {
                 if let Some(previous) = other {
-                    self.tx
-                        .execute(
-                            "UPDATE store_refs SET refcount = refcount - 1 WHERE store_key = ?1",
-                            params![previous],
-                        )
-                        .map_err(Error::store("failed to decrement previous store ref"))?;
+                    self.release_store_ref(previous)?;
                 }
 
                 self.tx
                     .execute(
                         "INSERT INTO store_refs (store_key, refcount) VALUES (?1, 1)
-                         ON CONFLICT(store_key) DO UPDATE SET refcount = refcount + 1",
+                         ON CONFLICT(store_key) DO UPDATE SET refcount = refcount + 1,
+                                                              archived = 0",
                         params![store_key],
                     )
                     .map_err(Error::store("failed to increment store ref"))?;
@@ -371,25 +1628,258 @@ impl<'a> InstallTransaction<'a> {
         Ok(())
     }
 
-    pub fn record_linked_file(
-        &self,
-        name: &str,
-        version: &str,
-        linked_path: &str,
-        target_path: &str,
+    /// Forget retained `version` of `name` and release its store reference.
+    /// Returns the store key it held, if it was retained.
+    pub fn drop_retained(&self, name: &str, version: &str) -> Result<Option<String>, Error> {
+        let store_key: Option<String> = self
+            .tx
+            .query_row(
+                "DELETE FROM retained_kegs WHERE name = ?1 AND version = ?2 RETURNING store_key",
+                params![name, version],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Error::store("failed to drop retained version"))?;
+
+        if let Some(ref key) = store_key {
+            self.release_store_ref(key)?;
+        }
+
+        Ok(store_key)
+    }
+
+    /// Make retained `version` of `name` the active keg again and retain
+    /// the one it replaces. Both keep their store references. The files
+    /// recorded for the replaced keg are forgotten; the caller links the
+    /// activated one and records its files.
+    pub fn activate_retained(&self, name: &str, version: &str) -> Result<(), Error> {
+        self.touch();
+        let now = unix_now();
+        self.tx
+            .execute(
+                "INSERT INTO retained_kegs
+                     (name, version, store_key, installed_at, retained_at, copy_strategy,
+                      patched_prefix, license, revision, rebuild, formula_version)
+                 SELECT name, version, store_key, installed_at, ?2, copy_strategy, patched_prefix,
+                        license, revision, rebuild, formula_version
+                 FROM installed_kegs WHERE name = ?1",
+                params![name, now],
+            )
+            .map_err(Error::store("failed to retain active version"))?;
+
+        let updated = self
+            .tx
+            .execute(
+                "UPDATE installed_kegs SET
+                     (version, store_key, installed_at, copy_strategy, patched_prefix,
+                      license, revision, rebuild, formula_version) = (
+                         SELECT version, store_key, installed_at, copy_strategy, patched_prefix,
+                                license, revision, rebuild, formula_version
+                         FROM retained_kegs WHERE name = ?1 AND version = ?2
+                     )
+                 WHERE name = ?1
+                   AND EXISTS (SELECT 1 FROM retained_kegs WHERE name = ?1 AND version = ?2)",
+                params![name, version],
+            )
+            .map_err(Error::store("failed to activate retained version"))?;
+        if updated == 0 {
+            return Err(Error::StoreCorruption {
+                message: format!("{name} {version} is not retained"),
+            });
+        }
+
+        self.tx
+            .execute(
+                "DELETE FROM retained_kegs WHERE name = ?1 AND version = ?2",
+                params![name, version],
+            )
+            .map_err(Error::store("failed to activate retained version"))?;
+
+        self.clear_keg_file_records(name)
+    }
+
+    /// Record whether `name` was requested explicitly. Once a keg has been
+    /// requested explicitly it stays explicit across reinstalls.
+    pub fn record_install_reason(&self, name: &str, explicit: bool) -> Result<(), Error> {
+        self.touch();
+        self.tx
+            .execute(
+                "UPDATE installed_kegs SET explicit = ?2 WHERE name = ?1",
+                params![name, explicit],
+            )
+            .map_err(Error::store("failed to record install reason"))?;
+
+        Ok(())
+    }
+
+    pub fn record_copy_strategy(&self, name: &str, strategy: CopyStrategy) -> Result<(), Error> {
+        self.touch();
+        self.tx
+            .execute(
+                "UPDATE installed_kegs SET copy_strategy = ?2 WHERE name = ?1",
+                params![name, strategy.as_str()],
+            )
+            .map_err(Error::store("failed to record copy strategy"))?;
+
+        Ok(())
+    }
+
+    /// Record `prefix` as the one this root installs into, unless another
+    /// already is.
+    pub fn claim_prefix(&self, prefix: &Path) -> Result<(), Error> {
+        self.tx
+            .execute(
+                "INSERT OR IGNORE INTO meta (key, value) VALUES ('prefix', ?1)",
+                params![prefix.to_string_lossy()],
+            )
+            .map_err(Error::store("failed to record prefix"))?;
+
+        Ok(())
+    }
+
+    /// Record the prefix `name`'s files were patched for.
+    pub fn record_patched_prefix(&self, name: &str, prefix: &Path) -> Result<(), Error> {
+        self.tx
+            .execute(
+                "UPDATE installed_kegs SET patched_prefix = ?2 WHERE name = ?1",
+                params![name, prefix.to_string_lossy()],
+            )
+            .map_err(Error::store("failed to record patched prefix"))?;
+
+        Ok(())
+    }
+
+    pub fn record_upstream_status(
+        &self,
+        name: &str,
+        lifecycle: &Lifecycle,
+        since: Option<&str>,
+    ) -> Result<(), Error> {
+        write_upstream_status(&self.tx, name, lifecycle, since)
+    }
+
+    /// Record the formula's version for a keg the bottle named differently.
+    /// The revision is read off it rather than off the keg directory.
+    pub fn record_formula_version(&self, name: &str, version: &str) -> Result<(), Error> {
+        self.touch();
+        self.tx
+            .execute(
+                "UPDATE installed_kegs SET formula_version = ?2, revision = ?3 WHERE name = ?1",
+                params![name, version, revision_of(version)],
+            )
+            .map_err(Error::store("failed to record formula version"))?;
+
+        Ok(())
+    }
+
+    /// The bottle rebuild `name` was installed from; source builds have none.
+    pub fn record_rebuild(&self, name: &str, rebuild: u32) -> Result<(), Error> {
+        self.touch();
+        self.tx
+            .execute(
+                "UPDATE installed_kegs SET rebuild = ?2 WHERE name = ?1",
+                params![name, rebuild],
+            )
+            .map_err(Error::store("failed to record rebuild"))?;
+
+        Ok(())
+    }
+
+    pub fn record_license(&self, name: &str, license: Option<&str>) -> Result<(), Error> {
+        self.touch();
+        self.tx
+            .execute(
+                "UPDATE installed_kegs SET license = ?2 WHERE name = ?1",
+                params![name, license],
+            )
+            .map_err(Error::store("failed to record license"))?;
+
+        Ok(())
+    }
+
+    /// Replace the recorded runtime dependencies of `name`.
+    pub fn record_dependencies(&self, name: &str, dependencies: &[String]) -> Result<(), Error> {
+        self.tx
+            .execute(
+                "DELETE FROM keg_dependencies WHERE name = ?1",
+                params![name],
+            )
+            .map_err(Error::store("failed to clear dependency records"))?;
+
+        for dependency in dependencies {
+            self.tx
+                .execute(
+                    "INSERT OR IGNORE INTO keg_dependencies (name, dependency) VALUES (?1, ?2)",
+                    params![name, dependency],
+                )
+                .map_err(Error::store("failed to record dependency"))?;
+        }
+
+        Ok(())
+    }
+
+    /// `target_path` is where the file resolves to and must be absolute;
+    /// `link_target` is what a symlink literally holds, which may be
+    /// relative.
+    pub fn record_linked_file(
+        &self,
+        name: &str,
+        version: &str,
+        linked_path: &str,
+        target_path: &str,
+        link_target: Option<&str>,
+        kind: KegFileKind,
     ) -> Result<(), Error> {
+        if !Path::new(target_path).is_absolute() {
+            return Err(Error::InvalidArgument {
+                message: format!(
+                    "keg file target for {linked_path} must be absolute, got {target_path}"
+                ),
+            });
+        }
         self.tx
             .execute(
-                "INSERT OR REPLACE INTO keg_files (name, version, linked_path, target_path)
-                 VALUES (?1, ?2, ?3, ?4)",
-                params![name, version, linked_path, target_path],
+                "INSERT OR REPLACE INTO keg_files
+                    (name, version, linked_path, target_path, kind, link_target)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    name,
+                    version,
+                    linked_path,
+                    target_path,
+                    kind.as_str(),
+                    link_target
+                ],
             )
             .map_err(Error::store("failed to record linked file"))?;
 
         Ok(())
     }
 
+    /// Record a file the [`Linker`](crate::cellar::Linker) put into the
+    /// prefix.
+    pub fn record_linked(
+        &self,
+        name: &str,
+        version: &str,
+        linked: &LinkedFile,
+    ) -> Result<(), Error> {
+        self.record_linked_file(
+            name,
+            version,
+            &linked.link_path.to_string_lossy(),
+            &linked.target_path.to_string_lossy(),
+            linked
+                .link_target
+                .as_ref()
+                .map(|target| target.to_string_lossy())
+                .as_deref(),
+            linked.kind,
+        )
+    }
+
     pub fn record_uninstall(&self, name: &str) -> Result<Option<String>, Error> {
+        self.touch();
         // Get the store_key before removing
         let store_key: Option<String> = self
             .tx
@@ -398,31 +1888,78 @@ impl<'a> InstallTransaction<'a> {
                 params![name],
                 |row| row.get(0),
             )
-            .ok();
+            .optional()
+            .map_err(Error::store("failed to query store key"))?;
 
         // Remove installed keg record
         self.tx
             .execute("DELETE FROM installed_kegs WHERE name = ?1", params![name])
             .map_err(Error::store("failed to remove install record"))?;
 
+        let retained_keys: Vec<String> = self
+            .tx
+            .prepare("DELETE FROM retained_kegs WHERE name = ?1 RETURNING store_key")
+            .and_then(|mut stmt| {
+                stmt.query_map(params![name], |row| row.get(0))?
+                    .collect::<Result<_, _>>()
+            })
+            .map_err(Error::store("failed to remove retained versions"))?;
+        for key in &retained_keys {
+            self.release_store_ref(key)?;
+        }
+
         self.tx
             .execute("DELETE FROM keg_files WHERE name = ?1", params![name])
             .map_err(Error::store("failed to remove keg files records"))?;
 
+        self.tx
+            .execute(
+                "DELETE FROM keg_dependencies WHERE name = ?1",
+                params![name],
+            )
+            .map_err(Error::store("failed to remove dependency records"))?;
+
         // Decrement store ref if we had one
         if let Some(ref key) = store_key {
-            self.tx
-                .execute(
-                    "UPDATE store_refs SET refcount = refcount - 1 WHERE store_key = ?1",
-                    params![key],
-                )
-                .map_err(Error::store("failed to decrement store ref"))?;
+            self.release_store_ref(key)?;
         }
 
         Ok(store_key)
     }
 
+    /// Drop one reference to `store_key`, never below zero. A count that is
+    /// already zero means a reference was released twice somewhere, so it
+    /// is logged rather than recorded as a negative count gc would trip
+    /// over forever.
+    fn release_store_ref(&self, store_key: &str) -> Result<(), Error> {
+        let refcount: Option<i64> = self
+            .tx
+            .query_row(
+                "SELECT refcount FROM store_refs WHERE store_key = ?1",
+                params![store_key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Error::store("failed to query store ref"))?;
+        if refcount.is_some_and(|count| count <= 0) {
+            warn!(
+                store_key,
+                ?refcount,
+                "store ref released with no references left"
+            );
+        }
+
+        self.tx
+            .execute(
+                "UPDATE store_refs SET refcount = MAX(refcount - 1, 0) WHERE store_key = ?1",
+                params![store_key],
+            )
+            .map_err(Error::store("failed to decrement store ref"))?;
+        Ok(())
+    }
+
     pub fn delete_installed_record(&self, name: &str) -> Result<(), Error> {
+        self.touch();
         self.tx
             .execute("DELETE FROM installed_kegs WHERE name = ?1", params![name])
             .map_err(Error::store("failed to remove install record"))?;
@@ -438,10 +1975,26 @@ impl<'a> InstallTransaction<'a> {
         Ok(())
     }
 
+    /// Mark `name` as moved to the new prefix of the relocation in progress.
+    pub fn record_keg_relocated(&self, name: &str) -> Result<(), Error> {
+        self.tx
+            .execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, '1')",
+                params![format!("relocated:{name}")],
+            )
+            .map_err(Error::store("failed to record relocated keg"))?;
+
+        Ok(())
+    }
+
     pub fn commit(self) -> Result<(), Error> {
         self.tx
             .commit()
-            .map_err(Error::store("failed to commit transaction"))
+            .map_err(Error::store("failed to commit transaction"))?;
+        if self.touched.get() {
+            self.generation.set(self.generation.get() + 1);
+        }
+        Ok(())
     }
 
     // Transaction is rolled back automatically when dropped without commit
@@ -482,7 +2035,7 @@ mod tests {
         assert!(installed.is_empty());
 
         // Store ref should also not exist
-        assert_eq!(db.get_store_refcount("abc123"), 0);
+        assert_eq!(db.get_store_refcount("abc123").unwrap(), 0);
     }
 
     #[test]
@@ -496,7 +2049,34 @@ mod tests {
             tx.commit().unwrap();
         }
 
-        assert_eq!(db.get_store_refcount("shared123"), 2);
+        assert_eq!(db.get_store_refcount("shared123").unwrap(), 2);
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_uninstall("foo").unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert_eq!(db.get_store_refcount("shared123").unwrap(), 1);
+        assert!(db.get_installed("foo").unwrap().is_none());
+        assert!(db.get_installed("bar").unwrap().is_some());
+    }
+
+    #[test]
+    fn releasing_a_store_ref_never_goes_below_zero() {
+        let mut db = Database::in_memory().unwrap();
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", "1.0.0", "key").unwrap();
+            tx.commit().unwrap();
+        }
+        // As if a crash had already released foo's reference.
+        db.replace_store_refs(&[StoreRef {
+            store_key: "key".to_string(),
+            refcount: 0,
+        }])
+        .unwrap();
 
         {
             let tx = db.transaction().unwrap();
@@ -504,9 +2084,34 @@ mod tests {
             tx.commit().unwrap();
         }
 
-        assert_eq!(db.get_store_refcount("shared123"), 1);
-        assert!(db.get_installed("foo").is_none());
-        assert!(db.get_installed("bar").is_some());
+        assert_eq!(db.get_store_refcount("key").unwrap(), 0);
+    }
+
+    #[test]
+    fn miscounted_store_keys_in_use_are_never_unreferenced() {
+        let mut db = Database::in_memory().unwrap();
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", "1.0.0", "live").unwrap();
+            tx.commit().unwrap();
+        }
+        db.replace_store_refs(&[
+            StoreRef {
+                store_key: "live".to_string(),
+                refcount: -1,
+            },
+            StoreRef {
+                store_key: "dead".to_string(),
+                refcount: -2,
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(db.get_unreferenced_store_keys().unwrap(), vec!["dead"]);
+        db.delete_store_ref("dead").unwrap();
+        assert!(db.get_unreferenced_store_keys().unwrap().is_empty());
+        assert_eq!(db.list_store_refs().unwrap().len(), 1);
     }
 
     #[test]
@@ -534,6 +2139,20 @@ mod tests {
         assert!(unreferenced.contains(&"key2".to_string()));
     }
 
+    #[test]
+    fn unreferenced_store_keys_come_back_sorted() {
+        let db = Database::in_memory().unwrap();
+        for key in ["d4", "a1", "c3", "b2"] {
+            db.register_store_entry(key).unwrap();
+        }
+
+        let first = db.get_unreferenced_store_keys().unwrap();
+        let second = db.get_unreferenced_store_keys().unwrap();
+
+        assert_eq!(first, ["a1", "b2", "c3", "d4"]);
+        assert_eq!(first, second);
+    }
+
     #[test]
     fn linked_files_are_recorded() {
         let mut db = Database::in_memory().unwrap();
@@ -546,6 +2165,8 @@ mod tests {
                 "1.0.0",
                 "/opt/homebrew/bin/foo",
                 "/opt/zerobrew/cellar/foo/1.0.0/bin/foo",
+                None,
+                KegFileKind::Symlink,
             )
             .unwrap();
             tx.commit().unwrap();
@@ -558,7 +2179,34 @@ mod tests {
             tx.commit().unwrap();
         }
 
-        assert!(db.get_installed("foo").is_none());
+        assert!(db.get_installed("foo").unwrap().is_none());
+    }
+
+    #[test]
+    fn keg_files_are_listed_per_keg_in_path_order() {
+        let mut db = Database::in_memory().unwrap();
+
+        {
+            let tx = db.transaction().unwrap();
+            for (name, path) in [
+                ("foo", "/p/share/foo"),
+                ("bar", "/p/bin/bar"),
+                ("foo", "/p/bin/foo"),
+            ] {
+                tx.record_linked_file(name, "1.0", path, "/c/target", None, KegFileKind::Symlink)
+                    .unwrap();
+            }
+            tx.commit().unwrap();
+        }
+
+        let paths: Vec<_> = db
+            .list_keg_files_for("foo", "1.0")
+            .unwrap()
+            .into_iter()
+            .map(|record| record.linked_path)
+            .collect();
+        assert_eq!(paths, ["/p/bin/foo", "/p/share/foo"]);
+        assert!(db.list_keg_files_for("foo", "2.0").unwrap().is_empty());
     }
 
     #[test]
@@ -571,7 +2219,7 @@ mod tests {
             tx.commit().unwrap();
         }
 
-        assert_eq!(db.get_store_refcount("samekey"), 1);
+        assert_eq!(db.get_store_refcount("samekey").unwrap(), 1);
 
         {
             let tx = db.transaction().unwrap();
@@ -579,7 +2227,7 @@ mod tests {
             tx.commit().unwrap();
         }
 
-        assert_eq!(db.get_store_refcount("samekey"), 1);
+        assert_eq!(db.get_store_refcount("samekey").unwrap(), 1);
     }
 
     #[test]
@@ -592,22 +2240,103 @@ mod tests {
             tx.commit().unwrap();
         }
 
-        assert_eq!(db.get_store_refcount("oldkey"), 1);
+        assert_eq!(db.get_store_refcount("oldkey").unwrap(), 1);
 
         {
             let tx = db.transaction().unwrap();
-            tx.record_install("foo", "1.1.0", "newkey").unwrap();
+            tx.record_install("foo", "1.0.0", "newkey").unwrap();
             tx.commit().unwrap();
         }
 
-        assert_eq!(db.get_store_refcount("oldkey"), 0);
-        assert_eq!(db.get_store_refcount("newkey"), 1);
+        assert_eq!(db.get_store_refcount("oldkey").unwrap(), 0);
+        assert_eq!(db.get_store_refcount("newkey").unwrap(), 1);
+        assert!(db.list_retained(Some("foo")).unwrap().is_empty());
 
-        let installed = db.get_installed("foo").unwrap();
-        assert_eq!(installed.version, "1.1.0");
+        let installed = db.get_installed("foo").unwrap().unwrap();
+        assert_eq!(installed.version, "1.0.0");
         assert_eq!(installed.store_key, "newkey");
     }
 
+    #[test]
+    fn upgrade_retains_previous_version_with_its_store_ref() {
+        let mut db = Database::in_memory().unwrap();
+
+        for (version, key) in [("1.0.0", "oldkey"), ("1.1.0", "newkey")] {
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", version, key).unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert_eq!(db.get_store_refcount("oldkey").unwrap(), 1);
+        assert_eq!(db.get_store_refcount("newkey").unwrap(), 1);
+        let retained = db.list_retained(Some("foo")).unwrap();
+        assert_eq!(retained.len(), 1);
+        assert_eq!(retained[0].version, "1.0.0");
+        assert_eq!(retained[0].store_key, "oldkey");
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.activate_retained("foo", "1.0.0").unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(
+            db.get_installed("foo").unwrap().unwrap().store_key,
+            "oldkey"
+        );
+        assert_eq!(db.list_retained(None).unwrap()[0].version, "1.1.0");
+        assert_eq!(db.get_store_refcount("oldkey").unwrap(), 1);
+        assert_eq!(db.get_store_refcount("newkey").unwrap(), 1);
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_uninstall("foo").unwrap();
+            tx.commit().unwrap();
+        }
+        assert!(db.list_retained(None).unwrap().is_empty());
+        assert_eq!(db.get_store_refcount("oldkey").unwrap(), 0);
+        assert_eq!(db.get_store_refcount("newkey").unwrap(), 0);
+    }
+
+    #[test]
+    fn licenses_follow_their_version_through_a_rollback() {
+        let mut db = Database::in_memory().unwrap();
+
+        for (version, license) in [("1.0.0", Some("GPL-2.0-only")), ("1.1.0", None)] {
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", version, version).unwrap();
+            tx.record_license("foo", license).unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(db.get_installed("foo").unwrap().unwrap().license, None);
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.activate_retained("foo", "1.0.0").unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(
+            db.list_installed().unwrap()[0].license.as_deref(),
+            Some("GPL-2.0-only")
+        );
+    }
+
+    #[test]
+    fn registered_store_entry_starts_unreferenced() {
+        let mut db = Database::in_memory().unwrap();
+        db.register_store_entry("tree").unwrap();
+        assert_eq!(db.get_unreferenced_store_keys().unwrap(), vec!["tree"]);
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("local", "1.0", "tree").unwrap();
+            tx.commit().unwrap();
+        }
+        db.register_store_entry("tree").unwrap();
+
+        assert_eq!(db.get_store_refcount("tree").unwrap(), 1);
+        assert!(db.get_unreferenced_store_keys().unwrap().is_empty());
+    }
+
     #[test]
     fn delete_store_ref_removes_unreferenced_entry() {
         let mut db = Database::in_memory().unwrap();
@@ -653,19 +2382,173 @@ mod tests {
     }
 
     #[test]
-    fn new_database_starts_at_version_1() {
-        let db = Database::in_memory().expect("failed to create database");
-        let version = Database::get_schema_version(&db.conn).expect("failed to get version");
-        assert_eq!(version, 1);
-    }
+    fn kegs_patched_for_another_prefix_are_reported_until_rolled_back() {
+        let mut db = Database::in_memory().unwrap();
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("jq", "1.7", "k1").unwrap();
+            tx.record_patched_prefix("jq", Path::new("/old")).unwrap();
+            tx.record_install("wget", "1.25", "k2").unwrap();
+            tx.record_patched_prefix("wget", Path::new("/new")).unwrap();
+            tx.record_install("legacy", "1.0", "k3").unwrap();
+            tx.commit().unwrap();
+        }
 
-    #[test]
-    fn migration_is_idempotent() {
-        let db = Database::in_memory().expect("failed to create database");
-        Database::migrate(&db.conn).expect("first migration failed");
+        let drifted = db.kegs_patched_for_other_prefix(Path::new("/new")).unwrap();
+        assert_eq!(
+            drifted,
+            [DriftedKeg {
+                name: "jq".to_string(),
+                version: "1.7".to_string(),
+                patched_prefix: PathBuf::from("/old"),
+            }]
+        );
+
+        // An upgrade records the new version's prefix; the old one keeps its
+        // own and brings it back on rollback.
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("jq", "1.8", "k4").unwrap();
+            tx.record_patched_prefix("jq", Path::new("/new")).unwrap();
+            tx.commit().unwrap();
+        }
+        assert!(
+            db.kegs_patched_for_other_prefix(Path::new("/new"))
+                .unwrap()
+                .is_empty()
+        );
+        {
+            let tx = db.transaction().unwrap();
+            tx.activate_retained("jq", "1.7").unwrap();
+            tx.commit().unwrap();
+        }
+        assert_eq!(
+            db.kegs_patched_for_other_prefix(Path::new("/new")).unwrap()[0].version,
+            "1.7"
+        );
+
+        db.begin_relocation(Path::new("/old"), Path::new("/new"))
+            .unwrap();
+        assert!(
+            db.kegs_patched_for_other_prefix(Path::new("/new"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn malformed_rows_are_errors_not_missing_kegs() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("zb.sqlite3");
+        {
+            let mut db = Database::open(&path).unwrap();
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", "1.0.0", "key").unwrap();
+            tx.commit().unwrap();
+        }
+        // Damage the file behind zerobrew's back, as a crash or a stray
+        // tool might.
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "UPDATE installed_kegs SET version = CAST(X'80' AS BLOB), store_key = X'80';",
+            )
+            .unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        let err = db.get_installed("foo").unwrap_err();
+        assert!(err.to_string().contains("failed to query installed keg"));
+        assert!(db.get_installed("bar").unwrap().is_none());
+
+        let tx = db.transaction().unwrap();
+        let err = tx.record_uninstall("foo").unwrap_err();
+        assert!(err.to_string().contains("failed to query store key"));
+    }
+
+    #[test]
+    fn new_database_starts_at_current_version() {
+        let db = Database::in_memory().expect("failed to create database");
+        let version = Database::get_schema_version(&db.conn).expect("failed to get version");
+        assert_eq!(version, Database::SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn events_round_trip_in_order() {
+        let db = Database::in_memory().unwrap();
+        let details = serde_json::json!({ "entries": 3, "bytes": 4096 });
+        db.record_event("gc", Some("alice"), Some(&details))
+            .unwrap();
+        db.record_event("cache-clean", None, None).unwrap();
+
+        let events = db.list_events().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, "gc");
+        assert_eq!(events[0].user.as_deref(), Some("alice"));
+        assert_eq!(events[0].details, Some(details));
+        assert_eq!(events[1].kind, "cache-clean");
+        assert_eq!(events[1].details, None);
+    }
+
+    #[test]
+    fn migration_is_idempotent() {
+        let db = Database::in_memory().expect("failed to create database");
+        Database::migrate(&db.conn).expect("first migration failed");
         Database::migrate(&db.conn).expect("second migration failed");
         let version = Database::get_schema_version(&db.conn).expect("failed to get version");
-        assert_eq!(version, 1);
+        assert_eq!(version, Database::SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn first_check_records_running_system() {
+        let mut db = Database::in_memory().unwrap();
+        assert_eq!(db.system_record().unwrap(), None);
+
+        let current = SystemRecord::current("0.2.1");
+        assert_eq!(db.check_system(&current).unwrap(), None);
+        assert_eq!(db.system_record().unwrap(), Some(current));
+    }
+
+    #[test]
+    fn version_change_is_reported_once() {
+        let mut db = Database::in_memory().unwrap();
+        db.check_system(&SystemRecord::current("0.2.1")).unwrap();
+
+        let newer = SystemRecord::current("0.3.0");
+        assert_eq!(db.check_system(&newer).unwrap(), Some("0.2.1".to_string()));
+        assert_eq!(db.check_system(&newer).unwrap(), None);
+
+        let record = db.system_record().unwrap().unwrap();
+        assert_eq!(record.created_by, "0.2.1");
+        assert_eq!(record.last_used_by, "0.3.0");
+    }
+
+    #[test]
+    fn foreign_architecture_is_rejected() {
+        let mut db = Database::in_memory().unwrap();
+        let mut other = SystemRecord::current("0.2.1");
+        other.arch = "not-this-arch".to_string();
+        db.check_system(&other).unwrap();
+
+        let err = db
+            .check_system(&SystemRecord::current("0.2.1"))
+            .unwrap_err();
+        assert!(err.to_string().contains("not-this-arch"));
+        assert!(err.to_string().contains("not portable"));
+    }
+
+    #[test]
+    fn foreign_os_is_rejected() {
+        let mut db = Database::in_memory().unwrap();
+        let mut other = SystemRecord::current("0.2.1");
+        other.os = "plan9".to_string();
+        db.check_system(&other).unwrap();
+
+        assert!(matches!(
+            db.check_system(&SystemRecord::current("0.3.0")),
+            Err(Error::StoreCorruption { .. })
+        ));
+        // A rejected check must not overwrite the record.
+        assert_eq!(db.system_record().unwrap().unwrap().os, "plan9");
     }
 
     #[test]
@@ -704,4 +2587,538 @@ mod tests {
             .expect("failed to query data");
         assert_eq!(name, "test");
     }
+
+    #[test]
+    fn v1_kegs_are_treated_as_explicit_after_migration() {
+        let conn = Connection::open_in_memory().expect("failed to open connection");
+        Database::migrate_to_v1(&conn).unwrap();
+        Database::set_schema_version(&conn, 1).unwrap();
+        conn.execute(
+            "INSERT INTO installed_kegs VALUES ('old', '1.0.0', 'key', 1)",
+            [],
+        )
+        .unwrap();
+
+        Database::migrate(&conn).expect("migration failed");
+        let db = Database::with_connection(conn);
+
+        assert!(db.get_installed("old").unwrap().unwrap().explicit);
+        assert!(db.list_dependency_edges().unwrap().is_empty());
+    }
+
+    #[test]
+    fn keg_file_kinds_round_trip() {
+        let mut db = Database::in_memory().unwrap();
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", "1.0.0", "abc123").unwrap();
+            for (path, kind) in [
+                ("/p/bin/foo", KegFileKind::Symlink),
+                ("/p/etc/foo.conf", KegFileKind::Config),
+                ("/p/bin/foo-wrapped", KegFileKind::Wrapper),
+                ("/p/share/foo", KegFileKind::Dir),
+            ] {
+                tx.record_linked_file("foo", "1.0.0", path, "/keg", None, kind)
+                    .unwrap();
+            }
+            tx.commit().unwrap();
+        }
+
+        let kinds: Vec<(String, KegFileKind)> = db
+            .list_keg_files()
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.linked_path, record.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("/p/bin/foo".to_string(), KegFileKind::Symlink),
+                ("/p/bin/foo-wrapped".to_string(), KegFileKind::Wrapper),
+                ("/p/etc/foo.conf".to_string(), KegFileKind::Config),
+                ("/p/share/foo".to_string(), KegFileKind::Dir),
+            ]
+        );
+    }
+
+    #[test]
+    fn v3_keg_files_become_symlinks_after_migration() {
+        let conn = Connection::open_in_memory().expect("failed to open connection");
+        for version in 1..=3 {
+            Database::migrate_to_version(&conn, version).unwrap();
+        }
+        Database::set_schema_version(&conn, 3).unwrap();
+        conn.execute(
+            "INSERT INTO keg_files VALUES ('old', '1.0.0', '/p/bin/old', '/keg/bin/old')",
+            [],
+        )
+        .unwrap();
+
+        Database::migrate(&conn).expect("migration failed");
+        let db = Database::with_connection(conn);
+
+        assert_eq!(db.list_keg_files().unwrap()[0].kind, KegFileKind::Symlink);
+    }
+
+    #[test]
+    fn keg_file_targets_round_trip_and_must_be_absolute() {
+        let mut db = Database::in_memory().unwrap();
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_linked_file(
+                "foo",
+                "1.0.0",
+                "/p/bin/foo",
+                "/p/Cellar/foo/1.0.0/bin/foo",
+                Some("../Cellar/foo/1.0.0/bin/foo"),
+                KegFileKind::Symlink,
+            )
+            .unwrap();
+            let err = tx
+                .record_linked_file(
+                    "foo",
+                    "1.0.0",
+                    "/p/bin/bar",
+                    "../Cellar/foo/1.0.0/bin/bar",
+                    None,
+                    KegFileKind::Symlink,
+                )
+                .unwrap_err();
+            assert!(err.to_string().contains("must be absolute"), "{err}");
+            tx.commit().unwrap();
+        }
+
+        let records = db.list_keg_files().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].target_path, "/p/Cellar/foo/1.0.0/bin/foo");
+        assert_eq!(
+            records[0].link_target.as_deref(),
+            Some("../Cellar/foo/1.0.0/bin/foo")
+        );
+    }
+
+    #[test]
+    fn v8_keg_file_targets_are_resolved_after_migration() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let prefix = tmp.path();
+        fs::create_dir_all(prefix.join("bin")).unwrap();
+        std::os::unix::fs::symlink("/keg/bin/live", prefix.join("bin/live")).unwrap();
+
+        let conn = Connection::open_in_memory().expect("failed to open connection");
+        for version in 1..=8 {
+            Database::migrate_to_version(&conn, version).unwrap();
+        }
+        Database::set_schema_version(&conn, 8).unwrap();
+        for (linked, target, kind) in [
+            ("bin/live", "/keg/bin/live", "symlink"),
+            ("bin/rel", "../Cellar/rel/1.0/bin/rel", "symlink"),
+            ("bin/gone", "/keg/bin/gone", "symlink"),
+            ("etc/rel.conf", "/keg/etc/rel.conf", "config"),
+        ] {
+            conn.execute(
+                "INSERT INTO keg_files (name, version, linked_path, target_path, kind)
+                 VALUES ('rel', '1.0', ?1, ?2, ?3)",
+                params![prefix.join(linked).to_string_lossy(), target, kind],
+            )
+            .unwrap();
+        }
+
+        Database::migrate(&conn).expect("migration failed");
+        let db = Database::with_connection(conn);
+
+        let targets: Vec<(String, Option<String>)> = db
+            .list_keg_files()
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.target_path, record.link_target))
+            .collect();
+        let at = |path: &str| prefix.join(path).to_string_lossy().into_owned();
+        assert_eq!(
+            targets,
+            [
+                ("/keg/bin/gone".to_string(), None),
+                (
+                    "/keg/bin/live".to_string(),
+                    Some("/keg/bin/live".to_string())
+                ),
+                (
+                    at("Cellar/rel/1.0/bin/rel"),
+                    Some("../Cellar/rel/1.0/bin/rel".to_string())
+                ),
+                ("/keg/etc/rel.conf".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn v10_kegs_read_their_revision_off_the_version() {
+        let conn = Connection::open_in_memory().expect("failed to open connection");
+        for version in 1..=10 {
+            Database::migrate_to_version(&conn, version).unwrap();
+        }
+        Database::set_schema_version(&conn, 10).unwrap();
+        conn.execute_batch(
+            "INSERT INTO installed_kegs (name, version, store_key, installed_at)
+             VALUES ('jq', '1.7.1_2', 'a', 0), ('tzdata', '2024_beta', 'b', 0);
+             INSERT INTO retained_kegs (name, version, store_key, installed_at, retained_at)
+             VALUES ('jq', '1.7.1_1', 'c', 0, 0);",
+        )
+        .unwrap();
+
+        Database::migrate(&conn).expect("migration failed");
+        let db = Database::with_connection(conn);
+
+        let builds: Vec<(u32, Option<u32>)> = db
+            .list_installed()
+            .unwrap()
+            .iter()
+            .map(|keg| (keg.revision, keg.rebuild))
+            .collect();
+        assert_eq!(builds, [(2, None), (0, None)]);
+        let retained: u32 = db
+            .conn
+            .query_row("SELECT revision FROM retained_kegs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(retained, 1);
+    }
+
+    #[test]
+    fn installed_set_goes_stale_once_an_installed_keg_changes() {
+        let mut db = Database::in_memory().unwrap();
+        let tx = db.transaction().unwrap();
+        tx.record_install("jq", "1.7.1", "a").unwrap();
+        tx.commit().unwrap();
+
+        let set = db.load_installed_set().unwrap();
+        assert!(db.is_current(&set));
+        assert_eq!(set.version("jq"), Some("1.7.1"));
+        assert_eq!(db.get_store_refcount("a").unwrap(), 1);
+        assert!(db.is_current(&set), "reads leave the set current");
+        db.record_upstream_status("jq", &Lifecycle::Active, None)
+            .unwrap();
+        db.record_event("upgrade", None, None).unwrap();
+        assert!(db.is_current(&set), "other tables leave the set current");
+
+        let tx = db.transaction().unwrap();
+        tx.record_install("wget", "1.25.0", "b").unwrap();
+        drop(tx);
+        assert!(db.is_current(&set), "a rolled back install changes nothing");
+
+        let tx = db.transaction().unwrap();
+        tx.record_install("wget", "1.25.0", "b").unwrap();
+        tx.commit().unwrap();
+        assert!(!db.is_current(&set));
+        let set = db.load_installed_set().unwrap();
+        assert_eq!(set.len(), 2);
+
+        let tx = db.transaction().unwrap();
+        tx.record_uninstall("jq").unwrap();
+        tx.commit().unwrap();
+        assert!(!db.is_current(&set));
+        let set = db.load_installed_set().unwrap();
+        assert!(!set.contains("jq"));
+
+        db.set_pinned("wget", true).unwrap();
+        assert!(!db.is_current(&set));
+        assert!(db.load_installed_set().unwrap().get("wget").unwrap().pinned);
+    }
+
+    /// Per-name lookups against one snapshot for a 500-entry bundle.
+    #[test]
+    #[ignore = "benchmark: run with --release -- --ignored --nocapture"]
+    fn installed_set_beats_a_query_per_name() {
+        use std::time::Instant;
+
+        let mut db = Database::in_memory().unwrap();
+        let names: Vec<String> = (0..500).map(|i| format!("formula{i}")).collect();
+        let tx = db.transaction().unwrap();
+        for name in &names {
+            tx.record_install(name, "1.0.0", name).unwrap();
+        }
+        tx.commit().unwrap();
+
+        // A bundle looks each entry up a few times: planning, filtering the
+        // plan and reporting.
+        const PASSES: usize = 4;
+        let started = Instant::now();
+        let mut queried = 0;
+        for _ in 0..PASSES {
+            for name in &names {
+                queried += usize::from(db.get_installed(name).unwrap().is_some());
+            }
+        }
+        let per_query = started.elapsed();
+
+        let started = Instant::now();
+        let set = db.load_installed_set().unwrap();
+        let mut looked_up = 0;
+        for _ in 0..PASSES {
+            for name in &names {
+                looked_up += usize::from(db.is_current(&set) && set.contains(name));
+            }
+        }
+        let snapshot = started.elapsed();
+
+        println!("query per name: {per_query:?}, one snapshot: {snapshot:?}");
+        assert_eq!(queried, looked_up);
+        assert!(snapshot < per_query);
+    }
+
+    #[test]
+    fn the_first_install_claims_the_prefix_until_nothing_is_left() {
+        let mut db = Database::in_memory().unwrap();
+        let (first, second) = (Path::new("/opt/zerobrew"), Path::new("/elsewhere"));
+        for prefix in [first, second] {
+            let tx = db.transaction().unwrap();
+            tx.record_install("jq", "1.7.1", "a").unwrap();
+            tx.claim_prefix(prefix).unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert_eq!(db.recorded_prefix().unwrap().as_deref(), Some(first));
+        assert_eq!(db.prefix_conflict(first).unwrap(), None);
+        assert_eq!(db.prefix_conflict(second).unwrap().as_deref(), Some(first));
+        db.release_prefix(second).unwrap();
+        assert_eq!(db.recorded_prefix().unwrap().as_deref(), Some(first));
+
+        db.begin_relocation(first, second).unwrap();
+        assert_eq!(db.prefix_conflict(second).unwrap(), None);
+        db.finish_relocation(second).unwrap();
+        assert_eq!(db.prefix_conflict(second).unwrap(), None);
+
+        let tx = db.transaction().unwrap();
+        tx.record_uninstall("jq").unwrap();
+        tx.commit().unwrap();
+        assert_eq!(db.prefix_conflict(first).unwrap(), None);
+        db.release_prefix(first).unwrap();
+        assert_eq!(db.recorded_prefix().unwrap(), None);
+    }
+
+    #[test]
+    fn upstream_statuses_follow_refreshes_of_installed_formulas() {
+        let mut db = Database::in_memory().unwrap();
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("terraform", "1.5.7", "a").unwrap();
+            tx.record_install("jq", "1.7.1", "b").unwrap();
+            tx.commit().unwrap();
+        }
+        let deprecated = Lifecycle::Deprecated {
+            reason: Some("changed its license".to_string()),
+        };
+        db.record_upstream_status("terraform", &deprecated, Some("2024-04-04"))
+            .unwrap();
+        db.record_upstream_status("jq", &Lifecycle::Active, None)
+            .unwrap();
+        db.record_upstream_status("gone", &Lifecycle::Disabled { reason: None }, None)
+            .unwrap();
+
+        let statuses = db.list_upstream_statuses().unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].name, "terraform");
+        assert_eq!(statuses[0].lifecycle, deprecated);
+        assert_eq!(statuses[0].since.as_deref(), Some("2024-04-04"));
+
+        db.record_upstream_status("terraform", &Lifecycle::Disabled { reason: None }, None)
+            .unwrap();
+        assert_eq!(
+            db.list_upstream_statuses().unwrap()[0].lifecycle,
+            Lifecycle::Disabled { reason: None }
+        );
+        db.record_upstream_status("terraform", &Lifecycle::Active, None)
+            .unwrap();
+        assert!(db.list_upstream_statuses().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rebuilds_are_reset_by_reinstalls_and_follow_rollbacks() {
+        let mut db = Database::in_memory().unwrap();
+
+        for (version, key, rebuild) in [("1.0_1", "a", Some(2)), ("1.1", "b", None)] {
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", version, key).unwrap();
+            if let Some(rebuild) = rebuild {
+                tx.record_rebuild("foo", rebuild).unwrap();
+            }
+            tx.commit().unwrap();
+        }
+        let keg = db.get_installed("foo").unwrap().unwrap();
+        assert_eq!((keg.revision, keg.rebuild), (0, None));
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.activate_retained("foo", "1.0_1").unwrap();
+            tx.commit().unwrap();
+        }
+        let keg = db.get_installed("foo").unwrap().unwrap();
+        assert_eq!(keg.build().to_string(), "1.0_1 (rebuild 2)");
+        assert_eq!((keg.revision, keg.rebuild), (1, Some(2)));
+    }
+
+    #[test]
+    fn formula_versions_are_reset_by_reinstalls_and_follow_rollbacks() {
+        let mut db = Database::in_memory().unwrap();
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", "1.2.3_1", "a").unwrap();
+            tx.record_formula_version("foo", "1.2.3").unwrap();
+            tx.commit().unwrap();
+        }
+        let keg = db.get_installed("foo").unwrap().unwrap();
+        assert_eq!(keg.version, "1.2.3_1");
+        assert_eq!(keg.upstream_version(), "1.2.3");
+        assert_eq!(keg.revision, 0);
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("foo", "1.2.4", "b").unwrap();
+            tx.commit().unwrap();
+        }
+        let keg = db.get_installed("foo").unwrap().unwrap();
+        assert_eq!(keg.formula_version, None);
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.activate_retained("foo", "1.2.3_1").unwrap();
+            tx.commit().unwrap();
+        }
+        let keg = db.get_installed("foo").unwrap().unwrap();
+        assert_eq!(keg.upstream_version(), "1.2.3");
+    }
+
+    #[test]
+    fn pins_survive_reinstalls_until_uninstalled() {
+        let mut db = Database::in_memory().unwrap();
+        assert!(matches!(
+            db.set_pinned("jq", true),
+            Err(Error::NotInstalled { name }) if name == "jq"
+        ));
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("jq", "1.7.1", "a").unwrap();
+            tx.commit().unwrap();
+        }
+        db.set_pinned("jq", true).unwrap();
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("jq", "1.7.1", "a").unwrap();
+            tx.commit().unwrap();
+        }
+        assert!(db.get_installed("jq").unwrap().unwrap().pinned);
+
+        db.set_pinned("jq", false).unwrap();
+        assert!(!db.get_installed("jq").unwrap().unwrap().pinned);
+
+        db.set_pinned("jq", true).unwrap();
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_uninstall("jq").unwrap();
+            tx.record_install("jq", "1.7.1", "a").unwrap();
+            tx.commit().unwrap();
+        }
+        assert!(!db.get_installed("jq").unwrap().unwrap().pinned);
+    }
+
+    #[test]
+    fn relocation_progress_is_tracked_until_finished() {
+        let mut db = Database::in_memory().unwrap();
+        assert_eq!(db.relocation_state().unwrap(), None);
+
+        db.begin_relocation(Path::new("/opt/zb"), Path::new("/srv/zb"))
+            .unwrap();
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_keg_relocated("jq").unwrap();
+            tx.record_keg_relocated("oniguruma").unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert_eq!(
+            db.relocation_state().unwrap(),
+            Some(RelocationState {
+                from: PathBuf::from("/opt/zb"),
+                to: PathBuf::from("/srv/zb"),
+                relocated: vec!["jq".to_string(), "oniguruma".to_string()],
+            })
+        );
+
+        db.finish_relocation(Path::new("/srv/zb")).unwrap();
+
+        assert_eq!(db.relocation_state().unwrap(), None);
+        assert_eq!(
+            db.recorded_prefix().unwrap(),
+            Some(PathBuf::from("/srv/zb"))
+        );
+    }
+
+    #[test]
+    fn copy_strategies_round_trip_through_their_column_value() {
+        for strategy in [
+            CopyStrategy::Clonefile,
+            CopyStrategy::Hardlink,
+            CopyStrategy::Copy,
+        ] {
+            assert_eq!(CopyStrategy::parse(strategy.as_str()), Some(strategy));
+        }
+        assert_eq!(CopyStrategy::parse("reflink"), None);
+    }
+
+    #[test]
+    fn copy_strategy_is_recorded_per_keg() {
+        let mut db = Database::in_memory().unwrap();
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("jq", "1.7", "k1").unwrap();
+            tx.record_copy_strategy("jq", CopyStrategy::Copy).unwrap();
+            tx.record_install("built", "1.0", "k2").unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert_eq!(
+            db.get_installed("jq").unwrap().unwrap().copy_strategy,
+            Some(CopyStrategy::Copy)
+        );
+        assert_eq!(
+            db.get_installed("built").unwrap().unwrap().copy_strategy,
+            None
+        );
+    }
+
+    #[test]
+    fn dependency_edges_and_reason_are_recorded() {
+        let mut db = Database::in_memory().unwrap();
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_install("git", "2.0", "k1").unwrap();
+            tx.record_install_reason("git", true).unwrap();
+            tx.record_dependencies("git", &["gettext".to_string(), "pcre2".to_string()])
+                .unwrap();
+            tx.record_install("gettext", "0.22", "k2").unwrap();
+            tx.record_install_reason("gettext", false).unwrap();
+            tx.commit().unwrap();
+        }
+
+        assert!(db.get_installed("git").unwrap().unwrap().explicit);
+        assert!(!db.get_installed("gettext").unwrap().unwrap().explicit);
+        assert_eq!(
+            db.list_dependency_edges().unwrap(),
+            vec![
+                ("git".to_string(), "gettext".to_string()),
+                ("git".to_string(), "pcre2".to_string()),
+            ]
+        );
+
+        {
+            let tx = db.transaction().unwrap();
+            tx.record_uninstall("git").unwrap();
+            tx.commit().unwrap();
+        }
+        assert!(db.list_dependency_edges().unwrap().is_empty());
+    }
 }
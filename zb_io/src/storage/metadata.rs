@@ -0,0 +1,145 @@
+//! The formula metadata each keg was installed from, kept beside the store
+//! so decisions about installed kegs don't depend on what the API says
+//! today.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use zb_core::{Error, Formula, formula_token};
+
+const METADATA_COMPRESSION_LEVEL: i32 = 9;
+
+/// `<root>/metadata/<name>-<version>.json.zst`, one per keg.
+pub struct KegMetadata {
+    dir: PathBuf,
+}
+
+impl KegMetadata {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            dir: root.join("metadata"),
+        }
+    }
+
+    pub fn path(&self, name: &str, version: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}-{version}.json.zst", formula_token(name)))
+    }
+
+    /// Keep `formula` as the metadata `version` of `name` was installed
+    /// from, replacing what an earlier install of the same version kept.
+    pub fn write(&self, name: &str, version: &str, formula: &Formula) -> Result<(), Error> {
+        let path = self.path(name, version);
+        let ctx = format!("failed to write formula metadata {}", path.display());
+        let json = serde_json::to_vec(formula).map_err(|e| Error::StoreCorruption {
+            message: format!("failed to serialize formula metadata: {e}"),
+        })?;
+        let compressed = zstd::encode_all(json.as_slice(), METADATA_COMPRESSION_LEVEL)
+            .map_err(Error::file(ctx.as_str()))?;
+
+        fs::create_dir_all(&self.dir).map_err(Error::file(ctx.as_str()))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, compressed).map_err(Error::file(ctx.as_str()))?;
+        fs::rename(&tmp, &path).map_err(Error::file(ctx.as_str()))
+    }
+
+    /// The metadata `version` of `name` was installed from, or `None` for a
+    /// keg installed before metadata was kept.
+    pub fn read(&self, name: &str, version: &str) -> Result<Option<Formula>, Error> {
+        let path = self.path(name, version);
+        let ctx = format!("failed to read formula metadata {}", path.display());
+        let compressed = match fs::read(&path) {
+            Ok(compressed) => compressed,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::file(ctx.as_str())(e)),
+        };
+        let json = zstd::decode_all(compressed.as_slice()).map_err(Error::file(ctx.as_str()))?;
+        serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("invalid formula metadata {}: {e}", path.display()),
+            })
+    }
+
+    pub fn remove(&self, name: &str, version: &str) -> Result<(), Error> {
+        let path = self.path(name, version);
+        let ctx = format!("failed to remove formula metadata {}", path.display());
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::file(ctx.as_str())(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn formula(name: &str, version: &str) -> Formula {
+        Formula::from_json(
+            name,
+            &format!(
+                r#"{{
+                    "name": "{name}",
+                    "versions": {{ "stable": "{version}" }},
+                    "dependencies": ["oniguruma"],
+                    "keg_only": true,
+                    "bottle": {{ "stable": {{ "files": {{}} }} }}
+                }}"#
+            ),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn metadata_round_trips_per_version() {
+        let tmp = TempDir::new().unwrap();
+        let metadata = KegMetadata::new(tmp.path());
+        metadata.write("jq", "1.7", &formula("jq", "1.7")).unwrap();
+        metadata.write("jq", "1.8", &formula("jq", "1.8")).unwrap();
+
+        assert!(tmp.path().join("metadata/jq-1.7.json.zst").is_file());
+        let kept = metadata.read("jq", "1.7").unwrap().unwrap();
+        assert_eq!(kept, formula("jq", "1.7"));
+        assert!(kept.is_keg_only());
+        assert_eq!(
+            metadata.read("jq", "1.8").unwrap().unwrap().versions.stable,
+            "1.8"
+        );
+    }
+
+    #[test]
+    fn tap_formulas_are_kept_under_their_token() {
+        let tmp = TempDir::new().unwrap();
+        let metadata = KegMetadata::new(tmp.path());
+        let terraform = formula("hashicorp/tap/terraform", "1.9.0");
+        metadata
+            .write("hashicorp/tap/terraform", "1.9.0", &terraform)
+            .unwrap();
+
+        assert!(
+            tmp.path()
+                .join("metadata/terraform-1.9.0.json.zst")
+                .is_file()
+        );
+        assert_eq!(
+            metadata.read("terraform", "1.9.0").unwrap(),
+            Some(terraform)
+        );
+    }
+
+    #[test]
+    fn missing_metadata_reads_as_none_and_removes_cleanly() {
+        let tmp = TempDir::new().unwrap();
+        let metadata = KegMetadata::new(tmp.path());
+        assert_eq!(metadata.read("jq", "1.7").unwrap(), None);
+
+        metadata.write("jq", "1.7", &formula("jq", "1.7")).unwrap();
+        metadata.remove("jq", "1.7").unwrap();
+        metadata.remove("jq", "1.7").unwrap();
+        assert_eq!(metadata.read("jq", "1.7").unwrap(), None);
+    }
+}
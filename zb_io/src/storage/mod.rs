@@ -1,7 +1,13 @@
 pub mod blob;
 pub mod db;
+pub mod metadata;
 pub mod store;
 
-pub use blob::{BlobCache, BlobWriter};
-pub use db::{Database, InstallTransaction, InstalledKeg, KegFileRecord, StoreRef};
-pub use store::Store;
+pub use blob::{BlobCache, BlobEntry, BlobWriter, IndexRebuild};
+pub use db::{
+    CopyStrategy, Database, DriftedKeg, HistoryEvent, InstallTransaction, InstalledKeg,
+    InstalledSet, KegFileKind, KegFileRecord, LinkedFile, RelocationState, RetainedKeg, StoreRef,
+    SystemRecord, UpstreamStatus,
+};
+pub use metadata::KegMetadata;
+pub use store::{Store, StoreArchive, StoreKey};
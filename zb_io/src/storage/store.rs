@@ -1,15 +1,45 @@
 use std::fs::{self, File};
 use std::io;
+#[cfg(feature = "materialize")]
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use fs4::fs_std::FileExt;
+#[cfg(feature = "materialize")]
+use sha2::{Digest, Sha256};
 
-use crate::extraction::extract::extract_archive;
+#[cfg(feature = "materialize")]
+use crate::cellar::materialize::clone_or_copy_dir;
+use crate::extraction::ExtractionBudget;
+use crate::extraction::extract::{PathLimits, extract_archive_buffered};
 use zb_core::Error;
 
+/// Names a store entry: the bottle's sha256, or the tree hash of a directory
+/// added with [`Store::add_dir`].
+pub type StoreKey = String;
+
+/// Compressed store entries left by `zb gc --archive` are named
+/// `<key>.tar.zst` in the archive directory.
+const ARCHIVE_SUFFIX: &str = ".tar.zst";
+
+/// A store entry kept compressed instead of expanded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreArchive {
+    pub store_key: StoreKey,
+    pub bytes: u64,
+    /// When the entry was archived.
+    pub archived_at: SystemTime,
+}
+
+/// Clones share the extraction budget.
+#[derive(Clone)]
 pub struct Store {
     store_dir: PathBuf,
+    archive_dir: PathBuf,
     locks_dir: PathBuf,
+    path_limits: PathLimits,
+    extraction_budget: ExtractionBudget,
 }
 
 impl Store {
@@ -22,16 +52,36 @@ impl Store {
 
         Ok(Self {
             store_dir,
+            archive_dir: root.join("archive"),
             locks_dir,
+            path_limits: PathLimits::default(),
+            extraction_budget: ExtractionBudget::from_system_memory(),
         })
     }
 
-    pub fn entry_path(&self, store_key: &str) -> PathBuf {
+    /// The limits bottle and archive entry paths are checked against when
+    /// they are unpacked into the store.
+    pub fn set_path_limits(&mut self, limits: PathLimits) {
+        self.path_limits = limits;
+    }
+
+    /// The memory budget bottles and archives are unpacked within. It is
+    /// shared by every thread unpacking into this store.
+    pub fn set_extraction_budget(&mut self, budget: ExtractionBudget) {
+        self.extraction_budget = budget;
+    }
+
+    pub fn extraction_budget(&self) -> &ExtractionBudget {
+        &self.extraction_budget
+    }
+
+    /// Where the entry for `store_key` lives, whether or not it exists.
+    pub fn path_for(&self, store_key: &str) -> PathBuf {
         self.store_dir.join(store_key)
     }
 
-    pub fn has_entry(&self, store_key: &str) -> bool {
-        self.entry_path(store_key).exists()
+    pub fn contains(&self, store_key: &str) -> bool {
+        self.path_for(store_key).exists()
     }
 
     pub fn list_entries(&self) -> Result<Vec<String>, Error> {
@@ -46,15 +96,196 @@ impl Store {
             if !file_type.is_dir() {
                 continue;
             }
-            if let Ok(name) = entry.file_name().into_string() {
+            // Dot-directories are entries still being filled.
+            if let Ok(name) = entry.file_name().into_string()
+                && !name.starts_with('.')
+            {
                 entries.push(name);
             }
         }
+        entries.sort();
+        Ok(entries)
+    }
+
+    /// Half-built entries left behind by interrupted extractions, and
+    /// half-written archives, sorted.
+    pub fn temp_entries(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut entries = Vec::new();
+        for entry in
+            fs::read_dir(&self.store_dir).map_err(Error::store("failed to read store directory"))?
+        {
+            let entry = entry.map_err(Error::store("failed to read store entry"))?;
+            if entry.file_name().to_string_lossy().starts_with(".tmp") {
+                entries.push(entry.path());
+            }
+        }
+        if let Ok(archives) = fs::read_dir(&self.archive_dir) {
+            for entry in archives {
+                let entry = entry.map_err(Error::store("failed to read archive entry"))?;
+                if entry.file_name().to_string_lossy().starts_with(".tmp") {
+                    entries.push(entry.path());
+                }
+            }
+        }
+        entries.sort();
         Ok(entries)
     }
 
-    pub fn ensure_entry(&self, store_key: &str, blob_path: &Path) -> Result<PathBuf, Error> {
-        let entry_path = self.entry_path(store_key);
+    /// Where the archive of `store_key` lives, whether or not it exists.
+    pub fn archive_path(&self, store_key: &str) -> PathBuf {
+        self.archive_dir
+            .join(format!("{store_key}{ARCHIVE_SUFFIX}"))
+    }
+
+    pub fn is_archived(&self, store_key: &str) -> bool {
+        self.archive_path(store_key).is_file()
+    }
+
+    /// Compress the entry for `store_key` into a zstd tarball under the
+    /// archive directory and remove the expanded entry. Returns the size of
+    /// the archive.
+    pub fn archive(&self, store_key: &str) -> Result<u64, Error> {
+        let entry_path = self.path_for(store_key);
+        let _lock = self.lock_entry(store_key)?;
+
+        fs::create_dir_all(&self.archive_dir)
+            .map_err(Error::store("failed to create archive directory"))?;
+        let tmp = tempfile::Builder::new()
+            .prefix(".tmp")
+            .suffix(ARCHIVE_SUFFIX)
+            .tempfile_in(&self.archive_dir)
+            .map_err(Error::store("failed to create archive"))?;
+        write_archive(&entry_path, tmp.as_file())?;
+        let bytes = tmp
+            .as_file()
+            .metadata()
+            .map_err(Error::store("failed to read archive size"))?
+            .len();
+        tmp.persist(self.archive_path(store_key))
+            .map_err(|e| Error::StoreCorruption {
+                message: format!("failed to move archive into place: {}", e.error),
+            })?;
+
+        fs::remove_dir_all(&entry_path).map_err(Error::store("failed to remove store entry"))?;
+        Ok(bytes)
+    }
+
+    /// Expand the archive of `store_key` back into the store and delete it.
+    pub async fn restore(&self, store_key: &str) -> Result<PathBuf, Error> {
+        let archive_path = self.archive_path(store_key);
+        let entry = self.unpack(store_key, &archive_path).await?;
+        // A leftover archive only takes space; `zb cache clean --archives`
+        // removes it.
+        let _ = fs::remove_file(&archive_path);
+        Ok(entry)
+    }
+
+    /// Archived entries, sorted by key.
+    pub fn list_archives(&self) -> Result<Vec<StoreArchive>, Error> {
+        let dir = match fs::read_dir(&self.archive_dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(Error::StoreCorruption {
+                    message: format!("failed to read archive directory: {e}"),
+                });
+            }
+        };
+
+        let mut archives = Vec::new();
+        for entry in dir {
+            let entry = entry.map_err(Error::store("failed to read archive entry"))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(store_key) = name.strip_suffix(ARCHIVE_SUFFIX) else {
+                continue;
+            };
+            if store_key.starts_with('.') {
+                continue;
+            }
+            let metadata = entry
+                .metadata()
+                .map_err(Error::store("failed to read archive metadata"))?;
+            archives.push(StoreArchive {
+                store_key: store_key.to_string(),
+                bytes: metadata.len(),
+                archived_at: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            });
+        }
+        archives.sort_by(|a, b| a.store_key.cmp(&b.store_key));
+        Ok(archives)
+    }
+
+    pub fn remove_archive(&self, store_key: &str) -> Result<(), Error> {
+        match fs::remove_file(self.archive_path(store_key)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::StoreCorruption {
+                message: format!("failed to remove archive of {store_key}: {e}"),
+            }),
+        }
+    }
+
+    /// Serialize work on the entry for `store_key` across processes until
+    /// the returned file is dropped.
+    fn lock_entry(&self, store_key: &str) -> Result<File, Error> {
+        let lock_path = self.locks_dir.join(format!("{store_key}.lock"));
+        let lock_file =
+            File::create(&lock_path).map_err(Error::store("failed to create lock file"))?;
+        lock_file
+            .lock_exclusive()
+            .map_err(Error::store("failed to acquire lock"))?;
+        Ok(lock_file)
+    }
+
+    pub async fn ensure_entry(&self, store_key: &str, blob_path: &Path) -> Result<PathBuf, Error> {
+        self.unpack(store_key, blob_path).await
+    }
+
+    /// The entry for `store_key`, unpacked from `archive` on a blocking
+    /// thread once the extraction budget admits it when it doesn't exist.
+    async fn unpack(&self, store_key: &str, archive: &Path) -> Result<PathBuf, Error> {
+        let entry_path = self.path_for(store_key);
+        if entry_path.exists() {
+            return Ok(entry_path);
+        }
+
+        let permit = self.extraction_budget.admit().await;
+        let store = self.clone();
+        let (store_key, archive) = (store_key.to_string(), archive.to_path_buf());
+        tokio::task::spawn_blocking(move || {
+            store.create_entry(&store_key, |tmp| {
+                extract_archive_buffered(&archive, tmp, &store.path_limits, permit.read_buffer())
+            })
+        })
+        .await
+        .map_err(Error::exec("extraction stopped unexpectedly"))?
+    }
+
+    /// Add the tree at `src` as a store entry keyed by its contents and
+    /// return the key. Adding an identical tree again is a no-op. The copy
+    /// never hard-links into `src`, and its files are made read-only.
+    ///
+    /// The entry is not referenced by anything yet; callers with a database
+    /// register it (see `Installer::import_store_dir`) so gc can find it.
+    #[cfg(feature = "materialize")]
+    pub fn add_dir(&self, src: &Path) -> Result<StoreKey, Error> {
+        let store_key = tree_hash(src)?;
+        self.create_entry(&store_key, |tmp| {
+            clone_or_copy_dir(src, tmp)?;
+            make_files_readonly(tmp)
+        })?;
+        Ok(store_key)
+    }
+
+    /// Fill a temporary directory with `fill` and move it into place as the
+    /// entry for `store_key`, unless the entry already exists. Concurrent
+    /// callers for the same key are serialized on a lock file.
+    fn create_entry(
+        &self,
+        store_key: &str,
+        fill: impl FnOnce(&Path) -> Result<(), Error>,
+    ) -> Result<PathBuf, Error> {
+        let entry_path = self.path_for(store_key);
 
         // Fast path: already exists
         if entry_path.exists() {
@@ -78,7 +309,7 @@ impl Store {
         let tmp_dir = tempfile::tempdir_in(&self.store_dir)
             .map_err(Error::store("failed to create temp directory"))?;
 
-        extract_archive(blob_path, tmp_dir.path())?;
+        fill(tmp_dir.path())?;
 
         // Persist the temp dir by converting it into a permanent path.
         // into_path() prevents auto-cleanup so rename failure still needs manual handling.
@@ -95,8 +326,8 @@ impl Store {
     }
 
     /// Remove a store entry. This should only be called when the refcount is 0.
-    pub fn remove_entry(&self, store_key: &str) -> Result<(), Error> {
-        let entry_path = self.entry_path(store_key);
+    pub fn remove(&self, store_key: &str) -> Result<(), Error> {
+        let entry_path = self.path_for(store_key);
 
         if !entry_path.exists() {
             return Ok(());
@@ -123,6 +354,96 @@ impl Store {
     }
 }
 
+/// Hash a directory tree deterministically: entries are visited in sorted
+/// order and each contributes its relative path, type, and for files the
+/// read/execute bits and content, for symlinks the target. Write bits are
+/// left out because store entries are made read-only.
+#[cfg(feature = "materialize")]
+pub(crate) fn tree_hash(src: &Path) -> Result<StoreKey, Error> {
+    let ctx = format!("failed to hash {}", src.display());
+    let walk_error = |e: walkdir::Error| Error::StoreCorruption {
+        message: format!("{ctx}: {e}"),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(b"zb-tree-v1\0");
+
+    for entry in walkdir::WalkDir::new(src)
+        .follow_links(false)
+        .sort_by_file_name()
+        .min_depth(1)
+    {
+        let entry = entry.map_err(walk_error)?;
+        let relative = entry.path().strip_prefix(src).unwrap_or(entry.path());
+        hash_field(&mut hasher, relative.as_os_str().as_encoded_bytes());
+
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            hasher.update(b"d");
+        } else if file_type.is_symlink() {
+            hasher.update(b"l");
+            let target = fs::read_link(entry.path()).map_err(Error::store(ctx.as_str()))?;
+            hash_field(&mut hasher, target.as_os_str().as_encoded_bytes());
+        } else {
+            hasher.update(b"f");
+            let mode = entry.metadata().map_err(walk_error)?.permissions().mode();
+            hasher.update((mode & 0o7555).to_be_bytes());
+            let mut file = File::open(entry.path()).map_err(Error::store(ctx.as_str()))?;
+            let len = file.metadata().map_err(Error::store(ctx.as_str()))?.len();
+            hasher.update(len.to_be_bytes());
+            io::copy(&mut file, &mut hasher).map_err(Error::store(ctx.as_str()))?;
+        }
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Write the tree at `src` into `file` as a zstd-compressed tarball. Symlinks
+/// are stored as links, and modes and times are kept so the entry expands
+/// back the same.
+fn write_archive(src: &Path, file: &File) -> Result<(), Error> {
+    let ctx = format!("failed to archive {}", src.display());
+    // zstd's default level: higher ones take minutes on large toolchains
+    // for a few percent.
+    let encoder = zstd::Encoder::new(file, 0).map_err(Error::store(ctx.as_str()))?;
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+    builder
+        .append_dir_all(".", src)
+        .map_err(Error::store(ctx.as_str()))?;
+    builder
+        .into_inner()
+        .and_then(zstd::Encoder::finish)
+        .map_err(Error::store(ctx.as_str()))?;
+    file.sync_all().map_err(Error::store(ctx.as_str()))
+}
+
+/// Length-prefixed so adjacent fields cannot run into each other.
+#[cfg(feature = "materialize")]
+fn hash_field(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u64).to_be_bytes());
+    hasher.update(bytes);
+}
+
+#[cfg(feature = "materialize")]
+fn make_files_readonly(dir: &Path) -> Result<(), Error> {
+    for entry in walkdir::WalkDir::new(dir).follow_links(false) {
+        let entry = entry.map_err(|e| Error::StoreCorruption {
+            message: format!("failed to walk {}: {e}", dir.display()),
+        })?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let ctx = format!("failed to make {} read-only", entry.path().display());
+        let mode = fs::metadata(entry.path())
+            .map_err(Error::store(ctx.as_str()))?
+            .permissions()
+            .mode();
+        fs::set_permissions(entry.path(), fs::Permissions::from_mode(mode & !0o222))
+            .map_err(Error::store(ctx.as_str()))?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,7 +452,6 @@ mod tests {
     use std::io::Write;
     use std::sync::Arc;
     use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::thread;
     use tar::Builder;
     use tempfile::TempDir;
 
@@ -152,8 +472,8 @@ mod tests {
         encoder.finish().unwrap()
     }
 
-    #[test]
-    fn second_call_is_noop() {
+    #[tokio::test]
+    async fn second_call_is_noop() {
         let tmp = TempDir::new().unwrap();
         let store = Store::new(tmp.path()).unwrap();
 
@@ -164,7 +484,7 @@ mod tests {
         let store_key = "abc123";
 
         // First call extracts
-        let path1 = store.ensure_entry(store_key, &blob_path).unwrap();
+        let path1 = store.ensure_entry(store_key, &blob_path).await.unwrap();
         assert!(path1.exists());
         assert!(path1.join("test.txt").exists());
 
@@ -172,17 +492,17 @@ mod tests {
         fs::write(path1.join("marker.txt"), "original").unwrap();
 
         // Second call should be a no-op
-        let path2 = store.ensure_entry(store_key, &blob_path).unwrap();
+        let path2 = store.ensure_entry(store_key, &blob_path).await.unwrap();
         assert_eq!(path1, path2);
 
         // Marker file should still exist (wasn't re-extracted)
         assert!(path2.join("marker.txt").exists());
     }
 
-    #[test]
-    fn concurrent_calls_unpack_once() {
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_calls_unpack_once() {
         let tmp = TempDir::new().unwrap();
-        let store = Arc::new(Store::new(tmp.path()).unwrap());
+        let store = Store::new(tmp.path()).unwrap();
 
         let tarball = create_test_tarball(b"concurrent test");
         let blob_path = tmp.path().join("test.tar.gz");
@@ -191,7 +511,7 @@ mod tests {
         let store_key = "concurrent123";
         let unpack_count = Arc::new(AtomicUsize::new(0));
 
-        // Spawn multiple threads that all try to ensure the same entry
+        // Spawn multiple tasks that all try to ensure the same entry
         let handles: Vec<_> = (0..10)
             .map(|_| {
                 let store = store.clone();
@@ -199,14 +519,14 @@ mod tests {
                 let count = unpack_count.clone();
                 let key = store_key.to_string();
 
-                thread::spawn(move || {
-                    let entry_path = store.entry_path(&key);
+                tokio::spawn(async move {
+                    let entry_path = store.path_for(&key);
                     let existed_before = entry_path.exists();
 
-                    let result = store.ensure_entry(&key, &blob);
+                    let result = store.ensure_entry(&key, &blob).await;
 
                     if !existed_before && result.is_ok() && entry_path.exists() {
-                        // This thread might have been the one to create it
+                        // This task might have been the one to create it
                         count.fetch_add(1, Ordering::SeqCst);
                     }
 
@@ -215,36 +535,190 @@ mod tests {
             })
             .collect();
 
-        // All threads should succeed
+        // All tasks should succeed
         for handle in handles {
-            let result = handle.join().unwrap();
+            let result = handle.await.unwrap();
             assert!(result.is_ok());
         }
 
         // Entry should exist
-        assert!(store.has_entry(store_key));
+        assert!(store.contains(store_key));
 
         // Content should be correct
-        let entry_path = store.entry_path(store_key);
+        let entry_path = store.path_for(store_key);
         let content = fs::read_to_string(entry_path.join("test.txt")).unwrap();
         assert_eq!(content, "concurrent test");
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_small_extraction_budget_unpacks_one_bottle_at_a_time() {
+        let tmp = TempDir::new().unwrap();
+        let mut store = Store::new(tmp.path()).unwrap();
+        store.set_extraction_budget(ExtractionBudget::new(1 << 20));
+
+        let handles: Vec<_> = (0..6)
+            .map(|i| {
+                let store = store.clone();
+                let blob = tmp.path().join(format!("bottle{i}.tar.gz"));
+                fs::write(&blob, create_test_tarball(format!("bottle {i}").as_bytes())).unwrap();
+                tokio::spawn(async move { store.ensure_entry(&format!("key{i}"), &blob).await })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+
+        let stats = store.extraction_budget().stats();
+        assert_eq!(stats.admitted, 6);
+        assert_eq!(stats.peak_in_flight, 1);
+        assert!(store.extraction_budget().limits().streamed());
+        for i in 0..6 {
+            let content = fs::read_to_string(store.path_for(&format!("key{i}")).join("test.txt"));
+            assert_eq!(content.unwrap(), format!("bottle {i}"));
+        }
+    }
+
+    #[cfg(feature = "materialize")]
+    fn sample_tree(root: &Path) {
+        fs::create_dir_all(root.join("bin")).unwrap();
+        fs::create_dir_all(root.join("share/doc")).unwrap();
+        fs::write(root.join("bin/tool"), b"#!/bin/sh\necho tool\n").unwrap();
+        fs::set_permissions(root.join("bin/tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(root.join("share/doc/README"), b"docs").unwrap();
+        std::os::unix::fs::symlink("tool", root.join("bin/tool-link")).unwrap();
+    }
+
     #[test]
-    fn has_entry_returns_correct_state() {
+    #[cfg(feature = "materialize")]
+    fn identical_trees_get_the_same_key() {
+        let tmp = TempDir::new().unwrap();
+        let store = Store::new(&tmp.path().join("root")).unwrap();
+        sample_tree(&tmp.path().join("a"));
+        sample_tree(&tmp.path().join("b"));
+
+        let a = store.add_dir(&tmp.path().join("a")).unwrap();
+        let b = store.add_dir(&tmp.path().join("b")).unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 64);
+        assert!(store.contains(&a));
+        assert_eq!(store.list_entries().unwrap(), vec![a]);
+    }
+
+    #[test]
+    #[cfg(feature = "materialize")]
+    fn one_changed_byte_changes_the_key() {
+        let tmp = TempDir::new().unwrap();
+        sample_tree(&tmp.path().join("a"));
+        sample_tree(&tmp.path().join("b"));
+        fs::write(tmp.path().join("b/share/doc/README"), b"docS").unwrap();
+
+        assert_ne!(
+            tree_hash(&tmp.path().join("a")).unwrap(),
+            tree_hash(&tmp.path().join("b")).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "materialize")]
+    fn mode_and_symlink_target_are_part_of_the_key() {
+        let tmp = TempDir::new().unwrap();
+        let base = tmp.path().join("base");
+        sample_tree(&base);
+        let key = tree_hash(&base).unwrap();
+
+        let mode = tmp.path().join("mode");
+        sample_tree(&mode);
+        fs::set_permissions(mode.join("bin/tool"), fs::Permissions::from_mode(0o644)).unwrap();
+        assert_ne!(tree_hash(&mode).unwrap(), key);
+
+        let link = tmp.path().join("link");
+        sample_tree(&link);
+        fs::remove_file(link.join("bin/tool-link")).unwrap();
+        std::os::unix::fs::symlink("../share", link.join("bin/tool-link")).unwrap();
+        assert_ne!(tree_hash(&link).unwrap(), key);
+
+        // Write bits are not part of the key, so a read-only copy hashes
+        // the same as its source.
+        fs::set_permissions(
+            base.join("share/doc/README"),
+            fs::Permissions::from_mode(0o444),
+        )
+        .unwrap();
+        assert_eq!(tree_hash(&base).unwrap(), key);
+    }
+
+    #[test]
+    #[cfg(feature = "materialize")]
+    fn added_entries_are_read_only_copies() {
+        let tmp = TempDir::new().unwrap();
+        let store = Store::new(&tmp.path().join("root")).unwrap();
+        let src = tmp.path().join("src");
+        sample_tree(&src);
+
+        let key = store.add_dir(&src).unwrap();
+        let entry = store.path_for(&key);
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&entry.join("bin/tool")), 0o555);
+        assert_eq!(mode(&entry.join("share/doc/README")) & 0o222, 0);
+        assert_eq!(mode(&src.join("bin/tool")), 0o755);
+        assert_eq!(
+            fs::read_link(entry.join("bin/tool-link")).unwrap(),
+            Path::new("tool")
+        );
+
+        store.remove(&key).unwrap();
+        assert!(!store.contains(&key));
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "materialize")]
+    async fn archived_entries_expand_back_the_same() {
+        let tmp = TempDir::new().unwrap();
+        let store = Store::new(&tmp.path().join("root")).unwrap();
+        let src = tmp.path().join("src");
+        sample_tree(&src);
+        let key = store.add_dir(&src).unwrap();
+
+        let bytes = store.archive(&key).unwrap();
+
+        assert!(!store.contains(&key));
+        assert!(store.is_archived(&key));
+        let archives = store.list_archives().unwrap();
+        assert_eq!(archives.len(), 1);
+        assert_eq!(
+            (archives[0].store_key.as_str(), archives[0].bytes),
+            (key.as_str(), bytes)
+        );
+        assert!(store.temp_entries().unwrap().is_empty());
+
+        let entry = store.restore(&key).await.unwrap();
+
+        assert_eq!(tree_hash(&entry).unwrap(), key);
+        assert_eq!(
+            fs::read_link(entry.join("bin/tool-link")).unwrap(),
+            Path::new("tool")
+        );
+        assert!(!store.is_archived(&key));
+        assert!(store.list_archives().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn contains_returns_correct_state() {
         let tmp = TempDir::new().unwrap();
         let store = Store::new(tmp.path()).unwrap();
 
         let store_key = "checkme";
 
-        assert!(!store.has_entry(store_key));
+        assert!(!store.contains(store_key));
 
         let tarball = create_test_tarball(b"exists");
         let blob_path = tmp.path().join("test.tar.gz");
         fs::write(&blob_path, &tarball).unwrap();
 
-        store.ensure_entry(store_key, &blob_path).unwrap();
+        store.ensure_entry(store_key, &blob_path).await.unwrap();
 
-        assert!(store.has_entry(store_key));
+        assert!(store.contains(store_key));
     }
 }